@@ -0,0 +1,15 @@
+// Errors of the /api/* routes are JSON, show their message instead of the raw body
+document.addEventListener("htmx:beforeSwap", (event) => {
+  const xhr = event.detail.xhr;
+  if (xhr.status < 400 || !(xhr.getResponseHeader("Content-Type") || "").startsWith("application/json")) {
+    return;
+  }
+  try {
+    const error = JSON.parse(xhr.responseText);
+    const text = document.createElement("span");
+    text.textContent = error.message;
+    event.detail.serverResponse = text.innerHTML;
+  } catch {
+    // not an error envelope, swapped as is
+  }
+});
@@ -0,0 +1,20 @@
+// Forms marked data-idempotent send an Idempotency-Key header, made here rather
+// than rendered into the page, so a page served from the browser's cache never
+// reuses a key. A submission retried before the server answered keeps its key,
+// the next one gets a new key.
+document.addEventListener("htmx:configRequest", (event) => {
+  const form = event.detail.elt.closest("form[data-idempotent]");
+  if (!form) {
+    return;
+  }
+  form.dataset.idempotencyKey ||= crypto.randomUUID();
+  event.detail.headers["Idempotency-Key"] = form.dataset.idempotencyKey;
+});
+
+document.addEventListener("htmx:afterRequest", (event) => {
+  const form = event.detail.elt.closest("form[data-idempotent]");
+  // status 0 is a network error, the request may be retried as it was
+  if (form && event.detail.xhr.status !== 0) {
+    delete form.dataset.idempotencyKey;
+  }
+});
@@ -0,0 +1,51 @@
+// Passkey registration and login. The server sends WebAuthn options in their
+// JSON form, which browsers parse and answer in themselves.
+async function errorMessage(response) {
+  const text = await response.text();
+  try {
+    return JSON.parse(text).message;
+  } catch {
+    return text;
+  }
+}
+
+async function runCeremony(form, startUrl, finishUrl, ceremony) {
+  const error = form.nextElementSibling;
+  error.textContent = "";
+  try {
+    const start = await fetch(startUrl, { method: "POST", body: new URLSearchParams(new FormData(form)) });
+    if (!start.ok) {
+      error.textContent = await errorMessage(start);
+      return;
+    }
+    const credential = await ceremony((await start.json()).publicKey);
+    const finish = await fetch(finishUrl, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify(credential.toJSON()),
+    });
+    if (!finish.ok) {
+      error.textContent = await errorMessage(finish);
+      return;
+    }
+    window.location.href = finish.headers.get("HX-Redirect") || "/";
+  } catch {
+    // cancelled in the browser's dialog, or passkeys aren't supported
+    error.textContent = "The passkey couldn't be used, try again";
+  }
+}
+
+document.addEventListener("submit", (event) => {
+  const form = event.target;
+  if (form.dataset.passkey === "register") {
+    event.preventDefault();
+    runCeremony(form, "/settings/passkeys/start", "/settings/passkeys/finish", (options) =>
+      navigator.credentials.create({ publicKey: PublicKeyCredential.parseCreationOptionsFromJSON(options) }),
+    );
+  } else if (form.dataset.passkey === "login") {
+    event.preventDefault();
+    runCeremony(form, "/api/login/passkey/start", "/api/login/passkey/finish", (options) =>
+      navigator.credentials.get({ publicKey: PublicKeyCredential.parseRequestOptionsFromJSON(options) }),
+    );
+  }
+});
@@ -0,0 +1,38 @@
+// Forms rendered with data-retry-after come with their submit button disabled,
+// it is enabled again once that many seconds have passed. The time left is
+// counted down in the [data-countdown] next to the form.
+function startRetryCountdown(form) {
+  const button = form.querySelector("button[type=submit]");
+  const countdown = form.parentElement.querySelector("[data-countdown]");
+  const until = Date.now() + Number(form.dataset.retryAfter) * 1000;
+
+  const tick = () => {
+    const remaining = Math.ceil((until - Date.now()) / 1000);
+    // the form was swapped out in the meantime
+    if (!form.isConnected) {
+      clearInterval(timer);
+      return;
+    }
+    if (remaining <= 0) {
+      clearInterval(timer);
+      delete form.dataset.retryAfter;
+      button.disabled = false;
+      if (countdown) {
+        countdown.textContent = "";
+      }
+      return;
+    }
+    if (countdown) {
+      const seconds = String(remaining % 60).padStart(2, "0");
+      countdown.textContent = `(${Math.floor(remaining / 60)}:${seconds})`;
+    }
+  };
+  const timer = setInterval(tick, 1000);
+  tick();
+}
+
+htmx.onLoad((content) => {
+  const forms = content.matches("form[data-retry-after]") ? [content] : [];
+  forms.push(...content.querySelectorAll("form[data-retry-after]"));
+  forms.forEach(startRetryCountdown);
+});
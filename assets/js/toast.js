@@ -0,0 +1,43 @@
+// Handlers send toasts in HX-Trigger, e.g. {"toast": {"level": "success", "message": "Todo added"}}.
+// A toast sent with a redirect is kept until the next page has loaded.
+const TOAST_STORAGE_KEY = "pendingToast";
+const TOAST_TIMEOUT_MS = 4000;
+
+function showToast(toast) {
+  const container = document.getElementById("toasts");
+  if (!container || !toast || !toast.message) {
+    return;
+  }
+  const element = document.createElement("div");
+  element.className = `toast toast-${toast.level}`;
+  element.setAttribute("role", toast.level === "error" ? "alert" : "status");
+  element.textContent = toast.message;
+  container.append(element);
+  setTimeout(() => element.remove(), TOAST_TIMEOUT_MS);
+}
+
+document.addEventListener("toast", (event) => showToast(event.detail));
+
+document.addEventListener("htmx:beforeOnLoad", (event) => {
+  const xhr = event.detail.xhr;
+  const trigger = xhr.getResponseHeader("HX-Trigger");
+  if (!trigger || !(xhr.getResponseHeader("HX-Redirect") || xhr.getResponseHeader("HX-Refresh"))) {
+    return;
+  }
+  try {
+    const toast = JSON.parse(trigger).toast;
+    if (toast) {
+      sessionStorage.setItem(TOAST_STORAGE_KEY, JSON.stringify(toast));
+    }
+  } catch {
+    // a list of event names, without a toast
+  }
+});
+
+document.addEventListener("DOMContentLoaded", () => {
+  const pending = sessionStorage.getItem(TOAST_STORAGE_KEY);
+  if (pending) {
+    sessionStorage.removeItem(TOAST_STORAGE_KEY);
+    showToast(JSON.parse(pending));
+  }
+});
@@ -0,0 +1,33 @@
+CREATE TABLE todo_list (
+    list_id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+    owner_id uuid UNIQUE NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (owner_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
+
+-- every user gets a single implicit list, existing todos are moved into it
+INSERT INTO todo_list (owner_id)
+SELECT user_id FROM user_info;
+
+ALTER TABLE todo
+ADD COLUMN list_id uuid REFERENCES todo_list (list_id) ON DELETE CASCADE;
+
+UPDATE todo
+SET list_id = tl.list_id
+FROM todo_list AS tl
+WHERE tl.owner_id = todo.user_id;
+
+ALTER TABLE todo
+ALTER COLUMN list_id SET NOT NULL;
+
+CREATE TYPE list_role AS ENUM ('viewer', 'editor');
+
+CREATE TABLE list_members (
+    list_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    role list_role NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (list_id, user_id),
+    FOREIGN KEY (list_id) REFERENCES todo_list (list_id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
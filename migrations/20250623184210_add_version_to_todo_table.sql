@@ -0,0 +1,2 @@
+ALTER TABLE todo
+ADD COLUMN version integer NOT NULL DEFAULT 1;
@@ -0,0 +1,9 @@
+ALTER TABLE todo
+ADD COLUMN due_date date,
+ADD COLUMN reminder_sent_at timestamptz;
+
+ALTER TABLE user_info
+ADD COLUMN due_date_reminders boolean NOT NULL DEFAULT TRUE;
+
+CREATE INDEX todo_due_date_idx ON todo (due_date)
+WHERE reminder_sent_at IS NULL;
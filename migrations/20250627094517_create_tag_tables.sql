@@ -0,0 +1,18 @@
+CREATE TABLE tag (
+    tag_id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id uuid NOT NULL,
+    name text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    UNIQUE (user_id, name),
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
+
+CREATE TABLE todo_tag (
+    todo_id uuid NOT NULL,
+    tag_id uuid NOT NULL,
+    PRIMARY KEY (todo_id, tag_id),
+    FOREIGN KEY (todo_id) REFERENCES todo (todo_id) ON DELETE CASCADE,
+    FOREIGN KEY (tag_id) REFERENCES tag (tag_id) ON DELETE CASCADE
+);
+
+CREATE INDEX todo_tag_tag_id_idx ON todo_tag (tag_id);
@@ -0,0 +1,4 @@
+CREATE TYPE todo_priority AS ENUM ('low', 'normal', 'high');
+
+ALTER TABLE todo
+ADD COLUMN priority todo_priority NOT NULL DEFAULT 'normal';
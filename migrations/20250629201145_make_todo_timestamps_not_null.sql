@@ -0,0 +1,11 @@
+UPDATE todo
+SET created_at = NOW()
+WHERE created_at IS NULL;
+
+UPDATE todo
+SET updated_at = NOW()
+WHERE updated_at IS NULL;
+
+ALTER TABLE todo
+ALTER COLUMN created_at SET NOT NULL,
+ALTER COLUMN updated_at SET NOT NULL;
@@ -0,0 +1,14 @@
+CREATE TYPE todo_sort AS ENUM ('created', 'priority');
+
+CREATE TABLE user_preferences (
+    user_id uuid PRIMARY KEY,
+    default_sort todo_sort NOT NULL DEFAULT 'created',
+    items_per_page integer NOT NULL DEFAULT 50 CHECK (items_per_page BETWEEN 1 AND 100),
+    show_completed boolean NOT NULL DEFAULT TRUE,
+    timezone text NOT NULL DEFAULT 'UTC',
+    updated_at timestamptz NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
+
+SELECT
+    trigger_updated_at('user_preferences');
@@ -0,0 +1,5 @@
+CREATE TYPE user_role AS ENUM ('user', 'admin');
+
+ALTER TABLE user_info
+ADD COLUMN role user_role NOT NULL DEFAULT 'user',
+ADD COLUMN locked_at timestamptz;
@@ -0,0 +1,15 @@
+CREATE TABLE audit_log (
+    audit_id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+    -- kept when the user is deleted, the metadata still says who it was
+    user_id uuid,
+    event_type text NOT NULL,
+    ip_address text,
+    user_agent text,
+    metadata jsonb NOT NULL DEFAULT '{}',
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE SET NULL
+);
+
+CREATE INDEX audit_log_user_id_idx ON audit_log (user_id, created_at DESC);
+
+CREATE INDEX audit_log_event_type_idx ON audit_log (event_type, created_at DESC);
@@ -0,0 +1,12 @@
+CREATE TABLE known_devices (
+    user_id uuid NOT NULL,
+    user_agent_family text NOT NULL,
+    network text NOT NULL,
+    first_seen_at timestamptz NOT NULL DEFAULT NOW(),
+    last_seen_at timestamptz NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, user_agent_family, network),
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
+
+ALTER TABLE user_info
+ADD COLUMN new_device_alerts boolean NOT NULL DEFAULT TRUE;
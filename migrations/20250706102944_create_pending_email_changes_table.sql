@@ -0,0 +1,10 @@
+CREATE TABLE pending_email_changes (
+    token text PRIMARY KEY,
+    user_id uuid NOT NULL,
+    new_email text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    used_at timestamptz,
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX pending_email_changes_user_id_idx ON pending_email_changes (user_id);
@@ -0,0 +1,10 @@
+CREATE TABLE username_history (
+    user_id uuid NOT NULL,
+    username text COLLATE "case_insensitive" NOT NULL,
+    changed_at timestamptz NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX username_history_username_idx ON username_history (username, changed_at DESC);
+
+CREATE INDEX username_history_user_id_idx ON username_history (user_id, changed_at DESC);
@@ -0,0 +1 @@
+ALTER TABLE user_info ADD COLUMN avatar TEXT;
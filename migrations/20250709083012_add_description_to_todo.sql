@@ -0,0 +1 @@
+ALTER TABLE todo ADD COLUMN description TEXT NOT NULL DEFAULT '';
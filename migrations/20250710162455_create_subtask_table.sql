@@ -0,0 +1,14 @@
+CREATE TABLE subtask (
+    subtask_id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+    todo_id uuid NOT NULL,
+    content text NOT NULL,
+    is_completed boolean NOT NULL DEFAULT FALSE,
+    position integer NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (todo_id) REFERENCES todo (todo_id) ON DELETE CASCADE
+);
+
+CREATE INDEX subtask_todo_id_position_idx ON subtask (todo_id, position);
+
+ALTER TABLE user_preferences
+    ADD COLUMN auto_complete_todos boolean NOT NULL DEFAULT FALSE;
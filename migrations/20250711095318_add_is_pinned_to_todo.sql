@@ -0,0 +1 @@
+ALTER TABLE todo ADD COLUMN is_pinned boolean NOT NULL DEFAULT FALSE;
@@ -0,0 +1,4 @@
+ALTER TABLE todo ADD COLUMN deleted_at timestamptz;
+
+-- only the purge worker looks for deleted todos
+CREATE INDEX todo_deleted_at_idx ON todo (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,16 @@
+CREATE TABLE todo_events (
+    -- increasing ids keep changes made in one transaction in order, they
+    -- share a created_at
+    event_id bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    todo_id uuid NOT NULL,
+    -- who made the change, kept as NULL when the user is deleted
+    user_id uuid,
+    payload jsonb NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (todo_id) REFERENCES todo (todo_id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE SET NULL
+);
+
+CREATE INDEX todo_events_todo_id_idx ON todo_events (todo_id, event_id DESC);
+
+CREATE INDEX todo_events_created_at_idx ON todo_events (created_at);
@@ -0,0 +1,8 @@
+-- the outcome of the latest run of each periodic task, for the admin dashboard
+CREATE TABLE scheduled_task (
+    name text PRIMARY KEY,
+    last_started_at timestamptz NOT NULL,
+    last_duration_ms bigint NOT NULL,
+    -- NULL when the last run succeeded
+    last_error text
+);
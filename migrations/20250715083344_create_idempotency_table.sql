@@ -0,0 +1,14 @@
+CREATE TABLE idempotency (
+    user_id uuid NOT NULL,
+    idempotency_key text NOT NULL,
+    request_hash text NOT NULL,
+    -- the response columns stay NULL while the first request is in flight
+    response_status_code smallint,
+    response_headers jsonb,
+    response_body bytea,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, idempotency_key),
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idempotency_created_at_idx ON idempotency (created_at);
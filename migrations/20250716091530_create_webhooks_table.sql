@@ -0,0 +1,38 @@
+CREATE TABLE webhooks (
+    webhook_id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id uuid NOT NULL,
+    url text NOT NULL,
+    -- signs the payloads, so it has to be kept as is rather than hashed
+    secret text NOT NULL,
+    -- the events sent to this endpoint, e.g. todo.created
+    events text[] NOT NULL CHECK (
+        cardinality(events) > 0
+        AND events <@ ARRAY['todo.created', 'todo.completed', 'todo.deleted']
+    ),
+    -- turned off after too many failed deliveries in a row
+    enabled boolean NOT NULL DEFAULT TRUE,
+    consecutive_failures integer NOT NULL DEFAULT 0,
+    last_attempt_at timestamptz,
+    -- NULL when the endpoint didn't respond
+    last_status smallint,
+    last_error text,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX webhooks_user_id_idx ON webhooks (user_id);
+
+-- deliveries waiting to be sent, written in the same transaction as the change
+CREATE TABLE outbox (
+    -- increasing ids keep the deliveries of one endpoint in order
+    delivery_id bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    webhook_id uuid NOT NULL,
+    event text NOT NULL,
+    payload jsonb NOT NULL,
+    attempts integer NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL DEFAULT NOW(),
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (webhook_id) REFERENCES webhooks (webhook_id) ON DELETE CASCADE
+);
+
+CREATE INDEX outbox_next_attempt_at_idx ON outbox (next_attempt_at);
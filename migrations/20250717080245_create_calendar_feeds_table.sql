@@ -0,0 +1,8 @@
+-- at most one feed per user, rotating it replaces the token
+CREATE TABLE calendar_feeds (
+    user_id uuid PRIMARY KEY,
+    -- SHA-256 of the token in the feed URL, so a leaked table doesn't leak the feeds
+    token_hash text NOT NULL UNIQUE,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
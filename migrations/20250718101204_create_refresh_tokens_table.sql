@@ -0,0 +1,13 @@
+-- refresh tokens of API clients logged in with a bearer token
+CREATE TABLE refresh_tokens (
+    -- SHA-256 of the token, which is only ever shown to the client
+    token_hash text PRIMARY KEY,
+    user_id uuid NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    expires_at timestamptz NOT NULL,
+    -- set when the token is used, logged out or revoked, as each one can only be used once
+    revoked_at timestamptz,
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX refresh_tokens_user_id_idx ON refresh_tokens (user_id);
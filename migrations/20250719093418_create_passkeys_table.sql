@@ -0,0 +1,15 @@
+CREATE TABLE passkeys (
+    passkey_id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id uuid NOT NULL,
+    credential_id bytea NOT NULL UNIQUE,
+    -- the credential as serialized by webauthn-rs, which holds the COSE public key
+    public_key jsonb NOT NULL,
+    -- the authenticator's signature counter, a lower one than stored hints at a cloned key
+    sign_count bigint NOT NULL DEFAULT 0,
+    label text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    last_used_at timestamptz,
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX passkeys_user_id_idx ON passkeys (user_id);
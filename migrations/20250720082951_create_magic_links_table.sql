@@ -0,0 +1,17 @@
+-- set once the user has shown they own the address, by confirming it after a change
+ALTER TABLE user_info ADD COLUMN email_verified_at timestamptz;
+
+-- single-use links that log a user in without their password
+CREATE TABLE magic_links (
+    -- SHA-256 of the token in the link, which is only ever emailed
+    token_hash text PRIMARY KEY,
+    user_id uuid NOT NULL,
+    -- the address the link went to, it stops working if the user's email changes
+    email text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz,
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX magic_links_user_id_idx ON magic_links (user_id);
@@ -0,0 +1,8 @@
+-- Editors add todos to other people's lists under their own user_id, those
+-- todos belong to the list and outlive the account of whoever added them
+ALTER TABLE todo ALTER COLUMN user_id DROP NOT NULL;
+
+ALTER TABLE todo DROP CONSTRAINT todo_user_id_fkey;
+
+ALTER TABLE todo
+ADD CONSTRAINT todo_user_id_fkey FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE SET NULL;
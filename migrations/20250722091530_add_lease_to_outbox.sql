@@ -0,0 +1,3 @@
+-- deliveries are claimed by setting a lease and sent outside any transaction,
+-- a crashed worker's claims become free again once their lease runs out
+ALTER TABLE outbox ADD COLUMN locked_until timestamptz;
@@ -0,0 +1,4 @@
+CREATE TYPE locale AS ENUM ('en', 'de');
+
+-- NULL follows the Accept-Language of the browser
+ALTER TABLE user_preferences ADD COLUMN locale locale;
@@ -0,0 +1,3 @@
+CREATE TYPE theme AS ENUM ('system', 'light', 'dark');
+
+ALTER TABLE user_preferences ADD COLUMN theme theme NOT NULL DEFAULT 'system';
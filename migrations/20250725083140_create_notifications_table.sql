@@ -0,0 +1,17 @@
+CREATE TABLE notifications (
+    notification_id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id uuid NOT NULL,
+    -- the tag of the payload, e.g. list_shared
+    kind text NOT NULL,
+    payload jsonb NOT NULL,
+    read_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES user_info (user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX notifications_user_id_idx ON notifications (user_id, created_at DESC);
+
+-- the unread count polled by every page
+CREATE INDEX notifications_unread_idx ON notifications (user_id) WHERE read_at IS NULL;
+
+CREATE INDEX notifications_created_at_idx ON notifications (created_at);
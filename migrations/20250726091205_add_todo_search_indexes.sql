@@ -0,0 +1,14 @@
+CREATE INDEX todo_content_fts_idx ON todo USING gin (to_tsvector('simple', todo_content));
+
+-- pg_trgm ships with Postgres but needs the contrib package and a user
+-- allowed to create it. Without it search still works, only without typo
+-- tolerance, so it isn't worth failing the migration over.
+DO $$
+BEGIN
+    CREATE EXTENSION IF NOT EXISTS pg_trgm;
+    CREATE INDEX IF NOT EXISTS todo_content_trgm_idx ON todo USING gin (todo_content gin_trgm_ops);
+EXCEPTION
+    WHEN insufficient_privilege OR undefined_file OR feature_not_supported THEN
+        RAISE WARNING 'pg_trgm is not available, todo search won''t tolerate typos: %', SQLERRM;
+END
+$$;
@@ -0,0 +1,54 @@
+-- Active (not deleted) todos added by the user, checked against their limit
+-- on every insert instead of counting the todos each time. Kept up to date
+-- by a trigger, so todos removed by a cascade, e.g. with the list of a
+-- deleted account, are counted too. todo_limit overrides the configured
+-- limit for one user.
+ALTER TABLE user_info
+ADD COLUMN active_todo_count bigint NOT NULL DEFAULT 0,
+ADD COLUMN todo_limit bigint CHECK (todo_limit >= 0);
+
+UPDATE user_info AS ui
+SET active_todo_count = (
+    SELECT COUNT(*) FROM todo AS td
+    WHERE td.user_id = ui.user_id AND td.deleted_at IS NULL
+);
+
+CREATE FUNCTION count_active_todos() RETURNS trigger AS
+$$
+BEGIN
+IF TG_OP = 'UPDATE'
+    AND (OLD.deleted_at IS NULL) = (NEW.deleted_at IS NULL)
+    AND OLD.user_id IS NOT DISTINCT FROM NEW.user_id THEN
+    RETURN NULL;
+END IF;
+
+IF TG_OP IN ('UPDATE', 'DELETE') AND OLD.deleted_at IS NULL THEN
+    UPDATE user_info SET active_todo_count = active_todo_count - 1
+    WHERE user_id = OLD.user_id;
+END IF;
+
+IF TG_OP IN ('INSERT', 'UPDATE') AND NEW.deleted_at IS NULL THEN
+    UPDATE user_info SET active_todo_count = active_todo_count + 1
+    WHERE user_id = NEW.user_id;
+END IF;
+
+RETURN NULL;
+
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER count_active_todos
+    AFTER INSERT OR DELETE OR UPDATE OF deleted_at, user_id
+    ON todo
+    FOR EACH ROW
+EXECUTE FUNCTION count_active_todos();
+
+-- the count isn't a change to the user
+DROP TRIGGER set_updated_at ON user_info;
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE
+    ON user_info
+    FOR EACH ROW
+    WHEN (OLD IS DISTINCT FROM NEW AND OLD.active_todo_count = NEW.active_todo_count)
+EXECUTE FUNCTION set_updated_at();
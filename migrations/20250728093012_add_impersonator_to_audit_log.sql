@@ -0,0 +1,3 @@
+-- the admin who was logged in as the user when the event happened
+ALTER TABLE audit_log
+    ADD COLUMN impersonator_id uuid REFERENCES user_info (user_id) ON DELETE SET NULL;
@@ -0,0 +1,16 @@
+CREATE TABLE invitations (
+    code text PRIMARY KEY,
+    -- kept when the admin is deleted
+    created_by uuid,
+    -- only this address can register with the code, when set
+    email text COLLATE "case_insensitive",
+    uses_remaining integer NOT NULL CHECK (uses_remaining >= 0),
+    expires_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (created_by) REFERENCES user_info (user_id) ON DELETE SET NULL
+);
+
+CREATE TABLE waitlist (
+    email text COLLATE "case_insensitive" PRIMARY KEY,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
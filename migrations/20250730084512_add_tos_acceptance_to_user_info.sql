@@ -0,0 +1,5 @@
+-- users from before the terms existed haven't accepted any version, so they
+-- are asked to on their next visit
+ALTER TABLE user_info
+    ADD COLUMN tos_accepted_version integer NOT NULL DEFAULT 0,
+    ADD COLUMN tos_accepted_at timestamptz;
@@ -0,0 +1,23 @@
+-- emails waiting to be sent, written in the same transaction as what they are
+-- about and sent by a background worker
+CREATE TABLE email_outbox (
+    email_id bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    recipient text NOT NULL,
+    -- which email it is, e.g. magic_link, and what it is rendered with
+    template text NOT NULL,
+    params jsonb NOT NULL,
+    attempts integer NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL DEFAULT NOW(),
+    -- emails are claimed by setting a lease and sent outside any transaction,
+    -- a crashed worker's claims become free again once their lease runs out
+    locked_until timestamptz,
+    last_error text,
+    -- set once the email is given up on, it stays for the admins to look at
+    dead_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX email_outbox_next_attempt_at_idx ON email_outbox (next_attempt_at)
+    WHERE dead_at IS NULL;
+CREATE INDEX email_outbox_dead_at_idx ON email_outbox (dead_at)
+    WHERE dead_at IS NOT NULL;
@@ -0,0 +1,8 @@
+-- the list page and the todo API choose their page from one list along
+-- these, the lateral lookups of each todo's tags and subtasks already have
+-- the primary key of todo_tag and the todo_id index of subtask
+CREATE INDEX todo_list_page_idx ON todo (list_id, is_pinned DESC, created_at DESC, todo_id DESC)
+    WHERE deleted_at IS NULL;
+
+CREATE INDEX todo_list_created_at_idx ON todo (list_id, created_at DESC, todo_id DESC)
+    WHERE deleted_at IS NULL;
@@ -0,0 +1,45 @@
+-- the columns already compare without case, but a write that doesn't go
+-- through the app could still store capitals. The existing ones are
+-- lowercased, refusing to if two accounts would end up with the same value.
+DO $$
+DECLARE
+    emails text;
+    usernames text;
+BEGIN
+    SELECT string_agg(format('%s (%s accounts)', lowered, accounts), ', ')
+    INTO emails
+    FROM (
+        SELECT lower(email) COLLATE "C" AS lowered, COUNT(*) AS accounts
+        FROM user_info
+        GROUP BY 1
+        HAVING COUNT(*) > 1
+    ) AS conflicts;
+
+    SELECT string_agg(format('%s (%s accounts)', lowered, accounts), ', ')
+    INTO usernames
+    FROM (
+        SELECT lower(username) COLLATE "C" AS lowered, COUNT(*) AS accounts
+        FROM user_info
+        GROUP BY 1
+        HAVING COUNT(*) > 1
+    ) AS conflicts;
+
+    IF emails IS NOT NULL OR usernames IS NOT NULL THEN
+        RAISE EXCEPTION 'Accounts would share an email or username once lowercased'
+            USING DETAIL = format(
+                'Emails: %s. Usernames: %s.',
+                COALESCE(emails, 'none'),
+                COALESCE(usernames, 'none')
+            ),
+            HINT = 'Rename all but one account of each before migrating again.';
+    END IF;
+END
+$$;
+
+-- compared byte for byte, the collation would find them equal already
+UPDATE user_info SET email = lower(email) WHERE email COLLATE "C" <> lower(email);
+UPDATE user_info SET username = lower(username) WHERE username COLLATE "C" <> lower(username);
+
+ALTER TABLE user_info
+    ADD CONSTRAINT user_info_email_lowercase CHECK (email COLLATE "C" = lower(email)),
+    ADD CONSTRAINT user_info_username_lowercase CHECK (username COLLATE "C" = lower(username));
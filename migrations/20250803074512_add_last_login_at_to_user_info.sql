@@ -0,0 +1,2 @@
+-- set by the login handlers, restoring a session from its cookie isn't a login
+ALTER TABLE user_info ADD COLUMN last_login_at timestamptz;
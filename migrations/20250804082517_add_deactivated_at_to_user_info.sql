@@ -0,0 +1,6 @@
+-- deactivated accounts can't be used until their owner logs in again, and
+-- are deleted once ACCOUNT_DEACTIVATION_GRACE_DAYS have passed
+ALTER TABLE user_info ADD COLUMN deactivated_at timestamptz;
+
+CREATE INDEX user_info_deactivated_at_idx ON user_info (deactivated_at)
+    WHERE deactivated_at IS NOT NULL;
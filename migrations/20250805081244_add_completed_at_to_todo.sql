@@ -0,0 +1,12 @@
+-- when a todo was last completed, NULL while it isn't. updated_at moves on
+-- every edit so it can't tell.
+ALTER TABLE todo ADD COLUMN completed_at timestamptz;
+
+-- the moment todos completed before now were completed isn't known, the day
+-- they were created is the closest there is. Statistics of those days count
+-- them on the day they were added instead.
+UPDATE todo SET completed_at = created_at WHERE is_completed;
+
+-- the "completed today" section of lists
+CREATE INDEX todo_completed_at_idx ON todo (list_id, completed_at)
+    WHERE completed_at IS NOT NULL AND deleted_at IS NULL;
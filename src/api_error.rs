@@ -0,0 +1,98 @@
+use axum::{
+    Json,
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+
+/// The body every error of the `/api/*` routes is sent as, e.g.
+/// `{"code": "username_taken", "message": "Username already exists", "field": "username"}`.
+///
+/// Clients match on `code`, which stays the same while `message` is free to
+/// change. `field` names the form field a validation error is about, and
+/// `errors` each field of a form that couldn't be read.
+#[derive(Debug, serde::Serialize)]
+pub struct ApiError {
+    #[serde(skip)]
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<&'static str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+}
+
+/// A field in the `errors` of an [`ApiError`], e.g.
+/// `{"field": "email", "code": "missing", "message": "This field is required"}`
+#[derive(Debug, serde::Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl ToString) -> Self {
+        Self {
+            status,
+            code,
+            message: message.to_string(),
+            field: None,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn with_field(mut self, field: &'static str) -> Self {
+        self.field = Some(field);
+        self
+    }
+
+    pub fn with_errors(mut self, errors: Vec<FieldError>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// The details of unexpected errors are only logged, never sent
+    pub fn internal(error: &anyhow::Error) -> Self {
+        tracing::error!(error = ?error, "Unexpected error in api route");
+        crate::error_reporting::report(error);
+        Self::panicked()
+    }
+
+    /// Sent when the handler panicked, which has been logged and reported by then
+    pub fn panicked() -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            "An internal server error occured",
+        )
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self)).into_response()
+    }
+}
+
+/// Fallback of the `/api` routes, so unknown ones get the same body as any other error
+pub async fn route_not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "not_found", "Not found")
+}
+
+/// Sent for known `/api` routes called with a method they don't handle
+pub async fn method_not_allowed() -> ApiError {
+    ApiError::new(
+        StatusCode::METHOD_NOT_ALLOWED,
+        "method_not_allowed",
+        "Method not allowed",
+    )
+}
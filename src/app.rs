@@ -1,25 +1,65 @@
-use std::{str::FromStr, sync::Arc};
+use std::{net::SocketAddr, path::Path, str::FromStr, sync::Arc};
 
-use axum::{Router, routing::get};
+use axum::{
+    Extension, Router, middleware,
+    routing::{get, post},
+};
 use axum_login::AuthManagerLayerBuilder;
 use axum_messages::MessagesManagerLayer;
-use fred::{interfaces::ClientLike, prelude::ReconnectPolicy};
 use secrecy::ExposeSecret;
+use sentry::integrations::tower::NewSentryLayer;
 use sqlx::{
     PgPool,
     postgres::{PgConnectOptions, PgPoolOptions, PgSslMode},
 };
 use tokio::net::TcpListener;
-use tower_http::services::ServeDir;
+use tower_http::{catch_panic::CatchPanicLayer, cors::CorsLayer, trace::TraceLayer};
 use tower_sessions::SessionManagerLayer;
-use tower_sessions_redis_store::RedisStore;
+use webauthn_rs::Webauthn;
 
 use crate::{
-    auth,
+    api_error, assets,
+    audit::AuditLogger,
+    auth::{
+        self, Hasher, PgUserRepo, SessionKeys, TokenKeys, UserCache, UserRepo,
+        sessions::{self, RedisSessionStore},
+    },
+    captcha::CaptchaClient,
+    catch_panic,
+    client_ip::TrustedProxies,
     config::{self, AppEnv, Config},
-    routes::{health_check, root::get_homepage, todo},
+    cors,
+    domain::email_address::EmailAddress,
+    email_client::EmailClient,
+    error_reporting,
+    events::EventRegistry,
+    features::Features,
+    maintenance,
+    preferences::PreferencesCache,
+    preflight::{self, PreflightReport},
+    redis::{self, Redis},
+    routes::{
+        admin, calendar, health_check, notifications,
+        root::get_homepage,
+        settings, stats,
+        todo::{self, PgTodoRepo, TodoRepo, ViewCache, search},
+        tos,
+    },
+    storage::{self, FileStore},
+    telemetry, theme, toast,
+    urls::UrlBuilder,
+    worker::{
+        deactivation::DeleteDeactivatedUsersTask, email::DeliverEmailsTask,
+        history::PruneTodoHistoryTask, idempotency::ExpireIdempotencyKeysTask,
+        magic_link::ExpireMagicLinksTask, notifications::PruneNotificationsTask,
+        purge::PurgeDeletedTodosTask, refresh_token::ExpireRefreshTokensTask,
+        reminder::DueDateReminderTask, scheduler::Scheduler, webhook::DeliverWebhooksTask,
+    },
 };
 
+#[cfg(feature = "test-util")]
+use crate::query_count;
+
 pub struct Application {
     app: Router,
     listener: TcpListener,
@@ -28,93 +68,307 @@ pub struct Application {
 pub struct ApiContext {
     pub config: Config,
     pub db: PgPool,
+    /// What the auth backend and the todo handlers go through instead of
+    /// `db`, so they can be tested with in memory fakes
+    pub users: Arc<dyn UserRepo>,
+    pub(crate) todos: Arc<dyn TodoRepo>,
+    pub redis: Redis,
+    pub email_client: EmailClient,
+    /// `None` if the CAPTCHA isn't configured, see [`crate::captcha`]
+    pub captcha: Option<CaptchaClient>,
+    /// Absolute links to the site, for anything read outside of it
+    pub urls: UrlBuilder,
+    pub hasher: Hasher,
+    /// `None` if bearer tokens aren't configured
+    pub token_keys: Option<TokenKeys>,
+    pub webauthn: Webauthn,
+    pub trusted_proxies: TrustedProxies,
+    pub user_cache: UserCache,
+    /// See [`todo::view_cache`]
+    pub(crate) view_cache: ViewCache,
+    pub events: Arc<EventRegistry>,
+    pub features: Features,
+    /// Whether todo search can fall back to pg_trgm, see [`search::trigram_available`]
+    pub trigram_search: bool,
+    pub preferences: PreferencesCache,
+    pub audit: AuditLogger,
+    pub files: Box<dyn FileStore>,
 }
 
 pub type AppRouter = Router<Arc<ApiContext>>;
 
-impl Application {
-    pub async fn build(config: Config) -> Self {
-        let app_env = config.application_settings.app_env;
-        let ssl_mode = match app_env {
-            config::AppEnv::Development => PgSslMode::Prefer,
-            config::AppEnv::Staging | config::AppEnv::Production => PgSslMode::Require,
-        };
-
-        let db_connect_options =
-            PgConnectOptions::from_str(config.database_settings.database_url.expose_secret())
-                .expect("Failed to parse database url")
-                .ssl_mode(ssl_mode);
+#[cfg(test)]
+impl ApiContext {
+    /// For unit tests of the handlers, going through the given repos.
+    /// Postgres and Redis are never connected to, so handlers reaching past
+    /// the repos fail.
+    pub(crate) fn for_tests(users: Arc<dyn UserRepo>, todos: Arc<dyn TodoRepo>) -> Self {
+        let config = Config::for_tests(&[
+            "--hmac-key=0123456789012345678901234567890123456789012345678901234567890123",
+            // tests add the same todo several times
+            "--todo-repeat-window-secs=0",
+        ]);
+        let settings = &config.application_settings;
 
         let db = PgPoolOptions::new()
-            .connect_with(db_connect_options)
-            .await
-            .expect("Failed to connect to Postgres");
+            .connect_lazy(config.database_settings.database_url.expose_secret())
+            .expect("Invalid database url");
+        let redis = Redis::new(
+            fred::prelude::Builder::from_config(
+                fred::prelude::Config::from_url(config.database_settings.redis_url.expose_secret())
+                    .expect("Invalid redis url"),
+            )
+            .build_pool(1)
+            .expect("Failed to create redis pool"),
+            &config.database_settings.redis_key_prefix,
+        );
+        let email_settings = &config.email_client_settings;
 
-        sqlx::migrate!()
-            .run(&db)
-            .await
-            .expect("Failed to run migrations");
+        Self {
+            db: db.clone(),
+            users,
+            todos,
+            redis: redis.clone(),
+            email_client: EmailClient::new(
+                email_settings.email_base_url.clone(),
+                EmailAddress::parse(&email_settings.email_sender).expect("Invalid sender email"),
+                email_settings.email_authorization_token.clone(),
+                std::time::Duration::from_millis(email_settings.email_timeout_millis),
+            ),
+            captcha: None,
+            urls: UrlBuilder::from_settings(settings).expect("Invalid base url"),
+            hasher: Hasher::from_settings(settings).expect("Invalid hashing settings"),
+            token_keys: None,
+            webauthn: auth::webauthn_from_settings(settings).expect("Invalid passkey settings"),
+            trusted_proxies: TrustedProxies::from_settings(settings)
+                .expect("Invalid trusted proxies"),
+            user_cache: UserCache::new(redis.clone(), false),
+            view_cache: ViewCache::new(redis, false, 0),
+            events: Arc::new(EventRegistry::default()),
+            features: Features::from_settings(settings),
+            trigram_search: false,
+            preferences: PreferencesCache::default(),
+            audit: AuditLogger::spawn(db),
+            files: storage::from_settings(&config.storage_settings),
+            config,
+        }
+    }
+}
+
+/// Served under `/assets` unless they are embedded, relative to where the
+/// app is started
+const ASSETS_DIR: &str = "assets";
+
+impl Application {
+    /// Sets the app up, after [`preflight::run`] found nothing wrong
+    pub async fn build(config: Config) -> Result<Self, PreflightReport> {
+        preflight::run(&config, Path::new(ASSETS_DIR)).await?;
+
+        let app_env = config.application_settings.app_env;
+        let db = connect_db(&config).await;
 
         let address = format!(
             "{}:{}",
             config.application_settings.app_host, config.application_settings.app_port
         );
 
-        let redis_config = fred::prelude::Config::from_url(&format!(
-            "{}/1",
-            &config.database_settings.redis_url.expose_secret()
-        ))
-        .expect("Failed to configure redis client");
+        // preflight waited for it to come up, but it can go down again
+        let redis = redis::connect(&config)
+            .await
+            .map_err(|e| PreflightReport { failures: vec![e] })?;
 
-        let redis_pool = fred::prelude::Builder::from_config(redis_config)
-            .with_connection_config(|redis_config| {
-                redis_config.connection_timeout = std::time::Duration::from_secs(10);
-            })
-            // use exponential backoff, starting at 100 ms and doubling on each failed attempt up to 30 sec
-            .set_policy(ReconnectPolicy::new_exponential(0, 100, 30_000, 2))
-            .build_pool(100)
-            .expect("Failed to create redis pool");
-
-        redis_pool.init().await.expect("Failed to connect to redis");
-
-        let key = cookie::Key::from(
-            config
-                .application_settings
-                .hmac_key
-                .expose_secret()
-                .as_bytes(),
+        let session_keys = Arc::new(
+            SessionKeys::from_settings(&config.application_settings).expect("Invalid session keys"),
+        );
+        tracing::info!(
+            verification_keys = session_keys.previous_key_count(),
+            "Session cookie keys loaded"
         );
 
-        let session_store = RedisStore::new(redis_pool);
+        let session_store = RedisSessionStore::new(redis.clone());
         let session_layer = SessionManagerLayer::new(session_store)
+            .with_name(auth::SESSION_COOKIE)
             .with_secure(app_env == AppEnv::Production || app_env == AppEnv::Staging)
+            .with_same_site(config.application_settings.session_same_site.into())
             .with_expiry(tower_sessions::Expiry::OnInactivity(
                 cookie::time::Duration::seconds(3600),
             ))
-            .with_signed(key);
+            .with_signed(session_keys.signing_key());
 
-        let backend = crate::auth::Backend::new(db.clone());
+        let hasher = Hasher::from_settings(&config.application_settings)
+            .expect("Invalid password hashing settings");
+        let user_cache = UserCache::new(
+            redis.clone(),
+            config.application_settings.user_cache_enabled,
+        );
+        let view_cache = ViewCache::new(
+            redis.clone(),
+            config.application_settings.list_cache_enabled,
+            config.application_settings.list_cache_ttl_secs,
+        );
+        let users: Arc<dyn UserRepo> = Arc::new(PgUserRepo::new(db.clone()));
+        let backend = crate::auth::Backend::new(
+            users.clone(),
+            hasher.clone(),
+            user_cache.clone(),
+            config.application_settings.account_deactivation_grace_days,
+        );
         let auth_layer = AuthManagerLayerBuilder::new(backend, session_layer).build();
 
-        let serve_dir = ServeDir::new("assets");
+        let token_keys =
+            TokenKeys::from_settings(&config.application_settings).expect("Invalid JWT settings");
+        let webauthn = auth::webauthn_from_settings(&config.application_settings)
+            .expect("Invalid passkey settings");
+        let trusted_proxies = TrustedProxies::from_settings(&config.application_settings)
+            .expect("Invalid trusted proxies");
+
+        let urls =
+            UrlBuilder::from_settings(&config.application_settings).expect("Invalid base url");
+        let captcha = CaptchaClient::from_settings(&config.application_settings)
+            .expect("Invalid CAPTCHA settings");
+
+        let cors =
+            cors::from_settings(&config.application_settings).expect("Invalid CORS settings");
+        // the Sentry client itself is set up in `main`, before anything can go wrong
+        let reporting_errors = config.application_settings.sentry_dsn.is_some();
+
+        let email_settings = &config.email_client_settings;
+        let email_client = EmailClient::new(
+            email_settings.email_base_url.clone(),
+            EmailAddress::parse(&email_settings.email_sender).expect("Invalid sender email"),
+            email_settings.email_authorization_token.clone(),
+            std::time::Duration::from_millis(email_settings.email_timeout_millis),
+        );
+
+        let scheduler = Scheduler::default()
+            .register(PurgeDeletedTodosTask {
+                grace_period: std::time::Duration::from_secs(
+                    config.application_settings.todo_undo_grace_secs,
+                ),
+            })
+            .register(DueDateReminderTask {
+                interval: std::time::Duration::from_secs(
+                    config.application_settings.reminder_interval_secs,
+                ),
+            })
+            .register(PruneTodoHistoryTask {
+                retention_days: config.application_settings.history_retention_days,
+            })
+            .register(PruneNotificationsTask {
+                retention_days: config.application_settings.notification_retention_days,
+            })
+            .register(ExpireIdempotencyKeysTask)
+            .register(ExpireRefreshTokensTask)
+            .register(ExpireMagicLinksTask)
+            .register(DeleteDeactivatedUsersTask {
+                grace_days: config.application_settings.account_deactivation_grace_days,
+            })
+            .register(DeliverWebhooksTask {
+                interval: std::time::Duration::from_secs(
+                    config.application_settings.webhook_interval_secs,
+                ),
+                retry_base: std::time::Duration::from_secs(
+                    config.application_settings.webhook_retry_base_secs,
+                ),
+                max_failures: config.application_settings.webhook_max_failures,
+                allow_private_targets: config.application_settings.webhook_allow_private_targets,
+            })
+            .register(DeliverEmailsTask {
+                interval: std::time::Duration::from_secs(
+                    config.application_settings.email_interval_secs,
+                ),
+                retry_base: std::time::Duration::from_secs(
+                    config.application_settings.email_retry_base_secs,
+                ),
+                max_attempts: config.application_settings.email_max_attempts,
+            });
+
+        let audit = AuditLogger::spawn(db.clone());
+        let files = storage::from_settings(&config.storage_settings);
 
-        let api_context = ApiContext { config, db };
+        let features = Features::from_settings(&config.application_settings);
+        let trigram_search = search::trigram_available(&db).await;
 
-        let app = api_router()
-            .with_state(Arc::new(api_context))
-            .layer(MessagesManagerLayer)
+        #[cfg(feature = "test-util")]
+        let todos = Arc::new(PgTodoRepo::new(query_count::CountingPool::new(db.clone())));
+        #[cfg(not(feature = "test-util"))]
+        let todos = Arc::new(PgTodoRepo::new(db.clone()));
+
+        let api_context = Arc::new(ApiContext {
+            config,
+            todos,
+            db,
+            users,
+            redis,
+            email_client,
+            captcha,
+            urls,
+            hasher,
+            token_keys,
+            webauthn,
+            trusted_proxies,
+            user_cache,
+            view_cache,
+            events: Arc::new(EventRegistry::default()),
+            features,
+            trigram_search,
+            preferences: PreferencesCache::default(),
+            audit,
+            files,
+        });
+
+        scheduler.spawn(api_context.clone());
+
+        let app = Router::new()
+            .merge(web_router())
+            .nest("/api", api_router(cors))
+            .with_state(api_context.clone())
+            // these need the context and the session, so they sit between them
+            .layer(middleware::from_fn(
+                auth::impersonation::audit_impersonated_requests,
+            ))
+            .layer(middleware::from_fn(sessions::expire_old_sessions))
+            .layer(middleware::from_fn(maintenance::check))
+            // for middleware like the rate limits, which is set up before the state
+            .layer(Extension(api_context))
+            .layer(Extension(session_keys.clone()))
+            .layer(middleware::from_fn(telemetry::record_session_user))
+            // both use the session, the API has no other way to authenticate yet
             .layer(auth_layer)
-            .nest_service("/assets", serve_dir);
+            .layer(middleware::from_fn_with_state(
+                session_keys,
+                auth::resign_session_cookie,
+            ))
+            .nest_service("/assets", assets::router(Path::new(ASSETS_DIR)))
+            .layer(TraceLayer::new_for_http().make_span_with(telemetry::request_span));
+        let app = if reporting_errors {
+            app.layer(middleware::from_fn(error_reporting::tag_request))
+                .layer(NewSentryLayer::new_from_top())
+        } else {
+            app
+        };
+        #[cfg(feature = "test-util")]
+        let app = app.layer(middleware::from_fn(query_count::count_queries));
+        let app = app.layer(middleware::from_fn(telemetry::assign_request_id));
 
         let listener = TcpListener::bind(address)
             .await
             .expect("Failed to bind port");
 
-        Self { app, listener }
+        Ok(Self { app, listener })
     }
 
+    /// Serves until the process is told to stop, then lets the requests in
+    /// flight finish
     pub async fn run(self) {
-        axum::serve(self.listener, self.app).await.unwrap();
+        // the peer address is where the client address is looked for, see `ClientIp`
+        axum::serve(
+            self.listener,
+            self.app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap();
     }
 
     pub fn address(&self) -> String {
@@ -126,10 +380,87 @@ impl Application {
     }
 }
 
-fn api_router() -> AppRouter {
+/// Connects to Postgres, the schema is brought up to date by
+/// [`preflight::run`] or [`preflight::migrations`]
+pub async fn connect_db(config: &Config) -> PgPool {
+    let db_connect_options = db_connect_options(config).expect("Failed to parse database url");
+
+    PgPoolOptions::new()
+        .connect_with(db_connect_options)
+        .await
+        .expect("Failed to connect to Postgres")
+}
+
+pub fn db_connect_options(config: &Config) -> Result<PgConnectOptions, sqlx::Error> {
+    let ssl_mode = match config.application_settings.app_env {
+        config::AppEnv::Development => PgSslMode::Prefer,
+        config::AppEnv::Staging | config::AppEnv::Production => PgSslMode::Require,
+    };
+
+    Ok(
+        PgConnectOptions::from_str(config.database_settings.database_url.expose_secret())?
+            .ssl_mode(ssl_mode),
+    )
+}
+
+/// The pages and the form handlers they post to, which answer with HTML
+/// or plain text and redirect to the login page
+fn web_router() -> AppRouter {
     Router::new()
         .route("/", get(get_homepage))
+        .route("/theme", post(theme::switch_theme))
         .merge(health_check::router())
         .merge(todo::router())
+        .merge(settings::router())
+        .merge(stats::router())
+        .merge(notifications::router())
+        .merge(admin::router())
         .merge(auth::router())
+        .merge(calendar::router())
+        .merge(tos::router())
+        .merge(catch_panic::test_router())
+        .layer(middleware::from_fn(sessions::flash_session_end))
+        .layer(MessagesManagerLayer)
+        .layer(CatchPanicLayer::custom(catch_panic::web_response))
+        .layer(middleware::from_fn(toast::error_toasts))
+}
+
+/// The JSON routes under `/api`, which only ever answer with JSON, errors
+/// as an [`ApiError`](crate::api_error::ApiError) body. Layers for browsers,
+/// like flash messages, are left to [`web_router`], while CORS is only
+/// allowed here.
+fn api_router(cors: CorsLayer) -> AppRouter {
+    Router::new()
+        .merge(auth::api_router())
+        .merge(todo::api_router())
+        .merge(catch_panic::test_router())
+        .fallback(api_error::route_not_found)
+        .method_not_allowed_fallback(api_error::method_not_allowed)
+        .layer(CatchPanicLayer::custom(catch_panic::api_response))
+        .layer(middleware::from_fn(toast::error_toasts))
+        .layer(cors)
+}
+
+/// Ctrl-C, or SIGTERM from whatever manages the process
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for ctrl-c");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+    tracing::info!("Shutting down");
 }
@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use axum::Router;
+
+/// `/assets`, compiled into the binary with the `embed-assets` feature.
+/// Otherwise they are read from `dir`, so they can be edited while the app
+/// runs.
+pub fn router(dir: &Path) -> Router {
+    #[cfg(feature = "embed-assets")]
+    {
+        let _ = dir;
+        embedded::router()
+    }
+    #[cfg(not(feature = "embed-assets"))]
+    {
+        Router::new().fallback_service(tower_http::services::ServeDir::new(dir))
+    }
+}
+
+#[cfg(feature = "embed-assets")]
+mod embedded {
+    use axum::{
+        Router,
+        extract::Path,
+        response::{IntoResponse, Response},
+        routing::get,
+    };
+    use http::{HeaderMap, HeaderValue, StatusCode, header};
+    use rust_embed::Embed;
+
+    #[derive(Embed)]
+    #[folder = "assets/"]
+    struct Assets;
+
+    /// A year, the ETag tells browsers whether their copy is still current
+    /// when they ask again after a deploy
+    const CACHE_CONTROL: &str = "public, max-age=31536000";
+
+    pub fn router() -> Router {
+        Router::new().route("/{*path}", get(get_asset))
+    }
+
+    async fn get_asset(Path(path): Path<String>, headers: HeaderMap) -> Response {
+        let Some(file) = Assets::get(&path) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+
+        let etag = format!("\"{}\"", hex::encode(file.metadata.sha256_hash()));
+        let cached = headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+        let etag = HeaderValue::from_str(&etag).expect("Hex is a valid header value");
+
+        if cached {
+            return (
+                StatusCode::NOT_MODIFIED,
+                [
+                    (header::ETAG, etag),
+                    (
+                        header::CACHE_CONTROL,
+                        HeaderValue::from_static(CACHE_CONTROL),
+                    ),
+                ],
+            )
+                .into_response();
+        }
+
+        let content_type = HeaderValue::from_str(file.metadata.mimetype())
+            .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+        (
+            [
+                (header::CONTENT_TYPE, content_type),
+                (header::ETAG, etag),
+                (
+                    header::CACHE_CONTROL,
+                    HeaderValue::from_static(CACHE_CONTROL),
+                ),
+            ],
+            file.data,
+        )
+            .into_response()
+    }
+}
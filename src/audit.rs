@@ -0,0 +1,332 @@
+use std::convert::Infallible;
+
+use anyhow::Context;
+use axum::extract::FromRequestParts;
+use http::{header, request::Parts};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{auth::impersonation, client_ip::ClientIp};
+
+/// Entries waiting to be written before new ones are dropped
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditEvent {
+    LoginSucceeded,
+    LoginFailed,
+    Logout,
+    Registered,
+    RegistrationBlocked,
+    AccountDeleted,
+    AccountDeactivated,
+    AccountReactivated,
+    AccountLocked,
+    AccountUnlocked,
+    EmailChangeRequested,
+    EmailChanged,
+    UsernameChanged,
+    PasskeyAdded,
+    PasskeyRemoved,
+    SuspiciousPasskeyUse,
+    TodoLimitChanged,
+    ImpersonationStarted,
+    ImpersonationEnded,
+    ImpersonatedRequest,
+    TosAccepted,
+}
+
+impl AuditEvent {
+    pub const ALL: [AuditEvent; 21] = [
+        AuditEvent::LoginSucceeded,
+        AuditEvent::LoginFailed,
+        AuditEvent::Logout,
+        AuditEvent::Registered,
+        AuditEvent::RegistrationBlocked,
+        AuditEvent::AccountDeleted,
+        AuditEvent::AccountDeactivated,
+        AuditEvent::AccountReactivated,
+        AuditEvent::AccountLocked,
+        AuditEvent::AccountUnlocked,
+        AuditEvent::EmailChangeRequested,
+        AuditEvent::EmailChanged,
+        AuditEvent::UsernameChanged,
+        AuditEvent::PasskeyAdded,
+        AuditEvent::PasskeyRemoved,
+        AuditEvent::SuspiciousPasskeyUse,
+        AuditEvent::TodoLimitChanged,
+        AuditEvent::ImpersonationStarted,
+        AuditEvent::ImpersonationEnded,
+        AuditEvent::ImpersonatedRequest,
+        AuditEvent::TosAccepted,
+    ];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|event| event.as_str() == s)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEvent::LoginSucceeded => "login_succeeded",
+            AuditEvent::LoginFailed => "login_failed",
+            AuditEvent::Logout => "logout",
+            AuditEvent::Registered => "registered",
+            AuditEvent::RegistrationBlocked => "registration_blocked",
+            AuditEvent::AccountDeleted => "account_deleted",
+            AuditEvent::AccountDeactivated => "account_deactivated",
+            AuditEvent::AccountReactivated => "account_reactivated",
+            AuditEvent::AccountLocked => "account_locked",
+            AuditEvent::AccountUnlocked => "account_unlocked",
+            AuditEvent::EmailChangeRequested => "email_change_requested",
+            AuditEvent::EmailChanged => "email_changed",
+            AuditEvent::UsernameChanged => "username_changed",
+            AuditEvent::PasskeyAdded => "passkey_added",
+            AuditEvent::PasskeyRemoved => "passkey_removed",
+            AuditEvent::SuspiciousPasskeyUse => "suspicious_passkey_use",
+            AuditEvent::TodoLimitChanged => "todo_limit_changed",
+            AuditEvent::ImpersonationStarted => "impersonation_started",
+            AuditEvent::ImpersonationEnded => "impersonation_ended",
+            AuditEvent::ImpersonatedRequest => "impersonated_request",
+            AuditEvent::TosAccepted => "tos_accepted",
+        }
+    }
+}
+
+impl std::fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Who made a request, as far as the audit log is concerned.
+///
+/// The address is the client's as seen through the trusted proxies, see
+/// [`ClientIp`]. Requests of an admin logged in as someone else carry the
+/// admin's id, see [`impersonation`].
+#[derive(Debug, Clone, Default)]
+pub struct RequestMetadata {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub impersonator_id: Option<Uuid>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for RequestMetadata {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ClientIp(ip_address) = ClientIp::from_request_parts(parts, state).await?;
+        let ip_address = ip_address.map(|ip| ip.to_string());
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        // routes without sessions, like the bearer token ones, can't impersonate
+        let impersonator_id = match Session::from_request_parts(parts, state).await {
+            Ok(session) => match impersonation::impersonator(&session).await {
+                Ok(impersonator) => impersonator.map(|impersonator| impersonator.user_id),
+                Err(e) => {
+                    tracing::warn!(error = ?e, "Failed to get the impersonator of the request");
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+
+        Ok(Self {
+            ip_address,
+            user_agent,
+            impersonator_id,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditEntry {
+    user_id: Option<Uuid>,
+    event: AuditEvent,
+    request: RequestMetadata,
+    metadata: serde_json::Value,
+}
+
+impl AuditEntry {
+    pub fn new(event: AuditEvent, user_id: Option<Uuid>, request: &RequestMetadata) -> Self {
+        Self {
+            user_id,
+            event,
+            request: request.clone(),
+            metadata: serde_json::json!({}),
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: serde_json::Value) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+/// Writes audit entries in the background so handlers never wait on them.
+///
+/// When the writer falls behind and the channel is full, entries are dropped
+/// with a warning instead of slowing requests down.
+#[derive(Debug, Clone)]
+pub struct AuditLogger {
+    sender: mpsc::Sender<AuditEntry>,
+}
+
+impl AuditLogger {
+    /// Spawns the task that writes entries to the database
+    pub fn spawn(db: PgPool) -> Self {
+        let (sender, mut receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        tokio::spawn(async move {
+            while let Some(entry) = receiver.recv().await {
+                if let Err(e) = insert_entry(&db, entry).await {
+                    tracing::warn!(error = ?e, "Failed to write audit log entry");
+                }
+            }
+        });
+
+        Self { sender }
+    }
+
+    pub fn record(&self, entry: AuditEntry) {
+        match self.sender.try_send(entry) {
+            Ok(()) => {}
+            Err(TrySendError::Full(entry)) => {
+                tracing::warn!(event = %entry.event, user_id = ?entry.user_id, "Audit log is full, dropping entry");
+            }
+            Err(TrySendError::Closed(entry)) => {
+                tracing::warn!(event = %entry.event, user_id = ?entry.user_id, "Audit log writer stopped, dropping entry");
+            }
+        }
+    }
+}
+
+/// Writes the entry before returning, unlike [`AuditLogger::record`], for
+/// what mustn't happen without being recorded
+pub async fn record_now(db: &PgPool, entry: AuditEntry) -> Result<(), anyhow::Error> {
+    insert_entry(db, entry).await
+}
+
+async fn insert_entry(db: &PgPool, entry: AuditEntry) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log
+            (user_id, event_type, ip_address, user_agent, metadata, impersonator_id)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        entry.user_id,
+        entry.event.as_str(),
+        entry.request.ip_address,
+        entry.request.user_agent,
+        entry.metadata,
+        entry.request.impersonator_id
+    )
+    .execute(db)
+    .await
+    .context("Failed to insert audit log entry")?;
+
+    Ok(())
+}
+
+pub struct AuditLogRow {
+    pub username: Option<String>,
+    pub event_type: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub metadata: serde_json::Value,
+    /// The admin logged in as the user at the time
+    pub impersonator: Option<String>,
+    pub created_at: OffsetDateTime,
+}
+
+/// Newest entries first. `username` also matches failed logins and deleted
+/// accounts, which only carry the username in their metadata.
+pub async fn fetch_entries(
+    db: &PgPool,
+    username: Option<&str>,
+    event: Option<AuditEvent>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<AuditLogRow>, anyhow::Error> {
+    sqlx::query_as!(
+        AuditLogRow,
+        r#"
+        SELECT
+            COALESCE(ui.username, al.metadata->>'username') AS username,
+            al.event_type, al.ip_address, al.user_agent, al.metadata,
+            im.username AS "impersonator?", al.created_at
+        FROM audit_log AS al
+        LEFT JOIN user_info AS ui ON ui.user_id = al.user_id
+        LEFT JOIN user_info AS im ON im.user_id = al.impersonator_id
+        WHERE ($1::text IS NULL OR ui.username = $1 OR al.metadata->>'username' = lower($1))
+            AND ($2::text IS NULL OR al.event_type = $2)
+        ORDER BY al.created_at DESC
+        LIMIT $3 OFFSET $4
+        "#,
+        username,
+        event.map(|event| event.as_str()),
+        limit,
+        offset
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to get audit log entries")
+}
+
+/// The security events of one user, for showing on their own settings page
+pub async fn fetch_user_entries(
+    db: &PgPool,
+    user_id: Uuid,
+    limit: i64,
+) -> Result<Vec<AuditLogRow>, anyhow::Error> {
+    sqlx::query_as!(
+        AuditLogRow,
+        r#"
+        SELECT
+            ui.username AS "username?", al.event_type, al.ip_address, al.user_agent,
+            al.metadata, im.username AS "impersonator?", al.created_at
+        FROM audit_log AS al
+        JOIN user_info AS ui ON ui.user_id = al.user_id
+        LEFT JOIN user_info AS im ON im.user_id = al.impersonator_id
+        WHERE al.user_id = $1
+        ORDER BY al.created_at DESC
+        LIMIT $2
+        "#,
+        user_id,
+        limit
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to get audit log entries")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn full_channel_drops_entries_without_blocking() {
+        let (sender, mut receiver) = mpsc::channel(1);
+        let logger = AuditLogger { sender };
+        let request = RequestMetadata::default();
+
+        logger.record(AuditEntry::new(AuditEvent::LoginSucceeded, None, &request));
+        logger.record(AuditEntry::new(AuditEvent::Logout, None, &request));
+
+        let entry = receiver.try_recv().unwrap();
+        assert_eq!(AuditEvent::LoginSucceeded, entry.event);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn event_names_are_unique() {
+        let mut names: Vec<_> = AuditEvent::ALL.iter().map(AuditEvent::as_str).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(AuditEvent::ALL.len(), names.len());
+    }
+}
@@ -0,0 +1,354 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, anyhow};
+use axum::extract::FromRequestParts;
+use axum_login::AuthnBackend;
+use http::{StatusCode, header, request::Parts};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, PgPool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    api_error::ApiError,
+    app::ApiContext,
+    auth::{AuthSession, User},
+    config::ApplicationSettings,
+    routes::tos,
+    telemetry,
+};
+
+/// Shorter keys are easy to brute force offline from any token
+const MIN_KEY_BYTES: usize = 32;
+
+/// Signs the access tokens of API clients and checks the ones they send.
+///
+/// Tokens are checked against the signing key and then each of the previous
+/// ones, so after a rotation tokens signed with the old key keep working
+/// until they expire.
+pub struct TokenKeys {
+    signing_key: EncodingKey,
+    verification_keys: Vec<DecodingKey>,
+    validation: Validation,
+    access_token_ttl: Duration,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct AccessClaims {
+    sub: Uuid,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Invalid or expired access token")]
+pub struct InvalidToken;
+
+impl TokenKeys {
+    /// `None` if no signing key is set, which turns bearer tokens off
+    pub fn from_settings(settings: &ApplicationSettings) -> Result<Option<Self>, anyhow::Error> {
+        let previous_keys = settings
+            .jwt_verification_keys
+            .as_ref()
+            .map(|keys| {
+                keys.expose_secret()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|key| !key.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let Some(signing_key) = &settings.jwt_signing_key else {
+            if !previous_keys.is_empty() {
+                return Err(anyhow!(
+                    "JWT verification keys are set without a signing key"
+                ));
+            }
+            return Ok(None);
+        };
+
+        Self::new(
+            signing_key.expose_secret(),
+            &previous_keys,
+            Duration::from_secs(settings.access_token_ttl_secs),
+        )
+        .map(Some)
+    }
+
+    fn new(
+        signing_key: &str,
+        previous_keys: &[String],
+        access_token_ttl: Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let keys = std::iter::once(signing_key).chain(previous_keys.iter().map(String::as_str));
+        let mut verification_keys = Vec::new();
+        for key in keys {
+            if key.len() < MIN_KEY_BYTES {
+                return Err(anyhow!("JWT keys must be at least {MIN_KEY_BYTES} bytes"));
+            }
+            verification_keys.push(DecodingKey::from_secret(key.as_bytes()));
+        }
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        // tokens are only checked by the server that issued them
+        validation.leeway = 0;
+
+        Ok(Self {
+            signing_key: EncodingKey::from_secret(signing_key.as_bytes()),
+            verification_keys,
+            validation,
+            access_token_ttl,
+        })
+    }
+
+    pub fn access_token_ttl(&self) -> Duration {
+        self.access_token_ttl
+    }
+
+    pub fn issue(&self, user_id: Uuid) -> Result<String, anyhow::Error> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let claims = AccessClaims {
+            sub: user_id,
+            iat: now,
+            exp: now + self.access_token_ttl.as_secs() as i64,
+        };
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.signing_key)
+            .context("Failed to sign access token")
+    }
+
+    /// The id of the user the token was issued to
+    pub fn verify(&self, token: &str) -> Result<Uuid, InvalidToken> {
+        self.verification_keys
+            .iter()
+            .find_map(|key| jsonwebtoken::decode::<AccessClaims>(token, key, &self.validation).ok())
+            .map(|data| data.claims.sub)
+            .ok_or(InvalidToken)
+    }
+}
+
+/// The user of an `/api` request, from the `Authorization: Bearer` access
+/// token if there is one and from the session otherwise.
+///
+/// A request with a bad token is rejected rather than falling back to the
+/// session, so a client notices it has to refresh.
+pub struct ApiUser(pub User);
+
+impl FromRequestParts<Arc<ApiContext>> for ApiUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<ApiContext>,
+    ) -> Result<Self, Self::Rejection> {
+        let auth_session = AuthSession::from_request_parts(parts, state)
+            .await
+            .map_err(|(_, e)| ApiError::internal(&anyhow!("Failed to get auth session: {e}")))?;
+
+        let Some(authorization) = parts.headers.get(header::AUTHORIZATION) else {
+            let user = auth_session.user.ok_or_else(|| {
+                ApiError::new(
+                    StatusCode::UNAUTHORIZED,
+                    "not_logged_in",
+                    "You need to be logged in",
+                )
+            })?;
+            return require_accepted_tos(state, user);
+        };
+
+        let invalid_token = || {
+            ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_access_token",
+                InvalidToken,
+            )
+        };
+        let token = authorization
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(invalid_token)?;
+        let user_id = state
+            .token_keys
+            .as_ref()
+            .ok_or_else(invalid_token)?
+            .verify(token.trim())
+            .map_err(|_| invalid_token())?;
+
+        // locked, deactivated and deleted users are not found, whatever their
+        // token says
+        let user = auth_session
+            .backend
+            .get_user(&user_id)
+            .await
+            .map_err(|e| ApiError::internal(&anyhow!(e)))?
+            .ok_or_else(invalid_token)?;
+        telemetry::record_user_id(user.user_id());
+        require_accepted_tos(state, user)
+    }
+}
+
+/// API clients get an error to show instead of the redirect the pages get,
+/// see [`crate::routes::tos::require_accepted_tos`]
+fn require_accepted_tos(api_context: &ApiContext, user: User) -> Result<ApiUser, ApiError> {
+    if !user.accepted_tos(api_context.config.application_settings.tos_version) {
+        return Err(tos::tos_not_accepted());
+    }
+    Ok(ApiUser(user))
+}
+
+/// New random refresh token. Only its hash is stored, like the calendar feed tokens.
+pub fn generate_refresh_token() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token))
+}
+
+pub async fn store_refresh_token(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+    token: &str,
+    ttl_days: i32,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO refresh_tokens (token_hash, user_id, expires_at)
+        VALUES ($1, $2, NOW() + make_interval(days => $3))
+        "#,
+        hash_refresh_token(token),
+        user_id,
+        ttl_days
+    )
+    .execute(executor)
+    .await
+    .context("Failed to store refresh token")?;
+    Ok(())
+}
+
+/// Revokes a refresh token that is still valid and returns its user, so
+/// each token can only be exchanged once
+pub async fn use_refresh_token(
+    executor: impl PgExecutor<'_>,
+    token: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    sqlx::query_scalar!(
+        r#"
+        UPDATE refresh_tokens SET revoked_at = NOW()
+        WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > NOW()
+        RETURNING user_id
+        "#,
+        hash_refresh_token(token)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to use refresh token")
+}
+
+/// Revokes every refresh token of a user, their access tokens run out on their own
+pub async fn revoke_refresh_tokens(db: &PgPool, user_id: Uuid) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE refresh_tokens SET revoked_at = NOW()
+        WHERE user_id = $1 AND revoked_at IS NULL
+        "#,
+        user_id
+    )
+    .execute(db)
+    .await
+    .context("Failed to revoke refresh tokens")?;
+    Ok(())
+}
+
+/// Deletes refresh tokens that can't be used anymore, returning how many
+pub async fn delete_expired_refresh_tokens(db: &PgPool) -> Result<u64, anyhow::Error> {
+    let query_result = sqlx::query!(
+        r#"
+        DELETE FROM refresh_tokens WHERE expires_at < NOW() OR revoked_at < NOW() - interval '1 day'
+        "#
+    )
+    .execute(db)
+    .await
+    .context("Failed to delete expired refresh tokens")?;
+
+    Ok(query_result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use claims::{assert_err, assert_ok_eq};
+    use jsonwebtoken::{EncodingKey, Header};
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    use crate::auth::bearer::{AccessClaims, TokenKeys};
+
+    const KEY: &str = "0123456789abcdef0123456789abcdef";
+    const OLD_KEY: &str = "fedcba9876543210fedcba9876543210";
+
+    fn keys(signing_key: &str, previous_keys: &[&str]) -> TokenKeys {
+        let previous_keys: Vec<String> = previous_keys.iter().map(|k| k.to_string()).collect();
+        TokenKeys::new(signing_key, &previous_keys, Duration::from_secs(60)).unwrap()
+    }
+
+    #[test]
+    pub fn issued_tokens_are_accepted() {
+        let keys = keys(KEY, &[]);
+        let user_id = Uuid::new_v4();
+        let token = keys.issue(user_id).unwrap();
+        assert_ok_eq!(keys.verify(&token), user_id);
+    }
+
+    #[test]
+    pub fn tokens_of_previous_keys_are_accepted_after_a_rotation() {
+        let user_id = Uuid::new_v4();
+        let token = keys(OLD_KEY, &[]).issue(user_id).unwrap();
+
+        assert_ok_eq!(keys(KEY, &[OLD_KEY]).verify(&token), user_id);
+        assert_err!(keys(KEY, &[]).verify(&token));
+    }
+
+    #[test]
+    pub fn expired_tokens_are_rejected() {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let claims = AccessClaims {
+            sub: Uuid::new_v4(),
+            iat: now - 120,
+            exp: now - 1,
+        };
+        let token = jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(KEY.as_bytes()),
+        )
+        .unwrap();
+        assert_err!(keys(KEY, &[]).verify(&token));
+    }
+
+    #[test]
+    pub fn tampered_and_unsigned_tokens_are_rejected() {
+        let keys = keys(KEY, &[]);
+        let token = keys.issue(Uuid::new_v4()).unwrap();
+        let (rest, _signature) = token.rsplit_once('.').unwrap();
+        assert_err!(keys.verify(&format!("{rest}.")));
+
+        let mut tampered = token.into_bytes();
+        let middle = tampered.len() / 2;
+        tampered[middle] = if tampered[middle] == b'A' { b'B' } else { b'A' };
+        assert_err!(keys.verify(&String::from_utf8(tampered).unwrap()));
+    }
+
+    #[test]
+    pub fn short_keys_are_rejected() {
+        assert!(TokenKeys::new("short", &[], Duration::from_secs(60)).is_err());
+        let previous_keys = ["short".to_string()];
+        assert!(TokenKeys::new(KEY, &previous_keys, Duration::from_secs(60)).is_err());
+    }
+}
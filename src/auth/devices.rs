@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use time::{OffsetDateTime, macros::format_description};
+use uuid::Uuid;
+
+use crate::{
+    app::ApiContext,
+    audit::RequestMetadata,
+    domain::{device::Device, email_address::EmailAddress},
+    emails::{self, Email, NewDevice},
+};
+
+/// Remembers the device a user logged in from and emails them when it's one
+/// they haven't used before.
+///
+/// Runs in a spawned task so logins don't wait on the lookup.
+pub fn check_login_device(api_context: Arc<ApiContext>, user_id: Uuid, request: RequestMetadata) {
+    tokio::spawn(async move {
+        if let Err(e) = notify_on_new_device(&api_context, user_id, &request).await {
+            tracing::warn!(error = ?e, %user_id, "Failed to check login device");
+        }
+    });
+}
+
+async fn notify_on_new_device(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    request: &RequestMetadata,
+) -> Result<(), anyhow::Error> {
+    let device = Device::new(request.user_agent.as_deref(), request.ip_address.as_deref());
+
+    // CTEs see the table as it was before the insert, so `has_devices` tells
+    // whether this is the first device ever, which isn't worth an alert
+    let login = sqlx::query!(
+        r#"
+        WITH known AS (
+            SELECT EXISTS(SELECT 1 FROM known_devices WHERE user_id = $1) AS has_devices
+        ), upsert AS (
+            INSERT INTO known_devices (user_id, user_agent_family, network)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, user_agent_family, network)
+            DO UPDATE SET last_seen_at = NOW()
+            RETURNING (xmax = 0) AS is_new
+        )
+        SELECT
+            known.has_devices AS "has_devices!", upsert.is_new AS "is_new!",
+            ui.email, ui.new_device_alerts
+        FROM known, upsert, user_info AS ui
+        WHERE ui.user_id = $1
+        "#,
+        user_id,
+        device.user_agent_family(),
+        device.network()
+    )
+    .fetch_one(&api_context.db)
+    .await
+    .context("Failed to record login device")?;
+
+    if !(login.is_new && login.has_devices && login.new_device_alerts) {
+        return Ok(());
+    }
+
+    let recipient = EmailAddress::parse(&login.email).context("Stored email is invalid")?;
+    let time = OffsetDateTime::now_utc()
+        .format(format_description!(
+            "[year]-[month]-[day] [hour]:[minute] UTC"
+        ))
+        .context("Failed to format login time")?;
+    let ip_address = request.ip_address.as_deref().unwrap_or("unknown");
+    let alert = Email::NewDevice(NewDevice {
+        user_agent_family: device.user_agent_family().to_string(),
+        time,
+        ip_address: ip_address.to_string(),
+    });
+    emails::enqueue(&api_context.db, &recipient, &alert)
+        .await
+        .context("Failed to queue new device email")?;
+
+    Ok(())
+}
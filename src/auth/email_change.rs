@@ -0,0 +1,272 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Form,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{AppendHeaders, IntoResponse},
+};
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{
+    api_error::ApiError,
+    app::ApiContext,
+    audit::{AuditEntry, AuditEvent, RequestMetadata},
+    auth::{ApiUser, AuthSession, sessions},
+    domain::email_address::{EmailAddress, InvalidEmailError},
+    emails::{self, ConfirmEmailChange, Email, EmailChangeRequested},
+    page::PageContext,
+};
+
+/// How long a confirmation link stays valid, in hours
+const TOKEN_LIFETIME_HOURS: i32 = 24;
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmailChangeError {
+    #[error("Invalid email address")]
+    InvalidEmail(#[from] InvalidEmailError),
+    #[error("Email already exists")]
+    EmailExists,
+    #[error("This is already your email address")]
+    SameEmail,
+    #[error("This confirmation link is invalid or has expired")]
+    InvalidToken,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for EmailChangeError {
+    fn into_response(self) -> axum::response::Response {
+        let message = self.to_string();
+        let error = match self {
+            EmailChangeError::InvalidEmail(_) => {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_email", message).with_field("email")
+            }
+            EmailChangeError::SameEmail => {
+                ApiError::new(StatusCode::BAD_REQUEST, "same_email", message).with_field("email")
+            }
+            EmailChangeError::InvalidToken => {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_token", message)
+            }
+            EmailChangeError::EmailExists => {
+                ApiError::new(StatusCode::CONFLICT, "email_taken", message).with_field("email")
+            }
+            EmailChangeError::UnexpectedError(e) => ApiError::internal(&e),
+        };
+        error.into_response()
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailChangeFormData {
+    email: String,
+}
+
+/// Starts an email change, which only takes effect once the new address is
+/// confirmed, so a hijacked session can't quietly take over the account
+pub async fn request_email_change(
+    State(api_context): State<Arc<ApiContext>>,
+    ApiUser(user): ApiUser,
+    request: RequestMetadata,
+    Form(form_data): Form<EmailChangeFormData>,
+) -> Result<impl IntoResponse, EmailChangeError> {
+    let new_email = EmailAddress::parse(&form_data.email)?;
+
+    let current_email = sqlx::query_scalar!(
+        r#"
+        SELECT email FROM user_info WHERE user_id = $1
+        "#,
+        user.user_id()
+    )
+    .fetch_one(&api_context.db)
+    .await
+    .context("Failed to get current email")?;
+    let current_email = EmailAddress::parse(&current_email).context("Stored email is invalid")?;
+
+    // stored addresses are lowercase, like parsed ones
+    if current_email.as_ref() == new_email.as_ref() {
+        return Err(EmailChangeError::SameEmail);
+    }
+    if email_taken(&api_context, &new_email).await? {
+        return Err(EmailChangeError::EmailExists);
+    }
+
+    let token = Uuid::new_v4().simple().to_string();
+    let mut transaction = api_context
+        .db
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO pending_email_changes (token, user_id, new_email) VALUES ($1, $2, $3)
+        "#,
+        token,
+        user.user_id(),
+        new_email.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store pending email change")?;
+    emails::enqueue(
+        &mut *transaction,
+        &new_email,
+        &Email::ConfirmEmailChange(ConfirmEmailChange {
+            token,
+            lifetime_hours: TOKEN_LIFETIME_HOURS,
+        }),
+    )
+    .await?;
+    emails::enqueue(
+        &mut *transaction,
+        &current_email,
+        &Email::EmailChangeRequested(EmailChangeRequested {
+            new_email: new_email.as_ref().to_string(),
+        }),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    api_context.audit.record(
+        AuditEntry::new(
+            AuditEvent::EmailChangeRequested,
+            Some(user.user_id()),
+            &request,
+        )
+        .with_metadata(serde_json::json!({ "new_email": new_email.as_ref() })),
+    );
+
+    Ok((
+        StatusCode::ACCEPTED,
+        "Check your new email address for a confirmation link",
+    ))
+}
+
+async fn email_taken(
+    api_context: &ApiContext,
+    email: &EmailAddress,
+) -> Result<bool, anyhow::Error> {
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM user_info WHERE email = $1) AS "exists!"
+        "#,
+        email.as_ref()
+    )
+    .fetch_one(&api_context.db)
+    .await
+    .context("Failed to check if email exists")?;
+
+    Ok(exists)
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "auth/email_confirmed.html")]
+pub struct EmailConfirmedTemplate {
+    page_context: PageContext,
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmEmailQuery {
+    token: String,
+}
+
+pub async fn confirm_email_change(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    session: Session,
+    request: RequestMetadata,
+    page_context: PageContext,
+    Query(query): Query<ConfirmEmailQuery>,
+) -> Result<impl IntoResponse, EmailChangeError> {
+    let mut transaction = api_context
+        .db
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+
+    // locking the row makes a second click wait and then see the token as used
+    let pending = sqlx::query!(
+        r#"
+        SELECT user_id, new_email
+        FROM pending_email_changes
+        WHERE token = $1
+            AND used_at IS NULL
+            AND created_at > NOW() - make_interval(hours => $2)
+        FOR UPDATE
+        "#,
+        query.token,
+        TOKEN_LIFETIME_HOURS
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to get pending email change")?
+    .ok_or(EmailChangeError::InvalidToken)?;
+
+    // someone may have registered the address since the change was requested,
+    // the unique constraint catches anyone doing it right now
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_info SET email = $2, email_verified_at = NOW() WHERE user_id = $1
+        "#,
+        pending.user_id,
+        pending.new_email
+    )
+    .execute(&mut *transaction)
+    .await;
+    match result {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return Err(EmailChangeError::EmailExists);
+        }
+        Err(e) => {
+            return Err(anyhow::Error::from(e)
+                .context("Failed to update email")
+                .into());
+        }
+    }
+
+    // other pending changes of the user are stale now
+    sqlx::query!(
+        r#"
+        UPDATE pending_email_changes SET used_at = NOW()
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        pending.user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to mark email change as used")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    // the link is usually opened where the user is logged in, and whoever
+    // controls the email address now controls the account
+    let user_id = auth_session.user.as_ref().map(|user| user.user_id());
+    if user_id == Some(pending.user_id) {
+        sessions::rotate_session(&api_context.redis, &session, user_id).await?;
+    }
+
+    api_context.audit.record(
+        AuditEntry::new(AuditEvent::EmailChanged, Some(pending.user_id), &request)
+            .with_metadata(serde_json::json!({ "new_email": pending.new_email })),
+    );
+
+    Ok((
+        AppendHeaders([("Cache-Control", "no-store")]),
+        EmailConfirmedTemplate {
+            page_context,
+            email: pending.new_email,
+        },
+    ))
+}
@@ -0,0 +1,80 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use time::{Duration, OffsetDateTime};
+
+/// A token older than this is rejected, so one can't be fetched once and
+/// reused forever
+const MAX_TOKEN_AGE: Duration = Duration::days(1);
+
+/// Signs the time the form was served, so the handler can tell how long it
+/// took to fill in. The token is `<unix millis>.<hex signature>`.
+pub fn issue(key: &[u8], issued_at: OffsetDateTime) -> String {
+    let timestamp = (issued_at.unix_timestamp_nanos() / 1_000_000).to_string();
+    let signature = hex::encode(mac(key, &timestamp).finalize().into_bytes());
+    format!("{timestamp}.{signature}")
+}
+
+/// How long ago the token was issued, `None` if it is forged, malformed or
+/// too old
+pub fn age(key: &[u8], token: &str, now: OffsetDateTime) -> Option<Duration> {
+    let (timestamp, signature) = token.split_once('.')?;
+    let signature = hex::decode(signature).ok()?;
+    mac(key, timestamp).verify_slice(&signature).ok()?;
+
+    let issued_at =
+        OffsetDateTime::from_unix_timestamp_nanos(timestamp.parse::<i128>().ok()? * 1_000_000)
+            .ok()?;
+    let age = now - issued_at;
+    (age <= MAX_TOKEN_AGE).then_some(age)
+}
+
+fn mac(key: &[u8], timestamp: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_none, assert_some_eq};
+    use time::{Duration, OffsetDateTime};
+
+    use crate::auth::form_token::{age, issue};
+
+    const KEY: &[u8] = b"some key";
+
+    #[test]
+    pub fn token_tells_how_long_ago_it_was_issued() {
+        // whole milliseconds, as the token doesn't keep anything finer
+        let issued_at = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let token = issue(KEY, issued_at);
+        assert_some_eq!(
+            age(KEY, &token, issued_at + Duration::seconds(5)),
+            Duration::seconds(5)
+        );
+    }
+
+    #[test]
+    pub fn token_signed_with_another_key_is_rejected() {
+        let issued_at = OffsetDateTime::now_utc();
+        let token = issue(b"another key", issued_at);
+        assert_none!(age(KEY, &token, issued_at));
+    }
+
+    #[test]
+    pub fn token_with_a_changed_timestamp_is_rejected() {
+        let issued_at = OffsetDateTime::now_utc();
+        let token = issue(KEY, issued_at);
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!("0.{signature}");
+        assert_none!(age(KEY, &forged, issued_at));
+        assert_none!(age(KEY, "not a token", issued_at));
+    }
+
+    #[test]
+    pub fn token_older_than_a_day_is_rejected() {
+        let issued_at = OffsetDateTime::now_utc();
+        let token = issue(KEY, issued_at);
+        assert_none!(age(KEY, &token, issued_at + Duration::days(2)));
+    }
+}
@@ -0,0 +1,109 @@
+//! Admins logged in as another user to see what they see, for support.
+//!
+//! The session remembers the admin, so they can go back to their own
+//! account, and every change made in the meantime is recorded under their id
+//! as well as the user's.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Extension,
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{
+    app::ApiContext,
+    audit::{AuditEntry, AuditEvent, RequestMetadata},
+    auth::AuthSession,
+};
+
+/// Session key of the admin logged in as the session's user
+const IMPERSONATOR_KEY: &str = "impersonator";
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Impersonator {
+    pub user_id: Uuid,
+    pub username: String,
+}
+
+/// The admin behind the session, if it is impersonating someone
+pub async fn impersonator(session: &Session) -> Result<Option<Impersonator>, anyhow::Error> {
+    session
+        .get(IMPERSONATOR_KEY)
+        .await
+        .context("Failed to get impersonator")
+}
+
+/// Remembers the admin, call it once the session is logged in as the user
+pub async fn start(session: &Session, impersonator: Impersonator) -> Result<(), anyhow::Error> {
+    session
+        .insert(IMPERSONATOR_KEY, impersonator)
+        .await
+        .context("Failed to save impersonator")
+}
+
+/// Forgets the admin, returning them to be logged back in
+pub async fn stop(session: &Session) -> Result<Option<Impersonator>, anyhow::Error> {
+    session
+        .remove(IMPERSONATOR_KEY)
+        .await
+        .context("Failed to remove impersonator")
+}
+
+/// Rejects impersonated sessions, for routes only the user themselves may
+/// use, like changing their email address or impersonating someone else
+pub async fn forbid_while_impersonating(
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    match impersonator(&session).await {
+        Ok(None) => next.run(request).await,
+        Ok(Some(_)) => (
+            StatusCode::FORBIDDEN,
+            "You can't do this while viewing as another user",
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!(error = ?e, "Failed to check for impersonation");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Records every request of an impersonated session that can change
+/// something, whether or not its handler records anything itself
+pub async fn audit_impersonated_requests(
+    Extension(api_context): Extension<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    metadata: RequestMetadata,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method().is_safe() || metadata.impersonator_id.is_none() {
+        return next.run(request).await;
+    }
+
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let response = next.run(request).await;
+    api_context.audit.record(
+        AuditEntry::new(
+            AuditEvent::ImpersonatedRequest,
+            auth_session.user.as_ref().map(|user| user.user_id()),
+            &metadata,
+        )
+        .with_metadata(serde_json::json!({
+            "method": method,
+            "path": path,
+            "status": response.status().as_u16(),
+        })),
+    );
+    response
+}
@@ -0,0 +1,160 @@
+//! Invitation codes, which admins hand out while registration is invite
+//! only, and the waitlist collecting addresses while it is closed, see
+//! [`RegistrationMode`](crate::config::RegistrationMode)
+
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::domain::email_address::EmailAddress;
+
+/// Invitations shown on the admin page, newest first
+const INVITATIONS_SHOWN: i64 = 100;
+
+pub struct InvitationRow {
+    pub code: String,
+    /// `None` once the admin is deleted
+    pub created_by: Option<String>,
+    pub email: Option<String>,
+    pub uses_remaining: i32,
+    pub expires_at: Option<OffsetDateTime>,
+    pub created_at: OffsetDateTime,
+}
+
+impl InvitationRow {
+    pub fn is_usable(&self) -> bool {
+        self.uses_remaining > 0
+            && self
+                .expires_at
+                .is_none_or(|expires_at| expires_at > OffsetDateTime::now_utc())
+    }
+}
+
+/// Short enough to type in, too many to guess
+pub fn generate_code() -> String {
+    hex::encode(rand::random::<[u8; 8]>())
+}
+
+/// Creates an invitation `uses` people can register with, only with `email`
+/// when set, returning its code
+pub async fn create(
+    db: &PgPool,
+    created_by: Uuid,
+    email: Option<&EmailAddress>,
+    uses: i32,
+    expires_at: Option<OffsetDateTime>,
+) -> Result<String, anyhow::Error> {
+    let code = generate_code();
+    sqlx::query!(
+        r#"
+        INSERT INTO invitations (code, created_by, email, uses_remaining, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        code,
+        created_by,
+        email.map(|email| email.as_ref()),
+        uses,
+        expires_at
+    )
+    .execute(db)
+    .await
+    .context("Failed to create invitation")?;
+    Ok(code)
+}
+
+pub async fn latest(db: &PgPool) -> Result<Vec<InvitationRow>, anyhow::Error> {
+    sqlx::query_as!(
+        InvitationRow,
+        r#"
+        SELECT
+            inv.code, ui.username AS "created_by?", inv.email, inv.uses_remaining,
+            inv.expires_at, inv.created_at
+        FROM invitations AS inv
+        LEFT JOIN user_info AS ui ON ui.user_id = inv.created_by
+        ORDER BY inv.created_at DESC, inv.code
+        LIMIT $1
+        "#,
+        INVITATIONS_SHOWN
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to get invitations")
+}
+
+/// Takes a use of the invitation for `email`, returning whether it could be
+/// used. Done in the registration's transaction, so a failed registration
+/// gives the use back, and concurrent registrations can't both take the
+/// last one.
+pub async fn consume(
+    transaction: &mut Transaction<'_, Postgres>,
+    code: &str,
+    email: &EmailAddress,
+) -> Result<bool, anyhow::Error> {
+    // the collation of `email` ignores case
+    let result = sqlx::query!(
+        r#"
+        UPDATE invitations SET uses_remaining = uses_remaining - 1
+        WHERE code = $1
+            AND uses_remaining > 0
+            AND (expires_at IS NULL OR expires_at > NOW())
+            AND (email IS NULL OR email = $2)
+        "#,
+        code,
+        email.as_ref()
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to use invitation")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Adds the address to the waitlist. Addresses already on it are left
+/// alone, without telling, so the form doesn't reveal who signed up.
+pub async fn join_waitlist(db: &PgPool, email: &EmailAddress) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO waitlist (email) VALUES ($1)
+        ON CONFLICT (email) DO NOTHING
+        "#,
+        email.as_ref()
+    )
+    .execute(db)
+    .await
+    .context("Failed to join waitlist")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, OffsetDateTime};
+
+    use super::{InvitationRow, generate_code};
+
+    fn invitation(uses_remaining: i32, expires_at: Option<OffsetDateTime>) -> InvitationRow {
+        InvitationRow {
+            code: generate_code(),
+            created_by: None,
+            email: None,
+            uses_remaining,
+            expires_at,
+            created_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    #[test]
+    fn invitations_are_usable_until_used_up_or_expired() {
+        let now = OffsetDateTime::now_utc();
+        assert!(invitation(1, None).is_usable());
+        assert!(invitation(1, Some(now + Duration::days(1))).is_usable());
+        assert!(!invitation(0, None).is_usable());
+        assert!(!invitation(1, Some(now - Duration::days(1))).is_usable());
+    }
+
+    #[test]
+    fn codes_are_16_hex_digits() {
+        let code = generate_code();
+        assert_eq!(16, code.len());
+        assert!(code.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+}
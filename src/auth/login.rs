@@ -1,39 +1,146 @@
+use std::{sync::Arc, time::Duration};
+
 use askama::Template;
 use askama_web::WebTemplate;
-use axum::http::StatusCode;
-use axum::response::AppendHeaders;
-use axum::{Form, response::IntoResponse};
+use axum::extract::State;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::IntoResponse;
+use axum::response::Response;
+
+use tower_sessions::Session;
+use uuid::Uuid;
 
-use crate::auth::{AuthError, AuthSession, LoginCredentials};
+use crate::api_error::ApiError;
+use crate::app::ApiContext;
+use crate::audit::{AuditEntry, AuditEvent, RequestMetadata};
+use crate::auth::{AuthError, AuthSession, LoginCredentials, User, devices, sessions};
+use crate::captcha::{self, CaptchaWidget};
+use crate::client_ip::ClientIp;
 use crate::domain::password::Password;
 use crate::domain::username::Username;
+use crate::i18n::{Locale, filters};
+use crate::negotiate::{Format, IsHtmx, redirect_response};
+use crate::page::PageContext;
+use crate::rate_limit::{RateLimit, retry_after_secs};
+use crate::validation::{self, FormErrors, InvalidField, ValidatedForm};
+
+/// Checked by the handler rather than layered on the route, so the login
+/// page can say how long to wait
+const LOGIN_RATE_LIMIT: RateLimit = RateLimit::new("login", 10, Duration::from_secs(60));
 
 #[derive(Template, WebTemplate)]
 #[template(path = "auth/login.html")]
-pub struct LoginTemplate {}
+pub struct LoginTemplate {
+    page_context: PageContext,
+    locale: Locale,
+    /// Why the form posted without htmx failed
+    error: Option<String>,
+    /// Given back after a failed login
+    username: String,
+    /// Seconds until the form can be sent again, see [`AuthError::retry_after`]
+    retry_after: Option<u64>,
+    /// Set once the client failed too often, see [`captcha`]
+    captcha: Option<CaptchaWidget>,
+    /// Shown next to their field, see [`ValidatedForm`]
+    invalid_fields: Vec<InvalidField>,
+}
 
-pub async fn login_page() -> LoginTemplate {
-    LoginTemplate {}
+impl LoginTemplate {
+    fn field_error(&self, field: &str) -> Option<&'static str> {
+        validation::field_error(&self.invalid_fields, field, self.locale)
+    }
+}
+
+/// The login form alone, which htmx swaps in for the one that failed
+#[derive(Template, WebTemplate)]
+#[template(path = "auth/login_form.html")]
+struct LoginFormTemplate {
+    locale: Locale,
+    error: Option<String>,
+    username: String,
+    retry_after: Option<u64>,
+    captcha: Option<CaptchaWidget>,
+    invalid_fields: Vec<InvalidField>,
+}
+
+impl LoginFormTemplate {
+    fn field_error(&self, field: &str) -> Option<&'static str> {
+        validation::field_error(&self.invalid_fields, field, self.locale)
+    }
+}
+
+pub async fn login_page(
+    State(api_context): State<Arc<ApiContext>>,
+    page_context: PageContext,
+    locale: Locale,
+    ClientIp(ip): ClientIp,
+) -> LoginTemplate {
+    LoginTemplate {
+        page_context,
+        locale,
+        error: None,
+        username: String::new(),
+        retry_after: None,
+        captcha: captcha::widget_for(&api_context, ip).await,
+        invalid_fields: Vec::new(),
+    }
 }
 
 impl IntoResponse for AuthError {
     fn into_response(self) -> axum::response::Response {
-        let (status_code, error) = match self {
-            AuthError::UnexpectedError(_) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "An internal server error occured",
-            ),
-            AuthError::InvalidCredentials => (StatusCode::UNAUTHORIZED, "Invalid credentials"),
-        };
+        let retry_after = self.retry_after();
+        let mut response = self.into_api_error().into_response();
+        set_retry_after(&mut response, retry_after);
+        response
+    }
+}
 
-        (status_code, error).into_response()
+fn set_retry_after(response: &mut Response, retry_after: Option<Duration>) {
+    if let Some(retry_after) = retry_after {
+        response.headers_mut().insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(retry_after_secs(retry_after)),
+        );
+    }
+}
+
+impl AuthError {
+    fn into_api_error(self) -> ApiError {
+        match self {
+            AuthError::UnexpectedError(e) => ApiError::internal(&e),
+            AuthError::InvalidCredentials => ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_credentials",
+                "Invalid credentials",
+            ),
+            AuthError::ServerBusy => ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "server_busy",
+                AuthError::ServerBusy,
+            ),
+            e @ AuthError::AccountDeactivated { .. } => {
+                ApiError::new(StatusCode::FORBIDDEN, "account_deactivated", e)
+            }
+            e @ AuthError::TooManySessions => {
+                ApiError::new(StatusCode::CONFLICT, "too_many_sessions", e)
+            }
+            e @ AuthError::Throttled { .. } => {
+                ApiError::new(StatusCode::TOO_MANY_REQUESTS, "rate_limited", e)
+            }
+            e @ AuthError::InvalidCaptcha => {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_captcha", e)
+            }
+        }
     }
 }
 
 #[derive(serde::Deserialize)]
 pub struct LoginFormData {
-    username: String,
+    pub(super) username: String,
     password: String,
+    /// Put in the form by the widget, see [`captcha`]
+    #[serde(default, alias = "cf-turnstile-response", alias = "h-captcha-response")]
+    captcha_token: Option<String>,
 }
 
 impl TryInto<LoginCredentials> for LoginFormData {
@@ -45,19 +152,125 @@ impl TryInto<LoginCredentials> for LoginFormData {
         let password =
             Password::parse(&self.password).map_err(|_| AuthError::InvalidCredentials)?;
 
-        Ok(LoginCredentials { username, password })
+        Ok(LoginCredentials::new(username, password))
     }
 }
 
+/// Sends JSON clients their error, and everyone else the login form again
+/// with the error on it, next to the field it is about if it is one: htmx
+/// the form alone to swap in, browsers posting the form themselves the whole
+/// page. The submit button stays disabled for as
+/// long as the error says to wait.
 pub async fn login_user(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    session: Session,
+    request: RequestMetadata,
+    is_htmx: IsHtmx,
+    headers: HeaderMap,
+    form: Result<ValidatedForm<LoginFormData>, FormErrors>,
+) -> Response {
+    let locale = Locale::negotiate(None, &headers);
+    let is_json = Format::from_headers(&headers) == Format::Json;
+    let ip = request.ip_address.clone();
+    let (username, error, retry_after, invalid_fields) = match form {
+        Ok(ValidatedForm(payload)) => {
+            let username = payload.username.clone();
+            match login(&api_context, auth_session, &session, request, payload).await {
+                Ok(()) => return redirect_response(is_htmx, "/"),
+                Err(e) if is_json => return e.into_response(),
+                Err(e) => {
+                    let retry_after = e.retry_after();
+                    (username, e.into_api_error(), retry_after, Vec::new())
+                }
+            }
+        }
+        Err(errors) if is_json => return errors.into_api_error(locale).into_response(),
+        Err(errors) => {
+            let username = errors.value("username").to_string();
+            let invalid_fields = errors.fields().to_vec();
+            (
+                username,
+                errors.into_api_error(locale),
+                None,
+                invalid_fields,
+            )
+        }
+    };
+    // the failure may have been the one after which a CAPTCHA is needed
+    let captcha = captcha::widget_for(&api_context, ip).await;
+    let mut response = if is_htmx.0 {
+        let form = LoginFormTemplate {
+            locale,
+            error: Some(error.message().to_string()),
+            username,
+            retry_after: retry_after.map(retry_after_secs),
+            captcha,
+            invalid_fields,
+        };
+        (error.status(), form).into_response()
+    } else {
+        // nobody is logged in after a failed login
+        let page = LoginTemplate {
+            page_context: PageContext {
+                features: api_context.features,
+                ..PageContext::from_headers(&headers)
+            },
+            locale,
+            error: Some(error.message().to_string()),
+            username,
+            retry_after: retry_after.map(retry_after_secs),
+            captcha,
+            invalid_fields,
+        };
+        (error.status(), page).into_response()
+    };
+    set_retry_after(&mut response, retry_after);
+    response
+}
+
+async fn login(
+    api_context: &Arc<ApiContext>,
     mut auth_session: AuthSession,
-    Form(payload): Form<LoginFormData>,
-) -> Result<impl IntoResponse, AuthError> {
-    let credentials: LoginCredentials = payload.try_into()?;
+    session: &Session,
+    request: RequestMetadata,
+    payload: LoginFormData,
+) -> Result<(), AuthError> {
+    if let Some(retry_after) = LOGIN_RATE_LIMIT
+        .retry_after(api_context, request.ip_address.as_deref())
+        .await
+    {
+        return Err(AuthError::Throttled { retry_after });
+    }
+
+    let attempted_username = payload.username.trim().to_lowercase();
+    captcha::check(
+        api_context,
+        request.ip_address.as_deref(),
+        payload.captcha_token.as_deref(),
+    )
+    .await?;
+    let record_failure = async || {
+        api_context.audit.record(
+            AuditEntry::new(AuditEvent::LoginFailed, None, &request)
+                .with_metadata(serde_json::json!({ "username": attempted_username })),
+        );
+        captcha::record_failure(api_context, request.ip_address.as_deref()).await;
+        AuthError::InvalidCredentials
+    };
 
-    let user = match auth_session.authenticate(credentials).await {
+    let credentials: LoginCredentials = match payload.try_into() {
+        Ok(credentials) => credentials,
+        Err(_) => return Err(record_failure().await),
+    };
+
+    // logging in on the site is how a deactivated account is reactivated
+    let user = match auth_session.authenticate(credentials.reactivating()).await {
         Ok(Some(user)) => user,
-        Ok(None) => return Err(AuthError::InvalidCredentials),
+        Ok(None) => return Err(record_failure().await),
+        Err(axum_login::Error::Backend(AuthError::ServerBusy)) => {
+            return Err(AuthError::ServerBusy);
+        }
         Err(_) => {
             return Err(AuthError::UnexpectedError(anyhow::anyhow!(
                 "An internal server error occured"
@@ -65,11 +278,208 @@ pub async fn login_user(
         }
     };
 
-    if auth_session.login(&user).await.is_err() {
+    if user.was_deactivated() {
+        api_context.audit.record(AuditEntry::new(
+            AuditEvent::AccountReactivated,
+            Some(user.user_id()),
+            &request,
+        ));
+    }
+
+    complete_login(
+        api_context,
+        &mut auth_session,
+        session,
+        &user,
+        request,
+        None,
+    )
+    .await
+}
+
+/// Logs the user in once they are authenticated, however that happened.
+/// `method` is recorded in the audit log when it isn't a password.
+pub(super) async fn complete_login(
+    api_context: &Arc<ApiContext>,
+    auth_session: &mut AuthSession,
+    session: &Session,
+    user: &User,
+    request: RequestMetadata,
+    method: Option<&str>,
+) -> Result<(), AuthError> {
+    let settings = &api_context.config.application_settings;
+    // held until the new session is tracked, dropping it releases the lock
+    let _lock = match settings.max_sessions_per_user {
+        Some(max_sessions) => {
+            let lock = sessions::lock_user_sessions(&api_context.db, user.user_id()).await?;
+            let has_room = sessions::make_room_for_session(
+                &api_context.redis,
+                user.user_id(),
+                session.id(),
+                max_sessions,
+                settings.session_limit_strategy,
+            )
+            .await?;
+            if !has_room {
+                return Err(AuthError::TooManySessions);
+            }
+            Some(lock)
+        }
+        None => None,
+    };
+
+    if auth_session.login(user).await.is_err() {
         return Err(AuthError::UnexpectedError(anyhow::anyhow!(
             "An internal server error occured"
         )));
     }
 
-    Ok((StatusCode::OK, AppendHeaders([("HX-Redirect", "/")])))
+    // login only cycles the session id when nobody was logged in yet, which
+    // would keep the id of a session someone else was logged into
+    sessions::rotate_session(&api_context.redis, session, Some(user.user_id())).await?;
+    sessions::record_login_time(session).await?;
+
+    let mut entry = AuditEntry::new(AuditEvent::LoginSucceeded, Some(user.user_id()), &request);
+    if let Some(method) = method {
+        entry = entry.with_metadata(serde_json::json!({ "method": method }));
+    }
+    api_context.audit.record(entry);
+    record_last_login(api_context, user.user_id());
+    devices::check_login_device(api_context.clone(), user.user_id(), request);
+
+    Ok(())
+}
+
+/// Sets `last_login_at` in a spawned task, so logins don't wait on the write.
+/// Only the login handlers call it, sessions restored from their cookie
+/// would write on every request otherwise.
+pub(super) fn record_last_login(api_context: &Arc<ApiContext>, user_id: Uuid) {
+    let db = api_context.db.clone();
+    tokio::spawn(async move {
+        let result = sqlx::query!(
+            "UPDATE user_info SET last_login_at = NOW() WHERE user_id = $1",
+            user_id
+        )
+        .execute(&db)
+        .await;
+        if let Err(e) = result {
+            tracing::warn!(error = ?e, %user_id, "Failed to record last login");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use askama::Template;
+
+    use crate::{
+        auth::login::{LoginFormTemplate, LoginTemplate},
+        features::Features,
+        i18n::Locale,
+        page::PageContext,
+        theme::Theme,
+    };
+
+    fn page_context(enabled: bool) -> PageContext {
+        PageContext {
+            features: Features {
+                registration: enabled,
+                magic_links: enabled,
+                passkeys: enabled,
+                webhooks: enabled,
+            },
+            ..PageContext::default()
+        }
+    }
+
+    #[test]
+    fn login_page_with_every_feature() {
+        let html = LoginTemplate {
+            page_context: page_context(true),
+            locale: Locale::En,
+            error: None,
+            username: String::new(),
+            retry_after: None,
+            captcha: None,
+            invalid_fields: Vec::new(),
+        }
+        .render()
+        .unwrap();
+        insta::assert_snapshot!(html);
+    }
+
+    #[test]
+    fn login_page_without_optional_features() {
+        let html = LoginTemplate {
+            page_context: page_context(false),
+            locale: Locale::En,
+            error: None,
+            username: String::new(),
+            retry_after: None,
+            captcha: None,
+            invalid_fields: Vec::new(),
+        }
+        .render()
+        .unwrap();
+        insta::assert_snapshot!(html);
+    }
+
+    #[test]
+    fn login_page_has_the_class_of_the_theme() {
+        for theme in Theme::ALL {
+            let html = LoginTemplate {
+                page_context: PageContext {
+                    theme,
+                    ..PageContext::default()
+                },
+                locale: Locale::En,
+                error: None,
+                username: String::new(),
+                retry_after: None,
+                captcha: None,
+                invalid_fields: Vec::new(),
+            }
+            .render()
+            .unwrap();
+            assert!(
+                html.contains(&format!(r#"class="theme-{theme}""#)),
+                "{theme}"
+            );
+            assert!(
+                html.contains(&format!(r#"value="{theme}" aria-pressed="true""#)),
+                "{theme}"
+            );
+        }
+    }
+
+    #[test]
+    fn forms_to_retry_later_count_down_with_their_button_disabled() {
+        let html = LoginFormTemplate {
+            locale: Locale::En,
+            error: Some("Too many login attempts, try again in 4 minutes".to_string()),
+            username: "alice".to_string(),
+            retry_after: Some(240),
+            captcha: None,
+            invalid_fields: Vec::new(),
+        }
+        .render()
+        .unwrap();
+        assert!(html.contains(r#"data-retry-after="240""#));
+        assert!(html.contains(r#"<button type="submit" disabled>"#));
+        assert!(html.contains("data-countdown"));
+        assert!(html.contains(r#"value="alice""#));
+
+        let html = LoginFormTemplate {
+            locale: Locale::En,
+            error: Some("Invalid credentials".to_string()),
+            username: String::new(),
+            retry_after: None,
+            captcha: None,
+            invalid_fields: Vec::new(),
+        }
+        .render()
+        .unwrap();
+        assert!(!html.contains("data-retry-after"));
+        assert!(html.contains(r#"<button type="submit">"#));
+    }
 }
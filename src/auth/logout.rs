@@ -1,9 +1,39 @@
-use axum::response::{AppendHeaders, IntoResponse};
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    response::{AppendHeaders, IntoResponse},
+};
 use http::StatusCode;
+use tower_sessions::Session;
+
+use crate::{
+    app::ApiContext,
+    audit::{AuditEntry, AuditEvent, RequestMetadata},
+    auth::{AuthSession, sessions},
+};
 
-use crate::auth::AuthSession;
+pub async fn logout(
+    State(api_context): State<Arc<ApiContext>>,
+    mut auth_session: AuthSession,
+    session: Session,
+    request: RequestMetadata,
+) -> impl IntoResponse {
+    if let (Some(user), Some(session_id)) = (&auth_session.user, session.id())
+        && let Err(e) =
+            sessions::untrack_session(&api_context.redis, user.user_id(), session_id).await
+    {
+        tracing::warn!(error = ?e, "Failed to untrack session on logout");
+    }
+
+    if let Some(user) = &auth_session.user {
+        api_context.audit.record(AuditEntry::new(
+            AuditEvent::Logout,
+            Some(user.user_id()),
+            &request,
+        ));
+    }
 
-pub async fn logout(mut auth_session: AuthSession) -> impl IntoResponse {
     match auth_session.logout().await {
         Ok(_) => (StatusCode::OK, AppendHeaders([("HX-Redirect", "/login")])).into_response(),
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
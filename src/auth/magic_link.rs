@@ -0,0 +1,219 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use axum::{
+    Form,
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_login::AuthnBackend;
+use http::StatusCode;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tower_sessions::Session;
+
+use crate::{
+    app::ApiContext,
+    audit::RequestMetadata,
+    auth::{AuthError, AuthSession, login::complete_login},
+    domain::email_address::{EmailAddress, InvalidEmailError},
+    emails::{self, Email, MagicLink},
+    rate_limit::{RateLimit, too_many_requests},
+};
+
+const LINK_LIFETIME_MINUTES: i32 = 15;
+
+/// Links sent to one address, on top of the per IP limit of the route, so an
+/// inbox can't be flooded from many addresses
+fn email_rate_limit() -> RateLimit {
+    RateLimit::new("magic_link_email", 3, Duration::from_secs(15 * 60))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MagicLinkError {
+    #[error(transparent)]
+    InvalidEmail(#[from] InvalidEmailError),
+    #[error("This login link is invalid or has expired")]
+    InvalidLink,
+    #[error(transparent)]
+    Auth(AuthError),
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for MagicLinkError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            MagicLinkError::InvalidEmail(_) | MagicLinkError::InvalidLink => {
+                StatusCode::BAD_REQUEST
+            }
+            MagicLinkError::Auth(e) => return e.into_response(),
+            MagicLinkError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+fn hash_link_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token))
+}
+
+#[derive(serde::Deserialize)]
+pub struct MagicLinkFormData {
+    email: String,
+}
+
+/// Emails a login link if the address belongs to an account that has
+/// confirmed it. The answer is the same either way, and the link is queued in
+/// a spawned task so the response time doesn't tell either.
+pub async fn request_magic_link(
+    State(api_context): State<Arc<ApiContext>>,
+    Form(form_data): Form<MagicLinkFormData>,
+) -> Result<Response, MagicLinkError> {
+    let email = EmailAddress::parse(&form_data.email)?;
+
+    if api_context.config.application_settings.rate_limit_enabled {
+        // the address column is case insensitive, so is its bucket
+        match email_rate_limit()
+            .check(&api_context.redis, email.as_ref().to_lowercase())
+            .await
+        {
+            Ok(None) => {}
+            Ok(Some(retry_after)) => return Ok(too_many_requests(retry_after)),
+            Err(e) => tracing::error!(error = ?e, "Failed to apply magic link rate limit"),
+        }
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = send_magic_link(&api_context, &email).await {
+            tracing::warn!(error = ?e, "Failed to send magic link");
+        }
+    });
+
+    Ok((
+        StatusCode::ACCEPTED,
+        "If an account uses this address, a login link is on its way",
+    )
+        .into_response())
+}
+
+async fn send_magic_link(
+    api_context: &ApiContext,
+    email: &EmailAddress,
+) -> Result<(), anyhow::Error> {
+    // an unconfirmed address may not belong to whoever registered it, and
+    // deactivated accounts are only reactivated with their password
+    let Some(user) = sqlx::query!(
+        r#"
+        SELECT user_id, email FROM user_info
+        WHERE email = $1 AND email_verified_at IS NOT NULL
+            AND locked_at IS NULL AND deactivated_at IS NULL
+        "#,
+        email.as_ref()
+    )
+    .fetch_optional(&api_context.db)
+    .await
+    .context("Failed to get user")?
+    else {
+        return Ok(());
+    };
+
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    let recipient = EmailAddress::parse(&user.email).context("Stored email is invalid")?;
+
+    let mut transaction = api_context
+        .db
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO magic_links (token_hash, user_id, email, expires_at)
+        VALUES ($1, $2, $3, NOW() + make_interval(mins => $4))
+        "#,
+        hash_link_token(&token),
+        user.user_id,
+        user.email,
+        LINK_LIFETIME_MINUTES
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store magic link")?;
+    emails::enqueue(
+        &mut *transaction,
+        &recipient,
+        &Email::MagicLink(MagicLink {
+            token,
+            lifetime_minutes: LINK_LIFETIME_MINUTES,
+        }),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+}
+
+/// Uses up the link and logs its user in
+pub async fn log_in_with_magic_link(
+    State(api_context): State<Arc<ApiContext>>,
+    mut auth_session: AuthSession,
+    session: Session,
+    request: RequestMetadata,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, MagicLinkError> {
+    // a single statement, so two clicks can't both use the link
+    let user_id = sqlx::query_scalar!(
+        r#"
+        UPDATE magic_links AS ml SET used_at = NOW()
+        FROM user_info AS ui
+        WHERE ml.token_hash = $1
+            AND ml.used_at IS NULL
+            AND ml.expires_at > NOW()
+            AND ui.user_id = ml.user_id
+            AND ui.email = ml.email
+            AND ui.email_verified_at IS NOT NULL
+        RETURNING ml.user_id
+        "#,
+        hash_link_token(&token)
+    )
+    .fetch_optional(&api_context.db)
+    .await
+    .context("Failed to use magic link")?
+    .ok_or(MagicLinkError::InvalidLink)?;
+
+    // locked or deactivated since the link was sent
+    let user = auth_session
+        .backend
+        .get_user(&user_id)
+        .await
+        .map_err(anyhow::Error::from)?
+        .ok_or(MagicLinkError::InvalidLink)?;
+
+    complete_login(
+        &api_context,
+        &mut auth_session,
+        &session,
+        &user,
+        request,
+        Some("magic_link"),
+    )
+    .await
+    .map_err(MagicLinkError::Auth)?;
+
+    Ok(Redirect::to("/"))
+}
+
+/// Deletes links that can't be used anymore, returning how many
+pub async fn delete_expired_magic_links(db: &PgPool) -> Result<u64, anyhow::Error> {
+    let query_result = sqlx::query!(
+        r#"
+        DELETE FROM magic_links WHERE expires_at < NOW()
+        "#
+    )
+    .execute(db)
+    .await
+    .context("Failed to delete expired magic links")?;
+
+    Ok(query_result.rows_affected())
+}
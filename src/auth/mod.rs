@@ -1,31 +1,159 @@
-use anyhow::Context;
+use std::{sync::Arc, time::Duration};
+
 use async_trait::async_trait;
 use axum::{
     Router,
+    extract::Request,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{get, post},
 };
 use axum_login::{AuthUser, AuthnBackend, UserId};
-use password_auth::verify_password;
+use http::StatusCode;
 use secrecy::{ExposeSecret, SecretString};
-use sqlx::{PgPool, prelude::FromRow};
+use sqlx::prelude::FromRow;
+use time::OffsetDateTime;
 use uuid::Uuid;
 
 use crate::{
     app::AppRouter,
+    captcha::CaptchaError,
     domain::{password::Password, username::Username},
+    features::{Feature, require_feature},
+    rate_limit::RateLimit,
 };
 
+mod bearer;
+pub use bearer::{ApiUser, TokenKeys, delete_expired_refresh_tokens, revoke_refresh_tokens};
+mod devices;
+mod email_change;
+mod form_token;
+pub mod impersonation;
+pub mod invitation;
 mod login;
 mod logout;
+mod magic_link;
+pub use magic_link::delete_expired_magic_links;
+mod passkey;
+pub use passkey::{fetch_passkeys, webauthn_from_settings};
+mod password_hashing;
+pub use password_hashing::{Hasher, HasherError};
 mod register;
+pub use register::{RegisterError, create_user, username_on_hold};
+pub(crate) mod repo;
+pub use repo::{PgUserRepo, UserRepo};
+mod session_keys;
+pub use session_keys::{SESSION_COOKIE, SessionKeys, resign_session_cookie};
+pub mod sessions;
+mod submission;
+mod token;
+mod user_cache;
+pub use user_cache::UserCache;
 
 pub fn router() -> AppRouter {
     Router::new()
-        .route("/register", get(register::register_page))
+        .route(
+            "/register",
+            get(register::register_page).route_layer(middleware::from_fn_with_state(
+                Feature::Registration,
+                require_feature,
+            )),
+        )
+        .route(
+            "/register/waitlist",
+            get(register::waitlisted_page).route_layer(middleware::from_fn_with_state(
+                Feature::Registration,
+                require_feature,
+            )),
+        )
         .route("/login", get(login::login_page))
+        .route(
+            "/login/magic",
+            post(magic_link::request_magic_link)
+                .layer(RateLimit::new("magic_link", 5, Duration::from_secs(600)))
+                .route_layer(middleware::from_fn_with_state(
+                    Feature::MagicLinks,
+                    require_feature,
+                )),
+        )
+        .route(
+            "/login/magic/{token}",
+            get(magic_link::log_in_with_magic_link).route_layer(middleware::from_fn_with_state(
+                Feature::MagicLinks,
+                require_feature,
+            )),
+        )
         .route("/logout", get(logout::logout))
-        .route("/api/register", post(register::register_user))
-        .route("/api/login", post(login::login_user))
+        .route(
+            "/confirm-email",
+            get(email_change::confirm_email_change).route_layer(middleware::from_fn(
+                impersonation::forbid_while_impersonating,
+            )),
+        )
+}
+
+/// Nested under `/api`
+pub fn api_router() -> AppRouter {
+    Router::new()
+        .route(
+            "/register",
+            post(register::register_user)
+                .layer(RateLimit::new("register", 5, Duration::from_secs(600)))
+                .route_layer(middleware::from_fn_with_state(
+                    Feature::Registration,
+                    require_feature,
+                )),
+        )
+        // rate limited in the handler, see `login::LOGIN_RATE_LIMIT`
+        .route("/login", post(login::login_user))
+        .route(
+            "/login/passkey/start",
+            post(passkey::start_passkey_login)
+                .layer(RateLimit::new("passkey_login", 10, Duration::from_secs(60)))
+                .route_layer(middleware::from_fn_with_state(
+                    Feature::Passkeys,
+                    require_feature,
+                )),
+        )
+        .route(
+            "/login/passkey/finish",
+            post(passkey::finish_passkey_login).route_layer(middleware::from_fn_with_state(
+                Feature::Passkeys,
+                require_feature,
+            )),
+        )
+        .route(
+            "/user/email",
+            post(email_change::request_email_change).route_layer(middleware::from_fn(
+                impersonation::forbid_while_impersonating,
+            )),
+        )
+        .route(
+            "/v1/auth/token",
+            post(token::issue_token).layer(RateLimit::new("token", 10, Duration::from_secs(60))),
+        )
+        .route("/v1/auth/refresh", post(token::refresh_token))
+        .route("/v1/auth/logout", post(token::revoke_token))
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::User => write!(f, "user"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
 }
 
 #[derive(Clone, Debug, FromRow)]
@@ -33,12 +161,33 @@ pub struct User {
     user_id: Uuid,
     pub username: String,
     password_hash: SecretString,
+    role: Role,
+    /// 0 before accepting any, see `TOS_VERSION`
+    tos_accepted_version: i32,
+    /// Only ever set on users found by their username, see
+    /// [`Backend::authenticate`]. Deactivated users aren't found by their id.
+    deactivated_at: Option<OffsetDateTime>,
 }
 
 impl User {
     pub fn user_id(&self) -> Uuid {
         self.user_id
     }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    /// Whether the user accepted the terms of service of `tos_version`, or
+    /// newer ones
+    pub fn accepted_tos(&self, tos_version: i32) -> bool {
+        self.tos_accepted_version >= tos_version
+    }
+
+    /// Whether the user was deactivated until the login that returned them
+    pub fn was_deactivated(&self) -> bool {
+        self.deactivated_at.is_some()
+    }
 }
 
 impl AuthUser for User {
@@ -56,16 +205,47 @@ impl AuthUser for User {
 pub struct LoginCredentials {
     username: Username,
     password: Password,
+    reactivate: bool,
 }
 
-#[derive(Clone, Debug)]
+impl LoginCredentials {
+    pub fn new(username: Username, password: Password) -> Self {
+        Self {
+            username,
+            password,
+            reactivate: false,
+        }
+    }
+
+    /// Reactivates the account if it is deactivated, instead of refusing it
+    pub fn reactivating(mut self) -> Self {
+        self.reactivate = true;
+        self
+    }
+}
+
+#[derive(Clone)]
 pub struct Backend {
-    db: PgPool,
+    users: Arc<dyn UserRepo>,
+    hasher: Hasher,
+    user_cache: UserCache,
+    /// How long deactivated accounts can be reactivated, in days
+    deactivation_grace_days: i32,
 }
 
 impl Backend {
-    pub fn new(db: PgPool) -> Self {
-        Self { db }
+    pub fn new(
+        users: Arc<dyn UserRepo>,
+        hasher: Hasher,
+        user_cache: UserCache,
+        deactivation_grace_days: i32,
+    ) -> Self {
+        Self {
+            users,
+            hasher,
+            user_cache,
+            deactivation_grace_days,
+        }
     }
 }
 
@@ -75,6 +255,59 @@ pub enum AuthError {
     UnexpectedError(#[from] anyhow::Error),
     #[error("Invalid credentials")]
     InvalidCredentials,
+    #[error("The server is busy, try again shortly")]
+    ServerBusy,
+    #[error(
+        "This account is deactivated, log in within {grace_days} days of deactivating it to reactivate it"
+    )]
+    AccountDeactivated { grace_days: i32 },
+    #[error("You are logged in on too many devices, log out on one of them to log in here")]
+    TooManySessions,
+    #[error("Too many login attempts, try again in {}", wait_time(*.retry_after))]
+    Throttled { retry_after: Duration },
+    #[error("Solve the CAPTCHA to log in")]
+    InvalidCaptcha,
+}
+
+impl From<CaptchaError> for AuthError {
+    fn from(e: CaptchaError) -> Self {
+        match e {
+            CaptchaError::Invalid => AuthError::InvalidCaptcha,
+            CaptchaError::UnexpectedError(e) => AuthError::UnexpectedError(e),
+        }
+    }
+}
+
+impl AuthError {
+    /// How long to wait before trying again, for the errors that go away
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            AuthError::Throttled { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
+}
+
+/// `retry_after` for people, in the seconds of `Retry-After` below a minute
+/// and in minutes, rounded up, from then on
+fn wait_time(retry_after: Duration) -> String {
+    let seconds = crate::rate_limit::retry_after_secs(retry_after);
+    let (count, unit) = if seconds < 60 {
+        (seconds, "second")
+    } else {
+        (seconds.div_ceil(60), "minute")
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{count} {unit}{plural}")
+}
+
+impl From<HasherError> for AuthError {
+    fn from(e: HasherError) -> Self {
+        match e {
+            HasherError::Busy => AuthError::ServerBusy,
+            HasherError::UnexpectedError(e) => AuthError::UnexpectedError(e),
+        }
+    }
 }
 
 #[async_trait]
@@ -87,50 +320,304 @@ impl AuthnBackend for Backend {
         &self,
         credentials: Self::Credentials,
     ) -> Result<Option<Self::User>, Self::Error> {
-        let user: Option<Self::User> = sqlx::query_as!(
-            Self::User,
-            r#"
-            SELECT ui.user_id, ui.username, up.password_hash
-            FROM user_info AS ui JOIN user_password AS up
-                ON ui.user_id = up.user_id
-            WHERE ui.username = $1
-            "#,
-            credentials.username.as_ref(),
-        )
-        .fetch_optional(&self.db)
-        .await
-        .context("Failed to fetch stored user credentials")?;
-
-        tokio::task::spawn_blocking(move || {
-            Ok(user.filter(|user| {
-                verify_password(
-                    credentials.password.expose_secret().as_bytes(),
-                    user.password_hash.expose_secret(),
-                )
-                .is_ok()
-            }))
+        let user = self.users.find_by_username(&credentials.username).await?;
+
+        let password = credentials.password;
+        let mut user = verify_credentials(user, self.hasher.dummy_hash(), async |hash| {
+            self.hasher.verify(password.clone(), hash).await
         })
-        .await
-        .context("Failed to spawn blocking task")?
+        .await?;
+
+        // only told apart from a wrong password once the password is right, so
+        // it doesn't give away which accounts exist
+        if let Some(user) = &user
+            && user.was_deactivated()
+        {
+            if !credentials.reactivate {
+                return Err(AuthError::AccountDeactivated {
+                    grace_days: self.deactivation_grace_days,
+                });
+            }
+            // past the grace period, the account is about to be deleted
+            if !self
+                .users
+                .reactivate(user.user_id, self.deactivation_grace_days)
+                .await?
+            {
+                return Ok(None);
+            }
+        }
+
+        // upgraded before returning, the session is tied to the hash it is made with
+        if let Some(user) = &mut user
+            && self
+                .hasher
+                .needs_upgrade(user.password_hash.expose_secret())
+            && let Some(new_hash) = upgrade_password_hash(
+                self.users.as_ref(),
+                &self.hasher,
+                user.user_id,
+                &user.password_hash,
+                password,
+            )
+            .await
+        {
+            user.password_hash = new_hash;
+            self.user_cache.invalidate(user.user_id).await;
+        }
+
+        Ok(user)
     }
 
     async fn get_user(&self, user_id: &UserId<Self>) -> Result<Option<Self::User>, Self::Error> {
-        let user: Option<Self::User> = sqlx::query_as!(
-            Self::User,
-            r#"
-            SELECT ui.user_id, ui.username, up.password_hash
-            FROM user_info AS ui JOIN user_password AS up
-                ON ui.user_id = up.user_id
-            WHERE ui.user_id = $1
-            "#,
-            user_id
-        )
-        .fetch_optional(&self.db)
-        .await
-        .context("Failed to get user")?;
+        if let Some(user) = self.user_cache.get(*user_id).await {
+            return Ok(Some(user));
+        }
+
+        // locked and deactivated users are treated as logged out on their next
+        // request
+        let user = self.users.find_by_id(*user_id).await?;
 
+        if let Some(user) = &user {
+            self.user_cache.set(user).await;
+        }
         Ok(user)
     }
 }
 
+/// Returns the user if `verify` accepts their hash. Without a user
+/// `dummy_hash` is verified instead, so the response time doesn't tell
+/// whether the username exists.
+async fn verify_credentials<E>(
+    user: Option<User>,
+    dummy_hash: SecretString,
+    verify: impl AsyncFnOnce(SecretString) -> Result<bool, E>,
+) -> Result<Option<User>, E> {
+    match user {
+        Some(user) => Ok(verify(user.password_hash.clone()).await?.then_some(user)),
+        None => {
+            verify(dummy_hash).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Rehashes a password made with weaker parameters than the current ones,
+/// returning the new hash if it was stored. Best-effort, the old hash keeps
+/// working if this fails.
+async fn upgrade_password_hash(
+    users: &dyn UserRepo,
+    hasher: &Hasher,
+    user_id: Uuid,
+    old_hash: &SecretString,
+    password: Password,
+) -> Option<SecretString> {
+    let result = async {
+        let new_hash = hasher.hash(password).await?;
+
+        // a password changed in the meantime is left alone
+        let stored = users
+            .replace_password_hash(user_id, old_hash, &new_hash)
+            .await?;
+
+        Ok::<_, HasherError>(stored.then_some(new_hash))
+    }
+    .await;
+
+    match result {
+        Ok(Some(new_hash)) => {
+            tracing::info!(%user_id, "Upgraded password hash to the current parameters");
+            Some(SecretString::from(new_hash))
+        }
+        Ok(None) => None,
+        Err(e) => {
+            tracing::warn!(%user_id, error = ?e, "Failed to upgrade password hash");
+            None
+        }
+    }
+}
+
 pub type AuthSession = axum_login::AuthSession<Backend>;
+
+/// Rejects users without the admin role, layer it inside `login_required!` so
+/// anonymous users are still sent to the login page
+pub async fn require_admin(auth_session: AuthSession, request: Request, next: Next) -> Response {
+    match auth_session.user {
+        Some(user) if user.is_admin() => next.run(request).await,
+        _ => StatusCode::FORBIDDEN.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, sync::Arc, time::Duration};
+
+    use axum_login::AuthnBackend;
+    use secrecy::{ExposeSecret, SecretString};
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    use crate::{
+        auth::{
+            AuthError, Backend, Hasher, LoginCredentials, Role, User, UserCache,
+            repo::fake::FakeUserRepo, verify_credentials, wait_time,
+        },
+        config::Config,
+        domain::{password::Password, username::Username},
+        redis::Redis,
+    };
+
+    fn user_with_hash(hash: &str) -> User {
+        User {
+            user_id: Uuid::new_v4(),
+            username: "alice".to_string(),
+            password_hash: SecretString::from(hash),
+            role: Role::User,
+            tos_accepted_version: 1,
+            deactivated_at: None,
+        }
+    }
+
+    #[tokio::test]
+    pub async fn unknown_user_still_runs_the_verifier() {
+        let calls = Cell::new(0);
+        let user = verify_credentials(None, SecretString::from("dummy"), async |hash| {
+            calls.set(calls.get() + 1);
+            assert_eq!("dummy", hash.expose_secret());
+            Ok::<_, ()>(true)
+        })
+        .await
+        .unwrap();
+        assert!(user.is_none());
+        assert_eq!(1, calls.get());
+    }
+
+    #[tokio::test]
+    pub async fn known_user_is_returned_only_if_verified() {
+        let user = user_with_hash("user hash");
+
+        let verified = verify_credentials(
+            Some(user.clone()),
+            SecretString::from("dummy"),
+            async |hash| Ok::<_, ()>(hash.expose_secret() == "user hash"),
+        )
+        .await
+        .unwrap();
+        assert!(verified.is_some());
+
+        let rejected = verify_credentials(Some(user), SecretString::from("dummy"), async |_| {
+            Ok::<_, ()>(false)
+        })
+        .await
+        .unwrap();
+        assert!(rejected.is_none());
+    }
+
+    #[tokio::test]
+    pub async fn backend_looks_users_up_through_the_repo() {
+        let config = Config::for_tests(&[
+            "--hmac-key=0123456789012345678901234567890123456789012345678901234567890123",
+        ]);
+        let hasher = Hasher::from_settings(&config.application_settings).unwrap();
+        let password = Password::parse("correct-horse-battery").unwrap();
+        let hash = hasher.hash(password.clone()).await.unwrap();
+        let user = user_with_hash(&hash);
+
+        let users = Arc::new(FakeUserRepo::default());
+        users.add(user.clone());
+        let redis = fred::prelude::Builder::default_centralized()
+            .build_pool(1)
+            .unwrap();
+        let backend = Backend::new(
+            users,
+            hasher,
+            UserCache::new(Redis::new(redis, ""), false),
+            30,
+        );
+
+        let username = Username::parse("alice").unwrap();
+        let logged_in = backend
+            .authenticate(LoginCredentials::new(username.clone(), password))
+            .await
+            .unwrap();
+        assert_eq!(Some(user.user_id), logged_in.map(|user| user.user_id));
+
+        let wrong_password = Password::parse("wrong-horse-battery").unwrap();
+        let rejected = backend
+            .authenticate(LoginCredentials::new(username, wrong_password))
+            .await
+            .unwrap();
+        assert!(rejected.is_none());
+
+        assert!(backend.get_user(&user.user_id).await.unwrap().is_some());
+        assert!(backend.get_user(&Uuid::new_v4()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    pub async fn deactivated_users_are_only_told_so_with_the_right_password() {
+        let config = Config::for_tests(&[
+            "--hmac-key=0123456789012345678901234567890123456789012345678901234567890123",
+        ]);
+        let hasher = Hasher::from_settings(&config.application_settings).unwrap();
+        let password = Password::parse("correct-horse-battery").unwrap();
+        let hash = hasher.hash(password.clone()).await.unwrap();
+        let mut user = user_with_hash(&hash);
+        user.deactivated_at = Some(OffsetDateTime::now_utc() - time::Duration::days(1));
+        let mut expired = user_with_hash(&hash);
+        expired.username = "bob".to_string();
+        expired.deactivated_at = Some(OffsetDateTime::now_utc() - time::Duration::days(31));
+
+        let users = Arc::new(FakeUserRepo::default());
+        users.add(user.clone());
+        users.add(expired);
+        let redis = fred::prelude::Builder::default_centralized()
+            .build_pool(1)
+            .unwrap();
+        let backend = Backend::new(
+            users,
+            hasher,
+            UserCache::new(Redis::new(redis, ""), false),
+            30,
+        );
+        assert!(backend.get_user(&user.user_id).await.unwrap().is_none());
+
+        let username = Username::parse("alice").unwrap();
+        let wrong_password = Password::parse("wrong-horse-battery").unwrap();
+        let rejected = backend
+            .authenticate(LoginCredentials::new(username.clone(), wrong_password))
+            .await
+            .unwrap();
+        assert!(rejected.is_none());
+
+        let refused = backend
+            .authenticate(LoginCredentials::new(username.clone(), password.clone()))
+            .await;
+        assert!(matches!(
+            refused,
+            Err(AuthError::AccountDeactivated { grace_days: 30 })
+        ));
+
+        let reactivated = backend
+            .authenticate(LoginCredentials::new(username, password.clone()).reactivating())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(reactivated.was_deactivated());
+        assert!(backend.get_user(&user.user_id).await.unwrap().is_some());
+
+        let username = Username::parse("bob").unwrap();
+        let too_late = backend
+            .authenticate(LoginCredentials::new(username, password).reactivating())
+            .await
+            .unwrap();
+        assert!(too_late.is_none());
+    }
+
+    #[test]
+    fn wait_times_are_rounded_up_to_whole_units() {
+        assert_eq!("1 second", wait_time(Duration::from_millis(200)));
+        assert_eq!("59 seconds", wait_time(Duration::from_millis(58_001)));
+        assert_eq!("1 minute", wait_time(Duration::from_secs(60)));
+        assert_eq!("4 minutes", wait_time(Duration::from_secs(181)));
+    }
+}
@@ -0,0 +1,269 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Form, Json,
+    extract::State,
+    response::{AppendHeaders, IntoResponse},
+};
+use axum_login::AuthnBackend;
+use http::StatusCode;
+use sqlx::PgPool;
+use tower_sessions::Session;
+use uuid::Uuid;
+use webauthn_rs::prelude::{
+    AuthenticationResult, Passkey, PasskeyAuthentication, PublicKeyCredential,
+    RequestChallengeResponse, Url, Webauthn, WebauthnBuilder, WebauthnError,
+};
+
+use crate::{
+    api_error::ApiError,
+    app::ApiContext,
+    audit::{AuditEntry, AuditEvent, RequestMetadata},
+    auth::{AuthError, AuthSession, login::complete_login},
+    config::ApplicationSettings,
+    domain::username::Username,
+};
+
+/// Session key of the login ceremony in progress
+const LOGIN_STATE_KEY: &str = "passkey_login";
+
+/// The relying party passkeys are registered with, from `WEBAUTHN_RP_ID` and
+/// `WEBAUTHN_RP_ORIGIN`, falling back to `APP_BASE_URL`
+pub fn webauthn_from_settings(settings: &ApplicationSettings) -> Result<Webauthn, anyhow::Error> {
+    let origin = settings
+        .webauthn_rp_origin
+        .as_deref()
+        .unwrap_or(&settings.app_base_url);
+    let origin = Url::parse(origin).context("Invalid passkey origin")?;
+    let rp_id = match &settings.webauthn_rp_id {
+        Some(rp_id) => rp_id.clone(),
+        None => origin
+            .host_str()
+            .context("Passkey origin has no host")?
+            .to_string(),
+    };
+
+    WebauthnBuilder::new(&rp_id, &origin)
+        .context("Invalid passkey relying party")?
+        .rp_name("tufourn")
+        .build()
+        .context("Invalid passkey relying party")
+}
+
+/// The registered passkeys of a user, as webauthn-rs needs them
+pub async fn fetch_passkeys(db: &PgPool, user_id: Uuid) -> Result<Vec<Passkey>, anyhow::Error> {
+    let credentials = sqlx::query_scalar!(
+        r#"
+        SELECT public_key FROM passkeys WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to get passkeys")?;
+
+    credentials
+        .into_iter()
+        .map(|credential| serde_json::from_value(credential).context("Invalid stored passkey"))
+        .collect()
+}
+
+/// Stores the new signature counter of the passkey that was used, `false`
+/// if it has been removed since the ceremony started
+async fn record_passkey_use(
+    db: &PgPool,
+    user_id: Uuid,
+    result: &AuthenticationResult,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = db.begin().await.context("Failed to begin transaction")?;
+
+    let Some(credential) = sqlx::query_scalar!(
+        r#"
+        SELECT public_key FROM passkeys
+        WHERE user_id = $1 AND credential_id = $2
+        FOR UPDATE
+        "#,
+        user_id,
+        result.cred_id().as_ref()
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to get passkey")?
+    else {
+        return Ok(false);
+    };
+
+    let mut passkey: Passkey =
+        serde_json::from_value(credential).context("Invalid stored passkey")?;
+    passkey.update_credential(result);
+
+    sqlx::query!(
+        r#"
+        UPDATE passkeys SET public_key = $3, sign_count = $4, last_used_at = NOW()
+        WHERE user_id = $1 AND credential_id = $2
+        "#,
+        user_id,
+        result.cred_id().as_ref(),
+        serde_json::to_value(&passkey).context("Failed to serialize passkey")?,
+        i64::from(result.counter())
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update passkey")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+    Ok(true)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PasskeyLoginError {
+    #[error("The passkey couldn't be verified")]
+    Rejected,
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+}
+
+impl From<anyhow::Error> for PasskeyLoginError {
+    fn from(e: anyhow::Error) -> Self {
+        PasskeyLoginError::Auth(AuthError::UnexpectedError(e))
+    }
+}
+
+impl IntoResponse for PasskeyLoginError {
+    fn into_response(self) -> axum::response::Response {
+        let message = self.to_string();
+        match self {
+            PasskeyLoginError::Rejected => {
+                ApiError::new(StatusCode::UNAUTHORIZED, "passkey_rejected", message).into_response()
+            }
+            PasskeyLoginError::Auth(e) => e.into_response(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasskeyLoginFormData {
+    username: String,
+}
+
+/// What the login ceremony needs to be finished, kept in the session
+#[derive(serde::Serialize, serde::Deserialize)]
+struct LoginState {
+    user_id: Uuid,
+    state: PasskeyAuthentication,
+}
+
+/// Starts logging in with one of the user's passkeys. Unknown users and
+/// users without passkeys get the same error as a wrong password, and so do
+/// deactivated ones, which only their password reactivates.
+pub async fn start_passkey_login(
+    State(api_context): State<Arc<ApiContext>>,
+    session: Session,
+    Form(form_data): Form<PasskeyLoginFormData>,
+) -> Result<Json<RequestChallengeResponse>, PasskeyLoginError> {
+    let username =
+        Username::parse(&form_data.username).map_err(|_| AuthError::InvalidCredentials)?;
+
+    let user_id = sqlx::query_scalar!(
+        r#"
+        SELECT user_id FROM user_info
+        WHERE username = $1 AND locked_at IS NULL AND deactivated_at IS NULL
+        "#,
+        username.as_ref()
+    )
+    .fetch_optional(&api_context.db)
+    .await
+    .context("Failed to get user")?
+    .ok_or(AuthError::InvalidCredentials)?;
+
+    let passkeys = fetch_passkeys(&api_context.db, user_id).await?;
+    if passkeys.is_empty() {
+        return Err(AuthError::InvalidCredentials.into());
+    }
+
+    let (challenge, state) = api_context
+        .webauthn
+        .start_passkey_authentication(&passkeys)
+        .context("Failed to start passkey authentication")?;
+
+    session
+        .insert(LOGIN_STATE_KEY, LoginState { user_id, state })
+        .await
+        .context("Failed to save passkey login")?;
+
+    Ok(Json(challenge))
+}
+
+/// Checks the browser's answer to the challenge and logs the user in
+pub async fn finish_passkey_login(
+    State(api_context): State<Arc<ApiContext>>,
+    mut auth_session: AuthSession,
+    session: Session,
+    request: RequestMetadata,
+    Json(credential): Json<PublicKeyCredential>,
+) -> Result<impl IntoResponse, PasskeyLoginError> {
+    // each challenge can only be answered once
+    let login: LoginState = session
+        .remove(LOGIN_STATE_KEY)
+        .await
+        .context("Failed to get passkey login")?
+        .ok_or(PasskeyLoginError::Rejected)?;
+
+    let result = match api_context
+        .webauthn
+        .finish_passkey_authentication(&credential, &login.state)
+    {
+        Ok(result) => result,
+        Err(WebauthnError::CredentialPossibleCompromise) => {
+            tracing::warn!(
+                user_id = %login.user_id,
+                credential_id = %credential.id,
+                "Passkey signature counter went backwards, it may have been cloned"
+            );
+            api_context.audit.record(
+                AuditEntry::new(
+                    AuditEvent::SuspiciousPasskeyUse,
+                    Some(login.user_id),
+                    &request,
+                )
+                .with_metadata(serde_json::json!({ "credential_id": credential.id })),
+            );
+            return Err(PasskeyLoginError::Rejected);
+        }
+        Err(e) => {
+            tracing::debug!(error = ?e, "Passkey authentication failed");
+            api_context.audit.record(
+                AuditEntry::new(AuditEvent::LoginFailed, Some(login.user_id), &request)
+                    .with_metadata(serde_json::json!({ "method": "passkey" })),
+            );
+            return Err(PasskeyLoginError::Rejected);
+        }
+    };
+
+    if !record_passkey_use(&api_context.db, login.user_id, &result).await? {
+        return Err(PasskeyLoginError::Rejected);
+    }
+
+    // locked in the meantime
+    let user = auth_session
+        .backend
+        .get_user(&login.user_id)
+        .await?
+        .ok_or(PasskeyLoginError::Rejected)?;
+
+    complete_login(
+        &api_context,
+        &mut auth_session,
+        &session,
+        &user,
+        request,
+        Some("passkey"),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, AppendHeaders([("HX-Redirect", "/")])))
+}
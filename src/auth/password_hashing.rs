@@ -0,0 +1,186 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, anyhow};
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, Version,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use password_auth::verify_password;
+use secrecy::{ExposeSecret, SecretString};
+use tokio::sync::Semaphore;
+
+use crate::{config::ApplicationSettings, domain::password::Password};
+
+#[derive(thiserror::Error, Debug)]
+pub enum HasherError {
+    #[error("The server is busy, try again shortly")]
+    Busy,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Hashes and checks passwords on the blocking pool, a limited number at a
+/// time.
+///
+/// Without the limit a burst of logins could take up the whole blocking
+/// pool and starve everything else that runs there. Requests over the limit
+/// wait for a while and then fail with [`HasherError::Busy`].
+#[derive(Debug, Clone)]
+pub struct Hasher {
+    policy: HashingPolicy,
+    permits: Arc<Semaphore>,
+    queue_timeout: Duration,
+    /// Made with the same parameters as new passwords, so checking against it
+    /// takes as long as checking a real one
+    dummy_hash: Arc<str>,
+}
+
+impl Hasher {
+    pub fn from_settings(settings: &ApplicationSettings) -> Result<Self, anyhow::Error> {
+        let permits = settings
+            .password_hashing_permits
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |cores| cores.get()));
+
+        let policy = HashingPolicy::from_settings(settings)?;
+        let dummy_hash = policy.hash(b"not the password of any account")?;
+
+        Ok(Self {
+            policy,
+            permits: Arc::new(Semaphore::new(permits)),
+            queue_timeout: Duration::from_millis(settings.password_hashing_queue_timeout_millis),
+            dummy_hash: dummy_hash.into(),
+        })
+    }
+
+    /// A hash no password matches, checked against when there is no account,
+    /// so the response time doesn't tell whether there is one
+    pub fn dummy_hash(&self) -> SecretString {
+        SecretString::from(self.dummy_hash.as_ref())
+    }
+
+    pub async fn hash(&self, password: Password) -> Result<String, HasherError> {
+        let policy = self.policy.clone();
+        self.run(move || policy.hash(password.expose_secret().as_bytes()))
+            .await?
+            .map_err(HasherError::from)
+    }
+
+    /// Whether the password matches the PHC string
+    pub async fn verify(
+        &self,
+        password: Password,
+        hash: SecretString,
+    ) -> Result<bool, HasherError> {
+        self.run(move || {
+            verify_password(password.expose_secret().as_bytes(), hash.expose_secret()).is_ok()
+        })
+        .await
+    }
+
+    pub fn needs_upgrade(&self, hash: &str) -> bool {
+        self.policy.needs_upgrade(hash)
+    }
+
+    async fn run<T: Send + 'static>(
+        &self,
+        work: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, HasherError> {
+        let permit = tokio::time::timeout(self.queue_timeout, self.permits.clone().acquire_owned())
+            .await
+            .map_err(|_| HasherError::Busy)?
+            .context("Password hashing semaphore closed")?;
+
+        let result = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            work()
+        })
+        .await
+        .context("Failed to spawn blocking task")?;
+
+        Ok(result)
+    }
+}
+
+/// The Argon2id parameters new password hashes are made with.
+///
+/// Hashes are self-describing, so raising the parameters doesn't break
+/// existing ones. They are upgraded the next time their owner logs in.
+#[derive(Debug, Clone)]
+struct HashingPolicy {
+    params: Params,
+}
+
+impl HashingPolicy {
+    fn from_settings(settings: &ApplicationSettings) -> Result<Self, anyhow::Error> {
+        let params = Params::new(
+            settings.argon2_memory_kib,
+            settings.argon2_iterations,
+            settings.argon2_parallelism,
+            None,
+        )
+        .map_err(|e| anyhow!("Invalid Argon2 parameters: {e}"))?;
+
+        Ok(Self { params })
+    }
+
+    /// Slow by design, call it from a blocking task
+    fn hash(&self, password: &[u8]) -> Result<String, anyhow::Error> {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+            .hash_password(password, &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| anyhow!("Failed to hash password: {e}"))
+    }
+
+    /// Whether the hash was made with weaker parameters than the policy, or
+    /// with another algorithm
+    fn needs_upgrade(&self, hash: &str) -> bool {
+        // a hash that doesn't parse can't be verified either, so nobody logs in with it
+        let Ok(parsed) = PasswordHash::new(hash) else {
+            return false;
+        };
+        if parsed.algorithm != Algorithm::Argon2id.ident() {
+            return true;
+        }
+        let Ok(params) = Params::try_from(&parsed) else {
+            return true;
+        };
+
+        params.m_cost() < self.params.m_cost()
+            || params.t_cost() < self.params.t_cost()
+            || params.p_cost() < self.params.p_cost()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::Params;
+
+    use crate::auth::password_hashing::HashingPolicy;
+
+    fn policy(memory_kib: u32, iterations: u32) -> HashingPolicy {
+        HashingPolicy {
+            params: Params::new(memory_kib, iterations, 1, None).unwrap(),
+        }
+    }
+
+    #[test]
+    pub fn hash_made_with_the_policy_needs_no_upgrade() {
+        let policy = policy(64, 1);
+        let hash = policy.hash(b"password").unwrap();
+        assert!(!policy.needs_upgrade(&hash));
+    }
+
+    #[test]
+    pub fn hash_with_less_memory_or_iterations_needs_an_upgrade() {
+        let hash = policy(64, 1).hash(b"password").unwrap();
+        assert!(policy(128, 1).needs_upgrade(&hash));
+        assert!(policy(64, 2).needs_upgrade(&hash));
+    }
+
+    #[test]
+    pub fn hash_with_stronger_parameters_is_left_alone() {
+        let hash = policy(128, 2).hash(b"password").unwrap();
+        assert!(!policy(64, 1).needs_upgrade(&hash));
+    }
+}
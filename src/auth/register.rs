@@ -4,172 +4,732 @@ use anyhow::Context;
 use askama::Template;
 use askama_web::WebTemplate;
 use axum::{
-    Form,
-    extract::State,
+    extract::{Query, State},
     http::StatusCode,
-    response::{AppendHeaders, IntoResponse},
+    response::{IntoResponse, Response},
 };
-use password_auth::generate_hash;
 use secrecy::ExposeSecret;
 use sqlx::{Executor, PgPool, Postgres, Transaction};
+use time::{Duration, OffsetDateTime};
+use tower_sessions::Session;
 use uuid::Uuid;
 
 use crate::{
+    api_error::ApiError,
     app::ApiContext,
+    audit::{AuditEntry, AuditEvent, RequestMetadata},
+    auth::{
+        Hasher, HasherError, Role, form_token, invitation,
+        submission::{self, Claim},
+    },
+    captcha::{self, CaptchaError, CaptchaWidget},
+    client_ip::ClientIp,
+    config::RegistrationMode,
     domain::{
         email_address::{EmailAddress, InvalidEmailError},
         password::{InvalidPasswordError, Password},
         username::{InvalidUsernameError, Username},
     },
+    emails::{self, Email, ExistingAccount},
+    i18n::{Locale, Translatable, filters},
+    negotiate::{IsHtmx, redirect_response},
+    page::PageContext,
+    validation::{self, FormErrors, InvalidField, ValidatedForm},
 };
 
 #[derive(Template, WebTemplate)]
 #[template(path = "auth/register.html")]
-pub struct RegisterTemplate {}
+pub struct RegisterTemplate {
+    page_context: PageContext,
+    locale: Locale,
+    form_token: String,
+    /// Makes sending the form twice harmless, see [`submission`]
+    submission_token: Option<String>,
+    mode: RegistrationMode,
+    /// From the link of an invitation
+    invite_code: String,
+    /// Given back after a failure, like the invitation code
+    email: String,
+    username: String,
+    /// Why the form posted without htmx failed
+    error: Option<String>,
+    /// Shown next to their field, see [`ValidatedForm`]
+    invalid_fields: Vec<InvalidField>,
+    /// Set once the client failed too often, see [`captcha`]
+    captcha: Option<CaptchaWidget>,
+}
+
+impl RegisterTemplate {
+    fn needs_invitation(&self) -> bool {
+        self.mode == RegistrationMode::Invite
+    }
+
+    /// Whether the form collects addresses for the waitlist instead
+    fn is_closed(&self) -> bool {
+        self.mode == RegistrationMode::Closed
+    }
+
+    fn field_error(&self, field: &str) -> Option<&'static str> {
+        validation::field_error(&self.invalid_fields, field, self.locale)
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct RegisterQuery {
+    code: Option<String>,
+}
+
+pub async fn register_page(
+    State(api_context): State<Arc<ApiContext>>,
+    page_context: PageContext,
+    locale: Locale,
+    session: Session,
+    ClientIp(ip): ClientIp,
+    Query(query): Query<RegisterQuery>,
+) -> RegisterTemplate {
+    // without a token the form is still accepted, only not checked
+    let submission_token = match api_context
+        .config
+        .application_settings
+        .registration_single_use_forms
+    {
+        true => submission::issue(&session)
+            .await
+            .inspect_err(|e| tracing::error!(error = ?e, "Failed to issue submission token"))
+            .ok(),
+        false => None,
+    };
+    RegisterTemplate {
+        page_context,
+        locale,
+        form_token: form_token::issue(hmac_key(&api_context), OffsetDateTime::now_utc()),
+        submission_token,
+        mode: api_context.config.application_settings.registration_mode,
+        invite_code: query.code.unwrap_or_default(),
+        email: String::new(),
+        username: String::new(),
+        error: None,
+        invalid_fields: Vec::new(),
+        captcha: captcha::widget_for(&api_context, ip).await,
+    }
+}
+
+/// Where people who joined the waitlist are sent
+#[derive(Template, WebTemplate)]
+#[template(path = "auth/waitlisted.html")]
+pub struct WaitlistedTemplate {
+    page_context: PageContext,
+    locale: Locale,
+}
+
+pub async fn waitlisted_page(page_context: PageContext, locale: Locale) -> WaitlistedTemplate {
+    WaitlistedTemplate {
+        page_context,
+        locale,
+    }
+}
 
-pub async fn register_page() -> RegisterTemplate {
-    RegisterTemplate {}
+fn hmac_key(api_context: &ApiContext) -> &[u8] {
+    api_context
+        .config
+        .application_settings
+        .hmac_key
+        .expose_secret()
+        .as_bytes()
 }
 
 #[derive(serde::Deserialize)]
 pub struct RegisterFormData {
     email: String,
+    /// Left out by the waitlist form
+    #[serde(default)]
     username: String,
+    #[serde(default)]
     password: String,
+    /// Needed while registration is invite only
+    invite_code: Option<String>,
+    /// Hidden from people, only bots filling in every field put something here
+    #[serde(default)]
+    website: String,
+    /// When the form was served, see [`form_token`]
+    form_token: Option<String>,
+    /// See [`submission`], left out by clients that didn't load the form
+    submission_token: Option<String>,
+    /// Put in the form by the widget, see [`captcha`]
+    #[serde(default, alias = "cf-turnstile-response", alias = "h-captcha-response")]
+    captcha_token: Option<String>,
 }
 
 struct RegisterCredentials {
     pub email: EmailAddress,
     pub username: Username,
-    pub password: Password,
+    pub password_hash: String,
+    pub role: Role,
+    /// The terms of service the user accepted by registering, if any
+    pub tos_version: Option<i32>,
 }
 
 #[derive(thiserror::Error, Debug)]
+#[error("{}", self.message(Locale::En))]
 pub enum RegisterError {
-    #[error("Invalid email address")]
     InvalidEmail(#[from] InvalidEmailError),
-    #[error("Invalid username")]
     InvalidUsername(#[from] InvalidUsernameError),
-    #[error("Invalid password")]
     InvalidPassword(#[from] InvalidPasswordError),
-    #[error("Email already exists")]
     EmailExists,
-    #[error("Username already exists")]
     UsernameExists,
-    #[error("An internal server error occured")]
+    InvalidFormToken,
+    SubmittedTooQuickly,
+    AlreadySubmitted,
+    InvalidInvitation,
+    InvalidCaptcha,
+    ServerBusy,
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl Translatable for RegisterError {
+    fn message_key(&self) -> &'static str {
+        match self {
+            RegisterError::InvalidEmail(_) => "error.register.invalid_email",
+            RegisterError::InvalidUsername(_) => "error.register.invalid_username",
+            RegisterError::InvalidPassword(_) => "error.register.invalid_password",
+            RegisterError::EmailExists => "error.register.email_exists",
+            RegisterError::UsernameExists => "error.register.username_exists",
+            RegisterError::InvalidFormToken => "error.register.invalid_form_token",
+            RegisterError::SubmittedTooQuickly => "error.register.submitted_too_quickly",
+            RegisterError::AlreadySubmitted => "error.register.already_submitted",
+            RegisterError::InvalidInvitation => "error.register.invalid_invitation",
+            RegisterError::InvalidCaptcha => "error.register.invalid_captcha",
+            RegisterError::ServerBusy => "error.register.server_busy",
+            RegisterError::UnexpectedError(_) => "error.internal",
+        }
+    }
+}
+
 impl IntoResponse for RegisterError {
     fn into_response(self) -> axum::response::Response {
-        let status_code = match self {
-            RegisterError::InvalidEmail(_)
-            | RegisterError::InvalidUsername(_)
-            | RegisterError::InvalidPassword(_) => StatusCode::BAD_REQUEST,
-            RegisterError::UsernameExists | RegisterError::EmailExists => StatusCode::CONFLICT,
-            RegisterError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        (status_code, self.to_string()).into_response()
+        self.into_api_error(Locale::En).into_response()
+    }
+}
+
+impl RegisterError {
+    /// Whether the error counts towards the CAPTCHA, which the server
+    /// failing doesn't
+    fn is_failed_attempt(&self) -> bool {
+        !matches!(
+            self,
+            RegisterError::InvalidCaptcha
+                | RegisterError::ServerBusy
+                | RegisterError::UnexpectedError(_)
+        )
+    }
+
+    /// The error with its message in the locale of the request
+    fn into_api_error(self, locale: Locale) -> ApiError {
+        let message = self.message(locale);
+        match self {
+            RegisterError::InvalidEmail(_) => {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_email", message).with_field("email")
+            }
+            RegisterError::InvalidUsername(_) => {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_username", message)
+                    .with_field("username")
+            }
+            RegisterError::InvalidPassword(_) => {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_password", message)
+                    .with_field("password")
+            }
+            RegisterError::InvalidFormToken => {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_form_token", message)
+            }
+            RegisterError::SubmittedTooQuickly => {
+                ApiError::new(StatusCode::BAD_REQUEST, "submitted_too_quickly", message)
+            }
+            RegisterError::AlreadySubmitted => {
+                ApiError::new(StatusCode::CONFLICT, "already_submitted", message)
+            }
+            RegisterError::InvalidInvitation => {
+                ApiError::new(StatusCode::FORBIDDEN, "invalid_invitation", message)
+                    .with_field("invite_code")
+            }
+            RegisterError::InvalidCaptcha => {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_captcha", message)
+            }
+            RegisterError::EmailExists => {
+                ApiError::new(StatusCode::CONFLICT, "email_taken", message).with_field("email")
+            }
+            RegisterError::UsernameExists => {
+                ApiError::new(StatusCode::CONFLICT, "username_taken", message)
+                    .with_field("username")
+            }
+            RegisterError::ServerBusy => {
+                ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "server_busy", message)
+            }
+            RegisterError::UnexpectedError(e) => ApiError::internal(&e),
+        }
+    }
+}
+
+impl From<CaptchaError> for RegisterError {
+    fn from(e: CaptchaError) -> Self {
+        match e {
+            CaptchaError::Invalid => RegisterError::InvalidCaptcha,
+            CaptchaError::UnexpectedError(e) => RegisterError::UnexpectedError(e),
+        }
+    }
+}
+
+impl From<HasherError> for RegisterError {
+    fn from(e: HasherError) -> Self {
+        match e {
+            HasherError::Busy => RegisterError::ServerBusy,
+            HasherError::UnexpectedError(e) => RegisterError::UnexpectedError(e),
+        }
+    }
+}
+
+/// What the form is filled in with again after a failure, all but the
+/// password
+struct Submitted {
+    form_token: Option<String>,
+    submission_token: Option<String>,
+    invite_code: String,
+    email: String,
+    username: String,
+}
+
+impl Submitted {
+    fn from_form(form_data: &RegisterFormData) -> Self {
+        Self {
+            form_token: form_data.form_token.clone(),
+            // given back by the failure, so it can be sent again
+            submission_token: form_data.submission_token.clone(),
+            invite_code: form_data.invite_code.clone().unwrap_or_default(),
+            email: form_data.email.clone(),
+            username: form_data.username.clone(),
+        }
+    }
+
+    fn from_errors(errors: &FormErrors) -> Self {
+        let value = |field| Some(errors.value(field).to_string()).filter(|value| !value.is_empty());
+        Self {
+            form_token: value("form_token"),
+            submission_token: value("submission_token"),
+            invite_code: errors.value("invite_code").to_string(),
+            email: errors.value("email").to_string(),
+            username: errors.value("username").to_string(),
+        }
     }
 }
 
+/// Browsers posting the form themselves get the form back with the error
+/// on it, filled in as it was sent and keeping when it was first served
 pub async fn register_user(
     State(api_context): State<Arc<ApiContext>>,
-    Form(form_data): Form<RegisterFormData>,
-) -> Result<impl IntoResponse, RegisterError> {
-    let email = validate_email(&form_data.email, &api_context.db).await?;
-    let username = validate_username(&form_data.username, &api_context.db).await?;
+    locale: Locale,
+    request: RequestMetadata,
+    is_htmx: IsHtmx,
+    page_context: PageContext,
+    session: Session,
+    form: Result<ValidatedForm<RegisterFormData>, FormErrors>,
+) -> Response {
+    let form_data = match form {
+        Ok(ValidatedForm(form_data)) => form_data,
+        Err(errors) if is_htmx.0 => return errors.into_api_error(locale).into_response(),
+        Err(errors) => {
+            let submitted = Submitted::from_errors(&errors);
+            let invalid_fields = errors.fields().to_vec();
+            let error = errors.into_api_error(locale);
+            let page = register_page_again(
+                &api_context,
+                &request,
+                page_context,
+                locale,
+                submitted,
+                &error,
+                invalid_fields,
+            )
+            .await;
+            return (error.status(), page).into_response();
+        }
+    };
+
+    let submitted = Submitted::from_form(&form_data);
+    let result = register(&api_context, &request, &session, form_data).await;
+    if let Err(e) = &result
+        && e.is_failed_attempt()
+    {
+        captcha::record_failure(&api_context, request.ip_address.as_deref()).await;
+    }
+    let (status, path) = match result {
+        Ok(Registration::Registered) => (StatusCode::CREATED, "/login"),
+        Ok(Registration::Waitlisted) => (StatusCode::ACCEPTED, "/register/waitlist"),
+        Err(e) if is_htmx.0 => return e.into_api_error(locale).into_response(),
+        Err(e) => {
+            let error = e.into_api_error(locale);
+            let page = register_page_again(
+                &api_context,
+                &request,
+                page_context,
+                locale,
+                submitted,
+                &error,
+                Vec::new(),
+            )
+            .await;
+            return (error.status(), page).into_response();
+        }
+    };
+
+    match is_htmx {
+        // htmx clients are told what became of the registration too
+        IsHtmx(true) => (status, redirect_response(is_htmx, path)).into_response(),
+        IsHtmx(false) => redirect_response(is_htmx, path),
+    }
+}
+
+async fn register_page_again(
+    api_context: &ApiContext,
+    request: &RequestMetadata,
+    page_context: PageContext,
+    locale: Locale,
+    submitted: Submitted,
+    error: &ApiError,
+    invalid_fields: Vec<InvalidField>,
+) -> RegisterTemplate {
+    RegisterTemplate {
+        page_context,
+        locale,
+        form_token: submitted
+            .form_token
+            .unwrap_or_else(|| form_token::issue(hmac_key(api_context), OffsetDateTime::now_utc())),
+        submission_token: submitted.submission_token,
+        mode: api_context.config.application_settings.registration_mode,
+        invite_code: submitted.invite_code,
+        email: submitted.email,
+        username: submitted.username,
+        error: Some(error.message().to_string()),
+        invalid_fields,
+        captcha: captcha::widget_for(api_context, request.ip_address.as_deref()).await,
+    }
+}
+
+/// What became of the registration, which depends on the
+/// [`RegistrationMode`]
+#[derive(Debug, PartialEq)]
+enum Registration {
+    Registered,
+    Waitlisted,
+}
+
+/// Acts on each form served only once, see [`submission`]
+async fn register(
+    api_context: &ApiContext,
+    request: &RequestMetadata,
+    session: &Session,
+    form_data: RegisterFormData,
+) -> Result<Registration, RegisterError> {
+    let token = form_data.submission_token.clone().filter(|_| {
+        api_context
+            .config
+            .application_settings
+            .registration_single_use_forms
+    });
+    let Some(token) = token else {
+        return register_once(api_context, request, form_data).await;
+    };
+
+    match submission::claim(&api_context.redis, session, &token).await? {
+        Claim::Claimed => {}
+        Claim::Unknown => return Err(RegisterError::InvalidFormToken),
+        Claim::AlreadyClaimed => return Err(RegisterError::AlreadySubmitted),
+    }
+    let registration = register_once(api_context, request, form_data).await;
+    submission::settle(&api_context.redis, session, &token, registration.is_ok()).await;
+    registration
+}
+
+/// Bots are told they registered too, see [`RegisterFormData::website`]
+async fn register_once(
+    api_context: &ApiContext,
+    request: &RequestMetadata,
+    form_data: RegisterFormData,
+) -> Result<Registration, RegisterError> {
+    let settings = &api_context.config.application_settings;
+    let blocked = |reason: &str| {
+        api_context.audit.record(
+            AuditEntry::new(AuditEvent::RegistrationBlocked, None, request).with_metadata(
+                serde_json::json!({ "reason": reason, "username": form_data.username }),
+            ),
+        );
+    };
+
+    let succeeded = match settings.registration_mode {
+        RegistrationMode::Open | RegistrationMode::Invite => Registration::Registered,
+        RegistrationMode::Closed => Registration::Waitlisted,
+    };
+
+    // bots are told they succeeded, so they don't learn to leave the field empty
+    if settings.registration_honeypot_enabled && !form_data.website.is_empty() {
+        blocked("honeypot");
+        return Ok(succeeded);
+    }
+
+    if settings.registration_min_fill_secs > 0 {
+        let age = form_data.form_token.as_deref().and_then(|token| {
+            form_token::age(hmac_key(api_context), token, OffsetDateTime::now_utc())
+        });
+        let Some(age) = age else {
+            blocked("invalid_form_token");
+            return Err(RegisterError::InvalidFormToken);
+        };
+        if age < Duration::seconds(settings.registration_min_fill_secs as i64) {
+            blocked("too_quick");
+            return Err(RegisterError::SubmittedTooQuickly);
+        }
+    }
+
+    captcha::check(
+        api_context,
+        request.ip_address.as_deref(),
+        form_data.captcha_token.as_deref(),
+    )
+    .await?;
+
+    let email = EmailAddress::parse(&form_data.email)?;
+    if settings.registration_mode == RegistrationMode::Closed {
+        invitation::join_waitlist(&api_context.db, &email).await?;
+        return Ok(Registration::Waitlisted);
+    }
+
+    let username = Username::parse(&form_data.username)?;
+    let hold_days = settings.username_hold_days;
+    if username_on_hold(&api_context.db, &username, hold_days, None).await? {
+        return Err(RegisterError::UsernameExists);
+    }
     let password = Password::parse(&form_data.password)?;
 
+    // hashed before the transaction starts, it can take a while under load
+    let register_credentials = RegisterCredentials {
+        email: email.clone(),
+        username,
+        password_hash: api_context.hasher.hash(password).await?,
+        role: Role::User,
+        tos_version: Some(settings.tos_version),
+    };
+
     let mut transaction = api_context
         .db
         .begin()
         .await
         .context("Failed to begin transaction")?;
 
-    let register_credentials = RegisterCredentials {
-        email,
-        username,
-        password,
+    let invite_code = match settings.registration_mode {
+        RegistrationMode::Invite => {
+            let code = form_data.invite_code.as_deref().map(str::trim);
+            let code = code.filter(|code| !code.is_empty());
+            let Some(code) = code else {
+                return Err(RegisterError::InvalidInvitation);
+            };
+            if !invitation::consume(&mut transaction, code, &email).await? {
+                return Err(RegisterError::InvalidInvitation);
+            }
+            Some(code)
+        }
+        RegistrationMode::Open | RegistrationMode::Closed => None,
     };
 
-    store_register_credentials(&mut transaction, register_credentials).await?;
+    // a failure rolls the transaction back, giving the invitation's use back
+    let user_id = match store_register_credentials(&mut transaction, register_credentials).await {
+        Ok(user_id) => user_id,
+        // usernames are public anyway, but who has an account with which email isn't
+        Err(RegisterError::EmailExists) if settings.registration_privacy_mode() => {
+            notify_existing_account(api_context, &email).await;
+            return Ok(Registration::Registered);
+        }
+        Err(e) => return Err(e),
+    };
 
     transaction
         .commit()
         .await
         .context("Failed to commit transaction")?;
 
-    Ok((
-        StatusCode::CREATED,
-        AppendHeaders([("HX-Redirect", "/login")]),
-    ))
+    let mut entry = AuditEntry::new(AuditEvent::Registered, Some(user_id), request);
+    if let Some(invite_code) = invite_code {
+        entry = entry.with_metadata(serde_json::json!({ "invitation": invite_code }));
+    }
+    api_context.audit.record(entry);
+
+    Ok(Registration::Registered)
 }
 
-async fn validate_username(username_str: &str, db: &PgPool) -> Result<Username, RegisterError> {
-    let username = Username::parse(username_str)?;
-    let username_exists = sqlx::query_scalar!(
-        r#"
-        SELECT EXISTS(SELECT 1 FROM user_info WHERE username = $1)
-        "#,
-        username.as_ref()
-    )
-    .fetch_one(db)
-    .await
-    .context("Failed to perform query to retrieve username")?;
-    if username_exists == Some(true) {
-        Err(RegisterError::UsernameExists)
-    } else {
-        Ok(username)
-    }
+/// Creates an account without going through the registration form, used to
+/// bootstrap the first admin from the command line
+pub async fn create_user(
+    db: &PgPool,
+    hasher: &Hasher,
+    email: &str,
+    username: &str,
+    password: &str,
+    role: Role,
+) -> Result<Uuid, RegisterError> {
+    let register_credentials = RegisterCredentials {
+        email: EmailAddress::parse(email)?,
+        username: Username::parse(username)?,
+        password_hash: hasher.hash(Password::parse(password)?).await?,
+        role,
+        // asked for on their first visit
+        tos_version: None,
+    };
+
+    let mut transaction = db.begin().await.context("Failed to begin transaction")?;
+    let user_id = store_register_credentials(&mut transaction, register_credentials).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(user_id)
 }
 
-async fn validate_email(email_str: &str, db: &PgPool) -> Result<EmailAddress, RegisterError> {
-    let email = EmailAddress::parse(email_str)?;
-    let email_exists = sqlx::query_scalar!(
+/// Whether another account gave up this username within the last `hold_days`
+/// days, the name stays reserved so it can't be picked up to impersonate them
+pub async fn username_on_hold(
+    db: &PgPool,
+    username: &Username,
+    hold_days: i32,
+    except_user_id: Option<Uuid>,
+) -> Result<bool, anyhow::Error> {
+    let on_hold = sqlx::query_scalar!(
         r#"
-        SELECT EXISTS(SELECT 1 FROM user_info WHERE email = $1)
+        SELECT EXISTS(
+            SELECT 1 FROM username_history
+            WHERE username = $1
+                AND user_id IS DISTINCT FROM $2
+                AND changed_at > NOW() - make_interval(days => $3)
+        ) AS "on_hold!"
         "#,
-        email.as_ref()
+        username.as_ref(),
+        except_user_id,
+        hold_days
     )
     .fetch_one(db)
     .await
-    .context("Failed to perform query to retrieve email")?;
-    if email_exists == Some(true) {
-        Err(RegisterError::EmailExists)
-    } else {
-        Ok(email)
+    .context("Failed to check username history")?;
+
+    Ok(on_hold)
+}
+
+/// Lets the owner of the address know someone tried to register with it.
+/// Best-effort, a failure must not change the response the caller sees.
+async fn notify_existing_account(api_context: &ApiContext, email: &EmailAddress) {
+    let notice = Email::ExistingAccount(ExistingAccount {});
+    if let Err(e) = emails::enqueue(&api_context.db, email, &notice).await {
+        tracing::error!(error = ?e, "Failed to queue existing account notice");
     }
 }
 
+/// Taken usernames and emails are caught by the unique constraints rather
+/// than checked up front, which would take extra round trips and still race
+/// with concurrent registrations
 async fn store_register_credentials(
     transaction: &mut Transaction<'_, Postgres>,
     register_credentials: RegisterCredentials,
-) -> Result<(), anyhow::Error> {
-    let user_id = Uuid::new_v4();
+) -> Result<Uuid, RegisterError> {
+    let result = sqlx::query_scalar!(
+        r#"
+        INSERT INTO user_info (username, email, role, tos_accepted_version, tos_accepted_at)
+        VALUES ($1, $2, $3, COALESCE($4, 0), CASE WHEN $4 IS NOT NULL THEN NOW() END)
+        RETURNING user_id
+        "#,
+        register_credentials.username.as_ref(),
+        register_credentials.email.as_ref(),
+        register_credentials.role as Role,
+        register_credentials.tos_version
+    )
+    .fetch_one(&mut **transaction)
+    .await;
+    let user_id = match result {
+        Ok(user_id) => user_id,
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return match e.constraint() {
+                Some("user_info_username_key") => Err(RegisterError::UsernameExists),
+                Some("user_info_email_key") => Err(RegisterError::EmailExists),
+                _ => Err(anyhow::anyhow!("Unexpected unique violation: {e}").into()),
+            };
+        }
+        Err(e) => {
+            return Err(anyhow::Error::from(e)
+                .context("Failed to insert user info into user_info table")
+                .into());
+        }
+    };
+
     transaction
         .execute(sqlx::query!(
             r#"
-            INSERT INTO user_info (user_id, username, email) VALUES ($1, $2, $3)
+            INSERT INTO user_password (user_id, password_hash) VALUES ($1, $2)
             "#,
             user_id,
-            register_credentials.username.as_ref(),
-            register_credentials.email.as_ref()
+            register_credentials.password_hash,
         ))
         .await
-        .context("Failed to insert user info into user_info table")?;
+        .context("Failed to insert user password into user_password table")?;
 
-    let password_hash = generate_hash(register_credentials.password.expose_secret().as_bytes());
     transaction
         .execute(sqlx::query!(
             r#"
-            INSERT INTO user_password (user_id, password_hash) VALUES ($1, $2)
+            INSERT INTO todo_list (owner_id) VALUES ($1)
             "#,
             user_id,
-            password_hash,
         ))
         .await
-        .context("Failed to insert user password into user_password table")?;
+        .context("Failed to insert todo list into todo_list table")?;
+
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use askama::Template;
+
+    use crate::{
+        auth::register::RegisterTemplate, config::RegistrationMode, i18n::Locale, page::PageContext,
+    };
+
+    fn register_page_in(mode: RegistrationMode) -> String {
+        RegisterTemplate {
+            page_context: PageContext::default(),
+            locale: Locale::En,
+            form_token: "1751198400.0123456789abcdef".to_string(),
+            submission_token: Some("0123456789abcdef0123456789abcdef".to_string()),
+            mode,
+            invite_code: "0123456789abcdef".to_string(),
+            email: String::new(),
+            username: String::new(),
+            error: None,
+            invalid_fields: Vec::new(),
+            captcha: None,
+        }
+        .render()
+        .unwrap()
+    }
+
+    #[test]
+    fn register_page() {
+        insta::assert_snapshot!(register_page_in(RegistrationMode::Open));
+    }
+
+    #[test]
+    fn register_page_asks_for_the_invitation_only_when_invite_only() {
+        assert!(!register_page_in(RegistrationMode::Open).contains("invite_code"));
+        insta::assert_snapshot!(register_page_in(RegistrationMode::Invite));
+    }
 
-    Ok(())
+    #[test]
+    fn closed_register_page_only_asks_for_an_email() {
+        let html = register_page_in(RegistrationMode::Closed);
+        assert!(!html.contains("name=\"password\""));
+        insta::assert_snapshot!(html);
+    }
 }
@@ -0,0 +1,197 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{Role, User};
+use crate::domain::username::Username;
+
+/// Where the [`super::Backend`] looks users up, so logging in can be tested
+/// without Postgres. Locked users are never found.
+#[async_trait]
+pub trait UserRepo: Send + Sync {
+    /// Finds deactivated users too, so they can log in to reactivate
+    async fn find_by_username(&self, username: &Username) -> Result<Option<User>, anyhow::Error>;
+
+    async fn find_by_id(&self, user_id: Uuid) -> Result<Option<User>, anyhow::Error>;
+
+    /// Clears the deactivation of the user unless it is more than
+    /// `grace_days` old, returning whether it was cleared
+    async fn reactivate(&self, user_id: Uuid, grace_days: i32) -> Result<bool, anyhow::Error>;
+
+    /// Stores `new_hash` unless the password was changed since `old_hash` was
+    /// read, returning whether it was stored
+    async fn replace_password_hash(
+        &self,
+        user_id: Uuid,
+        old_hash: &SecretString,
+        new_hash: &str,
+    ) -> Result<bool, anyhow::Error>;
+}
+
+pub struct PgUserRepo {
+    db: PgPool,
+}
+
+impl PgUserRepo {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl UserRepo for PgUserRepo {
+    async fn find_by_username(&self, username: &Username) -> Result<Option<User>, anyhow::Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+            SELECT
+                ui.user_id, ui.username, up.password_hash, ui.role AS "role: Role",
+                ui.tos_accepted_version, ui.deactivated_at
+            FROM user_info AS ui JOIN user_password AS up
+                ON ui.user_id = up.user_id
+            WHERE ui.username = $1 AND ui.locked_at IS NULL
+            "#,
+            username.as_ref(),
+        )
+        .fetch_optional(&self.db)
+        .await
+        .context("Failed to fetch stored user credentials")
+    }
+
+    async fn find_by_id(&self, user_id: Uuid) -> Result<Option<User>, anyhow::Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+            SELECT
+                ui.user_id, ui.username, up.password_hash, ui.role AS "role: Role",
+                ui.tos_accepted_version, ui.deactivated_at
+            FROM user_info AS ui JOIN user_password AS up
+                ON ui.user_id = up.user_id
+            WHERE ui.user_id = $1 AND ui.locked_at IS NULL AND ui.deactivated_at IS NULL
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await
+        .context("Failed to get user")
+    }
+
+    async fn reactivate(&self, user_id: Uuid, grace_days: i32) -> Result<bool, anyhow::Error> {
+        let query_result = sqlx::query!(
+            r#"
+            UPDATE user_info SET deactivated_at = NULL
+            WHERE user_id = $1 AND deactivated_at > NOW() - make_interval(days => $2)
+            "#,
+            user_id,
+            grace_days
+        )
+        .execute(&self.db)
+        .await
+        .context("Failed to reactivate user")?;
+
+        Ok(query_result.rows_affected() > 0)
+    }
+
+    async fn replace_password_hash(
+        &self,
+        user_id: Uuid,
+        old_hash: &SecretString,
+        new_hash: &str,
+    ) -> Result<bool, anyhow::Error> {
+        let query_result = sqlx::query!(
+            r#"
+            UPDATE user_password SET password_hash = $3
+            WHERE user_id = $1 AND password_hash = $2
+            "#,
+            user_id,
+            old_hash.expose_secret(),
+            new_hash
+        )
+        .execute(&self.db)
+        .await
+        .context("Failed to store upgraded password hash")?;
+
+        Ok(query_result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use std::sync::Mutex;
+
+    use time::OffsetDateTime;
+
+    use super::*;
+
+    /// In memory [`UserRepo`]
+    #[derive(Default)]
+    pub(crate) struct FakeUserRepo {
+        users: Mutex<Vec<User>>,
+    }
+
+    impl FakeUserRepo {
+        pub fn add(&self, user: User) {
+            self.users.lock().unwrap().push(user);
+        }
+    }
+
+    #[async_trait]
+    impl UserRepo for FakeUserRepo {
+        async fn find_by_username(
+            &self,
+            username: &Username,
+        ) -> Result<Option<User>, anyhow::Error> {
+            let users = self.users.lock().unwrap();
+            Ok(users
+                .iter()
+                .find(|user| user.username == username.as_ref())
+                .cloned())
+        }
+
+        async fn find_by_id(&self, user_id: Uuid) -> Result<Option<User>, anyhow::Error> {
+            let users = self.users.lock().unwrap();
+            Ok(users
+                .iter()
+                .find(|user| user.user_id == user_id && user.deactivated_at.is_none())
+                .cloned())
+        }
+
+        async fn reactivate(&self, user_id: Uuid, grace_days: i32) -> Result<bool, anyhow::Error> {
+            let mut users = self.users.lock().unwrap();
+            let grace_start = OffsetDateTime::now_utc() - time::Duration::days(grace_days.into());
+            match users.iter_mut().find(|user| {
+                user.user_id == user_id
+                    && user
+                        .deactivated_at
+                        .is_some_and(|deactivated_at| deactivated_at > grace_start)
+            }) {
+                Some(user) => {
+                    user.deactivated_at = None;
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+
+        async fn replace_password_hash(
+            &self,
+            user_id: Uuid,
+            old_hash: &SecretString,
+            new_hash: &str,
+        ) -> Result<bool, anyhow::Error> {
+            let mut users = self.users.lock().unwrap();
+            match users.iter_mut().find(|user| {
+                user.user_id == user_id
+                    && user.password_hash.expose_secret() == old_hash.expose_secret()
+            }) {
+                Some(user) => {
+                    user.password_hash = SecretString::from(new_hash);
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use cookie::{Cookie, CookieJar, Key};
+use http::{HeaderMap, HeaderValue, header};
+use secrecy::ExposeSecret;
+
+use crate::config::ApplicationSettings;
+
+/// Name of the session cookie, set on the session layer
+pub const SESSION_COOKIE: &str = "id";
+
+/// The cookie key panics on anything shorter
+const MIN_KEY_BYTES: usize = 64;
+
+/// Keys of the session cookie. New cookies are signed with `HMAC_KEY`,
+/// cookies signed with one of `HMAC_VERIFICATION_KEYS` are still accepted,
+/// so the key can be rotated without logging everyone out.
+///
+/// The session layer only takes one key, so [`resign_session_cookie`]
+/// signs the cookies of previous keys again before it sees them.
+#[derive(Clone)]
+pub struct SessionKeys {
+    signing_key: Key,
+    previous_keys: Vec<Key>,
+}
+
+impl SessionKeys {
+    pub fn from_settings(settings: &ApplicationSettings) -> Result<Self, anyhow::Error> {
+        let previous_keys = settings
+            .hmac_verification_keys
+            .as_ref()
+            .map(|keys| {
+                keys.expose_secret()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|key| !key.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        Self::new(settings.hmac_key.expose_secret(), &previous_keys)
+    }
+
+    fn new(signing_key: &str, previous_keys: &[String]) -> Result<Self, anyhow::Error> {
+        let keys = std::iter::once(signing_key).chain(previous_keys.iter().map(String::as_str));
+        let mut keys = keys
+            .map(|key| {
+                if key.len() < MIN_KEY_BYTES {
+                    return Err(anyhow!(
+                        "HMAC_KEY and HMAC_VERIFICATION_KEYS have to be at least {MIN_KEY_BYTES} bytes long"
+                    ));
+                }
+                Ok(Key::from(key.as_bytes()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let signing_key = keys.remove(0);
+        Ok(Self {
+            signing_key,
+            previous_keys: keys,
+        })
+    }
+
+    /// The key the session layer signs and verifies the cookie with
+    pub fn signing_key(&self) -> Key {
+        self.signing_key.clone()
+    }
+
+    pub fn previous_key_count(&self) -> usize {
+        self.previous_keys.len()
+    }
+
+    /// Id of the session the request's cookie is for, if the signing key
+    /// signed it. Unlike `Session::id`, known even once the session turned
+    /// out not to exist.
+    pub fn session_id(&self, headers: &HeaderMap) -> Option<String> {
+        let mut jar = CookieJar::new();
+        for value in headers.get_all(header::COOKIE) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for cookie in Cookie::split_parse(value.to_string()).filter_map(Result::ok) {
+                jar.add_original(cookie);
+            }
+        }
+        let cookie = jar.signed(&self.signing_key).get(SESSION_COOKIE)?;
+        Some(cookie.value().to_string())
+    }
+
+    /// The session cookie signed with the signing key, if a previous key
+    /// signed it. `None` when the signing key did, or no key did.
+    fn resign(&self, cookie: Cookie<'static>) -> Option<Cookie<'static>> {
+        let mut jar = CookieJar::new();
+        jar.add_original(cookie);
+        if jar.signed(&self.signing_key).get(SESSION_COOKIE).is_some() {
+            return None;
+        }
+
+        let verified = self
+            .previous_keys
+            .iter()
+            .find_map(|key| jar.signed(key).get(SESSION_COOKIE))?;
+        let mut resigned = CookieJar::new();
+        resigned.signed_mut(&self.signing_key).add(verified);
+        resigned.get(SESSION_COOKIE).cloned()
+    }
+
+    /// A `Cookie` header with the session cookie signed again, if it changed
+    fn resign_header(&self, value: &HeaderValue) -> Option<HeaderValue> {
+        let value = value.to_str().ok()?;
+        let mut changed = false;
+        let cookies: Vec<String> = Cookie::split_parse(value.to_string())
+            .filter_map(Result::ok)
+            .map(|cookie| {
+                if cookie.name() == SESSION_COOKIE
+                    && let Some(resigned) = self.resign(cookie.clone())
+                {
+                    changed = true;
+                    return resigned.stripped().to_string();
+                }
+                cookie.stripped().to_string()
+            })
+            .collect();
+        if !changed {
+            return None;
+        }
+        HeaderValue::from_str(&cookies.join("; ")).ok()
+    }
+
+    fn resign_headers(&self, headers: &mut HeaderMap) {
+        let values: Vec<HeaderValue> = headers.get_all(header::COOKIE).iter().cloned().collect();
+        let resigned: Vec<Option<HeaderValue>> = values
+            .iter()
+            .map(|value| self.resign_header(value))
+            .collect();
+        if resigned.iter().all(Option::is_none) {
+            return;
+        }
+
+        headers.remove(header::COOKIE);
+        for (value, resigned) in values.into_iter().zip(resigned) {
+            headers.append(header::COOKIE, resigned.unwrap_or(value));
+        }
+    }
+}
+
+/// Signs session cookies of previous keys with the signing key, layered
+/// outside the session layer. The session layer sends the cookie back signed
+/// with the signing key the next time the session changes, e.g. on login.
+pub async fn resign_session_cookie(
+    State(keys): State<Arc<SessionKeys>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !keys.previous_keys.is_empty() {
+        keys.resign_headers(request.headers_mut());
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, body::Body, middleware, routing::get};
+    use tower::ServiceExt;
+    use tower_sessions::{MemoryStore, Session, SessionManagerLayer, SessionStore};
+
+    use super::*;
+
+    const KEY_A: &str = "a-key-that-is-at-least-sixty-four-bytes-long-so-cookie-accepts-it";
+    const KEY_B: &str = "b-key-that-is-at-least-sixty-four-bytes-long-so-cookie-accepts-it";
+    const KEY_C: &str = "c-key-that-is-at-least-sixty-four-bytes-long-so-cookie-accepts-it";
+
+    /// An app setting a value in the session on `/set`, and answering with
+    /// it on `/get`
+    fn app(store: MemoryStore, signing_key: &str, previous_keys: &[&str]) -> Router {
+        let previous_keys: Vec<String> = previous_keys.iter().map(|key| key.to_string()).collect();
+        let keys = Arc::new(SessionKeys::new(signing_key, &previous_keys).unwrap());
+        Router::new()
+            .route(
+                "/set",
+                get(|session: Session| async move {
+                    session.insert("value", "kept").await.unwrap();
+                    // changes the session every time
+                    session.insert("nonce", uuid::Uuid::new_v4()).await.unwrap();
+                }),
+            )
+            .route(
+                "/get",
+                get(|session: Session| async move {
+                    session
+                        .get::<String>("value")
+                        .await
+                        .unwrap()
+                        .unwrap_or_default()
+                }),
+            )
+            .layer(
+                SessionManagerLayer::new(store)
+                    .with_name(SESSION_COOKIE)
+                    .with_signed(keys.signing_key()),
+            )
+            .layer(middleware::from_fn_with_state(keys, resign_session_cookie))
+    }
+
+    fn session_cookie(response: &Response) -> String {
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        Cookie::parse(set_cookie.to_string())
+            .unwrap()
+            .stripped()
+            .to_string()
+    }
+
+    /// The body of the response, and the session cookie if it was set
+    async fn send(app: Router, path: &str, cookie: &str) -> (String, Option<String>) {
+        let request = Request::builder()
+            .uri(path)
+            .header(header::COOKIE, format!("theme=dark; {cookie}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let cookie = response
+            .headers()
+            .contains_key(header::SET_COOKIE)
+            .then(|| session_cookie(&response));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (String::from_utf8(body.to_vec()).unwrap(), cookie)
+    }
+
+    #[tokio::test]
+    async fn sessions_of_a_previous_key_outlive_the_rotation() {
+        let store = MemoryStore::default();
+        let response = app(store.clone(), KEY_A, &[])
+            .oneshot(Request::get("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookie_a = session_cookie(&response);
+
+        // B signs new cookies, A is only accepted
+        let (value, _) = send(app(store.clone(), KEY_B, &[KEY_A]), "/get", &cookie_a).await;
+        assert_eq!("kept", value);
+        // the cookie is sent again, signed with B, once the session changes
+        let (_, cookie_b) = send(app(store.clone(), KEY_B, &[KEY_A]), "/set", &cookie_a).await;
+        let cookie_b = cookie_b.expect("Session cookie should be sent again");
+        assert_ne!(cookie_a, cookie_b);
+
+        // once A is dropped, only the cookie signed again keeps working
+        let (value, _) = send(app(store.clone(), KEY_B, &[]), "/get", &cookie_b).await;
+        assert_eq!("kept", value);
+        let (value, _) = send(app(store, KEY_B, &[]), "/get", &cookie_a).await;
+        assert_eq!("", value);
+    }
+
+    #[tokio::test]
+    async fn cookies_of_unknown_keys_are_left_alone() {
+        let store = MemoryStore::default();
+        let response = app(store.clone(), KEY_C, &[])
+            .oneshot(Request::get("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookie_c = session_cookie(&response);
+
+        let (value, _) = send(app(store, KEY_B, &[KEY_A]), "/get", &cookie_c).await;
+        assert_eq!("", value);
+    }
+
+    #[tokio::test]
+    async fn session_ids_are_only_read_from_cookies_of_the_signing_key() {
+        let store = MemoryStore::default();
+        let response = app(store.clone(), KEY_A, &[])
+            .oneshot(Request::get("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_str(&format!("other=1; {}", session_cookie(&response))).unwrap(),
+        );
+
+        let session_id = SessionKeys::new(KEY_A, &[])
+            .unwrap()
+            .session_id(&headers)
+            .expect("Session id should be read");
+        let session_id: tower_sessions::session::Id = session_id.parse().unwrap();
+        assert!(store.load(&session_id).await.unwrap().is_some());
+
+        assert_eq!(
+            None,
+            SessionKeys::new(KEY_B, &[]).unwrap().session_id(&headers)
+        );
+    }
+
+    #[test]
+    fn short_keys_are_rejected() {
+        assert!(SessionKeys::new("short", &[]).is_err());
+        assert!(SessionKeys::new(KEY_B, &["short".to_string()]).is_err());
+        assert_eq!(
+            1,
+            SessionKeys::new(KEY_B, &[KEY_A.to_string()])
+                .unwrap()
+                .previous_key_count()
+        );
+    }
+}
@@ -0,0 +1,402 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::{Extension, extract::Request, middleware::Next, response::Response};
+use axum_messages::Messages;
+use fred::{
+    interfaces::{KeysInterface, SetsInterface},
+    types::{Expiration, SetOptions},
+};
+use sqlx::{PgPool, Postgres, Transaction};
+use time::OffsetDateTime;
+use tower_sessions::{
+    Session, SessionStore,
+    session::{Id, Record},
+    session_store,
+};
+use uuid::Uuid;
+
+use crate::{
+    app::ApiContext,
+    auth::{AuthSession, SessionKeys},
+    config::SessionLimitStrategy,
+    redis::Redis,
+};
+
+/// Redis key of the record of a session. Without a prefix, the same as
+/// the store of `tower-sessions-redis-store` used before, so sessions
+/// survived the switch.
+fn session_key(redis: &Redis, session_id: impl std::fmt::Display) -> String {
+    redis.key(session_id)
+}
+
+/// Redis set of the session ids a user is logged in with.
+///
+/// The session store only knows sessions by id, so this index is what lets
+/// every session of a user be found, e.g. to log them out everywhere.
+fn user_sessions_key(redis: &Redis, user_id: Uuid) -> String {
+    redis.key(format!("user_sessions:{user_id}"))
+}
+
+/// Stores the session records in Redis under [`Redis::key`], encoded with
+/// MessagePack
+#[derive(Debug, Clone)]
+pub struct RedisSessionStore {
+    redis: Redis,
+}
+
+impl RedisSessionStore {
+    pub fn new(redis: Redis) -> Self {
+        Self { redis }
+    }
+
+    /// Saves the record only if its key does or doesn't exist yet, as given
+    /// by `options`, returning whether it was saved
+    async fn save_with_options(
+        &self,
+        record: &Record,
+        options: SetOptions,
+    ) -> session_store::Result<bool> {
+        let data =
+            rmp_serde::to_vec(record).map_err(|e| session_store::Error::Encode(e.to_string()))?;
+        self.redis
+            .set(
+                session_key(&self.redis, record.id),
+                data.as_slice(),
+                Some(Expiration::EXAT(record.expiry_date.unix_timestamp())),
+                Some(options),
+                false,
+            )
+            .await
+            .map_err(|e| session_store::Error::Backend(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        // ids are random, but one could already be taken
+        while !self.save_with_options(record, SetOptions::NX).await? {
+            record.id = Id::default();
+        }
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.save_with_options(record, SetOptions::XX).await?;
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let data: Option<Vec<u8>> = self
+            .redis
+            .get(session_key(&self.redis, session_id))
+            .await
+            .map_err(|e| session_store::Error::Backend(e.to_string()))?;
+        data.map(|data| {
+            rmp_serde::from_slice(&data).map_err(|e| session_store::Error::Decode(e.to_string()))
+        })
+        .transpose()
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        self.redis
+            .del::<(), _>(session_key(&self.redis, session_id))
+            .await
+            .map_err(|e| session_store::Error::Backend(e.to_string()))
+    }
+}
+
+pub async fn track_session(
+    redis: &Redis,
+    user_id: Uuid,
+    session_id: Id,
+) -> Result<(), anyhow::Error> {
+    redis
+        .sadd::<(), _, _>(user_sessions_key(redis, user_id), session_id.to_string())
+        .await
+        .context("Failed to track user session")
+}
+
+pub async fn untrack_session(
+    redis: &Redis,
+    user_id: Uuid,
+    session_id: Id,
+) -> Result<(), anyhow::Error> {
+    redis
+        .srem::<(), _, _>(user_sessions_key(redis, user_id), session_id.to_string())
+        .await
+        .context("Failed to untrack user session")
+}
+
+/// Gives the session a new id, keeping its data like flash messages, so an id
+/// someone got hold of before a change of privileges is of no use after it.
+///
+/// axum-login only does this when logging into a session that isn't logged
+/// in yet, so it is called again wherever privileges change. The session is
+/// tracked under its new id for `user_id`, see [`track_session`].
+pub async fn rotate_session(
+    redis: &Redis,
+    session: &Session,
+    user_id: Option<Uuid>,
+) -> Result<(), anyhow::Error> {
+    let old_id = session.id();
+    session
+        .cycle_id()
+        .await
+        .context("Failed to cycle session id")?;
+    // saving assigns the new id, so it can be tracked
+    session.save().await.context("Failed to save session")?;
+
+    if let Some(user_id) = user_id {
+        if let Some(old_id) = old_id {
+            untrack_session(redis, user_id, old_id).await?;
+        }
+        if let Some(new_id) = session.id() {
+            track_session(redis, user_id, new_id).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes every tracked session of a user from the session store
+pub async fn delete_user_sessions(redis: &Redis, user_id: Uuid) -> Result<(), anyhow::Error> {
+    let key = user_sessions_key(redis, user_id);
+    let session_ids: Vec<String> = redis
+        .smembers(&key)
+        .await
+        .context("Failed to get user sessions")?;
+
+    if !session_ids.is_empty() {
+        let session_keys: Vec<String> = session_ids
+            .iter()
+            .map(|session_id| session_key(redis, session_id))
+            .collect();
+        redis
+            .del::<(), _>(session_keys)
+            .await
+            .context("Failed to delete user sessions")?;
+    }
+
+    redis
+        .del::<(), _>(key)
+        .await
+        .context("Failed to delete user session index")
+}
+
+#[derive(Debug)]
+pub struct TrackedSession {
+    pub session_id: String,
+    /// Remaining lifetime of the session record in the store
+    pub time_to_live: Duration,
+}
+
+/// Lists the tracked sessions of a user that still exist in the session store
+pub async fn user_sessions(
+    redis: &Redis,
+    user_id: Uuid,
+) -> Result<Vec<TrackedSession>, anyhow::Error> {
+    let session_ids: Vec<String> = redis
+        .smembers(user_sessions_key(redis, user_id))
+        .await
+        .context("Failed to get user sessions")?;
+
+    let mut sessions = Vec::with_capacity(session_ids.len());
+    for session_id in session_ids {
+        let ttl: i64 = redis
+            .pttl(session_key(redis, &session_id))
+            .await
+            .context("Failed to get session expiry")?;
+        // negative values mean the record expired or never had an expiry
+        if ttl >= 0 {
+            sessions.push(TrackedSession {
+                session_id,
+                time_to_live: Duration::from_millis(ttl as u64),
+            });
+        }
+    }
+
+    Ok(sessions)
+}
+
+/// Redis key marking a session as evicted by [`make_room_for_session`], so
+/// its next request can say why it was logged out
+fn evicted_session_key(redis: &Redis, session_id: &str) -> String {
+    redis.key(format!("evicted_session:{session_id}"))
+}
+
+/// Holds off other logins of the user until the returned transaction ends,
+/// so parallel logins can't all find room for one more session
+pub async fn lock_user_sessions(
+    db: &PgPool,
+    user_id: Uuid,
+) -> Result<Transaction<'static, Postgres>, anyhow::Error> {
+    let mut transaction = db.begin().await.context("Failed to begin transaction")?;
+    sqlx::query!(
+        "SELECT pg_advisory_xact_lock(hashtext($1))",
+        format!("user_sessions:{user_id}")
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to lock user sessions")?;
+    Ok(transaction)
+}
+
+/// Makes room for one more session of the user within `max_sessions`,
+/// returning whether there is room. `current` is the session being logged
+/// into, which needs none if it is already one of the user's. Call with the
+/// lock of [`lock_user_sessions`] held until the session is tracked.
+pub async fn make_room_for_session(
+    redis: &Redis,
+    user_id: Uuid,
+    current: Option<Id>,
+    max_sessions: u32,
+    strategy: SessionLimitStrategy,
+) -> Result<bool, anyhow::Error> {
+    let mut sessions = user_sessions(redis, user_id).await?;
+    if let Some(current) = current
+        && sessions
+            .iter()
+            .any(|session| session.session_id == current.to_string())
+    {
+        return Ok(true);
+    }
+
+    let excess = (sessions.len() + 1).saturating_sub(max_sessions as usize);
+    if excess == 0 {
+        return Ok(true);
+    }
+    if strategy == SessionLimitStrategy::Reject {
+        return Ok(false);
+    }
+
+    // every request pushes the expiry of its session back, so the one
+    // closest to expiring is the one used longest ago
+    sessions.sort_by_key(|session| session.time_to_live);
+    for session in sessions.into_iter().take(excess) {
+        redis
+            .del::<(), _>(session_key(redis, &session.session_id))
+            .await
+            .context("Failed to delete evicted session")?;
+        redis
+            .srem::<(), _, _>(user_sessions_key(redis, user_id), &session.session_id)
+            .await
+            .context("Failed to untrack evicted session")?;
+        // kept as long as the session would have been, nobody can come back with it after
+        let _: Option<String> = redis
+            .set(
+                evicted_session_key(redis, &session.session_id),
+                "1",
+                Some(Expiration::EX(session.time_to_live.as_secs().max(1) as i64)),
+                None,
+                false,
+            )
+            .await
+            .context("Failed to mark evicted session")?;
+    }
+    Ok(true)
+}
+
+/// Session key of when the session was logged in, as a unix timestamp
+const LOGGED_IN_AT_KEY: &str = "logged_in_at";
+
+/// Starts the clock of [`expire_old_sessions`], call it when the session is
+/// logged in
+pub async fn record_login_time(session: &Session) -> Result<(), anyhow::Error> {
+    session
+        .insert(LOGGED_IN_AT_KEY, OffsetDateTime::now_utc().unix_timestamp())
+        .await
+        .context("Failed to save login time")
+}
+
+/// Marks a request whose session [`expire_old_sessions`] logged out, for
+/// [`flash_session_end`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ExpiredSession;
+
+/// Logs out sessions logged in more than `MAX_SESSION_AGE_DAYS` ago, however
+/// recently they were used, so a stolen cookie can't be used forever. The
+/// time is read from the session the auth layer already loaded, which
+/// costs no trip to Redis.
+pub async fn expire_old_sessions(
+    Extension(api_context): Extension<Arc<ApiContext>>,
+    mut auth_session: AuthSession,
+    session: Session,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if let Some(user) = auth_session.user.clone() {
+        let max_age = api_context.config.application_settings.max_session_age_days;
+        let logged_in_at = session
+            .get::<i64>(LOGGED_IN_AT_KEY)
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|logged_in_at| {
+                logged_in_at
+                    .map(OffsetDateTime::from_unix_timestamp)
+                    .transpose()
+                    .map_err(anyhow::Error::from)
+            });
+        match logged_in_at {
+            Ok(Some(logged_in_at))
+                if OffsetDateTime::now_utc() - logged_in_at > time::Duration::days(max_age) =>
+            {
+                let session_id = session.id();
+                if let Err(e) = auth_session.logout().await {
+                    tracing::warn!(error = ?e, "Failed to log out expired session");
+                } else if let Some(session_id) = session_id
+                    && let Err(e) =
+                        untrack_session(&api_context.redis, user.user_id(), session_id).await
+                {
+                    tracing::warn!(error = ?e, "Failed to untrack expired session");
+                }
+                // the handlers see the session logged out too
+                request.extensions_mut().insert(auth_session);
+                request.extensions_mut().insert(ExpiredSession);
+            }
+            Ok(Some(_)) => {}
+            // logged in before sessions had an age, it starts now
+            Ok(None) => {
+                if let Err(e) = record_login_time(&session).await {
+                    tracing::warn!(error = ?e, "Failed to start the age of a session");
+                }
+            }
+            Err(e) => tracing::warn!(error = ?e, "Failed to get the login time of the session"),
+        }
+    }
+    next.run(request).await
+}
+
+/// Tells whoever comes back with a session the app logged out why, in a
+/// flash message on the login page they are sent to: it was too old, see
+/// [`expire_old_sessions`], or evicted by [`make_room_for_session`]. Only
+/// logged out requests can be of an evicted session. Layered inside the
+/// messages layer.
+pub async fn flash_session_end(
+    Extension(api_context): Extension<Arc<ApiContext>>,
+    Extension(keys): Extension<Arc<SessionKeys>>,
+    auth_session: AuthSession,
+    messages: Messages,
+    request: Request,
+    next: Next,
+) -> Response {
+    let expired = request.extensions().get::<ExpiredSession>().is_some();
+    if expired {
+        messages.info("Your session expired, log in again");
+    } else if auth_session.user.is_none()
+        && let Some(session_id) = keys.session_id(request.headers())
+    {
+        let redis = &api_context.redis;
+        let evicted: Result<Option<String>, _> =
+            redis.getdel(evicted_session_key(redis, &session_id)).await;
+        match evicted {
+            Ok(Some(_)) => {
+                messages.info("You were logged out because you logged in elsewhere");
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!(error = ?e, "Failed to check for an evicted session"),
+        }
+    }
+    next.run(request).await
+}
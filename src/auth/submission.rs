@@ -0,0 +1,96 @@
+//! Single-use tokens of the registration form, so a form sent twice, by a
+//! double click or a browser retrying, is only acted on once.
+//!
+//! The token is random and kept in the session of whoever loaded the form.
+//! Sending the form claims it in Redis, which only one of several requests
+//! racing each other can do.
+
+use anyhow::Context;
+use fred::{
+    interfaces::KeysInterface,
+    types::{Expiration, SetOptions},
+};
+use tower_sessions::Session;
+
+use crate::redis::Redis;
+
+/// Session key of the token of the form last served
+const SESSION_KEY: &str = "register_submission";
+
+/// How long a claim is kept. Sessions end after an hour without requests,
+/// taking their token with them.
+const CLAIM_SECS: i64 = 60 * 60;
+
+fn claim_key(redis: &Redis, token: &str) -> String {
+    redis.key(format!("form_submission:{token}"))
+}
+
+/// What became of a submitted token
+#[derive(Debug, PartialEq)]
+pub enum Claim {
+    /// The submission is the first one, it goes ahead
+    Claimed,
+    /// The token isn't the one of the session, which expired or loaded the
+    /// form again since
+    Unknown,
+    /// Another submission of the same form came first
+    AlreadyClaimed,
+}
+
+/// Issues the token of a form about to be served, replacing the one served
+/// before
+pub async fn issue(session: &Session) -> Result<String, anyhow::Error> {
+    let token = hex::encode(rand::random::<[u8; 16]>());
+    session
+        .insert(SESSION_KEY, &token)
+        .await
+        .context("Failed to save form submission token")?;
+    Ok(token)
+}
+
+pub async fn claim(redis: &Redis, session: &Session, token: &str) -> Result<Claim, anyhow::Error> {
+    let issued: Option<String> = session
+        .get(SESSION_KEY)
+        .await
+        .context("Failed to get form submission token")?;
+    if issued.as_deref() != Some(token) {
+        return Ok(Claim::Unknown);
+    }
+
+    let claimed: Option<String> = redis
+        .set(
+            claim_key(redis, token),
+            "1",
+            Some(Expiration::EX(CLAIM_SECS)),
+            Some(SetOptions::NX),
+            false,
+        )
+        .await
+        .context("Failed to claim form submission")?;
+    Ok(match claimed {
+        Some(_) => Claim::Claimed,
+        None => Claim::AlreadyClaimed,
+    })
+}
+
+/// Settles a claimed token once the submission is handled. A submission that
+/// went through uses the token up, one that failed gives it back, so the
+/// form can be fixed and sent again.
+pub async fn settle(redis: &Redis, session: &Session, token: &str, succeeded: bool) {
+    let result = if succeeded {
+        session
+            .remove::<String>(SESSION_KEY)
+            .await
+            .map(|_| ())
+            .context("Failed to remove form submission token")
+    } else {
+        redis
+            .del::<(), _>(claim_key(redis, token))
+            .await
+            .context("Failed to release form submission")
+    };
+    // at worst the form has to be loaded again
+    if let Err(e) = result {
+        tracing::warn!(error = ?e, "Failed to settle form submission");
+    }
+}
@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{Form, Json, extract::State, response::IntoResponse};
+use axum_login::AuthnBackend;
+use http::StatusCode;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::{
+    api_error::ApiError,
+    app::ApiContext,
+    audit::{AuditEntry, AuditEvent, RequestMetadata},
+    auth::{
+        AuthError, AuthSession, LoginCredentials,
+        bearer::{self, TokenKeys},
+        login::{self, LoginFormData},
+    },
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum TokenError {
+    #[error("Bearer tokens are not enabled")]
+    Disabled,
+    #[error("Invalid or expired refresh token")]
+    InvalidRefreshToken,
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+}
+
+impl From<anyhow::Error> for TokenError {
+    fn from(e: anyhow::Error) -> Self {
+        TokenError::Auth(AuthError::UnexpectedError(e))
+    }
+}
+
+impl IntoResponse for TokenError {
+    fn into_response(self) -> axum::response::Response {
+        let message = self.to_string();
+        match self {
+            TokenError::Disabled => ApiError::new(StatusCode::NOT_FOUND, "not_found", message),
+            TokenError::InvalidRefreshToken => {
+                ApiError::new(StatusCode::UNAUTHORIZED, "invalid_refresh_token", message)
+                    .with_field("refresh_token")
+            }
+            TokenError::Auth(e) => return e.into_response(),
+        }
+        .into_response()
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct TokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    /// Seconds until the access token expires
+    expires_in: u64,
+    refresh_token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RefreshTokenFormData {
+    refresh_token: String,
+}
+
+fn token_keys(api_context: &ApiContext) -> Result<&TokenKeys, TokenError> {
+    api_context.token_keys.as_ref().ok_or(TokenError::Disabled)
+}
+
+async fn issue_tokens(
+    executor: impl PgExecutor<'_>,
+    api_context: &ApiContext,
+    keys: &TokenKeys,
+    user_id: Uuid,
+) -> Result<TokenResponse, anyhow::Error> {
+    let refresh_token = bearer::generate_refresh_token();
+    bearer::store_refresh_token(
+        executor,
+        user_id,
+        &refresh_token,
+        api_context
+            .config
+            .application_settings
+            .refresh_token_ttl_days,
+    )
+    .await?;
+
+    Ok(TokenResponse {
+        access_token: keys.issue(user_id)?,
+        token_type: "Bearer",
+        expires_in: keys.access_token_ttl().as_secs(),
+        refresh_token,
+    })
+}
+
+/// Logs in like `/api/login`, but answers with tokens for clients that
+/// can't keep a session cookie
+pub async fn issue_token(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    request: RequestMetadata,
+    Form(payload): Form<LoginFormData>,
+) -> Result<Json<TokenResponse>, TokenError> {
+    let keys = token_keys(&api_context)?;
+
+    let attempted_username = payload.username.trim().to_lowercase();
+    let record_failure = || {
+        api_context.audit.record(
+            AuditEntry::new(AuditEvent::LoginFailed, None, &request)
+                .with_metadata(serde_json::json!({ "username": attempted_username })),
+        )
+    };
+
+    let credentials: LoginCredentials = payload.try_into().inspect_err(|_| record_failure())?;
+    let user = match auth_session.backend.authenticate(credentials).await? {
+        Some(user) => user,
+        None => {
+            record_failure();
+            return Err(AuthError::InvalidCredentials.into());
+        }
+    };
+
+    let tokens = issue_tokens(&api_context.db, &api_context, keys, user.user_id()).await?;
+
+    api_context.audit.record(
+        AuditEntry::new(AuditEvent::LoginSucceeded, Some(user.user_id()), &request)
+            .with_metadata(serde_json::json!({ "method": "token" })),
+    );
+    login::record_last_login(&api_context, user.user_id());
+
+    Ok(Json(tokens))
+}
+
+/// Exchanges a refresh token for a new pair, the old one can't be used again
+pub async fn refresh_token(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Form(form_data): Form<RefreshTokenFormData>,
+) -> Result<Json<TokenResponse>, TokenError> {
+    let keys = token_keys(&api_context)?;
+
+    // the old token is only used up if the new one is stored
+    let mut transaction = api_context
+        .db
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+
+    let user_id = bearer::use_refresh_token(&mut *transaction, &form_data.refresh_token)
+        .await?
+        .ok_or(TokenError::InvalidRefreshToken)?;
+
+    // locked users can't refresh, their tokens are revoked when they are locked anyway
+    let user = auth_session
+        .backend
+        .get_user(&user_id)
+        .await?
+        .ok_or(TokenError::InvalidRefreshToken)?;
+
+    let tokens = issue_tokens(&mut *transaction, &api_context, keys, user.user_id()).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(Json(tokens))
+}
+
+/// Revokes a refresh token. The access tokens issued with it stay valid
+/// until they expire, so clients should also forget them.
+pub async fn revoke_token(
+    State(api_context): State<Arc<ApiContext>>,
+    request: RequestMetadata,
+    Form(form_data): Form<RefreshTokenFormData>,
+) -> Result<StatusCode, TokenError> {
+    token_keys(&api_context)?;
+
+    // revoking an unknown or used token is not an error, the client is logged out either way
+    if let Some(user_id) =
+        bearer::use_refresh_token(&api_context.db, &form_data.refresh_token).await?
+    {
+        api_context.audit.record(
+            AuditEntry::new(AuditEvent::Logout, Some(user_id), &request)
+                .with_metadata(serde_json::json!({ "method": "token" })),
+        );
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,117 @@
+use anyhow::Context;
+use fred::{
+    interfaces::KeysInterface,
+    types::{Expiration, SetOptions},
+};
+use secrecy::{ExposeSecret, SecretString};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Role, User},
+    redis::Redis,
+};
+
+/// How long a user is served from the cache before being reloaded. Changes
+/// that invalidate the entry show up right away, others within this time.
+const CACHE_TTL_SECONDS: i64 = 30;
+
+/// Redis cache in front of the user lookup axum-login makes on every
+/// authenticated request.
+///
+/// Entries must be invalidated whenever the user stops matching the row,
+/// most importantly when the password hash changes, as the sessions tied
+/// to the old hash would otherwise stay valid until the entry expires.
+/// Any Redis or deserialization error is treated as a miss.
+#[derive(Debug, Clone)]
+pub struct UserCache {
+    redis: Redis,
+    enabled: bool,
+}
+
+/// [`User`] isn't serializable itself so its hash can't end up in a
+/// response by accident, it is only copied into this for the cache
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedUser {
+    user_id: Uuid,
+    username: String,
+    password_hash: String,
+    role: Role,
+    tos_accepted_version: i32,
+}
+
+impl UserCache {
+    pub fn new(redis: Redis, enabled: bool) -> Self {
+        Self { redis, enabled }
+    }
+
+    pub async fn get(&self, user_id: Uuid) -> Option<User> {
+        if !self.enabled {
+            return None;
+        }
+        let cached: Option<String> = match self.redis.get(cache_key(&self.redis, user_id)).await {
+            Ok(cached) => cached,
+            Err(e) => {
+                tracing::warn!(%user_id, error = ?e, "Failed to get cached user");
+                return None;
+            }
+        };
+        let cached: CachedUser = serde_json::from_str(&cached?).ok()?;
+
+        Some(User {
+            user_id: cached.user_id,
+            username: cached.username,
+            password_hash: SecretString::from(cached.password_hash),
+            role: cached.role,
+            tos_accepted_version: cached.tos_accepted_version,
+            // deactivated users aren't found by their id, so never cached
+            deactivated_at: None,
+        })
+    }
+
+    pub async fn set(&self, user: &User) {
+        if !self.enabled {
+            return;
+        }
+        if let Err(e) = self.try_set(user).await {
+            tracing::warn!(user_id = %user.user_id, error = ?e, "Failed to cache user");
+        }
+    }
+
+    async fn try_set(&self, user: &User) -> Result<(), anyhow::Error> {
+        let cached = CachedUser {
+            user_id: user.user_id,
+            username: user.username.clone(),
+            password_hash: user.password_hash.expose_secret().to_string(),
+            role: user.role,
+            tos_accepted_version: user.tos_accepted_version,
+        };
+        let serialized = serde_json::to_string(&cached).context("Failed to serialize user")?;
+        let _: Option<String> = self
+            .redis
+            .set(
+                cache_key(&self.redis, user.user_id),
+                serialized,
+                Some(Expiration::EX(CACHE_TTL_SECONDS)),
+                None::<SetOptions>,
+                false,
+            )
+            .await
+            .context("Failed to cache user")?;
+
+        Ok(())
+    }
+
+    /// Call after the change is committed. A lookup that read the row right
+    /// before can still cache the old version, which then lasts until it expires.
+    pub async fn invalidate(&self, user_id: Uuid) {
+        // also when disabled here, other instances might have it enabled
+        let result: Result<i64, _> = self.redis.del(cache_key(&self.redis, user_id)).await;
+        if let Err(e) = result {
+            tracing::error!(%user_id, error = ?e, "Failed to invalidate cached user");
+        }
+    }
+}
+
+fn cache_key(redis: &Redis, user_id: Uuid) -> String {
+    redis.key(format!("session_user:{user_id}"))
+}
@@ -0,0 +1,257 @@
+//! Who may do what with lists and their todos. Handlers call these before
+//! reading or changing anything, instead of each narrowing its own queries
+//! down to the user, so adding a role only changes [`ListAccess`].
+//!
+//! Lists and todos the user can't see are reported as not found, so no one
+//! can find out what exists in other people's lists. Only users who can see
+//! a list are told when they can't change it.
+
+use axum::response::IntoResponse;
+use http::StatusCode;
+use uuid::Uuid;
+
+use crate::{app::ApiContext, routes::todo::list::ListAccess};
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthzError {
+    /// Doesn't exist, or the user can't see it
+    #[error("Not found")]
+    NotFound,
+    /// The user can see it, but not change it
+    #[error("You can't change this list")]
+    Forbidden,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl AuthzError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            AuthzError::NotFound => StatusCode::NOT_FOUND,
+            AuthzError::Forbidden => StatusCode::FORBIDDEN,
+            AuthzError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for AuthzError {
+    fn into_response(self) -> axum::response::Response {
+        (self.status_code(), self.to_string()).into_response()
+    }
+}
+
+/// A todo the user can see, with the list it is in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TodoAccess {
+    pub list_id: Uuid,
+    pub access: ListAccess,
+}
+
+pub async fn can_view_list(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    list_id: Uuid,
+) -> Result<ListAccess, AuthzError> {
+    api_context
+        .todos
+        .list_access(list_id, user_id)
+        .await?
+        .ok_or(AuthzError::NotFound)
+}
+
+pub async fn require_edit_list(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    list_id: Uuid,
+) -> Result<ListAccess, AuthzError> {
+    let access = can_view_list(api_context, user_id, list_id).await?;
+    if !access.can_edit() {
+        return Err(AuthzError::Forbidden);
+    }
+    Ok(access)
+}
+
+/// Sharing a list is up to its owner alone
+pub async fn require_own_list(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), AuthzError> {
+    if !can_view_list(api_context, user_id, list_id)
+        .await?
+        .is_owner()
+    {
+        return Err(AuthzError::Forbidden);
+    }
+    Ok(())
+}
+
+/// The list asked for, or the user's own one when none was
+pub async fn can_view_list_or_own(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    list_id: Option<Uuid>,
+) -> Result<(Uuid, ListAccess), AuthzError> {
+    match list_id {
+        Some(list_id) => Ok((list_id, can_view_list(api_context, user_id, list_id).await?)),
+        None => Ok((
+            api_context.todos.own_list_id(user_id).await?,
+            ListAccess::Owner,
+        )),
+    }
+}
+
+/// Same as [`can_view_list_or_own`], for adding to the list
+pub async fn require_edit_list_or_own(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    list_id: Option<Uuid>,
+) -> Result<Uuid, AuthzError> {
+    match list_id {
+        Some(list_id) => {
+            require_edit_list(api_context, user_id, list_id).await?;
+            Ok(list_id)
+        }
+        None => Ok(api_context.todos.own_list_id(user_id).await?),
+    }
+}
+
+/// Deleted todos can't be seen, only restored by undoing their deletion
+pub async fn can_view_todo(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    todo_id: Uuid,
+) -> Result<TodoAccess, AuthzError> {
+    let (list_id, access) = api_context
+        .todos
+        .todo_access(todo_id, user_id)
+        .await?
+        .ok_or(AuthzError::NotFound)?;
+    Ok(TodoAccess { list_id, access })
+}
+
+/// Also for the parts of a todo, like its subtasks, tags and description
+pub async fn require_edit_todo(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    todo_id: Uuid,
+) -> Result<TodoAccess, AuthzError> {
+    let todo_access = can_view_todo(api_context, user_id, todo_id).await?;
+    if !todo_access.access.can_edit() {
+        return Err(AuthzError::Forbidden);
+    }
+    Ok(todo_access)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        auth::repo::fake::FakeUserRepo,
+        domain::{priority::Priority, tag::Tags, todo_content::TodoContent},
+        routes::todo::{
+            list::ListRole,
+            repo::{NewTodoRow, TodoRepo, fake::FakeTodoRepo},
+        },
+    };
+
+    #[derive(Debug, Clone, Copy)]
+    enum Who {
+        Owner,
+        Editor,
+        Viewer,
+        Stranger,
+    }
+
+    /// Whether each check lets the user through, or what it answers instead
+    type Outcome = Result<(), StatusCode>;
+
+    fn outcome<T>(result: Result<T, AuthzError>) -> Outcome {
+        result.map(|_| ()).map_err(|e| e.into_response().status())
+    }
+
+    #[tokio::test]
+    async fn access_of_each_kind_of_user() {
+        const OK: Outcome = Ok(());
+        const NOT_FOUND: Outcome = Err(StatusCode::NOT_FOUND);
+        const FORBIDDEN: Outcome = Err(StatusCode::FORBIDDEN);
+        // view list, edit list, own list, view todo, edit todo
+        let expected = [
+            (Who::Owner, [OK, OK, OK, OK, OK]),
+            (Who::Editor, [OK, OK, FORBIDDEN, OK, OK]),
+            (Who::Viewer, [OK, FORBIDDEN, FORBIDDEN, OK, FORBIDDEN]),
+            (
+                Who::Stranger,
+                [NOT_FOUND, NOT_FOUND, NOT_FOUND, NOT_FOUND, NOT_FOUND],
+            ),
+        ];
+
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = ApiContext::for_tests(Arc::new(FakeUserRepo::default()), todos.clone());
+        let owner_id = Uuid::new_v4();
+        let list_id = todos.add_user(owner_id);
+        let editor_id = Uuid::new_v4();
+        todos.add_member(list_id, editor_id, ListAccess::Member(ListRole::Editor));
+        let viewer_id = Uuid::new_v4();
+        todos.add_member(list_id, viewer_id, ListAccess::Member(ListRole::Viewer));
+        let stranger_id = Uuid::new_v4();
+        todos.add_user(stranger_id);
+        let new_todo = NewTodoRow {
+            list_id,
+            todo_content: TodoContent::parse("buy milk").unwrap(),
+            due_date: None,
+            priority: Priority::default(),
+            tags: Tags::parse("").unwrap(),
+        };
+        let todo = todos
+            .create(owner_id, new_todo, i64::MAX, 0.0)
+            .await
+            .unwrap()
+            .todo;
+
+        for (who, expected) in expected {
+            let user_id = match who {
+                Who::Owner => owner_id,
+                Who::Editor => editor_id,
+                Who::Viewer => viewer_id,
+                Who::Stranger => stranger_id,
+            };
+            let actual = [
+                outcome(can_view_list(&api_context, user_id, list_id).await),
+                outcome(require_edit_list(&api_context, user_id, list_id).await),
+                outcome(require_own_list(&api_context, user_id, list_id).await),
+                outcome(can_view_todo(&api_context, user_id, todo.todo_id).await),
+                outcome(require_edit_todo(&api_context, user_id, todo.todo_id).await),
+            ];
+            assert_eq!(expected, actual, "{who:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_lists_and_todos_are_not_found() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = ApiContext::for_tests(Arc::new(FakeUserRepo::default()), todos.clone());
+        let user_id = Uuid::new_v4();
+        let own_list_id = todos.add_user(user_id);
+
+        let missing = Uuid::new_v4();
+        assert!(matches!(
+            can_view_list(&api_context, user_id, missing).await,
+            Err(AuthzError::NotFound)
+        ));
+        assert!(matches!(
+            require_edit_todo(&api_context, user_id, missing).await,
+            Err(AuthzError::NotFound)
+        ));
+        assert_eq!(
+            own_list_id,
+            require_edit_list_or_own(&api_context, user_id, None)
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::io::Cursor;
+
+use image::{
+    DynamicImage, ImageDecoder, ImageFormat,
+    codecs::{jpeg::JpegDecoder, png::PngDecoder, webp::WebPDecoder},
+    imageops::FilterType,
+};
+use sha2::{Digest, Sha256};
+
+/// Width and height of stored avatars, in pixels
+pub const AVATAR_SIZE: u32 = 256;
+/// Largest width or height accepted before decoding the whole image
+const MAX_DIMENSION: u32 = 4096;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum InvalidAvatarError {
+    #[error("Avatar must be a PNG, JPEG or WebP image")]
+    UnsupportedFormat,
+    #[error("Animated images can't be used as avatars")]
+    Animated,
+    #[error("Image is too large, the maximum is {MAX_DIMENSION}×{MAX_DIMENSION} pixels")]
+    TooLarge,
+    #[error("Image could not be read")]
+    Corrupt,
+}
+
+/// An uploaded image, resized and re-encoded as PNG.
+///
+/// Files are named after the hash of their contents, so a name never points to
+/// different bytes and can be cached forever.
+#[derive(Debug)]
+pub struct Avatar {
+    filename: String,
+    bytes: Vec<u8>,
+}
+
+impl Avatar {
+    /// Decodes and resizes an upload. This is CPU heavy, so it belongs in
+    /// `spawn_blocking`.
+    pub fn process(bytes: &[u8]) -> Result<Self, InvalidAvatarError> {
+        let image = match sniff_format(bytes)? {
+            ImageFormat::Png => {
+                let decoder =
+                    PngDecoder::new(Cursor::new(bytes)).map_err(|_| InvalidAvatarError::Corrupt)?;
+                if decoder.is_apng().map_err(|_| InvalidAvatarError::Corrupt)? {
+                    return Err(InvalidAvatarError::Animated);
+                }
+                decode(decoder)?
+            }
+            ImageFormat::Jpeg => decode(
+                JpegDecoder::new(Cursor::new(bytes)).map_err(|_| InvalidAvatarError::Corrupt)?,
+            )?,
+            ImageFormat::WebP => {
+                let decoder = WebPDecoder::new(Cursor::new(bytes))
+                    .map_err(|_| InvalidAvatarError::Corrupt)?;
+                if decoder.has_animation() {
+                    return Err(InvalidAvatarError::Animated);
+                }
+                decode(decoder)?
+            }
+            _ => return Err(InvalidAvatarError::UnsupportedFormat),
+        };
+
+        let mut bytes = Vec::new();
+        image
+            .resize_to_fill(AVATAR_SIZE, AVATAR_SIZE, FilterType::Lanczos3)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .map_err(|_| InvalidAvatarError::Corrupt)?;
+
+        let filename = format!("{}.png", hex::encode(Sha256::digest(&bytes)));
+        Ok(Self { filename, bytes })
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Finds the format from the file's magic bytes, the content type sent by
+/// the client can't be trusted
+fn sniff_format(bytes: &[u8]) -> Result<ImageFormat, InvalidAvatarError> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Ok(ImageFormat::Png)
+    } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+        Ok(ImageFormat::Jpeg)
+    } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+        Ok(ImageFormat::WebP)
+    } else {
+        Err(InvalidAvatarError::UnsupportedFormat)
+    }
+}
+
+fn decode(decoder: impl ImageDecoder) -> Result<DynamicImage, InvalidAvatarError> {
+    let (width, height) = decoder.dimensions();
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(InvalidAvatarError::TooLarge);
+    }
+    DynamicImage::from_decoder(decoder).map_err(|_| InvalidAvatarError::Corrupt)
+}
+
+/// Whether `filename` could have been produced by [`Avatar::process`], which
+/// keeps request paths from escaping the upload directory
+pub fn is_avatar_filename(filename: &str) -> bool {
+    filename.strip_suffix(".png").is_some_and(|hash| {
+        hash.len() == 64
+            && hash
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    })
+}
+
+/// Key of an avatar in the file store, spread over directories by the first
+/// two characters of its hash
+pub fn avatar_key(filename: &str) -> String {
+    format!("avatars/{}/{filename}", &filename[..2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::new_rgb8(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), format)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn images_are_resized_to_a_square() {
+        for format in [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP] {
+            let avatar = Avatar::process(&encode(300, 100, format)).unwrap();
+            let image = image::load_from_memory(avatar.bytes()).unwrap();
+            assert_eq!((AVATAR_SIZE, AVATAR_SIZE), (image.width(), image.height()));
+            assert!(is_avatar_filename(avatar.filename()));
+        }
+    }
+
+    #[test]
+    fn identical_images_get_the_same_filename() {
+        let first = Avatar::process(&encode(10, 10, ImageFormat::Png)).unwrap();
+        let second = Avatar::process(&encode(10, 10, ImageFormat::Png)).unwrap();
+        assert_eq!(first.filename(), second.filename());
+    }
+
+    #[test]
+    fn format_is_detected_from_contents() {
+        let mut bytes = b"not an image at all".to_vec();
+        assert_eq!(
+            InvalidAvatarError::UnsupportedFormat,
+            Avatar::process(&bytes).unwrap_err()
+        );
+
+        // GIFs are rejected whether animated or not
+        bytes = b"GIF89a\x01\x00\x01\x00".to_vec();
+        assert_eq!(
+            InvalidAvatarError::UnsupportedFormat,
+            Avatar::process(&bytes).unwrap_err()
+        );
+
+        bytes = encode(10, 10, ImageFormat::Png);
+        bytes.truncate(20);
+        assert_eq!(
+            InvalidAvatarError::Corrupt,
+            Avatar::process(&bytes).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn oversized_images_are_rejected() {
+        let bytes = encode(MAX_DIMENSION + 1, 1, ImageFormat::Png);
+        assert_eq!(
+            InvalidAvatarError::TooLarge,
+            Avatar::process(&bytes).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn filenames_outside_the_hash_format_are_rejected() {
+        let hash = "a".repeat(64);
+        assert!(is_avatar_filename(&format!("{hash}.png")));
+        assert!(!is_avatar_filename(&format!("{}.png", "A".repeat(64))));
+        assert!(!is_avatar_filename(&format!("{hash}.jpg")));
+        assert!(!is_avatar_filename("../../etc/passwd"));
+        assert!(!is_avatar_filename(".png"));
+    }
+}
@@ -0,0 +1,290 @@
+//! The CAPTCHA the login and registration forms ask for once an address
+//! failed too often. Addresses below `CAPTCHA_FAILURE_THRESHOLD` never see
+//! one, and nobody does without the keys of `CAPTCHA_PROVIDER`.
+
+use std::{fmt::Display, time::Duration};
+
+use anyhow::{Context, anyhow};
+use fred::interfaces::KeysInterface;
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::{
+    app::ApiContext,
+    config::{ApplicationSettings, CaptchaProvider},
+    redis::Redis,
+};
+
+/// Failures are forgotten this long after the last one
+const FAILURE_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Verification answers sooner than this, or the token counts as unverifiable
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl CaptchaProvider {
+    fn verify_url(self) -> &'static str {
+        match self {
+            CaptchaProvider::Turnstile => {
+                "https://challenges.cloudflare.com/turnstile/v0/siteverify"
+            }
+            CaptchaProvider::Hcaptcha => "https://api.hcaptcha.com/siteverify",
+        }
+    }
+}
+
+/// What the templates render the widget with
+#[derive(Debug, Clone)]
+pub struct CaptchaWidget {
+    provider: CaptchaProvider,
+    pub site_key: String,
+}
+
+impl CaptchaWidget {
+    pub fn script_url(&self) -> &'static str {
+        match self.provider {
+            CaptchaProvider::Turnstile => "https://challenges.cloudflare.com/turnstile/v0/api.js",
+            CaptchaProvider::Hcaptcha => "https://js.hcaptcha.com/1/api.js",
+        }
+    }
+
+    /// The class the provider's script looks for, it puts the widget there
+    /// and its token in a hidden field of the surrounding form
+    pub fn class(&self) -> &'static str {
+        match self.provider {
+            CaptchaProvider::Turnstile => "cf-turnstile",
+            CaptchaProvider::Hcaptcha => "h-captcha",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CaptchaError {
+    #[error("The CAPTCHA is missing or wasn't solved")]
+    Invalid,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Verifies tokens with the provider, which speak the same siteverify API
+#[derive(Debug)]
+pub struct CaptchaClient {
+    http_client: reqwest::Client,
+    widget: CaptchaWidget,
+    secret_key: SecretString,
+    verify_url: String,
+    failure_threshold: u32,
+}
+
+#[derive(serde::Serialize)]
+struct VerifyRequest<'a> {
+    secret: &'a str,
+    response: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remoteip: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct VerifyResponse {
+    success: bool,
+}
+
+impl CaptchaClient {
+    /// `None` without keys, which turns the CAPTCHA off
+    pub fn from_settings(settings: &ApplicationSettings) -> Result<Option<Self>, anyhow::Error> {
+        let (site_key, secret_key) =
+            match (&settings.captcha_site_key, &settings.captcha_secret_key) {
+                (Some(site_key), Some(secret_key)) => (site_key.clone(), secret_key.clone()),
+                (None, None) => return Ok(None),
+                (Some(_), None) => {
+                    return Err(anyhow!(
+                        "CAPTCHA_SITE_KEY is set without CAPTCHA_SECRET_KEY"
+                    ));
+                }
+                (None, Some(_)) => {
+                    return Err(anyhow!(
+                        "CAPTCHA_SECRET_KEY is set without CAPTCHA_SITE_KEY"
+                    ));
+                }
+            };
+        let http_client = reqwest::Client::builder()
+            .timeout(VERIFY_TIMEOUT)
+            .build()
+            .context("Failed to build CAPTCHA http client")?;
+
+        Ok(Some(Self {
+            http_client,
+            widget: CaptchaWidget {
+                provider: settings.captcha_provider,
+                site_key,
+            },
+            secret_key,
+            verify_url: settings
+                .captcha_verify_url
+                .clone()
+                .unwrap_or_else(|| settings.captcha_provider.verify_url().to_string()),
+            failure_threshold: settings.captcha_failure_threshold,
+        }))
+    }
+
+    /// Whether the provider says `token` was solved by whoever sent it
+    pub async fn verify(
+        &self,
+        token: &str,
+        remote_ip: Option<impl Display>,
+    ) -> Result<bool, anyhow::Error> {
+        let response: VerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&VerifyRequest {
+                secret: self.secret_key.expose_secret(),
+                response: token,
+                remoteip: remote_ip.map(|ip| ip.to_string()),
+            })
+            .send()
+            .await
+            .context("Failed to reach the CAPTCHA provider")?
+            .error_for_status()
+            .context("The CAPTCHA provider refused the verification")?
+            .json()
+            .await
+            .context("Failed to read the CAPTCHA verification")?;
+        Ok(response.success)
+    }
+}
+
+fn failures_key(redis: &Redis, ip: impl Display) -> String {
+    redis.key(format!("captcha_failures:{ip}"))
+}
+
+/// The widget to put on the form, if the client at `ip` has to solve a
+/// CAPTCHA. Like the rate limits, nobody is asked when Redis can't be read.
+pub async fn widget_for(
+    api_context: &ApiContext,
+    ip: Option<impl Display>,
+) -> Option<CaptchaWidget> {
+    let client = api_context.captcha.as_ref()?;
+    if client.failure_threshold == 0 {
+        return Some(client.widget.clone());
+    }
+
+    let result: Result<Option<u32>, _> = api_context
+        .redis
+        .get(failures_key(&api_context.redis, ip?))
+        .await;
+    match result {
+        Ok(failures) => (failures.unwrap_or_default() >= client.failure_threshold)
+            .then(|| client.widget.clone()),
+        Err(e) => {
+            tracing::error!(error = ?e, "Failed to read CAPTCHA failures");
+            None
+        }
+    }
+}
+
+/// Checks `token` if the client at `ip` has to solve a CAPTCHA
+pub async fn check(
+    api_context: &ApiContext,
+    ip: Option<impl Display + Clone>,
+    token: Option<&str>,
+) -> Result<(), CaptchaError> {
+    let Some(client) = api_context.captcha.as_ref() else {
+        return Ok(());
+    };
+    if widget_for(api_context, ip.clone()).await.is_none() {
+        return Ok(());
+    }
+
+    let token = token
+        .filter(|token| !token.is_empty())
+        .ok_or(CaptchaError::Invalid)?;
+    match client.verify(token, ip).await? {
+        true => Ok(()),
+        false => Err(CaptchaError::Invalid),
+    }
+}
+
+/// Counts a failed login or registration of the client at `ip`
+pub async fn record_failure(api_context: &ApiContext, ip: Option<impl Display>) {
+    let (Some(_), Some(ip)) = (&api_context.captcha, ip) else {
+        return;
+    };
+
+    let redis = &api_context.redis;
+    let key = failures_key(redis, ip);
+    let result = async {
+        let _: i64 = redis.incr(&key).await.context("Failed to count failure")?;
+        let _: bool = redis
+            .pexpire(&key, FAILURE_WINDOW.as_millis() as i64, None)
+            .await
+            .context("Failed to set failure expiry")?;
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::warn!(error = ?e, "Failed to record CAPTCHA failure");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{body_string_contains, method},
+    };
+
+    use crate::{captcha::CaptchaClient, config::Config};
+
+    fn settings(extra: &[&str]) -> Config {
+        let mut args =
+            vec!["--hmac-key=0123456789012345678901234567890123456789012345678901234567890123"];
+        args.extend_from_slice(extra);
+        Config::for_tests(&args)
+    }
+
+    #[test]
+    fn both_keys_are_needed() {
+        let config = settings(&[]);
+        assert!(
+            CaptchaClient::from_settings(&config.application_settings)
+                .unwrap()
+                .is_none()
+        );
+
+        let config = settings(&["--captcha-site-key=site"]);
+        assert!(CaptchaClient::from_settings(&config.application_settings).is_err());
+        let config = settings(&["--captcha-secret-key=secret"]);
+        assert!(CaptchaClient::from_settings(&config.application_settings).is_err());
+    }
+
+    #[tokio::test]
+    async fn tokens_are_verified_with_the_secret_key() {
+        let provider = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("secret=secret"))
+            .and(body_string_contains("response=solved"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": true
+            })))
+            .mount(&provider)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": false,
+                "error-codes": ["invalid-input-response"]
+            })))
+            .mount(&provider)
+            .await;
+
+        let verify_url = format!("--captcha-verify-url={}", provider.uri());
+        let config = settings(&[
+            "--captcha-site-key=site",
+            "--captcha-secret-key=secret",
+            &verify_url,
+        ]);
+        let client = CaptchaClient::from_settings(&config.application_settings)
+            .unwrap()
+            .unwrap();
+
+        assert!(client.verify("solved", Some("203.0.113.7")).await.unwrap());
+        assert!(!client.verify("guessed", None::<&str>).await.unwrap());
+    }
+}
@@ -0,0 +1,53 @@
+use std::any::Any;
+
+use axum::{
+    Router,
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+
+use crate::{api_error::ApiError, app::AppRouter};
+
+/// Answer of the pages and forms to a request whose handler panicked, the
+/// same as for their unexpected errors
+pub fn web_response(panic: Box<dyn Any + Send>) -> Response {
+    log_panic(panic.as_ref());
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "An internal server error occured",
+    )
+        .into_response()
+}
+
+/// Answer of the `/api` routes to a request whose handler panicked
+pub fn api_response(panic: Box<dyn Any + Send>) -> Response {
+    log_panic(panic.as_ref());
+    ApiError::panicked().into_response()
+}
+
+/// Logged in the span of the request, so with its id
+fn log_panic(panic: &(dyn Any + Send)) {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic payload");
+    tracing::error!(panic = message, "Handler panicked");
+}
+
+/// `/test/panic`, a handler that panics, only built for the tests
+pub fn test_router() -> AppRouter {
+    #[cfg(feature = "test-routes")]
+    {
+        Router::new().route("/test/panic", axum::routing::get(panic_for_tests))
+    }
+    #[cfg(not(feature = "test-routes"))]
+    {
+        Router::new()
+    }
+}
+
+#[cfg(feature = "test-routes")]
+async fn panic_for_tests() -> StatusCode {
+    panic!("Panicking for the tests")
+}
@@ -0,0 +1,202 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use anyhow::{Context, anyhow};
+use axum::extract::{ConnectInfo, FromRequestParts};
+use http::{Extensions, HeaderMap, request::Parts};
+use ipnet::IpNet;
+
+use crate::{app::ApiContext, config::ApplicationSettings};
+
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// The reverse proxies in front of the app, whose `X-Forwarded-For` entries
+/// are believed. Without any the header is ignored, as clients can send it
+/// themselves.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<IpNet>);
+
+impl TrustedProxies {
+    pub fn from_settings(settings: &ApplicationSettings) -> Result<Self, anyhow::Error> {
+        settings
+            .trusted_proxies
+            .as_deref()
+            .map(Self::parse)
+            .unwrap_or_else(|| Ok(Self::default()))
+    }
+
+    /// Comma separated CIDR ranges, a bare address is a range of its own
+    fn parse(list: &str) -> Result<Self, anyhow::Error> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                entry
+                    .parse::<IpNet>()
+                    .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| anyhow!("Invalid trusted proxy `{entry}`"))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+            .context("Invalid trusted proxies")
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        // a client on IPv6 may show up as an IPv4-mapped address
+        let ip = ip.to_canonical();
+        self.0.iter().any(|net| net.contains(&ip))
+    }
+
+    /// The address of the client behind the connection from `peer`.
+    ///
+    /// Each proxy appends the address it got the request from, so the header
+    /// is read from the right, as long as the entries are proxies we trust.
+    /// The first one that isn't is the client, whatever it put further left.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer;
+        let entries = headers
+            .get_all(FORWARDED_FOR)
+            .iter()
+            .rev()
+            .flat_map(|value| value.to_str().unwrap_or_default().rsplit(','));
+        for entry in entries {
+            if !self.contains(client) {
+                break;
+            }
+            // garbage added by a proxy we trust, the address it came from is the best we have
+            let Ok(ip) = entry.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip;
+        }
+        client
+    }
+}
+
+/// The client address of a request, taken through the trusted proxies of the
+/// [`ApiContext`] in its extensions. `None` if the request didn't come over a
+/// connection, like in tests calling the router directly.
+pub fn client_ip(extensions: &Extensions, headers: &HeaderMap) -> Option<IpAddr> {
+    let ConnectInfo(peer) = extensions.get::<ConnectInfo<SocketAddr>>()?;
+    let ip = match extensions.get::<Arc<ApiContext>>() {
+        Some(api_context) => api_context.trusted_proxies.client_ip(peer.ip(), headers),
+        None => peer.ip(),
+    };
+    Some(ip)
+}
+
+/// Extracts the client address, see [`client_ip`]
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(client_ip(&parts.extensions, &parts.headers)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use claims::{assert_err, assert_ok};
+    use http::{HeaderMap, HeaderValue};
+
+    use crate::client_ip::TrustedProxies;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn forwarded_for(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("X-Forwarded-For", HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn proxies(list: &str) -> TrustedProxies {
+        TrustedProxies::parse(list).unwrap()
+    }
+
+    #[test]
+    pub fn header_is_ignored_without_trusted_proxies() {
+        let headers = forwarded_for(&["203.0.113.7"]);
+        assert_eq!(
+            ip("198.51.100.1"),
+            TrustedProxies::default().client_ip(ip("198.51.100.1"), &headers)
+        );
+    }
+
+    #[test]
+    pub fn header_is_ignored_from_an_untrusted_peer() {
+        let headers = forwarded_for(&["203.0.113.7"]);
+        assert_eq!(
+            ip("198.51.100.1"),
+            proxies("10.0.0.0/8").client_ip(ip("198.51.100.1"), &headers)
+        );
+    }
+
+    #[test]
+    pub fn address_added_by_a_trusted_proxy_is_the_client() {
+        let headers = forwarded_for(&["203.0.113.7"]);
+        assert_eq!(
+            ip("203.0.113.7"),
+            proxies("10.0.0.0/8").client_ip(ip("10.1.2.3"), &headers)
+        );
+    }
+
+    #[test]
+    pub fn addresses_spoofed_by_the_client_are_skipped() {
+        // the client sent `127.0.0.1, 10.9.9.9` itself, the proxy appended its address
+        let headers = forwarded_for(&["127.0.0.1, 10.9.9.9, 203.0.113.7"]);
+        assert_eq!(
+            ip("203.0.113.7"),
+            proxies("10.0.0.0/8").client_ip(ip("10.1.2.3"), &headers)
+        );
+    }
+
+    #[test]
+    pub fn chains_of_trusted_proxies_are_walked() {
+        // a CDN in front of a load balancer in front of the app, over two headers
+        let headers = forwarded_for(&["192.0.2.1, 198.51.100.9", "203.0.113.7"]);
+        assert_eq!(
+            ip("198.51.100.9"),
+            proxies("10.0.0.0/8, 203.0.113.0/24").client_ip(ip("10.1.2.3"), &headers)
+        );
+    }
+
+    #[test]
+    pub fn garbage_stops_at_the_last_trusted_address() {
+        let headers = forwarded_for(&["not an address"]);
+        assert_eq!(
+            ip("10.1.2.3"),
+            proxies("10.0.0.0/8").client_ip(ip("10.1.2.3"), &headers)
+        );
+    }
+
+    #[test]
+    pub fn ipv4_mapped_peers_are_matched() {
+        let headers = forwarded_for(&["203.0.113.7"]);
+        assert_eq!(
+            ip("203.0.113.7"),
+            proxies("127.0.0.1").client_ip(ip("::ffff:127.0.0.1"), &headers)
+        );
+    }
+
+    #[test]
+    pub fn trusted_proxies_are_parsed() {
+        assert_ok!(TrustedProxies::parse(
+            "10.0.0.0/8, 192.168.1.1, ::1, fd00::/8"
+        ));
+        assert_ok!(TrustedProxies::parse(""));
+        assert_err!(TrustedProxies::parse("10.0.0.0/33"));
+        assert_err!(TrustedProxies::parse("proxy.internal"));
+    }
+}
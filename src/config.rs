@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use clap_derive::ValueEnum;
 use secrecy::SecretString;
 
@@ -9,6 +11,74 @@ pub struct Config {
     /// The Postgres database url for the application
     #[clap(flatten)]
     pub database_settings: DatabaseSettings,
+    /// Email delivery settings
+    #[clap(flatten)]
+    pub email_client_settings: EmailClientSettings,
+    /// Where uploaded files are stored
+    #[clap(flatten)]
+    pub storage_settings: StorageSettings,
+    /// Runs a one-off command instead of starting the server
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Creates a user account, e.g. to bootstrap the first admin
+    CreateUser(CreateUserArgs),
+    /// Closes the site for maintenance, or opens it again
+    #[clap(subcommand)]
+    Maintenance(MaintenanceCommand),
+    /// Fills the database with users and todos for local development
+    Seed(SeedArgs),
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum MaintenanceCommand {
+    /// Answers every request with the maintenance page until `off`
+    On(MaintenanceOnArgs),
+    /// Opens the site again
+    Off,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct MaintenanceOnArgs {
+    /// Shown instead of the default text
+    #[clap(long)]
+    pub message: Option<String>,
+    /// How long until the site is expected to open again, in minutes
+    #[clap(long)]
+    pub eta_minutes: Option<i64>,
+    /// Let admins keep using the site, to check it before reopening
+    #[clap(long)]
+    pub admin_bypass: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CreateUserArgs {
+    #[clap(long)]
+    pub username: String,
+    #[clap(long)]
+    pub email: String,
+    #[clap(long, env = "CREATE_USER_PASSWORD")]
+    pub password: SecretString,
+    /// Give the user the admin role
+    #[clap(long)]
+    pub admin: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SeedArgs {
+    /// How many users to create, named `seed-user-1` and so on
+    #[clap(long, default_value_t = 5)]
+    pub users: u32,
+    /// How many todos each user gets in their own list
+    #[clap(long, default_value_t = 20)]
+    pub todos_per_user: u32,
+    /// Delete the seeded users and everything they own first, rather than
+    /// skipping the users that already exist
+    #[clap(long)]
+    pub reset: bool,
 }
 
 #[derive(clap::Parser, Debug)]
@@ -16,15 +86,262 @@ pub struct ApplicationSettings {
     /// Application environment
     #[clap(long, env)]
     pub app_env: AppEnv,
+    /// What gets logged, in `RUST_LOG` syntax. Logs are JSON lines outside
+    /// of development. `axum::rejection=trace` shows why axum's extractors
+    /// rejected a request
+    #[clap(
+        long,
+        env = "RUST_LOG",
+        default_value = "site=debug,tower_http=debug,axum::rejection=trace"
+    )]
+    pub log_filter: String,
+    /// OTLP/HTTP endpoint traces are sent to, e.g.
+    /// `http://localhost:4318/v1/traces`. Needs the `otel` feature
+    #[clap(long, env)]
+    pub otel_endpoint: Option<String>,
+    /// Sentry DSN that unexpected errors and panics are reported to, nothing
+    /// is reported without it
+    #[clap(long, env)]
+    pub sentry_dsn: Option<SecretString>,
     /// Application host
     #[clap(long, env)]
     pub app_host: String,
     /// Application port
     #[clap(long, env)]
     pub app_port: u16,
-    /// HMAC key for signing and verification
+    /// Public url of the application, an absolute http(s) url that may end in
+    /// a path. Links in emails, calendar feeds and invitations start with it
+    #[clap(long, env)]
+    pub app_base_url: String,
+    /// HMAC key for signing and verification, at least 64 bytes
     #[clap(long, env)]
     pub hmac_key: SecretString,
+    /// Previous HMAC keys whose session cookies are still accepted, comma
+    /// separated, so `HMAC_KEY` can be rotated without logging everyone out
+    #[clap(long, env)]
+    pub hmac_verification_keys: Option<SecretString>,
+    /// Whether pending migrations are applied at startup. Without it the
+    /// app refuses to start until they have been applied some other way
+    #[clap(long, env, default_value_t = true, action = clap::ArgAction::Set)]
+    pub run_migrations: bool,
+    /// How often the due date reminder worker looks for todos, in seconds
+    #[clap(long, env, default_value_t = 300)]
+    pub reminder_interval_secs: u64,
+    /// How long an old username stays reserved for its previous owner, in days
+    #[clap(long, env, default_value_t = 90)]
+    pub username_hold_days: i32,
+    /// How long a deactivated account can be reactivated by logging in,
+    /// before it is deleted, in days
+    #[clap(long, env, default_value_t = 30)]
+    pub account_deactivation_grace_days: i32,
+    /// Most todos that can be pinned in one list
+    #[clap(long, env, default_value_t = 10)]
+    pub max_pinned_todos: i64,
+    /// How long a deleted todo can be restored before it is removed for good, in seconds
+    #[clap(long, env, default_value_t = 30)]
+    pub todo_undo_grace_secs: u64,
+    /// A todo added again by the same user to the same list within this many
+    /// seconds is taken for the same form sent twice, and the first one is
+    /// sent back instead. 0 turns the check off
+    #[clap(long, env, default_value_t = 2)]
+    pub todo_repeat_window_secs: u64,
+    /// How long the change history of a todo is kept, in days
+    #[clap(long, env, default_value_t = 365)]
+    pub history_retention_days: i32,
+    /// How long notifications are kept, read or not, in days
+    #[clap(long, env, default_value_t = 90)]
+    pub notification_retention_days: i32,
+    /// Most active todos a user can have, unless an admin gave them a limit
+    /// of their own
+    #[clap(long, env, default_value_t = 10_000)]
+    pub max_active_todos: i64,
+    /// Largest avatar image accepted for upload, in bytes
+    #[clap(long, env, default_value_t = 2 * 1024 * 1024)]
+    pub max_avatar_bytes: usize,
+    /// Memory used to hash a password with Argon2id, in KiB
+    #[clap(long, env, default_value_t = 19 * 1024)]
+    pub argon2_memory_kib: u32,
+    /// Passes over the memory when hashing a password
+    #[clap(long, env, default_value_t = 2)]
+    pub argon2_iterations: u32,
+    /// Lanes used when hashing a password
+    #[clap(long, env, default_value_t = 1)]
+    pub argon2_parallelism: u32,
+    /// Passwords hashed or checked at the same time, defaults to the number of CPUs
+    #[clap(long, env)]
+    pub password_hashing_permits: Option<usize>,
+    /// How long a login or registration waits to get its password hashed
+    /// before giving up, in milliseconds
+    #[clap(long, env, default_value_t = 5_000)]
+    pub password_hashing_queue_timeout_millis: u64,
+    /// Comma separated CIDR ranges of the reverse proxies in front of the app,
+    /// e.g. `10.0.0.0/8, 127.0.0.1`. Only requests from these have their
+    /// X-Forwarded-For header believed
+    #[clap(long, env)]
+    pub trusted_proxies: Option<String>,
+    /// Whether registrations filling in the hidden honeypot field are dropped
+    #[clap(long, env, default_value_t = true, action = clap::ArgAction::Set)]
+    pub registration_honeypot_enabled: bool,
+    /// Registrations sent sooner after loading the form are rejected as bots,
+    /// in seconds. 0 turns the check off
+    #[clap(long, env, default_value_t = 2)]
+    pub registration_min_fill_secs: u64,
+    /// Whether each registration form served can only be sent once, so
+    /// sending it twice doesn't fail with a taken username after the first
+    /// one went through
+    #[clap(long, env, default_value_t = true, action = clap::ArgAction::Set)]
+    pub registration_single_use_forms: bool,
+    /// Whether registering with an email that already has an account looks
+    /// like a success, with a notice sent to that address instead, so the
+    /// form can't be used to find out who has an account. On by default in
+    /// production
+    #[clap(long, env)]
+    pub registration_privacy_mode: Option<bool>,
+    /// Who can register: anyone, only people with an invitation code from an
+    /// admin, or no one, in which case the form collects addresses for a
+    /// waitlist instead
+    #[clap(long, env, default_value = "open")]
+    pub registration_mode: RegistrationMode,
+    /// Version of the terms of service users have to accept. Raising it asks
+    /// everyone to accept them again before going on
+    #[clap(long, env, default_value_t = 1)]
+    pub tos_version: i32,
+    /// Whether the per-address rate limits of public routes are applied
+    #[clap(long, env, default_value_t = true, action = clap::ArgAction::Set)]
+    pub rate_limit_enabled: bool,
+    /// Service the CAPTCHA widget comes from and its tokens are verified with
+    #[clap(long, env, default_value = "turnstile")]
+    pub captcha_provider: CaptchaProvider,
+    /// Site key of the CAPTCHA widget. Without it and `CAPTCHA_SECRET_KEY`
+    /// nobody is ever asked to solve one
+    #[clap(long, env)]
+    pub captcha_site_key: Option<String>,
+    /// Secret key CAPTCHA tokens are verified with
+    #[clap(long, env)]
+    pub captcha_secret_key: Option<SecretString>,
+    /// Failed logins and registrations of an address after which it has to
+    /// solve a CAPTCHA, until an hour after its last failure. 0 asks everyone
+    #[clap(long, env, default_value_t = 5)]
+    pub captcha_failure_threshold: u32,
+    /// Where CAPTCHA tokens are verified, the endpoint of `CAPTCHA_PROVIDER`
+    /// by default
+    #[clap(long, env)]
+    pub captcha_verify_url: Option<String>,
+    /// Redis key holding the maintenance flag, instances sharing it go into
+    /// maintenance together
+    #[clap(long, env, default_value = "maintenance")]
+    pub maintenance_redis_key: String,
+    /// Whether new accounts can be registered. Turned off features answer
+    /// with a 404 and are hidden from the pages
+    #[clap(long, env, default_value_t = true, action = clap::ArgAction::Set)]
+    pub feature_registration: bool,
+    /// Whether users can ask for a login link by email
+    #[clap(long, env, default_value_t = true, action = clap::ArgAction::Set)]
+    pub feature_magic_links: bool,
+    /// Whether users can add passkeys and log in with them
+    #[clap(long, env, default_value_t = true, action = clap::ArgAction::Set)]
+    pub feature_passkeys: bool,
+    /// Whether users can set up webhooks, and queued deliveries are sent
+    #[clap(long, env, default_value_t = true, action = clap::ArgAction::Set)]
+    pub feature_webhooks: bool,
+    /// Whether the user looked up for each authenticated request is cached
+    /// in Redis, turn it off to debug session issues
+    #[clap(long, env, default_value_t = true, action = clap::ArgAction::Set)]
+    pub user_cache_enabled: bool,
+    /// Whether rendered list views are cached in Redis. Every instance has to
+    /// agree on it
+    #[clap(long, env, default_value_t = false, action = clap::ArgAction::Set)]
+    pub list_cache_enabled: bool,
+    /// How long a cached list view is served at most, in seconds
+    #[clap(long, env, default_value_t = 60)]
+    pub list_cache_ttl_secs: u64,
+    /// How often queued webhook deliveries are sent, in seconds
+    #[clap(long, env, default_value_t = 10)]
+    pub webhook_interval_secs: u64,
+    /// How long a failed webhook delivery waits before it is retried, in
+    /// seconds. Doubles with every further attempt, up to an hour
+    #[clap(long, env, default_value_t = 30)]
+    pub webhook_retry_base_secs: u64,
+    /// Failed webhook deliveries in a row after which the endpoint is turned off
+    #[clap(long, env, default_value_t = 10)]
+    pub webhook_max_failures: i32,
+    /// Whether webhooks may point at loopback or private network addresses.
+    /// Only meant for development, as it lets users make the app send
+    /// requests to services on its own network
+    #[clap(long, env, default_value_t = false, action = clap::ArgAction::Set)]
+    pub webhook_allow_private_targets: bool,
+    /// How often queued emails are sent, in seconds
+    #[clap(long, env, default_value_t = 5)]
+    pub email_interval_secs: u64,
+    /// How long an email that failed to send waits before it is retried, in
+    /// seconds. Doubles with every further attempt, up to an hour
+    #[clap(long, env, default_value_t = 30)]
+    pub email_retry_base_secs: u64,
+    /// Attempts after which an email is given up on and listed for the admins
+    #[clap(long, env, default_value_t = 8)]
+    pub email_max_attempts: i32,
+    /// Origins allowed to call the JSON API from a browser, comma separated,
+    /// e.g. https://app.example.com. `*` allows any. Defaults to any
+    /// localhost port in development and to none otherwise
+    #[clap(long, env)]
+    pub cors_allowed_origins: Option<String>,
+    /// Methods allowed in cross-origin API requests, comma separated
+    #[clap(long, env, default_value = "GET,POST,PUT,PATCH,DELETE")]
+    pub cors_allowed_methods: String,
+    /// Whether cross-origin API requests may send cookies, which the session
+    /// needs. Can't be combined with `*` origins. On by default in development
+    #[clap(long, env)]
+    pub cors_allow_credentials: Option<bool>,
+    /// SameSite attribute of the session cookie. `none` is only needed for
+    /// cross-origin API requests with cookies, and browsers only accept it
+    /// over HTTPS
+    #[clap(long, env, default_value = "lax")]
+    pub session_same_site: SessionSameSite,
+    /// How long a session stays logged in however often it is used, in days.
+    /// Sessions without requests for an hour are logged out before that
+    #[clap(long, env, default_value_t = 30)]
+    pub max_session_age_days: i64,
+    /// Most sessions a user can be logged in with at once, unlimited by default
+    #[clap(long, env, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_sessions_per_user: Option<u32>,
+    /// What logging in past `MAX_SESSIONS_PER_USER` does: `reject` the login,
+    /// or `evict-oldest` session of the user to make room
+    #[clap(long, env, default_value = "evict-oldest")]
+    pub session_limit_strategy: SessionLimitStrategy,
+    /// HS256 key the access tokens of API clients are signed with, at least
+    /// 32 bytes. Bearer tokens aren't offered without one
+    #[clap(long, env)]
+    pub jwt_signing_key: Option<SecretString>,
+    /// Previous signing keys whose tokens are still accepted, comma
+    /// separated, so the signing key can be rotated without logging
+    /// everyone out
+    #[clap(long, env)]
+    pub jwt_verification_keys: Option<SecretString>,
+    /// How long an access token is valid, in seconds
+    #[clap(long, env, default_value_t = 15 * 60)]
+    pub access_token_ttl_secs: u64,
+    /// How long a refresh token can be used to get a new access token, in days
+    #[clap(long, env, default_value_t = 30)]
+    pub refresh_token_ttl_days: i32,
+    /// Relying party ID passkeys are bound to, the domain of the site or a
+    /// parent of it. Defaults to the host of `APP_BASE_URL`
+    #[clap(long, env)]
+    pub webauthn_rp_id: Option<String>,
+    /// Origin passkey ceremonies have to come from. Defaults to `APP_BASE_URL`
+    #[clap(long, env)]
+    pub webauthn_rp_origin: Option<String>,
+}
+
+impl ApplicationSettings {
+    pub fn registration_privacy_mode(&self) -> bool {
+        self.registration_privacy_mode
+            .unwrap_or(self.app_env == AppEnv::Production)
+    }
+
+    pub fn cors_allow_credentials(&self) -> bool {
+        self.cors_allow_credentials
+            .unwrap_or(self.app_env == AppEnv::Development)
+    }
 }
 
 #[derive(clap::Parser, Debug)]
@@ -33,6 +350,109 @@ pub struct DatabaseSettings {
     pub database_url: SecretString,
     #[clap(long, env)]
     pub redis_url: SecretString,
+    /// Prepended to every Redis key, e.g. `staging:`, so several
+    /// environments can share one Redis
+    #[clap(long, env, default_value = "")]
+    pub redis_key_prefix: String,
+    /// How long the app waits for Redis to come up at startup before giving
+    /// up, in seconds
+    #[clap(long, env, default_value_t = 30)]
+    pub redis_startup_wait_secs: u64,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct EmailClientSettings {
+    /// Base url of the email delivery API
+    #[clap(long, env)]
+    pub email_base_url: String,
+    /// Address emails are sent from
+    #[clap(long, env)]
+    pub email_sender: String,
+    /// Server token for the email delivery API
+    #[clap(long, env)]
+    pub email_authorization_token: SecretString,
+    /// Timeout for email delivery requests, in milliseconds
+    #[clap(long, env, default_value_t = 10_000)]
+    pub email_timeout_millis: u64,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct StorageSettings {
+    /// Backend uploaded files are stored in
+    #[clap(long, env, default_value = "local")]
+    pub storage_backend: StorageBackend,
+    /// Directory uploaded files are stored in by the local backend
+    #[clap(long, env, default_value = "uploads")]
+    pub upload_dir: PathBuf,
+    /// Custom S3 endpoint, e.g. for MinIO
+    #[clap(long, env)]
+    pub storage_endpoint: Option<String>,
+    /// S3 bucket for uploaded files
+    #[clap(long, env)]
+    pub storage_bucket: Option<String>,
+    /// S3 region of the bucket
+    #[clap(long, env, default_value = "us-east-1")]
+    pub storage_region: String,
+    /// S3 access key id
+    #[clap(long, env)]
+    pub storage_access_key_id: Option<String>,
+    /// S3 secret access key
+    #[clap(long, env)]
+    pub storage_secret_access_key: Option<SecretString>,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum, PartialEq)]
+pub enum StorageBackend {
+    #[clap(name = "local")]
+    Local,
+    #[clap(name = "s3")]
+    S3,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum, PartialEq)]
+pub enum RegistrationMode {
+    #[clap(name = "open")]
+    Open,
+    #[clap(name = "invite")]
+    Invite,
+    #[clap(name = "closed")]
+    Closed,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum, PartialEq)]
+pub enum SessionSameSite {
+    #[clap(name = "strict")]
+    Strict,
+    #[clap(name = "lax")]
+    Lax,
+    #[clap(name = "none")]
+    None,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum, PartialEq)]
+pub enum SessionLimitStrategy {
+    #[clap(name = "reject")]
+    Reject,
+    #[clap(name = "evict-oldest")]
+    EvictOldest,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum, PartialEq)]
+pub enum CaptchaProvider {
+    #[clap(name = "turnstile")]
+    Turnstile,
+    #[clap(name = "hcaptcha")]
+    Hcaptcha,
+}
+
+impl From<SessionSameSite> for tower_sessions::cookie::SameSite {
+    fn from(same_site: SessionSameSite) -> Self {
+        match same_site {
+            SessionSameSite::Strict => Self::Strict,
+            SessionSameSite::Lax => Self::Lax,
+            SessionSameSite::None => Self::None,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, ValueEnum, PartialEq)]
@@ -44,3 +464,35 @@ pub enum AppEnv {
     #[clap(name = "production")]
     Production,
 }
+
+impl AppEnv {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AppEnv::Development => "development",
+            AppEnv::Staging => "staging",
+            AppEnv::Production => "production",
+        }
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// The settings without a default, followed by `args`
+    pub(crate) fn for_tests(args: &[&str]) -> Self {
+        use clap::Parser;
+
+        let required = [
+            "site",
+            "--app-env=development",
+            "--app-host=localhost",
+            "--app-port=0",
+            "--app-base-url=http://localhost:8000",
+            "--database-url=postgres://localhost/site",
+            "--redis-url=redis://localhost:6379",
+            "--email-base-url=http://localhost:8001",
+            "--email-sender=noreply@example.com",
+            "--email-authorization-token=token",
+        ];
+        Self::parse_from(required.iter().chain(args))
+    }
+}
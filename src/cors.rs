@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use http::{HeaderName, HeaderValue, Method, header, request::Parts};
+use reqwest::Url;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::config::{AppEnv, ApplicationSettings};
+
+/// How long browsers may reuse the answer to a preflight request
+const PREFLIGHT_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum CorsConfigError {
+    #[error("Invalid CORS origin {0:?}, expected e.g. https://app.example.com")]
+    InvalidOrigin(String),
+    #[error("Invalid CORS method {0:?}")]
+    InvalidMethod(String),
+    #[error("CORS credentials can't be allowed for every origin, list the origins instead of *")]
+    WildcardWithCredentials,
+}
+
+/// CORS for the JSON API, so a frontend served from another origin can call it.
+///
+/// Preflight requests are answered by the layer itself, before any handler
+/// or authentication check.
+pub fn from_settings(settings: &ApplicationSettings) -> Result<CorsLayer, CorsConfigError> {
+    cors_layer(
+        settings.cors_allowed_origins.as_deref(),
+        &settings.cors_allowed_methods,
+        settings.cors_allow_credentials(),
+        settings.app_env,
+    )
+}
+
+fn cors_layer(
+    origins: Option<&str>,
+    methods: &str,
+    allow_credentials: bool,
+    app_env: AppEnv,
+) -> Result<CorsLayer, CorsConfigError> {
+    let allow_origin = match origins.map(str::trim) {
+        Some("*") if allow_credentials => return Err(CorsConfigError::WildcardWithCredentials),
+        Some("*") => AllowOrigin::any(),
+        Some(origins) => AllowOrigin::list(parse_origins(origins)?),
+        None if app_env == AppEnv::Development => {
+            AllowOrigin::predicate(|origin: &HeaderValue, _: &Parts| is_localhost_origin(origin))
+        }
+        None => AllowOrigin::list([]),
+    };
+
+    let methods = split_list(methods)
+        .map(|method| {
+            Method::from_bytes(method.to_uppercase().as_bytes())
+                .map_err(|_| CorsConfigError::InvalidMethod(method.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(methods)
+        // listed rather than allowing any, which browsers refuse along with credentials
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::IF_NONE_MATCH,
+            HeaderName::from_static("idempotency-key"),
+        ])
+        .expose_headers([header::ETAG])
+        .allow_credentials(allow_credentials)
+        .max_age(PREFLIGHT_MAX_AGE))
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// Origins are compared as sent by browsers, scheme, host and port only
+fn parse_origins(origins: &str) -> Result<Vec<HeaderValue>, CorsConfigError> {
+    split_list(origins)
+        .map(|origin| {
+            let invalid = || CorsConfigError::InvalidOrigin(origin.to_string());
+            let url = Url::parse(origin).map_err(|_| invalid())?;
+            if url.origin().ascii_serialization() != origin {
+                return Err(invalid());
+            }
+            HeaderValue::from_str(origin).map_err(|_| invalid())
+        })
+        .collect()
+}
+
+/// `http://localhost:<port>` and the like, where dev servers of frontends run
+fn is_localhost_origin(origin: &HeaderValue) -> bool {
+    let Some(host) = origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.strip_prefix("http://"))
+    else {
+        return false;
+    };
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => host,
+    };
+    matches!(host, "localhost" | "127.0.0.1" | "[::1]")
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_err_eq;
+    use http::HeaderValue;
+
+    use crate::{
+        config::AppEnv,
+        cors::{CorsConfigError, cors_layer, is_localhost_origin},
+    };
+
+    #[test]
+    pub fn listed_origins_and_methods_are_valid() {
+        assert!(
+            cors_layer(
+                Some("https://app.example.com, http://localhost:5173"),
+                "GET,post",
+                true,
+                AppEnv::Production
+            )
+            .is_ok()
+        );
+        assert!(cors_layer(None, "GET", false, AppEnv::Production).is_ok());
+        assert!(cors_layer(Some("*"), "GET", false, AppEnv::Production).is_ok());
+    }
+
+    #[test]
+    pub fn wildcard_with_credentials_is_rejected() {
+        assert_err_eq!(
+            cors_layer(Some(" * "), "GET", true, AppEnv::Development),
+            CorsConfigError::WildcardWithCredentials
+        );
+    }
+
+    #[test]
+    pub fn origins_with_more_than_scheme_host_and_port_are_rejected() {
+        for origin in [
+            "app.example.com",
+            "https://app.example.com/",
+            "https://app.example.com/spa",
+            "https://app.example.com:443",
+        ] {
+            assert_err_eq!(
+                cors_layer(Some(origin), "GET", false, AppEnv::Production),
+                CorsConfigError::InvalidOrigin(origin.to_string())
+            );
+        }
+    }
+
+    #[test]
+    pub fn unknown_methods_are_rejected() {
+        assert_err_eq!(
+            cors_layer(None, "GET,GE T", false, AppEnv::Production),
+            CorsConfigError::InvalidMethod("GE T".to_string())
+        );
+    }
+
+    #[test]
+    pub fn localhost_origins_are_recognized() {
+        for origin in [
+            "http://localhost:5173",
+            "http://localhost",
+            "http://127.0.0.1:3000",
+            "http://[::1]:8080",
+        ] {
+            assert!(is_localhost_origin(&HeaderValue::from_static(origin)));
+        }
+        for origin in [
+            "https://example.com",
+            "http://localhost.example.com",
+            "http://example.com:localhost",
+            "null",
+        ] {
+            assert!(!is_localhost_origin(&HeaderValue::from_static(origin)));
+        }
+    }
+}
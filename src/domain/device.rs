@@ -0,0 +1,123 @@
+use std::net::IpAddr;
+
+/// A coarse fingerprint of where a login came from.
+///
+/// Browser updates and address changes within the same network are common,
+/// so only the browser family and the network (/24 for IPv4, /64 for IPv6)
+/// are compared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    user_agent_family: String,
+    network: String,
+}
+
+impl Device {
+    pub fn new(user_agent: Option<&str>, ip_address: Option<&str>) -> Self {
+        Self {
+            user_agent_family: user_agent_family(user_agent.unwrap_or_default()),
+            network: ip_address
+                .and_then(|ip| ip.parse::<IpAddr>().ok())
+                .map(network)
+                .unwrap_or_else(|| "unknown".to_string()),
+        }
+    }
+
+    pub fn user_agent_family(&self) -> &str {
+        &self.user_agent_family
+    }
+
+    pub fn network(&self) -> &str {
+        &self.network
+    }
+}
+
+fn user_agent_family(user_agent: &str) -> String {
+    // order matters, most browsers also claim to be the ones they're based on
+    const BROWSERS: [(&str, &str); 6] = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+        ("curl/", "curl"),
+    ];
+
+    if let Some((_, family)) = BROWSERS
+        .iter()
+        .find(|(token, _)| user_agent.contains(token))
+    {
+        return family.to_string();
+    }
+
+    // otherwise the product name of the first token, without its version
+    match user_agent.split(['/', ' ']).next() {
+        Some(product) if !product.is_empty() => product.to_string(),
+        _ => "unknown".to_string(),
+    }
+}
+
+fn network(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{a}.{b}.{c}.0/24")
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            format!(
+                "{:x}:{:x}:{:x}:{:x}::/64",
+                segments[0], segments[1], segments[2], segments[3]
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::device::Device;
+
+    const FIREFOX: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:140.0) Gecko/20100101 Firefox/140.0";
+    const CHROME: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/138.0.0.0 Safari/537.36";
+    const EDGE: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/138.0.0.0 Safari/537.36 Edg/138.0.0.0";
+
+    #[test]
+    fn browsers_are_recognized() {
+        for (user_agent, family) in [(FIREFOX, "Firefox"), (CHROME, "Chrome"), (EDGE, "Edge")] {
+            let device = Device::new(Some(user_agent), None);
+            assert_eq!(family, device.user_agent_family());
+        }
+    }
+
+    #[test]
+    fn unknown_user_agents_use_the_product_name() {
+        let device = Device::new(Some("my-app/2.1 (build 7)"), None);
+        assert_eq!("my-app", device.user_agent_family());
+
+        let device = Device::new(None, None);
+        assert_eq!("unknown", device.user_agent_family());
+    }
+
+    #[test]
+    fn browser_updates_are_the_same_device() {
+        let old = Device::new(
+            Some("Mozilla/5.0 (X11; Linux x86_64; rv:139.0) Gecko/20100101 Firefox/139.0"),
+            Some("203.0.113.7"),
+        );
+        let new = Device::new(Some(FIREFOX), Some("203.0.113.7"));
+        assert_eq!(old, new);
+    }
+
+    #[test]
+    fn addresses_are_grouped_by_network() {
+        let device = Device::new(None, Some("203.0.113.7"));
+        assert_eq!("203.0.113.0/24", device.network());
+        assert_eq!(device, Device::new(None, Some("203.0.113.250")));
+        assert_ne!(device, Device::new(None, Some("203.0.114.7")));
+
+        let device = Device::new(None, Some("2001:db8:1:2:3:4:5:6"));
+        assert_eq!("2001:db8:1:2::/64", device.network());
+
+        let device = Device::new(None, Some("not an ip"));
+        assert_eq!("unknown", device.network());
+    }
+}
@@ -4,7 +4,7 @@ use validator::ValidateEmail;
 #[error("Invalid email")]
 pub struct InvalidEmailError;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EmailAddress(String);
 
 impl EmailAddress {
@@ -29,3 +29,47 @@ impl AsRef<str> for EmailAddress {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use proptest::prelude::*;
+
+    use crate::domain::{
+        email_address::EmailAddress,
+        strategies::{any_text, email_like},
+    };
+
+    #[test]
+    fn email_is_parsed_as_lowercase() {
+        let email = EmailAddress::parse("Alice@Example.com").unwrap();
+        assert_eq!("alice@example.com", email.as_ref());
+    }
+
+    #[test]
+    fn email_without_at_is_invalid() {
+        assert_err!(EmailAddress::parse("alice.example.com"));
+        assert_ok!(EmailAddress::parse("alice@example.com"));
+    }
+
+    proptest! {
+        #[test]
+        fn parsing_any_text_gives_a_stable_answer(s in any_text()) {
+            let first = EmailAddress::parse(&s).map(|email| email.to_string());
+            let second = EmailAddress::parse(&s).map(|email| email.to_string());
+            prop_assert_eq!(first.is_ok(), second.is_ok());
+            if let (Ok(first), Ok(second)) = (first, second) {
+                prop_assert_eq!(&first, &second);
+                prop_assert_eq!(s.to_lowercase(), first);
+            }
+        }
+
+        #[test]
+        fn accepted_emails_round_trip(s in email_like()) {
+            if let Ok(email) = EmailAddress::parse(&s) {
+                let reparsed = EmailAddress::parse(email.as_ref()).unwrap();
+                prop_assert_eq!(email.as_ref(), reparsed.as_ref());
+            }
+        }
+    }
+}
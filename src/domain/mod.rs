@@ -1,3 +1,43 @@
+use icu::segmenter::GraphemeClusterSegmenter;
+
+pub mod device;
 pub mod email_address;
 pub mod password;
+pub mod priority;
+#[cfg(test)]
+pub(crate) mod strategies;
+pub mod tag;
+pub mod timezone;
+pub mod todo_content;
+pub mod todo_description;
 pub mod username;
+pub mod webhook_url;
+
+/// Number of grapheme clusters, what people count as characters
+pub fn grapheme_count(s: &str) -> usize {
+    // segment_str returns the breakpoints, including one at the start, which
+    // an empty string may not get
+    GraphemeClusterSegmenter::new()
+        .segment_str(s)
+        .count()
+        .saturating_sub(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::domain::{grapheme_count, strategies::graphemes};
+
+    #[test]
+    fn empty_text_has_no_graphemes() {
+        assert_eq!(0, grapheme_count(""));
+    }
+
+    proptest! {
+        #[test]
+        fn graphemes_are_counted_as_people_see_them((s, len) in graphemes(0..100)) {
+            prop_assert_eq!(len, grapheme_count(&s));
+        }
+    }
+}
@@ -1,19 +1,31 @@
-use icu::segmenter::GraphemeClusterSegmenter;
 use secrecy::{ExposeSecret, SecretString};
 
+use crate::{
+    domain::grapheme_count,
+    i18n::{Locale, Translatable},
+};
+
 const MIN_PASSWORD_LENGTH: usize = 12;
 const MAX_PASSWORD_LENGTH: usize = 256;
 
 #[derive(thiserror::Error, Debug, PartialEq)]
+#[error("{}", self.message(Locale::En))]
 pub enum InvalidPasswordError {
-    #[error("Password is empty")]
     Empty,
-    #[error("Password is too short")]
     TooShort,
-    #[error("Password is too long")]
     TooLong,
 }
 
+impl Translatable for InvalidPasswordError {
+    fn message_key(&self) -> &'static str {
+        match self {
+            InvalidPasswordError::Empty => "error.password.empty",
+            InvalidPasswordError::TooShort => "error.password.too_short",
+            InvalidPasswordError::TooLong => "error.password.too_long",
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Password(SecretString);
 
@@ -23,8 +35,7 @@ impl Password {
             return Err(InvalidPasswordError::Empty);
         }
 
-        // segment_str returns the breakpoints, subtract 1 to grapheme cluster count
-        let len = GraphemeClusterSegmenter::new().segment_str(s).count() - 1;
+        let len = grapheme_count(s);
         if len < MIN_PASSWORD_LENGTH {
             return Err(InvalidPasswordError::TooShort);
         }
@@ -44,8 +55,14 @@ impl ExposeSecret<str> for Password {
 
 #[cfg(test)]
 mod tests {
-    use crate::domain::password::{InvalidPasswordError, Password};
     use claims::{assert_err_eq, assert_ok};
+    use proptest::prelude::*;
+    use secrecy::ExposeSecret;
+
+    use crate::domain::{
+        password::{InvalidPasswordError, MAX_PASSWORD_LENGTH, MIN_PASSWORD_LENGTH, Password},
+        strategies::{any_text, graphemes},
+    };
 
     #[test]
     fn empty_password_is_invalid() {
@@ -76,4 +93,29 @@ mod tests {
         let passwd = "ё".repeat(256);
         assert_ok!(Password::parse(&passwd));
     }
+
+    proptest! {
+        #[test]
+        fn parsing_any_text_never_panics(s in any_text()) {
+            if let Ok(password) = Password::parse(&s) {
+                prop_assert_eq!(s.as_str(), password.expose_secret());
+            }
+        }
+
+        #[test]
+        fn passwords_are_measured_in_graphemes(
+            (s, len) in graphemes(0..=MAX_PASSWORD_LENGTH + 10)
+        ) {
+            let expected = if len == 0 {
+                Err(InvalidPasswordError::Empty)
+            } else if len < MIN_PASSWORD_LENGTH {
+                Err(InvalidPasswordError::TooShort)
+            } else if len > MAX_PASSWORD_LENGTH {
+                Err(InvalidPasswordError::TooLong)
+            } else {
+                Ok(())
+            };
+            prop_assert_eq!(expected, Password::parse(&s).map(|_| ()));
+        }
+    }
 }
@@ -0,0 +1,68 @@
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("Priority must be low, normal or high")]
+pub struct InvalidPriorityError;
+
+/// Declared from lowest to highest, matching the order of the `todo_priority`
+/// enum in the database
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "todo_priority", rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    pub fn parse(s: &str) -> Result<Priority, InvalidPriorityError> {
+        match s.trim() {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            _ => Err(InvalidPriorityError),
+        }
+    }
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Priority::Low => write!(f, "low"),
+            Priority::Normal => write!(f, "normal"),
+            Priority::High => write!(f, "high"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+
+    use crate::domain::priority::Priority;
+
+    #[test]
+    pub fn known_priorities_are_valid() {
+        assert_ok_eq!(Priority::parse("low"), Priority::Low);
+        assert_ok_eq!(Priority::parse("normal"), Priority::Normal);
+        assert_ok_eq!(Priority::parse(" high "), Priority::High);
+    }
+
+    #[test]
+    pub fn unknown_priorities_are_invalid() {
+        assert_err!(Priority::parse(""));
+        assert_err!(Priority::parse("urgent"));
+        assert_err!(Priority::parse("HIGH"));
+    }
+
+    #[test]
+    pub fn priorities_display_as_their_parsed_value() {
+        for priority in Priority::ALL {
+            assert_ok_eq!(Priority::parse(&priority.to_string()), priority);
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Input generators for property tests of the domain parsers
+
+use proptest::{collection::SizeRange, prelude::*, sample::select};
+
+/// Grapheme clusters of one to several code points, which stay separate
+/// clusters when put next to each other
+const GRAPHEMES: [&str; 14] = [
+    "a",
+    "Z",
+    "7",
+    "-",
+    " ",
+    "ё",
+    // e with a combining acute accent
+    "e\u{301}",
+    "한",
+    "\r\n",
+    "👍🏽",
+    // woman technologist, two emoji joined by a ZWJ
+    "👩\u{200d}💻",
+    "🇫🇷",
+    "\u{0}",
+    "\u{feff}",
+];
+
+/// Any text, with whitespace, control characters, unassigned code points and
+/// combining marks in any place
+pub fn any_text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<String>(),
+        // long enough to cross the length limits
+        prop::collection::vec(any::<char>(), 0..1100).prop_map(String::from_iter),
+    ]
+}
+
+/// Text made of a known number of grapheme clusters, returned with it, so
+/// lengths can be checked without segmenting the text again
+pub fn graphemes(len: impl Into<SizeRange>) -> impl Strategy<Value = (String, usize)> {
+    prop::collection::vec(select(&GRAPHEMES[..]), len)
+        .prop_map(|graphemes| (graphemes.concat(), graphemes.len()))
+}
+
+/// Usernames as people would type them, mostly valid
+pub fn username_like() -> impl Strategy<Value = String> {
+    "[ ]{0,2}[a-zA-Z0-9._-]{0,70}[ ]{0,2}"
+}
+
+/// Email addresses as people would type them, mostly valid
+pub fn email_like() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9._+-]{0,20}@[a-zA-Z0-9-]{0,20}(\\.[a-zA-Z]{0,5}){0,2}"
+}
@@ -0,0 +1,138 @@
+const MAX_TAG_LENGTH: usize = 32;
+const MAX_TAGS_PER_TODO: usize = 10;
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum InvalidTagError {
+    #[error("Empty tag")]
+    Empty,
+    #[error("Tag too long")]
+    TooLong,
+    #[error("Tag contains forbidden character")]
+    ContainsForbiddenCharacter,
+    #[error("Too many tags, the maximum is 10")]
+    TooMany,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagName(String);
+
+impl TagName {
+    pub fn parse(s: &str) -> Result<TagName, InvalidTagError> {
+        let tag = s.trim().to_lowercase();
+
+        if tag.is_empty() {
+            return Err(InvalidTagError::Empty);
+        }
+
+        // tags are restricted to ascii, so the char count is the grapheme count
+        if tag.chars().count() > MAX_TAG_LENGTH {
+            return Err(InvalidTagError::TooLong);
+        }
+
+        if tag
+            .chars()
+            .any(|g| !(g.is_ascii_alphanumeric() || g == '-' || g == '_' || g == '.'))
+        {
+            return Err(InvalidTagError::ContainsForbiddenCharacter);
+        }
+
+        Ok(Self(tag))
+    }
+}
+
+impl std::fmt::Display for TagName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl AsRef<str> for TagName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The tags of a single todo, parsed from a comma-separated list
+#[derive(Debug, Clone, Default)]
+pub struct Tags(Vec<TagName>);
+
+impl Tags {
+    pub fn parse(s: &str) -> Result<Tags, InvalidTagError> {
+        let mut tags: Vec<TagName> = Vec::new();
+        for tag in s.split(',').filter(|tag| !tag.trim().is_empty()) {
+            let tag = TagName::parse(tag)?;
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+
+        if tags.len() > MAX_TAGS_PER_TODO {
+            return Err(InvalidTagError::TooMany);
+        }
+
+        Ok(Self(tags))
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.0.iter().map(|tag| tag.to_string()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err_eq, assert_ok};
+
+    use crate::domain::tag::{InvalidTagError, TagName, Tags};
+
+    #[test]
+    pub fn tag_is_trimmed_and_lowercased() {
+        assert_eq!(TagName::parse("  WoRk ").unwrap().as_ref(), "work");
+    }
+
+    #[test]
+    pub fn empty_tag_is_invalid() {
+        assert_err_eq!(TagName::parse(" "), InvalidTagError::Empty);
+    }
+
+    #[test]
+    pub fn a_33_character_tag_is_invalid() {
+        let tag = "a".repeat(33);
+        assert_err_eq!(TagName::parse(&tag), InvalidTagError::TooLong);
+
+        let tag = "a".repeat(32);
+        assert_ok!(TagName::parse(&tag));
+    }
+
+    #[test]
+    pub fn tag_containing_forbidden_characters_is_invalid() {
+        assert_err_eq!(
+            TagName::parse("home work"),
+            InvalidTagError::ContainsForbiddenCharacter
+        );
+        assert_err_eq!(
+            TagName::parse("ё"),
+            InvalidTagError::ContainsForbiddenCharacter
+        );
+    }
+
+    #[test]
+    pub fn tag_list_is_normalized_and_deduplicated() {
+        let tags = Tags::parse(" Work, home,,work ,HOME, errands").unwrap();
+        assert_eq!(tags.names(), vec!["work", "home", "errands"]);
+    }
+
+    #[test]
+    pub fn empty_tag_list_is_valid() {
+        assert!(Tags::parse("").unwrap().names().is_empty());
+        assert!(Tags::parse(" , ,").unwrap().names().is_empty());
+    }
+
+    #[test]
+    pub fn more_than_10_tags_is_invalid() {
+        let tags = (0..11).map(|i| format!("tag{i}")).collect::<Vec<_>>();
+        assert_err_eq!(Tags::parse(&tags.join(",")), InvalidTagError::TooMany);
+
+        let tags = (0..10).map(|i| format!("tag{i}")).collect::<Vec<_>>();
+        assert_ok!(Tags::parse(&tags.join(",")));
+    }
+}
@@ -0,0 +1,95 @@
+use time::{Date, OffsetDateTime};
+use time_tz::{OffsetDateTimeExt, TimeZone, Tz, timezones};
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("Unknown timezone")]
+pub struct InvalidTimezoneError;
+
+/// An IANA timezone name, e.g. "Europe/Berlin"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timezone(String);
+
+impl Timezone {
+    pub fn parse(s: &str) -> Result<Timezone, InvalidTimezoneError> {
+        let tz = timezones::get_by_name(s.trim()).ok_or(InvalidTimezoneError)?;
+        // windows names are accepted too, but stored under their IANA name
+        Ok(Self(tz.name().to_string()))
+    }
+}
+
+impl std::fmt::Display for Timezone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl AsRef<str> for Timezone {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The zone of a stored timezone name, UTC for names that aren't known
+/// (anymore), so a page never fails over a renamed zone
+pub fn tz_or_utc(name: &str) -> &'static Tz {
+    timezones::get_by_name(name).unwrap_or(timezones::db::UTC)
+}
+
+/// The calendar date in `tz` at `instant`, e.g. what "today" is for someone
+/// living there
+pub fn local_date(instant: OffsetDateTime, tz: &Tz) -> Date {
+    instant.to_timezone(tz).date()
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use time::macros::{date, datetime};
+    use time_tz::{TimeZone, timezones};
+
+    use crate::domain::timezone::{Timezone, local_date, tz_or_utc};
+
+    #[test]
+    pub fn iana_timezones_are_valid() {
+        assert_ok!(Timezone::parse("UTC"));
+        assert_ok!(Timezone::parse("Europe/Berlin"));
+        assert_ok!(Timezone::parse(" America/New_York "));
+    }
+
+    #[test]
+    pub fn unknown_timezones_are_invalid() {
+        assert_err!(Timezone::parse(""));
+        assert_err!(Timezone::parse("Mars/Olympus_Mons"));
+        assert_err!(Timezone::parse("+02:00"));
+    }
+
+    #[test]
+    pub fn timezone_name_is_trimmed() {
+        assert_eq!(
+            "Asia/Tokyo",
+            Timezone::parse(" Asia/Tokyo ").unwrap().as_ref()
+        );
+    }
+
+    #[test]
+    pub fn unknown_stored_names_fall_back_to_utc() {
+        assert_eq!(timezones::db::UTC, tz_or_utc("Mars/Olympus_Mons"));
+        assert_eq!("Asia/Tokyo", tz_or_utc("Asia/Tokyo").name());
+    }
+
+    #[test]
+    pub fn today_is_tomorrow_ahead_of_utc() {
+        let noon_utc = datetime!(2025-06-29 12:00 UTC);
+        // UTC+13
+        let tongatapu = tz_or_utc("Pacific/Tongatapu");
+        assert_eq!(date!(2025 - 06 - 30), local_date(noon_utc, tongatapu));
+        assert_eq!(
+            date!(2025 - 06 - 29),
+            local_date(datetime!(2025-06-29 10:59 UTC), tongatapu)
+        );
+        assert_eq!(
+            date!(2025 - 06 - 29),
+            local_date(noon_utc, tz_or_utc("UTC"))
+        );
+    }
+}
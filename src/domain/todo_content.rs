@@ -0,0 +1,79 @@
+use crate::domain::grapheme_count;
+
+const MAX_TODO_CONTENT_LENGTH: usize = 1024;
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum InvalidTodoContentError {
+    #[error("Todo is empty")]
+    Empty,
+    #[error("Todo is too long")]
+    TooLong,
+}
+
+#[derive(Debug, Clone)]
+pub struct TodoContent(String);
+
+impl TodoContent {
+    pub fn parse(s: &str) -> Result<TodoContent, InvalidTodoContentError> {
+        let content = s.trim();
+
+        if content.is_empty() {
+            return Err(InvalidTodoContentError::Empty);
+        }
+
+        let len = grapheme_count(content);
+        if len > MAX_TODO_CONTENT_LENGTH {
+            return Err(InvalidTodoContentError::TooLong);
+        }
+
+        Ok(Self(content.to_string()))
+    }
+}
+
+impl std::fmt::Display for TodoContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl AsRef<str> for TodoContent {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err_eq, assert_ok};
+
+    use crate::domain::todo_content::{InvalidTodoContentError, TodoContent};
+
+    #[test]
+    pub fn empty_todo_content_is_invalid() {
+        assert_err_eq!(TodoContent::parse(""), InvalidTodoContentError::Empty);
+        assert_err_eq!(TodoContent::parse("  \t"), InvalidTodoContentError::Empty);
+    }
+
+    #[test]
+    pub fn todo_content_is_trimmed() {
+        assert_eq!(
+            TodoContent::parse("  buy milk ").unwrap().as_ref(),
+            "buy milk"
+        );
+    }
+
+    #[test]
+    pub fn a_1024_grapheme_long_todo_content_is_valid() {
+        let content = "ё".repeat(1024);
+        assert_ok!(TodoContent::parse(&content));
+    }
+
+    #[test]
+    pub fn a_1025_grapheme_long_todo_content_is_invalid() {
+        let content = "ё".repeat(1025);
+        assert_err_eq!(
+            TodoContent::parse(&content),
+            InvalidTodoContentError::TooLong
+        );
+    }
+}
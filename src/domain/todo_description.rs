@@ -0,0 +1,59 @@
+use crate::domain::grapheme_count;
+
+const MAX_TODO_DESCRIPTION_LENGTH: usize = 10_000;
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum InvalidTodoDescriptionError {
+    #[error("Description is too long, the maximum is {MAX_TODO_DESCRIPTION_LENGTH} characters")]
+    TooLong,
+}
+
+/// Longer Markdown notes on a todo, may be empty
+#[derive(Debug, Clone)]
+pub struct TodoDescription(String);
+
+impl TodoDescription {
+    pub fn parse(s: &str) -> Result<TodoDescription, InvalidTodoDescriptionError> {
+        let description = s.trim();
+
+        let len = grapheme_count(description);
+        if len > MAX_TODO_DESCRIPTION_LENGTH {
+            return Err(InvalidTodoDescriptionError::TooLong);
+        }
+
+        Ok(Self(description.to_string()))
+    }
+}
+
+impl AsRef<str> for TodoDescription {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err_eq, assert_ok};
+
+    use crate::domain::todo_description::{InvalidTodoDescriptionError, TodoDescription};
+
+    #[test]
+    pub fn empty_description_is_valid() {
+        assert_eq!(TodoDescription::parse("  \n").unwrap().as_ref(), "");
+    }
+
+    #[test]
+    pub fn a_10000_grapheme_long_description_is_valid() {
+        let description = "ё".repeat(10_000);
+        assert_ok!(TodoDescription::parse(&description));
+    }
+
+    #[test]
+    pub fn a_10001_grapheme_long_description_is_invalid() {
+        let description = "ё".repeat(10_001);
+        assert_err_eq!(
+            TodoDescription::parse(&description),
+            InvalidTodoDescriptionError::TooLong
+        );
+    }
+}
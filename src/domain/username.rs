@@ -1,17 +1,30 @@
-use icu::segmenter::GraphemeClusterSegmenter;
+use crate::{
+    domain::grapheme_count,
+    i18n::{Locale, Translatable},
+};
 
 const MAX_USER_NAME_LENGTH: usize = 64;
 
 #[derive(Debug, PartialEq, thiserror::Error)]
+#[error("{}", self.message(Locale::En))]
 pub enum InvalidUsernameError {
-    #[error("Empty username")]
     Empty,
-    #[error("Username too long")]
     TooLong,
-    #[error("Username contains forbidden character")]
     ContainsForbiddenCharacter,
 }
 
+impl Translatable for InvalidUsernameError {
+    fn message_key(&self) -> &'static str {
+        match self {
+            InvalidUsernameError::Empty => "error.username.empty",
+            InvalidUsernameError::TooLong => "error.username.too_long",
+            InvalidUsernameError::ContainsForbiddenCharacter => {
+                "error.username.forbidden_character"
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Username(String);
 
@@ -23,11 +36,7 @@ impl Username {
             return Err(InvalidUsernameError::Empty);
         }
 
-        // segment_str returns breakpoints, subtract 1 to get grapheme cluster count
-        let len = GraphemeClusterSegmenter::new()
-            .segment_str(&username)
-            .count()
-            - 1;
+        let len = grapheme_count(&username);
         if len > MAX_USER_NAME_LENGTH {
             return Err(InvalidUsernameError::TooLong);
         }
@@ -58,8 +67,16 @@ impl AsRef<str> for Username {
 #[cfg(test)]
 mod tests {
     use claims::{assert_err_eq, assert_ok};
+    use proptest::prelude::*;
 
-    use crate::domain::username::{InvalidUsernameError, Username};
+    use crate::domain::{
+        strategies::{any_text, username_like},
+        username::{InvalidUsernameError, MAX_USER_NAME_LENGTH, Username},
+    };
+
+    fn is_allowed(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'
+    }
 
     #[test]
     pub fn empty_username_is_invalid() {
@@ -123,4 +140,45 @@ mod tests {
             InvalidUsernameError::ContainsForbiddenCharacter
         );
     }
+
+    proptest! {
+        #[test]
+        fn parsing_any_text_gives_a_stable_answer(s in any_text()) {
+            let first = Username::parse(&s).map(|username| username.to_string());
+            let second = Username::parse(&s).map(|username| username.to_string());
+            prop_assert_eq!(&first, &second);
+
+            let trimmed = s.trim().to_lowercase();
+            match first {
+                Ok(username) => prop_assert_eq!(trimmed, username),
+                Err(InvalidUsernameError::Empty) => prop_assert!(trimmed.is_empty()),
+                Err(InvalidUsernameError::TooLong) => {
+                    prop_assert!(trimmed.chars().count() > MAX_USER_NAME_LENGTH)
+                }
+                Err(InvalidUsernameError::ContainsForbiddenCharacter) => {
+                    prop_assert!(!trimmed.chars().all(is_allowed))
+                }
+            }
+        }
+
+        #[test]
+        fn accepted_usernames_round_trip(s in username_like()) {
+            if let Ok(username) = Username::parse(&s) {
+                let reparsed = Username::parse(username.as_ref()).unwrap();
+                prop_assert_eq!(username.as_ref(), reparsed.as_ref());
+            }
+        }
+
+        #[test]
+        fn usernames_are_accepted_within_the_length_limit(s in username_like()) {
+            // the generated usernames are ascii, one char per grapheme
+            let len = s.trim().chars().count();
+            let result = Username::parse(&s);
+            prop_assert_eq!(
+                (1..=MAX_USER_NAME_LENGTH).contains(&len),
+                result.is_ok(),
+                "{:?} with {} characters gave {:?}", s, len, result
+            );
+        }
+    }
 }
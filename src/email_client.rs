@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::domain::email_address::EmailAddress;
+
+/// Client for a Postmark-style HTTP email delivery API
+#[derive(Debug)]
+pub struct EmailClient {
+    http_client: reqwest::Client,
+    base_url: String,
+    sender: EmailAddress,
+    authorization_token: SecretString,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+}
+
+impl EmailClient {
+    pub fn new(
+        base_url: String,
+        sender: EmailAddress,
+        authorization_token: SecretString,
+        timeout: Duration,
+    ) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("Failed to build email http client");
+
+        Self {
+            http_client,
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+
+    /// Sends an email with both bodies, which the API puts together as a
+    /// `multipart/alternative` message so clients show the one they can
+    pub async fn send_email(
+        &self,
+        recipient: &EmailAddress,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), reqwest::Error> {
+        let url = format!("{}/email", self.base_url.trim_end_matches('/'));
+        let request_body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+        };
+
+        self.http_client
+            .post(&url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use claims::{assert_err, assert_ok};
+    use secrecy::SecretString;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{header, header_exists, method, path},
+    };
+
+    use crate::{domain::email_address::EmailAddress, email_client::EmailClient};
+
+    fn email_client(base_url: String) -> EmailClient {
+        EmailClient::new(
+            base_url,
+            EmailAddress::parse("sender@example.com").unwrap(),
+            SecretString::from("token"),
+            Duration::from_millis(200),
+        )
+    }
+
+    fn recipient() -> EmailAddress {
+        EmailAddress::parse("recipient@example.com").unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_posts_to_the_email_endpoint() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/email"))
+            .and(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client(mock_server.uri())
+            .send_email(&recipient(), "subject", "<p>html</p>", "text")
+            .await;
+        assert_ok!(result);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client(mock_server.uri())
+            .send_email(&recipient(), "subject", "<p>html</p>", "text")
+            .await;
+        assert_err!(result);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client(mock_server.uri())
+            .send_email(&recipient(), "subject", "<p>html</p>", "text")
+            .await;
+        assert_err!(result);
+    }
+}
@@ -0,0 +1,295 @@
+use anyhow::Context;
+use askama::Template;
+use sqlx::{PgPool, postgres::PgExecutor};
+use time::OffsetDateTime;
+
+use crate::{domain::email_address::EmailAddress, urls::UrlBuilder};
+
+/// The emails the app sends. They are queued in the `email_outbox` table as
+/// their template name and params, and rendered by the delivery worker, see
+/// [`crate::worker::email`].
+///
+/// The names and params are stored, so renaming them strands the queued
+/// emails of the old name.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "template", content = "params", rename_all = "snake_case")]
+pub enum Email {
+    ExistingAccount(ExistingAccount),
+    NewDevice(NewDevice),
+    MagicLink(MagicLink),
+    ConfirmEmailChange(ConfirmEmailChange),
+    EmailChangeRequested(EmailChangeRequested),
+    DueDateReminder(DueDateReminder),
+}
+
+/// Someone tried to register with an address that already has an account
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct ExistingAccount {}
+
+/// A login from a device the user hasn't used before
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct NewDevice {
+    pub user_agent_family: String,
+    /// Already formatted, in UTC
+    pub time: String,
+    pub ip_address: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct MagicLink {
+    pub token: String,
+    pub lifetime_minutes: i32,
+}
+
+/// Sent to the new address of an email change
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct ConfirmEmailChange {
+    pub token: String,
+    pub lifetime_hours: i32,
+}
+
+/// Sent to the current address of an email change
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct EmailChangeRequested {
+    pub new_email: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct DueDateReminder {
+    pub todo_content: String,
+}
+
+/// An email ready to be handed to the [`crate::email_client::EmailClient`]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// Both bodies are rendered from the same email, the templates of each one
+/// extending the layout of its format and matching on the email. Links are
+/// only ever built with `urls` in the templates, so they are absolute and
+/// point at the site whichever email it is.
+#[derive(Template)]
+#[template(path = "emails/email.html")]
+struct HtmlTemplate<'a> {
+    email: &'a Email,
+    urls: &'a UrlBuilder,
+}
+
+#[derive(Template)]
+#[template(path = "emails/email.txt")]
+struct TextTemplate<'a> {
+    email: &'a Email,
+    urls: &'a UrlBuilder,
+}
+
+impl Email {
+    pub fn subject(&self) -> &'static str {
+        match self {
+            Email::ExistingAccount(_) => "Someone tried to register with your email address",
+            Email::NewDevice(_) => "New sign-in to your account",
+            Email::MagicLink(_) => "Your login link",
+            Email::ConfirmEmailChange(_) => "Confirm your new email address",
+            Email::EmailChangeRequested(_) => "Your email address is being changed",
+            Email::DueDateReminder(_) => "A todo is due today",
+        }
+    }
+
+    /// Both bodies of the email, with links to the site from `urls`
+    pub fn render(&self, urls: &UrlBuilder) -> Result<RenderedEmail, anyhow::Error> {
+        Ok(RenderedEmail {
+            subject: self.subject().to_string(),
+            html: HtmlTemplate { email: self, urls }
+                .render()
+                .context("Failed to render HTML body")?,
+            text: TextTemplate { email: self, urls }
+                .render()
+                .context("Failed to render text body")?,
+        })
+    }
+
+    /// The template name and params, as they are stored
+    fn to_row(&self) -> Result<(String, serde_json::Value), anyhow::Error> {
+        let serde_json::Value::Object(mut row) =
+            serde_json::to_value(self).context("Failed to serialize email")?
+        else {
+            anyhow::bail!("Email isn't serialized to an object");
+        };
+        let template = match row.remove("template") {
+            Some(serde_json::Value::String(template)) => template,
+            _ => anyhow::bail!("Serialized email has no template name"),
+        };
+        let params = row.remove("params").unwrap_or_default();
+        Ok((template, params))
+    }
+
+    /// The email stored with `template` and `params`
+    pub fn from_row(template: &str, params: serde_json::Value) -> Result<Self, anyhow::Error> {
+        serde_json::from_value(serde_json::json!({ "template": template, "params": params }))
+            .with_context(|| format!("Invalid params for email template {template}"))
+    }
+}
+
+/// Queues `email` to `recipient` in the outbox.
+///
+/// Should run in the same transaction as what the email is about, so a
+/// rolled back change never sends it and a committed one always does.
+pub async fn enqueue(
+    executor: impl PgExecutor<'_>,
+    recipient: &EmailAddress,
+    email: &Email,
+) -> Result<(), anyhow::Error> {
+    let (template, params) = email.to_row()?;
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (recipient, template, params) VALUES ($1, $2, $3)
+        "#,
+        recipient.as_ref(),
+        template,
+        params
+    )
+    .execute(executor)
+    .await
+    .context("Failed to queue email")?;
+
+    Ok(())
+}
+
+/// An email that was given up on after too many failed attempts
+pub struct DeadEmail {
+    pub email_id: i64,
+    pub recipient: String,
+    pub template: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub dead_at: OffsetDateTime,
+}
+
+/// The most recently given up on emails, newest first
+pub async fn dead_letters(db: &PgPool, limit: i64) -> Result<Vec<DeadEmail>, anyhow::Error> {
+    sqlx::query_as!(
+        DeadEmail,
+        r#"
+        SELECT
+            email_id, recipient, template, attempts, last_error, created_at,
+            dead_at AS "dead_at!"
+        FROM email_outbox
+        WHERE dead_at IS NOT NULL
+        ORDER BY dead_at DESC, email_id DESC
+        LIMIT $1
+        "#,
+        limit
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to get dead emails")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        emails::{
+            ConfirmEmailChange, DueDateReminder, Email, EmailChangeRequested, ExistingAccount,
+            MagicLink, NewDevice,
+        },
+        urls::UrlBuilder,
+    };
+
+    fn urls() -> UrlBuilder {
+        UrlBuilder::new("https://todo.example.com/").unwrap()
+    }
+
+    /// One of each email, named like their snapshot
+    fn every_email() -> Vec<Email> {
+        vec![
+            Email::ExistingAccount(ExistingAccount {}),
+            Email::NewDevice(NewDevice {
+                user_agent_family: "Firefox".to_string(),
+                time: "2025-07-31 09:02 UTC".to_string(),
+                ip_address: "203.0.113.7".to_string(),
+            }),
+            Email::MagicLink(MagicLink {
+                token: "abc123".to_string(),
+                lifetime_minutes: 15,
+            }),
+            Email::ConfirmEmailChange(ConfirmEmailChange {
+                token: "def456".to_string(),
+                lifetime_hours: 24,
+            }),
+            Email::EmailChangeRequested(EmailChangeRequested {
+                new_email: "alice@new.com".to_string(),
+            }),
+            Email::DueDateReminder(DueDateReminder {
+                todo_content: "file taxes".to_string(),
+            }),
+        ]
+    }
+
+    /// Values of the `href` attributes in `html`
+    fn hrefs(html: &str) -> Vec<&str> {
+        html.split("href=\"")
+            .skip(1)
+            .map(|rest| &rest[..rest.find('"').unwrap()])
+            .collect()
+    }
+
+    #[test]
+    pub fn emails_round_trip_through_their_row() {
+        for email in every_email() {
+            let (template, params) = email.to_row().unwrap();
+            assert_eq!(email, Email::from_row(&template, params).unwrap());
+        }
+        let (template, params) = every_email()[2].to_row().unwrap();
+        assert_eq!("magic_link", template);
+        assert_eq!(15, params["lifetime_minutes"]);
+    }
+
+    #[test]
+    pub fn unknown_templates_are_rejected() {
+        assert!(Email::from_row("password_reset", serde_json::json!({})).is_err());
+        assert!(Email::from_row("magic_link", serde_json::json!({ "token": 1 })).is_err());
+    }
+
+    #[test]
+    pub fn every_link_is_absolute_and_points_at_the_site() {
+        for email in every_email() {
+            let rendered = email.render(&urls()).unwrap();
+            let hrefs = hrefs(&rendered.html);
+            assert!(!hrefs.is_empty());
+            for href in hrefs {
+                let rest = href
+                    .strip_prefix("https://todo.example.com/")
+                    .unwrap_or_else(|| panic!("{href} in {} isn't on the site", email.subject()));
+                assert!(!rest.starts_with('/'), "{href} has a doubled slash");
+                // the same links are in the text body
+                assert!(rendered.text.contains(href), "{href} is only in the HTML");
+            }
+        }
+    }
+
+    #[test]
+    pub fn user_content_is_only_escaped_in_the_html_body() {
+        let email = Email::DueDateReminder(DueDateReminder {
+            todo_content: "<b>taxes</b> & more".to_string(),
+        });
+        let rendered = email.render(&urls()).unwrap();
+        assert!(
+            rendered
+                .html
+                .contains("&#60;b&#62;taxes&#60;/b&#62; &#38; more")
+        );
+        assert!(rendered.text.contains("\"<b>taxes</b> & more\""));
+    }
+
+    #[test]
+    pub fn rendered_emails() {
+        for email in every_email() {
+            let (template, _) = email.to_row().unwrap();
+            let rendered = email.render(&urls()).unwrap();
+            insta::assert_snapshot!(format!("{template}_html"), rendered.html);
+            insta::assert_snapshot!(format!("{template}_text"), rendered.text);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::Context;
+use axum::{extract::Request, middleware::Next, response::Response};
+use secrecy::ExposeSecret;
+use sentry::{ClientInitGuard, ClientOptions, types::Dsn};
+
+use crate::{
+    config::ApplicationSettings,
+    telemetry::{self, RequestId},
+};
+
+/// Starts reporting to Sentry if `SENTRY_DSN` is set, until the guard is
+/// dropped. Panics are reported from then on, unexpected errors once they
+/// are passed to [`report`].
+pub fn init(settings: &ApplicationSettings) -> Result<Option<ClientInitGuard>, anyhow::Error> {
+    let Some(dsn) = &settings.sentry_dsn else {
+        return Ok(None);
+    };
+    let dsn: Dsn = dsn.expose_secret().parse().context("Invalid Sentry DSN")?;
+
+    Ok(Some(sentry::init((
+        dsn,
+        ClientOptions {
+            environment: Some(settings.app_env.as_str().into()),
+            release: sentry::release_name!(),
+            // where the error was reported from, anyhow only has a backtrace
+            // with RUST_BACKTRACE set
+            attach_stacktrace: true,
+            ..Default::default()
+        },
+    ))))
+}
+
+/// Reports an unexpected error, does nothing unless [`init`] started the
+/// client
+pub fn report(error: &anyhow::Error) {
+    sentry::integrations::anyhow::capture_anyhow(error);
+}
+
+/// Tags everything reported while handling the request with its id and
+/// route. Only the route template is sent, the path may hold ids and the
+/// rest of the request personal data.
+pub async fn tag_request(request: Request, next: Next) -> Response {
+    let request_id = request.extensions().get::<RequestId>().copied();
+    let route = telemetry::route(&request).to_string();
+    sentry::configure_scope(|scope| {
+        if let Some(RequestId(request_id)) = request_id {
+            scope.set_tag("request_id", request_id);
+        }
+        scope.set_tag("route", route);
+    });
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use axum::{Router, body::Body, extract::Request, middleware, routing::get};
+    use sentry::integrations::tower::NewSentryLayer;
+    use tower::ServiceExt;
+
+    use crate::{api_error::ApiError, error_reporting::tag_request, telemetry};
+
+    #[test]
+    pub fn internal_api_errors_are_reported_with_the_route() {
+        let app = Router::new()
+            .route(
+                "/todos/{todo_id}",
+                get(|| async { ApiError::internal(&anyhow!("Database is down")) }),
+            )
+            .layer(middleware::from_fn(tag_request))
+            .layer(NewSentryLayer::new_from_top())
+            .layer(middleware::from_fn(telemetry::assign_request_id));
+        let request = Request::get(format!("/todos/{}", uuid::Uuid::new_v4()))
+            .body(Body::empty())
+            .unwrap();
+
+        // the test client is bound to this thread, so is the runtime
+        let events = sentry::test::with_captured_events(|| {
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(app.oneshot(request))
+                .unwrap();
+        });
+
+        assert_eq!(1, events.len());
+        let event = &events[0];
+        assert_eq!("/todos/{todo_id}", event.tags["route"]);
+        assert!(event.tags.contains_key("request_id"));
+        assert_eq!(
+            Some("Database is down"),
+            event.exception.values[0].value.as_deref()
+        );
+    }
+}
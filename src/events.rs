@@ -0,0 +1,181 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, ready},
+};
+
+use tokio::sync::broadcast;
+use tokio_stream::{Stream, wrappers::BroadcastStream};
+use uuid::Uuid;
+
+/// Most event streams a single user can have open at once, e.g. browser tabs
+const MAX_SUBSCRIBERS_PER_USER: usize = 8;
+/// Events buffered per user before slow subscribers start missing them
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TodoEventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl std::fmt::Display for TodoEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TodoEventKind::Created => write!(f, "created"),
+            TodoEventKind::Updated => write!(f, "updated"),
+            TodoEventKind::Deleted => write!(f, "deleted"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TodoEvent {
+    pub kind: TodoEventKind,
+    pub list_id: Uuid,
+    pub todo_id: Uuid,
+    /// Rendered todo row, empty for deleted todos
+    pub fragment: String,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Too many open event streams")]
+pub struct TooManySubscribers;
+
+/// Per-user broadcast channels for live todo updates.
+///
+/// A user's channel is created by their first subscriber and removed when
+/// their last subscriber goes away.
+#[derive(Debug, Default)]
+pub struct EventRegistry {
+    channels: Mutex<HashMap<Uuid, broadcast::Sender<TodoEvent>>>,
+}
+
+impl EventRegistry {
+    pub fn subscribe(self: &Arc<Self>, user_id: Uuid) -> Result<Subscription, TooManySubscribers> {
+        let mut channels = self.channels.lock().unwrap();
+        let sender = channels
+            .entry(user_id)
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0);
+
+        if sender.receiver_count() >= MAX_SUBSCRIBERS_PER_USER {
+            return Err(TooManySubscribers);
+        }
+
+        Ok(Subscription {
+            registry: self.clone(),
+            user_id,
+            receiver: BroadcastStream::new(sender.subscribe()),
+        })
+    }
+
+    pub fn has_subscribers(&self, user_id: Uuid) -> bool {
+        self.channels.lock().unwrap().contains_key(&user_id)
+    }
+
+    pub fn publish(&self, user_id: Uuid, event: TodoEvent) {
+        if let Some(sender) = self.channels.lock().unwrap().get(&user_id) {
+            // no receivers just means every tab closed since the check
+            let _ = sender.send(event);
+        }
+    }
+}
+
+/// Stream of a user's todo events, which unregisters itself when dropped
+#[derive(Debug)]
+pub struct Subscription {
+    registry: Arc<EventRegistry>,
+    user_id: Uuid,
+    receiver: BroadcastStream<TodoEvent>,
+}
+
+impl Stream for Subscription {
+    type Item = TodoEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.receiver).poll_next(cx)) {
+                Some(Ok(event)) => return Poll::Ready(Some(event)),
+                // a lagging subscriber skips the events it missed
+                Some(Err(_)) => continue,
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut channels = self.registry.channels.lock().unwrap();
+        // our own receiver is still alive at this point
+        if channels
+            .get(&self.user_id)
+            .is_some_and(|sender| sender.receiver_count() <= 1)
+        {
+            channels.remove(&self.user_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use claims::{assert_err, assert_ok};
+    use tokio_stream::StreamExt;
+    use uuid::Uuid;
+
+    use crate::events::{EventRegistry, MAX_SUBSCRIBERS_PER_USER, TodoEvent, TodoEventKind};
+
+    fn event() -> TodoEvent {
+        TodoEvent {
+            kind: TodoEventKind::Created,
+            list_id: Uuid::new_v4(),
+            todo_id: Uuid::new_v4(),
+            fragment: "<tr></tr>".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn events_are_only_delivered_to_the_target_user() {
+        let registry = Arc::new(EventRegistry::default());
+        let alice = Uuid::new_v4();
+        let bob = Uuid::new_v4();
+        let mut alice_subscription = registry.subscribe(alice).unwrap();
+        let _bob_subscription = registry.subscribe(bob).unwrap();
+
+        let sent = event();
+        registry.publish(alice, sent.clone());
+
+        let received = alice_subscription.next().await.unwrap();
+        assert_eq!(sent.todo_id, received.todo_id);
+    }
+
+    #[test]
+    fn subscribers_per_user_are_capped() {
+        let registry = Arc::new(EventRegistry::default());
+        let user_id = Uuid::new_v4();
+        let subscriptions: Vec<_> = (0..MAX_SUBSCRIBERS_PER_USER)
+            .map(|_| registry.subscribe(user_id).unwrap())
+            .collect();
+
+        assert_err!(registry.subscribe(user_id));
+        drop(subscriptions);
+        assert_ok!(registry.subscribe(user_id));
+    }
+
+    #[test]
+    fn channel_is_removed_when_last_subscriber_drops() {
+        let registry = Arc::new(EventRegistry::default());
+        let user_id = Uuid::new_v4();
+        let first = registry.subscribe(user_id).unwrap();
+        let second = registry.subscribe(user_id).unwrap();
+
+        drop(first);
+        assert!(registry.has_subscribers(user_id));
+        drop(second);
+        assert!(!registry.has_subscribers(user_id));
+    }
+}
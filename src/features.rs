@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use axum::{
+    Extension,
+    extract::{OriginalUri, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+
+use crate::{api_error, app::ApiContext, config::ApplicationSettings};
+
+/// Parts of the site that can be turned off per environment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Feature {
+    Registration,
+    MagicLinks,
+    Passkeys,
+    Webhooks,
+}
+
+/// Which [`Feature`]s are on, from the `FEATURE_*` settings. Also passed to
+/// the templates linking to them, so they hide what is turned off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Features {
+    pub registration: bool,
+    pub magic_links: bool,
+    pub passkeys: bool,
+    pub webhooks: bool,
+}
+
+/// Everything on, like the settings' defaults
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            registration: true,
+            magic_links: true,
+            passkeys: true,
+            webhooks: true,
+        }
+    }
+}
+
+impl Features {
+    pub fn from_settings(settings: &ApplicationSettings) -> Self {
+        Self {
+            registration: settings.feature_registration,
+            magic_links: settings.feature_magic_links,
+            passkeys: settings.feature_passkeys,
+            webhooks: settings.feature_webhooks,
+        }
+    }
+
+    pub fn enabled(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::Registration => self.registration,
+            Feature::MagicLinks => self.magic_links,
+            Feature::Passkeys => self.passkeys,
+            Feature::Webhooks => self.webhooks,
+        }
+    }
+}
+
+/// Answers the routes of a turned off feature with a 404, as if they didn't
+/// exist, layered with
+/// `middleware::from_fn_with_state(Feature::Registration, require_feature)`.
+/// The routes stay registered, the flag is only read when a request comes
+/// in. Needs the [`ApiContext`] as a request extension, like the rate
+/// limits.
+pub async fn require_feature(
+    State(feature): State<Feature>,
+    Extension(api_context): Extension<Arc<ApiContext>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if api_context.features.enabled(feature) {
+        return next.run(request).await;
+    }
+
+    // routes nested under `/api` only see the rest of the path
+    let under_api = request
+        .extensions()
+        .get::<OriginalUri>()
+        .is_some_and(|uri| uri.path().starts_with("/api/"));
+    if under_api {
+        api_error::route_not_found().await.into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
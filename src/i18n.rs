@@ -0,0 +1,231 @@
+//! Translations of the pages and of the messages people see, looked up by
+//! key in `locales/<code>.txt`. Keys missing from a locale fall back to
+//! English, so a page never shows a bare key.
+
+use std::{collections::HashMap, convert::Infallible, sync::Arc, sync::LazyLock};
+
+use axum::extract::FromRequestParts;
+use http::{HeaderMap, header, request::Parts};
+
+use crate::{app::ApiContext, auth::AuthSession};
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "locale", rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::De];
+
+    /// The language tag, e.g. `de`
+    pub fn code(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::De => "de",
+        }
+    }
+
+    /// The name of the language in that language, for picking one
+    pub fn name(self) -> &'static str {
+        match self {
+            Locale::En => "English",
+            Locale::De => "Deutsch",
+        }
+    }
+
+    /// A language tag like `de-AT`, going by its language only
+    pub fn parse(tag: &str) -> Option<Self> {
+        let language = tag.trim().split(['-', '_']).next()?;
+        Locale::ALL
+            .into_iter()
+            .find(|locale| locale.code().eq_ignore_ascii_case(language))
+    }
+
+    /// The supported locale `Accept-Language` ranks highest, if any
+    pub fn from_accept_language(headers: &HeaderMap) -> Option<Self> {
+        let accept_language = headers.get(header::ACCEPT_LANGUAGE)?.to_str().ok()?;
+        let mut best: Option<(Locale, f32)> = None;
+        for range in accept_language.split(',') {
+            let mut params = range.split(';');
+            let Some(locale) = params.next().and_then(Locale::parse) else {
+                continue;
+            };
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            // on a tie the range listed first wins
+            if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+                best = Some((locale, quality));
+            }
+        }
+        best.map(|(locale, _)| locale)
+    }
+
+    /// The locale the user picked in their settings, or else the one their
+    /// browser asks for, or else English
+    pub fn negotiate(preferred: Option<Locale>, headers: &HeaderMap) -> Self {
+        preferred
+            .or_else(|| Locale::from_accept_language(headers))
+            .unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for Locale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// The locale of the request, see [`Locale::negotiate`]
+impl FromRequestParts<Arc<ApiContext>> for Locale {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<ApiContext>,
+    ) -> Result<Self, Self::Rejection> {
+        let user = AuthSession::from_request_parts(parts, state)
+            .await
+            .ok()
+            .and_then(|auth_session| auth_session.user);
+        let preferred = match user {
+            Some(user) => match state.preferences.get(&state.db, user.user_id()).await {
+                Ok(preferences) => preferences.locale,
+                Err(e) => {
+                    tracing::warn!(error = ?e, "Failed to get the locale of the user");
+                    None
+                }
+            },
+            None => None,
+        };
+        Ok(Locale::negotiate(preferred, &parts.headers))
+    }
+}
+
+type Translations = HashMap<&'static str, &'static str>;
+
+static EN: LazyLock<Translations> = LazyLock::new(|| parse(include_str!("../locales/en.txt")));
+static DE: LazyLock<Translations> = LazyLock::new(|| parse(include_str!("../locales/de.txt")));
+
+/// `key = value` lines, skipping blank ones and `#` comments
+fn parse(file: &'static str) -> Translations {
+    file.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect()
+}
+
+fn translations(locale: Locale) -> &'static Translations {
+    match locale {
+        Locale::En => &EN,
+        Locale::De => &DE,
+    }
+}
+
+/// The text of `key` in `locale`, in English if it isn't translated, or the
+/// key itself if it doesn't exist at all
+pub fn translate(locale: Locale, key: &str) -> &str {
+    translations(locale)
+        .get(key)
+        .or_else(|| EN.get(key))
+        .copied()
+        .unwrap_or_else(|| {
+            tracing::warn!(key, "Missing translation");
+            key
+        })
+}
+
+/// Errors whose message is shown to people, in their locale
+pub trait Translatable {
+    fn message_key(&self) -> &'static str;
+
+    fn message(&self, locale: Locale) -> &'static str {
+        translate(locale, self.message_key())
+    }
+}
+
+/// `{{ "login.title"|t(locale) }}` in the templates, which have the locale of
+/// the request as a field
+pub mod filters {
+    use std::fmt::Display;
+
+    use super::Locale;
+
+    pub fn t<'a>(key: &'a str, _: &dyn askama::Values, locale: &Locale) -> askama::Result<&'a str> {
+        Ok(super::translate(*locale, key))
+    }
+
+    /// Puts `value` in place of the `{}` of a translation, e.g.
+    /// `{{ "todos.page"|t(locale)|fill(page) }}`
+    pub fn fill(text: &str, _: &dyn askama::Values, value: impl Display) -> askama::Result<String> {
+        Ok(text.replacen("{}", &value.to_string(), 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use http::{HeaderMap, HeaderValue, header};
+
+    use super::{DE, EN, Locale, translate};
+
+    fn accepting(accept_language: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT_LANGUAGE,
+            HeaderValue::from_str(accept_language).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn every_locale_has_every_key() {
+        let english: BTreeSet<_> = EN.keys().collect();
+        let german: BTreeSet<_> = DE.keys().collect();
+        assert_eq!(english, german);
+    }
+
+    #[test]
+    fn accept_language_picks_the_best_supported_locale() {
+        for (accept_language, expected) in [
+            ("de", Some(Locale::De)),
+            ("de-AT,de;q=0.9", Some(Locale::De)),
+            ("fr, de;q=0.8, en;q=0.5", Some(Locale::De)),
+            ("en-US,en;q=0.9,de;q=0.8", Some(Locale::En)),
+            ("de;q=0.2, en", Some(Locale::En)),
+            ("de;q=0", None),
+            ("fr, *", None),
+            ("", None),
+        ] {
+            assert_eq!(
+                expected,
+                Locale::from_accept_language(&accepting(accept_language)),
+                "{accept_language}"
+            );
+        }
+    }
+
+    #[test]
+    fn the_users_choice_beats_the_browser() {
+        let headers = accepting("de");
+        assert_eq!(Locale::En, Locale::negotiate(Some(Locale::En), &headers));
+        assert_eq!(Locale::De, Locale::negotiate(None, &headers));
+        assert_eq!(Locale::En, Locale::negotiate(None, &HeaderMap::new()));
+    }
+
+    #[test]
+    fn missing_keys_fall_back() {
+        assert_eq!("Registrieren", translate(Locale::De, "register.title"));
+        assert_eq!("no.such.key", translate(Locale::De, "no.such.key"));
+    }
+}
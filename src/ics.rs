@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use time::{Date, OffsetDateTime, UtcOffset, macros::format_description};
+
+/// Longest line allowed by RFC 5545, in octets without the line break
+const MAX_LINE_OCTETS: usize = 75;
+
+/// An all-day event, as calendar apps show those next to the user's own
+/// events, while many of them ignore tasks
+pub struct Event<'a> {
+    /// Unique across calendars and stable, so apps update the event in place
+    pub uid: String,
+    /// When the event last changed
+    pub stamp: OffsetDateTime,
+    pub date: Date,
+    pub summary: &'a str,
+    pub description: Option<&'a str>,
+    pub url: Option<String>,
+    /// 1 is the highest, 9 the lowest
+    pub priority: Option<u8>,
+}
+
+/// Writes an iCalendar (RFC 5545) `VCALENDAR` with `VEVENT` entries
+pub struct Calendar {
+    output: String,
+}
+
+impl Calendar {
+    /// `refresh_interval` is how often subscribed apps are asked to reload it
+    pub fn new(name: &str, refresh_interval: Duration) -> Self {
+        let mut calendar = Self {
+            output: String::new(),
+        };
+        let refresh_interval = format!("PT{}S", refresh_interval.as_secs());
+        calendar.line("BEGIN:VCALENDAR");
+        calendar.line("VERSION:2.0");
+        calendar.line("PRODID:-//new-site//todo//EN");
+        calendar.line("CALSCALE:GREGORIAN");
+        calendar.line("METHOD:PUBLISH");
+        calendar.line(&format!("NAME:{}", escape_text(name)));
+        calendar.line(&format!("X-WR-CALNAME:{}", escape_text(name)));
+        calendar.line(&format!(
+            "REFRESH-INTERVAL;VALUE=DURATION:{refresh_interval}"
+        ));
+        // the same for apps that predate RFC 7986
+        calendar.line(&format!("X-PUBLISHED-TTL:{refresh_interval}"));
+        calendar
+    }
+
+    pub fn add_event(&mut self, event: &Event) {
+        let end = event.date.next_day().unwrap_or(event.date);
+        self.line("BEGIN:VEVENT");
+        self.line(&format!("UID:{}", escape_text(&event.uid)));
+        self.line(&format!("DTSTAMP:{}", format_utc(event.stamp)));
+        self.line(&format!("DTSTART;VALUE=DATE:{}", format_date(event.date)));
+        self.line(&format!("DTEND;VALUE=DATE:{}", format_date(end)));
+        self.line(&format!("SUMMARY:{}", escape_text(event.summary)));
+        if let Some(description) = event.description {
+            self.line(&format!("DESCRIPTION:{}", escape_text(description)));
+        }
+        if let Some(url) = &event.url {
+            self.line(&format!("URL:{url}"));
+        }
+        if let Some(priority) = event.priority {
+            self.line(&format!("PRIORITY:{priority}"));
+        }
+        // all-day events shouldn't block the day in free/busy lookups
+        self.line("TRANSP:TRANSPARENT");
+        self.line("END:VEVENT");
+    }
+
+    pub fn finish(mut self) -> String {
+        self.line("END:VCALENDAR");
+        self.output
+    }
+
+    /// Adds a content line, folded so no line is longer than 75 octets.
+    /// Continuation lines start with a space, which readers remove again.
+    fn line(&mut self, line: &str) {
+        let mut rest = line;
+        let mut limit = MAX_LINE_OCTETS;
+        loop {
+            if rest.len() <= limit {
+                self.output.push_str(rest);
+                self.output.push_str("\r\n");
+                return;
+            }
+            // never split a multi-byte character
+            let split = rest.floor_char_boundary(limit);
+            self.output.push_str(&rest[..split]);
+            self.output.push_str("\r\n ");
+            rest = &rest[split..];
+            limit = MAX_LINE_OCTETS - 1;
+        }
+    }
+}
+
+/// Escapes a TEXT value, in which commas and semicolons separate values
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn format_date(date: Date) -> String {
+    date.format(format_description!("[year][month][day]"))
+        .expect("Dates can always be formatted")
+}
+
+fn format_utc(timestamp: OffsetDateTime) -> String {
+    timestamp
+        .to_offset(UtcOffset::UTC)
+        .format(format_description!(
+            "[year][month][day]T[hour][minute][second]Z"
+        ))
+        .expect("Timestamps can always be formatted")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use time::macros::{date, datetime};
+
+    use crate::ics::{Calendar, Event, escape_text};
+
+    fn event(summary: &str) -> Event<'_> {
+        Event {
+            uid: "todo-1@example.com".to_string(),
+            stamp: datetime!(2025-07-16 09:15:30.5 +02:00),
+            date: date!(2025 - 07 - 31),
+            summary,
+            description: None,
+            url: None,
+            priority: None,
+        }
+    }
+
+    #[test]
+    pub fn text_is_escaped() {
+        assert_eq!(
+            r"milk\, eggs\; bread \\ butter\nthen cook",
+            escape_text("milk, eggs; bread \\ butter\r\nthen cook")
+        );
+    }
+
+    #[test]
+    pub fn event_has_all_day_dates_and_utc_stamp() {
+        let mut calendar = Calendar::new("Todos", Duration::from_secs(3600));
+        calendar.add_event(&event("buy milk"));
+        let output = calendar.finish();
+
+        assert!(output.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(output.ends_with("END:VCALENDAR\r\n"));
+        assert!(output.contains("REFRESH-INTERVAL;VALUE=DURATION:PT3600S\r\n"));
+        assert!(output.contains("DTSTAMP:20250716T071530Z\r\n"));
+        assert!(output.contains("DTSTART;VALUE=DATE:20250731\r\n"));
+        assert!(output.contains("DTEND;VALUE=DATE:20250801\r\n"));
+        assert!(output.contains("SUMMARY:buy milk\r\n"));
+    }
+
+    #[test]
+    pub fn long_lines_are_folded_between_characters() {
+        let summary = "ä".repeat(100);
+        let mut calendar = Calendar::new("Todos", Duration::from_secs(3600));
+        calendar.add_event(&event(&summary));
+        let output = calendar.finish();
+
+        for line in output.split("\r\n") {
+            assert!(line.len() <= 75, "{line} is too long");
+        }
+        let unfolded = output.replace("\r\n ", "");
+        assert!(unfolded.contains(&format!("SUMMARY:{summary}\r\n")));
+    }
+}
@@ -0,0 +1,241 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{
+    body::Body,
+    response::{IntoResponse, Response},
+};
+use http::{HeaderMap, StatusCode};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, types::Json};
+use uuid::Uuid;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+/// Set on responses that were replayed instead of processed again
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+const MAX_KEY_LENGTH: usize = 255;
+
+/// How often a retry checks whether the first request has finished
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Polls before a retry gives up waiting for the first request
+const MAX_POLLS: usize = 20;
+
+#[derive(thiserror::Error, Debug)]
+pub enum IdempotencyError {
+    #[error("Idempotency key must be between 1 and {MAX_KEY_LENGTH} visible characters")]
+    InvalidKey,
+    #[error("Idempotency key was already used for a different request")]
+    KeyReused,
+    #[error("A request with this idempotency key is still being processed")]
+    InProgress,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for IdempotencyError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match self {
+            IdempotencyError::InvalidKey => StatusCode::BAD_REQUEST,
+            IdempotencyError::KeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            IdempotencyError::InProgress => StatusCode::CONFLICT,
+            IdempotencyError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    /// `None` if the request didn't send a key
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, IdempotencyError> {
+        let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+            return Ok(None);
+        };
+        // visible ASCII only, so keys are never ambiguous once stored
+        let key = value.to_str().map_err(|_| IdempotencyError::InvalidKey)?;
+        if key.is_empty()
+            || key.len() > MAX_KEY_LENGTH
+            || !key.bytes().all(|b| b.is_ascii_graphic())
+        {
+            return Err(IdempotencyError::InvalidKey);
+        }
+        Ok(Some(Self(key.to_string())))
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Fingerprint of a request, compared when a key is used again
+pub fn request_hash(request: &impl serde::Serialize) -> Result<String, anyhow::Error> {
+    let serialized = serde_json::to_vec(request).context("Failed to serialize request")?;
+    Ok(hex::encode(Sha256::digest(serialized)))
+}
+
+pub enum NextAction {
+    /// First time the key is seen, the request should be processed and its
+    /// response passed to [`save_response`]
+    Process,
+    /// The response the request got the first time
+    Replay(Response),
+}
+
+/// Claims the key for this request, or finds the response it already got.
+///
+/// The claim is an insert guarded by the primary key, so of two concurrent
+/// requests with the same key only one gets to process it. The other waits
+/// briefly for the response and gives up with a conflict.
+pub async fn try_begin(
+    db: &PgPool,
+    user_id: Uuid,
+    key: &IdempotencyKey,
+    request_hash: &str,
+) -> Result<NextAction, IdempotencyError> {
+    for _ in 0..MAX_POLLS {
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO idempotency (user_id, idempotency_key, request_hash)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+            user_id,
+            key.as_ref(),
+            request_hash
+        )
+        .execute(db)
+        .await
+        .context("Failed to claim idempotency key")?;
+        if inserted.rows_affected() > 0 {
+            return Ok(NextAction::Process);
+        }
+
+        let saved = sqlx::query!(
+            r#"
+            SELECT
+                request_hash,
+                response_status_code,
+                response_headers AS "response_headers: Json<Vec<(String, String)>>",
+                response_body
+            FROM idempotency
+            WHERE user_id = $1 AND idempotency_key = $2
+            "#,
+            user_id,
+            key.as_ref()
+        )
+        .fetch_optional(db)
+        .await
+        .context("Failed to get saved response")?;
+
+        // gone if the first request failed in between, it can be claimed again
+        if let Some(saved) = saved {
+            if saved.request_hash != request_hash {
+                return Err(IdempotencyError::KeyReused);
+            }
+            if let (Some(status_code), Some(Json(headers)), Some(body)) = (
+                saved.response_status_code,
+                saved.response_headers,
+                saved.response_body,
+            ) {
+                return Ok(NextAction::Replay(saved_response(
+                    status_code,
+                    headers,
+                    body,
+                )?));
+            }
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    Err(IdempotencyError::InProgress)
+}
+
+fn saved_response(
+    status_code: i16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+) -> Result<Response, anyhow::Error> {
+    let mut response = Response::builder().status(status_code as u16);
+    for (name, value) in headers {
+        response = response.header(name, value);
+    }
+    response
+        .header(REPLAYED_HEADER, "true")
+        .body(Body::from(body))
+        .context("Failed to build saved response")
+}
+
+/// Stores the response for replays and passes it on.
+///
+/// Server errors aren't stored, the key is released instead so the client
+/// can retry.
+pub async fn save_response(
+    db: &PgPool,
+    user_id: Uuid,
+    key: &IdempotencyKey,
+    response: Response,
+) -> Result<Response, anyhow::Error> {
+    if response.status().is_server_error() {
+        sqlx::query!(
+            r#"
+            DELETE FROM idempotency WHERE user_id = $1 AND idempotency_key = $2
+            "#,
+            user_id,
+            key.as_ref()
+        )
+        .execute(db)
+        .await
+        .context("Failed to release idempotency key")?;
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .context("Failed to read response body")?;
+    // every header the handlers set is valid UTF-8
+    let headers: Vec<(String, String)> = parts
+        .headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    sqlx::query!(
+        r#"
+        UPDATE idempotency
+        SET response_status_code = $3, response_headers = $4, response_body = $5
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        key.as_ref(),
+        parts.status.as_u16() as i16,
+        Json(&headers) as _,
+        body.as_ref()
+    )
+    .execute(db)
+    .await
+    .context("Failed to save response")?;
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// Removes keys old enough that clients won't retry them anymore, returning
+/// how many were removed
+pub async fn delete_expired_keys(db: &PgPool, max_age: Duration) -> Result<u64, anyhow::Error> {
+    let query_result = sqlx::query!(
+        r#"
+        DELETE FROM idempotency WHERE created_at < NOW() - make_interval(secs => $1)
+        "#,
+        max_age.as_secs_f64()
+    )
+    .execute(db)
+    .await
+    .context("Failed to delete expired idempotency keys")?;
+
+    Ok(query_result.rows_affected())
+}
@@ -1,5 +1,43 @@
+pub mod api_error;
 pub mod app;
+pub mod assets;
+pub mod audit;
 pub mod auth;
+pub mod authz;
+pub mod avatar;
+pub mod captcha;
+pub mod catch_panic;
+pub mod client_ip;
 pub mod config;
+pub mod cors;
 pub mod domain;
+pub mod email_client;
+pub mod emails;
+pub mod error_reporting;
+pub mod events;
+pub mod features;
+pub mod i18n;
+pub mod ics;
+pub mod idempotency;
+pub mod maintenance;
+pub mod markdown;
+pub mod negotiate;
+pub mod notifications;
+pub mod page;
+pub mod preferences;
+pub mod preflight;
+#[cfg(feature = "test-util")]
+pub mod query_count;
+pub mod rate_limit;
+pub mod redis;
 pub mod routes;
+pub mod sanitize;
+pub mod seed;
+pub mod storage;
+pub mod telemetry;
+pub mod theme;
+pub mod toast;
+pub mod urls;
+pub mod validation;
+pub mod webhook;
+pub mod worker;
@@ -1,29 +1,122 @@
 use clap::Parser;
-use site::{app::Application, config::Config};
-use tracing_subscriber::layer::SubscriberExt;
+use secrecy::ExposeSecret;
+use site::{
+    app::{Application, connect_db},
+    auth::{Hasher, Role, create_user},
+    config::{Command, Config, MaintenanceCommand},
+    error_reporting,
+    maintenance::{self, Maintenance},
+    preflight, redis, seed,
+    telemetry::{self, TraceExport},
+};
+use time::OffsetDateTime;
 use tracing_subscriber::util::SubscriberInitExt;
 
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
 
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                // axum logs rejections from built-in extractors with the `axum::rejection`
-                // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
-                format!(
-                    "{}=debug,tower_http=debug,axum::rejection=trace",
-                    env!("CARGO_CRATE_NAME")
-                )
-                .into()
-            }),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let mut config = Config::parse();
 
-    let config = Config::parse();
+    let trace_export = TraceExport::from_settings(&config.application_settings)
+        .expect("Invalid trace export settings");
+    telemetry::subscriber(
+        config.application_settings.app_env,
+        &config.application_settings.log_filter,
+        std::io::stdout,
+        trace_export.as_ref().map(TraceExport::layer),
+    )
+    .expect("Invalid log filter")
+    .init();
+    // reports until dropped at the end of main
+    let _error_reporting = error_reporting::init(&config.application_settings)
+        .expect("Invalid error reporting settings");
 
-    let app = Application::build(config).await;
+    match config.command.take() {
+        Some(Command::CreateUser(args)) => {
+            let db = connect_db(&config).await;
+            preflight::migrations(&db, config.application_settings.run_migrations)
+                .await
+                .expect("Failed to bring the schema up to date");
+            let hasher = Hasher::from_settings(&config.application_settings)
+                .expect("Invalid password hashing settings");
+            let role = if args.admin { Role::Admin } else { Role::User };
+            let user_id = create_user(
+                &db,
+                &hasher,
+                &args.email,
+                &args.username,
+                args.password.expose_secret(),
+                role,
+            )
+            .await
+            .expect("Failed to create user");
+            tracing::info!(%user_id, %role, "Created user {}", args.username);
+            return;
+        }
+        Some(Command::Maintenance(command)) => {
+            let redis = redis::connect(&config)
+                .await
+                .expect("Failed to connect to Redis");
+            let key = &config.application_settings.maintenance_redis_key;
+            match command {
+                MaintenanceCommand::On(args) => {
+                    let maintenance = Maintenance {
+                        message: args.message,
+                        until: args.eta_minutes.map(|minutes| {
+                            OffsetDateTime::now_utc() + time::Duration::minutes(minutes)
+                        }),
+                        admin_bypass: args.admin_bypass,
+                    };
+                    maintenance::start(&redis, key, &maintenance)
+                        .await
+                        .expect("Failed to start maintenance");
+                    tracing::info!("Started maintenance");
+                }
+                MaintenanceCommand::Off => {
+                    maintenance::end(&redis, key)
+                        .await
+                        .expect("Failed to end maintenance");
+                    tracing::info!("Ended maintenance");
+                }
+            }
+            return;
+        }
+        Some(Command::Seed(args)) => {
+            let app_env = config.application_settings.app_env;
+            seed::check_app_env(app_env).expect("Failed to seed the database");
+            let db = connect_db(&config).await;
+            preflight::migrations(&db, config.application_settings.run_migrations)
+                .await
+                .expect("Failed to bring the schema up to date");
+            let hasher = Hasher::from_settings(&config.application_settings)
+                .expect("Invalid password hashing settings");
+            let summary = seed::seed(&db, &hasher, app_env, &args)
+                .await
+                .expect("Failed to seed the database");
+            tracing::info!(
+                users_created = summary.users_created,
+                users_skipped = summary.users_skipped,
+                todos_created = summary.todos_created,
+                "Seeded the database, every seeded user's password is {:?}",
+                seed::SEED_PASSWORD
+            );
+            return;
+        }
+        None => {}
+    }
+
+    let app = match Application::build(config).await {
+        Ok(app) => app,
+        Err(report) => {
+            // read by whoever deploys, so not as a log line
+            eprintln!("{report}");
+            std::process::exit(1);
+        }
+    };
     app.run().await;
+
+    if let Some(trace_export) = trace_export {
+        trace_export.shutdown();
+    }
 }
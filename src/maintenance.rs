@@ -0,0 +1,206 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Extension,
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use fred::interfaces::KeysInterface;
+use http::{StatusCode, header};
+use time::{OffsetDateTime, macros::format_description};
+
+use crate::{
+    api_error::ApiError, app::ApiContext, auth::AuthSession, page::PageContext, redis::Redis,
+};
+
+/// How long clients are told to wait when no end was announced
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Reachable during maintenance, so load balancers keep the instance and an
+/// admin can log in and reopen the site
+const EXEMPT_PATHS: [&str; 5] = [
+    "/health_check",
+    "/login",
+    "/api/login",
+    "/admin/maintenance",
+    "/admin/maintenance/clear",
+];
+
+/// Stored as JSON in Redis under `MAINTENANCE_REDIS_KEY` while the site is
+/// closed, so every instance closes at once
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Maintenance {
+    /// Shown to users instead of the default text
+    pub message: Option<String>,
+    /// When the site is expected to open again
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub until: Option<OffsetDateTime>,
+    /// Whether admins keep using the site, to check it before reopening
+    pub admin_bypass: bool,
+}
+
+impl Maintenance {
+    fn message(&self) -> &str {
+        self.message
+            .as_deref()
+            .unwrap_or("The site is down for maintenance, please come back later")
+    }
+
+    fn retry_after(&self) -> Duration {
+        self.until
+            .map(|until| until - OffsetDateTime::now_utc())
+            .and_then(|remaining| Duration::try_from(remaining).ok())
+            .filter(|remaining| !remaining.is_zero())
+            .unwrap_or(DEFAULT_RETRY_AFTER)
+    }
+}
+
+/// `None` unless the site is in maintenance
+pub async fn get(redis: &Redis, key: &str) -> Result<Option<Maintenance>, anyhow::Error> {
+    let stored: Option<String> = redis
+        .get(redis.key(key))
+        .await
+        .context("Failed to get maintenance flag")?;
+    stored
+        .map(|stored| serde_json::from_str(&stored))
+        .transpose()
+        .context("Invalid maintenance flag")
+}
+
+pub async fn start(
+    redis: &Redis,
+    key: &str,
+    maintenance: &Maintenance,
+) -> Result<(), anyhow::Error> {
+    let stored = serde_json::to_string(maintenance).context("Failed to serialize maintenance")?;
+    redis
+        .set::<(), _, _>(redis.key(key), stored, None, None, false)
+        .await
+        .context("Failed to set maintenance flag")
+}
+
+pub async fn end(redis: &Redis, key: &str) -> Result<(), anyhow::Error> {
+    redis
+        .del::<(), _>(redis.key(key))
+        .await
+        .context("Failed to clear maintenance flag")
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "maintenance.html")]
+struct MaintenanceTemplate<'a> {
+    /// Only from the cookies, the database may be what is being maintained
+    page_context: PageContext,
+    message: &'a str,
+    /// In UTC, the page doesn't know the user's timezone
+    until: Option<String>,
+}
+
+/// Answers every request but the [`EXEMPT_PATHS`] with a 503 while the site
+/// is in maintenance, JSON under `/api`. Costs one Redis read per request,
+/// if Redis can't be read the site stays open.
+pub async fn check(
+    Extension(api_context): Extension<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if EXEMPT_PATHS.contains(&path) {
+        return next.run(request).await;
+    }
+
+    let key = &api_context
+        .config
+        .application_settings
+        .maintenance_redis_key;
+    let maintenance = match get(&api_context.redis, key).await {
+        Ok(Some(maintenance)) => maintenance,
+        Ok(None) => return next.run(request).await,
+        Err(e) => {
+            tracing::warn!(error = ?e, "Failed to check maintenance mode");
+            return next.run(request).await;
+        }
+    };
+    if maintenance.admin_bypass
+        && auth_session
+            .user
+            .as_ref()
+            .is_some_and(|user| user.is_admin())
+    {
+        return next.run(request).await;
+    }
+
+    // whole seconds, rounded up like the rate limits
+    let retry_after = [(
+        header::RETRY_AFTER,
+        maintenance
+            .retry_after()
+            .as_millis()
+            .div_ceil(1000)
+            .to_string(),
+    )];
+    if path == "/api" || path.starts_with("/api/") {
+        return (
+            retry_after,
+            ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "maintenance",
+                maintenance.message(),
+            ),
+        )
+            .into_response();
+    }
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        retry_after,
+        MaintenanceTemplate {
+            page_context: PageContext::from_headers(request.headers()),
+            message: maintenance.message(),
+            until: maintenance.until.and_then(|until| {
+                until
+                    .format(format_description!(
+                        "[year]-[month]-[day] [hour]:[minute] UTC"
+                    ))
+                    .ok()
+            }),
+        },
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use askama::Template;
+
+    use crate::{maintenance::MaintenanceTemplate, page::PageContext};
+
+    #[test]
+    fn maintenance_page_escapes_the_message() {
+        let html = MaintenanceTemplate {
+            page_context: PageContext::default(),
+            message: "<b>Upgrading</b> the database & \"more\"",
+            until: Some("2025-07-01 09:30 UTC".to_string()),
+        }
+        .render()
+        .unwrap();
+        assert!(!html.contains("<b>Upgrading</b>"));
+        insta::assert_snapshot!(html);
+    }
+
+    #[test]
+    fn maintenance_page_without_an_end() {
+        let html = MaintenanceTemplate {
+            page_context: PageContext::default(),
+            message: "The site is down for maintenance, please come back later",
+            until: None,
+        }
+        .render()
+        .unwrap();
+        insta::assert_snapshot!(html);
+    }
+}
@@ -0,0 +1,216 @@
+use linkify::{LinkFinder, LinkKind};
+use pulldown_cmark::{CowStr, Event, LinkType, Options, Parser, Tag, TagEnd};
+
+use crate::sanitize::{self, Profile};
+
+/// Renders user written Markdown to HTML that is safe to embed in a page.
+///
+/// Raw HTML in the input is shown as text rather than passed through, bare
+/// URLs become links, and the output is run through an allowlist sanitizer
+/// as a second line of defense.
+pub fn render(input: &str) -> String {
+    sanitize::clean(&to_html(input), Profile::Markdown)
+}
+
+/// Like [`render`], for a title shown within a line of other content, so
+/// without paragraphs, lists or other blocks
+pub fn render_inline(input: &str) -> String {
+    sanitize::clean(&to_html(input), Profile::InlineMarkdown)
+        .trim()
+        .to_string()
+}
+
+/// Like [`render`], keeping only the text, for places that can't hold
+/// markup such as a link
+pub fn render_plain(input: &str) -> String {
+    sanitize::clean(&to_html(input), Profile::Plain)
+        .trim()
+        .to_string()
+}
+
+fn to_html(input: &str) -> String {
+    let parser = Parser::new_ext(input, Options::ENABLE_STRIKETHROUGH);
+
+    let mut events = Vec::new();
+    let mut link_depth = 0;
+    let mut code_depth = 0;
+    for event in parser {
+        match event {
+            Event::Html(html) | Event::InlineHtml(html) => events.push(Event::Text(html)),
+            Event::Text(text) if link_depth == 0 && code_depth == 0 => {
+                autolink(text, &mut events);
+            }
+            Event::Start(tag) => {
+                match tag {
+                    Tag::Link { .. } => link_depth += 1,
+                    Tag::CodeBlock(_) => code_depth += 1,
+                    _ => {}
+                }
+                events.push(Event::Start(tag));
+            }
+            Event::End(tag) => {
+                match tag {
+                    TagEnd::Link => link_depth -= 1,
+                    TagEnd::CodeBlock => code_depth -= 1,
+                    _ => {}
+                }
+                events.push(Event::End(tag));
+            }
+            event => events.push(event),
+        }
+    }
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+    html
+}
+
+/// Splits text into plain text and links around the URLs it contains
+fn autolink<'a>(text: CowStr<'a>, events: &mut Vec<Event<'a>>) {
+    let mut finder = LinkFinder::new();
+    finder.kinds(&[LinkKind::Url]);
+
+    for span in finder.spans(&text) {
+        let part = CowStr::from(span.as_str().to_string());
+        match span.kind() {
+            Some(LinkKind::Url) => {
+                events.push(Event::Start(Tag::Link {
+                    link_type: LinkType::Autolink,
+                    dest_url: part.clone(),
+                    title: CowStr::from(""),
+                    id: CowStr::from(""),
+                }));
+                events.push(Event::Text(part));
+                events.push(Event::End(TagEnd::Link));
+            }
+            _ => events.push(Event::Text(part)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, render_inline, render_plain};
+    use crate::sanitize::{INLINE_MARKDOWN_TAGS, MARKDOWN_TAGS};
+
+    /// Text is escaped in the output, so every `<` starts a real tag. Those
+    /// may only be allowed tags without attributes, except for links to safe
+    /// schemes.
+    fn assert_inert(html: &str, allowed_tags: &[&str]) {
+        for tag in html.split('<').skip(1) {
+            let tag = &tag[..tag.find('>').expect("Unclosed tag")];
+            let tag = tag.strip_prefix('/').unwrap_or(tag);
+
+            let Some(attributes) = tag.strip_prefix("a ") else {
+                assert!(allowed_tags.contains(&tag), "<{tag}> in {html}");
+                continue;
+            };
+            let href = attributes
+                .strip_suffix("rel=\"noopener noreferrer nofollow\"")
+                .unwrap_or_else(|| panic!("Unexpected link attributes <{tag}> in {html}"))
+                .trim_end();
+            if href.is_empty() {
+                continue;
+            }
+            let url = href
+                .strip_prefix("href=\"")
+                .and_then(|href| href.strip_suffix('"'))
+                .unwrap_or_else(|| panic!("Unexpected link attributes <{tag}> in {html}"));
+            assert!(!url.contains('"'), "<{tag}> in {html}");
+            assert!(
+                ["http://", "https://", "mailto:"]
+                    .iter()
+                    .any(|scheme| url.starts_with(scheme)),
+                "<{tag}> in {html}"
+            );
+        }
+    }
+
+    #[test]
+    fn basic_markdown_is_rendered() {
+        assert_eq!(
+            "<p><strong>bold</strong> and <em>italic</em></p>\n",
+            render("**bold** and *italic*")
+        );
+        assert_eq!(
+            "<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n",
+            render("- one\n- two")
+        );
+        assert_eq!("<p><del>done</del></p>\n", render("~~done~~"));
+    }
+
+    #[test]
+    fn bare_urls_are_linked() {
+        assert_eq!(
+            "<p>see <a href=\"https://example.com/a?b=c\" rel=\"noopener noreferrer nofollow\">https://example.com/a?b=c</a> for more</p>\n",
+            render("see https://example.com/a?b=c for more")
+        );
+    }
+
+    #[test]
+    fn urls_in_code_and_links_are_left_alone() {
+        assert_eq!(
+            "<p><code>https://example.com</code></p>\n",
+            render("`https://example.com`")
+        );
+        assert_eq!(
+            "<p><a href=\"https://example.com\" rel=\"noopener noreferrer nofollow\">https://example.org</a></p>\n",
+            render("[https://example.org](https://example.com)")
+        );
+    }
+
+    #[test]
+    fn raw_html_is_shown_as_text() {
+        assert_eq!(
+            "<p>&lt;b&gt;not bold&lt;/b&gt;</p>\n",
+            render("<b>not bold</b>")
+        );
+    }
+
+    #[test]
+    fn xss_payloads_are_inert() {
+        let payloads = [
+            "<script>alert(1)</script>",
+            "<img src=x onerror=alert(1)>",
+            "<svg onload=alert(1)>",
+            "<iframe src=\"javascript:alert(1)\"></iframe>",
+            "<a href=\"javascript:alert(1)\">click</a>",
+            "[click](javascript:alert(1))",
+            "[click](JaVaScRiPt:alert(1))",
+            "[click](data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==)",
+            "![x](https://example.com/x.png)",
+            "![x](x \"onerror=alert(1)\")",
+            "<div style=\"background:url(javascript:alert(1))\">x</div>",
+            "<style>body{display:none}</style>",
+            "<form action=https://evil.example><button>go</button></form>",
+            "[a](https://example.com \"title\" onclick=alert(1))",
+            "javascript:alert(1)",
+            "<<script>script>alert(1)<</script>/script>",
+            "```\n<script>alert(1)</script>\n```",
+        ];
+
+        for payload in payloads {
+            assert_inert(&render(payload), &MARKDOWN_TAGS);
+            assert_inert(&render_inline(payload), &INLINE_MARKDOWN_TAGS);
+            assert_inert(&render_plain(payload), &[]);
+        }
+    }
+
+    #[test]
+    fn only_safe_link_schemes_are_kept() {
+        let html = render("[mail](mailto:someone@example.com) [js](javascript:alert(1))");
+        assert!(html.contains("href=\"mailto:someone@example.com\""));
+        assert!(!html.contains("javascript"));
+    }
+
+    #[test]
+    fn titles_are_rendered_without_blocks() {
+        assert_eq!("buy <strong>milk</strong>", render_inline("buy **milk**"));
+        assert_eq!("one\ntwo", render_inline("- one\n- two"));
+        assert_eq!("buy milk", render_plain("buy **milk**"));
+        assert_eq!(
+            "&lt;img src=x onerror=alert(1)&gt;",
+            render_plain("<img src=x onerror=alert(1)>")
+        );
+    }
+}
@@ -0,0 +1,239 @@
+//! Serving the same route as HTML or JSON, depending on what the client asks
+//! for, so the htmx pages and JSON clients don't need routes of their own
+
+use std::convert::Infallible;
+
+use askama::Template;
+use axum::{
+    Json,
+    extract::{FromRequestParts, Request},
+    middleware::Next,
+    response::{AppendHeaders, Html, IntoResponse, Redirect, Response},
+};
+use http::{HeaderMap, HeaderValue, StatusCode, header, request::Parts};
+
+use crate::{api_error::ApiError, auth::AuthSession};
+
+/// Sent by htmx with every request it makes
+pub const HX_REQUEST_HEADER: &str = "HX-Request";
+
+/// What a response is rendered as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Html,
+    Json,
+}
+
+impl Format {
+    /// JSON when `Accept` prefers `application/json` to HTML, HTML otherwise,
+    /// including when `Accept` is missing or names neither
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        // htmx accepts anything, but always swaps in HTML
+        if headers.contains_key(HX_REQUEST_HEADER) {
+            return Format::Html;
+        }
+        let Some(accept) = headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+        else {
+            return Format::Html;
+        };
+
+        let mut html = 0.0;
+        let mut json = 0.0;
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media_type = params.next().unwrap_or_default().trim();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if media_type.eq_ignore_ascii_case("application/json") {
+                json = quality.max(json);
+            } else if ["text/html", "text/*", "*/*"]
+                .iter()
+                .any(|html_type| media_type.eq_ignore_ascii_case(html_type))
+            {
+                html = quality.max(html);
+            }
+        }
+
+        if json > 0.0 && json > html {
+            Format::Json
+        } else {
+            Format::Html
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Format {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Format::from_headers(&parts.headers))
+    }
+}
+
+/// Whether htmx sent the request, from its `HX-Request` header. Forms are
+/// posted by the browser itself when JavaScript is off, which follows
+/// redirects and shows whatever page it gets back, but ignores the htmx
+/// headers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IsHtmx(pub bool);
+
+impl IsHtmx {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        IsHtmx(headers.contains_key(HX_REQUEST_HEADER))
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for IsHtmx {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(IsHtmx::from_headers(&parts.headers))
+    }
+}
+
+/// Sends the client to `path` once a form went through: htmx with an
+/// `HX-Redirect` header, anything else with a 303 to follow with a GET
+pub fn redirect_response(is_htmx: IsHtmx, path: &str) -> Response {
+    match is_htmx {
+        IsHtmx(true) => (StatusCode::OK, AppendHeaders([("HX-Redirect", path)])).into_response(),
+        IsHtmx(false) => Redirect::to(path).into_response(),
+    }
+}
+
+/// A view model sent as its template or as JSON, built once by the handler
+/// either way
+pub enum HtmlOrJson<T> {
+    Html(T),
+    Json(T),
+}
+
+impl<T> HtmlOrJson<T> {
+    pub fn new(format: Format, view: T) -> Self {
+        match format {
+            Format::Html => HtmlOrJson::Html(view),
+            Format::Json => HtmlOrJson::Json(view),
+        }
+    }
+}
+
+impl<T: Template + serde::Serialize> IntoResponse for HtmlOrJson<T> {
+    fn into_response(self) -> Response {
+        let mut response = match self {
+            HtmlOrJson::Html(view) => match view.render() {
+                Ok(html) => Html(html).into_response(),
+                Err(e) => {
+                    tracing::error!(error = ?e, "Failed to render template");
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            },
+            HtmlOrJson::Json(view) => Json(view).into_response(),
+        };
+        set_vary(&mut response);
+        response
+    }
+}
+
+/// Caches must not hand the HTML to a JSON client or the other way around,
+/// nor a page to someone reading another language
+pub fn set_vary(response: &mut Response) {
+    response.headers_mut().insert(
+        header::VARY,
+        HeaderValue::from_static("Accept, Accept-Language, HX-Request"),
+    );
+}
+
+/// Answers JSON clients without a session with a 401 instead of the login
+/// page. Layer it outside `login_required!`, which still redirects the rest.
+pub async fn json_login_required(
+    auth_session: AuthSession,
+    format: Format,
+    request: Request,
+    next: Next,
+) -> Response {
+    if format == Format::Json && auth_session.user.is_none() {
+        return ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "not_logged_in",
+            "You need to be logged in",
+        )
+        .into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, HeaderValue, StatusCode, header};
+
+    use super::{Format, HX_REQUEST_HEADER, IsHtmx, redirect_response};
+
+    fn accepting(accept: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+        headers
+    }
+
+    #[test]
+    fn json_is_sent_when_preferred() {
+        for accept in [
+            "application/json",
+            "Application/JSON",
+            "application/json, text/html;q=0.5",
+            "text/html;q=0.2, application/json;q=0.9",
+            "application/json, */*;q=0.1",
+        ] {
+            assert_eq!(
+                Format::Json,
+                Format::from_headers(&accepting(accept)),
+                "{accept}"
+            );
+        }
+    }
+
+    #[test]
+    fn html_is_the_default() {
+        assert_eq!(Format::Html, Format::from_headers(&HeaderMap::new()));
+        for accept in [
+            "text/html",
+            "*/*",
+            "image/png",
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+            "application/json;q=0.5, text/html",
+            "application/json;q=0",
+            "nonsense",
+        ] {
+            assert_eq!(
+                Format::Html,
+                Format::from_headers(&accepting(accept)),
+                "{accept}"
+            );
+        }
+    }
+
+    #[test]
+    fn htmx_requests_get_html() {
+        let mut headers = accepting("application/json");
+        headers.insert(HX_REQUEST_HEADER, HeaderValue::from_static("true"));
+        assert_eq!(Format::Html, Format::from_headers(&headers));
+    }
+
+    #[test]
+    fn htmx_is_sent_a_header_to_redirect() {
+        let response = redirect_response(IsHtmx(true), "/todo");
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("/todo", response.headers()["HX-Redirect"]);
+        assert!(!response.headers().contains_key(header::LOCATION));
+    }
+
+    #[test]
+    fn browsers_are_sent_a_see_other() {
+        let response = redirect_response(IsHtmx(false), "/todo");
+        assert_eq!(StatusCode::SEE_OTHER, response.status());
+        assert_eq!("/todo", response.headers()[header::LOCATION]);
+        assert!(!response.headers().contains_key("HX-Redirect"));
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::Context;
+use sqlx::{PgPool, types::Json};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::routes::todo::list::{ListRole, list_url};
+
+/// Notifications shown on the notifications page, newest first
+const NOTIFICATIONS_SHOWN: i64 = 50;
+
+/// Something that happened to a user, as stored in `notifications`.
+///
+/// Stored as JSON, so new kinds don't need a migration. Existing variants
+/// can't be renamed without breaking the notifications already written.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Notification {
+    ListShared {
+        list_id: Uuid,
+        owner_username: String,
+        role: ListRole,
+    },
+}
+
+impl Notification {
+    /// The `kind` column, the same as the tag of the payload
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Notification::ListShared { .. } => "list_shared",
+        }
+    }
+
+    /// Where the notification leads to
+    pub fn url(&self) -> String {
+        match self {
+            Notification::ListShared { list_id, .. } => list_url(*list_id),
+        }
+    }
+}
+
+impl std::fmt::Display for Notification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Notification::ListShared {
+                owner_username,
+                role,
+                ..
+            } => write!(f, "{owner_username} shared a list with you as {role}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NotificationRow {
+    pub notification_id: Uuid,
+    pub notification: Notification,
+    pub read_at: Option<OffsetDateTime>,
+    pub created_at: OffsetDateTime,
+}
+
+impl NotificationRow {
+    pub fn is_read(&self) -> bool {
+        self.read_at.is_some()
+    }
+}
+
+pub async fn notify(
+    db: &PgPool,
+    user_id: Uuid,
+    notification: Notification,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO notifications (user_id, kind, payload)
+        VALUES ($1, $2, $3)
+        "#,
+        user_id,
+        notification.kind(),
+        Json(&notification) as _
+    )
+    .execute(db)
+    .await
+    .context("Failed to store notification")?;
+    Ok(())
+}
+
+/// The latest notifications of the user, read or not
+pub async fn latest(db: &PgPool, user_id: Uuid) -> Result<Vec<NotificationRow>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            notification_id, payload AS "notification: Json<Notification>", read_at, created_at
+        FROM notifications
+        WHERE user_id = $1
+        ORDER BY created_at DESC, notification_id
+        LIMIT $2
+        "#,
+        user_id,
+        NOTIFICATIONS_SHOWN
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to get notifications")?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| NotificationRow {
+            notification_id: row.notification_id,
+            notification: row.notification.0,
+            read_at: row.read_at,
+            created_at: row.created_at,
+        })
+        .collect())
+}
+
+pub async fn unread_count(db: &PgPool, user_id: Uuid) -> Result<i64, anyhow::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM notifications
+        WHERE user_id = $1 AND read_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+    .context("Failed to count unread notifications")
+}
+
+/// `false` if the user has no such notification
+pub async fn mark_read(
+    db: &PgPool,
+    user_id: Uuid,
+    notification_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE notifications SET read_at = COALESCE(read_at, NOW())
+        WHERE notification_id = $1 AND user_id = $2
+        "#,
+        notification_id,
+        user_id
+    )
+    .execute(db)
+    .await
+    .context("Failed to mark notification as read")?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn mark_all_read(db: &PgPool, user_id: Uuid) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE notifications SET read_at = NOW()
+        WHERE user_id = $1 AND read_at IS NULL
+        "#,
+        user_id
+    )
+    .execute(db)
+    .await
+    .context("Failed to mark notifications as read")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::Notification;
+    use crate::routes::todo::list::ListRole;
+
+    #[test]
+    fn kind_is_the_tag_of_the_payload() {
+        let notification = Notification::ListShared {
+            list_id: Uuid::from_u128(1),
+            owner_username: "alice".to_string(),
+            role: ListRole::Editor,
+        };
+        let payload = serde_json::to_value(&notification).unwrap();
+        assert_eq!(notification.kind(), payload["kind"]);
+        assert_eq!(
+            notification,
+            serde_json::from_value::<Notification>(payload).unwrap()
+        );
+        assert_eq!(
+            "alice shared a list with you as editor",
+            notification.to_string()
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! What every page passes to `base.html`, so the layout can be changed
+//! without touching the template of each page
+
+use std::{convert::Infallible, sync::Arc};
+
+use axum::extract::FromRequestParts;
+use axum_messages::{Level, Message, Messages};
+use http::{HeaderMap, request::Parts};
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{
+    app::ApiContext,
+    auth::{
+        AuthSession, User,
+        impersonation::{self, Impersonator},
+    },
+    features::Features,
+    theme::Theme,
+};
+
+/// The `page_context` field of every template extending `base.html`
+#[derive(Debug, Clone, Default)]
+pub struct PageContext {
+    pub theme: Theme,
+    /// Who is logged in, for the navigation
+    pub user: Option<CurrentUser>,
+    /// Messages left in [`Messages`] by the handler that redirected here,
+    /// shown once
+    pub flashes: Vec<Message>,
+    pub features: Features,
+    /// The admin viewing as the user, for the banner leading back
+    pub impersonator: Option<Impersonator>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrentUser {
+    pub user_id: Uuid,
+    pub username: String,
+}
+
+impl From<&User> for CurrentUser {
+    fn from(user: &User) -> Self {
+        Self {
+            user_id: user.user_id(),
+            username: user.username.clone(),
+        }
+    }
+}
+
+impl PageContext {
+    /// From the request alone, for pages served before the user is known
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            theme: Theme::from_cookie(headers).unwrap_or_default(),
+            ..Self::default()
+        }
+    }
+
+    /// Class of a flash message, e.g. `flash-success`
+    pub fn flash_class(&self, flash: &Message) -> &'static str {
+        match flash.level {
+            Level::Debug | Level::Info => "flash-info",
+            Level::Success => "flash-success",
+            Level::Warning => "flash-warning",
+            Level::Error => "flash-error",
+        }
+    }
+
+    /// The user an admin is viewing as, for the banner in `base.html`
+    pub fn impersonated_username(&self) -> Option<&str> {
+        self.impersonator.as_ref()?;
+        self.user.as_ref().map(|user| user.username.as_str())
+    }
+
+    /// The themes to switch to, for the switch in `base.html`
+    pub fn themes(&self) -> &'static [Theme] {
+        &Theme::ALL
+    }
+}
+
+/// The theme comes from its cookie. Browsers without one, e.g. after
+/// logging in somewhere new, get the theme the user picked before.
+impl FromRequestParts<Arc<ApiContext>> for PageContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<ApiContext>,
+    ) -> Result<Self, Self::Rejection> {
+        let user = AuthSession::from_request_parts(parts, state)
+            .await
+            .ok()
+            .and_then(|auth_session| auth_session.user);
+        let session = Session::from_request_parts(parts, state).await.ok();
+
+        let theme = match (Theme::from_cookie(&parts.headers), &user) {
+            (Some(theme), _) => theme,
+            (None, Some(user)) => match state.preferences.get(&state.db, user.user_id()).await {
+                Ok(preferences) => preferences.theme,
+                Err(e) => {
+                    tracing::warn!(error = ?e, "Failed to get the theme of the user");
+                    Theme::default()
+                }
+            },
+            (None, None) => Theme::default(),
+        };
+        // only the web routes have messages
+        let flashes = match Messages::from_request_parts(parts, state).await {
+            Ok(messages) => messages.collect(),
+            Err(_) => Vec::new(),
+        };
+        let impersonator =
+            match (&user, &session) {
+                (Some(_), Some(session)) => impersonation::impersonator(session)
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::warn!(error = ?e, "Failed to get the impersonator");
+                        None
+                    }),
+                _ => None,
+            };
+
+        Ok(Self {
+            theme,
+            user: user.as_ref().map(CurrentUser::from),
+            flashes,
+            features: state.features,
+            impersonator,
+        })
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use moka::future::Cache;
+use sqlx::PgPool;
+use time::{Date, OffsetDateTime};
+use time_tz::Tz;
+use uuid::Uuid;
+
+use crate::{domain::timezone, i18n::Locale, theme::Theme};
+
+/// How long preferences are served from memory before being reloaded
+const CACHE_TTL: Duration = Duration::from_secs(300);
+const CACHE_CAPACITY: u64 = 10_000;
+/// Upper bound of `items_per_page`, the todo view never renders more rows
+pub const MAX_ITEMS_PER_PAGE: i32 = 100;
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "todo_sort", rename_all = "lowercase")]
+pub enum TodoSort {
+    /// Newest first
+    #[default]
+    Created,
+    /// Highest priority first, then newest first
+    Priority,
+}
+
+impl std::fmt::Display for TodoSort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TodoSort::Created => write!(f, "created"),
+            TodoSort::Priority => write!(f, "priority"),
+        }
+    }
+}
+
+/// User-level defaults, used when a request doesn't say otherwise
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preferences {
+    pub default_sort: TodoSort,
+    pub items_per_page: i32,
+    pub show_completed: bool,
+    pub timezone: String,
+    /// Complete a todo once all of its subtasks are completed
+    pub auto_complete_todos: bool,
+    /// `None` follows the browser, see [`Locale::negotiate`]
+    pub locale: Option<Locale>,
+    pub theme: Theme,
+}
+
+impl Preferences {
+    /// The zone timestamps are shown in and due dates are counted in
+    pub fn tz(&self) -> &'static Tz {
+        timezone::tz_or_utc(&self.timezone)
+    }
+
+    /// The current date where the user lives
+    pub fn today(&self) -> Date {
+        timezone::local_date(OffsetDateTime::now_utc(), self.tz())
+    }
+}
+
+impl Default for Preferences {
+    /// Matches the column defaults of `user_preferences`, for users who never saved any
+    fn default() -> Self {
+        Self {
+            default_sort: TodoSort::Created,
+            items_per_page: 50,
+            show_completed: true,
+            timezone: "UTC".to_string(),
+            auto_complete_todos: false,
+            locale: None,
+            theme: Theme::System,
+        }
+    }
+}
+
+/// In-memory cache in front of the `user_preferences` table.
+///
+/// Entries are invalidated when the settings are saved on this instance, and
+/// expire after [`CACHE_TTL`] to pick up changes made elsewhere.
+#[derive(Debug, Clone)]
+pub struct PreferencesCache {
+    cache: Cache<Uuid, Preferences>,
+}
+
+impl Default for PreferencesCache {
+    fn default() -> Self {
+        Self {
+            cache: Cache::builder()
+                .max_capacity(CACHE_CAPACITY)
+                .time_to_live(CACHE_TTL)
+                .build(),
+        }
+    }
+}
+
+impl PreferencesCache {
+    pub async fn get(&self, db: &PgPool, user_id: Uuid) -> Result<Preferences, anyhow::Error> {
+        self.cache
+            .try_get_with(user_id, load_preferences(db, user_id))
+            .await
+            .map_err(|e| anyhow::anyhow!("{e:?}"))
+    }
+
+    pub async fn invalidate(&self, user_id: Uuid) {
+        self.cache.invalidate(&user_id).await;
+    }
+
+    /// Lets tests without a database serve the page of a user
+    #[cfg(test)]
+    pub async fn insert(&self, user_id: Uuid, preferences: Preferences) {
+        self.cache.insert(user_id, preferences).await;
+    }
+}
+
+async fn load_preferences(db: &PgPool, user_id: Uuid) -> Result<Preferences, anyhow::Error> {
+    let preferences = sqlx::query_as!(
+        Preferences,
+        r#"
+        SELECT
+            default_sort AS "default_sort: TodoSort", items_per_page, show_completed, timezone,
+            auto_complete_todos, locale AS "locale: Locale", theme AS "theme: Theme"
+        FROM user_preferences
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+    .context("Failed to get user preferences")?;
+
+    Ok(preferences.unwrap_or_default())
+}
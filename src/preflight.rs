@@ -0,0 +1,241 @@
+use std::{collections::HashSet, fmt, path::Path, time::Duration};
+
+use anyhow::{Context, anyhow};
+use sqlx::{PgPool, migrate::Migrator, postgres::PgPoolOptions};
+
+use crate::{
+    app::db_connect_options,
+    auth::{Hasher, SessionKeys, TokenKeys, webauthn_from_settings},
+    captcha::CaptchaClient,
+    client_ip::TrustedProxies,
+    config::{Config, StorageBackend},
+    cors,
+    domain::email_address::EmailAddress,
+    redis,
+    urls::UrlBuilder,
+};
+
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// How long Postgres gets to answer before it counts as down
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Everything [`run`] found wrong, printed as a list when the app refuses
+/// to start
+#[derive(Debug)]
+pub struct PreflightReport {
+    pub failures: Vec<anyhow::Error>,
+}
+
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The app can't start:")?;
+        for failure in &self.failures {
+            write!(f, "\n  - {failure:#}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PreflightReport {}
+
+/// Checks the settings, the assets and the services the app needs before
+/// it starts serving, so a broken deploy fails at startup with everything
+/// that is wrong rather than on the first requests. Applies the pending
+/// migrations unless `RUN_MIGRATIONS` is off.
+pub async fn run(config: &Config, assets_dir: &Path) -> Result<(), PreflightReport> {
+    let mut failures = check_settings(config);
+    // embedded ones were checked when compiling
+    if !cfg!(feature = "embed-assets")
+        && let Err(e) = check_assets(assets_dir)
+    {
+        failures.push(e);
+    }
+    if let Err(e) = check_db(config).await {
+        failures.push(e);
+    }
+    // waits for it to come up, as it often starts along with the app
+    if let Err(e) = redis::wait_until_up(config).await {
+        failures.push(e);
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(PreflightReport { failures })
+    }
+}
+
+/// Settings that would only fail once they are used, or that are set
+/// halfway
+fn check_settings(config: &Config) -> Vec<anyhow::Error> {
+    let settings = &config.application_settings;
+    let storage = &config.storage_settings;
+    let mut failures = Vec::new();
+
+    if let Err(e) = SessionKeys::from_settings(settings) {
+        failures.push(e.context("Invalid session keys"));
+    }
+    if let Err(e) = Hasher::from_settings(settings) {
+        failures.push(e.context("Invalid password hashing settings"));
+    }
+    if let Err(e) = TokenKeys::from_settings(settings) {
+        failures.push(e.context("Invalid JWT settings"));
+    }
+    if let Err(e) = CaptchaClient::from_settings(settings) {
+        failures.push(e.context("Invalid CAPTCHA settings"));
+    }
+    if let Err(e) = webauthn_from_settings(settings) {
+        failures.push(e.context("Invalid passkey settings"));
+    }
+    if let Err(e) = UrlBuilder::from_settings(settings) {
+        failures.push(e.context("Invalid APP_BASE_URL"));
+    }
+    if let Err(e) = TrustedProxies::from_settings(settings) {
+        failures.push(e.context("Invalid trusted proxies"));
+    }
+    if let Err(e) = cors::from_settings(settings) {
+        failures.push(anyhow::Error::from(e).context("Invalid CORS settings"));
+    }
+    if let Err(e) = EmailAddress::parse(&config.email_client_settings.email_sender) {
+        failures.push(anyhow::Error::from(e).context("Invalid EMAIL_SENDER"));
+    }
+
+    match (
+        &storage.storage_access_key_id,
+        &storage.storage_secret_access_key,
+    ) {
+        (Some(_), None) => failures.push(anyhow!(
+            "STORAGE_ACCESS_KEY_ID is set without STORAGE_SECRET_ACCESS_KEY"
+        )),
+        (None, Some(_)) => failures.push(anyhow!(
+            "STORAGE_SECRET_ACCESS_KEY is set without STORAGE_ACCESS_KEY_ID"
+        )),
+        (None, None) if storage.storage_backend == StorageBackend::S3 => failures.push(anyhow!(
+            "The s3 storage backend needs STORAGE_ACCESS_KEY_ID and STORAGE_SECRET_ACCESS_KEY"
+        )),
+        _ => {}
+    }
+    if storage.storage_backend == StorageBackend::S3 && storage.storage_bucket.is_none() {
+        failures.push(anyhow!("The s3 storage backend needs STORAGE_BUCKET"));
+    }
+
+    failures
+}
+
+/// The pages link to the stylesheets and scripts in there
+fn check_assets(assets_dir: &Path) -> Result<(), anyhow::Error> {
+    std::fs::read_dir(assets_dir)
+        .map(|_| ())
+        .with_context(|| format!("Can't read the assets directory {}", assets_dir.display()))
+}
+
+async fn check_db(config: &Config) -> Result<(), anyhow::Error> {
+    let options = db_connect_options(config).context("Invalid DATABASE_URL")?;
+    let db = PgPoolOptions::new()
+        .acquire_timeout(CONNECT_TIMEOUT)
+        .connect_with(options)
+        .await
+        .context("Can't connect to Postgres")?;
+    sqlx::query("SELECT 1")
+        .execute(&db)
+        .await
+        .context("Postgres doesn't answer queries")?;
+
+    migrations(&db, config.application_settings.run_migrations).await
+}
+
+/// Applies the pending migrations, or only checks there are none if `run`
+/// is false
+pub async fn migrations(db: &PgPool, run: bool) -> Result<(), anyhow::Error> {
+    if run {
+        return MIGRATOR.run(db).await.context("Failed to run migrations");
+    }
+
+    let applied: HashSet<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(db)
+            .await
+            .context("Can't read the applied migrations")?
+            .into_iter()
+            .collect();
+    let pending: Vec<String> = MIGRATOR
+        .iter()
+        .filter(|migration| {
+            !migration.migration_type.is_down_migration() && !applied.contains(&migration.version)
+        })
+        .map(|migration| format!("{}_{}", migration.version, migration.description))
+        .collect();
+    if !pending.is_empty() {
+        return Err(anyhow!(
+            "Migrations are pending and RUN_MIGRATIONS is off: {}",
+            pending.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "--hmac-key=0123456789012345678901234567890123456789012345678901234567890123";
+
+    #[test]
+    fn valid_settings_pass() {
+        assert!(check_settings(&Config::for_tests(&[KEY])).is_empty());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let failures = check_settings(&Config::for_tests(&[
+            "--hmac-key=short",
+            "--storage-access-key-id=key",
+            "--jwt-verification-keys=old-key",
+        ]));
+
+        let messages: Vec<String> = failures.iter().map(|e| format!("{e:#}")).collect();
+        assert_eq!(3, messages.len(), "{messages:?}");
+        assert!(messages[0].contains("HMAC_KEY"));
+        assert!(messages[1].contains("JWT"));
+        assert!(messages[2].contains("STORAGE_SECRET_ACCESS_KEY"));
+    }
+
+    #[test]
+    fn app_base_url_has_to_be_an_http_url_without_a_query() {
+        let mut config = Config::for_tests(&[KEY]);
+        config.application_settings.app_base_url = "https://example.com/?ref=mail".to_string();
+        let failures = check_settings(&config);
+
+        let messages: Vec<String> = failures.iter().map(|e| format!("{e:#}")).collect();
+        assert!(
+            messages.iter().any(|m| m.contains("APP_BASE_URL")),
+            "{messages:?}"
+        );
+    }
+
+    #[test]
+    fn s3_backend_needs_a_bucket_and_credentials() {
+        let failures = check_settings(&Config::for_tests(&[KEY, "--storage-backend=s3"]));
+
+        assert_eq!(2, failures.len());
+    }
+
+    #[test]
+    fn missing_assets_directory_is_reported() {
+        assert!(check_assets(Path::new("does-not-exist")).is_err());
+        assert!(check_assets(Path::new("assets")).is_ok());
+    }
+
+    #[test]
+    fn report_lists_every_failure() {
+        let report = PreflightReport {
+            failures: vec![anyhow!("first"), anyhow!("second")],
+        };
+
+        assert_eq!(
+            "The app can't start:\n  - first\n  - second",
+            report.to_string()
+        );
+    }
+}
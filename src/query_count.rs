@@ -0,0 +1,116 @@
+//! Counts the queries a request runs through the todo repository, so the
+//! tests can tell a page doesn't run one per todo. Only built with the
+//! `test-util` feature, where every response tells its count in
+//! [`QUERY_COUNT_HEADER`].
+
+use std::{cell::Cell, future::Future, pin::Pin};
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use sqlx::{
+    Describe, Either, Execute, Executor, PgPool, Postgres,
+    postgres::{PgQueryResult, PgRow, PgStatement, PgTypeInfo},
+};
+use tokio_stream::Stream;
+
+use crate::routes::todo::Db;
+
+pub const QUERY_COUNT_HEADER: &str = "x-query-count";
+
+tokio::task_local! {
+    static QUERIES: Cell<usize>;
+}
+
+type BoxFuture<'e, T> = Pin<Box<dyn Future<Output = T> + Send + 'e>>;
+type BoxStream<'e, T> = Pin<Box<dyn Stream<Item = T> + Send + 'e>>;
+
+/// The pool, counting the queries run on it into the request they are run
+/// for. Those run outside of a request, or in a transaction, aren't counted.
+#[derive(Debug, Clone)]
+pub struct CountingPool {
+    pool: PgPool,
+}
+
+impl CountingPool {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    fn count(&self) {
+        let _ = QUERIES.try_with(|queries| queries.set(queries.get() + 1));
+    }
+}
+
+impl<'c> Executor<'c> for &'c CountingPool {
+    type Database = Postgres;
+
+    fn fetch_many<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxStream<'e, Result<Either<PgQueryResult, PgRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: 'q + Execute<'q, Postgres>,
+    {
+        self.count();
+        self.pool.fetch_many(query)
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxFuture<'e, Result<Option<PgRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: 'q + Execute<'q, Postgres>,
+    {
+        self.count();
+        self.pool.fetch_optional(query)
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [PgTypeInfo],
+    ) -> BoxFuture<'e, Result<PgStatement<'q>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        self.pool.prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+    ) -> BoxFuture<'e, Result<Describe<Postgres>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        self.pool.describe(sql)
+    }
+}
+
+impl Db for CountingPool {
+    type Executor<'c> = &'c CountingPool;
+
+    fn executor(&self) -> &CountingPool {
+        self
+    }
+
+    fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+/// Counts the queries of the request and sends the count along
+pub async fn count_queries(request: Request, next: Next) -> Response {
+    QUERIES
+        .scope(Cell::new(0), async {
+            let mut response = next.run(request).await;
+            let count = QUERIES.with(Cell::get);
+            response
+                .headers_mut()
+                .insert(QUERY_COUNT_HEADER, count.into());
+            response
+        })
+        .await
+}
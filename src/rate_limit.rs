@@ -0,0 +1,189 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+};
+
+use anyhow::Context;
+use axum::{
+    extract::Request,
+    response::{IntoResponse, Response},
+};
+use fred::{
+    interfaces::KeysInterface,
+    types::{Expiration, SetOptions},
+};
+use http::{StatusCode, header};
+use time::OffsetDateTime;
+use tower::{Layer, Service};
+
+use crate::{api_error::ApiError, app::ApiContext, client_ip::client_ip, redis::Redis};
+
+/// Per client IP token bucket in front of a route, holding `limit` requests
+/// and refilling completely over `window`.
+///
+/// The buckets live in Redis, so the limit holds across instances. Needs the
+/// [`ApiContext`] as a request extension, see [`crate::app::Application::build`].
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Key prefix of the buckets, unique across limits
+    name: &'static str,
+    limit: u32,
+    window: Duration,
+}
+
+impl RateLimit {
+    pub const fn new(name: &'static str, limit: u32, window: Duration) -> Self {
+        Self {
+            name,
+            limit,
+            window,
+        }
+    }
+
+    /// Takes a token from the bucket of `subject`, usually the client IP,
+    /// returning how long to wait if it is empty.
+    ///
+    /// The bucket is stored as the time it will be full again, which each
+    /// request pushes back by the time one token takes to refill. A request
+    /// that would push it further than a whole window ahead finds the bucket
+    /// empty. This needs only atomic commands, so concurrent requests can't
+    /// take the same token.
+    pub async fn check(
+        &self,
+        redis: &Redis,
+        subject: impl std::fmt::Display,
+    ) -> Result<Option<Duration>, anyhow::Error> {
+        let key = redis.key(format!("rate_limit:{}:{subject}", self.name));
+        let window_ms = self.window.as_millis() as i64;
+        let refill_ms = window_ms / i64::from(self.limit.max(1));
+        let now_ms = (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64;
+
+        // the key expires once the bucket is full, a missing one is full now
+        let _: Option<String> = redis
+            .set(
+                &key,
+                now_ms,
+                Some(Expiration::PX(window_ms)),
+                Some(SetOptions::NX),
+                false,
+            )
+            .await
+            .context("Failed to initialize rate limit bucket")?;
+        let full_at: i64 = redis
+            .incr_by(&key, refill_ms)
+            .await
+            .context("Failed to take rate limit token")?;
+
+        let backlog_ms = full_at - now_ms;
+        if backlog_ms > window_ms {
+            let _: i64 = redis
+                .decr_by(&key, refill_ms)
+                .await
+                .context("Failed to return rate limit token")?;
+            return Ok(Some(Duration::from_millis((backlog_ms - window_ms) as u64)));
+        }
+
+        // also clears a key that expired right before the increment recreated it
+        let _: bool = redis
+            .pexpire(&key, backlog_ms.max(1), None)
+            .await
+            .context("Failed to set rate limit expiry")?;
+
+        Ok(None)
+    }
+
+    /// How long `subject` has to wait, if the limits are enabled and the
+    /// request has a subject. An unavailable Redis shouldn't take the routes
+    /// down with it, requests are let through when the bucket can't be read.
+    pub async fn retry_after(
+        &self,
+        api_context: &ApiContext,
+        subject: Option<impl std::fmt::Display>,
+    ) -> Option<Duration> {
+        if !api_context.config.application_settings.rate_limit_enabled {
+            return None;
+        }
+        match self.check(&api_context.redis, subject?).await {
+            Ok(retry_after) => retry_after,
+            Err(e) => {
+                tracing::error!(limit = self.name, error = ?e, "Failed to apply rate limit");
+                None
+            }
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimit {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            rate_limit: *self,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    rate_limit: RateLimit,
+}
+
+impl<S> Service<Request> for RateLimitService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // the clone might not be ready, the one that was polled is used instead
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let rate_limit = self.rate_limit;
+
+        Box::pin(async move {
+            let Some(api_context) = request.extensions().get::<Arc<ApiContext>>().cloned() else {
+                tracing::error!("Missing api context, the rate limit is not applied");
+                return inner.call(request).await;
+            };
+            let client_ip = client_ip(request.extensions(), request.headers());
+            match rate_limit.retry_after(&api_context, client_ip).await {
+                None => inner.call(request).await,
+                Some(retry_after) => Ok(too_many_requests(retry_after)),
+            }
+        })
+    }
+}
+
+/// `retry_after` in the whole seconds of a `Retry-After` header, rounded up
+/// so a client retrying on time gets through
+pub fn retry_after_secs(retry_after: Duration) -> u64 {
+    (retry_after.as_millis().div_ceil(1000) as u64).max(1)
+}
+
+pub fn too_many_requests(retry_after: Duration) -> Response {
+    (
+        [(
+            header::RETRY_AFTER,
+            retry_after_secs(retry_after).to_string(),
+        )],
+        ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Too many requests, try again later",
+        ),
+    )
+        .into_response()
+}
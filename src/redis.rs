@@ -0,0 +1,255 @@
+//! The Redis pool the app shares for sessions, the user cache, rate limits
+//! and the maintenance flag.
+//!
+//! Every key goes under `REDIS_KEY_PREFIX`, so several environments can
+//! share one Redis, see [`Redis::key`].
+
+use std::{
+    fmt,
+    ops::Deref,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, anyhow};
+use fred::{
+    interfaces::{ClientLike, EventInterface},
+    prelude::{Pool, ReconnectPolicy},
+};
+use secrecy::ExposeSecret;
+use tokio::time::Instant;
+
+use crate::config::Config;
+
+/// How long each connection attempt gets before it counts as failed
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Clients in the pool
+const POOL_SIZE: usize = 100;
+/// Wait after the first failed attempt at startup, doubled after each one
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The pool, dereferencing to it for the commands, along with the prefix
+/// of the keys
+#[derive(Clone)]
+pub struct Redis {
+    pool: Pool,
+    prefix: Arc<str>,
+    /// Reconnections of the clients of the pool since startup
+    reconnects: Arc<AtomicU64>,
+}
+
+impl fmt::Debug for Redis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Redis")
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Deref for Redis {
+    type Target = Pool;
+
+    fn deref(&self) -> &Pool {
+        &self.pool
+    }
+}
+
+/// What `/health/ready` reports of the pool
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RedisHealth {
+    pub connected_clients: usize,
+    pub clients: usize,
+    pub reconnects: u64,
+}
+
+impl RedisHealth {
+    pub fn is_up(&self) -> bool {
+        self.connected_clients > 0
+    }
+}
+
+impl Redis {
+    pub fn new(pool: Pool, prefix: &str) -> Self {
+        Self {
+            pool,
+            prefix: prefix.into(),
+            reconnects: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// The key to store `key` under. Every key the app uses goes through
+    /// here.
+    pub fn key(&self, key: impl fmt::Display) -> String {
+        format!("{}{key}", self.prefix)
+    }
+
+    pub fn health(&self) -> RedisHealth {
+        let clients = self.pool.clients();
+        RedisHealth {
+            connected_clients: clients
+                .iter()
+                .filter(|client| client.is_connected())
+                .count(),
+            clients: clients.len(),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Counts the reconnections of the clients, once they connected
+    fn count_reconnects(&self) {
+        for client in self.pool.clients() {
+            let reconnects = self.reconnects.clone();
+            client.on_reconnect(move |server| {
+                let reconnects = reconnects.clone();
+                async move {
+                    reconnects.fetch_add(1, Ordering::Relaxed);
+                    tracing::info!(%server, "Reconnected to Redis");
+                    Ok(())
+                }
+            });
+        }
+    }
+}
+
+/// Connects to the Redis database of the app, waiting for Redis to come up
+/// for `REDIS_STARTUP_WAIT_SECS`. Once connected, the pool keeps
+/// reconnecting if the connection drops.
+pub async fn connect(config: &Config) -> Result<Redis, anyhow::Error> {
+    let settings = &config.database_settings;
+    let redis_config = redis_config(config)?;
+    wait_for(&redis_config, startup_wait(config)).await?;
+
+    let pool = fred::prelude::Builder::from_config(redis_config)
+        .with_connection_config(|redis_config| {
+            redis_config.connection_timeout = CONNECT_TIMEOUT;
+        })
+        // use exponential backoff, starting at 100 ms and doubling on each failed attempt up to 30 sec
+        .set_policy(ReconnectPolicy::new_exponential(0, 100, 30_000, 2))
+        .build_pool(POOL_SIZE)
+        .context("Failed to create Redis pool")?;
+    pool.init().await.context("Can't connect to Redis")?;
+
+    let redis = Redis::new(pool, &settings.redis_key_prefix);
+    redis.count_reconnects();
+    Ok(redis)
+}
+
+/// Waits for Redis to answer for `REDIS_STARTUP_WAIT_SECS`, for the checks
+/// before the app starts
+pub async fn wait_until_up(config: &Config) -> Result<(), anyhow::Error> {
+    wait_for(&redis_config(config)?, startup_wait(config)).await
+}
+
+fn redis_config(config: &Config) -> Result<fred::prelude::Config, anyhow::Error> {
+    fred::prelude::Config::from_url(&format!(
+        "{}/1",
+        config.database_settings.redis_url.expose_secret()
+    ))
+    .context("Invalid REDIS_URL")
+}
+
+fn startup_wait(config: &Config) -> Duration {
+    Duration::from_secs(config.database_settings.redis_startup_wait_secs)
+}
+
+/// Tries to connect until Redis answers, backing off between attempts, and
+/// gives up after `wait`
+async fn wait_for(
+    redis_config: &fred::prelude::Config,
+    wait: Duration,
+) -> Result<(), anyhow::Error> {
+    let give_up_at = Instant::now() + wait;
+    let mut delay = FIRST_RETRY_DELAY;
+    for attempt in 1.. {
+        let e = match ping(redis_config).await {
+            Ok(()) => {
+                if attempt > 1 {
+                    tracing::info!(attempt, "Redis is up");
+                }
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        if Instant::now() + delay > give_up_at {
+            return Err(e.context(anyhow!(
+                "Redis isn't up after {attempt} attempts in {}s",
+                wait.as_secs()
+            )));
+        }
+        tracing::warn!(
+            attempt,
+            error = %format!("{e:#}"),
+            "Redis isn't up yet, retrying in {delay:?}"
+        );
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+    }
+    unreachable!("the attempts never run out")
+}
+
+/// Connects a single client, without reconnecting, and pings
+async fn ping(redis_config: &fred::prelude::Config) -> Result<(), anyhow::Error> {
+    let client = fred::prelude::Builder::from_config(redis_config.clone())
+        .with_connection_config(|redis_config| {
+            redis_config.connection_timeout = CONNECT_TIMEOUT;
+        })
+        .build()
+        .context("Invalid REDIS_URL")?;
+    client.init().await.context("Can't connect to Redis")?;
+    let ping = client
+        .ping::<()>(None)
+        .await
+        .context("Redis doesn't answer PING");
+    let _ = client.quit().await;
+    ping
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unconnected(prefix: &str) -> Redis {
+        let pool = fred::prelude::Builder::default_centralized()
+            .build_pool(2)
+            .unwrap();
+        Redis::new(pool, prefix)
+    }
+
+    #[test]
+    fn keys_go_under_the_prefix() {
+        assert_eq!(
+            "staging:user_sessions:1",
+            unconnected("staging:").key("user_sessions:1")
+        );
+        assert_eq!("maintenance", unconnected("").key("maintenance"));
+    }
+
+    #[test]
+    fn unconnected_pool_is_down() {
+        let health = unconnected("").health();
+        assert_eq!(
+            RedisHealth {
+                connected_clients: 0,
+                clients: 2,
+                reconnects: 0
+            },
+            health
+        );
+        assert!(!health.is_up());
+    }
+
+    #[tokio::test]
+    async fn waiting_gives_up_after_the_window() {
+        // nothing listens on port 1
+        let redis_config = fred::prelude::Config::from_url("redis://127.0.0.1:1").unwrap();
+
+        let e = wait_for(&redis_config, Duration::from_millis(300))
+            .await
+            .unwrap_err();
+        assert!(format!("{e:#}").contains("Redis isn't up after"), "{e:#}");
+    }
+}
@@ -0,0 +1,725 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Form, Router,
+    extract::{Path, Query, State},
+    middleware,
+    response::{AppendHeaders, IntoResponse},
+    routing::{get, post},
+};
+use axum_login::login_required;
+use http::StatusCode;
+use time::OffsetDateTime;
+use time_tz::Tz;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{
+    app::{ApiContext, AppRouter},
+    audit::{self, AuditEntry, AuditEvent, AuditLogRow, RequestMetadata},
+    auth::{
+        AuthSession, Backend, Role,
+        impersonation::{self, Impersonator},
+        invitation::{self, InvitationRow},
+        require_admin, revoke_refresh_tokens, sessions,
+    },
+    config::RegistrationMode,
+    domain::email_address::EmailAddress,
+    emails::{self, DeadEmail},
+    maintenance::{self, Maintenance},
+    page::PageContext,
+    routes::{todo::filters, tos},
+    urls::UrlBuilder,
+};
+
+const USERS_PER_PAGE: i64 = 25;
+const AUDIT_ENTRIES_PER_PAGE: i64 = 50;
+/// Most dead emails listed, the latest ones
+const DEAD_EMAILS_SHOWN: i64 = 100;
+
+pub fn router() -> AppRouter {
+    Router::new()
+        .route("/admin", get(users_page))
+        .route("/admin/users/{user_id}/lock", post(lock_user))
+        .route("/admin/users/{user_id}/unlock", post(unlock_user))
+        .route("/admin/users/{user_id}/todo-limit", post(set_todo_limit))
+        .route(
+            "/admin/users/{user_id}/impersonate",
+            post(impersonate_user).route_layer(middleware::from_fn(
+                impersonation::forbid_while_impersonating,
+            )),
+        )
+        .route(
+            "/admin/invitations",
+            get(invitations_page).post(create_invitation),
+        )
+        .route("/admin/audit", get(audit_log_page))
+        .route("/admin/emails", get(dead_emails_page))
+        .route("/admin/maintenance", post(start_maintenance))
+        .route("/admin/maintenance/clear", post(end_maintenance))
+        .route_layer(middleware::from_fn(require_admin))
+        // by then the session is logged in as the user, who usually isn't an admin
+        .route("/admin/stop-impersonating", post(stop_impersonating))
+        .route_layer(middleware::from_fn(tos::require_accepted_tos))
+        .route_layer(login_required!(Backend, login_url = "/login"))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AdminError {
+    #[error("You can't lock your own account")]
+    CannotLockSelf,
+    #[error("User not found")]
+    UserNotFound,
+    #[error("Unknown event type")]
+    InvalidEventType,
+    #[error("The expected duration has to be a positive number of minutes")]
+    InvalidEta,
+    #[error("The todo limit has to be a number of todos, or empty for the default")]
+    InvalidTodoLimit,
+    #[error("Invalid email address")]
+    InvalidInvitationEmail,
+    #[error("An invitation has to be usable at least once")]
+    InvalidInvitationUses,
+    #[error("An invitation has to expire in a positive number of days, or never")]
+    InvalidInvitationExpiry,
+    #[error("You can't view as yourself")]
+    CannotImpersonateSelf,
+    #[error("You aren't viewing as another user")]
+    NotImpersonating,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match self {
+            AdminError::CannotLockSelf
+            | AdminError::InvalidEventType
+            | AdminError::InvalidEta
+            | AdminError::InvalidTodoLimit
+            | AdminError::InvalidInvitationEmail
+            | AdminError::InvalidInvitationUses
+            | AdminError::InvalidInvitationExpiry
+            | AdminError::CannotImpersonateSelf
+            | AdminError::NotImpersonating => StatusCode::BAD_REQUEST,
+            AdminError::UserNotFound => StatusCode::NOT_FOUND,
+            AdminError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+struct UserSummary {
+    user_id: Uuid,
+    username: String,
+    email: String,
+    role: Role,
+    created_at: OffsetDateTime,
+    last_login_at: Option<OffsetDateTime>,
+    locked_at: Option<OffsetDateTime>,
+    todo_count: i64,
+    /// `None` for the configured limit
+    todo_limit: Option<i64>,
+}
+
+/// Latest run of a periodic task, see [`crate::worker::scheduler`]
+struct ScheduledTaskRun {
+    name: String,
+    last_started_at: OffsetDateTime,
+    last_duration_ms: i64,
+    last_error: Option<String>,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "admin/users.html")]
+struct UsersTemplate {
+    current_user_id: Uuid,
+    users: Vec<UserSummary>,
+    q: String,
+    page: i64,
+    has_next_page: bool,
+    tasks: Vec<ScheduledTaskRun>,
+    maintenance: Option<Maintenance>,
+    /// Of the admin looking at the page
+    timezone: &'static Tz,
+    page_context: PageContext,
+    /// Of users without a limit of their own
+    default_todo_limit: i64,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UsersQuery {
+    q: Option<String>,
+    page: Option<i64>,
+}
+
+async fn users_page(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    page_context: PageContext,
+    Query(query): Query<UsersQuery>,
+) -> Result<impl IntoResponse, AdminError> {
+    let current_user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let q = query.q.unwrap_or_default().trim().to_string();
+    let page = query.page.unwrap_or(1).max(1);
+
+    // one extra row tells whether there is a next page
+    let mut users = sqlx::query_as!(
+        UserSummary,
+        r#"
+        SELECT
+            ui.user_id, ui.username, ui.email, ui.role AS "role: Role", ui.created_at,
+            ui.last_login_at, ui.locked_at, ui.active_todo_count AS todo_count, ui.todo_limit
+        FROM user_info AS ui
+        WHERE $1 = ''
+            -- substring search isn't supported on the case insensitive collation
+            OR strpos(lower(ui.username COLLATE "default"), lower($1)) > 0
+            OR strpos(lower(ui.email COLLATE "default"), lower($1)) > 0
+        ORDER BY ui.created_at, ui.username
+        LIMIT $2 OFFSET $3
+        "#,
+        q,
+        USERS_PER_PAGE + 1,
+        (page - 1) * USERS_PER_PAGE
+    )
+    .fetch_all(&api_context.db)
+    .await
+    .context("Failed to get users")?;
+
+    let has_next_page = users.len() as i64 > USERS_PER_PAGE;
+    users.truncate(USERS_PER_PAGE as usize);
+
+    let tasks = sqlx::query_as!(
+        ScheduledTaskRun,
+        r#"
+        SELECT name, last_started_at, last_duration_ms, last_error
+        FROM scheduled_task
+        ORDER BY name
+        "#
+    )
+    .fetch_all(&api_context.db)
+    .await
+    .context("Failed to get scheduled tasks")?;
+
+    let maintenance = maintenance::get(
+        &api_context.redis,
+        &api_context
+            .config
+            .application_settings
+            .maintenance_redis_key,
+    )
+    .await?;
+    let preferences = api_context
+        .preferences
+        .get(&api_context.db, current_user.user_id())
+        .await?;
+
+    Ok(UsersTemplate {
+        current_user_id: current_user.user_id(),
+        users,
+        q,
+        page,
+        has_next_page,
+        tasks,
+        maintenance,
+        timezone: preferences.tz(),
+        page_context,
+        default_todo_limit: api_context.config.application_settings.max_active_todos,
+    })
+}
+
+async fn lock_user(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    request: RequestMetadata,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AdminError> {
+    let current_user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+    if current_user.user_id() == user_id {
+        return Err(AdminError::CannotLockSelf);
+    }
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_info SET locked_at = COALESCE(locked_at, NOW()) WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&api_context.db)
+    .await
+    .context("Failed to lock user")?;
+    if result.rows_affected() == 0 {
+        return Err(AdminError::UserNotFound);
+    }
+
+    // locked users are rejected on their next request anyway, this also frees the store
+    sessions::delete_user_sessions(&api_context.redis, user_id).await?;
+    revoke_refresh_tokens(&api_context.db, user_id).await?;
+    api_context.user_cache.invalidate(user_id).await;
+
+    api_context.audit.record(
+        AuditEntry::new(AuditEvent::AccountLocked, Some(user_id), &request)
+            .with_metadata(serde_json::json!({ "admin": current_user.username })),
+    );
+
+    Ok((StatusCode::OK, AppendHeaders([("HX-Redirect", "/admin")])))
+}
+
+async fn unlock_user(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    request: RequestMetadata,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AdminError> {
+    let current_user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_info SET locked_at = NULL WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&api_context.db)
+    .await
+    .context("Failed to unlock user")?;
+    if result.rows_affected() == 0 {
+        return Err(AdminError::UserNotFound);
+    }
+
+    api_context.audit.record(
+        AuditEntry::new(AuditEvent::AccountUnlocked, Some(user_id), &request)
+            .with_metadata(serde_json::json!({ "admin": current_user.username })),
+    );
+
+    Ok((StatusCode::OK, AppendHeaders([("HX-Redirect", "/admin")])))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TodoLimitFormData {
+    /// Empty to go back to the configured limit
+    todo_limit: String,
+}
+
+async fn set_todo_limit(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    request: RequestMetadata,
+    Path(user_id): Path<Uuid>,
+    Form(form_data): Form<TodoLimitFormData>,
+) -> Result<impl IntoResponse, AdminError> {
+    let current_user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let todo_limit = match form_data.todo_limit.trim() {
+        "" => None,
+        todo_limit => match todo_limit.parse::<i64>() {
+            Ok(todo_limit) if todo_limit >= 0 => Some(todo_limit),
+            _ => return Err(AdminError::InvalidTodoLimit),
+        },
+    };
+
+    // lowering the limit below the todos the user has only stops them from
+    // adding more, none are deleted
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_info SET todo_limit = $2 WHERE user_id = $1
+        "#,
+        user_id,
+        todo_limit
+    )
+    .execute(&api_context.db)
+    .await
+    .context("Failed to set todo limit")?;
+    if result.rows_affected() == 0 {
+        return Err(AdminError::UserNotFound);
+    }
+
+    api_context.audit.record(
+        AuditEntry::new(AuditEvent::TodoLimitChanged, Some(user_id), &request).with_metadata(
+            serde_json::json!({ "admin": current_user.username, "todo_limit": todo_limit }),
+        ),
+    );
+
+    Ok((StatusCode::OK, AppendHeaders([("HX-Redirect", "/admin")])))
+}
+
+/// Logs the session in as the user, remembering the admin, see
+/// [`impersonation`]. The user isn't told, and doesn't get a new device alert.
+async fn impersonate_user(
+    State(api_context): State<Arc<ApiContext>>,
+    mut auth_session: AuthSession,
+    session: Session,
+    request: RequestMetadata,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AdminError> {
+    let current_user = auth_session
+        .user
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+    if current_user.user_id() == user_id {
+        return Err(AdminError::CannotImpersonateSelf);
+    }
+    // locked users aren't found, they couldn't be logged in as
+    let user = api_context
+        .users
+        .find_by_id(user_id)
+        .await?
+        .ok_or(AdminError::UserNotFound)?;
+
+    // recorded before anything happens, so no impersonation goes unrecorded
+    audit::record_now(
+        &api_context.db,
+        AuditEntry::new(AuditEvent::ImpersonationStarted, Some(user_id), &request)
+            .with_metadata(serde_json::json!({ "admin": current_user.username })),
+    )
+    .await?;
+
+    if let Some(session_id) = session.id() {
+        sessions::untrack_session(&api_context.redis, current_user.user_id(), session_id).await?;
+    }
+    auth_session
+        .login(&user)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to log in as user: {e}"))?;
+    impersonation::start(
+        &session,
+        Impersonator {
+            user_id: current_user.user_id(),
+            username: current_user.username.clone(),
+        },
+    )
+    .await?;
+    sessions::rotate_session(&api_context.redis, &session, Some(user_id)).await?;
+    tracing::info!(admin = %current_user.username, user = %user.username, "Started impersonation");
+
+    Ok((StatusCode::OK, AppendHeaders([("HX-Redirect", "/")])))
+}
+
+/// Logs the session back in as the admin who was viewing as its user
+async fn stop_impersonating(
+    State(api_context): State<Arc<ApiContext>>,
+    mut auth_session: AuthSession,
+    session: Session,
+    request: RequestMetadata,
+) -> Result<impl IntoResponse, AdminError> {
+    let user = auth_session
+        .user
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+    let impersonator = impersonation::impersonator(&session)
+        .await?
+        .ok_or(AdminError::NotImpersonating)?;
+
+    api_context.audit.record(
+        AuditEntry::new(
+            AuditEvent::ImpersonationEnded,
+            Some(user.user_id()),
+            &request,
+        )
+        .with_metadata(serde_json::json!({ "admin": impersonator.username })),
+    );
+
+    if let Some(session_id) = session.id() {
+        sessions::untrack_session(&api_context.redis, user.user_id(), session_id).await?;
+    }
+    // an admin who was locked or demoted meanwhile only gets logged out
+    let admin = api_context
+        .users
+        .find_by_id(impersonator.user_id)
+        .await?
+        .filter(|admin| admin.is_admin());
+    let Some(admin) = admin else {
+        auth_session
+            .logout()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to log out: {e}"))?;
+        return Ok((StatusCode::OK, AppendHeaders([("HX-Redirect", "/login")])));
+    };
+
+    impersonation::stop(&session).await?;
+    auth_session
+        .login(&admin)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to log back in as admin: {e}"))?;
+    sessions::rotate_session(&api_context.redis, &session, Some(admin.user_id())).await?;
+    tracing::info!(admin = %admin.username, user = %user.username, "Ended impersonation");
+
+    Ok((StatusCode::OK, AppendHeaders([("HX-Redirect", "/admin")])))
+}
+
+#[derive(serde::Deserialize)]
+pub struct MaintenanceFormData {
+    message: Option<String>,
+    /// Empty when no end is announced
+    eta_minutes: Option<String>,
+    /// Checkbox, only sent when checked
+    admin_bypass: Option<String>,
+}
+
+async fn start_maintenance(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Form(form_data): Form<MaintenanceFormData>,
+) -> Result<impl IntoResponse, AdminError> {
+    let current_user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let until = match form_data.eta_minutes.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(minutes) => {
+            let minutes: i64 = minutes.parse().map_err(|_| AdminError::InvalidEta)?;
+            if minutes <= 0 {
+                return Err(AdminError::InvalidEta);
+            }
+            Some(OffsetDateTime::now_utc() + time::Duration::minutes(minutes))
+        }
+    };
+    let maintenance = Maintenance {
+        message: form_data
+            .message
+            .map(|message| message.trim().to_string())
+            .filter(|message| !message.is_empty()),
+        until,
+        admin_bypass: form_data.admin_bypass.is_some(),
+    };
+    maintenance::start(
+        &api_context.redis,
+        &api_context
+            .config
+            .application_settings
+            .maintenance_redis_key,
+        &maintenance,
+    )
+    .await?;
+    tracing::info!(admin = %current_user.username, "Started maintenance");
+
+    Ok((StatusCode::OK, AppendHeaders([("HX-Redirect", "/admin")])))
+}
+
+async fn end_maintenance(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+) -> Result<impl IntoResponse, AdminError> {
+    let current_user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    maintenance::end(
+        &api_context.redis,
+        &api_context
+            .config
+            .application_settings
+            .maintenance_redis_key,
+    )
+    .await?;
+    tracing::info!(admin = %current_user.username, "Ended maintenance");
+
+    Ok((StatusCode::OK, AppendHeaders([("HX-Redirect", "/admin")])))
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "admin/invitations.html")]
+struct InvitationsTemplate {
+    invitations: Vec<InvitationRow>,
+    registration_mode: RegistrationMode,
+    urls: UrlBuilder,
+    /// Of the admin looking at the page
+    timezone: &'static Tz,
+    page_context: PageContext,
+}
+
+impl InvitationsTemplate {
+    fn invite_only(&self) -> bool {
+        self.registration_mode == RegistrationMode::Invite
+    }
+}
+
+async fn invitations_page(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    page_context: PageContext,
+) -> Result<impl IntoResponse, AdminError> {
+    let current_user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let invitations = invitation::latest(&api_context.db).await?;
+    let preferences = api_context
+        .preferences
+        .get(&api_context.db, current_user.user_id())
+        .await?;
+    Ok(InvitationsTemplate {
+        invitations,
+        registration_mode: api_context.config.application_settings.registration_mode,
+        urls: api_context.urls.clone(),
+        timezone: preferences.tz(),
+        page_context,
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct InvitationFormData {
+    /// Empty for an invitation anyone can use
+    #[serde(default)]
+    email: String,
+    /// Empty for a single use
+    #[serde(default)]
+    uses: String,
+    /// Empty for an invitation that doesn't expire
+    #[serde(default)]
+    expires_in_days: String,
+}
+
+async fn create_invitation(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Form(form_data): Form<InvitationFormData>,
+) -> Result<impl IntoResponse, AdminError> {
+    let current_user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let email = match form_data.email.trim() {
+        "" => None,
+        email => Some(EmailAddress::parse(email).map_err(|_| AdminError::InvalidInvitationEmail)?),
+    };
+    let uses = match form_data.uses.trim() {
+        "" => 1,
+        uses => match uses.parse::<i32>() {
+            Ok(uses) if uses > 0 => uses,
+            _ => return Err(AdminError::InvalidInvitationUses),
+        },
+    };
+    let expires_at = match form_data.expires_in_days.trim() {
+        "" => None,
+        days => match days.parse::<i64>() {
+            Ok(days) if days > 0 => Some(OffsetDateTime::now_utc() + time::Duration::days(days)),
+            _ => return Err(AdminError::InvalidInvitationExpiry),
+        },
+    };
+
+    invitation::create(
+        &api_context.db,
+        current_user.user_id(),
+        email.as_ref(),
+        uses,
+        expires_at,
+    )
+    .await?;
+    tracing::info!(admin = %current_user.username, uses, "Created invitation");
+
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([("HX-Redirect", "/admin/invitations")]),
+    ))
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "admin/audit.html")]
+struct AuditLogTemplate {
+    entries: Vec<AuditLogRow>,
+    user: String,
+    event: String,
+    page: i64,
+    has_next_page: bool,
+    /// Of the admin looking at the page
+    timezone: &'static Tz,
+    page_context: PageContext,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AuditLogQuery {
+    user: Option<String>,
+    event: Option<String>,
+    page: Option<i64>,
+}
+
+async fn audit_log_page(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    page_context: PageContext,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<impl IntoResponse, AdminError> {
+    let current_user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+    let user = query.user.unwrap_or_default().trim().to_string();
+    let event = query.event.unwrap_or_default();
+    let event_filter = match event.as_str() {
+        "" => None,
+        s => Some(AuditEvent::parse(s).ok_or(AdminError::InvalidEventType)?),
+    };
+    let page = query.page.unwrap_or(1).max(1);
+
+    let mut entries = audit::fetch_entries(
+        &api_context.db,
+        Some(user.as_str()).filter(|user| !user.is_empty()),
+        event_filter,
+        AUDIT_ENTRIES_PER_PAGE + 1,
+        (page - 1) * AUDIT_ENTRIES_PER_PAGE,
+    )
+    .await?;
+
+    let has_next_page = entries.len() as i64 > AUDIT_ENTRIES_PER_PAGE;
+    entries.truncate(AUDIT_ENTRIES_PER_PAGE as usize);
+    let preferences = api_context
+        .preferences
+        .get(&api_context.db, current_user.user_id())
+        .await?;
+
+    Ok(AuditLogTemplate {
+        entries,
+        user,
+        event,
+        page,
+        has_next_page,
+        timezone: preferences.tz(),
+        page_context,
+    })
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "admin/emails.html")]
+struct DeadEmailsTemplate {
+    emails: Vec<DeadEmail>,
+    max_attempts: i32,
+    /// Of the admin looking at the page
+    timezone: &'static Tz,
+    page_context: PageContext,
+}
+
+/// Emails the outbox gave up on, see [`crate::worker::email`]
+async fn dead_emails_page(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    page_context: PageContext,
+) -> Result<impl IntoResponse, AdminError> {
+    let current_user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let emails = emails::dead_letters(&api_context.db, DEAD_EMAILS_SHOWN).await?;
+    let preferences = api_context
+        .preferences
+        .get(&api_context.db, current_user.user_id())
+        .await?;
+
+    Ok(DeadEmailsTemplate {
+        emails,
+        max_attempts: api_context.config.application_settings.email_max_attempts,
+        timezone: preferences.tz(),
+        page_context,
+    })
+}
@@ -0,0 +1,124 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use axum::{
+    Router,
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use http::{StatusCode, header};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    app::{ApiContext, AppRouter},
+    domain::priority::Priority,
+    ics::{Calendar, Event},
+};
+
+/// How often calendar apps are asked to reload the feed. Most of them poll
+/// less often than this whatever the feed says.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Most todos put in a feed, the ones due soonest
+const MAX_FEED_TODOS: i64 = 1000;
+
+/// The feed is authenticated by the token in its URL rather than the
+/// session, as calendar apps can't log in
+pub fn router() -> AppRouter {
+    Router::new().route("/calendar/{feed_file}", get(calendar_feed))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CalendarError {
+    #[error("Calendar feed not found")]
+    NotFound,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for CalendarError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            CalendarError::NotFound => StatusCode::NOT_FOUND,
+            CalendarError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+/// New random token for a feed URL. Only its hash is stored, so it can be
+/// shown once and never again.
+pub fn generate_feed_token() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+pub fn hash_feed_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token))
+}
+
+/// The user's incomplete todos with a due date, as all-day events
+pub async fn calendar_feed(
+    State(api_context): State<Arc<ApiContext>>,
+    Path(feed_file): Path<String>,
+) -> Result<impl IntoResponse, CalendarError> {
+    let token = feed_file
+        .strip_suffix(".ics")
+        .ok_or(CalendarError::NotFound)?;
+
+    let user_id = sqlx::query_scalar!(
+        r#"
+        SELECT user_id FROM calendar_feeds WHERE token_hash = $1
+        "#,
+        hash_feed_token(token)
+    )
+    .fetch_optional(&api_context.db)
+    .await
+    .context("Failed to get calendar feed")?
+    .ok_or(CalendarError::NotFound)?;
+
+    let todos = sqlx::query!(
+        r#"
+        SELECT
+            td.todo_id, td.todo_content, td.description, td.due_date AS "due_date!",
+            td.priority AS "priority: Priority", td.updated_at
+        FROM todo AS td
+        JOIN todo_list AS tl ON tl.list_id = td.list_id
+        WHERE tl.owner_id = $1
+            AND td.due_date IS NOT NULL
+            AND NOT td.is_completed
+            AND td.deleted_at IS NULL
+        ORDER BY td.due_date, td.created_at
+        LIMIT $2
+        "#,
+        user_id,
+        MAX_FEED_TODOS
+    )
+    .fetch_all(&api_context.db)
+    .await
+    .context("Failed to get due todos")?;
+
+    let mut calendar = Calendar::new("Todos", REFRESH_INTERVAL);
+    for todo in &todos {
+        calendar.add_event(&Event {
+            uid: format!("{}@todo", todo.todo_id),
+            stamp: todo.updated_at,
+            date: todo.due_date,
+            summary: &todo.todo_content,
+            description: Some(todo.description.as_str()).filter(|d| !d.is_empty()),
+            url: Some(api_context.urls.todo_url(todo.todo_id).to_string()),
+            priority: Some(match todo.priority {
+                Priority::High => 1,
+                Priority::Normal => 5,
+                Priority::Low => 9,
+            }),
+        });
+    }
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/calendar; charset=utf-8"),
+            (header::CACHE_CONTROL, "private, no-cache"),
+        ],
+        calendar.finish(),
+    ))
+}
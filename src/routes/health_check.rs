@@ -1,9 +1,62 @@
-use axum::{Router, routing::get};
+use std::{sync::Arc, time::Duration};
 
-use crate::app::AppRouter;
+use axum::{Json, Router, extract::State, response::IntoResponse, routing::get};
+use fred::interfaces::ClientLike;
+use http::StatusCode;
+
+use crate::{
+    app::{ApiContext, AppRouter},
+    redis::RedisHealth,
+    routes::todo::view_cache::ViewCacheStats,
+};
+
+/// How long Postgres and Redis get to answer a readiness check
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
 
 pub fn router() -> AppRouter {
-    Router::new().route("/health_check", get(health_check))
+    Router::new()
+        .route("/health_check", get(health_check))
+        .route("/health/ready", get(ready))
 }
 
+/// The app is running, whether or not it can serve requests
 async fn health_check() {}
+
+#[derive(Debug, serde::Serialize)]
+struct Readiness {
+    postgres: bool,
+    redis: bool,
+    redis_pool: RedisHealth,
+    list_cache: ViewCacheStats,
+}
+
+/// Whether Postgres and Redis answer, for load balancers to only send
+/// requests to instances that can serve them. 503 when either doesn't.
+async fn ready(State(api_context): State<Arc<ApiContext>>) -> impl IntoResponse {
+    let postgres = tokio::time::timeout(
+        READY_TIMEOUT,
+        sqlx::query("SELECT 1").execute(&api_context.db),
+    )
+    .await
+    .is_ok_and(|result| result.is_ok());
+    let redis_pool = api_context.redis.health();
+    let redis = redis_pool.is_up()
+        && tokio::time::timeout(READY_TIMEOUT, api_context.redis.ping::<()>(None))
+            .await
+            .is_ok_and(|result| result.is_ok());
+
+    let status = if postgres && redis {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(Readiness {
+            postgres,
+            redis,
+            redis_pool,
+            list_cache: api_context.view_cache.stats(),
+        }),
+    )
+}
@@ -1,3 +1,9 @@
+pub mod admin;
+pub mod calendar;
 pub mod health_check;
+pub mod notifications;
 pub mod root;
+pub mod settings;
+pub mod stats;
 pub mod todo;
+pub mod tos;
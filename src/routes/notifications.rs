@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Router,
+    extract::{Path, State},
+    middleware,
+    response::{AppendHeaders, IntoResponse},
+    routing::{get, post},
+};
+use axum_login::login_required;
+use http::StatusCode;
+use time_tz::Tz;
+use uuid::Uuid;
+
+use crate::{
+    app::{ApiContext, AppRouter},
+    auth::{AuthSession, Backend},
+    notifications::{self, NotificationRow},
+    page::PageContext,
+    routes::{todo::filters, tos},
+};
+
+pub fn router() -> AppRouter {
+    Router::new()
+        .route("/notifications", get(notifications_page))
+        .route("/notifications/bell", get(bell))
+        .route(
+            "/notifications/{notification_id}/read",
+            post(mark_notification_read),
+        )
+        .route("/notifications/read-all", post(mark_all_notifications_read))
+        .route_layer(middleware::from_fn(tos::require_accepted_tos))
+        .route_layer(login_required!(Backend, login_url = "/login"))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum NotificationError {
+    #[error("Notification not found")]
+    NotFound,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for NotificationError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match self {
+            NotificationError::NotFound => StatusCode::NOT_FOUND,
+            NotificationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "notifications/notifications.html")]
+struct NotificationsTemplate {
+    page_context: PageContext,
+    notifications: Vec<NotificationRow>,
+    timezone: &'static Tz,
+}
+
+impl NotificationsTemplate {
+    fn has_unread(&self) -> bool {
+        self.notifications.iter().any(|row| !row.is_read())
+    }
+}
+
+async fn notifications_page(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    page_context: PageContext,
+) -> Result<impl IntoResponse, NotificationError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let notifications = notifications::latest(&api_context.db, user.user_id()).await?;
+    let preferences = api_context
+        .preferences
+        .get(&api_context.db, user.user_id())
+        .await?;
+
+    Ok(NotificationsTemplate {
+        page_context,
+        notifications,
+        timezone: preferences.tz(),
+    })
+}
+
+/// The unread count next to the bell of `base.html`, which polls it
+#[derive(Template, WebTemplate)]
+#[template(path = "notifications/bell.html")]
+struct BellTemplate {
+    unread: i64,
+}
+
+async fn bell(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+) -> Result<impl IntoResponse, NotificationError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let unread = notifications::unread_count(&api_context.db, user.user_id()).await?;
+    Ok(BellTemplate { unread })
+}
+
+async fn mark_notification_read(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path(notification_id): Path<Uuid>,
+) -> Result<impl IntoResponse, NotificationError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    if !notifications::mark_read(&api_context.db, user.user_id(), notification_id).await? {
+        return Err(NotificationError::NotFound);
+    }
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([("HX-Redirect", "/notifications")]),
+    ))
+}
+
+async fn mark_all_notifications_read(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+) -> Result<impl IntoResponse, NotificationError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    notifications::mark_all_read(&api_context.db, user.user_id()).await?;
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([("HX-Redirect", "/notifications")]),
+    ))
+}
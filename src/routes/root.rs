@@ -2,10 +2,73 @@ use askama::Template;
 use askama_web::WebTemplate;
 use axum::response::IntoResponse;
 
+use crate::page::PageContext;
+
 #[derive(Template, WebTemplate)]
 #[template(path = "root.html")]
-struct RootTemplate;
+struct RootTemplate {
+    page_context: PageContext,
+}
+
+pub async fn get_homepage(page_context: PageContext) -> impl IntoResponse {
+    RootTemplate { page_context }.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use askama::Template;
+    use uuid::Uuid;
+
+    use super::RootTemplate;
+    use crate::{
+        auth::impersonation::Impersonator,
+        page::{CurrentUser, PageContext},
+    };
+
+    #[test]
+    fn homepage_for_a_logged_in_user() {
+        let html = RootTemplate {
+            page_context: PageContext {
+                user: Some(CurrentUser {
+                    user_id: Uuid::from_u128(1),
+                    username: "alice".to_string(),
+                }),
+                ..PageContext::default()
+            },
+        }
+        .render()
+        .unwrap();
+        insta::assert_snapshot!(html);
+    }
+
+    #[test]
+    fn homepage_for_an_admin_viewing_as_a_user() {
+        let html = RootTemplate {
+            page_context: PageContext {
+                user: Some(CurrentUser {
+                    user_id: Uuid::from_u128(1),
+                    username: "alice".to_string(),
+                }),
+                impersonator: Some(Impersonator {
+                    user_id: Uuid::from_u128(2),
+                    username: "admin".to_string(),
+                }),
+                ..PageContext::default()
+            },
+        }
+        .render()
+        .unwrap();
+        assert!(html.contains("Viewing as alice"));
+        insta::assert_snapshot!(html);
+    }
 
-pub async fn get_homepage() -> impl IntoResponse {
-    RootTemplate.into_response()
+    #[test]
+    fn homepage_for_a_visitor() {
+        let html = RootTemplate {
+            page_context: PageContext::default(),
+        }
+        .render()
+        .unwrap();
+        insta::assert_snapshot!(html);
+    }
 }
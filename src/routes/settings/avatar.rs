@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Multipart, Path, State, multipart::MultipartError},
+    response::{AppendHeaders, IntoResponse},
+};
+use http::{StatusCode, header};
+
+use crate::{
+    app::ApiContext,
+    auth::AuthSession,
+    avatar::{self, Avatar, InvalidAvatarError},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum AvatarError {
+    #[error("No file was uploaded")]
+    MissingFile,
+    #[error("File is too large, the maximum size is {} KiB", .0 / 1024)]
+    FileTooLarge(usize),
+    #[error(transparent)]
+    InvalidImage(#[from] InvalidAvatarError),
+    #[error("Avatar not found")]
+    NotFound,
+    #[error("Invalid upload")]
+    Multipart(#[from] MultipartError),
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for AvatarError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match &self {
+            AvatarError::MissingFile | AvatarError::InvalidImage(_) => StatusCode::BAD_REQUEST,
+            AvatarError::FileTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AvatarError::NotFound => StatusCode::NOT_FOUND,
+            AvatarError::Multipart(e) => e.status(),
+            AvatarError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+/// Replaces the user's avatar with an uploaded PNG, JPEG or WebP image.
+///
+/// The upload is read in chunks so an oversized file is rejected as soon as
+/// it crosses the limit instead of being buffered whole.
+pub async fn upload_avatar(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AvatarError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let max_bytes = api_context.config.application_settings.max_avatar_bytes;
+    let mut upload = None;
+    while let Some(mut field) = multipart.next_field().await? {
+        if field.name() != Some("avatar") {
+            continue;
+        }
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.chunk().await? {
+            if bytes.len() + chunk.len() > max_bytes {
+                return Err(AvatarError::FileTooLarge(max_bytes));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        upload = Some(bytes);
+    }
+    let upload = upload.ok_or(AvatarError::MissingFile)?;
+
+    let avatar = tokio::task::spawn_blocking(move || Avatar::process(&upload))
+        .await
+        .context("Failed to process avatar")??;
+
+    let filename = avatar.filename().to_string();
+    api_context
+        .files
+        .put(
+            &avatar::avatar_key(&filename),
+            avatar.into_bytes(),
+            "image/png",
+        )
+        .await?;
+
+    let old_avatar = set_avatar(&api_context, user.user_id(), Some(&filename)).await?;
+    if let Some(old_avatar) = old_avatar
+        && old_avatar != filename
+    {
+        remove_unused_avatar(&api_context, &old_avatar).await?;
+    }
+
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([("HX-Redirect", "/settings")]),
+    ))
+}
+
+pub async fn delete_avatar(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+) -> Result<impl IntoResponse, AvatarError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    if let Some(old_avatar) = set_avatar(&api_context, user.user_id(), None).await? {
+        remove_unused_avatar(&api_context, &old_avatar).await?;
+    }
+
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([("HX-Redirect", "/settings")]),
+    ))
+}
+
+/// Sets the user's avatar and returns the one it replaced
+async fn set_avatar(
+    api_context: &ApiContext,
+    user_id: uuid::Uuid,
+    filename: Option<&str>,
+) -> Result<Option<String>, anyhow::Error> {
+    let mut transaction = api_context
+        .db
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+
+    let old_avatar = sqlx::query_scalar!(
+        r#"
+        SELECT avatar FROM user_info WHERE user_id = $1 FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to get current avatar")?;
+
+    sqlx::query!(
+        r#"
+        UPDATE user_info SET avatar = $2 WHERE user_id = $1
+        "#,
+        user_id,
+        filename
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to save avatar")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(old_avatar)
+}
+
+/// Removes an avatar file that no user points to anymore. Files are named by
+/// their contents, so two users uploading the same image share one file.
+pub async fn remove_unused_avatar(
+    api_context: &ApiContext,
+    filename: &str,
+) -> Result<(), anyhow::Error> {
+    let in_use = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM user_info WHERE avatar = $1) AS "exists!"
+        "#,
+        filename
+    )
+    .fetch_one(&api_context.db)
+    .await
+    .context("Failed to check if avatar is in use")?;
+
+    if !in_use {
+        api_context
+            .files
+            .delete(&avatar::avatar_key(filename))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Serves an avatar. The name changes whenever the image does, so clients may
+/// cache it forever.
+pub async fn get_avatar(
+    State(api_context): State<Arc<ApiContext>>,
+    Path(filename): Path<String>,
+) -> Result<impl IntoResponse, AvatarError> {
+    if !avatar::is_avatar_filename(&filename) {
+        return Err(AvatarError::NotFound);
+    }
+
+    let bytes = api_context
+        .files
+        .get(&avatar::avatar_key(&filename))
+        .await?
+        .ok_or(AvatarError::NotFound)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+        ],
+        bytes,
+    ))
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::State,
+    response::{AppendHeaders, Html, IntoResponse},
+};
+use http::StatusCode;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::SettingsError;
+use crate::{
+    app::ApiContext,
+    auth::AuthSession,
+    routes::calendar::{generate_feed_token, hash_feed_token},
+};
+
+/// When the user's current feed URL was made, `None` if they have none
+pub async fn feed_created_at(
+    db: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<OffsetDateTime>, anyhow::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT created_at FROM calendar_feeds WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+    .context("Failed to get calendar feed")
+}
+
+/// Makes a new feed URL, replacing the old one, which stops working right
+/// away. The URL is only shown in the response, as just its hash is kept.
+pub async fn rotate_calendar_feed(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+) -> Result<impl IntoResponse, SettingsError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let token = generate_feed_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO calendar_feeds (user_id, token_hash) VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE SET
+            token_hash = EXCLUDED.token_hash,
+            created_at = NOW()
+        "#,
+        user.user_id(),
+        hash_feed_token(&token)
+    )
+    .execute(&api_context.db)
+    .await
+    .context("Failed to save calendar feed")?;
+
+    let url = api_context.urls.calendar_feed_url(&token);
+    let Ok(escaped_url) = askama::filters::escape(url.as_str(), askama::filters::Html);
+    Ok(Html(format!(
+        "<p>Subscribe to <code>{escaped_url}</code> in your calendar app. \
+         Copy it now, it won't be shown again.</p>"
+    )))
+}
+
+pub async fn delete_calendar_feed(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+) -> Result<impl IntoResponse, SettingsError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM calendar_feeds WHERE user_id = $1
+        "#,
+        user.user_id()
+    )
+    .execute(&api_context.db)
+    .await
+    .context("Failed to delete calendar feed")?;
+
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([("HX-Redirect", "/settings")]),
+    ))
+}
@@ -0,0 +1,453 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    response::IntoResponse,
+};
+use fred::{
+    interfaces::KeysInterface,
+    prelude::Pool,
+    types::{Expiration, SetOptions},
+};
+use http::{StatusCode, header};
+use sqlx::{PgPool, types::Json};
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{
+    app::ApiContext,
+    auth::{AuthSession, sessions},
+    i18n::Locale,
+    preferences::Preferences,
+    theme::Theme,
+};
+
+/// Bumped whenever the layout of the export document changes
+const EXPORT_SCHEMA_VERSION: u32 = 5;
+/// Exports are expensive, so each user gets one per window
+const EXPORT_RATE_LIMIT_SECONDS: i64 = 600;
+/// Todos are sent in chunks of about this many bytes
+const EXPORT_CHUNK_BYTES: usize = 16 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ExportError {
+    #[error("An export was already requested recently, try again later")]
+    RateLimited,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for ExportError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match self {
+            ExportError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ExportError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+/// The export document, apart from its `todos` array. The todos are
+/// streamed after the rest, so they never all have to be in memory.
+#[derive(serde::Serialize)]
+struct UserExport {
+    schema_version: u32,
+    #[serde(with = "time::serde::rfc3339")]
+    exported_at: OffsetDateTime,
+    profile: ExportedProfile,
+    preferences: ExportedPreferences,
+    sessions: Vec<ExportedSession>,
+    #[serde(flatten)]
+    records: AccountRecords,
+}
+
+/// What is kept about the account besides its profile, each oldest first
+#[derive(serde::Serialize)]
+struct AccountRecords {
+    audit_log: Vec<ExportedAuditEntry>,
+    known_devices: Vec<ExportedDevice>,
+    username_history: Vec<ExportedUsername>,
+    passkeys: Vec<ExportedPasskey>,
+    webhooks: Vec<ExportedWebhook>,
+}
+
+/// Everything from `user_info`, the password hash is never exported
+#[derive(serde::Serialize)]
+struct ExportedProfile {
+    user_id: Uuid,
+    username: String,
+    email: String,
+    due_date_reminders: bool,
+    /// The image itself is served at its public url, so isn't exported again
+    has_avatar: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    updated_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    last_login_at: Option<OffsetDateTime>,
+}
+
+#[derive(serde::Serialize)]
+struct ExportedPreferences {
+    default_sort: String,
+    items_per_page: i32,
+    show_completed: bool,
+    timezone: String,
+    auto_complete_todos: bool,
+    locale: Option<Locale>,
+    theme: Theme,
+}
+
+impl From<Preferences> for ExportedPreferences {
+    fn from(preferences: Preferences) -> Self {
+        Self {
+            default_sort: preferences.default_sort.to_string(),
+            items_per_page: preferences.items_per_page,
+            show_completed: preferences.show_completed,
+            timezone: preferences.timezone,
+            auto_complete_todos: preferences.auto_complete_todos,
+            locale: preferences.locale,
+            theme: preferences.theme,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ExportedTodo {
+    todo_id: Uuid,
+    list_id: Uuid,
+    todo_content: String,
+    description: String,
+    is_completed: bool,
+    is_pinned: bool,
+    priority: String,
+    /// `YYYY-MM-DD`
+    due_date: Option<String>,
+    tags: Vec<String>,
+    /// In checklist order
+    subtasks: Json<Vec<ExportedSubtask>>,
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    updated_at: OffsetDateTime,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ExportedSubtask {
+    content: String,
+    is_completed: bool,
+}
+
+/// Session ids double as the cookie value, so only metadata is exported
+#[derive(serde::Serialize)]
+struct ExportedSession {
+    current: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    expires_at: OffsetDateTime,
+}
+
+#[derive(serde::Serialize)]
+struct ExportedAuditEntry {
+    event_type: String,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    metadata: serde_json::Value,
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+}
+
+#[derive(serde::Serialize)]
+struct ExportedDevice {
+    user_agent_family: String,
+    network: String,
+    #[serde(with = "time::serde::rfc3339")]
+    first_seen_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    last_seen_at: OffsetDateTime,
+}
+
+/// A username the user had before changing it
+#[derive(serde::Serialize)]
+struct ExportedUsername {
+    username: String,
+    #[serde(with = "time::serde::rfc3339")]
+    changed_at: OffsetDateTime,
+}
+
+/// The credential itself stays out, it is only useful to the server
+#[derive(serde::Serialize)]
+struct ExportedPasskey {
+    label: String,
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    last_used_at: Option<OffsetDateTime>,
+}
+
+/// Without the signing secret, which would let anyone forge deliveries
+#[derive(serde::Serialize)]
+struct ExportedWebhook {
+    url: String,
+    events: Vec<String>,
+    enabled: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+}
+
+pub async fn export_data(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    session: Session,
+) -> Result<impl IntoResponse, ExportError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+    let user_id = user.user_id();
+
+    if !acquire_export_slot(&api_context.redis, user_id).await? {
+        return Err(ExportError::RateLimited);
+    }
+
+    let (profile, preferences, tracked_sessions, records) = tokio::join!(
+        fetch_profile(&api_context, user_id),
+        api_context.preferences.get(&api_context.db, user_id),
+        sessions::user_sessions(&api_context.redis, user_id),
+        fetch_account_records(&api_context.db, user_id),
+    );
+
+    let now = OffsetDateTime::now_utc();
+    let current_session = session.id().map(|id| id.to_string());
+    let export = UserExport {
+        schema_version: EXPORT_SCHEMA_VERSION,
+        exported_at: now,
+        profile: profile?,
+        preferences: preferences?.into(),
+        sessions: tracked_sessions?
+            .into_iter()
+            .map(|tracked| ExportedSession {
+                current: current_session.as_deref() == Some(tracked.session_id.as_str()),
+                expires_at: now + tracked.time_to_live,
+            })
+            .collect(),
+        records: records?,
+    };
+
+    let mut head = serde_json::to_vec(&export).context("Failed to serialize export")?;
+    // reopens the object serialized above to add the todos as its last field
+    head.pop();
+    head.extend_from_slice(br#","todos":["#);
+
+    let (sender, receiver) = mpsc::channel(4);
+    tokio::spawn(stream_todos(api_context.db.clone(), user_id, head, sender));
+
+    let filename = format!(
+        "attachment; filename=\"{}-export.json\"",
+        user.username.to_lowercase()
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (header::CONTENT_DISPOSITION, filename),
+        ],
+        Body::from_stream(ReceiverStream::new(receiver)),
+    ))
+}
+
+/// Sends `head` followed by the todos and the end of the document. A failure
+/// halfway through ends the body with an error, so the client can't take
+/// the truncated export for a complete one.
+async fn stream_todos(
+    db: PgPool,
+    user_id: Uuid,
+    head: Vec<u8>,
+    sender: mpsc::Sender<Result<Bytes, anyhow::Error>>,
+) {
+    if let Err(e) = send_todos(&db, user_id, head, &sender).await {
+        tracing::error!(%user_id, error = ?e, "Failed to stream export");
+        let _ = sender.send(Err(e)).await;
+    }
+}
+
+async fn send_todos(
+    db: &PgPool,
+    user_id: Uuid,
+    mut chunk: Vec<u8>,
+    sender: &mpsc::Sender<Result<Bytes, anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    let mut todos = sqlx::query_as!(
+        ExportedTodo,
+        r#"
+        SELECT
+            td.todo_id, td.list_id AS "list_id!", td.todo_content, td.description,
+            td.is_completed, td.is_pinned, td.priority::text AS "priority!", td.due_date::text AS due_date,
+            td.created_at, td.updated_at,
+            COALESCE(
+                array_agg(tg.name ORDER BY tg.name) FILTER (WHERE tg.name IS NOT NULL),
+                '{}'
+            ) AS "tags!",
+            COALESCE(
+                (
+                    SELECT json_agg(
+                        json_build_object('content', st.content, 'is_completed', st.is_completed)
+                        ORDER BY st.position, st.created_at
+                    )
+                    FROM subtask AS st
+                    WHERE st.todo_id = td.todo_id
+                ),
+                '[]'
+            ) AS "subtasks!: Json<Vec<ExportedSubtask>>"
+        FROM todo AS td
+        LEFT JOIN todo_tag AS tt ON tt.todo_id = td.todo_id
+        LEFT JOIN tag AS tg ON tg.tag_id = tt.tag_id
+        WHERE td.user_id = $1 AND td.deleted_at IS NULL
+        GROUP BY td.todo_id
+        ORDER BY td.created_at
+        "#,
+        user_id
+    )
+    .fetch(db);
+
+    let mut first = true;
+    while let Some(todo) = todos.next().await {
+        let todo = todo.context("Failed to get todos")?;
+        if !first {
+            chunk.push(b',');
+        }
+        first = false;
+        serde_json::to_writer(&mut chunk, &todo).context("Failed to serialize todo")?;
+
+        if chunk.len() >= EXPORT_CHUNK_BYTES {
+            // a closed channel means the client went away
+            if sender
+                .send(Ok(std::mem::take(&mut chunk).into()))
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    chunk.extend_from_slice(b"]}");
+    let _ = sender.send(Ok(chunk.into())).await;
+    Ok(())
+}
+
+/// Returns false if the user already exported within the rate limit window
+async fn acquire_export_slot(redis: &Pool, user_id: Uuid) -> Result<bool, anyhow::Error> {
+    let acquired: Option<String> = redis
+        .set(
+            format!("export_rate_limit:{user_id}"),
+            1,
+            Some(Expiration::EX(EXPORT_RATE_LIMIT_SECONDS)),
+            Some(SetOptions::NX),
+            false,
+        )
+        .await
+        .context("Failed to check export rate limit")?;
+
+    Ok(acquired.is_some())
+}
+
+async fn fetch_profile(
+    api_context: &ApiContext,
+    user_id: Uuid,
+) -> Result<ExportedProfile, anyhow::Error> {
+    sqlx::query_as!(
+        ExportedProfile,
+        r#"
+        SELECT
+            user_id, username, email, due_date_reminders, avatar IS NOT NULL AS "has_avatar!",
+            created_at, updated_at, last_login_at
+        FROM user_info
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(&api_context.db)
+    .await
+    .context("Failed to get user profile")
+}
+
+async fn fetch_account_records(
+    db: &PgPool,
+    user_id: Uuid,
+) -> Result<AccountRecords, anyhow::Error> {
+    let audit_log = sqlx::query_as!(
+        ExportedAuditEntry,
+        r#"
+        SELECT event_type, ip_address, user_agent, metadata, created_at
+        FROM audit_log
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(db);
+    let known_devices = sqlx::query_as!(
+        ExportedDevice,
+        r#"
+        SELECT user_agent_family, network, first_seen_at, last_seen_at
+        FROM known_devices
+        WHERE user_id = $1
+        ORDER BY first_seen_at
+        "#,
+        user_id
+    )
+    .fetch_all(db);
+    let username_history = sqlx::query_as!(
+        ExportedUsername,
+        r#"
+        SELECT username, changed_at
+        FROM username_history
+        WHERE user_id = $1
+        ORDER BY changed_at
+        "#,
+        user_id
+    )
+    .fetch_all(db);
+    let passkeys = sqlx::query_as!(
+        ExportedPasskey,
+        r#"
+        SELECT label, created_at, last_used_at
+        FROM passkeys
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(db);
+    let webhooks = sqlx::query_as!(
+        ExportedWebhook,
+        r#"
+        SELECT url, events, enabled, created_at
+        FROM webhooks
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(db);
+
+    let (audit_log, known_devices, username_history, passkeys, webhooks) = tokio::join!(
+        audit_log,
+        known_devices,
+        username_history,
+        passkeys,
+        webhooks
+    );
+    Ok(AccountRecords {
+        audit_log: audit_log.context("Failed to get audit log")?,
+        known_devices: known_devices.context("Failed to get known devices")?,
+        username_history: username_history.context("Failed to get username history")?,
+        passkeys: passkeys.context("Failed to get passkeys")?,
+        webhooks: webhooks.context("Failed to get webhooks")?,
+    })
+}
@@ -0,0 +1,522 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Form, Router,
+    extract::{DefaultBodyLimit, State},
+    middleware,
+    response::{AppendHeaders, IntoResponse},
+    routing::{delete, get, post},
+};
+use axum_login::login_required;
+use axum_messages::Messages;
+use http::{StatusCode, header};
+use time::{Date, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{
+    app::{ApiContext, AppRouter},
+    audit::{self, AuditEntry, AuditEvent, AuditLogRow, RequestMetadata},
+    auth::{
+        AuthSession, Backend, LoginCredentials, User, impersonation::forbid_while_impersonating,
+        revoke_refresh_tokens, sessions,
+    },
+    domain::{
+        password::Password,
+        timezone::{InvalidTimezoneError, Timezone},
+        username::Username,
+    },
+    features::{Feature, require_feature},
+    i18n::Locale,
+    page::PageContext,
+    preferences::{MAX_ITEMS_PER_PAGE, Preferences, TodoSort},
+    routes::{todo::filters, tos},
+    theme::Theme,
+};
+
+mod avatar;
+mod calendar;
+mod export;
+mod passkeys;
+mod username;
+mod webhooks;
+
+const RECENT_SECURITY_EVENTS: i64 = 10;
+
+pub fn router() -> AppRouter {
+    Router::new()
+        .route("/settings", get(get_settings).post(update_settings))
+        .route(
+            "/settings/avatar",
+            // the handler enforces the configured avatar size limit itself
+            post(avatar::upload_avatar)
+                .delete(avatar::delete_avatar)
+                .layer(DefaultBodyLimit::disable()),
+        )
+        .route(
+            "/settings/calendar-feed",
+            post(calendar::rotate_calendar_feed).delete(calendar::delete_calendar_feed),
+        )
+        .route("/settings/export", get(export::export_data))
+        .merge(
+            Router::new()
+                .route(
+                    "/settings/passkeys/start",
+                    post(passkeys::start_registration),
+                )
+                .route(
+                    "/settings/passkeys/finish",
+                    post(passkeys::finish_registration),
+                )
+                .route(
+                    "/settings/passkeys/{passkey_id}",
+                    delete(passkeys::delete_passkey),
+                )
+                .route_layer(middleware::from_fn_with_state(
+                    Feature::Passkeys,
+                    require_feature,
+                ))
+                .route_layer(middleware::from_fn(forbid_while_impersonating)),
+        )
+        .merge(
+            // an admin viewing as the user can't take over, deactivate or delete
+            // the account
+            Router::new()
+                .route("/settings/username", post(username::change_username))
+                .route(
+                    "/settings/delete-account",
+                    get(delete_account_page).post(delete_account),
+                )
+                .route(
+                    "/settings/deactivate",
+                    get(deactivate_account_page).post(deactivate_account),
+                )
+                .route_layer(middleware::from_fn(forbid_while_impersonating)),
+        )
+        .merge(
+            Router::new()
+                .route("/settings/webhooks", post(webhooks::create_webhook))
+                .route(
+                    "/settings/webhooks/{webhook_id}",
+                    delete(webhooks::delete_webhook),
+                )
+                .route(
+                    "/settings/webhooks/{webhook_id}/enable",
+                    post(webhooks::enable_webhook),
+                )
+                .route(
+                    "/settings/webhooks/{webhook_id}/disable",
+                    post(webhooks::disable_webhook),
+                )
+                .route_layer(middleware::from_fn_with_state(
+                    Feature::Webhooks,
+                    require_feature,
+                )),
+        )
+        .route_layer(middleware::from_fn(tos::require_accepted_tos))
+        .route_layer(login_required!(Backend, login_url = "/login"))
+        .route("/avatars/{filename}", get(avatar::get_avatar))
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "settings/settings.html")]
+pub struct SettingsTemplate {
+    page_context: PageContext,
+    username: String,
+    avatar: Option<String>,
+    next_username_change: Option<Date>,
+    preferences: Preferences,
+    due_date_reminders: bool,
+    new_device_alerts: bool,
+    security_events: Vec<AuditLogRow>,
+    webhooks: Vec<webhooks::WebhookRow>,
+    calendar_feed_created_at: Option<OffsetDateTime>,
+    passkeys: Vec<passkeys::PasskeyRow>,
+    created_at: OffsetDateTime,
+    last_login_at: Option<OffsetDateTime>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SettingsError {
+    #[error("Unknown timezone")]
+    InvalidTimezone(#[from] InvalidTimezoneError),
+    #[error("Items per page must be between 1 and {MAX_ITEMS_PER_PAGE}")]
+    InvalidItemsPerPage,
+    #[error("Unknown sort order")]
+    InvalidSort,
+    #[error("Unknown language")]
+    InvalidLocale,
+    #[error("Unknown theme")]
+    InvalidTheme,
+    #[error("Wrong password")]
+    WrongPassword,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for SettingsError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match self {
+            SettingsError::InvalidTimezone(_)
+            | SettingsError::InvalidItemsPerPage
+            | SettingsError::InvalidSort
+            | SettingsError::InvalidLocale
+            | SettingsError::InvalidTheme => StatusCode::BAD_REQUEST,
+            SettingsError::WrongPassword => StatusCode::UNAUTHORIZED,
+            SettingsError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+pub async fn get_settings(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    page_context: PageContext,
+) -> Result<SettingsTemplate, SettingsError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let preferences = api_context
+        .preferences
+        .get(&api_context.db, user.user_id())
+        .await?;
+
+    let account = sqlx::query!(
+        r#"
+        SELECT avatar, due_date_reminders, new_device_alerts, created_at, last_login_at
+        FROM user_info
+        WHERE user_id = $1
+        "#,
+        user.user_id()
+    )
+    .fetch_one(&api_context.db)
+    .await
+    .context("Failed to get account settings")?;
+
+    let next_username_change =
+        username::next_username_change(&api_context.db, user.user_id()).await?;
+
+    let security_events =
+        audit::fetch_user_entries(&api_context.db, user.user_id(), RECENT_SECURITY_EVENTS).await?;
+
+    let webhooks = webhooks::fetch_webhooks(&api_context.db, user.user_id()).await?;
+
+    let calendar_feed_created_at =
+        calendar::feed_created_at(&api_context.db, user.user_id()).await?;
+
+    let passkeys = passkeys::fetch_passkey_rows(&api_context.db, user.user_id()).await?;
+
+    Ok(SettingsTemplate {
+        page_context,
+        username: user.username,
+        avatar: account.avatar,
+        next_username_change,
+        preferences,
+        due_date_reminders: account.due_date_reminders,
+        new_device_alerts: account.new_device_alerts,
+        security_events,
+        webhooks,
+        calendar_feed_created_at,
+        passkeys,
+        created_at: account.created_at,
+        last_login_at: account.last_login_at,
+    })
+}
+
+/// Checkboxes are only submitted when checked, so they are optional fields
+#[derive(Debug, serde::Deserialize)]
+pub struct SettingsFormData {
+    default_sort: String,
+    items_per_page: String,
+    show_completed: Option<String>,
+    timezone: String,
+    auto_complete_todos: Option<String>,
+    /// Empty to follow the browser
+    #[serde(default)]
+    locale: String,
+    /// Left out by forms without the theme, which keep the system theme
+    theme: Option<String>,
+    due_date_reminders: Option<String>,
+    new_device_alerts: Option<String>,
+}
+
+pub async fn update_settings(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    messages: Messages,
+    Form(form_data): Form<SettingsFormData>,
+) -> Result<impl IntoResponse, SettingsError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let default_sort = match form_data.default_sort.as_str() {
+        "created" => TodoSort::Created,
+        "priority" => TodoSort::Priority,
+        _ => return Err(SettingsError::InvalidSort),
+    };
+    let items_per_page = form_data
+        .items_per_page
+        .trim()
+        .parse::<i32>()
+        .ok()
+        .filter(|n| (1..=MAX_ITEMS_PER_PAGE).contains(n))
+        .ok_or(SettingsError::InvalidItemsPerPage)?;
+    let timezone = Timezone::parse(&form_data.timezone)?;
+    let locale = match form_data.locale.as_str() {
+        "" => None,
+        locale => Some(Locale::parse(locale).ok_or(SettingsError::InvalidLocale)?),
+    };
+    let theme = match form_data.theme.as_deref() {
+        None => Theme::System,
+        Some(theme) => Theme::parse(theme).ok_or(SettingsError::InvalidTheme)?,
+    };
+
+    let mut transaction = api_context
+        .db
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO user_preferences (
+            user_id, default_sort, items_per_page, show_completed, timezone, auto_complete_todos,
+            locale, theme
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (user_id) DO UPDATE SET
+            default_sort = EXCLUDED.default_sort,
+            items_per_page = EXCLUDED.items_per_page,
+            show_completed = EXCLUDED.show_completed,
+            timezone = EXCLUDED.timezone,
+            auto_complete_todos = EXCLUDED.auto_complete_todos,
+            locale = EXCLUDED.locale,
+            theme = EXCLUDED.theme
+        "#,
+        user.user_id(),
+        default_sort as TodoSort,
+        items_per_page,
+        form_data.show_completed.is_some(),
+        timezone.as_ref(),
+        form_data.auto_complete_todos.is_some(),
+        locale as Option<Locale>,
+        theme as Theme
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to save user preferences")?;
+
+    sqlx::query!(
+        r#"
+        UPDATE user_info SET due_date_reminders = $2, new_device_alerts = $3 WHERE user_id = $1
+        "#,
+        user.user_id(),
+        form_data.due_date_reminders.is_some(),
+        form_data.new_device_alerts.is_some()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to save notification settings")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    api_context.preferences.invalidate(user.user_id()).await;
+    messages.success("Settings saved");
+
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([(
+            header::SET_COOKIE,
+            theme.cookie(api_context.config.application_settings.app_env),
+        )]),
+        AppendHeaders([("HX-Redirect", "/settings")]),
+    ))
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "settings/delete_account.html")]
+pub struct DeleteAccountTemplate {
+    page_context: PageContext,
+    username: String,
+}
+
+pub async fn delete_account_page(
+    auth_session: AuthSession,
+    page_context: PageContext,
+) -> Result<DeleteAccountTemplate, SettingsError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    Ok(DeleteAccountTemplate {
+        page_context,
+        username: user.username,
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct DeleteAccountFormData {
+    password: String,
+}
+
+/// Checks the password the user confirmed a change to their account with
+async fn verify_password(
+    auth_session: &AuthSession,
+    user: &User,
+    password: &str,
+) -> Result<(), SettingsError> {
+    let username = Username::parse(&user.username).context("Stored username is invalid")?;
+    let password = Password::parse(password).map_err(|_| SettingsError::WrongPassword)?;
+    let verified = auth_session
+        .authenticate(LoginCredentials::new(username, password))
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to verify password: {e}"))?;
+    match verified {
+        Some(_) => Ok(()),
+        None => Err(SettingsError::WrongPassword),
+    }
+}
+
+/// The row of a deleted user, with what it leaves to clean up
+pub struct DeletedUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub avatar: Option<String>,
+}
+
+/// Removes what a deleted user leaves outside of Postgres: their avatar,
+/// sessions and cached entries. The rest went with the row, see
+/// [`delete_account`].
+pub async fn clean_up_deleted_user(
+    api_context: &ApiContext,
+    user: &DeletedUser,
+) -> Result<(), anyhow::Error> {
+    if let Some(old_avatar) = &user.avatar {
+        avatar::remove_unused_avatar(api_context, old_avatar).await?;
+    }
+    sessions::delete_user_sessions(&api_context.redis, user.user_id).await?;
+    api_context.user_cache.invalidate(user.user_id).await;
+    api_context.preferences.invalidate(user.user_id).await;
+    Ok(())
+}
+
+pub async fn delete_account(
+    State(api_context): State<Arc<ApiContext>>,
+    mut auth_session: AuthSession,
+    request: RequestMetadata,
+    Form(form_data): Form<DeleteAccountFormData>,
+) -> Result<impl IntoResponse, SettingsError> {
+    let user = auth_session
+        .user
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+    verify_password(&auth_session, &user, &form_data.password).await?;
+
+    // the user's list with its todos, tags, preferences and the password are
+    // removed by ON DELETE CASCADE. Todos they added to other people's lists
+    // stay there, with no one recorded as having added them
+    let deleted = sqlx::query_as!(
+        DeletedUser,
+        r#"
+        DELETE FROM user_info WHERE user_id = $1 RETURNING user_id, username, avatar
+        "#,
+        user.user_id()
+    )
+    .fetch_one(&api_context.db)
+    .await
+    .context("Failed to delete user")?;
+
+    auth_session
+        .logout()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to log out: {e}"))?;
+    clean_up_deleted_user(&api_context, &deleted).await?;
+
+    // the user row is gone, so the entry can't reference it
+    api_context.audit.record(
+        AuditEntry::new(AuditEvent::AccountDeleted, None, &request).with_metadata(
+            serde_json::json!({ "user_id": user.user_id(), "username": user.username }),
+        ),
+    );
+
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([("HX-Redirect", "/register")]),
+    ))
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "settings/deactivate_account.html")]
+pub struct DeactivateAccountTemplate {
+    page_context: PageContext,
+    username: String,
+    grace_days: i32,
+}
+
+pub async fn deactivate_account_page(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    page_context: PageContext,
+) -> Result<DeactivateAccountTemplate, SettingsError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    Ok(DeactivateAccountTemplate {
+        page_context,
+        username: user.username,
+        grace_days: api_context
+            .config
+            .application_settings
+            .account_deactivation_grace_days,
+    })
+}
+
+/// Hides the account until its owner logs in again, see
+/// [`crate::worker::deactivation`] for what happens if they don't
+pub async fn deactivate_account(
+    State(api_context): State<Arc<ApiContext>>,
+    mut auth_session: AuthSession,
+    request: RequestMetadata,
+    Form(form_data): Form<DeleteAccountFormData>,
+) -> Result<impl IntoResponse, SettingsError> {
+    let user = auth_session
+        .user
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+    verify_password(&auth_session, &user, &form_data.password).await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE user_info SET deactivated_at = NOW() WHERE user_id = $1
+        "#,
+        user.user_id()
+    )
+    .execute(&api_context.db)
+    .await
+    .context("Failed to deactivate user")?;
+
+    auth_session
+        .logout()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to log out: {e}"))?;
+    sessions::delete_user_sessions(&api_context.redis, user.user_id()).await?;
+    revoke_refresh_tokens(&api_context.db, user.user_id()).await?;
+    api_context.user_cache.invalidate(user.user_id()).await;
+
+    api_context.audit.record(AuditEntry::new(
+        AuditEvent::AccountDeactivated,
+        Some(user.user_id()),
+        &request,
+    ));
+
+    Ok((StatusCode::OK, AppendHeaders([("HX-Redirect", "/login")])))
+}
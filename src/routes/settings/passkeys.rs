@@ -0,0 +1,232 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Form, Json,
+    extract::{Path, State},
+    response::{AppendHeaders, IntoResponse},
+};
+use http::StatusCode;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tower_sessions::Session;
+use uuid::Uuid;
+use webauthn_rs::prelude::{
+    CreationChallengeResponse, CredentialID, PasskeyRegistration, RegisterPublicKeyCredential,
+};
+
+use crate::{
+    app::ApiContext,
+    audit::{AuditEntry, AuditEvent, RequestMetadata},
+    auth::{AuthSession, fetch_passkeys},
+};
+
+pub const MAX_PASSKEYS_PER_USER: usize = 10;
+const MAX_LABEL_CHARS: usize = 64;
+
+/// Session key of the registration ceremony in progress
+const REGISTRATION_STATE_KEY: &str = "passkey_registration";
+
+#[derive(thiserror::Error, Debug)]
+pub enum PasskeyError {
+    #[error("Passkey names are 1 to {MAX_LABEL_CHARS} characters long")]
+    InvalidLabel,
+    #[error("You can have at most {MAX_PASSKEYS_PER_USER} passkeys")]
+    TooManyPasskeys,
+    #[error("The passkey couldn't be registered, try again")]
+    Rejected,
+    #[error("Passkey not found")]
+    NotFound,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for PasskeyError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match self {
+            PasskeyError::InvalidLabel | PasskeyError::Rejected => StatusCode::BAD_REQUEST,
+            PasskeyError::TooManyPasskeys => StatusCode::CONFLICT,
+            PasskeyError::NotFound => StatusCode::NOT_FOUND,
+            PasskeyError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+/// A passkey as shown on the settings page
+#[derive(Debug)]
+pub struct PasskeyRow {
+    pub passkey_id: Uuid,
+    pub label: String,
+    pub created_at: OffsetDateTime,
+    pub last_used_at: Option<OffsetDateTime>,
+}
+
+pub async fn fetch_passkey_rows(
+    db: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<PasskeyRow>, anyhow::Error> {
+    sqlx::query_as!(
+        PasskeyRow,
+        r#"
+        SELECT passkey_id, label, created_at, last_used_at
+        FROM passkeys
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to get passkeys")
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasskeyFormData {
+    label: String,
+}
+
+/// What the registration ceremony needs to be finished, kept in the session
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RegistrationState {
+    label: String,
+    state: PasskeyRegistration,
+}
+
+/// Starts registering a passkey, the browser creates it from the returned
+/// options and sends it to [`finish_registration`]
+pub async fn start_registration(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    session: Session,
+    Form(form_data): Form<PasskeyFormData>,
+) -> Result<Json<CreationChallengeResponse>, PasskeyError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let label = form_data.label.trim();
+    if label.is_empty() || label.chars().count() > MAX_LABEL_CHARS {
+        return Err(PasskeyError::InvalidLabel);
+    }
+
+    let passkeys = fetch_passkeys(&api_context.db, user.user_id()).await?;
+    if passkeys.len() >= MAX_PASSKEYS_PER_USER {
+        return Err(PasskeyError::TooManyPasskeys);
+    }
+    // the authenticator refuses to make a second passkey for the same account
+    let exclude_credentials: Vec<CredentialID> = passkeys
+        .iter()
+        .map(|passkey| passkey.cred_id().clone())
+        .collect();
+
+    let (challenge, state) = api_context
+        .webauthn
+        .start_passkey_registration(
+            user.user_id(),
+            &user.username,
+            &user.username,
+            Some(exclude_credentials),
+        )
+        .context("Failed to start passkey registration")?;
+
+    session
+        .insert(
+            REGISTRATION_STATE_KEY,
+            RegistrationState {
+                label: label.to_string(),
+                state,
+            },
+        )
+        .await
+        .context("Failed to save passkey registration")?;
+
+    Ok(Json(challenge))
+}
+
+pub async fn finish_registration(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    session: Session,
+    request: RequestMetadata,
+    Json(credential): Json<RegisterPublicKeyCredential>,
+) -> Result<impl IntoResponse, PasskeyError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let registration: RegistrationState = session
+        .remove(REGISTRATION_STATE_KEY)
+        .await
+        .context("Failed to get passkey registration")?
+        .ok_or(PasskeyError::Rejected)?;
+
+    let passkey = api_context
+        .webauthn
+        .finish_passkey_registration(&credential, &registration.state)
+        .map_err(|e| {
+            tracing::debug!(error = ?e, "Passkey registration failed");
+            PasskeyError::Rejected
+        })?;
+
+    let passkey_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO passkeys (user_id, credential_id, public_key, label)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (credential_id) DO NOTHING
+        RETURNING passkey_id
+        "#,
+        user.user_id(),
+        passkey.cred_id().as_ref(),
+        serde_json::to_value(&passkey).context("Failed to serialize passkey")?,
+        registration.label
+    )
+    .fetch_optional(&api_context.db)
+    .await
+    .context("Failed to save passkey")?
+    .ok_or(PasskeyError::Rejected)?;
+
+    api_context.audit.record(
+        AuditEntry::new(AuditEvent::PasskeyAdded, Some(user.user_id()), &request).with_metadata(
+            serde_json::json!({ "passkey_id": passkey_id, "label": registration.label }),
+        ),
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        AppendHeaders([("HX-Redirect", "/settings")]),
+    ))
+}
+
+pub async fn delete_passkey(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    request: RequestMetadata,
+    Path(passkey_id): Path<Uuid>,
+) -> Result<impl IntoResponse, PasskeyError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let label = sqlx::query_scalar!(
+        r#"
+        DELETE FROM passkeys WHERE passkey_id = $1 AND user_id = $2 RETURNING label
+        "#,
+        passkey_id,
+        user.user_id()
+    )
+    .fetch_optional(&api_context.db)
+    .await
+    .context("Failed to delete passkey")?
+    .ok_or(PasskeyError::NotFound)?;
+
+    api_context.audit.record(
+        AuditEntry::new(AuditEvent::PasskeyRemoved, Some(user.user_id()), &request)
+            .with_metadata(serde_json::json!({ "passkey_id": passkey_id, "label": label })),
+    );
+
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([("HX-Redirect", "/settings")]),
+    ))
+}
@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Form,
+    extract::State,
+    response::{AppendHeaders, IntoResponse},
+};
+use http::StatusCode;
+use sqlx::PgPool;
+use time::{Date, Duration};
+use uuid::Uuid;
+
+use crate::{
+    app::ApiContext,
+    audit::{AuditEntry, AuditEvent, RequestMetadata},
+    auth::{AuthSession, username_on_hold},
+    domain::username::{InvalidUsernameError, Username},
+};
+
+/// Minimum time between two username changes of the same user
+pub const USERNAME_CHANGE_INTERVAL_DAYS: i64 = 30;
+
+#[derive(thiserror::Error, Debug)]
+pub enum UsernameChangeError {
+    #[error("Invalid username")]
+    InvalidUsername(#[from] InvalidUsernameError),
+    #[error("This is already your username")]
+    SameUsername,
+    #[error("Username already exists")]
+    UsernameExists,
+    #[error("This username was recently used by another account")]
+    UsernameOnHold,
+    #[error("You can change your username again on {0}")]
+    TooSoon(Date),
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for UsernameChangeError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match self {
+            UsernameChangeError::InvalidUsername(_) | UsernameChangeError::SameUsername => {
+                StatusCode::BAD_REQUEST
+            }
+            UsernameChangeError::UsernameExists
+            | UsernameChangeError::UsernameOnHold
+            | UsernameChangeError::TooSoon(_) => StatusCode::CONFLICT,
+            UsernameChangeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+/// The first day the user may change their username again, `None` if they
+/// can do it right away
+pub async fn next_username_change(
+    db: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<Date>, anyhow::Error> {
+    let last_change = sqlx::query_scalar!(
+        r#"
+        SELECT MAX(changed_at) FROM username_history WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+    .context("Failed to get last username change")?;
+
+    Ok(last_change
+        .map(|changed_at| changed_at + Duration::days(USERNAME_CHANGE_INTERVAL_DAYS))
+        .filter(|next_change| *next_change > time::OffsetDateTime::now_utc())
+        .map(|next_change| next_change.date()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct UsernameFormData {
+    username: String,
+}
+
+pub async fn change_username(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    request: RequestMetadata,
+    Form(form_data): Form<UsernameFormData>,
+) -> Result<impl IntoResponse, UsernameChangeError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let username = Username::parse(&form_data.username)?;
+    if username.as_ref() == user.username.to_lowercase() {
+        return Err(UsernameChangeError::SameUsername);
+    }
+    if let Some(next_change) = next_username_change(&api_context.db, user.user_id()).await? {
+        return Err(UsernameChangeError::TooSoon(next_change));
+    }
+    let hold_days = api_context.config.application_settings.username_hold_days;
+    if username_on_hold(&api_context.db, &username, hold_days, Some(user.user_id())).await? {
+        return Err(UsernameChangeError::UsernameOnHold);
+    }
+
+    let mut transaction = api_context
+        .db
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO username_history (user_id, username) VALUES ($1, $2)
+        "#,
+        user.user_id(),
+        user.username
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record old username")?;
+
+    // the unique constraint is the uniqueness check, so there's no window
+    // between checking and taking the name
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_info SET username = $2 WHERE user_id = $1
+        "#,
+        user.user_id(),
+        username.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await;
+    match result {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return Err(UsernameChangeError::UsernameExists);
+        }
+        Err(e) => {
+            return Err(anyhow::Error::from(e)
+                .context("Failed to update username")
+                .into());
+        }
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+    api_context.user_cache.invalidate(user.user_id()).await;
+
+    api_context.audit.record(
+        AuditEntry::new(AuditEvent::UsernameChanged, Some(user.user_id()), &request).with_metadata(
+            serde_json::json!({
+                "old_username": user.username,
+                "new_username": username.as_ref(),
+            }),
+        ),
+    );
+
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([("HX-Redirect", "/settings")]),
+    ))
+}
@@ -0,0 +1,249 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Form,
+    extract::{Path, State},
+    response::{AppendHeaders, IntoResponse},
+};
+use http::StatusCode;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    app::ApiContext,
+    auth::AuthSession,
+    domain::webhook_url::{InvalidWebhookUrlError, WebhookUrl},
+    webhook::{self, MAX_WEBHOOKS_PER_USER, WebhookEvent},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum WebhookError {
+    #[error(transparent)]
+    InvalidUrl(#[from] InvalidWebhookUrlError),
+    #[error("Pick at least one event")]
+    NoEvents,
+    #[error("You can have at most {MAX_WEBHOOKS_PER_USER} webhooks")]
+    TooManyWebhooks,
+    #[error("Webhook not found")]
+    NotFound,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for WebhookError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match self {
+            WebhookError::InvalidUrl(_) | WebhookError::NoEvents => StatusCode::BAD_REQUEST,
+            WebhookError::TooManyWebhooks => StatusCode::CONFLICT,
+            WebhookError::NotFound => StatusCode::NOT_FOUND,
+            WebhookError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+/// A webhook as shown on the settings page
+#[derive(Debug)]
+pub struct WebhookRow {
+    pub webhook_id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+    pub enabled: bool,
+    pub consecutive_failures: i32,
+    pub last_attempt_at: Option<OffsetDateTime>,
+    /// `None` when the last attempt got no response
+    pub last_status: Option<i16>,
+    pub last_error: Option<String>,
+    /// Deliveries still waiting to be sent
+    pub queued: i64,
+}
+
+pub async fn fetch_webhooks(db: &PgPool, user_id: Uuid) -> Result<Vec<WebhookRow>, anyhow::Error> {
+    sqlx::query_as!(
+        WebhookRow,
+        r#"
+        SELECT
+            wh.webhook_id, wh.url, wh.secret, wh.events, wh.enabled, wh.consecutive_failures,
+            wh.last_attempt_at, wh.last_status, wh.last_error,
+            (SELECT COUNT(*) FROM outbox AS o WHERE o.webhook_id = wh.webhook_id) AS "queued!"
+        FROM webhooks AS wh
+        WHERE wh.user_id = $1
+        ORDER BY wh.created_at
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to get webhooks")
+}
+
+/// Checkboxes are only submitted when checked, so they are optional fields
+#[derive(Debug, serde::Deserialize)]
+pub struct WebhookFormData {
+    url: String,
+    todo_created: Option<String>,
+    todo_completed: Option<String>,
+    todo_deleted: Option<String>,
+}
+
+pub async fn create_webhook(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Form(form_data): Form<WebhookFormData>,
+) -> Result<impl IntoResponse, WebhookError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let url = WebhookUrl::parse(&form_data.url)?;
+    let events: Vec<&str> = [
+        (WebhookEvent::TodoCreated, &form_data.todo_created),
+        (WebhookEvent::TodoCompleted, &form_data.todo_completed),
+        (WebhookEvent::TodoDeleted, &form_data.todo_deleted),
+    ]
+    .into_iter()
+    .filter(|(_, checked)| checked.is_some())
+    .map(|(event, _)| event.as_str())
+    .collect();
+    if events.is_empty() {
+        return Err(WebhookError::NoEvents);
+    }
+
+    let mut transaction = api_context
+        .db
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+
+    // locking the user keeps concurrent requests from going over the limit
+    sqlx::query!(
+        r#"
+        SELECT user_id FROM user_info WHERE user_id = $1 FOR UPDATE
+        "#,
+        user.user_id()
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to lock user")?;
+
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM webhooks WHERE user_id = $1
+        "#,
+        user.user_id()
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to count webhooks")?;
+    if count >= MAX_WEBHOOKS_PER_USER {
+        return Err(WebhookError::TooManyWebhooks);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO webhooks (user_id, url, secret, events)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        user.user_id(),
+        url.as_ref(),
+        webhook::generate_secret(),
+        &events as &[&str]
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to create webhook")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok((
+        StatusCode::CREATED,
+        AppendHeaders([("HX-Redirect", "/settings")]),
+    ))
+}
+
+/// Turns the webhook back on, e.g. after it was turned off for failing
+pub async fn enable_webhook(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<impl IntoResponse, WebhookError> {
+    set_enabled(&api_context, auth_session, webhook_id, true).await
+}
+
+pub async fn disable_webhook(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<impl IntoResponse, WebhookError> {
+    set_enabled(&api_context, auth_session, webhook_id, false).await
+}
+
+async fn set_enabled(
+    api_context: &ApiContext,
+    auth_session: AuthSession,
+    webhook_id: Uuid,
+    enabled: bool,
+) -> Result<impl IntoResponse + use<>, WebhookError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE webhooks SET enabled = $3, consecutive_failures = 0
+        WHERE webhook_id = $1 AND user_id = $2
+        "#,
+        webhook_id,
+        user.user_id(),
+        enabled
+    )
+    .execute(&api_context.db)
+    .await
+    .context("Failed to update webhook")?
+    .rows_affected();
+    if updated == 0 {
+        return Err(WebhookError::NotFound);
+    }
+
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([("HX-Redirect", "/settings")]),
+    ))
+}
+
+/// Removes the webhook along with its queued deliveries
+pub async fn delete_webhook(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<impl IntoResponse, WebhookError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM webhooks WHERE webhook_id = $1 AND user_id = $2
+        "#,
+        webhook_id,
+        user.user_id()
+    )
+    .execute(&api_context.db)
+    .await
+    .context("Failed to delete webhook")?
+    .rows_affected();
+    if deleted == 0 {
+        return Err(WebhookError::NotFound);
+    }
+
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([("HX-Redirect", "/settings")]),
+    ))
+}
@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{Router, extract::State, middleware, response::IntoResponse, routing::get};
+use axum_login::login_required;
+use fred::{
+    interfaces::KeysInterface,
+    prelude::Pool,
+    types::{Expiration, SetOptions},
+};
+use http::StatusCode;
+use sqlx::PgPool;
+use time::Date;
+use uuid::Uuid;
+
+use crate::{
+    app::{ApiContext, AppRouter},
+    auth::{AuthSession, Backend},
+    page::PageContext,
+    routes::tos,
+};
+
+/// Days shown in the chart, ending today
+const STATS_DAYS: i32 = 30;
+/// Stats only change as fast as the user gets things done, so a slightly
+/// stale page is fine
+const STATS_CACHE_SECONDS: i64 = 300;
+/// Height of the tallest bar in the chart, in pixels
+const CHART_HEIGHT: i64 = 100;
+
+pub fn router() -> AppRouter {
+    Router::new()
+        .route("/stats", get(stats_page))
+        .route_layer(middleware::from_fn(tos::require_accepted_tos))
+        .route_layer(login_required!(Backend, login_url = "/login"))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum StatsError {
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for StatsError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match self {
+            StatsError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct DayStats {
+    pub day: Date,
+    pub created: i64,
+    pub completed: i64,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Stats {
+    /// Oldest day first, one entry per day including empty ones
+    pub days: Vec<DayStats>,
+    /// Consecutive days with a completion, ending today or yesterday
+    pub streak: i64,
+    /// Average age of the open todos in days, `None` without any
+    pub average_open_age_days: Option<f64>,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "stats/stats.html")]
+struct StatsTemplate {
+    page_context: PageContext,
+    stats: Stats,
+    max_count: i64,
+}
+
+impl StatsTemplate {
+    fn bar_height(&self, count: &i64) -> i64 {
+        if self.max_count == 0 {
+            0
+        } else {
+            count * CHART_HEIGHT / self.max_count
+        }
+    }
+
+    fn chart_width(&self) -> usize {
+        self.stats.days.len() * 20
+    }
+
+    fn chart_height(&self) -> i64 {
+        CHART_HEIGHT
+    }
+}
+
+/// Shows how many todos the user created and completed recently.
+///
+/// Completions are counted by when the user's own todos were completed, in
+/// whichever list they are and whoever completed them. Todos completed again
+/// count once, on the last day.
+pub async fn stats_page(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    page_context: PageContext,
+) -> Result<impl IntoResponse, StatsError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+    let user_id = user.user_id();
+
+    let stats = match cached_stats(&api_context.redis, user_id).await? {
+        Some(stats) => stats,
+        None => {
+            let preferences = api_context
+                .preferences
+                .get(&api_context.db, user_id)
+                .await?;
+            let stats = compute_stats(&api_context.db, user_id, &preferences.timezone).await?;
+            cache_stats(&api_context.redis, user_id, &stats).await?;
+            stats
+        }
+    };
+
+    let max_count = stats
+        .days
+        .iter()
+        .map(|day| day.created.max(day.completed))
+        .max()
+        .unwrap_or(0);
+    Ok(StatsTemplate {
+        page_context,
+        stats,
+        max_count,
+    })
+}
+
+fn cache_key(user_id: Uuid) -> String {
+    format!("stats:{user_id}")
+}
+
+async fn cached_stats(redis: &Pool, user_id: Uuid) -> Result<Option<Stats>, anyhow::Error> {
+    let cached: Option<String> = redis
+        .get(cache_key(user_id))
+        .await
+        .context("Failed to get cached stats")?;
+
+    // an entry that no longer parses is recomputed like a missing one
+    Ok(cached.and_then(|cached| serde_json::from_str(&cached).ok()))
+}
+
+async fn cache_stats(redis: &Pool, user_id: Uuid, stats: &Stats) -> Result<(), anyhow::Error> {
+    let serialized = serde_json::to_string(stats).context("Failed to serialize stats")?;
+    let _: Option<String> = redis
+        .set(
+            cache_key(user_id),
+            serialized,
+            Some(Expiration::EX(STATS_CACHE_SECONDS)),
+            None::<SetOptions>,
+            false,
+        )
+        .await
+        .context("Failed to cache stats")?;
+
+    Ok(())
+}
+
+/// Days are counted in the user's timezone
+async fn compute_stats(db: &PgPool, user_id: Uuid, timezone: &str) -> Result<Stats, anyhow::Error> {
+    let days = sqlx::query_as!(
+        DayStats,
+        r#"
+        WITH created AS (
+            SELECT date_trunc('day', created_at AT TIME ZONE $2) AS day, COUNT(*) AS count
+            FROM todo
+            WHERE user_id = $1 AND deleted_at IS NULL
+            GROUP BY 1
+        ),
+        completed AS (
+            SELECT date_trunc('day', completed_at AT TIME ZONE $2) AS day, COUNT(*) AS count
+            FROM todo
+            WHERE user_id = $1 AND completed_at IS NOT NULL AND deleted_at IS NULL
+            GROUP BY 1
+        )
+        SELECT
+            d.day::date AS "day!",
+            COALESCE(cr.count, 0) AS "created!",
+            COALESCE(co.count, 0) AS "completed!"
+        FROM generate_series(
+            date_trunc('day', NOW() AT TIME ZONE $2) - make_interval(days => $3 - 1),
+            date_trunc('day', NOW() AT TIME ZONE $2),
+            INTERVAL '1 day'
+        ) AS d(day)
+        LEFT JOIN created AS cr ON cr.day = d.day
+        LEFT JOIN completed AS co ON co.day = d.day
+        ORDER BY d.day
+        "#,
+        user_id,
+        timezone,
+        STATS_DAYS
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to count todos per day")?;
+
+    // consecutive days share the same difference between the day and its rank
+    let streak = sqlx::query_scalar!(
+        r#"
+        WITH days AS (
+            SELECT DISTINCT date_trunc('day', completed_at AT TIME ZONE $2)::date AS day
+            FROM todo
+            WHERE user_id = $1 AND completed_at IS NOT NULL AND deleted_at IS NULL
+        ),
+        runs AS (
+            SELECT day, day - (ROW_NUMBER() OVER (ORDER BY day))::int AS run
+            FROM days
+        )
+        SELECT COUNT(*) AS "streak!"
+        FROM runs
+        WHERE run = (
+            SELECT run FROM runs
+            WHERE day >= (NOW() AT TIME ZONE $2)::date - 1
+            ORDER BY day DESC
+            LIMIT 1
+        )
+        "#,
+        user_id,
+        timezone
+    )
+    .fetch_one(db)
+    .await
+    .context("Failed to compute streak")?;
+
+    let average_open_age_days = sqlx::query_scalar!(
+        r#"
+        SELECT (EXTRACT(EPOCH FROM AVG(NOW() - created_at)) / 86400)::float8
+        FROM todo
+        WHERE user_id = $1 AND NOT is_completed AND deleted_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+    .context("Failed to compute average open age")?;
+
+    Ok(Stats {
+        days,
+        streak,
+        average_open_age_days,
+    })
+}
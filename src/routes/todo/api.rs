@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+};
+use http::{HeaderMap, StatusCode};
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+
+use super::{Todo, cursor::Cursor, etag};
+use crate::{
+    api_error::ApiError,
+    app::ApiContext,
+    auth::ApiUser,
+    authz::{self, AuthzError},
+};
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 100;
+
+#[derive(thiserror::Error, Debug)]
+pub enum TodoApiError {
+    #[error("The cursor is invalid")]
+    InvalidCursor,
+    #[error("List not found")]
+    ListNotFound,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<AuthzError> for TodoApiError {
+    fn from(e: AuthzError) -> Self {
+        match e {
+            // viewing never needs more than being able to see the list
+            AuthzError::NotFound | AuthzError::Forbidden => TodoApiError::ListNotFound,
+            AuthzError::UnexpectedError(e) => TodoApiError::UnexpectedError(e),
+        }
+    }
+}
+
+impl IntoResponse for TodoApiError {
+    fn into_response(self) -> axum::response::Response {
+        let message = self.to_string();
+        let error = match self {
+            TodoApiError::InvalidCursor => {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_cursor", message)
+                    .with_field("cursor")
+            }
+            TodoApiError::ListNotFound => {
+                ApiError::new(StatusCode::NOT_FOUND, "list_not_found", message)
+            }
+            TodoApiError::UnexpectedError(e) => ApiError::internal(&e),
+        };
+        error.into_response()
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TodoPageQuery {
+    /// The user's own list if left out
+    list_id: Option<Uuid>,
+    /// `next_cursor` of the previous page, left out for the first one
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct TodoPage {
+    items: Vec<Todo>,
+    /// `None` on the last page
+    next_cursor: Option<String>,
+}
+
+/// Todos of a list, newest first.
+///
+/// Pages are keyed on the last todo of the previous one rather than an
+/// offset, so todos added between two requests don't shift the pages.
+/// Answers with 304 when the client's `If-None-Match` still matches the
+/// page, see [`etag::list_etag`].
+pub async fn list_todos(
+    State(api_context): State<Arc<ApiContext>>,
+    ApiUser(user): ApiUser,
+    headers: HeaderMap,
+    Query(query): Query<TodoPageQuery>,
+) -> Result<Response, TodoApiError> {
+    let key = api_context
+        .config
+        .application_settings
+        .hmac_key
+        .expose_secret()
+        .as_bytes();
+
+    let after = match query.cursor.as_deref() {
+        Some(cursor) => Some(Cursor::decode(key, cursor).ok_or(TodoApiError::InvalidCursor)?),
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let (list_id, _) =
+        authz::can_view_list_or_own(&api_context, user.user_id(), query.list_id).await?;
+
+    let view = format!("api:{:?}:{limit}", query.cursor);
+    let etag = api_context
+        .todos
+        .list_etag(list_id, user.user_id(), &view)
+        .await?;
+    let cache_headers = etag::cache_headers(&etag);
+    if etag::not_modified(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    // one extra todo tells whether there is a next page
+    let mut items = api_context
+        .todos
+        .page_after(list_id, after, limit + 1)
+        .await?;
+    let next_cursor = if items.len() as i64 > limit {
+        items.truncate(limit as usize);
+        items.last().map(|todo| {
+            Cursor {
+                created_at: todo.created_at,
+                todo_id: todo.todo_id,
+            }
+            .encode(key)
+        })
+    } else {
+        None
+    };
+
+    Ok((cache_headers, Json(TodoPage { items, next_cursor })).into_response())
+}
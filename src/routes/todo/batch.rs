@@ -0,0 +1,285 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Form,
+    extract::State,
+    response::{AppendHeaders, IntoResponse},
+};
+use http::StatusCode;
+use time::Date;
+use time_tz::Tz;
+use uuid::Uuid;
+
+use super::{
+    Todo, TodoCounts,
+    events::publish_todo_event,
+    filters,
+    history::{self, TodoChange},
+    quota::{self, QuotaError},
+};
+use crate::{
+    app::ApiContext,
+    auth::AuthSession,
+    authz::{self, AuthzError},
+    domain::{priority::Priority, todo_content::TodoContent},
+    events::TodoEventKind,
+    negotiate::{Format, HtmlOrJson},
+};
+
+/// Most lines, blank ones included, accepted in a single batch
+const MAX_BATCH_LINES: usize = 100;
+
+#[derive(thiserror::Error, Debug)]
+pub enum BatchError {
+    #[error("Too many lines, the maximum is {MAX_BATCH_LINES}")]
+    TooManyLines,
+    #[error("List not found")]
+    ListNotFound,
+    #[error("You can't add todos to this list")]
+    Forbidden,
+    #[error("Adding these todos would take you over your limit of {limit} todos")]
+    QuotaExceeded { limit: i64 },
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for BatchError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match &self {
+            BatchError::TooManyLines => StatusCode::BAD_REQUEST,
+            BatchError::ListNotFound => StatusCode::NOT_FOUND,
+            BatchError::Forbidden => StatusCode::FORBIDDEN,
+            BatchError::QuotaExceeded { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            BatchError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+impl From<AuthzError> for BatchError {
+    fn from(e: AuthzError) -> Self {
+        match e {
+            AuthzError::NotFound => BatchError::ListNotFound,
+            AuthzError::Forbidden => BatchError::Forbidden,
+            AuthzError::UnexpectedError(e) => BatchError::UnexpectedError(e),
+        }
+    }
+}
+
+impl From<QuotaError> for BatchError {
+    fn from(e: QuotaError) -> Self {
+        match e {
+            QuotaError::Exceeded { limit } => BatchError::QuotaExceeded { limit },
+            QuotaError::UnexpectedError(e) => BatchError::UnexpectedError(e),
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct BatchForm {
+    /// One todo per line
+    todo_content: String,
+    list_id: Option<Uuid>,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct SkippedLine {
+    /// Counting from 1
+    line: usize,
+    reason: String,
+}
+
+/// The rows of the added todos, and the summary swapped in out of band
+#[derive(Template, WebTemplate, serde::Serialize)]
+#[template(path = "todo/batch_rows.html")]
+pub struct BatchTemplate {
+    todos: Vec<Todo>,
+    skipped: Vec<SkippedLine>,
+    #[serde(skip)]
+    can_edit: bool,
+    #[serde(skip)]
+    timezone: &'static Tz,
+    #[serde(skip)]
+    today: Date,
+}
+
+/// Splits the text into the todos of its lines and the lines that were
+/// skipped. Too many lines fail the whole batch, so nothing is added.
+fn split_lines(text: &str) -> Result<(Vec<TodoContent>, Vec<SkippedLine>), BatchError> {
+    let mut todos = Vec::new();
+    let mut skipped = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if i >= MAX_BATCH_LINES {
+            return Err(BatchError::TooManyLines);
+        }
+        match TodoContent::parse(line) {
+            Ok(todo_content) => todos.push(todo_content),
+            Err(e) => skipped.push(SkippedLine {
+                line: i + 1,
+                reason: e.to_string(),
+            }),
+        }
+    }
+    Ok((todos, skipped))
+}
+
+/// Adds a todo for each line of the text, e.g. a pasted list.
+///
+/// Blank and invalid lines are skipped and reported back. The others are
+/// added in one transaction, so either all of them are added or none.
+pub async fn add_batch(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    format: Format,
+    Form(form): Form<BatchForm>,
+) -> Result<impl IntoResponse, BatchError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let list_id =
+        authz::require_edit_list_or_own(&api_context, user.user_id(), form.list_id).await?;
+
+    let (contents, skipped) = split_lines(&form.todo_content)?;
+    let contents: Vec<String> = contents.iter().map(ToString::to_string).collect();
+
+    let mut transaction = api_context
+        .db
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+
+    quota::reserve(
+        &mut transaction,
+        user.user_id(),
+        contents.len() as i64,
+        api_context.config.application_settings.max_active_todos,
+    )
+    .await?;
+
+    let todos = sqlx::query!(
+        r#"
+        INSERT INTO todo (user_id, list_id, todo_content)
+        SELECT $1, $2, * FROM UNNEST($3::text[])
+        RETURNING
+            todo_id, list_id, todo_content, is_completed, is_pinned, version, due_date,
+            priority AS "priority: Priority", created_at, updated_at, completed_at
+        "#,
+        user.user_id(),
+        list_id,
+        &contents,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to insert todos")?;
+
+    for todo in &todos {
+        history::record_change(
+            &mut transaction,
+            todo.todo_id,
+            user.user_id(),
+            TodoChange::Created,
+        )
+        .await?;
+    }
+
+    let counts = TodoCounts::fetch(&mut *transaction, list_id).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+    for todo in &todos {
+        publish_todo_event(&api_context, TodoEventKind::Created, list_id, todo.todo_id).await;
+    }
+
+    // the rows as the database stored them, new todos have no tags or
+    // subtasks yet
+    let todos = todos
+        .into_iter()
+        .map(|row| Todo {
+            todo_id: row.todo_id,
+            list_id: row.list_id,
+            todo_content: row.todo_content,
+            is_completed: row.is_completed,
+            is_pinned: row.is_pinned,
+            version: row.version,
+            due_date: row.due_date,
+            priority: row.priority,
+            tags: Vec::new(),
+            subtask_count: 0,
+            completed_subtask_count: 0,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            completed_at: row.completed_at,
+        })
+        .collect();
+
+    let preferences = api_context
+        .preferences
+        .get(&api_context.db, user.user_id())
+        .await?;
+    // nothing is created when every line was skipped
+    let status = if contents.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    Ok((
+        status,
+        AppendHeaders([("HX-Trigger", counts.hx_trigger())]),
+        HtmlOrJson::new(
+            format,
+            BatchTemplate {
+                todos,
+                skipped,
+                can_edit: true,
+                timezone: preferences.tz(),
+                today: preferences.today(),
+            },
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BatchError, MAX_BATCH_LINES, SkippedLine, split_lines};
+
+    #[test]
+    fn each_line_is_a_todo_and_invalid_ones_are_skipped() {
+        let too_long = "x".repeat(1025);
+        let text = format!("buy milk\n\n  walk the dog  \n{too_long}\r\ncall mom\n");
+        let (todos, skipped) = split_lines(&text).unwrap();
+
+        let todos: Vec<_> = todos.iter().map(ToString::to_string).collect();
+        assert_eq!(vec!["buy milk", "walk the dog", "call mom"], todos);
+        assert_eq!(
+            vec![
+                SkippedLine {
+                    line: 2,
+                    reason: "Todo is empty".to_string()
+                },
+                SkippedLine {
+                    line: 4,
+                    reason: "Todo is too long".to_string()
+                },
+            ],
+            skipped
+        );
+    }
+
+    #[test]
+    fn batches_over_the_line_cap_are_refused() {
+        let lines = vec!["buy milk"; MAX_BATCH_LINES];
+        assert!(split_lines(&lines.join("\n")).is_ok());
+
+        let lines = vec!["buy milk"; MAX_BATCH_LINES + 1];
+        assert!(matches!(
+            split_lines(&lines.join("\n")),
+            Err(BatchError::TooManyLines)
+        ));
+    }
+}
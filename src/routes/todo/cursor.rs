@@ -0,0 +1,102 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Position in the todos sorted newest first, the next page starts after it.
+///
+/// Sent to clients as `<base64 payload>.<base64 signature>`, so they can't
+/// point it at todos they wouldn't get to otherwise or craft slow queries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cursor {
+    pub created_at: OffsetDateTime,
+    pub todo_id: Uuid,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Payload {
+    /// Microseconds, the precision Postgres keeps, so the position is exact
+    created_at: i64,
+    todo_id: Uuid,
+}
+
+impl Cursor {
+    pub fn encode(&self, key: &[u8]) -> String {
+        let payload = Payload {
+            created_at: (self.created_at.unix_timestamp_nanos() / 1_000) as i64,
+            todo_id: self.todo_id,
+        };
+        let payload = URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&payload).expect("Cursor payload is always serializable"));
+        let signature = URL_SAFE_NO_PAD.encode(mac(key, &payload).finalize().into_bytes());
+        format!("{payload}.{signature}")
+    }
+
+    /// `None` if the cursor is forged or malformed
+    pub fn decode(key: &[u8], cursor: &str) -> Option<Self> {
+        let (payload, signature) = cursor.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        mac(key, payload).verify_slice(&signature).ok()?;
+
+        let payload: Payload =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+        Some(Self {
+            created_at: OffsetDateTime::from_unix_timestamp_nanos(
+                i128::from(payload.created_at) * 1_000,
+            )
+            .ok()?,
+            todo_id: payload.todo_id,
+        })
+    }
+}
+
+fn mac(key: &[u8], payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_none, assert_some_eq};
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    use crate::routes::todo::cursor::Cursor;
+
+    const KEY: &[u8] = b"some key";
+
+    fn cursor() -> Cursor {
+        Cursor {
+            // whole microseconds, as the cursor doesn't keep anything finer
+            created_at: OffsetDateTime::from_unix_timestamp_nanos(1_700_000_000_123_456_000)
+                .unwrap(),
+            todo_id: Uuid::new_v4(),
+        }
+    }
+
+    #[test]
+    pub fn cursor_survives_the_round_trip() {
+        let cursor = cursor();
+        assert_some_eq!(Cursor::decode(KEY, &cursor.encode(KEY)), cursor);
+    }
+
+    #[test]
+    pub fn cursor_signed_with_another_key_is_rejected() {
+        let encoded = cursor().encode(b"another key");
+        assert_none!(Cursor::decode(KEY, &encoded));
+    }
+
+    #[test]
+    pub fn cursor_with_a_changed_payload_is_rejected() {
+        let encoded = cursor().encode(KEY);
+        let (_, signature) = encoded.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{signature}",
+            cursor().encode(KEY).split_once('.').unwrap().0
+        );
+        assert_none!(Cursor::decode(KEY, &forged));
+        assert_none!(Cursor::decode(KEY, "not a cursor"));
+    }
+}
@@ -0,0 +1,310 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Form,
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+use time::Date;
+use time_tz::Tz;
+use uuid::Uuid;
+
+use super::{
+    Todo,
+    events::publish_todo_event,
+    filters,
+    history::{self, HistoryEntry, TodoChange},
+    list::list_url,
+    subtask::Subtask,
+};
+use crate::{
+    app::ApiContext, auth::AuthSession, authz, domain::todo_description::TodoDescription,
+    events::TodoEventKind, page::PageContext,
+};
+
+#[derive(Template, WebTemplate)]
+#[template(path = "todo/todo_detail.html")]
+struct TodoDetailTemplate {
+    todo: Todo,
+    description: String,
+    owner_username: String,
+    subtasks: Vec<Subtask>,
+    /// First page of the history, later ones are loaded on demand
+    history: Vec<HistoryEntry>,
+    has_more_history: bool,
+    can_edit: bool,
+    timezone: &'static Tz,
+    today: Date,
+    page_context: PageContext,
+}
+
+impl TodoDetailTemplate {
+    fn list_url(&self) -> String {
+        list_url(self.todo.list_id)
+    }
+}
+
+/// The description block of the detail page, swapped in after an edit
+#[derive(Template, WebTemplate)]
+#[template(path = "todo/todo_description.html")]
+struct TodoDescriptionTemplate {
+    todo_id: Uuid,
+    description: String,
+    can_edit: bool,
+}
+
+pub async fn get_todo(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    page_context: PageContext,
+    Path(todo_id): Path<Uuid>,
+) -> Response {
+    match auth_session.user {
+        Some(user) => todo_detail(&api_context, user.user_id(), todo_id, page_context).await,
+        None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn todo_detail(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    todo_id: Uuid,
+    page_context: PageContext,
+) -> Response {
+    let access = match authz::can_view_todo(api_context, user_id, todo_id).await {
+        Ok(todo_access) => todo_access.access,
+        Err(e) => return e.into_response(),
+    };
+
+    let todo = match api_context.todos.fetch(todo_id).await {
+        Ok(Some(todo)) => todo,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let description = api_context.todos.description(todo_id).await;
+    let owner_username = api_context.todos.owner_username(todo.list_id).await;
+    let subtasks = api_context.todos.subtasks(todo_id).await;
+    let history = api_context.todos.history(todo_id, 1).await;
+    let preferences = api_context.preferences.get(&api_context.db, user_id).await;
+
+    match (description, owner_username, subtasks, history, preferences) {
+        (
+            Ok(description),
+            Ok(owner_username),
+            Ok(subtasks),
+            Ok((history, has_more_history)),
+            Ok(preferences),
+        ) => TodoDetailTemplate {
+            todo,
+            description,
+            owner_username,
+            subtasks,
+            history,
+            has_more_history,
+            can_edit: access.can_edit(),
+            timezone: preferences.tz(),
+            today: preferences.today(),
+            page_context,
+        }
+        .into_response(),
+        _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct UpdateDescription {
+    description: String,
+}
+
+pub async fn update_description(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path(todo_id): Path<Uuid>,
+    Form(update_description): Form<UpdateDescription>,
+) -> impl IntoResponse {
+    let user = match auth_session.user {
+        Some(user) => user,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let description = match TodoDescription::parse(&update_description.description) {
+        Ok(description) => description,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    let list_id = match authz::require_edit_todo(&api_context, user.user_id(), todo_id).await {
+        Ok(todo_access) => todo_access.list_id,
+        Err(e) => return e.into_response(),
+    };
+
+    let result = async {
+        let mut transaction = api_context
+            .db
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+
+        let query_result = sqlx::query!(
+            r#"
+            UPDATE todo
+            SET description = $1, version = version + 1
+            WHERE todo_id = $2 AND list_id = $3
+            "#,
+            description.as_ref(),
+            todo_id,
+            list_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to update description")?;
+        if query_result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        history::record_change(
+            &mut transaction,
+            todo_id,
+            user.user_id(),
+            TodoChange::DescriptionEdited,
+        )
+        .await?;
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+
+        Ok::<_, anyhow::Error>(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => {
+            publish_todo_event(&api_context, TodoEventKind::Updated, list_id, todo_id).await;
+            TodoDescriptionTemplate {
+                todo_id,
+                description: description.as_ref().to_string(),
+                can_edit: true,
+            }
+            .into_response()
+        }
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::response::Response;
+    use http::StatusCode;
+    use uuid::Uuid;
+
+    use super::todo_detail;
+    use crate::{
+        app::ApiContext,
+        auth::repo::fake::FakeUserRepo,
+        domain::{priority::Priority, tag::Tags, todo_content::TodoContent},
+        page::PageContext,
+        preferences::Preferences,
+        routes::todo::{
+            list::{ListAccess, ListRole},
+            repo::{NewTodoRow, TodoRepo, fake::FakeTodoRepo},
+        },
+    };
+
+    async fn body(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    /// A user with their list and default preferences, so their pages can be
+    /// served without a database
+    async fn add_user(api_context: &ApiContext, todos: &FakeTodoRepo, user_id: Uuid) -> Uuid {
+        api_context
+            .preferences
+            .insert(user_id, Preferences::default())
+            .await;
+        todos.add_user(user_id)
+    }
+
+    /// A todo in the list of a user named alice, returning both ids
+    async fn alices_todo(api_context: &ApiContext, todos: &FakeTodoRepo) -> (Uuid, Uuid) {
+        let owner_id = Uuid::new_v4();
+        let list_id = add_user(api_context, todos, owner_id).await;
+        todos.set_username(owner_id, "alice");
+        let new_todo = NewTodoRow {
+            list_id,
+            todo_content: TodoContent::parse("buy milk").unwrap(),
+            due_date: None,
+            priority: Priority::High,
+            tags: Tags::parse("errands").unwrap(),
+        };
+        let todo = todos
+            .create(owner_id, new_todo, i64::MAX, 0.0)
+            .await
+            .unwrap()
+            .todo;
+        todos.set_description(todo.todo_id, "the oat one");
+        (owner_id, todo.todo_id)
+    }
+
+    #[tokio::test]
+    async fn detail_page_shows_the_todo_its_description_and_owner() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = ApiContext::for_tests(Arc::new(FakeUserRepo::default()), todos.clone());
+        let (owner_id, todo_id) = alices_todo(&api_context, &todos).await;
+
+        let response = todo_detail(&api_context, owner_id, todo_id, PageContext::default()).await;
+
+        assert_eq!(StatusCode::OK, response.status());
+        let html = body(response).await;
+        assert!(html.contains("buy milk"));
+        assert!(html.contains("the oat one"));
+        assert!(html.contains("alice's list"));
+        assert!(html.contains("Edit description"));
+    }
+
+    #[tokio::test]
+    async fn viewers_get_a_read_only_detail_page() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = ApiContext::for_tests(Arc::new(FakeUserRepo::default()), todos.clone());
+        let (owner_id, todo_id) = alices_todo(&api_context, &todos).await;
+        let viewer_id = Uuid::new_v4();
+        add_user(&api_context, &todos, viewer_id).await;
+        let list_id = todos.own_list_id(owner_id).await.unwrap();
+        todos.add_member(list_id, viewer_id, ListAccess::Member(ListRole::Viewer));
+
+        let response = todo_detail(&api_context, viewer_id, todo_id, PageContext::default()).await;
+
+        assert_eq!(StatusCode::OK, response.status());
+        let html = body(response).await;
+        assert!(html.contains("the oat one"));
+        assert!(!html.contains("Edit description"));
+    }
+
+    #[tokio::test]
+    async fn foreign_and_deleted_todos_have_no_detail_page() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = ApiContext::for_tests(Arc::new(FakeUserRepo::default()), todos.clone());
+        let (owner_id, todo_id) = alices_todo(&api_context, &todos).await;
+        let other_id = Uuid::new_v4();
+        add_user(&api_context, &todos, other_id).await;
+
+        let response = todo_detail(&api_context, other_id, todo_id, PageContext::default()).await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+
+        let list_id = todos.own_list_id(owner_id).await.unwrap();
+        todos.delete(todo_id, list_id, owner_id).await.unwrap();
+        let response = todo_detail(&api_context, owner_id, todo_id, PageContext::default()).await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+    }
+}
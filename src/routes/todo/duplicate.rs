@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use http::StatusCode;
+use uuid::Uuid;
+
+use super::{Created, TodoRowTemplate, events::publish_todo_event, list::list_url};
+use crate::{
+    app::ApiContext,
+    auth::AuthSession,
+    authz,
+    events::TodoEventKind,
+    negotiate::{Format, HtmlOrJson},
+    toast::{ToastLevel, with_toast},
+};
+
+/// Subtasks copied at most, so a todo with an unreasonable number of them
+/// can't make one request insert all of them again
+const MAX_DUPLICATED_SUBTASKS: i64 = 200;
+
+/// Adds a copy of the todo to its list, see [`super::TodoRepo::duplicate`].
+///
+/// Only editors can, the copy counts against the requester's todo limit
+/// like a todo they added. Responds like adding a todo does, with the copy
+/// as JSON, or its row for htmx to put right below the original.
+pub async fn duplicate_todo(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    format: Format,
+    Path(todo_id): Path<Uuid>,
+) -> Response {
+    let user = match auth_session.user {
+        Some(user) => user,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let list_id = match authz::require_edit_todo(&api_context, user.user_id(), todo_id).await {
+        Ok(todo_access) => todo_access.list_id,
+        Err(e) => return e.into_response(),
+    };
+
+    let duplicated = api_context
+        .todos
+        .duplicate(
+            todo_id,
+            list_id,
+            user.user_id(),
+            api_context.config.application_settings.max_active_todos,
+            MAX_DUPLICATED_SUBTASKS,
+        )
+        .await;
+    let Created { todo, counts, .. } = match duplicated {
+        Ok(Some(created)) => created,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => return e.into_response(),
+    };
+
+    let copy_id = todo.todo_id;
+    publish_todo_event(&api_context, TodoEventKind::Created, list_id, copy_id).await;
+    let row = TodoRowTemplate::for_user(&api_context, user.user_id(), todo, true).await;
+    let response = match format {
+        // the copy goes right below the original, see `todoAdded` in
+        // `todo/todo_list.html`
+        Format::Html => {
+            let added = serde_json::json!({ "todoAdded": { "todo_id": copy_id } });
+            (
+                StatusCode::CREATED,
+                AppendHeaders([
+                    ("HX-Retarget", format!("#todo-{todo_id}")),
+                    ("HX-Reswap", "afterend".to_string()),
+                    ("HX-Trigger", counts.hx_trigger()),
+                    ("HX-Trigger-After-Settle", added.to_string()),
+                ]),
+                HtmlOrJson::new(format, row),
+            )
+                .into_response()
+        }
+        Format::Json => (
+            StatusCode::CREATED,
+            AppendHeaders([
+                ("HX-Redirect", list_url(list_id)),
+                ("HX-Trigger", counts.hx_trigger()),
+            ]),
+            HtmlOrJson::new(format, row),
+        )
+            .into_response(),
+    };
+    with_toast(response, ToastLevel::Success, "Todo duplicated")
+}
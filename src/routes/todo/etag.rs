@@ -0,0 +1,161 @@
+use anyhow::Context;
+use http::{HeaderMap, HeaderName, header};
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgExecutor;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{i18n::Locale, negotiate::Format, preferences::TodoSort};
+
+/// Sent with the list view, so browsers revalidate every time instead of
+/// reusing it, and shared caches never store it
+pub const CACHE_CONTROL: &str = "private, no-cache";
+
+/// What the list page shows besides the todos and the people involved
+pub struct PageView {
+    pub sort: TodoSort,
+    pub show_completed: bool,
+    pub per_page: i64,
+    pub timezone: String,
+    /// The HTML and JSON of the page are tagged apart
+    pub format: Format,
+    pub locale: Locale,
+    /// Minutes since the epoch, relative timestamps on the page are rendered
+    /// against the current one and would go stale otherwise
+    pub minute: i64,
+}
+
+impl PageView {
+    pub fn minute_of(now: OffsetDateTime) -> i64 {
+        now.unix_timestamp().div_euclid(60)
+    }
+
+    /// Passed to [`list_etag`] as the view
+    pub fn key(&self) -> String {
+        format!(
+            "page:{}:{}:{}:{}:{:?}:{}:{}",
+            self.sort,
+            self.show_completed,
+            self.per_page,
+            self.timezone,
+            self.format,
+            self.locale,
+            self.minute
+        )
+    }
+}
+
+/// Weak ETag of a response showing the list, the page or a page of the API,
+/// from one aggregate query instead of the queries that render it. `view` is
+/// whatever else the response depends on, e.g. [`PageView::key`].
+///
+/// Every change to a todo moves its `updated_at`, deleted ones included, and
+/// adding or purging one changes the count. The timestamps are summed up as
+/// hashes rather than taking the latest, as a transaction that started
+/// earlier can commit an older one after a newer. Sharing and the names of
+/// the people involved are shown as well, so they are part of it too. It is
+/// weak as responses with the same tag may differ in formatting.
+pub async fn list_etag(
+    executor: impl PgExecutor<'_>,
+    list_id: Uuid,
+    user_id: Uuid,
+    view: &str,
+) -> Result<String, anyhow::Error> {
+    let aggregate = sqlx::query!(
+        r#"
+        SELECT
+            (
+                SELECT COALESCE(SUM(hashtext(todo_id::text || updated_at::text)), 0)
+                FROM todo WHERE list_id = $1
+            ) AS "todos_checksum!",
+            (SELECT COUNT(*) FROM todo WHERE list_id = $1) AS "todo_count!",
+            (
+                SELECT string_agg(
+                    lm.list_id::text || lm.user_id::text || lm.role::text, ','
+                    ORDER BY lm.list_id, lm.user_id
+                )
+                FROM list_members AS lm
+                WHERE lm.list_id = $1 OR lm.user_id = $2
+            ) AS memberships,
+            (
+                SELECT MAX(ui.updated_at)
+                FROM user_info AS ui
+                WHERE ui.user_id IN (
+                    SELECT tl.owner_id FROM todo_list AS tl
+                    JOIN list_members AS lm ON lm.list_id = tl.list_id
+                    WHERE lm.user_id = $2
+                    UNION
+                    SELECT tl.owner_id FROM todo_list AS tl WHERE tl.list_id = $1
+                    UNION
+                    SELECT lm.user_id FROM list_members AS lm WHERE lm.list_id = $1
+                )
+            ) AS users_updated_at
+        "#,
+        list_id,
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to get list aggregate")?;
+
+    let fingerprint = format!(
+        "{list_id}:{user_id}:{view}:{}:{}:{:?}:{:?}",
+        aggregate.todos_checksum,
+        aggregate.todo_count,
+        aggregate.memberships,
+        aggregate.users_updated_at,
+    );
+    let digest = hex::encode(Sha256::digest(fingerprint));
+    Ok(format!("W/\"{}\"", &digest[..32]))
+}
+
+/// The headers a list view is sent with
+pub fn cache_headers(etag: &str) -> [(HeaderName, String); 2] {
+    [
+        (header::ETAG, etag.to_string()),
+        (header::CACHE_CONTROL, CACHE_CONTROL.to_string()),
+    ]
+}
+
+/// Whether the client already has the version tagged `etag`. Weak
+/// comparison, as the tags are weak anyway.
+pub fn not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, HeaderValue, header};
+
+    use crate::routes::todo::etag::not_modified;
+
+    fn if_none_match(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    pub fn matching_tag_is_not_modified() {
+        assert!(not_modified(&if_none_match(r#"W/"abc""#), r#"W/"abc""#));
+        assert!(not_modified(&if_none_match(r#""abc""#), r#"W/"abc""#));
+        assert!(not_modified(
+            &if_none_match(r#""xyz", W/"abc""#),
+            r#"W/"abc""#
+        ));
+        assert!(not_modified(&if_none_match("*"), r#"W/"abc""#));
+    }
+
+    #[test]
+    pub fn other_or_missing_tag_is_modified() {
+        assert!(!not_modified(&if_none_match(r#"W/"xyz""#), r#"W/"abc""#));
+        assert!(!not_modified(&HeaderMap::new(), r#"W/"abc""#));
+    }
+}
@@ -0,0 +1,128 @@
+use std::{convert::Infallible, sync::Arc};
+
+use anyhow::Context;
+use askama::Template;
+use axum::{
+    extract::State,
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use http::StatusCode;
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use super::TodoRowTemplate;
+use crate::{
+    app::ApiContext,
+    auth::AuthSession,
+    events::{TodoEvent, TodoEventKind},
+};
+
+/// Streams live updates for every list the user can see.
+///
+/// Event names are scoped so each part of the page only reacts to its own
+/// events: `created-{list_id}` for new rows, and `updated-{todo_id}` and
+/// `deleted-{todo_id}` for existing ones.
+pub async fn todo_events(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+) -> impl IntoResponse {
+    let user = match auth_session.user {
+        Some(user) => user,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let subscription = match api_context.events.subscribe(user.user_id()) {
+        Ok(subscription) => subscription,
+        Err(e) => return (StatusCode::TOO_MANY_REQUESTS, e.to_string()).into_response(),
+    };
+
+    let stream = subscription.map(|event| {
+        let target = match event.kind {
+            TodoEventKind::Created => event.list_id,
+            TodoEventKind::Updated | TodoEventKind::Deleted => event.todo_id,
+        };
+        Ok::<_, Infallible>(
+            Event::default()
+                .event(format!("{}-{}", event.kind, target))
+                .data(event.fragment),
+        )
+    });
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Notifies everyone with access to the list about a change to one of its todos,
+/// and drops the views of the list they have cached.
+///
+/// The change has already been committed, so failures are only logged.
+pub async fn publish_todo_event(
+    api_context: &ApiContext,
+    kind: TodoEventKind,
+    list_id: Uuid,
+    todo_id: Uuid,
+) {
+    super::view_cache::invalidate_list(api_context, list_id).await;
+    if let Err(e) = try_publish_todo_event(api_context, kind, list_id, todo_id).await {
+        tracing::warn!(error = ?e, todo_id = %todo_id, "Failed to publish todo event");
+    }
+}
+
+async fn try_publish_todo_event(
+    api_context: &ApiContext,
+    kind: TodoEventKind,
+    list_id: Uuid,
+    todo_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let recipients = api_context.todos.recipients(list_id).await?;
+    let recipients: Vec<_> = recipients
+        .into_iter()
+        .filter(|recipient| api_context.events.has_subscribers(recipient.user_id))
+        .collect();
+    if recipients.is_empty() {
+        return Ok(());
+    }
+
+    let todo = match kind {
+        TodoEventKind::Deleted => None,
+        TodoEventKind::Created | TodoEventKind::Updated => Some(
+            api_context
+                .todos
+                .fetch(todo_id)
+                .await?
+                .context("Todo no longer exists")?,
+        ),
+    };
+
+    for recipient in recipients {
+        let fragment = match &todo {
+            // in the zone of the recipient, who may live elsewhere
+            Some(todo) => TodoRowTemplate::for_user(
+                api_context,
+                recipient.user_id,
+                todo.clone(),
+                recipient.can_edit,
+            )
+            .await
+            .render()
+            .context("Failed to render todo row")?,
+            None => String::new(),
+        };
+
+        api_context.events.publish(
+            recipient.user_id,
+            TodoEvent {
+                kind,
+                list_id,
+                todo_id,
+                fragment,
+            },
+        );
+    }
+
+    Ok(())
+}
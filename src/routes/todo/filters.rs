@@ -0,0 +1,191 @@
+use time::{
+    Duration, OffsetDateTime, format_description::BorrowedFormatItem, macros::format_description,
+};
+use time_tz::{OffsetDateTimeExt, Tz};
+
+pub use crate::i18n::filters::{fill, t};
+
+/// Key of the [`OffsetDateTime`] that [`relative_time`] counts from when a
+/// template is rendered with values, so tests get the same output every day
+pub const NOW: &str = "now";
+
+/// Renders a timestamp relative to now, e.g. "3 hours ago"
+pub fn relative_time(
+    timestamp: &OffsetDateTime,
+    values: &dyn askama::Values,
+) -> askama::Result<String> {
+    let now = askama::get_value::<OffsetDateTime>(values, NOW)
+        .copied()
+        .unwrap_or_else(|_| OffsetDateTime::now_utc());
+    Ok(relative_time_from(*timestamp, now))
+}
+
+const LOCAL_TIME_FORMAT: &[BorrowedFormatItem<'_>] = format_description!(
+    "[year]-[month]-[day] [hour]:[minute] [offset_hour sign:mandatory]:[offset_minute]"
+);
+
+/// Renders a timestamp in the user's zone, e.g. "2025-06-30 01:00 +13:00",
+/// for templates with the user's zone as a field, e.g.
+/// `{{ todo.created_at|local_time(timezone) }}`
+pub fn local_time(
+    timestamp: &OffsetDateTime,
+    _: &dyn askama::Values,
+    timezone: &Tz,
+) -> askama::Result<String> {
+    timestamp
+        .to_timezone(timezone)
+        .format(LOCAL_TIME_FORMAT)
+        .map_err(askama::Error::custom)
+}
+
+/// Renders user written Markdown as sanitized HTML, mark the result `safe`
+pub fn markdown(content: &str, _: &dyn askama::Values) -> askama::Result<String> {
+    Ok(crate::markdown::render(content))
+}
+
+/// Renders a user written Markdown title as sanitized inline HTML, mark the
+/// result `safe`
+pub fn inline_markdown(content: &str, _: &dyn askama::Values) -> askama::Result<String> {
+    Ok(crate::markdown::render_inline(content))
+}
+
+/// Renders user written Markdown as escaped text without markup, mark the
+/// result `safe`
+pub fn plain_markdown(content: &str, _: &dyn askama::Values) -> askama::Result<String> {
+    Ok(crate::markdown::render_plain(content))
+}
+
+fn relative_time_from(timestamp: OffsetDateTime, now: OffsetDateTime) -> String {
+    let elapsed = now - timestamp;
+
+    // timestamps slightly in the future come from clock skew between app and db
+    if elapsed < Duration::MINUTE {
+        return "just now".to_string();
+    }
+
+    let (count, unit) = if elapsed < Duration::HOUR {
+        (elapsed.whole_minutes(), "minute")
+    } else if elapsed < Duration::DAY {
+        (elapsed.whole_hours(), "hour")
+    } else if elapsed < Duration::days(2) {
+        return "yesterday".to_string();
+    } else if elapsed < Duration::days(30) {
+        (elapsed.whole_days(), "day")
+    } else if elapsed < Duration::days(365) {
+        (elapsed.whole_days() / 30, "month")
+    } else if elapsed < Duration::days(2 * 365) {
+        return "last year".to_string();
+    } else {
+        (elapsed.whole_days() / 365, "year")
+    };
+
+    if count == 1 {
+        format!("1 {unit} ago")
+    } else {
+        format!("{count} {unit}s ago")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, macros::datetime};
+    use time_tz::timezones;
+
+    use crate::routes::todo::filters::{local_time, relative_time_from};
+
+    #[test]
+    pub fn less_than_a_minute_is_just_now() {
+        let now = datetime!(2025-06-29 12:00 UTC);
+        assert_eq!("just now", relative_time_from(now, now));
+        assert_eq!(
+            "just now",
+            relative_time_from(now - Duration::seconds(59), now)
+        );
+    }
+
+    #[test]
+    pub fn future_timestamps_are_just_now() {
+        let now = datetime!(2025-06-29 12:00 UTC);
+        assert_eq!(
+            "just now",
+            relative_time_from(now + Duration::seconds(5), now)
+        );
+    }
+
+    #[test]
+    pub fn minutes_and_hours_are_pluralized() {
+        let now = datetime!(2025-06-29 12:00 UTC);
+        assert_eq!(
+            "1 minute ago",
+            relative_time_from(now - Duration::seconds(60), now)
+        );
+        assert_eq!(
+            "59 minutes ago",
+            relative_time_from(now - Duration::minutes(59), now)
+        );
+        assert_eq!(
+            "1 hour ago",
+            relative_time_from(now - Duration::minutes(60), now)
+        );
+        assert_eq!(
+            "3 hours ago",
+            relative_time_from(now - Duration::hours(3), now)
+        );
+    }
+
+    #[test]
+    pub fn one_to_two_days_is_yesterday() {
+        let now = datetime!(2025-06-29 12:00 UTC);
+        assert_eq!(
+            "23 hours ago",
+            relative_time_from(now - Duration::hours(23), now)
+        );
+        assert_eq!(
+            "yesterday",
+            relative_time_from(now - Duration::hours(24), now)
+        );
+        assert_eq!(
+            "yesterday",
+            relative_time_from(now - Duration::hours(47), now)
+        );
+        assert_eq!(
+            "2 days ago",
+            relative_time_from(now - Duration::hours(48), now)
+        );
+    }
+
+    #[test]
+    pub fn one_to_two_years_is_last_year() {
+        let now = datetime!(2025-06-29 12:00 UTC);
+        assert_eq!(
+            "12 months ago",
+            relative_time_from(now - Duration::days(364), now)
+        );
+        assert_eq!(
+            "last year",
+            relative_time_from(now - Duration::days(365), now)
+        );
+        assert_eq!(
+            "last year",
+            relative_time_from(now - Duration::days(729), now)
+        );
+        assert_eq!(
+            "2 years ago",
+            relative_time_from(now - Duration::days(730), now)
+        );
+    }
+
+    #[test]
+    pub fn local_time_is_in_the_users_zone() {
+        let timestamp = datetime!(2025-06-29 12:00 UTC);
+        let tongatapu = timezones::get_by_name("Pacific/Tongatapu").unwrap();
+        assert_eq!(
+            "2025-06-30 01:00 +13:00",
+            local_time(&timestamp, &(), tongatapu).unwrap()
+        );
+        assert_eq!(
+            "2025-06-29 12:00 +00:00",
+            local_time(&timestamp, &(), timezones::db::UTC).unwrap()
+        );
+    }
+}
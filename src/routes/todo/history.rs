@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+};
+use http::StatusCode;
+use sqlx::{PgConnection, postgres::PgExecutor, types::Json};
+use time::OffsetDateTime;
+use time_tz::Tz;
+use uuid::Uuid;
+
+use super::filters;
+use crate::{
+    app::ApiContext,
+    auth::AuthSession,
+    authz,
+    domain::priority::Priority,
+    webhook::{self, WebhookEvent},
+};
+
+/// History entries shown per page
+const HISTORY_PAGE_SIZE: i64 = 20;
+
+/// A change to a todo as recorded in `todo_events`.
+///
+/// Stored as JSON, so new kinds of changes don't need a migration. Existing
+/// variants can't be renamed without breaking the history already written.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TodoChange {
+    Created,
+    Completed,
+    Uncompleted,
+    PriorityChanged { old: Priority, new: Priority },
+    DescriptionEdited,
+    Deleted,
+    Restored,
+}
+
+impl std::fmt::Display for TodoChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TodoChange::Created => write!(f, "created this todo"),
+            TodoChange::Completed => write!(f, "marked it as done"),
+            TodoChange::Uncompleted => write!(f, "marked it as not done"),
+            TodoChange::PriorityChanged { old, new } => {
+                write!(f, "changed the priority from {old} to {new}")
+            }
+            TodoChange::DescriptionEdited => write!(f, "edited the description"),
+            TodoChange::Deleted => write!(f, "deleted it"),
+            TodoChange::Restored => write!(f, "restored it"),
+        }
+    }
+}
+
+/// Records a change made by `user_id` and queues the webhooks it triggers.
+/// Should run in the same transaction as the change itself, so the history
+/// can't miss or invent one.
+pub async fn record_change(
+    connection: &mut PgConnection,
+    todo_id: Uuid,
+    user_id: Uuid,
+    change: TodoChange,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO todo_events (todo_id, user_id, payload)
+        VALUES ($1, $2, $3)
+        "#,
+        todo_id,
+        user_id,
+        Json(&change) as _
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to record todo change")?;
+
+    if let Some(event) = WebhookEvent::for_change(&change) {
+        webhook::enqueue(connection, todo_id, event).await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct HistoryEntry {
+    /// `None` once the user who made the change is deleted
+    pub username: Option<String>,
+    pub change: TodoChange,
+    pub created_at: OffsetDateTime,
+}
+
+/// One page of a todo's history, newest first, and whether there are more
+pub async fn fetch_history(
+    executor: impl PgExecutor<'_>,
+    todo_id: Uuid,
+    page: i64,
+) -> Result<(Vec<HistoryEntry>, bool), anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT ui.username AS "username?", te.payload AS "change: Json<TodoChange>", te.created_at
+        FROM todo_events AS te
+        LEFT JOIN user_info AS ui ON ui.user_id = te.user_id
+        WHERE te.todo_id = $1
+        ORDER BY te.event_id DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        todo_id,
+        // one extra row tells whether there is a next page
+        HISTORY_PAGE_SIZE + 1,
+        (page - 1) * HISTORY_PAGE_SIZE
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to get todo history")?;
+
+    let has_more = rows.len() as i64 > HISTORY_PAGE_SIZE;
+    let entries = rows
+        .into_iter()
+        .take(HISTORY_PAGE_SIZE as usize)
+        .map(|row| HistoryEntry {
+            username: row.username,
+            change: row.change.0,
+            created_at: row.created_at,
+        })
+        .collect();
+
+    Ok((entries, has_more))
+}
+
+/// Entries of the history timeline, followed by a button loading the next page
+#[derive(Template, WebTemplate)]
+#[template(path = "todo/todo_history.html")]
+struct TodoHistoryTemplate {
+    todo_id: Uuid,
+    history: Vec<HistoryEntry>,
+    history_page: i64,
+    has_more_history: bool,
+    timezone: &'static Tz,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct HistoryQuery {
+    page: Option<i64>,
+}
+
+pub async fn get_history(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path(todo_id): Path<Uuid>,
+    Query(query): Query<HistoryQuery>,
+) -> impl IntoResponse {
+    let user = match auth_session.user {
+        Some(user) => user,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if let Err(e) = authz::can_view_todo(&api_context, user.user_id(), todo_id).await {
+        return e.into_response();
+    }
+
+    let page = query.page.unwrap_or(1).max(1);
+    let history = api_context.todos.history(todo_id, page).await;
+    let preferences = api_context
+        .preferences
+        .get(&api_context.db, user.user_id())
+        .await;
+    match (history, preferences) {
+        (Ok((history, has_more_history)), Ok(preferences)) => TodoHistoryTemplate {
+            todo_id,
+            history,
+            history_page: page,
+            has_more_history,
+            timezone: preferences.tz(),
+        }
+        .into_response(),
+        _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
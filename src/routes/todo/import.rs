@@ -0,0 +1,319 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    extract::{Multipart, State, multipart::MultipartError},
+    response::{AppendHeaders, IntoResponse},
+};
+use http::StatusCode;
+use time::Date;
+use uuid::Uuid;
+
+use super::{
+    DUE_DATE_FORMAT, TodoCounts,
+    events::publish_todo_event,
+    history::{self, TodoChange},
+    quota::{self, QuotaError},
+};
+use crate::{
+    app::ApiContext,
+    auth::AuthSession,
+    authz::{self, AuthzError},
+    domain::todo_content::TodoContent,
+    events::TodoEventKind,
+};
+
+/// Largest CSV file accepted for import, in bytes
+const MAX_IMPORT_FILE_BYTES: usize = 1024 * 1024;
+/// Most data rows (excluding the header) accepted in a single import
+const MAX_IMPORT_ROWS: usize = 1000;
+/// Rows inserted per statement
+const INSERT_BATCH_SIZE: usize = 500;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ImportError {
+    #[error("No file was uploaded")]
+    MissingFile,
+    #[error("File is too large, the maximum size is 1 MiB")]
+    FileTooLarge,
+    #[error("File is not valid UTF-8 text")]
+    NotUtf8,
+    #[error("File has too many rows, the maximum is {MAX_IMPORT_ROWS}")]
+    TooManyRows,
+    #[error("File is missing a todo_content column")]
+    MissingContentColumn,
+    #[error("Invalid list id")]
+    InvalidListId,
+    #[error("List not found")]
+    ListNotFound,
+    #[error("You can't add todos to this list")]
+    Forbidden,
+    #[error("Importing these todos would take you over your limit of {limit} todos")]
+    QuotaExceeded { limit: i64 },
+    #[error("Invalid upload")]
+    Multipart(#[from] MultipartError),
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for ImportError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match &self {
+            ImportError::MissingFile
+            | ImportError::NotUtf8
+            | ImportError::TooManyRows
+            | ImportError::MissingContentColumn
+            | ImportError::InvalidListId => StatusCode::BAD_REQUEST,
+            ImportError::FileTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ImportError::ListNotFound => StatusCode::NOT_FOUND,
+            ImportError::Forbidden => StatusCode::FORBIDDEN,
+            ImportError::QuotaExceeded { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ImportError::Multipart(e) => e.status(),
+            ImportError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+impl From<AuthzError> for ImportError {
+    fn from(e: AuthzError) -> Self {
+        match e {
+            AuthzError::NotFound => ImportError::ListNotFound,
+            AuthzError::Forbidden => ImportError::Forbidden,
+            AuthzError::UnexpectedError(e) => ImportError::UnexpectedError(e),
+        }
+    }
+}
+
+impl From<QuotaError> for ImportError {
+    fn from(e: QuotaError) -> Self {
+        match e {
+            QuotaError::Exceeded { limit } => ImportError::QuotaExceeded { limit },
+            QuotaError::UnexpectedError(e) => ImportError::UnexpectedError(e),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SkippedRow {
+    /// Line number in the file, counting the header as line 1
+    line: usize,
+    reason: String,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "todo/import_summary.html")]
+pub struct ImportSummaryTemplate {
+    imported: usize,
+    skipped: Vec<SkippedRow>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CsvRow {
+    #[serde(default)]
+    todo_content: Option<String>,
+    #[serde(default)]
+    is_completed: Option<String>,
+    #[serde(default)]
+    due_date: Option<String>,
+}
+
+#[derive(Debug)]
+struct ImportedTodo {
+    todo_content: TodoContent,
+    is_completed: bool,
+    due_date: Option<Date>,
+}
+
+impl TryFrom<CsvRow> for ImportedTodo {
+    type Error = String;
+
+    fn try_from(row: CsvRow) -> Result<Self, Self::Error> {
+        let todo_content = TodoContent::parse(row.todo_content.as_deref().unwrap_or_default())
+            .map_err(|e| e.to_string())?;
+
+        let is_completed = match row.is_completed.as_deref().map(str::trim) {
+            None | Some("") => false,
+            Some(s) if s.eq_ignore_ascii_case("true") || s == "1" => true,
+            Some(s) if s.eq_ignore_ascii_case("false") || s == "0" => false,
+            Some(_) => return Err("is_completed must be true or false".to_string()),
+        };
+
+        let due_date = match row.due_date.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(s) => Some(
+                Date::parse(s, DUE_DATE_FORMAT)
+                    .map_err(|_| "due_date must be formatted as YYYY-MM-DD".to_string())?,
+            ),
+        };
+
+        Ok(Self {
+            todo_content,
+            is_completed,
+            due_date,
+        })
+    }
+}
+
+/// Splits a CSV file into valid todos and the rows that were skipped.
+///
+/// Limits are enforced up front so a rejected file never imports anything.
+fn parse_csv(bytes: &[u8]) -> Result<(Vec<ImportedTodo>, Vec<SkippedRow>), ImportError> {
+    let text = std::str::from_utf8(bytes).map_err(|_| ImportError::NotUtf8)?;
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::Headers)
+        .from_reader(text.as_bytes());
+
+    let has_content_column = reader
+        .headers()
+        .map(|headers| headers.iter().any(|header| header == "todo_content"))
+        .unwrap_or(false);
+    if !has_content_column {
+        return Err(ImportError::MissingContentColumn);
+    }
+
+    let mut todos = Vec::new();
+    let mut skipped = Vec::new();
+    for (i, row) in reader.deserialize::<CsvRow>().enumerate() {
+        if i >= MAX_IMPORT_ROWS {
+            return Err(ImportError::TooManyRows);
+        }
+
+        let line = i + 2;
+        match row {
+            Ok(row) => match ImportedTodo::try_from(row) {
+                Ok(todo) => todos.push(todo),
+                Err(reason) => skipped.push(SkippedRow { line, reason }),
+            },
+            Err(_) => skipped.push(SkippedRow {
+                line,
+                reason: "Malformed row".to_string(),
+            }),
+        }
+    }
+
+    Ok((todos, skipped))
+}
+
+/// Imports todos from an uploaded CSV file with a `todo_content` column and
+/// optional `is_completed` and `due_date` columns.
+///
+/// Rows that fail validation are skipped and reported back. The valid rows are
+/// inserted in a single transaction, so either all of them are imported or none.
+pub async fn import_todos(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, ImportError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    let mut file = None;
+    let mut list_id = None;
+    while let Some(field) = multipart.next_field().await? {
+        match field.name() {
+            Some("file") => {
+                let bytes = field.bytes().await?;
+                if bytes.len() > MAX_IMPORT_FILE_BYTES {
+                    return Err(ImportError::FileTooLarge);
+                }
+                file = Some(bytes);
+            }
+            Some("list_id") => {
+                let text = field.text().await?;
+                if !text.is_empty() {
+                    list_id = Some(Uuid::parse_str(&text).map_err(|_| ImportError::InvalidListId)?);
+                }
+            }
+            _ => {}
+        }
+    }
+    let file = file.ok_or(ImportError::MissingFile)?;
+
+    let list_id = authz::require_edit_list_or_own(&api_context, user.user_id(), list_id).await?;
+
+    let (todos, skipped) = parse_csv(&file)?;
+
+    let mut transaction = api_context
+        .db
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+
+    quota::reserve(
+        &mut transaction,
+        user.user_id(),
+        todos.len() as i64,
+        api_context.config.application_settings.max_active_todos,
+    )
+    .await?;
+
+    let mut todo_ids = Vec::with_capacity(todos.len());
+    for batch in todos.chunks(INSERT_BATCH_SIZE) {
+        let contents: Vec<String> = batch
+            .iter()
+            .map(|todo| todo.todo_content.to_string())
+            .collect();
+        let completed: Vec<bool> = batch.iter().map(|todo| todo.is_completed).collect();
+        let due_dates: Vec<Option<Date>> = batch.iter().map(|todo| todo.due_date).collect();
+
+        let inserted = sqlx::query_scalar!(
+            r#"
+            INSERT INTO todo (
+                user_id, list_id, todo_content, is_completed, completed_at, due_date
+            )
+            -- the file doesn't say when, completed todos count as
+            -- completed when they were imported
+            SELECT
+                $1, $2, row.todo_content, row.is_completed,
+                CASE WHEN row.is_completed THEN NOW() END, row.due_date
+            FROM UNNEST($3::text[], $4::boolean[], $5::date[])
+                AS row(todo_content, is_completed, due_date)
+            RETURNING todo_id
+            "#,
+            user.user_id(),
+            list_id,
+            &contents,
+            &completed,
+            &due_dates as &[Option<Date>],
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        .context("Failed to insert imported todos")?;
+        todo_ids.extend(inserted);
+    }
+
+    for todo_id in &todo_ids {
+        history::record_change(
+            &mut transaction,
+            *todo_id,
+            user.user_id(),
+            TodoChange::Created,
+        )
+        .await?;
+    }
+
+    let counts = TodoCounts::fetch(&mut *transaction, list_id).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+    for todo_id in todo_ids {
+        publish_todo_event(&api_context, TodoEventKind::Created, list_id, todo_id).await;
+    }
+
+    Ok((
+        AppendHeaders([("HX-Trigger", counts.hx_trigger())]),
+        ImportSummaryTemplate {
+            imported: todos.len(),
+            skipped,
+        },
+    ))
+}
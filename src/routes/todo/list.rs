@@ -0,0 +1,399 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Form,
+    extract::{Path, State},
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use http::{HeaderMap, StatusCode};
+use sqlx::postgres::PgExecutor;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    app::ApiContext,
+    auth::{AuthSession, User},
+    authz::{self, AuthzError},
+    domain::username::Username,
+    negotiate::{IsHtmx, redirect_response},
+    notifications::{self, Notification},
+    page::PageContext,
+    telemetry::query_span,
+    toast::{ToastLevel, with_toast},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "list_role", rename_all = "lowercase")]
+pub enum ListRole {
+    Viewer,
+    Editor,
+}
+
+impl std::fmt::Display for ListRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListRole::Viewer => write!(f, "viewer"),
+            ListRole::Editor => write!(f, "editor"),
+        }
+    }
+}
+
+/// What the current user is allowed to do with a list
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListAccess {
+    Owner,
+    Member(ListRole),
+}
+
+impl ListAccess {
+    pub fn is_owner(self) -> bool {
+        self == ListAccess::Owner
+    }
+
+    pub fn can_edit(self) -> bool {
+        matches!(
+            self,
+            ListAccess::Owner | ListAccess::Member(ListRole::Editor)
+        )
+    }
+}
+
+/// Returns `None` when the list doesn't exist or the user has no access to it,
+/// so callers can't tell the two apart
+pub async fn list_access(
+    executor: impl PgExecutor<'_>,
+    list_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<ListAccess>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT tl.owner_id = $2 AS "is_owner!", lm.role AS "role?: ListRole"
+        FROM todo_list AS tl
+        LEFT JOIN list_members AS lm
+            ON lm.list_id = tl.list_id AND lm.user_id = $2
+        WHERE tl.list_id = $1 AND (tl.owner_id = $2 OR lm.user_id IS NOT NULL)
+        "#,
+        list_id,
+        user_id
+    )
+    .fetch_optional(executor)
+    .instrument(query_span("SELECT list access"))
+    .await
+    .context("Failed to get list access")?;
+
+    Ok(row.and_then(|row| to_access(row.is_owner, row.role)))
+}
+
+/// Same as [`list_access`], but looks the list up through one of its todos
+pub async fn todo_access(
+    executor: impl PgExecutor<'_>,
+    todo_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<(Uuid, ListAccess)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT tl.list_id, tl.owner_id = $2 AS "is_owner!", lm.role AS "role?: ListRole"
+        FROM todo AS td
+        JOIN todo_list AS tl ON tl.list_id = td.list_id
+        LEFT JOIN list_members AS lm
+            ON lm.list_id = tl.list_id AND lm.user_id = $2
+        WHERE td.todo_id = $1
+            AND td.deleted_at IS NULL
+            AND (tl.owner_id = $2 OR lm.user_id IS NOT NULL)
+        "#,
+        todo_id,
+        user_id
+    )
+    .fetch_optional(executor)
+    .instrument(query_span("SELECT todo access"))
+    .await
+    .context("Failed to get todo access")?;
+
+    Ok(row.and_then(|row| to_access(row.is_owner, row.role).map(|access| (row.list_id, access))))
+}
+
+fn to_access(is_owner: bool, role: Option<ListRole>) -> Option<ListAccess> {
+    if is_owner {
+        Some(ListAccess::Owner)
+    } else {
+        role.map(ListAccess::Member)
+    }
+}
+
+pub async fn own_list_id(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<Uuid, anyhow::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT list_id FROM todo_list WHERE owner_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to get own todo list")
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ListMember {
+    pub user_id: Uuid,
+    pub username: String,
+    pub role: ListRole,
+}
+
+pub async fn list_members(
+    executor: impl PgExecutor<'_>,
+    list_id: Uuid,
+) -> Result<Vec<ListMember>, anyhow::Error> {
+    sqlx::query_as!(
+        ListMember,
+        r#"
+        SELECT lm.user_id, ui.username, lm.role AS "role: ListRole"
+        FROM list_members AS lm
+        JOIN user_info AS ui ON ui.user_id = lm.user_id
+        WHERE lm.list_id = $1
+        ORDER BY ui.username
+        "#,
+        list_id
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to get list members")
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SharedList {
+    pub list_id: Uuid,
+    pub owner_username: String,
+    pub role: ListRole,
+}
+
+/// Lists owned by someone else that the user is a member of
+pub async fn shared_lists(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<Vec<SharedList>, anyhow::Error> {
+    sqlx::query_as!(
+        SharedList,
+        r#"
+        SELECT tl.list_id, ui.username AS owner_username, lm.role AS "role: ListRole"
+        FROM list_members AS lm
+        JOIN todo_list AS tl ON tl.list_id = lm.list_id
+        JOIN user_info AS ui ON ui.user_id = tl.owner_id
+        WHERE lm.user_id = $1
+        ORDER BY ui.username
+        "#,
+        user_id
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to get shared lists")
+}
+
+pub fn list_url(list_id: Uuid) -> String {
+    format!("/todo?list_id={list_id}")
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ShareError {
+    #[error("List not found")]
+    ListNotFound,
+    #[error("Only the list owner can manage sharing")]
+    NotOwner,
+    #[error("User not found")]
+    UserNotFound,
+    #[error("Member not found")]
+    MemberNotFound,
+    #[error("A list cannot be shared with its owner")]
+    SharedWithOwner,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ShareError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ShareError::ListNotFound | ShareError::UserNotFound | ShareError::MemberNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            ShareError::NotOwner => StatusCode::FORBIDDEN,
+            ShareError::SharedWithOwner => StatusCode::BAD_REQUEST,
+            ShareError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for ShareError {
+    fn into_response(self) -> axum::response::Response {
+        (self.status_code(), self.to_string()).into_response()
+    }
+}
+
+impl From<AuthzError> for ShareError {
+    fn from(e: AuthzError) -> Self {
+        match e {
+            AuthzError::NotFound => ShareError::ListNotFound,
+            AuthzError::Forbidden => ShareError::NotOwner,
+            AuthzError::UnexpectedError(e) => ShareError::UnexpectedError(e),
+        }
+    }
+}
+
+/// Shares the list with the user, or changes their role if it already is
+pub(crate) async fn add_member(
+    executor: impl PgExecutor<'_>,
+    list_id: Uuid,
+    member_id: Uuid,
+    role: ListRole,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_members (list_id, user_id, role)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (list_id, user_id) DO UPDATE SET role = EXCLUDED.role
+        "#,
+        list_id,
+        member_id,
+        role as ListRole
+    )
+    .execute(executor)
+    .await
+    .context("Failed to add list member")?;
+    Ok(())
+}
+
+/// `false` if the user wasn't a member
+pub(crate) async fn remove_member(
+    executor: impl PgExecutor<'_>,
+    list_id: Uuid,
+    member_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM list_members
+        WHERE list_id = $1 AND user_id = $2
+        "#,
+        list_id,
+        member_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to remove list member")?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ShareFormData {
+    username: String,
+    role: ListRole,
+}
+
+/// Browsers posting the form themselves are sent back to the list, or shown
+/// it again with the error
+pub async fn share_list(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    is_htmx: IsHtmx,
+    page_context: PageContext,
+    headers: HeaderMap,
+    Path(list_id): Path<Uuid>,
+    Form(form_data): Form<ShareFormData>,
+) -> Response {
+    let Some(user) = auth_session.user else {
+        return ShareError::UnexpectedError(anyhow::anyhow!("Missing user in session"))
+            .into_response();
+    };
+
+    match add_list_member(&api_context, &user, list_id, form_data).await {
+        Ok(username) if is_htmx.0 => with_toast(
+            AppendHeaders([("HX-Redirect", list_url(list_id))]),
+            ToastLevel::Success,
+            format!("List shared with {username}"),
+        ),
+        Ok(_) => redirect_response(is_htmx, &list_url(list_id)),
+        Err(e) if is_htmx.0 => e.into_response(),
+        Err(e) => {
+            super::form_error_page(
+                &api_context,
+                user.user_id(),
+                Some(list_id),
+                &headers,
+                page_context,
+                e.status_code(),
+                e.to_string(),
+            )
+            .await
+        }
+    }
+}
+
+/// Shares the list with the user named in the form, returning their name
+async fn add_list_member(
+    api_context: &ApiContext,
+    user: &User,
+    list_id: Uuid,
+    form_data: ShareFormData,
+) -> Result<Username, ShareError> {
+    authz::require_own_list(api_context, user.user_id(), list_id).await?;
+
+    // a malformed username can't belong to anyone, so it gets the same
+    // response as an unknown one
+    let username = Username::parse(&form_data.username).map_err(|_| ShareError::UserNotFound)?;
+
+    let member_id = api_context
+        .users
+        .find_by_username(&username)
+        .await?
+        .ok_or(ShareError::UserNotFound)?
+        .user_id();
+
+    if member_id == user.user_id() {
+        return Err(ShareError::SharedWithOwner);
+    }
+
+    api_context
+        .todos
+        .add_member(list_id, member_id, form_data.role)
+        .await?;
+    super::view_cache::invalidate_list(api_context, list_id).await;
+
+    let notification = Notification::ListShared {
+        list_id,
+        owner_username: user.username.clone(),
+        role: form_data.role,
+    };
+    if let Err(e) = notifications::notify(&api_context.db, member_id, notification).await {
+        // the list is shared either way
+        tracing::warn!(error = ?e, "Failed to notify the new list member");
+    }
+
+    Ok(username)
+}
+
+pub async fn revoke_member(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path((list_id, member_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, ShareError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+
+    authz::require_own_list(&api_context, user.user_id(), list_id).await?;
+
+    if !api_context.todos.remove_member(list_id, member_id).await? {
+        return Err(ShareError::MemberNotFound);
+    }
+    // the member has no access left, so isn't among the recipients anymore
+    api_context.view_cache.invalidate([member_id]).await;
+    super::view_cache::invalidate_list(&api_context, list_id).await;
+
+    Ok(with_toast(
+        AppendHeaders([("HX-Redirect", list_url(list_id))]),
+        ToastLevel::Success,
+        "Member removed",
+    ))
+}
@@ -5,176 +5,1913 @@ use askama::Template;
 use askama_web::WebTemplate;
 use axum::{
     Form, Router,
-    extract::{Path, State},
-    response::{AppendHeaders, IntoResponse},
-    routing::{delete, get},
+    extract::{Path, Query, State},
+    middleware,
+    response::{AppendHeaders, Html, IntoResponse, Response},
+    routing::{delete, get, post, put},
 };
 use axum_login::login_required;
-use http::StatusCode;
+use http::{HeaderMap, StatusCode, header};
+use serde::{Deserialize, Deserializer};
+use sqlx::postgres::PgExecutor;
+use time::{
+    Date, OffsetDateTime, format_description::BorrowedFormatItem, macros::format_description,
+};
+use time_tz::Tz;
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::{
     app::{ApiContext, AppRouter},
     auth::{AuthSession, Backend},
+    authz,
+    domain::{
+        priority::Priority,
+        tag::{TagName, Tags},
+        todo_content::TodoContent,
+    },
+    events::TodoEventKind,
+    i18n::Locale,
+    idempotency::{self, IdempotencyKey, NextAction},
+    negotiate::{self, Format, HtmlOrJson, IsHtmx, json_login_required, redirect_response},
+    page::PageContext,
+    preferences::{MAX_ITEMS_PER_PAGE, Preferences, TodoSort},
+    routes::tos,
+    telemetry::query_span,
+    toast::{ToastLevel, with_toast},
 };
 
+mod api;
+mod batch;
+pub(crate) mod cursor;
+mod detail;
+mod duplicate;
+mod etag;
+mod events;
+pub(crate) mod filters;
+pub(crate) mod history;
+mod import;
+pub(crate) mod list;
+mod pin;
+pub(crate) mod quota;
+pub(crate) mod repo;
+pub(crate) mod search;
+pub(crate) mod subtask;
+pub(crate) mod tag;
+mod undo;
+pub(crate) mod view_cache;
+
+use list::{ListMember, SharedList, list_url};
+#[cfg(feature = "test-util")]
+pub(crate) use repo::Db;
+use repo::{Created, DueFilter, NewTodoRow, TodoFilter, TodoUpdate};
+pub(crate) use repo::{PgTodoRepo, TodoRepo};
+use view_cache::CachedView;
+pub(crate) use view_cache::ViewCache;
+
 pub fn router() -> AppRouter {
     Router::new()
         .route("/todo", get(get_todos).post(new_todo))
-        .route("/todo/{todo_id}", delete(delete_todo).put(update_todo))
+        .route("/todo/batch", post(batch::add_batch))
+        .route("/todo/import", post(import::import_todos))
+        .route("/todo/events", get(events::todo_events))
+        .route("/todo/search", get(search::search))
+        .route(
+            "/todo/{todo_id}",
+            get(detail::get_todo).delete(delete_todo).put(update_todo),
+        )
+        .route(
+            "/todo/{todo_id}/description",
+            put(detail::update_description),
+        )
+        .route("/todo/{todo_id}/duplicate", post(duplicate::duplicate_todo))
+        .route("/todo/{todo_id}/history", get(history::get_history))
+        .route(
+            "/todo/{todo_id}/subtasks",
+            get(subtask::get_subtasks).post(subtask::create_subtask),
+        )
+        .route(
+            "/todo/{todo_id}/subtasks/{subtask_id}",
+            put(subtask::update_subtask).delete(subtask::delete_subtask),
+        )
+        .route("/todo/{todo_id}/pin", post(pin::toggle_pin))
+        .route("/todo/{todo_id}/undo", post(undo::undo_delete))
+        .route("/todo/{todo_id}/tags", put(tag::update_tags))
+        .route("/tags", get(tag::get_tags))
+        .route("/lists/{list_id}/share", post(list::share_list))
+        .route(
+            "/lists/{list_id}/members/{user_id}",
+            delete(list::revoke_member),
+        )
+        .route_layer(middleware::from_fn(tos::require_accepted_tos))
         .route_layer(login_required!(Backend, login_url = "/login"))
+        .route_layer(middleware::from_fn(json_login_required))
 }
 
-#[derive(Debug)]
-struct Todo {
-    todo_id: Uuid,
+/// Nested under `/api`. The handlers check the session themselves, so
+/// clients get a JSON error instead of a redirect to the login page.
+pub fn api_router() -> AppRouter {
+    Router::new().route("/todo", get(api::list_todos))
+}
+
+/// A row of the list as the templates show it, its tags and subtask counts
+/// included, so a page of them is loaded in one query, see
+/// [`TodoRepo::filtered`]
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct Todo {
+    pub(crate) todo_id: Uuid,
+    list_id: Uuid,
     todo_content: String,
     is_completed: bool,
+    is_pinned: bool,
+    pub(crate) version: i32,
+    #[serde(with = "due_date_format::option")]
+    due_date: Option<Date>,
+    priority: Priority,
+    tags: Vec<String>,
+    subtask_count: i64,
+    completed_subtask_count: i64,
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    updated_at: OffsetDateTime,
+    /// When it was last completed, `None` while it isn't
+    #[serde(with = "time::serde::rfc3339::option")]
+    completed_at: Option<OffsetDateTime>,
+}
+
+/// A todo of the list's "completed today" section
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct CompletedTodo {
+    todo_id: Uuid,
+    todo_content: String,
+    #[serde(with = "time::serde::rfc3339")]
+    completed_at: OffsetDateTime,
+}
+
+impl Todo {
+    /// Due before `today` where the user lives, and not completed yet
+    fn is_overdue(&self, today: Date) -> bool {
+        DueFilter::Overdue.matches(self.due_date, self.is_completed, today)
+    }
+
+    fn is_due(&self, today: Date) -> bool {
+        DueFilter::Today.matches(self.due_date, self.is_completed, today)
+    }
+}
+
+#[derive(Template, WebTemplate, serde::Serialize)]
+#[template(path = "todo/todo_row.html")]
+struct TodoRowTemplate {
+    #[serde(flatten)]
+    todo: Todo,
+    can_edit: bool,
+    conflict: bool,
+    /// Of the user the row is rendered for, see [`filters::local_time`]
+    #[serde(skip)]
+    timezone: &'static Tz,
+    /// The user's date, which tells whether the todo is overdue
+    #[serde(skip)]
+    today: Date,
+}
+
+impl TodoRowTemplate {
+    /// The row as the user sees it. Rendered once a change went through, so
+    /// preferences that fail to load fall back to the defaults rather than
+    /// failing the response.
+    async fn for_user(api_context: &ApiContext, user_id: Uuid, todo: Todo, can_edit: bool) -> Self {
+        let preferences = match api_context.preferences.get(&api_context.db, user_id).await {
+            Ok(preferences) => preferences,
+            Err(e) => {
+                tracing::warn!(error = ?e, "Failed to get the preferences of the user");
+                Preferences::default()
+            }
+        };
+        Self {
+            todo,
+            can_edit,
+            conflict: false,
+            timezone: preferences.tz(),
+            today: preferences.today(),
+        }
+    }
+}
+
+/// What the add form is filled in with. Empty but for the list, unless it
+/// is shown again with why the todo couldn't be added.
+#[derive(Debug, Default)]
+struct TodoForm {
+    list_id: Option<Uuid>,
+    todo_content: String,
+    due_date: Option<Date>,
+    tags: String,
+    priority: Option<String>,
+    error: Option<String>,
+}
+
+impl TodoForm {
+    fn for_list(list_id: Uuid) -> Self {
+        Self {
+            list_id: Some(list_id),
+            ..Self::default()
+        }
+    }
+
+    /// The form as it was sent
+    fn sent(new_todo: &NewTodo) -> Self {
+        Self {
+            list_id: new_todo.list_id,
+            todo_content: new_todo.todo_content.clone(),
+            due_date: new_todo.due_date,
+            tags: new_todo.tags.clone(),
+            priority: new_todo.priority.clone(),
+            error: None,
+        }
+    }
+
+    /// The priority that was sent, if it was one
+    fn is_selected(&self, priority: &Priority) -> bool {
+        let selected = self
+            .priority
+            .as_deref()
+            .and_then(|selected| Priority::parse(selected).ok())
+            .unwrap_or_default();
+        *priority == selected
+    }
+}
+
+/// The add form on its own, which replaces the one on the page
+#[derive(Template, WebTemplate)]
+#[template(path = "todo/todo_form.html")]
+struct TodoFormTemplate {
+    locale: Locale,
+    todo_form: TodoForm,
+    /// Sent along with the added row, swapped in out of band
+    oob: bool,
 }
 
+/// The list page around a rendered [`TodoTemplate`]
 #[derive(Template, WebTemplate)]
 #[template(path = "todo/todos_template.html")]
+struct TodoPageTemplate {
+    locale: Locale,
+    list: String,
+    page_context: PageContext,
+}
+
+/// The list page without the layout, which is what [`view_cache`] keeps,
+/// or the list as JSON
+#[derive(Template, serde::Serialize)]
+#[template(path = "todo/todo_list.html")]
 struct TodoTemplate {
+    #[serde(skip)]
+    locale: Locale,
+    list_id: Uuid,
+    owner_username: String,
+    is_owner: bool,
+    can_edit: bool,
     todos: Vec<Todo>,
+    /// Completed on `today`, whatever the filter, latest first
+    completed_today: Vec<CompletedTodo>,
+    /// Counts cover the whole list, regardless of the tag filter
+    active_count: i64,
+    completed_count: i64,
+    /// Tag the todos are filtered by, if any
+    tag: Option<String>,
+    /// Due date bucket the todos are filtered by, if any
+    due: Option<DueFilter>,
+    sort: TodoSort,
+    show_completed: bool,
+    page: i64,
+    has_next_page: bool,
+    members: Vec<ListMember>,
+    shared_lists: Vec<SharedList>,
+    #[serde(skip)]
+    timezone: &'static Tz,
+    #[serde(skip)]
+    today: Date,
+    /// Why a form posted without htmx failed
+    #[serde(skip)]
+    form_error: Option<String>,
+    #[serde(skip)]
+    todo_form: TodoForm,
+}
+
+impl TodoTemplate {
+    /// Link to the current view of the list with the given options
+    fn url(&self, sort: TodoSort, show_completed: bool, page: i64) -> String {
+        self.url_due(sort, show_completed, page, self.due)
+    }
+
+    /// Link to the first page of the current view, in another due date bucket
+    fn due_url(&self, due: Option<DueFilter>) -> String {
+        self.url_due(self.sort, self.show_completed, 1, due)
+    }
+
+    fn url_due(
+        &self,
+        sort: TodoSort,
+        show_completed: bool,
+        page: i64,
+        due: Option<DueFilter>,
+    ) -> String {
+        let mut url = format!(
+            "/todo?list_id={}&sort={sort}&show_completed={show_completed}&page={page}",
+            self.list_id
+        );
+        // tag names are restricted to url-safe characters
+        if let Some(tag) = &self.tag {
+            url.push_str(&format!("&tag={tag}"));
+        }
+        if let Some(due) = due {
+            url.push_str(&format!("&due={due}"));
+        }
+        url
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct TodoCounts {
+    active: i64,
+    completed: i64,
+}
+
+impl TodoCounts {
+    async fn fetch(executor: impl PgExecutor<'_>, list_id: Uuid) -> Result<Self, anyhow::Error> {
+        sqlx::query_as!(
+            TodoCounts,
+            r#"
+            SELECT
+                COUNT(*) FILTER (WHERE NOT is_completed) AS "active!",
+                COUNT(*) FILTER (WHERE is_completed) AS "completed!"
+            FROM todo
+            WHERE list_id = $1 AND deleted_at IS NULL
+            "#,
+            list_id
+        )
+        .fetch_one(executor)
+        .await
+        .context("Failed to count todos")
+    }
+
+    /// `HX-Trigger` payload that lets the footer update without refetching the list
+    fn hx_trigger(&self) -> String {
+        serde_json::json!({ "todoCounts": self }).to_string()
+    }
 }
 
+#[derive(Debug, Default, serde::Deserialize)]
+struct TodoQuery {
+    list_id: Option<Uuid>,
+    tag: Option<String>,
+    due: Option<DueFilter>,
+    /// Options left out fall back to the user's preferences
+    sort: Option<TodoSort>,
+    show_completed: Option<bool>,
+    page: Option<i64>,
+}
+
+/// The list as HTML or JSON, see [`Format::from_headers`]. Answers with 304
+/// when the client's `If-None-Match` still matches the list, see
+/// [`etag::list_etag`].
 async fn get_todos(
     State(api_context): State<Arc<ApiContext>>,
     auth_session: AuthSession,
-) -> impl IntoResponse {
-    let user = match auth_session.user {
-        Some(user) => user,
-        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    page_context: PageContext,
+    headers: HeaderMap,
+    Query(query): Query<TodoQuery>,
+) -> Response {
+    match auth_session.user {
+        Some(user) => list_page(&api_context, user.user_id(), &headers, query, page_context).await,
+        None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn list_page(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    headers: &HeaderMap,
+    query: TodoQuery,
+    page_context: PageContext,
+) -> Response {
+    render_list(api_context, user_id, headers, query, page_context, None).await
+}
+
+/// The list page, with `form_error` shown above it when it isn't `None`
+async fn render_list(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    headers: &HeaderMap,
+    query: TodoQuery,
+    page_context: PageContext,
+    form_error: Option<String>,
+) -> Response {
+    let format = Format::from_headers(headers);
+    let tag = match query.tag.as_deref().filter(|tag| !tag.trim().is_empty()) {
+        Some(tag) => match TagName::parse(tag) {
+            Ok(tag) => Some(tag.to_string()),
+            Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        },
+        None => None,
     };
 
-    let user_todos = sqlx::query_as!(
-        Todo,
-        r#"
-        SELECT todo_id, todo_content, is_completed FROM todo AS td
-        WHERE td.user_id = $1
-        ORDER BY td.created_at DESC
-        "#,
-        user.user_id()
-    )
-    .fetch_all(&api_context.db)
-    .await
-    .context("Failed to get todos");
+    let (list_id, access) =
+        match authz::can_view_list_or_own(api_context, user_id, query.list_id).await {
+            Ok(list) => list,
+            Err(e) => return e.into_response(),
+        };
+
+    let preferences = match api_context.preferences.get(&api_context.db, user_id).await {
+        Ok(preferences) => preferences,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let sort = query.sort.unwrap_or(preferences.default_sort);
+    let show_completed = query.show_completed.unwrap_or(preferences.show_completed);
+    let page = query.page.unwrap_or(1).max(1);
+    // also bounded here, so no stored value can make the page unbounded
+    let per_page = i64::from(preferences.items_per_page.clamp(1, MAX_ITEMS_PER_PAGE));
+    let locale = Locale::negotiate(preferences.locale, headers);
+
+    let view = etag::PageView {
+        sort,
+        show_completed,
+        per_page,
+        timezone: preferences.timezone.clone(),
+        format,
+        locale,
+        minute: etag::PageView::minute_of(OffsetDateTime::now_utc()),
+    };
+    let page_template = |list| TodoPageTemplate {
+        locale,
+        list,
+        page_context,
+    };
 
-    if let Ok(todos) = user_todos {
-        let todo_template = TodoTemplate { todos };
-        todo_template.into_response()
-    } else {
-        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    // looked up before the todos are read, so a change made meanwhile bumps
+    // the generation past the entry made from them
+    let cache_key = match form_error {
+        // the error is only shown this once
+        Some(_) => None,
+        None => {
+            let view_key = format!(
+                "{list_id}:{access:?}:{tag:?}:{:?}:{page}:{}",
+                query.due,
+                view.key()
+            );
+            api_context.view_cache.key(user_id, &view_key).await
+        }
+    };
+    if let Some(key) = &cache_key
+        && let Some(cached) = api_context.view_cache.get(key).await
+    {
+        return send_view(headers, format, cached, page_template);
+    }
+
+    let etag = match api_context
+        .todos
+        .list_etag(list_id, user_id, &view.key())
+        .await
+    {
+        Ok(etag) => etag,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    if form_error.is_none() && etag::not_modified(headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, etag::cache_headers(&etag)).into_response();
+    }
+
+    let owner_username = api_context.todos.owner_username(list_id).await;
+    let today = preferences.today();
+    let filter = TodoFilter {
+        tag: tag.clone(),
+        due: query.due,
+        today,
+        sort,
+        show_completed,
+        // one extra row tells whether there is a next page
+        limit: per_page + 1,
+        offset: (page - 1) * per_page,
+    };
+    let user_todos = api_context.todos.filtered(list_id, &filter).await;
+    let counts = api_context.todos.counts(list_id).await;
+    let members = api_context.todos.members(list_id).await;
+    let shared_lists = api_context.todos.shared_lists(user_id).await;
+    let completed_today = api_context
+        .todos
+        .completed_on(list_id, today, preferences.tz())
+        .await;
+
+    match (
+        owner_username,
+        user_todos,
+        counts,
+        members,
+        shared_lists,
+        completed_today,
+    ) {
+        (
+            Ok(owner_username),
+            Ok(mut todos),
+            Ok(counts),
+            Ok(members),
+            Ok(shared_lists),
+            Ok(completed_today),
+        ) => {
+            let has_next_page = todos.len() as i64 > per_page;
+            todos.truncate(per_page as usize);
+            let todo_template = TodoTemplate {
+                locale,
+                list_id,
+                owner_username,
+                is_owner: access.is_owner(),
+                can_edit: access.can_edit(),
+                todos,
+                completed_today,
+                active_count: counts.active,
+                completed_count: counts.completed,
+                tag,
+                due: query.due,
+                sort,
+                show_completed,
+                page,
+                has_next_page,
+                members,
+                shared_lists,
+                timezone: preferences.tz(),
+                today,
+                form_error,
+                todo_form: TodoForm::for_list(list_id),
+            };
+            let body = match format {
+                Format::Html => todo_template.render().context("Failed to render list"),
+                Format::Json => {
+                    serde_json::to_string(&todo_template).context("Failed to serialize list")
+                }
+            };
+            let body = match body {
+                Ok(body) => body,
+                Err(e) => {
+                    tracing::error!(error = ?e, "Failed to render list view");
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+
+            if todo_template.form_error.is_some() {
+                let mut response = match format {
+                    Format::Html => page_template(body).into_response(),
+                    Format::Json => json_body(body),
+                };
+                negotiate::set_vary(&mut response);
+                return response;
+            }
+            let view = CachedView { etag, body };
+            if let Some(key) = &cache_key {
+                api_context.view_cache.set(key, &view).await;
+            }
+            send_view(headers, format, view, page_template)
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
 
-#[derive(Debug, serde::Deserialize)]
+/// Sends the view with its ETag, in the page for HTML, or a 304 when the
+/// client already has it
+fn send_view(
+    headers: &HeaderMap,
+    format: Format,
+    view: CachedView,
+    page_template: impl FnOnce(String) -> TodoPageTemplate,
+) -> Response {
+    let cache_headers = etag::cache_headers(&view.etag);
+    if etag::not_modified(headers, &view.etag) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    let mut response = match format {
+        Format::Html => (cache_headers, page_template(view.body)).into_response(),
+        Format::Json => (cache_headers, json_body(view.body)).into_response(),
+    };
+    negotiate::set_vary(&mut response);
+    response
+}
+
+fn json_body(body: String) -> Response {
+    ([(header::CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
 struct NewTodo {
     todo_content: String,
+    list_id: Option<Uuid>,
+    #[serde(default, deserialize_with = "deserialize_due_date")]
+    due_date: Option<Date>,
+    /// Comma-separated tag names
+    #[serde(default)]
+    tags: String,
+    priority: Option<String>,
+}
+
+const DUE_DATE_FORMAT: &[BorrowedFormatItem<'_>] = format_description!("[year]-[month]-[day]");
+time::serde::format_description!(due_date_format, Date, DUE_DATE_FORMAT);
+
+/// Date inputs submit an empty string when left blank
+fn deserialize_due_date<'de, D>(deserializer: D) -> Result<Option<Date>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)?
+        .as_deref()
+        .map(str::trim)
+    {
+        None | Some("") => Ok(None),
+        Some(s) => Date::parse(s, DUE_DATE_FORMAT)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
 }
 
 #[derive(Debug, serde::Deserialize)]
 struct UpdateTodo {
-    is_completed: bool,
+    is_completed: Option<bool>,
+    priority: Option<String>,
+    /// Version of the todo the client last saw
+    version: i32,
 }
 
+/// Adds a todo, responding with it as JSON. A request retried with the same
+/// `Idempotency-Key` header gets the response of the first one instead of
+/// adding a duplicate.
 async fn new_todo(
     State(api_context): State<Arc<ApiContext>>,
     auth_session: AuthSession,
+    page_context: PageContext,
+    locale: Locale,
+    headers: HeaderMap,
     Form(new_todo): Form<NewTodo>,
-) -> impl IntoResponse {
+) -> Response {
     let user = match auth_session.user {
         Some(user) => user,
         None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     };
+    let format = Format::from_headers(&headers);
+    let is_htmx = IsHtmx::from_headers(&headers);
+    // the key is only ever added by the page's JavaScript
+    if !is_htmx.0 && format == Format::Html {
+        return submit_todo_form(
+            &api_context,
+            user.user_id(),
+            new_todo,
+            &headers,
+            page_context,
+        )
+        .await;
+    }
 
-    let new_todo = sqlx::query!(
-        r#"
-        INSERT INTO todo (user_id, todo_content)
-        VALUES ($1, $2)
-        "#,
-        user.user_id(),
-        new_todo.todo_content
-    )
-    .execute(&api_context.db)
-    .await
-    .context("Failed to add todo");
+    let key = match IdempotencyKey::from_headers(&headers) {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            return create_todo(&api_context, user.user_id(), new_todo, format, locale).await;
+        }
+        Err(e) => return e.into_response(),
+    };
+    let request_hash = match idempotency::request_hash(&new_todo) {
+        Ok(request_hash) => request_hash,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    match idempotency::try_begin(&api_context.db, user.user_id(), &key, &request_hash).await {
+        Ok(NextAction::Process) => {}
+        Ok(NextAction::Replay(response)) => return response,
+        Err(e) => return e.into_response(),
+    }
 
-    match new_todo {
-        Ok(_) => (
-            StatusCode::CREATED,
-            AppendHeaders([("HX-Redirect", "/todo")]),
-        )
-            .into_response(),
+    let response = create_todo(&api_context, user.user_id(), new_todo, format, locale).await;
+    match idempotency::save_response(&api_context.db, user.user_id(), &key, response).await {
+        Ok(response) => response,
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
 
+/// Adds the todo for htmx or JSON clients. htmx gets the row to put first in
+/// the list along with an emptied form swapped in out of band, or the form
+/// as it was sent with the error when the todo couldn't be added, so typing
+/// the next one can go on right away either way.
+async fn create_todo(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    new_todo: NewTodo,
+    format: Format,
+    locale: Locale,
+) -> Response {
+    let sent = TodoForm::sent(&new_todo);
+    match add_todo(api_context, user_id, new_todo).await {
+        Ok(Created {
+            todo,
+            counts,
+            is_new,
+        }) => {
+            let list_id = todo.list_id;
+            let todo_id = todo.todo_id;
+            let row = TodoRowTemplate::for_user(api_context, user_id, todo, true).await;
+            let response = match format {
+                Format::Html => {
+                    let form = TodoFormTemplate {
+                        locale,
+                        todo_form: TodoForm::for_list(list_id),
+                        oob: true,
+                    };
+                    let added = serde_json::json!({ "todoAdded": { "todo_id": todo_id } });
+                    (
+                        AppendHeaders([
+                            // the row of a form sent twice is already in the list
+                            (
+                                "HX-Reswap",
+                                if is_new { "afterbegin" } else { "none" }.to_string(),
+                            ),
+                            ("HX-Trigger", counts.hx_trigger()),
+                            ("HX-Trigger-After-Settle", added.to_string()),
+                        ]),
+                        render_fragments(&row, &form),
+                    )
+                        .into_response()
+                }
+                Format::Json => (
+                    AppendHeaders([
+                        ("HX-Redirect", list_url(list_id)),
+                        ("HX-Trigger", counts.hx_trigger()),
+                    ]),
+                    HtmlOrJson::new(format, row),
+                )
+                    .into_response(),
+            };
+            if !is_new {
+                // the form was sent twice, the first one already said so
+                return (StatusCode::OK, response).into_response();
+            }
+            with_toast(
+                (StatusCode::CREATED, response),
+                ToastLevel::Success,
+                "Todo added",
+            )
+        }
+        Err((status, message)) => match format {
+            Format::Html => {
+                let form = TodoFormTemplate {
+                    locale,
+                    todo_form: TodoForm {
+                        error: Some(message),
+                        ..sent
+                    },
+                    oob: false,
+                };
+                // the error is in the form, see `toast::error_toasts`
+                (
+                    status,
+                    AppendHeaders([("HX-Retarget", "#todo-form"), ("HX-Reswap", "outerHTML")]),
+                    form,
+                )
+                    .into_response()
+            }
+            Format::Json => (status, message).into_response(),
+        },
+    }
+}
+
+/// The row followed by the form, in one body
+fn render_fragments(row: &TodoRowTemplate, form: &TodoFormTemplate) -> Response {
+    match (row.render(), form.render()) {
+        (Ok(row), Ok(form)) => Html(row + &form).into_response(),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!(error = ?e, "Failed to render the added todo");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// The form posted by the browser itself, which is sent back to the list,
+/// or shown the list again with the error
+async fn submit_todo_form(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    new_todo: NewTodo,
+    headers: &HeaderMap,
+    page_context: PageContext,
+) -> Response {
+    let requested_list_id = new_todo.list_id;
+    match add_todo(api_context, user_id, new_todo).await {
+        Ok(Created { todo, .. }) => redirect_response(IsHtmx(false), &list_url(todo.list_id)),
+        Err((status, message)) => {
+            form_error_page(
+                api_context,
+                user_id,
+                requested_list_id,
+                headers,
+                page_context,
+                status,
+                message,
+            )
+            .await
+        }
+    }
+}
+
+/// Adds the todo and lets the list's viewers know, or fails with the status
+/// and the message to respond with. The same todo sent again right away is
+/// only added once, see [`TodoRepo::create`].
+async fn add_todo(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    new_todo: NewTodo,
+) -> Result<Created, (StatusCode, String)> {
+    let settings = &api_context.config.application_settings;
+    let bad_request = |e: &dyn std::fmt::Display| (StatusCode::BAD_REQUEST, e.to_string());
+
+    let todo_content = TodoContent::parse(&new_todo.todo_content).map_err(|e| bad_request(&e))?;
+    let tags = Tags::parse(&new_todo.tags).map_err(|e| bad_request(&e))?;
+    let priority = match new_todo.priority.as_deref().map(Priority::parse) {
+        Some(priority) => priority.map_err(|e| bad_request(&e))?,
+        None => Priority::default(),
+    };
+
+    let list_id = authz::require_edit_list_or_own(api_context, user_id, new_todo.list_id)
+        .await
+        .map_err(|e| (e.status_code(), e.to_string()))?;
+
+    let created = api_context
+        .todos
+        .create(
+            user_id,
+            NewTodoRow {
+                list_id,
+                todo_content,
+                due_date: new_todo.due_date,
+                priority,
+                tags,
+            },
+            settings.max_active_todos,
+            settings.todo_repeat_window_secs as f64,
+        )
+        .await
+        .map_err(|e| (e.status_code(), e.to_string()))?;
+
+    if created.is_new {
+        let todo_id = created.todo.todo_id;
+        events::publish_todo_event(api_context, TodoEventKind::Created, list_id, todo_id).await;
+    }
+    Ok(created)
+}
+
+/// The list page again, with `message` by its forms, for a form posted
+/// without htmx that failed
+pub(super) async fn form_error_page(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    list_id: Option<Uuid>,
+    headers: &HeaderMap,
+    page_context: PageContext,
+    status: StatusCode,
+    message: String,
+) -> Response {
+    let query = TodoQuery {
+        list_id,
+        ..TodoQuery::default()
+    };
+    let mut response = render_list(
+        api_context,
+        user_id,
+        headers,
+        query,
+        page_context,
+        Some(message),
+    )
+    .await;
+    if response.status().is_success() {
+        *response.status_mut() = status;
+    }
+    response
+}
+
 async fn delete_todo(
     State(api_context): State<Arc<ApiContext>>,
     auth_session: AuthSession,
+    format: Format,
     Path(todo_id): Path<Uuid>,
-) -> impl IntoResponse {
-    let user = match auth_session.user {
-        Some(user) => user,
-        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-    };
+) -> Response {
+    match auth_session.user {
+        Some(user) => remove_todo(&api_context, user.user_id(), todo_id, format).await,
+        None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
 
-    let result = sqlx::query!(
-        r#"
-        DELETE FROM todo
-        WHERE todo_id = $1 AND user_id = $2
-        "#,
-        todo_id,
-        user.user_id()
-    )
-    .execute(&api_context.db)
-    .await
-    .context("Failed to delete todo");
+async fn remove_todo(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    todo_id: Uuid,
+    format: Format,
+) -> Response {
+    let list_id = match authz::require_edit_todo(api_context, user_id, todo_id).await {
+        Ok(todo_access) => todo_access.list_id,
+        Err(e) => return e.into_response(),
+    };
 
-    if let Ok(query_result) = result {
-        if query_result.rows_affected() > 0 {
-            (StatusCode::OK, AppendHeaders([("HX-Redirect", "/todo")])).into_response()
-        } else {
-            StatusCode::NOT_FOUND.into_response()
+    // the row is removed by the empty main response, the toast is swapped in
+    // out of band
+    match api_context.todos.delete(todo_id, list_id, user_id).await {
+        Ok((Some(todo_content), counts)) => {
+            events::publish_todo_event(api_context, TodoEventKind::Deleted, list_id, todo_id).await;
+            (
+                StatusCode::OK,
+                AppendHeaders([("HX-Trigger", counts.hx_trigger())]),
+                HtmlOrJson::new(
+                    format,
+                    undo::UndoToastTemplate {
+                        todo_id,
+                        todo_content,
+                    },
+                ),
+            )
+                .into_response()
         }
-    } else {
-        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        Ok((None, _)) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
 
 async fn update_todo(
     State(api_context): State<Arc<ApiContext>>,
     auth_session: AuthSession,
+    format: Format,
     Path(todo_id): Path<Uuid>,
     Form(update_todo): Form<UpdateTodo>,
-) -> impl IntoResponse {
-    let user = match auth_session.user {
-        Some(user) => user,
-        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+) -> Response {
+    match auth_session.user {
+        Some(user) => change_todo(&api_context, user.user_id(), todo_id, update_todo, format).await,
+        None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn change_todo(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    todo_id: Uuid,
+    update_todo: UpdateTodo,
+    format: Format,
+) -> Response {
+    let list_id = match authz::require_edit_todo(api_context, user_id, todo_id).await {
+        Ok(todo_access) => todo_access.list_id,
+        Err(e) => return e.into_response(),
     };
 
-    let result = sqlx::query!(
+    let priority = match update_todo.priority.as_deref().map(Priority::parse) {
+        Some(Ok(priority)) => Some(priority),
+        Some(Err(e)) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        None => None,
+    };
+
+    let update = TodoUpdate {
+        is_completed: update_todo.is_completed,
+        priority,
+        version: update_todo.version,
+    };
+    match api_context
+        .todos
+        .update(todo_id, list_id, user_id, update)
+        .await
+    {
+        Ok(true) => {
+            events::publish_todo_event(api_context, TodoEventKind::Updated, list_id, todo_id).await;
+            let (counts, todo) = match tokio::try_join!(
+                api_context.todos.counts(list_id),
+                api_context.todos.fetch(todo_id)
+            ) {
+                Ok((counts, Some(todo))) => (counts, todo),
+                Ok((_, None)) => return StatusCode::NOT_FOUND.into_response(),
+                Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            };
+            let response = (
+                StatusCode::OK,
+                AppendHeaders([
+                    ("HX-Redirect", list_url(list_id)),
+                    ("HX-Trigger", counts.hx_trigger()),
+                ]),
+                HtmlOrJson::new(
+                    format,
+                    TodoRowTemplate::for_user(api_context, user_id, todo, true).await,
+                ),
+            );
+            with_toast(response, ToastLevel::Success, "Todo updated")
+        }
+        Ok(false) => conflict_response(api_context, user_id, todo_id, format).await,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Responds with the current server-side row, so the client can show what changed
+/// since the version it tried to update
+async fn conflict_response(
+    api_context: &ApiContext,
+    user_id: Uuid,
+    todo_id: Uuid,
+    format: Format,
+) -> Response {
+    match api_context.todos.fetch(todo_id).await {
+        Ok(Some(todo)) => {
+            let row = TodoRowTemplate {
+                conflict: true,
+                ..TodoRowTemplate::for_user(api_context, user_id, todo, true).await
+            };
+            let response = (StatusCode::CONFLICT, HtmlOrJson::new(format, row));
+            with_toast(
+                response,
+                ToastLevel::Error,
+                "Someone else changed this todo, here is their version",
+            )
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn fetch_todo(
+    executor: impl PgExecutor<'_>,
+    todo_id: Uuid,
+) -> Result<Option<Todo>, anyhow::Error> {
+    sqlx::query_as!(
+        Todo,
         r#"
-        UPDATE todo
-        SET is_completed = $1
-        WHERE todo_id = $2 AND user_id = $3
+        SELECT
+            td.todo_id, td.list_id, td.todo_content, td.is_completed, td.is_pinned, td.version,
+            td.due_date,
+            td.priority AS "priority: Priority", td.created_at, td.updated_at, td.completed_at,
+            COALESCE(
+                array_agg(tg.name ORDER BY tg.name) FILTER (WHERE tg.name IS NOT NULL),
+                '{}'
+            ) AS "tags!",
+            st.total AS "subtask_count!",
+            st.completed AS "completed_subtask_count!"
+        FROM todo AS td
+        LEFT JOIN todo_tag AS tt ON tt.todo_id = td.todo_id
+        LEFT JOIN tag AS tg ON tg.tag_id = tt.tag_id
+        -- counted per todo, joining the rows would multiply the tags and
+        -- grouping the whole table would count every other list's subtasks
+        LEFT JOIN LATERAL (
+            SELECT COUNT(*) AS total, COUNT(*) FILTER (WHERE is_completed) AS completed
+            FROM subtask
+            WHERE subtask.todo_id = td.todo_id
+        ) AS st ON TRUE
+        WHERE td.todo_id = $1 AND td.deleted_at IS NULL
+        GROUP BY td.todo_id, st.total, st.completed
         "#,
-        update_todo.is_completed,
-        todo_id,
-        user.user_id()
+        todo_id
     )
-    .execute(&api_context.db)
+    .fetch_optional(executor)
+    .instrument(query_span("SELECT todo"))
     .await
-    .context("Failed to update todo");
+    .context("Failed to get todo")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{any::Any, sync::Arc};
+
+    use axum::response::Response;
+    use http::{HeaderMap, StatusCode, header};
+    use uuid::Uuid;
+
+    use askama::Template;
+    use time::{
+        Duration, OffsetDateTime,
+        macros::{date, datetime},
+    };
+    use time_tz::timezones;
+
+    use super::{
+        CompletedTodo, NewTodo, Todo, TodoForm, TodoPageTemplate, TodoQuery, TodoRowTemplate,
+        TodoTemplate, UpdateTodo, change_todo, create_todo, filters,
+        list::{ListAccess, ListMember, ListRole, SharedList, list_url},
+        list_page, remove_todo,
+        repo::{DueFilter, TodoRepo, fake::FakeTodoRepo},
+        submit_todo_form,
+    };
+    use crate::{
+        app::ApiContext,
+        auth::repo::fake::FakeUserRepo,
+        domain::priority::Priority,
+        i18n::Locale,
+        negotiate::Format,
+        page::PageContext,
+        preferences::{Preferences, TodoSort},
+    };
+
+    fn api_context(todos: &Arc<FakeTodoRepo>) -> ApiContext {
+        ApiContext::for_tests(Arc::new(FakeUserRepo::default()), todos.clone())
+    }
+
+    fn new_todo(todo_content: &str) -> NewTodo {
+        NewTodo {
+            todo_content: todo_content.to_string(),
+            list_id: None,
+            due_date: None,
+            tags: String::new(),
+            priority: None,
+        }
+    }
+
+    fn complete(version: i32) -> UpdateTodo {
+        UpdateTodo {
+            is_completed: Some(true),
+            priority: None,
+            version,
+        }
+    }
+
+    /// Adds a todo to the user's own list, returning its id
+    async fn add_todo(api_context: &ApiContext, todos: &FakeTodoRepo, user_id: Uuid) -> Uuid {
+        let response = create_todo(
+            api_context,
+            user_id,
+            new_todo("buy milk"),
+            Format::Html,
+            Locale::En,
+        )
+        .await;
+        assert_eq!(StatusCode::CREATED, response.status());
+        todos.todos().last().unwrap().todo_id
+    }
+
+    #[tokio::test]
+    async fn creating_a_todo_stores_it_in_the_users_list() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let user_id = Uuid::new_v4();
+        let list_id = todos.add_user(user_id);
+
+        add_todo(&api_context, &todos, user_id).await;
+
+        let stored = todos.todos();
+        assert_eq!(1, stored.len());
+        assert_eq!(list_id, stored[0].list_id);
+        assert_eq!("buy milk", stored[0].todo_content);
+    }
+
+    #[tokio::test]
+    async fn invalid_todos_are_rejected() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let user_id = Uuid::new_v4();
+        todos.add_user(user_id);
+
+        let mut bad_priority = new_todo("buy milk");
+        bad_priority.priority = Some("urgent-ish".to_string());
+        let mut bad_tags = new_todo("buy milk");
+        bad_tags.tags = "not a tag!".to_string();
+
+        for invalid in [new_todo(""), new_todo("   "), bad_priority, bad_tags] {
+            let response =
+                create_todo(&api_context, user_id, invalid, Format::Html, Locale::En).await;
+            assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        }
+        assert!(todos.todos().is_empty());
+    }
+
+    #[tokio::test]
+    async fn todos_cannot_be_added_to_foreign_lists() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let user_id = Uuid::new_v4();
+        todos.add_user(user_id);
+        let other_list_id = todos.add_user(Uuid::new_v4());
+
+        let mut foreign = new_todo("buy milk");
+        foreign.list_id = Some(other_list_id);
+        let response = create_todo(&api_context, user_id, foreign, Format::Html, Locale::En).await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert!(todos.todos().is_empty());
+    }
+
+    #[tokio::test]
+    async fn foreign_todos_are_not_found() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let owner_id = Uuid::new_v4();
+        todos.add_user(owner_id);
+        let other_id = Uuid::new_v4();
+        todos.add_user(other_id);
+        let todo_id = add_todo(&api_context, &todos, owner_id).await;
+
+        let response =
+            change_todo(&api_context, other_id, todo_id, complete(1), Format::Html).await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        let response = remove_todo(&api_context, other_id, todo_id, Format::Html).await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert!(!todos.todos()[0].is_completed);
+    }
+
+    #[tokio::test]
+    async fn viewers_cannot_change_todos() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let owner_id = Uuid::new_v4();
+        let list_id = todos.add_user(owner_id);
+        let viewer_id = Uuid::new_v4();
+        todos.add_member(list_id, viewer_id, ListAccess::Member(ListRole::Viewer));
+        let todo_id = add_todo(&api_context, &todos, owner_id).await;
+
+        let response =
+            change_todo(&api_context, viewer_id, todo_id, complete(1), Format::Html).await;
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        let response = remove_todo(&api_context, viewer_id, todo_id, Format::Html).await;
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+    }
+
+    #[tokio::test]
+    async fn each_kind_of_user_gets_the_access_of_their_role() {
+        // viewing the list, adding to it, changing and deleting a todo
+        let expected = [
+            (
+                "owner",
+                [
+                    StatusCode::OK,
+                    StatusCode::CREATED,
+                    StatusCode::OK,
+                    StatusCode::OK,
+                ],
+            ),
+            (
+                "editor",
+                [
+                    StatusCode::OK,
+                    StatusCode::CREATED,
+                    StatusCode::OK,
+                    StatusCode::OK,
+                ],
+            ),
+            (
+                "viewer",
+                [
+                    StatusCode::OK,
+                    StatusCode::FORBIDDEN,
+                    StatusCode::FORBIDDEN,
+                    StatusCode::FORBIDDEN,
+                ],
+            ),
+            (
+                "stranger",
+                [
+                    StatusCode::NOT_FOUND,
+                    StatusCode::NOT_FOUND,
+                    StatusCode::NOT_FOUND,
+                    StatusCode::NOT_FOUND,
+                ],
+            ),
+        ];
+
+        for (who, expected) in expected {
+            let todos = Arc::new(FakeTodoRepo::default());
+            let api_context = api_context(&todos);
+            let owner_id = Uuid::new_v4();
+            let list_id = todos.add_user(owner_id);
+            let todo_id = add_todo(&api_context, &todos, owner_id).await;
+            let user_id = match who {
+                "owner" => owner_id,
+                "editor" | "viewer" => {
+                    let member_id = Uuid::new_v4();
+                    let role = if who == "editor" {
+                        ListRole::Editor
+                    } else {
+                        ListRole::Viewer
+                    };
+                    todos.add_member(list_id, member_id, ListAccess::Member(role));
+                    member_id
+                }
+                _ => {
+                    let stranger_id = Uuid::new_v4();
+                    todos.add_user(stranger_id);
+                    stranger_id
+                }
+            };
+            api_context
+                .preferences
+                .insert(user_id, Preferences::default())
+                .await;
+
+            let mut new_todo = new_todo("walk dog");
+            new_todo.list_id = Some(list_id);
+            let actual = [
+                list_page(
+                    &api_context,
+                    user_id,
+                    &HeaderMap::new(),
+                    list_query(Some(list_id), None),
+                    PageContext::default(),
+                )
+                .await
+                .status(),
+                create_todo(&api_context, user_id, new_todo, Format::Html, Locale::En)
+                    .await
+                    .status(),
+                change_todo(&api_context, user_id, todo_id, complete(1), Format::Html)
+                    .await
+                    .status(),
+                remove_todo(&api_context, user_id, todo_id, Format::Html)
+                    .await
+                    .status(),
+            ];
+            assert_eq!(expected, actual, "{who}");
+        }
+    }
+
+    #[tokio::test]
+    async fn stale_updates_conflict() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let user_id = Uuid::new_v4();
+        todos.add_user(user_id);
+        let todo_id = add_todo(&api_context, &todos, user_id).await;
+
+        let response = change_todo(&api_context, user_id, todo_id, complete(1), Format::Html).await;
+        assert_eq!(StatusCode::OK, response.status());
+        let response = change_todo(&api_context, user_id, todo_id, complete(1), Format::Html).await;
+        assert_eq!(StatusCode::CONFLICT, response.status());
+    }
+
+    #[tokio::test]
+    async fn deleted_todos_are_not_found() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let user_id = Uuid::new_v4();
+        todos.add_user(user_id);
+        let todo_id = add_todo(&api_context, &todos, user_id).await;
+
+        let response = remove_todo(&api_context, user_id, todo_id, Format::Html).await;
+        assert_eq!(StatusCode::OK, response.status());
+        let response = remove_todo(&api_context, user_id, todo_id, Format::Html).await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+    }
+
+    /// Rendered the same on every run, timestamps in the future show as
+    /// "just now"
+    fn list_query(list_id: Option<Uuid>, tag: Option<&str>) -> TodoQuery {
+        TodoQuery {
+            list_id,
+            tag: tag.map(str::to_string),
+            due: None,
+            sort: None,
+            show_completed: None,
+            page: None,
+        }
+    }
+
+    async fn body(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    /// A user with their list and default preferences, so their page can be
+    /// served without a database
+    async fn list_owner(api_context: &ApiContext, todos: &FakeTodoRepo, username: &str) -> Uuid {
+        let user_id = Uuid::new_v4();
+        todos.add_user(user_id);
+        todos.set_username(user_id, username);
+        api_context
+            .preferences
+            .insert(user_id, Preferences::default())
+            .await;
+        user_id
+    }
+
+    #[tokio::test]
+    async fn list_page_shows_the_todos_and_members_of_the_list() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let owner_id = list_owner(&api_context, &todos, "alice").await;
+        let list_id = todos.own_list_id(owner_id).await.unwrap();
+        let member_id = Uuid::new_v4();
+        todos.set_username(member_id, "bob");
+        todos.add_member(list_id, member_id, ListAccess::Member(ListRole::Editor));
+        add_todo(&api_context, &todos, owner_id).await;
+
+        let response = list_page(
+            &api_context,
+            owner_id,
+            &HeaderMap::new(),
+            list_query(None, None),
+            PageContext::default(),
+        )
+        .await;
+
+        assert_eq!(StatusCode::OK, response.status());
+        let html = body(response).await;
+        assert!(html.contains("buy milk"));
+        assert!(html.contains("bob"));
+    }
+
+    #[tokio::test]
+    async fn forms_posted_without_htmx_are_sent_back_to_the_list() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let owner_id = list_owner(&api_context, &todos, "alice").await;
+        let list_id = todos.own_list_id(owner_id).await.unwrap();
+
+        let response = submit_todo_form(
+            &api_context,
+            owner_id,
+            new_todo("buy milk"),
+            &HeaderMap::new(),
+            PageContext::default(),
+        )
+        .await;
+
+        assert_eq!(StatusCode::SEE_OTHER, response.status());
+        assert_eq!(list_url(list_id), response.headers()[header::LOCATION]);
+        assert_eq!(1, todos.todos().len());
+    }
+
+    #[tokio::test]
+    async fn failed_forms_posted_without_htmx_show_the_list_with_the_error() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let owner_id = list_owner(&api_context, &todos, "alice").await;
+        add_todo(&api_context, &todos, owner_id).await;
+
+        let response = submit_todo_form(
+            &api_context,
+            owner_id,
+            new_todo("   "),
+            &HeaderMap::new(),
+            PageContext::default(),
+        )
+        .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert!(!response.headers().contains_key(header::ETAG));
+        let html = body(response).await;
+        assert!(html.contains(r#"<p class="error" role="alert">"#));
+        assert!(html.contains("buy milk"));
+        assert_eq!(1, todos.todos().len());
+    }
+
+    #[tokio::test]
+    async fn list_page_is_sent_as_json_when_asked_for() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let owner_id = list_owner(&api_context, &todos, "alice").await;
+        add_todo(&api_context, &todos, owner_id).await;
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, "application/json".parse().unwrap());
+
+        let response = list_page(
+            &api_context,
+            owner_id,
+            &headers,
+            list_query(None, None),
+            PageContext::default(),
+        )
+        .await;
+
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("application/json", response.headers()[header::CONTENT_TYPE]);
+        let json: serde_json::Value = serde_json::from_str(&body(response).await).unwrap();
+        assert_eq!("alice", json["owner_username"]);
+        assert_eq!("buy milk", json["todos"][0]["todo_content"]);
+        assert_eq!(1, json["active_count"]);
+    }
+
+    #[tokio::test]
+    async fn list_page_is_in_the_language_of_the_browser() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let owner_id = list_owner(&api_context, &todos, "alice").await;
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, "de".parse().unwrap());
+
+        let german = list_page(
+            &api_context,
+            owner_id,
+            &headers,
+            list_query(None, None),
+            PageContext::default(),
+        )
+        .await;
+        let english = list_page(
+            &api_context,
+            owner_id,
+            &HeaderMap::new(),
+            list_query(None, None),
+            PageContext::default(),
+        )
+        .await;
+
+        assert_ne!(
+            german.headers()[header::ETAG],
+            english.headers()[header::ETAG]
+        );
+        assert!(
+            body(german)
+                .await
+                .contains("Diese Liste hat noch keine Aufgaben.")
+        );
+        assert!(body(english).await.contains("This list has no todos yet."));
+    }
+
+    #[tokio::test]
+    async fn html_and_json_list_pages_have_different_etags() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let owner_id = list_owner(&api_context, &todos, "alice").await;
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, "application/json".parse().unwrap());
+
+        let html = list_page(
+            &api_context,
+            owner_id,
+            &HeaderMap::new(),
+            list_query(None, None),
+            PageContext::default(),
+        )
+        .await;
+        let json = list_page(
+            &api_context,
+            owner_id,
+            &headers,
+            list_query(None, None),
+            PageContext::default(),
+        )
+        .await;
+
+        assert_ne!(html.headers()[header::ETAG], json.headers()[header::ETAG]);
+    }
+
+    #[tokio::test]
+    async fn mutations_answer_with_the_todo_as_json() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let user_id = Uuid::new_v4();
+        todos.add_user(user_id);
+
+        let response = create_todo(
+            &api_context,
+            user_id,
+            new_todo("buy milk"),
+            Format::Json,
+            Locale::En,
+        )
+        .await;
+        assert_eq!(StatusCode::CREATED, response.status());
+        let created: serde_json::Value = serde_json::from_str(&body(response).await).unwrap();
+        assert_eq!("buy milk", created["todo_content"]);
+        let todo_id = created["todo_id"].as_str().unwrap().parse().unwrap();
+
+        let response = change_todo(&api_context, user_id, todo_id, complete(1), Format::Json).await;
+        assert_eq!(StatusCode::OK, response.status());
+        let updated: serde_json::Value = serde_json::from_str(&body(response).await).unwrap();
+        assert_eq!(true, updated["is_completed"]);
+        assert_eq!(2, updated["version"]);
+
+        let response = change_todo(&api_context, user_id, todo_id, complete(1), Format::Json).await;
+        assert_eq!(StatusCode::CONFLICT, response.status());
+        let conflict: serde_json::Value = serde_json::from_str(&body(response).await).unwrap();
+        assert_eq!(true, conflict["conflict"]);
+    }
+
+    #[tokio::test]
+    async fn todos_past_the_quota_are_refused_until_one_is_deleted() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let mut api_context = api_context(&todos);
+        api_context.config.application_settings.max_active_todos = 2;
+        let user_id = Uuid::new_v4();
+        todos.add_user(user_id);
+
+        let first = add_todo(&api_context, &todos, user_id).await;
+        add_todo(&api_context, &todos, user_id).await;
+        let response = create_todo(
+            &api_context,
+            user_id,
+            new_todo("buy eggs"),
+            Format::Html,
+            Locale::En,
+        )
+        .await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+        assert!(
+            body(response)
+                .await
+                .contains("You can&#39;t have more than 2 todos, delete some to add new ones")
+        );
+
+        let response = remove_todo(&api_context, user_id, first, Format::Html).await;
+        assert_eq!(StatusCode::OK, response.status());
+        add_todo(&api_context, &todos, user_id).await;
+    }
+
+    #[tokio::test]
+    async fn the_same_todo_sent_twice_is_only_added_once() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let mut api_context = api_context(&todos);
+        api_context
+            .config
+            .application_settings
+            .todo_repeat_window_secs = 2;
+        let user_id = Uuid::new_v4();
+        todos.add_user(user_id);
+
+        let first = add_todo(&api_context, &todos, user_id).await;
+        let response = create_todo(
+            &api_context,
+            user_id,
+            new_todo("buy milk"),
+            Format::Json,
+            Locale::En,
+        )
+        .await;
+        assert_eq!(StatusCode::OK, response.status());
+        // the first one already said it was added
+        let trigger = response.headers()["HX-Trigger"].to_str().unwrap();
+        assert!(!trigger.contains("toast"), "{trigger}");
+        let repeated: serde_json::Value = serde_json::from_str(&body(response).await).unwrap();
+        assert_eq!(first.to_string(), repeated["todo_id"]);
+        assert_eq!(1, todos.todos().len());
+
+        // anything else is a new todo
+        let response = create_todo(
+            &api_context,
+            user_id,
+            new_todo("buy eggs"),
+            Format::Json,
+            Locale::En,
+        )
+        .await;
+        assert_eq!(StatusCode::CREATED, response.status());
+        assert_eq!(2, todos.todos().len());
+    }
 
-    if let Ok(query_result) = result {
-        if query_result.rows_affected() > 0 {
-            (StatusCode::OK, AppendHeaders([("HX-Redirect", "/todo")])).into_response()
-        } else {
-            StatusCode::NOT_FOUND.into_response()
+    #[tokio::test]
+    async fn list_page_only_shows_todos_with_the_tag() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let user_id = list_owner(&api_context, &todos, "alice").await;
+        let mut tagged = new_todo("call the plumber");
+        tagged.tags = "home".to_string();
+        create_todo(&api_context, user_id, tagged, Format::Html, Locale::En).await;
+        create_todo(
+            &api_context,
+            user_id,
+            new_todo("write the report"),
+            Format::Html,
+            Locale::En,
+        )
+        .await;
+
+        let query = list_query(None, Some("home"));
+        let response = list_page(
+            &api_context,
+            user_id,
+            &HeaderMap::new(),
+            query,
+            PageContext::default(),
+        )
+        .await;
+
+        let html = body(response).await;
+        assert!(html.contains("call the plumber"));
+        assert!(!html.contains("write the report"));
+    }
+
+    #[tokio::test]
+    async fn due_dates_are_bucketed_by_the_users_today() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let user_id = list_owner(&api_context, &todos, "alice").await;
+        // UTC+13, a day ahead of UTC for most of the UTC day
+        let preferences = Preferences {
+            timezone: "Pacific/Tongatapu".to_string(),
+            ..Preferences::default()
+        };
+        api_context
+            .preferences
+            .insert(user_id, preferences.clone())
+            .await;
+        let today = preferences.today();
+        for (todo_content, due_date) in [
+            ("due yesterday", today - Duration::days(1)),
+            ("due today", today),
+            ("due tomorrow", today + Duration::days(1)),
+        ] {
+            let mut new_todo = new_todo(todo_content);
+            new_todo.due_date = Some(due_date);
+            create_todo(&api_context, user_id, new_todo, Format::Html, Locale::En).await;
+        }
+
+        let mut query = list_query(None, None);
+        query.due = Some(DueFilter::Today);
+        let html = body(
+            list_page(
+                &api_context,
+                user_id,
+                &HeaderMap::new(),
+                query,
+                PageContext::default(),
+            )
+            .await,
+        )
+        .await;
+        assert!(html.contains("due today"));
+        assert!(html.contains(r#"class="due today""#));
+        assert!(!html.contains("due yesterday"));
+        assert!(!html.contains("due tomorrow"));
+
+        let mut query = list_query(None, None);
+        query.due = Some(DueFilter::Overdue);
+        let html = body(
+            list_page(
+                &api_context,
+                user_id,
+                &HeaderMap::new(),
+                query,
+                PageContext::default(),
+            )
+            .await,
+        )
+        .await;
+        assert!(html.contains("due yesterday"));
+        assert!(html.contains(r#"class="due overdue""#));
+        assert!(!html.contains("due tomorrow"));
+    }
+
+    #[tokio::test]
+    async fn unchanged_list_page_is_not_sent_again() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let user_id = list_owner(&api_context, &todos, "alice").await;
+        add_todo(&api_context, &todos, user_id).await;
+
+        let response = list_page(
+            &api_context,
+            user_id,
+            &HeaderMap::new(),
+            list_query(None, None),
+            PageContext::default(),
+        )
+        .await;
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::IF_NONE_MATCH,
+            response.headers()[header::ETAG].clone(),
+        );
+
+        let response = list_page(
+            &api_context,
+            user_id,
+            &headers,
+            list_query(None, None),
+            PageContext::default(),
+        )
+        .await;
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+
+        add_todo(&api_context, &todos, user_id).await;
+        let response = list_page(
+            &api_context,
+            user_id,
+            &headers,
+            list_query(None, None),
+            PageContext::default(),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    #[tokio::test]
+    async fn foreign_list_pages_are_not_found() {
+        let todos = Arc::new(FakeTodoRepo::default());
+        let api_context = api_context(&todos);
+        let user_id = list_owner(&api_context, &todos, "alice").await;
+        let other_id = list_owner(&api_context, &todos, "bob").await;
+        let other_list_id = todos.own_list_id(other_id).await.unwrap();
+
+        let query = list_query(Some(other_list_id), None);
+        let response = list_page(
+            &api_context,
+            user_id,
+            &HeaderMap::new(),
+            query,
+            PageContext::default(),
+        )
+        .await;
+
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+    }
+
+    const NOW: OffsetDateTime = datetime!(2025-06-29 12:00 UTC);
+
+    fn todo(n: u128, todo_content: &str) -> Todo {
+        Todo {
+            todo_id: Uuid::from_u128(n),
+            list_id: Uuid::from_u128(100),
+            todo_content: todo_content.to_string(),
+            is_completed: n == 2,
+            is_pinned: n == 1,
+            version: 1,
+            due_date: Some(date!(2025 - 07 - 04)),
+            priority: Priority::High,
+            tags: vec!["errands".to_string(), "shopping".to_string()],
+            subtask_count: 2,
+            completed_subtask_count: 1,
+            created_at: NOW - Duration::days(n as i64 + 2),
+            updated_at: NOW - Duration::hours(n as i64 * 3),
+            completed_at: (n == 2).then(|| NOW - Duration::hours(n as i64 * 3)),
+        }
+    }
+
+    /// Renders with [`filters::NOW`] set, so relative times don't change
+    /// from one day to the next
+    fn render_at_now(template: &impl Template) -> String {
+        template
+            .render_with_values(&(filters::NOW, &NOW as &dyn Any))
+            .unwrap()
+    }
+
+    fn todo_page(todos: Vec<Todo>, can_edit: bool) -> TodoTemplate {
+        let completed_today = todos
+            .iter()
+            .filter_map(|todo| {
+                Some(CompletedTodo {
+                    todo_id: todo.todo_id,
+                    todo_content: todo.todo_content.clone(),
+                    completed_at: todo.completed_at?,
+                })
+            })
+            .collect();
+        TodoTemplate {
+            locale: Locale::En,
+            list_id: Uuid::from_u128(100),
+            owner_username: "alice".to_string(),
+            is_owner: can_edit,
+            can_edit,
+            todos,
+            completed_today,
+            active_count: 1,
+            completed_count: 1,
+            tag: Some("errands".to_string()),
+            due: None,
+            sort: TodoSort::Priority,
+            show_completed: true,
+            page: 2,
+            has_next_page: true,
+            members: vec![ListMember {
+                user_id: Uuid::from_u128(200),
+                username: "bob".to_string(),
+                role: ListRole::Editor,
+            }],
+            shared_lists: vec![SharedList {
+                list_id: Uuid::from_u128(300),
+                owner_username: "carol".to_string(),
+                role: ListRole::Viewer,
+            }],
+            timezone: timezones::db::europe::BERLIN,
+            today: NOW.date(),
+            form_error: None,
+            todo_form: TodoForm::for_list(Uuid::from_u128(100)),
         }
-    } else {
-        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+
+    /// The list in the layout of the page
+    fn render_page(list: &TodoTemplate) -> String {
+        render_at_now(&TodoPageTemplate {
+            locale: Locale::En,
+            list: render_at_now(list),
+            page_context: PageContext::default(),
+        })
+    }
+
+    #[test]
+    fn todo_page_for_the_owner() {
+        let todos = vec![todo(1, "buy **milk**"), todo(2, "Tom & Jerry's \"show\"")];
+        let html = render_page(&todo_page(todos, true));
+        insta::assert_snapshot!(html);
+    }
+
+    #[test]
+    fn todo_page_for_a_viewer() {
+        let html = render_page(&todo_page(vec![todo(1, "buy milk")], false));
+        insta::assert_snapshot!(html);
+    }
+
+    #[test]
+    fn scripts_in_todos_are_not_rendered() {
+        let html = render_at_now(&TodoRowTemplate {
+            todo: todo(
+                3,
+                "<script>alert('hi')</script> <img src=x onerror=alert(1)>",
+            ),
+            can_edit: true,
+            conflict: false,
+            timezone: timezones::db::UTC,
+            today: NOW.date(),
+        });
+        // shown as text
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<img"));
+        assert!(html.contains("&lt;script&gt;"));
+        insta::assert_snapshot!(html);
     }
 }
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    response::{AppendHeaders, IntoResponse},
+};
+use http::StatusCode;
+use uuid::Uuid;
+
+use super::{TodoRowTemplate, events::publish_todo_event};
+use crate::{app::ApiContext, auth::AuthSession, authz, events::TodoEventKind};
+
+enum PinOutcome {
+    Toggled,
+    LimitReached,
+    NotFound,
+}
+
+/// Pins or unpins a todo, pinned todos are listed before all others.
+///
+/// Responds with the updated row and a `todoPinned` event, which the list
+/// page uses to move the row without reloading.
+pub async fn toggle_pin(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path(todo_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let user = match auth_session.user {
+        Some(user) => user,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let list_id = match authz::require_edit_todo(&api_context, user.user_id(), todo_id).await {
+        Ok(todo_access) => todo_access.list_id,
+        Err(e) => return e.into_response(),
+    };
+
+    let max_pinned = api_context.config.application_settings.max_pinned_todos;
+    let result = async {
+        let mut transaction = api_context
+            .db
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+
+        // every user owns exactly one list, so this is also the limit per user.
+        // Locking the list keeps concurrent pins from going over it.
+        sqlx::query!(
+            r#"
+            SELECT list_id FROM todo_list WHERE list_id = $1 FOR UPDATE
+            "#,
+            list_id
+        )
+        .fetch_one(&mut *transaction)
+        .await
+        .context("Failed to lock list")?;
+
+        let pinned = sqlx::query!(
+            r#"
+            SELECT
+                td.is_pinned,
+                (
+                    SELECT COUNT(*) FROM todo
+                    WHERE list_id = $2 AND is_pinned AND deleted_at IS NULL
+                ) AS "pinned_count!"
+            FROM todo AS td
+            WHERE td.todo_id = $1 AND td.list_id = $2
+            "#,
+            todo_id,
+            list_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to count pinned todos")?;
+        let Some(pinned) = pinned else {
+            return Ok(PinOutcome::NotFound);
+        };
+        if !pinned.is_pinned && pinned.pinned_count >= max_pinned {
+            return Ok(PinOutcome::LimitReached);
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE todo
+            SET is_pinned = NOT is_pinned, version = version + 1
+            WHERE todo_id = $1
+            "#,
+            todo_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to pin todo")?;
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+
+        Ok::<_, anyhow::Error>(PinOutcome::Toggled)
+    }
+    .await;
+
+    match result {
+        Ok(PinOutcome::Toggled) => {}
+        Ok(PinOutcome::LimitReached) => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("You can pin up to {max_pinned} todos, unpin one first"),
+            )
+                .into_response();
+        }
+        Ok(PinOutcome::NotFound) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    publish_todo_event(&api_context, TodoEventKind::Updated, list_id, todo_id).await;
+
+    match api_context.todos.fetch(todo_id).await {
+        Ok(Some(todo)) => {
+            let trigger = serde_json::json!({
+                "todoPinned": { "todo_id": todo.todo_id, "is_pinned": todo.is_pinned }
+            })
+            .to_string();
+            (
+                StatusCode::OK,
+                AppendHeaders([("HX-Trigger-After-Swap", trigger)]),
+                TodoRowTemplate::for_user(&api_context, user.user_id(), todo, true).await,
+            )
+                .into_response()
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
@@ -0,0 +1,62 @@
+//! How many active todos a user may have, so a runaway script can't fill the
+//! database. The count is kept in `user_info.active_todo_count` by a trigger.
+
+use anyhow::Context;
+use axum::response::IntoResponse;
+use http::StatusCode;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+#[derive(thiserror::Error, Debug)]
+pub enum QuotaError {
+    #[error("You can't have more than {limit} todos, delete some to add new ones")]
+    Exceeded { limit: i64 },
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl QuotaError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            QuotaError::Exceeded { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            QuotaError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for QuotaError {
+    fn into_response(self) -> axum::response::Response {
+        (self.status_code(), self.to_string()).into_response()
+    }
+}
+
+/// Fails unless the user can add `adding` more todos, under their own limit
+/// or `default_limit`. The user stays locked until the transaction ends, so
+/// concurrent inserts can't both take the last free places.
+pub async fn reserve(
+    connection: &mut PgConnection,
+    user_id: Uuid,
+    adding: i64,
+    default_limit: i64,
+) -> Result<(), QuotaError> {
+    let quota = sqlx::query!(
+        r#"
+        SELECT active_todo_count, COALESCE(todo_limit, $2) AS "todo_limit!"
+        FROM user_info
+        WHERE user_id = $1
+        FOR UPDATE
+        "#,
+        user_id,
+        default_limit
+    )
+    .fetch_one(connection)
+    .await
+    .context("Failed to get todo quota")?;
+
+    if quota.active_todo_count + adding > quota.todo_limit {
+        return Err(QuotaError::Exceeded {
+            limit: quota.todo_limit,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,1600 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use sqlx::{PgPool, postgres::PgExecutor};
+use time::{Date, OffsetDateTime};
+use time_tz::{TimeZone, Tz};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use super::{
+    CompletedTodo, Todo, TodoCounts,
+    cursor::Cursor,
+    etag, fetch_todo,
+    history::{self, HistoryEntry, TodoChange},
+    list::{self, ListAccess, ListMember, ListRole, SharedList},
+    quota::{self, QuotaError},
+    subtask::{self, Subtask},
+    tag::{self, TagCount},
+};
+use crate::{
+    domain::{priority::Priority, tag::Tags, todo_content::TodoContent},
+    preferences::TodoSort,
+    telemetry::query_span,
+};
+
+/// A todo about to be added, already validated
+pub(crate) struct NewTodoRow {
+    pub list_id: Uuid,
+    pub todo_content: TodoContent,
+    pub due_date: Option<Date>,
+    pub priority: Priority,
+    pub tags: Tags,
+}
+
+/// What [`TodoRepo::create`] did
+pub(crate) struct Created {
+    pub todo: Todo,
+    /// Of the todo's list, after
+    pub counts: TodoCounts,
+    /// `false` when the same todo had just been added, which is returned
+    /// instead
+    pub is_new: bool,
+}
+
+/// Fields of a todo to change, `None` keeps the current value
+pub(crate) struct TodoUpdate {
+    pub is_completed: Option<bool>,
+    pub priority: Option<Priority>,
+    /// Version of the todo the client last saw
+    pub version: i32,
+}
+
+/// Todos by when they are due, counted from the user's today
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DueFilter {
+    /// Due before today and not completed yet
+    Overdue,
+    Today,
+    /// Due after today
+    Upcoming,
+}
+
+impl DueFilter {
+    pub const ALL: [DueFilter; 3] = [DueFilter::Overdue, DueFilter::Today, DueFilter::Upcoming];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DueFilter::Overdue => "overdue",
+            DueFilter::Today => "today",
+            DueFilter::Upcoming => "upcoming",
+        }
+    }
+
+    /// Its name on the list page, see [`crate::i18n::translate`]
+    pub fn message_key(self) -> &'static str {
+        match self {
+            DueFilter::Overdue => "todos.due.overdue",
+            DueFilter::Today => "todos.due.today",
+            DueFilter::Upcoming => "todos.due.upcoming",
+        }
+    }
+
+    /// Whether a todo is in the bucket, the same as [`PgTodoRepo::filtered`] decides
+    pub fn matches(self, due_date: Option<Date>, is_completed: bool, today: Date) -> bool {
+        let Some(due_date) = due_date else {
+            return false;
+        };
+        match self {
+            DueFilter::Overdue => due_date < today && !is_completed,
+            DueFilter::Today => due_date == today,
+            DueFilter::Upcoming => due_date > today,
+        }
+    }
+}
+
+impl std::fmt::Display for DueFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Which todos of a list the list page shows, pinned ones first
+pub(crate) struct TodoFilter {
+    /// Only todos with this tag
+    pub tag: Option<String>,
+    /// Only todos due in this bucket
+    pub due: Option<DueFilter>,
+    /// The date where the user lives, which [`DueFilter`] counts from. Due
+    /// dates are calendar dates, so comparing them with the local date
+    /// already respects the user's zone.
+    pub today: Date,
+    pub sort: TodoSort,
+    pub show_completed: bool,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// Someone to tell about changes to a list, see
+/// [`super::events::publish_todo_event`]
+pub(crate) struct ListRecipient {
+    pub user_id: Uuid,
+    pub can_edit: bool,
+}
+
+/// Where the todo handlers read and write todos, so their logic can be
+/// tested against [`fake::FakeTodoRepo`] without Postgres. Changes are
+/// recorded in the history of the todo along with the change itself.
+#[async_trait]
+pub(crate) trait TodoRepo: Send + Sync {
+    /// `None` when the list doesn't exist or the user has no access to it
+    async fn list_access(
+        &self,
+        list_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<ListAccess>, anyhow::Error>;
+
+    /// Like [`TodoRepo::list_access`], through one of the list's todos
+    async fn todo_access(
+        &self,
+        todo_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<(Uuid, ListAccess)>, anyhow::Error>;
+
+    async fn own_list_id(&self, user_id: Uuid) -> Result<Uuid, anyhow::Error>;
+
+    /// `None` once the todo is deleted
+    async fn fetch(&self, todo_id: Uuid) -> Result<Option<Todo>, anyhow::Error>;
+
+    async fn counts(&self, list_id: Uuid) -> Result<TodoCounts, anyhow::Error>;
+
+    /// The owner and the members of the list
+    async fn recipients(&self, list_id: Uuid) -> Result<Vec<ListRecipient>, anyhow::Error>;
+
+    /// Adds the todo, returning it with the counts of its list after. Fails
+    /// when the user already has as many active todos as they may, see
+    /// [`quota::reserve`].
+    ///
+    /// A todo with the same content the user added to the list less than
+    /// `repeat_window_secs` ago is taken for the same form sent twice, and
+    /// returned instead of adding another one.
+    async fn create(
+        &self,
+        user_id: Uuid,
+        new_todo: NewTodoRow,
+        default_limit: i64,
+        repeat_window_secs: f64,
+    ) -> Result<Created, QuotaError>;
+
+    /// Adds a copy of the todo to its list as added by `user_id`, with its
+    /// description, due date, priority, tags and first `max_subtasks`
+    /// subtasks. Neither the copy nor its subtasks are completed, and it
+    /// isn't pinned. `None` if the todo is gone, fails like [`Self::create`]
+    /// when the user can't add another todo.
+    async fn duplicate(
+        &self,
+        todo_id: Uuid,
+        list_id: Uuid,
+        user_id: Uuid,
+        default_limit: i64,
+        max_subtasks: i64,
+    ) -> Result<Option<Created>, QuotaError>;
+
+    /// Marks the todo as deleted, returning its content, or `None` if it
+    /// already was, with the counts of its list after
+    async fn delete(
+        &self,
+        todo_id: Uuid,
+        list_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<(Option<String>, TodoCounts), anyhow::Error>;
+
+    /// `false` if the todo has moved past `update.version` in the meantime
+    async fn update(
+        &self,
+        todo_id: Uuid,
+        list_id: Uuid,
+        user_id: Uuid,
+        update: TodoUpdate,
+    ) -> Result<bool, anyhow::Error>;
+
+    /// See [`etag::list_etag`]
+    async fn list_etag(
+        &self,
+        list_id: Uuid,
+        user_id: Uuid,
+        view: &str,
+    ) -> Result<String, anyhow::Error>;
+
+    async fn owner_username(&self, list_id: Uuid) -> Result<String, anyhow::Error>;
+
+    async fn filtered(
+        &self,
+        list_id: Uuid,
+        filter: &TodoFilter,
+    ) -> Result<Vec<Todo>, anyhow::Error>;
+
+    /// Up to `limit` todos after the cursor, newest first
+    async fn page_after(
+        &self,
+        list_id: Uuid,
+        after: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Vec<Todo>, anyhow::Error>;
+
+    /// Todos of the list completed on `day` in `tz` and still completed,
+    /// latest first
+    async fn completed_on(
+        &self,
+        list_id: Uuid,
+        day: Date,
+        tz: &Tz,
+    ) -> Result<Vec<CompletedTodo>, anyhow::Error>;
+
+    async fn members(&self, list_id: Uuid) -> Result<Vec<ListMember>, anyhow::Error>;
+
+    /// Lists owned by someone else that the user is a member of
+    async fn shared_lists(&self, user_id: Uuid) -> Result<Vec<SharedList>, anyhow::Error>;
+
+    /// Shares the list with the user, or changes their role if it already is
+    async fn add_member(
+        &self,
+        list_id: Uuid,
+        member_id: Uuid,
+        role: ListRole,
+    ) -> Result<(), anyhow::Error>;
+
+    /// `false` if the user wasn't a member
+    async fn remove_member(&self, list_id: Uuid, member_id: Uuid) -> Result<bool, anyhow::Error>;
+
+    async fn description(&self, todo_id: Uuid) -> Result<String, anyhow::Error>;
+
+    async fn subtasks(&self, todo_id: Uuid) -> Result<Vec<Subtask>, anyhow::Error>;
+
+    /// One page of the todo's history, newest first, and whether there are more
+    async fn history(
+        &self,
+        todo_id: Uuid,
+        page: i64,
+    ) -> Result<(Vec<HistoryEntry>, bool), anyhow::Error>;
+
+    /// The tags used in the list and on how many of its todos
+    async fn tag_counts(&self, list_id: Uuid) -> Result<Vec<TagCount>, anyhow::Error>;
+
+    /// The list of a deleted todo and whether it was deleted less than
+    /// `grace_secs` ago, `None` unless it is deleted
+    async fn deleted(
+        &self,
+        todo_id: Uuid,
+        grace_secs: f64,
+    ) -> Result<Option<(Uuid, bool)>, anyhow::Error>;
+
+    /// Restores the todo, returning the counts of its list after, or `None`
+    /// if it was deleted `grace_secs` or more ago. It counts against the
+    /// todo limit of whoever added it again, like a todo they added.
+    async fn restore(
+        &self,
+        todo_id: Uuid,
+        list_id: Uuid,
+        user_id: Uuid,
+        grace_secs: f64,
+        default_limit: i64,
+    ) -> Result<Option<TodoCounts>, QuotaError>;
+}
+
+/// What [`PgTodoRepo`] runs its queries on, the pool itself, or a wrapper
+/// counting them for the tests
+pub(crate) trait Db: Send + Sync + 'static {
+    type Executor<'c>: PgExecutor<'c>
+    where
+        Self: 'c;
+
+    fn executor(&self) -> Self::Executor<'_>;
+
+    /// Where transactions are begun, their queries aren't counted
+    fn pool(&self) -> &PgPool;
+}
+
+impl Db for PgPool {
+    type Executor<'c> = &'c PgPool;
+
+    fn executor(&self) -> &PgPool {
+        self
+    }
+
+    fn pool(&self) -> &PgPool {
+        self
+    }
+}
+
+pub(crate) struct PgTodoRepo<D = PgPool> {
+    db: D,
+}
+
+impl<D: Db> PgTodoRepo<D> {
+    pub fn new(db: D) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl<D: Db> TodoRepo for PgTodoRepo<D> {
+    async fn list_access(
+        &self,
+        list_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<ListAccess>, anyhow::Error> {
+        list::list_access(self.db.executor(), list_id, user_id).await
+    }
+
+    async fn todo_access(
+        &self,
+        todo_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<(Uuid, ListAccess)>, anyhow::Error> {
+        list::todo_access(self.db.executor(), todo_id, user_id).await
+    }
+
+    async fn own_list_id(&self, user_id: Uuid) -> Result<Uuid, anyhow::Error> {
+        list::own_list_id(self.db.executor(), user_id).await
+    }
+
+    async fn fetch(&self, todo_id: Uuid) -> Result<Option<Todo>, anyhow::Error> {
+        fetch_todo(self.db.executor(), todo_id).await
+    }
+
+    async fn counts(&self, list_id: Uuid) -> Result<TodoCounts, anyhow::Error> {
+        TodoCounts::fetch(self.db.executor(), list_id).await
+    }
+
+    async fn recipients(&self, list_id: Uuid) -> Result<Vec<ListRecipient>, anyhow::Error> {
+        sqlx::query_as!(
+            ListRecipient,
+            r#"
+            SELECT owner_id AS "user_id!", TRUE AS "can_edit!" FROM todo_list WHERE list_id = $1
+            UNION ALL
+            SELECT user_id, role = 'editor' FROM list_members WHERE list_id = $1
+            "#,
+            list_id
+        )
+        .fetch_all(self.db.executor())
+        .await
+        .context("Failed to get list recipients")
+    }
+
+    async fn create(
+        &self,
+        user_id: Uuid,
+        new_todo: NewTodoRow,
+        default_limit: i64,
+        repeat_window_secs: f64,
+    ) -> Result<Created, QuotaError> {
+        let mut transaction = self
+            .db
+            .pool()
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+
+        // also locks the user, so a repeat sent at the same time waits for
+        // the first one to be added
+        quota::reserve(&mut transaction, user_id, 1, default_limit).await?;
+
+        if repeat_window_secs > 0.0 {
+            // the clock rather than NOW(), which is when the transaction
+            // started, maybe before the first one was committed
+            let repeated = sqlx::query_scalar!(
+                r#"
+                SELECT todo_id FROM todo
+                WHERE user_id = $1 AND list_id = $2 AND todo_content = $3
+                    AND deleted_at IS NULL
+                    AND created_at > clock_timestamp() - make_interval(secs => $4)
+                ORDER BY created_at DESC
+                LIMIT 1
+                "#,
+                user_id,
+                new_todo.list_id,
+                new_todo.todo_content.as_ref(),
+                repeat_window_secs
+            )
+            .fetch_optional(&mut *transaction)
+            .await
+            .context("Failed to look for a repeated todo")?;
+            if let Some(todo_id) = repeated {
+                transaction
+                    .commit()
+                    .await
+                    .context("Failed to commit transaction")?;
+                let todo = fetch_todo(self.db.executor(), todo_id)
+                    .await?
+                    .context("Repeated todo is gone")?;
+                let counts = TodoCounts::fetch(self.db.executor(), new_todo.list_id).await?;
+                return Ok(Created {
+                    todo,
+                    counts,
+                    is_new: false,
+                });
+            }
+        }
+
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO todo (user_id, list_id, todo_content, due_date, priority)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING
+                todo_id, list_id, todo_content, is_completed, is_pinned, version, due_date,
+                priority AS "priority: Priority", created_at, updated_at, completed_at
+            "#,
+            user_id,
+            new_todo.list_id,
+            new_todo.todo_content.as_ref(),
+            new_todo.due_date,
+            new_todo.priority as Priority
+        )
+        .fetch_one(&mut *transaction)
+        .await
+        .context("Failed to add todo")?;
+        let todo_id = inserted.todo_id;
+
+        history::record_change(&mut transaction, todo_id, user_id, TodoChange::Created).await?;
+        tag::set_todo_tags(&mut transaction, new_todo.list_id, todo_id, &new_todo.tags).await?;
+        let counts = TodoCounts::fetch(&mut *transaction, new_todo.list_id).await?;
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+
+        // the row as the database stored it, without fetching it again
+        let mut tag_names = new_todo.tags.names();
+        tag_names.sort();
+        let todo = Todo {
+            todo_id,
+            list_id: inserted.list_id,
+            todo_content: inserted.todo_content,
+            is_completed: inserted.is_completed,
+            is_pinned: inserted.is_pinned,
+            version: inserted.version,
+            due_date: inserted.due_date,
+            priority: inserted.priority,
+            tags: tag_names,
+            subtask_count: 0,
+            completed_subtask_count: 0,
+            created_at: inserted.created_at,
+            updated_at: inserted.updated_at,
+            completed_at: inserted.completed_at,
+        };
+
+        Ok(Created {
+            todo,
+            counts,
+            is_new: true,
+        })
+    }
+
+    async fn duplicate(
+        &self,
+        todo_id: Uuid,
+        list_id: Uuid,
+        user_id: Uuid,
+        default_limit: i64,
+        max_subtasks: i64,
+    ) -> Result<Option<Created>, QuotaError> {
+        let mut transaction = self
+            .db
+            .pool()
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+
+        quota::reserve(&mut transaction, user_id, 1, default_limit).await?;
+
+        let copy_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO todo (user_id, list_id, todo_content, description, due_date, priority)
+            SELECT $3, list_id, todo_content, description, due_date, priority
+            FROM todo
+            WHERE todo_id = $1 AND list_id = $2 AND deleted_at IS NULL
+            RETURNING todo_id
+            "#,
+            todo_id,
+            list_id,
+            user_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to copy todo")?;
+        let Some(copy_id) = copy_id else {
+            return Ok(None);
+        };
+
+        // tags belong to the list's owner, so the copy in the same list can
+        // have the same ones
+        sqlx::query!(
+            r#"
+            INSERT INTO todo_tag (todo_id, tag_id)
+            SELECT $2, tag_id FROM todo_tag WHERE todo_id = $1
+            "#,
+            todo_id,
+            copy_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to copy tags")?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO subtask (todo_id, content, position)
+            SELECT $2, content, position
+            FROM subtask
+            WHERE todo_id = $1
+            ORDER BY position
+            LIMIT $3
+            "#,
+            todo_id,
+            copy_id,
+            max_subtasks
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to copy subtasks")?;
+
+        history::record_change(&mut transaction, copy_id, user_id, TodoChange::Created).await?;
+        let counts = TodoCounts::fetch(&mut *transaction, list_id).await?;
+        let todo = fetch_todo(&mut *transaction, copy_id)
+            .await?
+            .context("Copied todo is gone")?;
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+
+        Ok(Some(Created {
+            todo,
+            counts,
+            is_new: true,
+        }))
+    }
+
+    async fn delete(
+        &self,
+        todo_id: Uuid,
+        list_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<(Option<String>, TodoCounts), anyhow::Error> {
+        let mut transaction = self
+            .db
+            .pool()
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+
+        // only marked as deleted so it can be restored, the purge worker removes
+        // it for good once the undo window has passed
+        let todo_content = sqlx::query_scalar!(
+            r#"
+            UPDATE todo
+            SET deleted_at = NOW()
+            WHERE todo_id = $1 AND list_id = $2 AND deleted_at IS NULL
+            RETURNING todo_content
+            "#,
+            todo_id,
+            list_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to delete todo")?;
+        if todo_content.is_some() {
+            history::record_change(&mut transaction, todo_id, user_id, TodoChange::Deleted).await?;
+        }
+
+        let counts = TodoCounts::fetch(&mut *transaction, list_id).await?;
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+
+        Ok((todo_content, counts))
+    }
+
+    async fn update(
+        &self,
+        todo_id: Uuid,
+        list_id: Uuid,
+        user_id: Uuid,
+        update: TodoUpdate,
+    ) -> Result<bool, anyhow::Error> {
+        let mut transaction = self
+            .db
+            .pool()
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+
+        // fields that weren't submitted keep their current value. The version
+        // check means `old` is the row as it was right before this update.
+        let updated = sqlx::query!(
+            r#"
+            UPDATE todo AS td
+            SET is_completed = COALESCE($1, td.is_completed),
+                -- kept while it stays completed, so completing it again
+                -- doesn't move it to today
+                completed_at = CASE
+                    WHEN NOT COALESCE($1, td.is_completed) THEN NULL
+                    WHEN td.is_completed THEN td.completed_at
+                    ELSE NOW()
+                END,
+                priority = COALESCE($5, td.priority),
+                version = td.version + 1
+            FROM todo AS old
+            WHERE td.todo_id = $2 AND td.list_id = $3 AND td.version = $4
+                AND old.todo_id = td.todo_id
+            RETURNING
+                old.is_completed AS was_completed, td.is_completed,
+                old.priority AS "old_priority: Priority", td.priority AS "priority: Priority"
+            "#,
+            update.is_completed,
+            todo_id,
+            list_id,
+            update.version,
+            update.priority as Option<Priority>
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to update todo")?;
+        let Some(updated) = updated else {
+            return Ok(false);
+        };
+
+        let mut changes = Vec::new();
+        match (updated.was_completed, updated.is_completed) {
+            (false, true) => changes.push(TodoChange::Completed),
+            (true, false) => changes.push(TodoChange::Uncompleted),
+            _ => {}
+        }
+        if updated.old_priority != updated.priority {
+            changes.push(TodoChange::PriorityChanged {
+                old: updated.old_priority,
+                new: updated.priority,
+            });
+        }
+        for change in changes {
+            history::record_change(&mut transaction, todo_id, user_id, change).await?;
+        }
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+
+        Ok(true)
+    }
+
+    async fn list_etag(
+        &self,
+        list_id: Uuid,
+        user_id: Uuid,
+        view: &str,
+    ) -> Result<String, anyhow::Error> {
+        etag::list_etag(self.db.executor(), list_id, user_id, view).await
+    }
+
+    async fn owner_username(&self, list_id: Uuid) -> Result<String, anyhow::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT ui.username FROM todo_list AS tl
+            JOIN user_info AS ui ON ui.user_id = tl.owner_id
+            WHERE tl.list_id = $1
+            "#,
+            list_id
+        )
+        .fetch_one(self.db.executor())
+        .instrument(query_span("SELECT list owner"))
+        .await
+        .context("Failed to get list owner")
+    }
+
+    /// One statement whatever the number of todos and their decorations.
+    /// The page is chosen from the todos alone first, along the
+    /// `todo_list_page_idx` index for the default sort. Only its rows then
+    /// get their tags and subtask counts, each from a lateral subquery along
+    /// the primary key of `todo_tag` and the `todo_id` index of `subtask`.
+    /// Joining the tag and subtask rows directly would multiply each other
+    /// and need the whole list grouped before the limit.
+    async fn filtered(
+        &self,
+        list_id: Uuid,
+        filter: &TodoFilter,
+    ) -> Result<Vec<Todo>, anyhow::Error> {
+        sqlx::query_as!(
+            Todo,
+            r#"
+            WITH page AS (
+                SELECT td.*
+                FROM todo AS td
+                WHERE td.list_id = $1
+                    AND td.deleted_at IS NULL
+                    AND ($2::text IS NULL OR EXISTS (
+                        SELECT 1 FROM todo_tag AS ft
+                        JOIN tag AS ftg ON ftg.tag_id = ft.tag_id
+                        WHERE ft.todo_id = td.todo_id AND ftg.name = $2
+                    ))
+                    AND ($4 OR NOT td.is_completed)
+                    AND (
+                        $7::text IS NULL
+                        OR ($7 = 'overdue' AND td.due_date < $8 AND NOT td.is_completed)
+                        OR ($7 = 'today' AND td.due_date = $8)
+                        OR ($7 = 'upcoming' AND td.due_date > $8)
+                    )
+                ORDER BY
+                    td.is_pinned DESC,
+                    CASE WHEN $3 THEN td.priority END DESC NULLS LAST,
+                    td.created_at DESC,
+                    td.todo_id DESC
+                LIMIT $5 OFFSET $6
+            )
+            SELECT
+                page.todo_id AS "todo_id!", page.list_id AS "list_id!",
+                page.todo_content AS "todo_content!", page.is_completed AS "is_completed!",
+                page.is_pinned AS "is_pinned!", page.version AS "version!", page.due_date,
+                page.priority AS "priority!: Priority", page.created_at AS "created_at!",
+                page.updated_at AS "updated_at!", page.completed_at,
+                tg.names AS "tags!",
+                st.total AS "subtask_count!",
+                st.completed AS "completed_subtask_count!"
+            FROM page
+            LEFT JOIN LATERAL (
+                SELECT COALESCE(array_agg(tag.name ORDER BY tag.name), '{}') AS names
+                FROM todo_tag
+                JOIN tag ON tag.tag_id = todo_tag.tag_id
+                WHERE todo_tag.todo_id = page.todo_id
+            ) AS tg ON TRUE
+            LEFT JOIN LATERAL (
+                SELECT COUNT(*) AS total, COUNT(*) FILTER (WHERE is_completed) AS completed
+                FROM subtask
+                WHERE subtask.todo_id = page.todo_id
+            ) AS st ON TRUE
+            -- the order of the page isn't kept through the joins
+            ORDER BY
+                page.is_pinned DESC,
+                CASE WHEN $3 THEN page.priority END DESC NULLS LAST,
+                page.created_at DESC,
+                page.todo_id DESC
+            "#,
+            list_id,
+            filter.tag.as_deref(),
+            filter.sort == TodoSort::Priority,
+            filter.show_completed,
+            filter.limit,
+            filter.offset,
+            filter.due.map(DueFilter::as_str),
+            filter.today
+        )
+        .fetch_all(self.db.executor())
+        .instrument(query_span("SELECT todos"))
+        .await
+        .context("Failed to get todos")
+    }
+
+    /// Planned like [`Self::filtered`], along `todo_list_created_at_idx`
+    async fn page_after(
+        &self,
+        list_id: Uuid,
+        after: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Vec<Todo>, anyhow::Error> {
+        sqlx::query_as!(
+            Todo,
+            r#"
+            WITH page AS (
+                SELECT td.*
+                FROM todo AS td
+                WHERE td.list_id = $1
+                    AND td.deleted_at IS NULL
+                    AND ($2::timestamptz IS NULL OR (td.created_at, td.todo_id) < ($2, $3))
+                ORDER BY td.created_at DESC, td.todo_id DESC
+                LIMIT $4
+            )
+            SELECT
+                page.todo_id AS "todo_id!", page.list_id AS "list_id!",
+                page.todo_content AS "todo_content!", page.is_completed AS "is_completed!",
+                page.is_pinned AS "is_pinned!", page.version AS "version!", page.due_date,
+                page.priority AS "priority!: Priority", page.created_at AS "created_at!",
+                page.updated_at AS "updated_at!", page.completed_at,
+                tg.names AS "tags!",
+                st.total AS "subtask_count!",
+                st.completed AS "completed_subtask_count!"
+            FROM page
+            LEFT JOIN LATERAL (
+                SELECT COALESCE(array_agg(tag.name ORDER BY tag.name), '{}') AS names
+                FROM todo_tag
+                JOIN tag ON tag.tag_id = todo_tag.tag_id
+                WHERE todo_tag.todo_id = page.todo_id
+            ) AS tg ON TRUE
+            LEFT JOIN LATERAL (
+                SELECT COUNT(*) AS total, COUNT(*) FILTER (WHERE is_completed) AS completed
+                FROM subtask
+                WHERE subtask.todo_id = page.todo_id
+            ) AS st ON TRUE
+            ORDER BY page.created_at DESC, page.todo_id DESC
+            "#,
+            list_id,
+            after.map(|cursor| cursor.created_at) as Option<OffsetDateTime>,
+            after.map(|cursor| cursor.todo_id) as Option<Uuid>,
+            limit
+        )
+        .fetch_all(self.db.executor())
+        .await
+        .context("Failed to get todos")
+    }
+
+    /// Along `todo_completed_at_idx`
+    async fn completed_on(
+        &self,
+        list_id: Uuid,
+        day: Date,
+        tz: &Tz,
+    ) -> Result<Vec<CompletedTodo>, anyhow::Error> {
+        sqlx::query_as!(
+            CompletedTodo,
+            r#"
+            SELECT todo_id, todo_content, completed_at AS "completed_at!"
+            FROM todo
+            WHERE list_id = $1
+                AND completed_at IS NOT NULL
+                AND deleted_at IS NULL
+                AND (completed_at AT TIME ZONE $3)::date = $2
+            ORDER BY completed_at DESC
+            "#,
+            list_id,
+            day,
+            tz.name()
+        )
+        .fetch_all(self.db.executor())
+        .instrument(query_span("SELECT completed todos"))
+        .await
+        .context("Failed to get completed todos")
+    }
+
+    async fn members(&self, list_id: Uuid) -> Result<Vec<ListMember>, anyhow::Error> {
+        list::list_members(self.db.executor(), list_id).await
+    }
+
+    async fn shared_lists(&self, user_id: Uuid) -> Result<Vec<SharedList>, anyhow::Error> {
+        list::shared_lists(self.db.executor(), user_id).await
+    }
+
+    async fn add_member(
+        &self,
+        list_id: Uuid,
+        member_id: Uuid,
+        role: ListRole,
+    ) -> Result<(), anyhow::Error> {
+        list::add_member(self.db.executor(), list_id, member_id, role).await
+    }
+
+    async fn remove_member(&self, list_id: Uuid, member_id: Uuid) -> Result<bool, anyhow::Error> {
+        list::remove_member(self.db.executor(), list_id, member_id).await
+    }
+
+    async fn description(&self, todo_id: Uuid) -> Result<String, anyhow::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT description FROM todo WHERE todo_id = $1
+            "#,
+            todo_id
+        )
+        .fetch_one(self.db.executor())
+        .await
+        .context("Failed to get todo description")
+    }
+
+    async fn subtasks(&self, todo_id: Uuid) -> Result<Vec<Subtask>, anyhow::Error> {
+        subtask::fetch_subtasks(self.db.executor(), todo_id).await
+    }
+
+    async fn history(
+        &self,
+        todo_id: Uuid,
+        page: i64,
+    ) -> Result<(Vec<HistoryEntry>, bool), anyhow::Error> {
+        history::fetch_history(self.db.executor(), todo_id, page).await
+    }
+
+    async fn tag_counts(&self, list_id: Uuid) -> Result<Vec<TagCount>, anyhow::Error> {
+        sqlx::query_as!(
+            TagCount,
+            r#"
+            SELECT tg.name, COUNT(td.todo_id) AS "todo_count!"
+            FROM tag AS tg
+            JOIN todo_tag AS tt ON tt.tag_id = tg.tag_id
+            JOIN todo AS td ON td.todo_id = tt.todo_id
+            WHERE td.list_id = $1 AND td.deleted_at IS NULL
+            GROUP BY tg.name
+            ORDER BY tg.name
+            "#,
+            list_id
+        )
+        .fetch_all(self.db.executor())
+        .await
+        .context("Failed to get tags")
+    }
+
+    async fn deleted(
+        &self,
+        todo_id: Uuid,
+        grace_secs: f64,
+    ) -> Result<Option<(Uuid, bool)>, anyhow::Error> {
+        let deleted = sqlx::query!(
+            r#"
+            SELECT list_id, deleted_at > NOW() - make_interval(secs => $2) AS "restorable!"
+            FROM todo
+            WHERE todo_id = $1 AND deleted_at IS NOT NULL
+            "#,
+            todo_id,
+            grace_secs
+        )
+        .fetch_optional(self.db.executor())
+        .await
+        .context("Failed to get deleted todo")?;
+        Ok(deleted.map(|deleted| (deleted.list_id, deleted.restorable)))
+    }
+
+    async fn restore(
+        &self,
+        todo_id: Uuid,
+        list_id: Uuid,
+        user_id: Uuid,
+        grace_secs: f64,
+        default_limit: i64,
+    ) -> Result<Option<TodoCounts>, QuotaError> {
+        let mut transaction = self
+            .db
+            .pool()
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+
+        // the trigger counts the todo for whoever added it, if they still exist
+        let added_by = sqlx::query_scalar!(
+            r#"
+            SELECT user_id FROM todo
+            WHERE todo_id = $1 AND deleted_at > NOW() - make_interval(secs => $2)
+            "#,
+            todo_id,
+            grace_secs
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to get deleted todo")?;
+        match added_by {
+            None => return Ok(None),
+            Some(Some(added_by)) => {
+                quota::reserve(&mut transaction, added_by, 1, default_limit).await?;
+            }
+            Some(None) => {}
+        }
+
+        let query_result = sqlx::query!(
+            r#"
+            UPDATE todo
+            SET deleted_at = NULL
+            WHERE todo_id = $1 AND deleted_at > NOW() - make_interval(secs => $2)
+            "#,
+            todo_id,
+            grace_secs
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to restore todo")?;
+        if query_result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        history::record_change(&mut transaction, todo_id, user_id, TodoChange::Restored).await?;
+        let counts = TodoCounts::fetch(&mut *transaction, list_id).await?;
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+
+        Ok(Some(counts))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use std::{
+        cmp::Reverse,
+        collections::{BTreeMap, HashMap},
+        hash::{DefaultHasher, Hash, Hasher},
+        sync::Mutex,
+    };
+
+    use super::*;
+    use crate::domain::timezone;
+
+    /// In memory [`TodoRepo`], each user owning one list. Lists can be
+    /// shared with [`FakeTodoRepo::add_member`]. Todos have no subtasks or
+    /// history, deleted ones can always be restored, and no user has a todo
+    /// limit of their own.
+    #[derive(Default)]
+    pub(crate) struct FakeTodoRepo {
+        state: Mutex<FakeState>,
+    }
+
+    #[derive(Default)]
+    struct FakeState {
+        /// Owner of each list
+        lists: HashMap<Uuid, Uuid>,
+        members: Vec<(Uuid, Uuid, ListAccess)>,
+        todos: Vec<Todo>,
+        deleted: Vec<Uuid>,
+        /// Who added each todo, for the quota
+        added_by: HashMap<Uuid, Uuid>,
+        /// Users without one are named after their id
+        usernames: HashMap<Uuid, String>,
+        descriptions: HashMap<Uuid, String>,
+    }
+
+    impl FakeState {
+        fn access(&self, list_id: Uuid, user_id: Uuid) -> Option<ListAccess> {
+            if self.lists.get(&list_id) == Some(&user_id) {
+                return Some(ListAccess::Owner);
+            }
+            self.members
+                .iter()
+                .find(|(list, user, _)| *list == list_id && *user == user_id)
+                .map(|(_, _, access)| *access)
+        }
+
+        fn live_todo(&self, todo_id: Uuid) -> Option<&Todo> {
+            self.todos
+                .iter()
+                .find(|todo| todo.todo_id == todo_id && !self.deleted.contains(&todo_id))
+        }
+
+        fn live_todos(&self, list_id: Uuid) -> impl Iterator<Item = &Todo> {
+            self.todos.iter().filter(move |todo| {
+                todo.list_id == list_id && !self.deleted.contains(&todo.todo_id)
+            })
+        }
+
+        fn username(&self, user_id: Uuid) -> String {
+            self.usernames
+                .get(&user_id)
+                .cloned()
+                .unwrap_or_else(|| user_id.to_string())
+        }
+
+        fn counts(&self, list_id: Uuid) -> TodoCounts {
+            let (completed, active): (Vec<_>, Vec<_>) =
+                self.live_todos(list_id).partition(|todo| todo.is_completed);
+            TodoCounts {
+                active: active.len() as i64,
+                completed: completed.len() as i64,
+            }
+        }
+    }
+
+    impl FakeTodoRepo {
+        /// Gives the user their list, like registering does
+        pub fn add_user(&self, user_id: Uuid) -> Uuid {
+            let list_id = Uuid::new_v4();
+            self.state.lock().unwrap().lists.insert(list_id, user_id);
+            list_id
+        }
+
+        pub fn add_member(&self, list_id: Uuid, user_id: Uuid, access: ListAccess) {
+            self.state
+                .lock()
+                .unwrap()
+                .members
+                .push((list_id, user_id, access));
+        }
+
+        pub fn set_username(&self, user_id: Uuid, username: &str) {
+            self.state
+                .lock()
+                .unwrap()
+                .usernames
+                .insert(user_id, username.to_string());
+        }
+
+        pub fn set_description(&self, todo_id: Uuid, description: &str) {
+            self.state
+                .lock()
+                .unwrap()
+                .descriptions
+                .insert(todo_id, description.to_string());
+        }
+
+        pub fn todos(&self) -> Vec<Todo> {
+            self.state.lock().unwrap().todos.clone()
+        }
+    }
+
+    /// Highest first, like the `todo_priority` enum sorted descending
+    fn priority_rank(priority: Priority) -> usize {
+        Priority::ALL
+            .iter()
+            .position(|p| *p == priority)
+            .expect("Priority::ALL has every priority")
+    }
+
+    #[async_trait]
+    impl TodoRepo for FakeTodoRepo {
+        async fn list_access(
+            &self,
+            list_id: Uuid,
+            user_id: Uuid,
+        ) -> Result<Option<ListAccess>, anyhow::Error> {
+            Ok(self.state.lock().unwrap().access(list_id, user_id))
+        }
+
+        async fn todo_access(
+            &self,
+            todo_id: Uuid,
+            user_id: Uuid,
+        ) -> Result<Option<(Uuid, ListAccess)>, anyhow::Error> {
+            let state = self.state.lock().unwrap();
+            Ok(state.live_todo(todo_id).and_then(|todo| {
+                state
+                    .access(todo.list_id, user_id)
+                    .map(|access| (todo.list_id, access))
+            }))
+        }
+
+        async fn own_list_id(&self, user_id: Uuid) -> Result<Uuid, anyhow::Error> {
+            self.state
+                .lock()
+                .unwrap()
+                .lists
+                .iter()
+                .find(|(_, owner)| **owner == user_id)
+                .map(|(list_id, _)| *list_id)
+                .context("No list for user")
+        }
+
+        async fn fetch(&self, todo_id: Uuid) -> Result<Option<Todo>, anyhow::Error> {
+            Ok(self.state.lock().unwrap().live_todo(todo_id).cloned())
+        }
+
+        async fn counts(&self, list_id: Uuid) -> Result<TodoCounts, anyhow::Error> {
+            Ok(self.state.lock().unwrap().counts(list_id))
+        }
+
+        async fn recipients(&self, list_id: Uuid) -> Result<Vec<ListRecipient>, anyhow::Error> {
+            let state = self.state.lock().unwrap();
+            let owner = state.lists.get(&list_id).map(|owner| ListRecipient {
+                user_id: *owner,
+                can_edit: true,
+            });
+            let members = state
+                .members
+                .iter()
+                .filter(|(list, _, _)| *list == list_id)
+                .map(|(_, user_id, access)| ListRecipient {
+                    user_id: *user_id,
+                    can_edit: access.can_edit(),
+                });
+            Ok(owner.into_iter().chain(members).collect())
+        }
+
+        async fn create(
+            &self,
+            user_id: Uuid,
+            new_todo: NewTodoRow,
+            default_limit: i64,
+            repeat_window_secs: f64,
+        ) -> Result<Created, QuotaError> {
+            let mut state = self.state.lock().unwrap();
+            let now = OffsetDateTime::now_utc();
+            let repeated = state
+                .live_todos(new_todo.list_id)
+                .filter(|todo| {
+                    state.added_by.get(&todo.todo_id) == Some(&user_id)
+                        && todo.todo_content == new_todo.todo_content.as_ref()
+                        && (now - todo.created_at).as_seconds_f64() < repeat_window_secs
+                })
+                .max_by_key(|todo| todo.created_at)
+                .cloned();
+            if let Some(todo) = repeated {
+                return Ok(Created {
+                    counts: state.counts(new_todo.list_id),
+                    todo,
+                    is_new: false,
+                });
+            }
+
+            let active = state
+                .added_by
+                .iter()
+                .filter(|(todo_id, added_by)| {
+                    **added_by == user_id && !state.deleted.contains(todo_id)
+                })
+                .count() as i64;
+            if active >= default_limit {
+                return Err(QuotaError::Exceeded {
+                    limit: default_limit,
+                });
+            }
+
+            let mut tags = new_todo.tags.names();
+            tags.sort();
+            let todo = Todo {
+                todo_id: Uuid::new_v4(),
+                list_id: new_todo.list_id,
+                todo_content: new_todo.todo_content.as_ref().to_string(),
+                is_completed: false,
+                is_pinned: false,
+                version: 1,
+                due_date: new_todo.due_date,
+                priority: new_todo.priority,
+                tags,
+                subtask_count: 0,
+                completed_subtask_count: 0,
+                created_at: now,
+                updated_at: now,
+                completed_at: None,
+            };
+            state.added_by.insert(todo.todo_id, user_id);
+            state.todos.push(todo.clone());
+            Ok(Created {
+                todo,
+                counts: state.counts(new_todo.list_id),
+                is_new: true,
+            })
+        }
+
+        async fn duplicate(
+            &self,
+            todo_id: Uuid,
+            list_id: Uuid,
+            user_id: Uuid,
+            default_limit: i64,
+            max_subtasks: i64,
+        ) -> Result<Option<Created>, QuotaError> {
+            let mut state = self.state.lock().unwrap();
+            let Some(original) = state
+                .live_todo(todo_id)
+                .filter(|todo| todo.list_id == list_id)
+                .cloned()
+            else {
+                return Ok(None);
+            };
+            let active = state
+                .added_by
+                .iter()
+                .filter(|(todo_id, added_by)| {
+                    **added_by == user_id && !state.deleted.contains(todo_id)
+                })
+                .count() as i64;
+            if active >= default_limit {
+                return Err(QuotaError::Exceeded {
+                    limit: default_limit,
+                });
+            }
+
+            let now = OffsetDateTime::now_utc();
+            let todo = Todo {
+                todo_id: Uuid::new_v4(),
+                is_completed: false,
+                is_pinned: false,
+                version: 1,
+                subtask_count: original.subtask_count.min(max_subtasks),
+                completed_subtask_count: 0,
+                created_at: now,
+                updated_at: now,
+                completed_at: None,
+                ..original
+            };
+            if let Some(description) = state.descriptions.get(&todo_id).cloned() {
+                state.descriptions.insert(todo.todo_id, description);
+            }
+            state.added_by.insert(todo.todo_id, user_id);
+            state.todos.push(todo.clone());
+            Ok(Some(Created {
+                todo,
+                counts: state.counts(list_id),
+                is_new: true,
+            }))
+        }
+
+        async fn delete(
+            &self,
+            todo_id: Uuid,
+            list_id: Uuid,
+            _user_id: Uuid,
+        ) -> Result<(Option<String>, TodoCounts), anyhow::Error> {
+            let mut state = self.state.lock().unwrap();
+            let todo_content = state
+                .live_todo(todo_id)
+                .filter(|todo| todo.list_id == list_id)
+                .map(|todo| todo.todo_content.clone());
+            if todo_content.is_some() {
+                state.deleted.push(todo_id);
+            }
+            Ok((todo_content, state.counts(list_id)))
+        }
+
+        async fn update(
+            &self,
+            todo_id: Uuid,
+            list_id: Uuid,
+            _user_id: Uuid,
+            update: TodoUpdate,
+        ) -> Result<bool, anyhow::Error> {
+            let mut state = self.state.lock().unwrap();
+            let deleted = state.deleted.clone();
+            let Some(todo) = state.todos.iter_mut().find(|todo| {
+                todo.todo_id == todo_id
+                    && todo.list_id == list_id
+                    && todo.version == update.version
+                    && !deleted.contains(&todo_id)
+            }) else {
+                return Ok(false);
+            };
+            if let Some(is_completed) = update.is_completed {
+                todo.completed_at = match (todo.is_completed, is_completed) {
+                    (_, false) => None,
+                    (true, true) => todo.completed_at,
+                    (false, true) => Some(OffsetDateTime::now_utc()),
+                };
+                todo.is_completed = is_completed;
+            }
+            if let Some(priority) = update.priority {
+                todo.priority = priority;
+            }
+            todo.version += 1;
+            Ok(true)
+        }
+
+        async fn list_etag(
+            &self,
+            list_id: Uuid,
+            user_id: Uuid,
+            view: &str,
+        ) -> Result<String, anyhow::Error> {
+            let state = self.state.lock().unwrap();
+            let todos: Vec<_> = state
+                .live_todos(list_id)
+                .map(|todo| format!("{}:{}", todo.todo_id, todo.version))
+                .collect();
+            let members = state
+                .members
+                .iter()
+                .filter(|(list, _, _)| *list == list_id)
+                .count();
+            let mut hasher = DefaultHasher::new();
+            (list_id, user_id, view, members, todos).hash(&mut hasher);
+            Ok(format!("\"{:x}\"", hasher.finish()))
+        }
+
+        async fn owner_username(&self, list_id: Uuid) -> Result<String, anyhow::Error> {
+            let state = self.state.lock().unwrap();
+            let owner = state.lists.get(&list_id).context("No such list")?;
+            Ok(state.username(*owner))
+        }
+
+        async fn filtered(
+            &self,
+            list_id: Uuid,
+            filter: &TodoFilter,
+        ) -> Result<Vec<Todo>, anyhow::Error> {
+            let state = self.state.lock().unwrap();
+            let mut todos: Vec<_> = state
+                .live_todos(list_id)
+                .filter(|todo| filter.show_completed || !todo.is_completed)
+                .filter(|todo| {
+                    filter
+                        .tag
+                        .as_ref()
+                        .is_none_or(|tag| todo.tags.contains(tag))
+                })
+                .filter(|todo| {
+                    filter.due.is_none_or(|due| {
+                        due.matches(todo.due_date, todo.is_completed, filter.today)
+                    })
+                })
+                .cloned()
+                .collect();
+            todos.sort_by_key(|todo| {
+                let rank =
+                    (filter.sort == TodoSort::Priority).then(|| priority_rank(todo.priority));
+                (!todo.is_pinned, rank, Reverse(todo.created_at))
+            });
+            Ok(todos
+                .into_iter()
+                .skip(filter.offset as usize)
+                .take(filter.limit as usize)
+                .collect())
+        }
+
+        async fn page_after(
+            &self,
+            list_id: Uuid,
+            after: Option<Cursor>,
+            limit: i64,
+        ) -> Result<Vec<Todo>, anyhow::Error> {
+            let state = self.state.lock().unwrap();
+            let mut todos: Vec<_> = state
+                .live_todos(list_id)
+                .filter(|todo| {
+                    after.is_none_or(|cursor| {
+                        (todo.created_at, todo.todo_id) < (cursor.created_at, cursor.todo_id)
+                    })
+                })
+                .cloned()
+                .collect();
+            todos.sort_by_key(|todo| Reverse((todo.created_at, todo.todo_id)));
+            todos.truncate(limit as usize);
+            Ok(todos)
+        }
+
+        async fn completed_on(
+            &self,
+            list_id: Uuid,
+            day: Date,
+            tz: &Tz,
+        ) -> Result<Vec<CompletedTodo>, anyhow::Error> {
+            let state = self.state.lock().unwrap();
+            let mut completed: Vec<_> = state
+                .live_todos(list_id)
+                .filter_map(|todo| {
+                    let completed_at = todo.completed_at?;
+                    (timezone::local_date(completed_at, tz) == day).then(|| CompletedTodo {
+                        todo_id: todo.todo_id,
+                        todo_content: todo.todo_content.clone(),
+                        completed_at,
+                    })
+                })
+                .collect();
+            completed.sort_by_key(|todo| Reverse(todo.completed_at));
+            Ok(completed)
+        }
+
+        async fn members(&self, list_id: Uuid) -> Result<Vec<ListMember>, anyhow::Error> {
+            let state = self.state.lock().unwrap();
+            let mut members: Vec<_> = state
+                .members
+                .iter()
+                .filter_map(|(list, user_id, access)| match access {
+                    ListAccess::Member(role) if *list == list_id => Some(ListMember {
+                        user_id: *user_id,
+                        username: state.username(*user_id),
+                        role: *role,
+                    }),
+                    _ => None,
+                })
+                .collect();
+            members.sort_by(|a, b| a.username.cmp(&b.username));
+            Ok(members)
+        }
+
+        async fn shared_lists(&self, user_id: Uuid) -> Result<Vec<SharedList>, anyhow::Error> {
+            let state = self.state.lock().unwrap();
+            let mut shared: Vec<_> = state
+                .members
+                .iter()
+                .filter_map(|(list_id, user, access)| match access {
+                    ListAccess::Member(role) if *user == user_id => Some(SharedList {
+                        list_id: *list_id,
+                        owner_username: state.username(state.lists[list_id]),
+                        role: *role,
+                    }),
+                    _ => None,
+                })
+                .collect();
+            shared.sort_by(|a, b| a.owner_username.cmp(&b.owner_username));
+            Ok(shared)
+        }
+
+        async fn add_member(
+            &self,
+            list_id: Uuid,
+            member_id: Uuid,
+            role: ListRole,
+        ) -> Result<(), anyhow::Error> {
+            let mut state = self.state.lock().unwrap();
+            state
+                .members
+                .retain(|(list, user, _)| !(*list == list_id && *user == member_id));
+            state
+                .members
+                .push((list_id, member_id, ListAccess::Member(role)));
+            Ok(())
+        }
+
+        async fn remove_member(
+            &self,
+            list_id: Uuid,
+            member_id: Uuid,
+        ) -> Result<bool, anyhow::Error> {
+            let mut state = self.state.lock().unwrap();
+            let before = state.members.len();
+            state
+                .members
+                .retain(|(list, user, _)| !(*list == list_id && *user == member_id));
+            Ok(state.members.len() < before)
+        }
+
+        async fn description(&self, todo_id: Uuid) -> Result<String, anyhow::Error> {
+            let state = self.state.lock().unwrap();
+            Ok(state
+                .descriptions
+                .get(&todo_id)
+                .cloned()
+                .unwrap_or_default())
+        }
+
+        async fn subtasks(&self, _todo_id: Uuid) -> Result<Vec<Subtask>, anyhow::Error> {
+            Ok(Vec::new())
+        }
+
+        async fn history(
+            &self,
+            _todo_id: Uuid,
+            _page: i64,
+        ) -> Result<(Vec<HistoryEntry>, bool), anyhow::Error> {
+            Ok((Vec::new(), false))
+        }
+
+        async fn tag_counts(&self, list_id: Uuid) -> Result<Vec<TagCount>, anyhow::Error> {
+            let state = self.state.lock().unwrap();
+            let mut counts = BTreeMap::new();
+            for tag in state.live_todos(list_id).flat_map(|todo| &todo.tags) {
+                *counts.entry(tag.clone()).or_insert(0) += 1;
+            }
+            Ok(counts
+                .into_iter()
+                .map(|(name, todo_count)| TagCount { name, todo_count })
+                .collect())
+        }
+
+        async fn deleted(
+            &self,
+            todo_id: Uuid,
+            _grace_secs: f64,
+        ) -> Result<Option<(Uuid, bool)>, anyhow::Error> {
+            let state = self.state.lock().unwrap();
+            Ok(state
+                .todos
+                .iter()
+                .find(|todo| todo.todo_id == todo_id && state.deleted.contains(&todo_id))
+                .map(|todo| (todo.list_id, true)))
+        }
+
+        async fn restore(
+            &self,
+            todo_id: Uuid,
+            list_id: Uuid,
+            _user_id: Uuid,
+            _grace_secs: f64,
+            default_limit: i64,
+        ) -> Result<Option<TodoCounts>, QuotaError> {
+            let mut state = self.state.lock().unwrap();
+            if !state.deleted.contains(&todo_id) {
+                return Ok(None);
+            }
+            if let Some(added_by) = state.added_by.get(&todo_id) {
+                let active = state
+                    .added_by
+                    .iter()
+                    .filter(|(todo_id, user_id)| {
+                        *user_id == added_by && !state.deleted.contains(todo_id)
+                    })
+                    .count() as i64;
+                if active >= default_limit {
+                    return Err(QuotaError::Exceeded {
+                        limit: default_limit,
+                    });
+                }
+            }
+            state.deleted.retain(|deleted| *deleted != todo_id);
+            Ok(Some(state.counts(list_id)))
+        }
+    }
+}
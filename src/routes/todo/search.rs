@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::filters;
+use crate::{app::ApiContext, auth::AuthSession, page::PageContext};
+
+/// Most todos shown for a search
+const SEARCH_LIMIT: i64 = 50;
+
+/// How the todos of a search were found
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchMatch {
+    /// Containing every word searched for
+    Words,
+    /// With a word like the one searched for, found with pg_trgm when no todo
+    /// has the words, e.g. after a typo
+    Similar,
+    /// Containing the text searched for, when no todo has the words and
+    /// pg_trgm isn't installed
+    Substring,
+}
+
+#[derive(Debug)]
+pub struct SearchResult {
+    pub todo_id: Uuid,
+    pub todo_content: String,
+    pub is_completed: bool,
+}
+
+/// Whether pg_trgm is installed, which the migrations leave out when the
+/// database doesn't allow it. Checked once at startup.
+pub async fn trigram_available(db: &PgPool) -> bool {
+    let installed = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'pg_trgm') AS "installed!"
+        "#
+    )
+    .fetch_one(db)
+    .await;
+    match installed {
+        Ok(true) => true,
+        Ok(false) => {
+            tracing::warn!("pg_trgm is not installed, todo search won't tolerate typos");
+            false
+        }
+        Err(e) => {
+            tracing::warn!(error = ?e, "Failed to check for pg_trgm, todo search won't tolerate typos");
+            false
+        }
+    }
+}
+
+/// The todos of the lists the user can see matching `q`. Looks for todos
+/// with the words first, and only when there are none for similar ones.
+pub async fn search_todos(
+    db: &PgPool,
+    user_id: Uuid,
+    q: &str,
+    trigram: bool,
+) -> Result<(Vec<SearchResult>, SearchMatch), anyhow::Error> {
+    let results = sqlx::query_as!(
+        SearchResult,
+        r#"
+        SELECT td.todo_id, td.todo_content, td.is_completed
+        FROM todo AS td
+        JOIN todo_list AS tl ON tl.list_id = td.list_id
+        LEFT JOIN list_members AS lm ON lm.list_id = td.list_id AND lm.user_id = $1
+        WHERE td.deleted_at IS NULL
+            AND (tl.owner_id = $1 OR lm.user_id IS NOT NULL)
+            AND to_tsvector('simple', td.todo_content) @@ plainto_tsquery('simple', $2)
+        ORDER BY
+            ts_rank(to_tsvector('simple', td.todo_content), plainto_tsquery('simple', $2)) DESC,
+            td.created_at DESC
+        LIMIT $3
+        "#,
+        user_id,
+        q,
+        SEARCH_LIMIT
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to search todos")?;
+    if !results.is_empty() {
+        return Ok((results, SearchMatch::Words));
+    }
+
+    if trigram {
+        // `<%` is true above `pg_trgm.word_similarity_threshold`, 0.6 by
+        // default, and unlike `word_similarity` itself can use the index
+        let results = sqlx::query_as!(
+            SearchResult,
+            r#"
+            SELECT td.todo_id, td.todo_content, td.is_completed
+            FROM todo AS td
+            JOIN todo_list AS tl ON tl.list_id = td.list_id
+            LEFT JOIN list_members AS lm ON lm.list_id = td.list_id AND lm.user_id = $1
+            WHERE td.deleted_at IS NULL
+                AND (tl.owner_id = $1 OR lm.user_id IS NOT NULL)
+                AND $2 <% td.todo_content
+            ORDER BY word_similarity($2, td.todo_content) DESC, td.created_at DESC
+            LIMIT $3
+            "#,
+            user_id,
+            q,
+            SEARCH_LIMIT
+        )
+        .fetch_all(db)
+        .await
+        .context("Failed to search similar todos")?;
+        return Ok((results, SearchMatch::Similar));
+    }
+
+    let results = sqlx::query_as!(
+        SearchResult,
+        r#"
+        SELECT td.todo_id, td.todo_content, td.is_completed
+        FROM todo AS td
+        JOIN todo_list AS tl ON tl.list_id = td.list_id
+        LEFT JOIN list_members AS lm ON lm.list_id = td.list_id AND lm.user_id = $1
+        WHERE td.deleted_at IS NULL
+            AND (tl.owner_id = $1 OR lm.user_id IS NOT NULL)
+            AND td.todo_content ILIKE $2
+        ORDER BY td.created_at DESC
+        LIMIT $3
+        "#,
+        user_id,
+        like_pattern(q),
+        SEARCH_LIMIT
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to search todos by substring")?;
+    Ok((results, SearchMatch::Substring))
+}
+
+/// `ILIKE` pattern matching `q` anywhere, with its wildcards taken literally
+fn like_pattern(q: &str) -> String {
+    let escaped = q
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SearchQuery {
+    q: Option<String>,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "todo/search.html")]
+struct SearchTemplate {
+    page_context: PageContext,
+    q: String,
+    results: Vec<SearchResult>,
+    /// `None` before anything was searched for
+    search_match: Option<SearchMatch>,
+}
+
+impl SearchTemplate {
+    /// Whether the results only look like what was searched for
+    fn did_you_mean(&self) -> bool {
+        self.search_match == Some(SearchMatch::Similar)
+    }
+}
+
+pub async fn search(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    page_context: PageContext,
+    Query(query): Query<SearchQuery>,
+) -> Response {
+    let Some(user) = auth_session.user else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    let q = query.q.unwrap_or_default().trim().to_string();
+    if q.is_empty() {
+        return SearchTemplate {
+            page_context,
+            q,
+            results: Vec::new(),
+            search_match: None,
+        }
+        .into_response();
+    }
+
+    match search_todos(
+        &api_context.db,
+        user.user_id(),
+        &q,
+        api_context.trigram_search,
+    )
+    .await
+    {
+        Ok((results, search_match)) => SearchTemplate {
+            page_context,
+            q,
+            results,
+            search_match: Some(search_match),
+        }
+        .into_response(),
+        Err(e) => {
+            tracing::error!(error = ?e, "Failed to search todos");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::like_pattern;
+
+    #[test]
+    fn like_pattern_takes_wildcards_literally() {
+        assert_eq!("%milk%", like_pattern("milk"));
+        assert_eq!("%100\\%%", like_pattern("100%"));
+        assert_eq!("%a\\_b%", like_pattern("a_b"));
+        assert_eq!("%c:\\\\%", like_pattern("c:\\"));
+    }
+}
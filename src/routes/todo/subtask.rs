@@ -0,0 +1,341 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Form,
+    extract::{Path, State},
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use http::StatusCode;
+use sqlx::postgres::PgExecutor;
+use uuid::Uuid;
+
+use super::{
+    events::publish_todo_event,
+    history::{self, TodoChange},
+};
+use crate::{
+    app::ApiContext, auth::AuthSession, authz, domain::todo_content::TodoContent,
+    events::TodoEventKind,
+};
+
+#[derive(Debug, Clone)]
+pub struct Subtask {
+    pub subtask_id: Uuid,
+    pub content: String,
+    pub is_completed: bool,
+}
+
+/// The checklist of a todo, on the detail page and after every change
+#[derive(Template, WebTemplate)]
+#[template(path = "todo/subtasks.html")]
+pub struct SubtasksTemplate {
+    todo_id: Uuid,
+    subtasks: Vec<Subtask>,
+    can_edit: bool,
+}
+
+pub async fn fetch_subtasks(
+    executor: impl PgExecutor<'_>,
+    todo_id: Uuid,
+) -> Result<Vec<Subtask>, anyhow::Error> {
+    sqlx::query_as!(
+        Subtask,
+        r#"
+        SELECT subtask_id, content, is_completed
+        FROM subtask
+        WHERE todo_id = $1
+        ORDER BY position, created_at
+        "#,
+        todo_id
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to get subtasks")
+}
+
+async fn subtasks_response(api_context: &ApiContext, todo_id: Uuid, can_edit: bool) -> Response {
+    match api_context.todos.subtasks(todo_id).await {
+        Ok(subtasks) => SubtasksTemplate {
+            todo_id,
+            subtasks,
+            can_edit,
+        }
+        .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn get_subtasks(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path(todo_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let user = match auth_session.user {
+        Some(user) => user,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    match authz::can_view_todo(&api_context, user.user_id(), todo_id).await {
+        Ok(todo_access) => {
+            subtasks_response(&api_context, todo_id, todo_access.access.can_edit()).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct NewSubtask {
+    content: String,
+}
+
+pub async fn create_subtask(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path(todo_id): Path<Uuid>,
+    Form(new_subtask): Form<NewSubtask>,
+) -> impl IntoResponse {
+    let user = match auth_session.user {
+        Some(user) => user,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let content = match TodoContent::parse(&new_subtask.content) {
+        Ok(content) => content,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    let list_id = match authz::require_edit_todo(&api_context, user.user_id(), todo_id).await {
+        Ok(todo_access) => todo_access.list_id,
+        Err(e) => return e.into_response(),
+    };
+
+    let result = async {
+        let mut transaction = api_context
+            .db
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+        sqlx::query!(
+            r#"
+            INSERT INTO subtask (todo_id, content, position)
+            SELECT $1, $2, COALESCE(MAX(position), 0) + 1
+            FROM subtask
+            WHERE todo_id = $1
+            "#,
+            todo_id,
+            content.as_ref()
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to add subtask")?;
+        touch_todo(&mut *transaction, todo_id).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")
+    }
+    .await;
+
+    match result {
+        Ok(_) => {
+            publish_todo_event(&api_context, TodoEventKind::Updated, list_id, todo_id).await;
+            let response = subtasks_response(&api_context, todo_id, true).await;
+            (StatusCode::CREATED, response).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct UpdateSubtask {
+    is_completed: Option<bool>,
+    content: Option<String>,
+}
+
+pub async fn update_subtask(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path((todo_id, subtask_id)): Path<(Uuid, Uuid)>,
+    Form(update_subtask): Form<UpdateSubtask>,
+) -> impl IntoResponse {
+    let user = match auth_session.user {
+        Some(user) => user,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let content = match update_subtask.content.as_deref().map(TodoContent::parse) {
+        Some(Ok(content)) => Some(content),
+        Some(Err(e)) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        None => None,
+    };
+
+    let list_id = match authz::require_edit_todo(&api_context, user.user_id(), todo_id).await {
+        Ok(todo_access) => todo_access.list_id,
+        Err(e) => return e.into_response(),
+    };
+
+    let auto_complete = match api_context
+        .preferences
+        .get(&api_context.db, user.user_id())
+        .await
+    {
+        Ok(preferences) => preferences.auto_complete_todos,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let result = async {
+        let mut transaction = api_context
+            .db
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+
+        // the subtask has to belong to the todo that access was checked for
+        let query_result = sqlx::query!(
+            r#"
+            UPDATE subtask
+            SET is_completed = COALESCE($1, is_completed),
+                content = COALESCE($2, content)
+            WHERE subtask_id = $3 AND todo_id = $4
+            "#,
+            update_subtask.is_completed,
+            content.as_ref().map(AsRef::as_ref),
+            subtask_id,
+            todo_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to update subtask")?;
+        if query_result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        touch_todo(&mut *transaction, todo_id).await?;
+
+        let mut todo_completed = false;
+        if auto_complete && update_subtask.is_completed == Some(true) {
+            let query_result = sqlx::query!(
+                r#"
+                UPDATE todo
+                SET is_completed = TRUE, completed_at = NOW(), version = version + 1
+                WHERE todo_id = $1
+                    AND NOT is_completed
+                    AND NOT EXISTS (
+                        SELECT 1 FROM subtask WHERE todo_id = $1 AND NOT is_completed
+                    )
+                "#,
+                todo_id
+            )
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to complete todo")?;
+            todo_completed = query_result.rows_affected() > 0;
+            if todo_completed {
+                history::record_change(
+                    &mut transaction,
+                    todo_id,
+                    user.user_id(),
+                    TodoChange::Completed,
+                )
+                .await?;
+            }
+        }
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+
+        Ok::<_, anyhow::Error>(Some(todo_completed))
+    }
+    .await;
+
+    match result {
+        Ok(Some(todo_completed)) => {
+            publish_todo_event(&api_context, TodoEventKind::Updated, list_id, todo_id).await;
+            if todo_completed {
+                // the status of the todo is shown outside the checklist
+                (
+                    StatusCode::OK,
+                    AppendHeaders([("HX-Redirect", format!("/todo/{todo_id}"))]),
+                )
+                    .into_response()
+            } else {
+                subtasks_response(&api_context, todo_id, true).await
+            }
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn delete_subtask(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path((todo_id, subtask_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let user = match auth_session.user {
+        Some(user) => user,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let list_id = match authz::require_edit_todo(&api_context, user.user_id(), todo_id).await {
+        Ok(todo_access) => todo_access.list_id,
+        Err(e) => return e.into_response(),
+    };
+
+    let result = async {
+        let mut transaction = api_context
+            .db
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+        let query_result = sqlx::query!(
+            r#"
+            DELETE FROM subtask WHERE subtask_id = $1 AND todo_id = $2
+            "#,
+            subtask_id,
+            todo_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete subtask")?;
+        if query_result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        touch_todo(&mut *transaction, todo_id).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+        Ok::<_, anyhow::Error>(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => {
+            publish_todo_event(&api_context, TodoEventKind::Updated, list_id, todo_id).await;
+            subtasks_response(&api_context, todo_id, true).await
+        }
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Subtasks live in their own table, so touch the todo to record the change.
+/// Its `updated_at` is what the list view's ETag is made from.
+async fn touch_todo(executor: impl PgExecutor<'_>, todo_id: Uuid) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE todo SET updated_at = NOW() WHERE todo_id = $1
+        "#,
+        todo_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to update todo timestamp")?;
+
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Form,
+    extract::{Path, Query, State},
+    response::{AppendHeaders, IntoResponse},
+};
+use http::StatusCode;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use super::{events::publish_todo_event, list::list_url};
+use crate::{app::ApiContext, auth::AuthSession, authz, domain::tag::Tags, events::TodoEventKind};
+
+/// Replaces the tags of a todo.
+///
+/// Tags belong to the owner of the todo's list, and are created on first use.
+pub async fn set_todo_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    todo_id: Uuid,
+    tags: &Tags,
+) -> Result<(), anyhow::Error> {
+    let names = tags.names();
+
+    sqlx::query!(
+        r#"
+        DELETE FROM todo_tag WHERE todo_id = $1
+        "#,
+        todo_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to clear todo tags")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO tag (user_id, name)
+        SELECT tl.owner_id, UNNEST($2::text[])
+        FROM todo_list AS tl
+        WHERE tl.list_id = $1
+        ON CONFLICT (user_id, name) DO NOTHING
+        "#,
+        list_id,
+        &names
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to insert tags")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO todo_tag (todo_id, tag_id)
+        SELECT $2, tg.tag_id
+        FROM todo_list AS tl
+        JOIN tag AS tg ON tg.user_id = tl.owner_id
+        WHERE tl.list_id = $1 AND tg.name = ANY($3)
+        "#,
+        list_id,
+        todo_id,
+        &names
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to tag todo")?;
+
+    delete_orphaned_tags(transaction, list_id).await
+}
+
+/// Removes the tags of a list's owner that are no longer on any todo
+pub async fn delete_orphaned_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM tag AS tg
+        USING todo_list AS tl
+        WHERE tl.list_id = $1
+            AND tg.user_id = tl.owner_id
+            AND NOT EXISTS (SELECT 1 FROM todo_tag AS tt WHERE tt.tag_id = tg.tag_id)
+        "#,
+        list_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete orphaned tags")?;
+
+    Ok(())
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct UpdateTags {
+    tags: String,
+}
+
+pub async fn update_tags(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path(todo_id): Path<Uuid>,
+    Form(update_tags): Form<UpdateTags>,
+) -> impl IntoResponse {
+    let user = match auth_session.user {
+        Some(user) => user,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let tags = match Tags::parse(&update_tags.tags) {
+        Ok(tags) => tags,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    let list_id = match authz::require_edit_todo(&api_context, user.user_id(), todo_id).await {
+        Ok(todo_access) => todo_access.list_id,
+        Err(e) => return e.into_response(),
+    };
+
+    let result = async {
+        let mut transaction = api_context
+            .db
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+        set_todo_tags(&mut transaction, list_id, todo_id, &tags).await?;
+        // tags live in their own tables, so touch the todo to record the change
+        sqlx::query!(
+            r#"
+            UPDATE todo SET updated_at = NOW() WHERE todo_id = $1
+            "#,
+            todo_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to update todo timestamp")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")
+    }
+    .await;
+
+    match result {
+        Ok(_) => {
+            publish_todo_event(&api_context, TodoEventKind::Updated, list_id, todo_id).await;
+            (
+                StatusCode::OK,
+                AppendHeaders([("HX-Redirect", list_url(list_id))]),
+            )
+                .into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TagCount {
+    pub name: String,
+    pub todo_count: i64,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "todo/tags.html")]
+struct TagsTemplate {
+    list_id: Uuid,
+    tags: Vec<TagCount>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TagsQuery {
+    list_id: Option<Uuid>,
+}
+
+/// Sidebar fragment listing the tags used in a list, with how many todos have each
+pub async fn get_tags(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Query(query): Query<TagsQuery>,
+) -> impl IntoResponse {
+    let user = match auth_session.user {
+        Some(user) => user,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let list_id =
+        match authz::can_view_list_or_own(&api_context, user.user_id(), query.list_id).await {
+            Ok((list_id, _)) => list_id,
+            Err(e) => return e.into_response(),
+        };
+
+    let tags = api_context.todos.tag_counts(list_id).await;
+
+    match tags {
+        Ok(tags) => TagsTemplate { list_id, tags }.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    extract::{Path, State},
+    response::{AppendHeaders, IntoResponse},
+};
+use http::StatusCode;
+use uuid::Uuid;
+
+use super::{events::publish_todo_event, filters, list::list_url};
+use crate::{
+    app::ApiContext,
+    auth::AuthSession,
+    authz,
+    events::TodoEventKind,
+    toast::{ToastLevel, with_toast},
+};
+
+const UNDO_EXPIRED: &str = "This todo can no longer be restored";
+
+/// Swapped in out of band when a todo is deleted, offering to restore it
+#[derive(Template, WebTemplate, serde::Serialize)]
+#[template(path = "todo/undo_toast.html")]
+pub struct UndoToastTemplate {
+    pub todo_id: Uuid,
+    pub todo_content: String,
+}
+
+/// Restores a deleted todo, as long as its grace period hasn't run out.
+///
+/// Deleted todos are only removed for good by the purge worker, but the
+/// window is checked here too so an undo can't outlive it between runs. The
+/// todo counts against its author's todo limit again, so it isn't restored
+/// once the limit is reached.
+pub async fn undo_delete(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    Path(todo_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let user = match auth_session.user {
+        Some(user) => user,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let grace_secs = api_context.config.application_settings.todo_undo_grace_secs as f64;
+    let deleted = api_context.todos.deleted(todo_id, grace_secs).await;
+    let (list_id, restorable) = match deleted {
+        Ok(Some(deleted)) => deleted,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if let Err(e) = authz::require_edit_list(&api_context, user.user_id(), list_id).await {
+        return e.into_response();
+    }
+    if !restorable {
+        return (StatusCode::GONE, UNDO_EXPIRED).into_response();
+    }
+
+    let result = api_context
+        .todos
+        .restore(
+            todo_id,
+            list_id,
+            user.user_id(),
+            grace_secs,
+            api_context.config.application_settings.max_active_todos,
+        )
+        .await;
+
+    match result {
+        Ok(Some(counts)) => {
+            publish_todo_event(&api_context, TodoEventKind::Created, list_id, todo_id).await;
+            let response = (
+                StatusCode::OK,
+                AppendHeaders([
+                    ("HX-Redirect", list_url(list_id)),
+                    ("HX-Trigger", counts.hx_trigger()),
+                ]),
+            );
+            with_toast(response, ToastLevel::Success, "Todo restored")
+        }
+        // the window ran out between the check and the update
+        Ok(None) => (StatusCode::GONE, UNDO_EXPIRED).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
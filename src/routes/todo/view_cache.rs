@@ -0,0 +1,172 @@
+//! Redis cache of the rendered list views, so showing a list that hasn't
+//! changed doesn't query its todos again.
+//!
+//! Every user has a generation counter in their keys, which changes to the
+//! lists they can see bump, dropping all their entries at once. A change
+//! shows on the next view however long the entries had left. Everything
+//! else the view depends on, the filters and the preferences among them, is
+//! part of the key, so a view for other preferences is never served from an
+//! entry made for the old ones. Changes made outside of the todo routes,
+//! like a renamed member, show once the entries expire.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::Context;
+use fred::{
+    interfaces::KeysInterface,
+    types::{Expiration, SetOptions},
+};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{app::ApiContext, redis::Redis};
+
+/// A view as it is sent, the HTML without the layout around it
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CachedView {
+    pub etag: String,
+    pub body: String,
+}
+
+/// Hits and misses since startup, on this instance
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ViewCacheStats {
+    pub enabled: bool,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Any Redis or deserialization error is treated as a miss. Every instance
+/// has to agree on whether it is enabled, as the ones without it don't
+/// bump the generations either.
+#[derive(Debug, Clone)]
+pub struct ViewCache {
+    redis: Redis,
+    enabled: bool,
+    ttl_secs: i64,
+    counters: Arc<Counters>,
+}
+
+fn generation_key(redis: &Redis, user_id: Uuid) -> String {
+    redis.key(format!("list_view_generation:{user_id}"))
+}
+
+impl ViewCache {
+    pub fn new(redis: Redis, enabled: bool, ttl_secs: u64) -> Self {
+        Self {
+            redis,
+            enabled,
+            ttl_secs: ttl_secs as i64,
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    pub fn stats(&self) -> ViewCacheStats {
+        ViewCacheStats {
+            enabled: self.enabled,
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Where the view of the user described by `view` is kept, `None` when
+    /// the cache is off or Redis fails
+    pub async fn key(&self, user_id: Uuid, view: &str) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+        let generation: Option<u64> =
+            match self.redis.get(generation_key(&self.redis, user_id)).await {
+                Ok(generation) => generation,
+                Err(e) => {
+                    tracing::warn!(%user_id, error = ?e, "Failed to get list view generation");
+                    return None;
+                }
+            };
+        let digest = hex::encode(Sha256::digest(view));
+        Some(self.redis.key(format!(
+            "list_view:{user_id}:{}:{digest}",
+            generation.unwrap_or(0)
+        )))
+    }
+
+    pub async fn get(&self, key: &str) -> Option<CachedView> {
+        let cached: Option<String> = match self.redis.get(key).await {
+            Ok(cached) => cached,
+            Err(e) => {
+                tracing::warn!(error = ?e, "Failed to get cached list view");
+                None
+            }
+        };
+        let cached = cached.and_then(|cached| serde_json::from_str(&cached).ok());
+        let counter = match cached {
+            Some(_) => &self.counters.hits,
+            None => &self.counters.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        cached
+    }
+
+    pub async fn set(&self, key: &str, view: &CachedView) {
+        if let Err(e) = self.try_set(key, view).await {
+            tracing::warn!(error = ?e, "Failed to cache list view");
+        }
+    }
+
+    async fn try_set(&self, key: &str, view: &CachedView) -> Result<(), anyhow::Error> {
+        let serialized = serde_json::to_string(view).context("Failed to serialize list view")?;
+        let _: Option<String> = self
+            .redis
+            .set(
+                key,
+                serialized,
+                Some(Expiration::EX(self.ttl_secs)),
+                None::<SetOptions>,
+                false,
+            )
+            .await
+            .context("Failed to cache list view")?;
+        Ok(())
+    }
+
+    /// Drops the cached views of the users. Call after the change is
+    /// committed. The counters never expire, so one can't start over and
+    /// meet an entry made before.
+    pub async fn invalidate(&self, user_ids: impl IntoIterator<Item = Uuid>) {
+        if !self.enabled {
+            return;
+        }
+        for user_id in user_ids {
+            let result: Result<u64, _> =
+                self.redis.incr(generation_key(&self.redis, user_id)).await;
+            if let Err(e) = result {
+                tracing::error!(%user_id, error = ?e, "Failed to invalidate cached list views");
+            }
+        }
+    }
+}
+
+/// Drops the cached views of everyone with access to the list
+pub async fn invalidate_list(api_context: &ApiContext, list_id: Uuid) {
+    if !api_context.view_cache.enabled {
+        return;
+    }
+    match api_context.todos.recipients(list_id).await {
+        Ok(recipients) => {
+            let user_ids = recipients.into_iter().map(|recipient| recipient.user_id);
+            api_context.view_cache.invalidate(user_ids).await;
+        }
+        Err(e) => {
+            tracing::error!(%list_id, error = ?e, "Failed to invalidate cached list views");
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! The terms of service, and the acceptance of their current version,
+//! `TOS_VERSION`, which logged in users need before going on
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Extension, Form, Router,
+    extract::{Request, State},
+    middleware::{self, Next},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+use axum_login::login_required;
+use http::StatusCode;
+use tower_sessions::Session;
+
+use crate::{
+    api_error::ApiError,
+    app::{ApiContext, AppRouter},
+    audit::{AuditEntry, AuditEvent, RequestMetadata},
+    auth::{AuthSession, Backend, impersonation},
+    i18n::{Locale, filters},
+    negotiate::{Format, HX_REQUEST_HEADER},
+    page::PageContext,
+};
+
+pub fn router() -> AppRouter {
+    Router::new()
+        // readable before registering, which accepts them
+        .route("/tos", get(tos_page))
+        .merge(
+            Router::new()
+                .route("/tos", post(accept_tos))
+                // only the user can accept them for themselves
+                .route_layer(middleware::from_fn(
+                    impersonation::forbid_while_impersonating,
+                ))
+                .route_layer(login_required!(Backend, login_url = "/login")),
+        )
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TosError {
+    #[error("The terms of service have changed, reload the page to read the new ones")]
+    OutdatedVersion,
+    #[error("An internal server error occured")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for TosError {
+    fn into_response(self) -> axum::response::Response {
+        let status_code = match self {
+            TosError::OutdatedVersion => StatusCode::CONFLICT,
+            TosError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+/// The answer of the JSON routes to users who haven't accepted the current
+/// terms, see [`require_accepted_tos`]
+pub fn tos_not_accepted() -> ApiError {
+    ApiError::new(
+        StatusCode::FORBIDDEN,
+        "tos_not_accepted",
+        "Accept the current terms of service on /tos to go on",
+    )
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "tos/tos.html")]
+struct TosTemplate {
+    page_context: PageContext,
+    locale: Locale,
+    tos_version: i32,
+    /// Whether the user is logged in and has to accept this version
+    needs_acceptance: bool,
+}
+
+async fn tos_page(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    page_context: PageContext,
+    locale: Locale,
+) -> TosTemplate {
+    let tos_version = api_context.config.application_settings.tos_version;
+    TosTemplate {
+        needs_acceptance: auth_session
+            .user
+            .is_some_and(|user| !user.accepted_tos(tos_version)),
+        page_context,
+        locale,
+        tos_version,
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct AcceptTosFormData {
+    /// The version the user read
+    version: i32,
+}
+
+async fn accept_tos(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    request: RequestMetadata,
+    Form(form_data): Form<AcceptTosFormData>,
+) -> Result<impl IntoResponse, TosError> {
+    let user = auth_session
+        .user
+        .ok_or_else(|| anyhow::anyhow!("Missing user in session"))?;
+    // they may have changed since the page was loaded
+    let tos_version = api_context.config.application_settings.tos_version;
+    if form_data.version != tos_version {
+        return Err(TosError::OutdatedVersion);
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE user_info SET tos_accepted_version = $2, tos_accepted_at = NOW()
+        WHERE user_id = $1
+        "#,
+        user.user_id(),
+        tos_version
+    )
+    .execute(&api_context.db)
+    .await
+    .context("Failed to record terms of service acceptance")?;
+    // the version is checked on the cached user
+    api_context.user_cache.invalidate(user.user_id()).await;
+
+    api_context.audit.record(
+        AuditEntry::new(AuditEvent::TosAccepted, Some(user.user_id()), &request)
+            .with_metadata(serde_json::json!({ "version": tos_version })),
+    );
+
+    Ok((StatusCode::OK, AppendHeaders([("HX-Redirect", "/")])))
+}
+
+/// Sends logged in users who haven't accepted the current terms to `/tos`,
+/// layered inside `login_required!` on the routes needing a login. JSON
+/// requests get [`tos_not_accepted`] instead, as do the `/api` routes, see
+/// [`crate::auth::ApiUser`].
+///
+/// Admins viewing as a user go on, so they can look around and return.
+pub async fn require_accepted_tos(
+    Extension(api_context): Extension<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    let tos_version = api_context.config.application_settings.tos_version;
+    if auth_session
+        .user
+        .as_ref()
+        .is_none_or(|user| user.accepted_tos(tos_version))
+    {
+        return next.run(request).await;
+    }
+    match impersonation::impersonator(&session).await {
+        Ok(Some(_)) => return next.run(request).await,
+        Ok(None) => {}
+        Err(e) => tracing::warn!(error = ?e, "Failed to check for impersonation"),
+    }
+
+    if request.headers().contains_key(HX_REQUEST_HEADER) {
+        return (StatusCode::OK, AppendHeaders([("HX-Redirect", "/tos")])).into_response();
+    }
+    match Format::from_headers(request.headers()) {
+        Format::Json => tos_not_accepted().into_response(),
+        Format::Html => Redirect::to("/tos").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use askama::Template;
+
+    use super::TosTemplate;
+    use crate::{i18n::Locale, page::PageContext};
+
+    fn tos_page(needs_acceptance: bool) -> String {
+        TosTemplate {
+            page_context: PageContext::default(),
+            locale: Locale::En,
+            tos_version: 3,
+            needs_acceptance,
+        }
+        .render()
+        .unwrap()
+    }
+
+    #[test]
+    fn only_users_who_need_to_accept_get_the_form() {
+        let html = tos_page(true);
+        assert!(html.contains("Version 3"));
+        assert!(html.contains(r#"<input type="hidden" name="version" value="3">"#));
+        assert!(!tos_page(false).contains("hx-post=\"/tos\""));
+    }
+}
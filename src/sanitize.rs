@@ -0,0 +1,207 @@
+//! The allowlists HTML built from user written content is cleaned with before
+//! a template marks it `safe`. Everything else is escaped by askama, and
+//! `|safe` is only allowed where `tests::SAFE_ALLOWLIST` says so.
+
+use std::{collections::HashSet, sync::LazyLock};
+
+/// Markup of descriptions, which are whole documents
+pub(crate) const MARKDOWN_TAGS: [&str; 12] = [
+    "p",
+    "br",
+    "em",
+    "strong",
+    "del",
+    "code",
+    "pre",
+    "blockquote",
+    "ul",
+    "ol",
+    "li",
+    "a",
+];
+
+/// Markup of todo titles, which are shown within a line of other content
+pub(crate) const INLINE_MARKDOWN_TAGS: [&str; 6] = ["br", "em", "strong", "del", "code", "a"];
+
+/// What's left of the HTML, from the most to the least markup
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    Markdown,
+    InlineMarkdown,
+    /// Text only, for places that can't hold markup, e.g. a link
+    Plain,
+}
+
+fn builder(tags: &[&'static str]) -> ammonia::Builder<'static> {
+    let mut builder = ammonia::Builder::empty();
+    builder
+        .tags(tags.iter().copied().collect())
+        .clean_content_tags(HashSet::from(["script", "style"]))
+        .url_schemes(HashSet::from(["http", "https", "mailto"]))
+        .link_rel(Some("noopener noreferrer nofollow"));
+    if tags.contains(&"a") {
+        builder.tag_attributes([("a", HashSet::from(["href"]))].into());
+    }
+    builder
+}
+
+static MARKDOWN: LazyLock<ammonia::Builder<'static>> = LazyLock::new(|| builder(&MARKDOWN_TAGS));
+static INLINE_MARKDOWN: LazyLock<ammonia::Builder<'static>> =
+    LazyLock::new(|| builder(&INLINE_MARKDOWN_TAGS));
+static PLAIN: LazyLock<ammonia::Builder<'static>> = LazyLock::new(|| builder(&[]));
+
+/// Drops the markup `profile` doesn't allow, keeping the text inside it.
+///
+/// The result is safe to embed in a page, text in it stays escaped.
+pub fn clean(html: &str, profile: Profile) -> String {
+    let builder = match profile {
+        Profile::Markdown => &MARKDOWN,
+        Profile::InlineMarkdown => &INLINE_MARKDOWN,
+        Profile::Plain => &PLAIN,
+    };
+    builder.clean(html).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::{Profile, clean};
+
+    /// Every `|safe` in the templates, with what it marks safe. Only output
+    /// of the `markdown` filters, which is cleaned here, and templates that
+    /// escaped their own fields belong in it.
+    const SAFE_ALLOWLIST: [(&str, &str); 7] = [
+        (
+            "todo/search.html",
+            "result.todo_content|plain_markdown|safe",
+        ),
+        ("todo/todo_description.html", "description|markdown|safe"),
+        (
+            "todo/todo_list.html",
+            "completed.todo_content|plain_markdown|safe",
+        ),
+        (
+            "todo/todo_detail.html",
+            "todo.todo_content|inline_markdown|safe",
+        ),
+        (
+            "todo/todo_row.html",
+            "todo.todo_content|inline_markdown|safe",
+        ),
+        ("todo/todos_template.html", "list|safe"),
+        ("todo/undo_toast.html", "todo_content|plain_markdown|safe"),
+    ];
+
+    fn templates(dir: &Path, found: &mut Vec<(String, String)>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                templates(&path, found);
+            } else {
+                found.push((
+                    path.to_string_lossy().into_owned(),
+                    fs::read_to_string(&path).unwrap(),
+                ));
+            }
+        }
+    }
+
+    /// Expressions of `source` that skip escaping, without whitespace control
+    fn unescaped(source: &str) -> Vec<String> {
+        let mut found = Vec::new();
+        for (open, close) in [("{{", "}}"), ("{%", "%}")] {
+            for part in source.split(open).skip(1) {
+                let expression = part.split(close).next().unwrap_or(part);
+                let expression = expression
+                    .trim_matches(|c: char| c.is_whitespace() || "-~+".contains(c))
+                    .replace(' ', "");
+                let is_safe = expression
+                    .split('|')
+                    .skip(1)
+                    .any(|filter| filter == "safe" || filter.starts_with("safe("));
+                if is_safe || expression.starts_with("filtersafe") {
+                    found.push(expression);
+                }
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn only_allowlisted_template_output_is_unescaped() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("templates");
+        let mut sources = Vec::new();
+        templates(&root, &mut sources);
+
+        let mut seen = Vec::new();
+        for (path, source) in sources {
+            let name = path
+                .strip_prefix(&format!("{}/", root.display()))
+                .unwrap()
+                .to_string();
+            for expression in unescaped(&source) {
+                assert!(
+                    SAFE_ALLOWLIST.contains(&(name.as_str(), expression.as_str())),
+                    "`{expression}` in {name} isn't escaped, clean it with a markdown filter \
+                     or add it to SAFE_ALLOWLIST"
+                );
+                seen.push((name.clone(), expression));
+            }
+        }
+        for (name, expression) in SAFE_ALLOWLIST {
+            assert!(
+                seen.iter()
+                    .any(|seen| seen.0 == name && seen.1 == expression),
+                "`{expression}` is no longer in {name}, remove it from SAFE_ALLOWLIST"
+            );
+        }
+    }
+
+    #[test]
+    fn unescaped_expressions_are_found() {
+        assert_eq!(
+            vec!["a|safe", "b|upper|safe", "filtersafe"],
+            unescaped(
+                "{{ a|safe }} {{- b | upper | safe -}} {{ c }} {% filter safe %}x{% endfilter %}"
+            )
+        );
+        assert!(unescaped("{{ safe }} {{ a|unsafe_thing }}").is_empty());
+    }
+
+    #[test]
+    fn profiles_keep_less_and_less_markup() {
+        let html = "<p><strong>buy</strong> <a href=\"https://example.com\">milk</a></p>\n\
+                    <ul>\n<li>now</li>\n</ul>\n";
+        assert_eq!(
+            "<p><strong>buy</strong> <a href=\"https://example.com\" rel=\"noopener noreferrer nofollow\">milk</a></p>\n\
+             <ul>\n<li>now</li>\n</ul>\n",
+            clean(html, Profile::Markdown)
+        );
+        assert_eq!(
+            "<strong>buy</strong> <a href=\"https://example.com\" rel=\"noopener noreferrer nofollow\">milk</a>\n\nnow\n\n",
+            clean(html, Profile::InlineMarkdown)
+        );
+        assert_eq!("buy milk\n\nnow\n\n", clean(html, Profile::Plain));
+    }
+
+    #[test]
+    fn text_stays_escaped_and_scripts_are_dropped() {
+        for profile in [Profile::Markdown, Profile::InlineMarkdown, Profile::Plain] {
+            assert_eq!(
+                "&lt;img src=x onerror=alert(1)&gt; &amp; ",
+                clean(
+                    "&lt;img src=x onerror=alert(1)&gt; &amp; <script>alert(1)</script>",
+                    profile
+                )
+            );
+        }
+        assert_eq!(
+            "<a rel=\"noopener noreferrer nofollow\">x</a>",
+            clean(
+                "<a href=\"javascript:alert(1)\" onclick=\"alert(1)\">x</a>",
+                Profile::InlineMarkdown
+            )
+        );
+    }
+}
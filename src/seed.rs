@@ -0,0 +1,235 @@
+//! Fills a development database with users and todos, through the same
+//! domain types and repos as the routes, so seeded data is always data the
+//! app could have stored itself
+
+use anyhow::Context;
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Hasher, PgUserRepo, RegisterError, Role, UserRepo, create_user},
+    config::{AppEnv, SeedArgs},
+    domain::{priority::Priority, tag::Tags, todo_content::TodoContent, username::Username},
+    routes::todo::{
+        list::ListRole,
+        repo::{NewTodoRow, PgTodoRepo, TodoRepo, TodoUpdate},
+    },
+};
+
+/// Every seeded user has this password
+pub const SEED_PASSWORD: &str = "seed password 123";
+
+const SEED_USERNAME_PREFIX: &str = "seed-user-";
+
+const CONTENTS: [&str; 8] = [
+    "Buy milk",
+    "Call the dentist about the appointment",
+    "Réserver le train pour Lyon",
+    "Geburtstagsgeschenk für Oma besorgen",
+    "買い物リストを作る 🛒",
+    "Fix the leaking tap 🔧💧",
+    "Прочитать книгу",
+    "Plan the trip 👩\u{200d}👩\u{200d}👧 🇵🇹",
+];
+
+const TAGS: [&str; 5] = [
+    "",
+    "home",
+    "work, urgent",
+    "errands",
+    "home, errands, weekend",
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum SeedError {
+    #[error("Refusing to seed a production database")]
+    Production,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// What [`seed`] did
+#[derive(Debug, Default)]
+pub struct SeedSummary {
+    pub users_created: u32,
+    /// Users that already existed, their todos are left as they are
+    pub users_skipped: u32,
+    pub todos_created: u32,
+}
+
+/// Refuses production, so seeding can be refused before connecting to its
+/// database
+pub fn check_app_env(app_env: AppEnv) -> Result<(), SeedError> {
+    if app_env == AppEnv::Production {
+        return Err(SeedError::Production);
+    }
+    Ok(())
+}
+
+/// Creates `args.users` users named `seed-user-1` and so on, each with
+/// `args.todos_per_user` todos in their own list, and shares the lists of
+/// the first users with each other. Users that already exist are skipped,
+/// unless `args.reset` deletes them first.
+pub async fn seed(
+    db: &PgPool,
+    hasher: &Hasher,
+    app_env: AppEnv,
+    args: &SeedArgs,
+) -> Result<SeedSummary, SeedError> {
+    check_app_env(app_env)?;
+
+    if args.reset {
+        // usernames compare case-insensitively, which prefix searches don't support
+        sqlx::query!(
+            r#"DELETE FROM user_info WHERE starts_with(username COLLATE "C", $1)"#,
+            SEED_USERNAME_PREFIX
+        )
+        .execute(db)
+        .await
+        .context("Failed to delete seeded users")?;
+    }
+
+    let users = PgUserRepo::new(db.clone());
+    let todos = PgTodoRepo::new(db.clone());
+    let mut summary = SeedSummary::default();
+    let mut user_ids = Vec::new();
+
+    for n in 1..=args.users {
+        let username = format!("{SEED_USERNAME_PREFIX}{n}");
+        let result = create_user(
+            db,
+            hasher,
+            &format!("{username}@example.com"),
+            &username,
+            SEED_PASSWORD,
+            Role::User,
+        )
+        .await;
+        let user_id = match result {
+            Ok(user_id) => user_id,
+            Err(RegisterError::UsernameExists) => {
+                summary.users_skipped += 1;
+                user_ids.push(existing_user_id(&users, &username).await?);
+                continue;
+            }
+            Err(e) => {
+                return Err(anyhow::Error::from(e)
+                    .context(format!("Failed to create {username}"))
+                    .into());
+            }
+        };
+        summary.users_created += 1;
+        user_ids.push(user_id);
+
+        for i in 0..args.todos_per_user {
+            seed_todo(&todos, user_id, i).await?;
+            summary.todos_created += 1;
+        }
+    }
+
+    // the first user edits the second's list, who only sees the first's
+    if let [first, second, ..] = user_ids[..] {
+        let first_list = todos.own_list_id(first).await?;
+        let second_list = todos.own_list_id(second).await?;
+        todos
+            .add_member(second_list, first, ListRole::Editor)
+            .await?;
+        todos
+            .add_member(first_list, second, ListRole::Viewer)
+            .await?;
+    }
+
+    Ok(summary)
+}
+
+async fn existing_user_id(users: &PgUserRepo, username: &str) -> Result<Uuid, anyhow::Error> {
+    let username = Username::parse(username).context("Invalid seed username")?;
+    let user = users
+        .find_by_username(&username)
+        .await?
+        .with_context(|| format!("{username} exists but can't be found, is it locked?"))?;
+    Ok(user.user_id())
+}
+
+/// The `i`th todo of a user, varied by `i` so every list has a spread of
+/// contents, tags, priorities, due dates and completed todos
+async fn seed_todo(todos: &PgTodoRepo, user_id: Uuid, i: u32) -> Result<(), anyhow::Error> {
+    let i = i as usize;
+    let content = if i % 10 == 9 {
+        long_content(i)
+    } else {
+        format!("{} #{}", CONTENTS[i % CONTENTS.len()], i + 1)
+    };
+    // some overdue, some due today, some later and some never
+    let due_date =
+        (i % 4 != 3).then(|| OffsetDateTime::now_utc().date() + Duration::days(i as i64 % 7 - 3));
+
+    let new_todo = NewTodoRow {
+        list_id: todos.own_list_id(user_id).await?,
+        todo_content: TodoContent::parse(&content).context("Invalid seed todo content")?,
+        due_date,
+        priority: Priority::ALL[i % Priority::ALL.len()],
+        tags: Tags::parse(TAGS[i % TAGS.len()]).context("Invalid seed tags")?,
+    };
+    let list_id = new_todo.list_id;
+    // seeding as many todos as asked for, whatever the quota
+    let todo = todos.create(user_id, new_todo, i64::MAX, 0.0).await?.todo;
+
+    if i.is_multiple_of(3) {
+        let update = TodoUpdate {
+            is_completed: Some(true),
+            priority: None,
+            version: todo.version,
+        };
+        todos.update(todo.todo_id, list_id, user_id, update).await?;
+    }
+    Ok(())
+}
+
+/// Several hundred graphemes of mixed scripts, emoji and combining marks,
+/// to check how the pages cope with long todos
+fn long_content(i: usize) -> String {
+    let paragraph = "Lorem ipsum dolor sit amet, 日本語のテキスト, ελληνικά, \
+                     e\u{301}te\u{301} 👩\u{200d}💻🇫🇷👍🏽. ";
+    format!("#{} {}", i + 1, paragraph.repeat(8).trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_matches;
+    use sqlx::postgres::PgPoolOptions;
+
+    use super::{SeedError, long_content, seed};
+    use crate::{
+        auth::Hasher,
+        config::{AppEnv, Config, SeedArgs},
+        domain::todo_content::TodoContent,
+    };
+
+    #[tokio::test]
+    async fn seeding_production_is_refused() {
+        let config = Config::for_tests(&[
+            "--hmac-key=0123456789012345678901234567890123456789012345678901234567890123",
+        ]);
+        let hasher = Hasher::from_settings(&config.application_settings).unwrap();
+        // never connects, production is refused before any query
+        let db = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/site")
+            .unwrap();
+        let args = SeedArgs {
+            users: 1,
+            todos_per_user: 1,
+            reset: true,
+        };
+
+        let result = seed(&db, &hasher, AppEnv::Production, &args).await;
+
+        assert_matches!(result, Err(SeedError::Production));
+    }
+
+    #[test]
+    fn long_content_is_valid_todo_content() {
+        assert!(TodoContent::parse(&long_content(999)).is_ok());
+    }
+}
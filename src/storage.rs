@@ -0,0 +1,213 @@
+use std::{
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use aws_sdk_s3::{
+    Client,
+    config::{Credentials, Region},
+    operation::get_object::GetObjectError,
+    primitives::ByteStream,
+};
+use secrecy::ExposeSecret;
+
+use crate::config::{StorageBackend, StorageSettings};
+
+/// Where uploaded files are kept.
+///
+/// Keys are `/` separated relative paths like `avatars/ab/ab12….png`. Writing
+/// an existing key replaces the file and deleting a missing one is not an
+/// error.
+#[async_trait]
+pub trait FileStore: Send + Sync {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str)
+    -> Result<(), anyhow::Error>;
+
+    /// `None` if nothing is stored under the key
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, anyhow::Error>;
+
+    async fn delete(&self, key: &str) -> Result<(), anyhow::Error>;
+}
+
+/// Builds the backend selected in the config
+pub fn from_settings(settings: &StorageSettings) -> Box<dyn FileStore> {
+    match settings.storage_backend {
+        StorageBackend::Local => Box::new(LocalFileStore::new(settings.upload_dir.clone())),
+        StorageBackend::S3 => Box::new(S3FileStore::new(settings)),
+    }
+}
+
+/// Stores files in a directory on the local disk
+#[derive(Debug, Clone)]
+pub struct LocalFileStore {
+    root: PathBuf,
+}
+
+impl LocalFileStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, anyhow::Error> {
+        // keys come from our own code, but a key escaping the root would be
+        // bad enough to check anyway
+        let key = Path::new(key);
+        if key.as_os_str().is_empty()
+            || !key
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            anyhow::bail!("Invalid file key {key:?}");
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl FileStore for LocalFileStore {
+    async fn put(
+        &self,
+        key: &str,
+        bytes: Vec<u8>,
+        _content_type: &str,
+    ) -> Result<(), anyhow::Error> {
+        let path = self.path(key)?;
+        let dir = path.parent().context("File path has no parent")?;
+        tokio::fs::create_dir_all(dir)
+            .await
+            .context("Failed to create upload directory")?;
+
+        // written under a temporary name first so a crash never leaves a
+        // truncated file behind the final name
+        let temp_path = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+        tokio::fs::write(&temp_path, bytes)
+            .await
+            .context("Failed to write file")?;
+        tokio::fs::rename(&temp_path, &path)
+            .await
+            .context("Failed to move file into place")?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(anyhow::Error::from(e).context("Failed to read file")),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), anyhow::Error> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(anyhow::Error::from(e).context("Failed to delete file")),
+        }
+    }
+}
+
+/// Stores files in an S3 bucket, or anything speaking its API like MinIO
+#[derive(Debug, Clone)]
+pub struct S3FileStore {
+    client: Client,
+    bucket: String,
+}
+
+impl S3FileStore {
+    pub fn new(settings: &StorageSettings) -> Self {
+        let credentials = Credentials::new(
+            settings
+                .storage_access_key_id
+                .clone()
+                .expect("Missing storage access key id"),
+            settings
+                .storage_secret_access_key
+                .as_ref()
+                .expect("Missing storage secret access key")
+                .expose_secret(),
+            None,
+            None,
+            "config",
+        );
+
+        let mut config = aws_sdk_s3::config::Builder::new()
+            .region(Region::new(settings.storage_region.clone()))
+            .credentials_provider(credentials);
+        if let Some(endpoint) = &settings.storage_endpoint {
+            // self hosted servers usually don't have a DNS entry per bucket
+            config = config.endpoint_url(endpoint).force_path_style(true);
+        }
+
+        Self {
+            client: Client::from_conf(config.build()),
+            bucket: settings
+                .storage_bucket
+                .clone()
+                .expect("Missing storage bucket"),
+        }
+    }
+}
+
+#[async_trait]
+impl FileStore for S3FileStore {
+    async fn put(
+        &self,
+        key: &str,
+        bytes: Vec<u8>,
+        content_type: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .body(ByteStream::from(bytes))
+            .send()
+            .await
+            .context("Failed to upload file")?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let result = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
+        let output = match result {
+            Ok(output) => output,
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(GetObjectError::is_no_such_key) =>
+            {
+                return Ok(None);
+            }
+            Err(e) => return Err(anyhow::Error::from(e).context("Failed to download file")),
+        };
+
+        let bytes = output
+            .body
+            .collect()
+            .await
+            .context("Failed to read downloaded file")?;
+        Ok(Some(bytes.to_vec()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), anyhow::Error> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .context("Failed to delete file")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{Span, Subscriber, field::Empty};
+use tracing_subscriber::{EnvFilter, Layer, Registry, fmt::MakeWriter, layer::SubscriberExt};
+use uuid::Uuid;
+
+#[cfg(not(feature = "otel"))]
+use crate::config::ApplicationSettings;
+use crate::{auth::AuthSession, config::AppEnv};
+
+#[cfg(feature = "otel")]
+mod otel;
+#[cfg(feature = "otel")]
+pub use otel::TraceExport;
+
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Built without the `otel` feature, traces can't be exported
+#[cfg(not(feature = "otel"))]
+pub enum TraceExport {}
+
+#[cfg(not(feature = "otel"))]
+impl TraceExport {
+    pub fn from_settings(settings: &ApplicationSettings) -> Result<Option<Self>, anyhow::Error> {
+        anyhow::ensure!(
+            settings.otel_endpoint.is_none(),
+            "OTEL_ENDPOINT is set, but the app was built without the otel feature"
+        );
+        Ok(None)
+    }
+
+    pub fn layer(&self) -> BoxedLayer {
+        match *self {}
+    }
+
+    pub fn shutdown(self) {
+        match self {}
+    }
+}
+
+/// Log output for the environment: readable lines when developing, JSON lines
+/// for the log collector everywhere else. `filter` takes `RUST_LOG` directives
+/// and applies to `export` as well.
+pub fn subscriber<W>(
+    app_env: AppEnv,
+    filter: &str,
+    writer: W,
+    export: Option<BoxedLayer>,
+) -> Result<Box<dyn Subscriber + Send + Sync>, anyhow::Error>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::try_new(filter)?;
+    let registry = tracing_subscriber::registry().with(export).with(filter);
+
+    Ok(match app_env {
+        AppEnv::Development => Box::new(
+            registry.with(
+                tracing_subscriber::fmt::layer()
+                    .pretty()
+                    .with_writer(writer),
+            ),
+        ),
+        AppEnv::Staging | AppEnv::Production => Box::new(
+            registry.with(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    // the request span carries the request id and user id
+                    .with_current_span(true)
+                    .with_span_list(false)
+                    .with_writer(writer),
+            ),
+        ),
+    })
+}
+
+/// Id of a request, to find everything it logged or reported
+#[derive(Debug, Clone, Copy)]
+pub struct RequestId(pub Uuid);
+
+/// Gives the request its [`RequestId`], needs to wrap the layers using it
+pub async fn assign_request_id(mut request: Request, next: Next) -> Response {
+    request.extensions_mut().insert(RequestId(Uuid::new_v4()));
+    next.run(request).await
+}
+
+/// The template of the route the request matched, like
+/// `/api/v1/todos/{todo_id}`, so requests can be grouped by it without an
+/// entry per id
+pub fn route(request: &Request) -> &str {
+    request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or("unmatched")
+}
+
+/// Span of a request, see [`crate::app::Application::build`]
+pub fn request_span(request: &Request) -> Span {
+    let route = route(request);
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .map_or_else(Uuid::new_v4, |request_id| request_id.0);
+
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        route,
+        %request_id,
+        user_id = Empty,
+        otel.name = format!("{} {route}", request.method()),
+        otel.kind = "server",
+    );
+    #[cfg(feature = "otel")]
+    otel::set_remote_parent(&span, request.headers());
+    span
+}
+
+/// Span around a database query, so exported traces show where the time of a
+/// request went. `operation` names it, like `SELECT todo`.
+pub fn query_span(operation: &'static str) -> Span {
+    tracing::info_span!(
+        "query",
+        otel.name = operation,
+        otel.kind = "client",
+        db.system = "postgresql",
+    )
+}
+
+/// Adds the user to the span of the request being handled
+pub fn record_user_id(user_id: Uuid) {
+    Span::current().record("user_id", tracing::field::display(user_id));
+}
+
+/// Records the user of the session, users of bearer tokens are recorded by
+/// [`crate::auth::ApiUser`]
+pub async fn record_session_user(
+    auth_session: AuthSession,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(user) = &auth_session.user {
+        record_user_id(user.user_id());
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{Router, body::Body, extract::Request, routing::get};
+    use tower::ServiceExt;
+    use tower_http::trace::TraceLayer;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use crate::{
+        config::AppEnv,
+        telemetry::{request_span, subscriber},
+    };
+
+    /// Collects everything logged, to look at once the subscriber is done
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl CapturedLogs {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    impl Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for CapturedLogs {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn log_in_request(app_env: AppEnv) -> Vec<String> {
+        let logs = CapturedLogs::default();
+        let subscriber = subscriber(app_env, "info", logs.clone(), None).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "request",
+                request_id = "abc",
+                user_id = tracing::field::Empty
+            );
+            let _entered = span.enter();
+            super::record_user_id(uuid::Uuid::nil());
+            tracing::info!(todo_id = 7, "Todo created");
+        });
+        logs.lines()
+    }
+
+    #[test]
+    pub fn production_logs_are_json_lines() {
+        for app_env in [AppEnv::Staging, AppEnv::Production] {
+            let lines = log_in_request(app_env);
+            assert_eq!(1, lines.len());
+
+            let line: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+            assert!(line["timestamp"].is_string());
+            assert_eq!("INFO", line["level"]);
+            assert_eq!("site::telemetry::tests", line["target"]);
+            assert_eq!("Todo created", line["fields"]["message"]);
+            assert_eq!(7, line["fields"]["todo_id"]);
+            assert_eq!("abc", line["span"]["request_id"]);
+            assert_eq!(uuid::Uuid::nil().to_string(), line["span"]["user_id"]);
+        }
+    }
+
+    #[test]
+    pub fn development_logs_are_readable() {
+        let lines = log_in_request(AppEnv::Development);
+
+        assert!(
+            lines
+                .iter()
+                .all(|line| serde_json::from_str::<serde_json::Value>(line).is_err())
+        );
+        let logs = lines.join("\n");
+        assert!(logs.contains("Todo created"));
+        assert!(logs.contains("request_id"));
+    }
+
+    #[test]
+    pub fn invalid_filters_are_rejected() {
+        assert!(subscriber(AppEnv::Production, "site=loud", std::io::stdout, None).is_err());
+    }
+
+    #[tokio::test]
+    pub async fn request_spans_have_the_route_template() {
+        let logs = CapturedLogs::default();
+        let subscriber = subscriber(AppEnv::Production, "info", logs.clone(), None).unwrap();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route(
+                "/todos/{todo_id}",
+                get(|| async { tracing::info!("Handled") }),
+            )
+            .layer(TraceLayer::new_for_http().make_span_with(request_span));
+        let request = Request::get(format!("/todos/{}", uuid::Uuid::new_v4()))
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap();
+
+        let lines = logs.lines();
+        let line: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!("Handled", line["fields"]["message"]);
+        assert_eq!("/todos/{todo_id}", line["span"]["route"]);
+        assert_eq!("GET", line["span"]["method"]);
+    }
+}
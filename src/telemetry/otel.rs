@@ -0,0 +1,162 @@
+use anyhow::Context;
+use http::HeaderMap;
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    Resource,
+    propagation::TraceContextPropagator,
+    trace::{SdkTracerProvider, TracerProviderBuilder},
+};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{config::ApplicationSettings, telemetry::BoxedLayer};
+
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Sends spans to an OpenTelemetry collector over OTLP/HTTP, in batches from
+/// a thread of its own. [`TraceExport::shutdown`] sends what is left.
+pub struct TraceExport {
+    provider: SdkTracerProvider,
+}
+
+impl TraceExport {
+    /// `None` unless `OTEL_ENDPOINT` is set
+    pub fn from_settings(settings: &ApplicationSettings) -> Result<Option<Self>, anyhow::Error> {
+        let Some(endpoint) = &settings.otel_endpoint else {
+            return Ok(None);
+        };
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .context("Failed to create OTLP exporter")?;
+
+        Ok(Some(Self::new(
+            SdkTracerProvider::builder().with_batch_exporter(exporter),
+        )))
+    }
+
+    fn new(builder: TracerProviderBuilder) -> Self {
+        // incoming `traceparent` headers continue the caller's trace
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = builder
+            .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+            .build();
+        Self { provider }
+    }
+
+    /// Layer turning the spans of the app into OpenTelemetry ones
+    pub fn layer(&self) -> BoxedLayer {
+        Box::new(tracing_opentelemetry::layer().with_tracer(self.provider.tracer(SERVICE_NAME)))
+    }
+
+    pub fn shutdown(self) {
+        if let Err(e) = self.provider.shutdown() {
+            tracing::warn!(error = ?e, "Failed to flush traces");
+        }
+    }
+}
+
+/// Makes `span` a child of the span in the request's `traceparent` header
+pub fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+    let parent =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    // only fails when traces aren't exported, then there is nothing to connect
+    let _ = span.set_parent(parent);
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{Router, body::Body, extract::Request, routing::get};
+    use opentelemetry::trace::SpanId;
+    use opentelemetry_sdk::{
+        error::OTelSdkResult,
+        trace::{SdkTracerProvider, SpanData},
+    };
+    use tower::ServiceExt;
+    use tower_http::trace::TraceLayer;
+    use tracing::Instrument;
+
+    use crate::{
+        config::AppEnv,
+        telemetry::{TraceExport, query_span, request_span, subscriber},
+    };
+
+    /// Stands in for the collector, keeping every span it is sent
+    #[derive(Debug, Clone, Default)]
+    struct Collector(Arc<Mutex<Vec<SpanData>>>);
+
+    impl opentelemetry_sdk::trace::SpanExporter for Collector {
+        async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+            self.0.lock().unwrap().extend(batch);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    pub async fn request_and_query_spans_are_exported() {
+        let collector = Collector::default();
+        let export =
+            TraceExport::new(SdkTracerProvider::builder().with_simple_exporter(collector.clone()));
+        let subscriber = subscriber(
+            AppEnv::Production,
+            "info",
+            std::io::sink,
+            Some(export.layer()),
+        )
+        .unwrap();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route(
+                "/todos/{todo_id}",
+                get(|| async { async {}.instrument(query_span("SELECT todo")).await }),
+            )
+            .layer(TraceLayer::new_for_http().make_span_with(request_span));
+        let request = Request::get(format!("/todos/{}", uuid::Uuid::new_v4()))
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap();
+        export.shutdown();
+
+        let spans = collector.0.lock().unwrap().clone();
+        let request = spans
+            .iter()
+            .find(|span| span.name == "GET /todos/{todo_id}")
+            .expect("No request span");
+        let query = spans
+            .iter()
+            .find(|span| span.name == "SELECT todo")
+            .expect("No query span");
+
+        // the caller's trace goes on
+        assert_eq!(
+            "4bf92f3577b34da6a3ce929d0e0e4736",
+            request.span_context.trace_id().to_string()
+        );
+        assert_eq!(
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            request.parent_span_id
+        );
+        assert_eq!(request.span_context.span_id(), query.parent_span_id);
+    }
+}
@@ -0,0 +1,222 @@
+//! Light and dark pages. The theme is kept in a cookie, so the base template
+//! can put its class on `<html>` before the first paint without looking the
+//! user up, and in the preferences of users, so it follows them to other
+//! browsers.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Form,
+    extract::State,
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+};
+use cookie::{Cookie, SameSite};
+use http::{HeaderMap, HeaderValue, StatusCode, header};
+
+use crate::{app::ApiContext, auth::AuthSession, config::AppEnv, negotiate::HX_REQUEST_HEADER};
+
+pub const THEME_COOKIE: &str = "theme";
+
+/// About as long as browsers keep a cookie
+const THEME_COOKIE_MAX_AGE: cookie::time::Duration = cookie::time::Duration::days(400);
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "theme", rename_all = "lowercase")]
+pub enum Theme {
+    /// Light or dark, whichever the operating system is set to
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::System, Theme::Light, Theme::Dark];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Theme::ALL.into_iter().find(|theme| theme.as_str() == s)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Theme::System => "system",
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+        }
+    }
+
+    /// Class of the `<html>` element, see `assets/css/theme.css`
+    pub fn class(self) -> &'static str {
+        match self {
+            Theme::System => "theme-system",
+            Theme::Light => "theme-light",
+            Theme::Dark => "theme-dark",
+        }
+    }
+
+    /// The theme in the request's cookie, if it has a valid one
+    pub fn from_cookie(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(Cookie::split_parse)
+            .filter_map(Result::ok)
+            .find(|cookie| cookie.name() == THEME_COOKIE)
+            .and_then(|cookie| Theme::parse(cookie.value()))
+    }
+
+    /// `Set-Cookie` value remembering the theme in the browser
+    pub fn cookie(self, app_env: AppEnv) -> HeaderValue {
+        let cookie = Cookie::build((THEME_COOKIE, self.as_str()))
+            .path("/")
+            .max_age(THEME_COOKIE_MAX_AGE)
+            .same_site(SameSite::Lax)
+            .http_only(true)
+            .secure(app_env == AppEnv::Production || app_env == AppEnv::Staging)
+            .build();
+        HeaderValue::from_str(&cookie.to_string()).expect("Theme cookie is a valid header value")
+    }
+}
+
+impl std::fmt::Display for Theme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ThemeFormData {
+    theme: String,
+}
+
+/// Switches the theme of the browser, and of the user if someone is logged
+/// in, then reloads the page so `<html>` gets the new class
+pub async fn switch_theme(
+    State(api_context): State<Arc<ApiContext>>,
+    auth_session: AuthSession,
+    headers: HeaderMap,
+    Form(form_data): Form<ThemeFormData>,
+) -> Response {
+    let Some(theme) = Theme::parse(&form_data.theme) else {
+        return (StatusCode::BAD_REQUEST, "Unknown theme").into_response();
+    };
+
+    if let Some(user) = auth_session.user
+        && let Err(e) = save_theme(&api_context, user.user_id(), theme).await
+    {
+        tracing::error!(error = ?e, "Failed to save theme");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let cookie = (
+        header::SET_COOKIE,
+        theme.cookie(api_context.config.application_settings.app_env),
+    );
+    if headers.contains_key(HX_REQUEST_HEADER) {
+        // htmx only swaps the body, the class of `<html>` needs a reload
+        return (
+            StatusCode::NO_CONTENT,
+            AppendHeaders([cookie]),
+            AppendHeaders([("HX-Refresh", "true")]),
+        )
+            .into_response();
+    }
+    (AppendHeaders([cookie]), Redirect::to(&back_to(&headers))).into_response()
+}
+
+async fn save_theme(
+    api_context: &ApiContext,
+    user_id: uuid::Uuid,
+    theme: Theme,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO user_preferences (user_id, theme) VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE SET theme = EXCLUDED.theme
+        "#,
+        user_id,
+        theme as Theme
+    )
+    .execute(&api_context.db)
+    .await
+    .context("Failed to save theme")?;
+    api_context.preferences.invalidate(user_id).await;
+    Ok(())
+}
+
+/// The page the switch was on, going by the path of the `Referer` only so
+/// it can't send anyone to another site
+fn back_to(headers: &HeaderMap) -> String {
+    headers
+        .get(header::REFERER)
+        .and_then(|referer| referer.to_str().ok())
+        .and_then(|referer| referer.parse::<http::Uri>().ok())
+        .and_then(|uri| uri.path_and_query().map(|path| path.to_string()))
+        .filter(|path| path.starts_with('/') && !path.starts_with("//"))
+        .unwrap_or_else(|| "/".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, HeaderValue, header};
+
+    use super::{Theme, back_to};
+    use crate::config::AppEnv;
+
+    fn with_header(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn theme_is_read_from_its_cookie() {
+        for (cookies, expected) in [
+            ("theme=dark", Some(Theme::Dark)),
+            ("id=abc; theme=light; other=1", Some(Theme::Light)),
+            ("theme=system", Some(Theme::System)),
+            ("theme=purple", None),
+            ("themes=dark", None),
+            ("", None),
+        ] {
+            assert_eq!(
+                expected,
+                Theme::from_cookie(&with_header(header::COOKIE, cookies)),
+                "{cookies}"
+            );
+        }
+        assert_eq!(None, Theme::from_cookie(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn theme_cookie_outlives_the_session() {
+        let cookie = Theme::Dark.cookie(AppEnv::Production);
+        let cookie = cookie.to_str().unwrap();
+        assert!(cookie.starts_with("theme=dark;"));
+        assert!(cookie.contains("Max-Age=34560000"));
+        assert!(cookie.contains("Path=/"));
+        assert!(cookie.contains("Secure"));
+    }
+
+    #[test]
+    fn switching_goes_back_to_the_same_site_only() {
+        for (referer, expected) in [
+            ("https://example.com/todo?page=2", "/todo?page=2"),
+            ("/settings", "/settings"),
+            ("https://evil.example//evil.example/", "/"),
+            ("not a url", "/"),
+        ] {
+            assert_eq!(
+                expected,
+                back_to(&with_header(header::REFERER, referer)),
+                "{referer}"
+            );
+        }
+        assert_eq!("/", back_to(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,197 @@
+//! Short notifications shown by the pages after an htmx request, sent as an
+//! `HX-Trigger` event the base template listens for
+
+use axum::{
+    body::Body,
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{HeaderValue, StatusCode, header};
+use serde_json::{Map, Value};
+
+use crate::negotiate::HX_REQUEST_HEADER;
+
+const HX_TRIGGER_HEADER: &str = "HX-Trigger";
+const HX_RETARGET_HEADER: &str = "HX-Retarget";
+
+/// Name of the event in `HX-Trigger`, see `assets/js/toast.js`
+const TOAST_EVENT: &str = "toast";
+
+/// Error bodies larger than this aren't a message for a toast
+const MAX_ERROR_BODY_BYTES: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToastLevel {
+    Success,
+    Error,
+}
+
+/// Adds a toast to the response, e.g. `{"toast": {"level": "success", "message": "Todo added"}}`,
+/// keeping the events already in its `HX-Trigger`
+pub fn with_toast(
+    response: impl IntoResponse,
+    level: ToastLevel,
+    message: impl Into<String>,
+) -> Response {
+    let mut response = response.into_response();
+    let mut events = response
+        .headers()
+        .get(HX_TRIGGER_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(trigger_events)
+        .unwrap_or_default();
+    events.insert(
+        TOAST_EVENT.to_string(),
+        serde_json::json!({ "level": level, "message": message.into() }),
+    );
+
+    let value = ascii_json(&Value::Object(events));
+    match HeaderValue::from_str(&value) {
+        Ok(value) => {
+            response.headers_mut().insert(HX_TRIGGER_HEADER, value);
+        }
+        Err(e) => tracing::warn!(error = ?e, "Toast is not a valid header value"),
+    }
+    response
+}
+
+/// JSON with everything but ASCII escaped, as header values can't hold
+/// anything else, e.g. a username with accents in a message
+fn ascii_json(value: &Value) -> String {
+    let mut json = String::new();
+    for c in value.to_string().chars() {
+        if c.is_ascii() {
+            json.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                json.push_str(&format!("\\u{unit:04x}"));
+            }
+        }
+    }
+    json
+}
+
+/// The events of an `HX-Trigger` value, which is either a JSON object or a
+/// comma separated list of event names
+fn trigger_events(value: &str) -> Map<String, Value> {
+    match serde_json::from_str(value) {
+        Ok(Value::Object(events)) => events,
+        _ => value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| (name.to_string(), Value::Null))
+            .collect(),
+    }
+}
+
+fn has_toast(response: &Response) -> bool {
+    response
+        .headers()
+        .get(HX_TRIGGER_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| trigger_events(value).contains_key(TOAST_EVENT))
+}
+
+/// Turns the failures of htmx requests the user can do something about into
+/// error toasts, so they are shown even where the page has no place for
+/// them. Errors about form fields are left to the form, which shows them
+/// next to the field, as are errors sent to another target with
+/// `HX-Retarget`, which has a place for them.
+pub async fn error_toasts(request: Request, next: Next) -> Response {
+    let is_htmx = request.headers().contains_key(HX_REQUEST_HEADER);
+    let response = next.run(request).await;
+    let status = response.status();
+    let recoverable = status.is_client_error() || status == StatusCode::SERVICE_UNAVAILABLE;
+    let retargeted = response.headers().contains_key(HX_RETARGET_HEADER);
+    if !is_htmx || !recoverable || retargeted || has_toast(&response) {
+        return response;
+    }
+
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let is_json = content_type.starts_with("application/json");
+    if !is_json && !content_type.starts_with("text/plain") {
+        let message = status.canonical_reason().unwrap_or("Something went wrong");
+        return with_toast(response, ToastLevel::Error, message);
+    }
+
+    // the message is in the body, which is put back once read
+    let (parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, MAX_ERROR_BODY_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!(error = ?e, "Failed to read error body");
+            return status.into_response();
+        }
+    };
+    let message = if is_json {
+        let error: Value = serde_json::from_slice(&bytes).unwrap_or_default();
+        if error.get("field").is_some() || error.get("errors").is_some() {
+            return Response::from_parts(parts, Body::from(bytes));
+        }
+        error["message"].as_str().map(str::to_string)
+    } else {
+        Some(String::from_utf8_lossy(&bytes).trim().to_string()).filter(|text| !text.is_empty())
+    };
+    let message = message
+        .or(status.canonical_reason().map(str::to_string))
+        .unwrap_or_else(|| "Something went wrong".to_string());
+    with_toast(
+        Response::from_parts(parts, Body::from(bytes)),
+        ToastLevel::Error,
+        message,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::{AppendHeaders, IntoResponse};
+    use http::StatusCode;
+    use serde_json::Value;
+
+    use super::{ToastLevel, with_toast};
+
+    fn trigger(response: &axum::response::Response) -> Value {
+        serde_json::from_str(response.headers()["HX-Trigger"].to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn toast_is_sent_as_an_event() {
+        let response = with_toast(StatusCode::OK, ToastLevel::Success, "Todo added");
+
+        assert_eq!(
+            serde_json::json!({ "toast": { "level": "success", "message": "Todo added" } }),
+            trigger(&response)
+        );
+    }
+
+    #[test]
+    fn other_events_are_kept() {
+        let counts = AppendHeaders([("HX-Trigger", r#"{"todoCounts":{"active":1}}"#)]);
+        let response = with_toast(counts.into_response(), ToastLevel::Error, "Oops");
+        assert_eq!(1, trigger(&response)["todoCounts"]["active"]);
+        assert_eq!("error", trigger(&response)["toast"]["level"]);
+
+        let names = AppendHeaders([("HX-Trigger", "listChanged, saved")]);
+        let response = with_toast(names.into_response(), ToastLevel::Success, "Saved");
+        assert_eq!(Value::Null, trigger(&response)["listChanged"]);
+        assert!(trigger(&response).get("saved").is_some());
+        assert_eq!("Saved", trigger(&response)["toast"]["message"]);
+    }
+
+    #[test]
+    fn messages_may_be_in_any_script() {
+        let response = with_toast(StatusCode::OK, ToastLevel::Success, "Partagée avec José 👍");
+
+        assert_eq!(
+            "Partagée avec José 👍",
+            trigger(&response)["toast"]["message"]
+        );
+    }
+}
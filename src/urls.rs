@@ -0,0 +1,175 @@
+use anyhow::{Context, anyhow};
+use reqwest::Url;
+use uuid::Uuid;
+
+use crate::config::ApplicationSettings;
+
+/// Builds the absolute links to the site that leave it, in emails, calendar
+/// feeds and the like, from `APP_BASE_URL`.
+///
+/// Tokens and ids are added as single path segments or query values, so
+/// they are percent-encoded and can't point anywhere but below the base,
+/// whatever they contain.
+#[derive(Clone, Debug)]
+pub struct UrlBuilder {
+    base: Url,
+}
+
+impl UrlBuilder {
+    pub fn from_settings(settings: &ApplicationSettings) -> Result<Self, anyhow::Error> {
+        Self::new(&settings.app_base_url)
+    }
+
+    pub fn new(base_url: &str) -> Result<Self, anyhow::Error> {
+        let base = Url::parse(base_url).context("APP_BASE_URL isn't a URL")?;
+        if !matches!(base.scheme(), "http" | "https") {
+            return Err(anyhow!("APP_BASE_URL has to be an http or https URL"));
+        }
+        if base.host_str().is_none() || base.cannot_be_a_base() {
+            return Err(anyhow!("APP_BASE_URL has to be an absolute URL"));
+        }
+        if base.query().is_some() || base.fragment().is_some() {
+            return Err(anyhow!("APP_BASE_URL can't have a query or a fragment"));
+        }
+        Ok(Self { base })
+    }
+
+    /// The base followed by `segments`, each one encoded as a single segment.
+    ///
+    /// `.` and `..` would move up the path instead, and can't be written in a
+    /// URL at all, so they are pushed as their encoded spelling. That is
+    /// encoded once more and matches no route, which is fine as no token or id
+    /// is ever just dots.
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.base.clone();
+        url.path_segments_mut()
+            .expect("Base URL was checked to be a base")
+            .pop_if_empty()
+            .extend(segments.iter().map(|segment| match *segment {
+                "." => "%2E",
+                ".." => "%2E%2E",
+                segment => segment,
+            }));
+        url
+    }
+
+    /// Like [`Self::url`], with `value` as the only query parameter `name`
+    fn url_with_query(&self, segments: &[&str], name: &str, value: &str) -> Url {
+        let mut url = self.url(segments);
+        url.query_pairs_mut().append_pair(name, value);
+        url
+    }
+
+    pub fn home_url(&self) -> Url {
+        self.url(&[""])
+    }
+
+    pub fn login_url(&self) -> Url {
+        self.url(&["login"])
+    }
+
+    pub fn settings_url(&self) -> Url {
+        self.url(&["settings"])
+    }
+
+    pub fn todos_url(&self) -> Url {
+        self.url(&["todo"])
+    }
+
+    pub fn todo_url(&self, todo_id: Uuid) -> Url {
+        self.url(&["todo", &todo_id.to_string()])
+    }
+
+    pub fn magic_link_url(&self, token: &str) -> Url {
+        self.url(&["login", "magic", token])
+    }
+
+    /// Confirms the new address of an email change
+    pub fn confirm_email_url(&self, token: &str) -> Url {
+        self.url_with_query(&["confirm-email"], "token", token)
+    }
+
+    pub fn invitation_url(&self, code: &str) -> Url {
+        self.url_with_query(&["register"], "code", code)
+    }
+
+    pub fn calendar_feed_url(&self, token: &str) -> Url {
+        self.url(&["calendar", &format!("{token}.ics")])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::urls::UrlBuilder;
+
+    #[test]
+    pub fn trailing_slashes_of_the_base_make_no_difference() {
+        for base in ["https://example.com", "https://example.com/"] {
+            let urls = UrlBuilder::new(base).unwrap();
+            assert_eq!("https://example.com/", urls.home_url().as_str());
+            assert_eq!("https://example.com/login", urls.login_url().as_str());
+        }
+        for base in ["https://example.com/app", "https://example.com/app/"] {
+            let urls = UrlBuilder::new(base).unwrap();
+            assert_eq!("https://example.com/app/", urls.home_url().as_str());
+            assert_eq!(
+                "https://example.com/app/login/magic/abc",
+                urls.magic_link_url("abc").as_str()
+            );
+        }
+    }
+
+    #[test]
+    pub fn links_have_their_ids_and_tokens() {
+        let urls = UrlBuilder::new("http://localhost:8000").unwrap();
+        let todo_id = Uuid::nil();
+        assert_eq!(
+            format!("http://localhost:8000/todo/{todo_id}"),
+            urls.todo_url(todo_id).as_str()
+        );
+        assert_eq!(
+            "http://localhost:8000/confirm-email?token=abc",
+            urls.confirm_email_url("abc").as_str()
+        );
+        assert_eq!(
+            "http://localhost:8000/calendar/abc.ics",
+            urls.calendar_feed_url("abc").as_str()
+        );
+    }
+
+    #[test]
+    pub fn tokens_cant_escape_the_base() {
+        let urls = UrlBuilder::new("https://example.com/app/").unwrap();
+        for token in ["../../admin", "..", "a/../../b", "%2e%2e", "x?y#z"] {
+            let url = urls.magic_link_url(token);
+            assert!(
+                url.path().starts_with("/app/login/magic/"),
+                "{url} escapes the base"
+            );
+            assert_eq!(None, url.query());
+            assert_eq!(None, url.fragment());
+        }
+
+        let url = urls.confirm_email_url("a&token=b#c");
+        assert_eq!("/app/confirm-email", url.path());
+        let tokens: Vec<_> = url.query_pairs().collect();
+        assert_eq!(1, tokens.len());
+        assert_eq!("a&token=b#c", tokens[0].1);
+    }
+
+    #[test]
+    pub fn only_absolute_http_urls_are_accepted() {
+        for base in [
+            "localhost:8000",
+            "/app",
+            "ftp://example.com",
+            "mailto:admin@example.com",
+            "https://example.com/?a=b",
+            "https://example.com/#top",
+        ] {
+            assert!(UrlBuilder::new(base).is_err(), "{base} is accepted");
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Reading forms into their types with [`ValidatedForm`], which says what was
+//! wrong with each field when that fails instead of axum's plain text 422.
+
+use axum::{
+    Form,
+    extract::{FromRequest, Request, rejection::FormRejection},
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    api_error::{ApiError, FieldError},
+    i18n::{Locale, Translatable, translate},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldErrorKind {
+    Missing,
+    Invalid,
+}
+
+impl FieldErrorKind {
+    fn code(self) -> &'static str {
+        match self {
+            FieldErrorKind::Missing => "missing",
+            FieldErrorKind::Invalid => "invalid",
+        }
+    }
+}
+
+impl Translatable for FieldErrorKind {
+    fn message_key(&self) -> &'static str {
+        match self {
+            FieldErrorKind::Missing => "error.form.missing",
+            FieldErrorKind::Invalid => "error.form.invalid",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidField {
+    pub field: String,
+    pub kind: FieldErrorKind,
+}
+
+/// Why a form couldn't be read, with what was sent so the form can be shown
+/// again as it was
+#[derive(Debug)]
+pub struct FormErrors {
+    status: StatusCode,
+    /// Empty when the body wasn't a form at all
+    fields: Vec<InvalidField>,
+    values: Vec<(String, String)>,
+    /// What axum said about a body that wasn't a form
+    rejection: Option<String>,
+}
+
+impl FormErrors {
+    fn unreadable(rejection: FormRejection) -> Self {
+        Self {
+            status: rejection.status(),
+            fields: Vec::new(),
+            values: Vec::new(),
+            rejection: Some(rejection.body_text()),
+        }
+    }
+
+    pub fn fields(&self) -> &[InvalidField] {
+        &self.fields
+    }
+
+    /// What was sent for `field`, empty if nothing was
+    pub fn value(&self, field: &str) -> &str {
+        self.values
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_default()
+    }
+
+    /// The error with its messages in the locale of the request
+    pub fn into_api_error(self, locale: Locale) -> ApiError {
+        if let Some(rejection) = self.rejection {
+            return ApiError::new(self.status, "invalid_form", rejection);
+        }
+        let errors = self
+            .fields
+            .iter()
+            .map(|invalid| FieldError {
+                field: invalid.field.clone(),
+                code: invalid.kind.code(),
+                message: invalid.kind.message(locale).to_string(),
+            })
+            .collect();
+        ApiError::new(
+            self.status,
+            "invalid_form",
+            translate(locale, "error.form.invalid_fields"),
+        )
+        .with_errors(errors)
+    }
+}
+
+impl IntoResponse for FormErrors {
+    fn into_response(self) -> Response {
+        self.into_api_error(Locale::En).into_response()
+    }
+}
+
+/// The message for `field` among `fields`, for templates showing it next to
+/// the field
+pub fn field_error(fields: &[InvalidField], field: &str, locale: Locale) -> Option<&'static str> {
+    fields
+        .iter()
+        .find(|invalid| invalid.field == field)
+        .map(|invalid| invalid.kind.message(locale))
+}
+
+/// Like [`Form`], rejecting with [`FormErrors`]. Handlers that show the form
+/// again take `Result<ValidatedForm<T>, FormErrors>`.
+pub struct ValidatedForm<T>(pub T);
+
+impl<T, S> FromRequest<S> for ValidatedForm<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = FormErrors;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Form(values) = Form::<Vec<(String, String)>>::from_request(request, state)
+            .await
+            .map_err(FormErrors::unreadable)?;
+        match deserialize(values.clone()) {
+            Ok(form) => Ok(ValidatedForm(form)),
+            Err(fields) => Err(FormErrors {
+                status: StatusCode::UNPROCESSABLE_ENTITY,
+                fields,
+                values,
+                rejection: None,
+            }),
+        }
+    }
+}
+
+/// Serde stops at the first error, so the form is read again without each
+/// field it stopped at to find the others. Fields that are still wrong once
+/// left out or empty end the search.
+fn deserialize<T: DeserializeOwned>(
+    mut values: Vec<(String, String)>,
+) -> Result<T, Vec<InvalidField>> {
+    let mut fields: Vec<InvalidField> = Vec::new();
+    loop {
+        let encoded = serde_urlencoded::to_string(&values).unwrap_or_default();
+        let deserializer =
+            serde_urlencoded::Deserializer::new(form_urlencoded::parse(encoded.as_bytes()));
+        let error = match serde_path_to_error::deserialize(deserializer) {
+            Ok(form) if fields.is_empty() => return Ok(form),
+            Ok(_) => return Err(fields),
+            Err(e) => e,
+        };
+
+        let path = error.path().to_string();
+        let message = error.into_inner().to_string();
+        let invalid = match message
+            .strip_prefix("missing field `")
+            .and_then(|rest| rest.strip_suffix('`'))
+        {
+            Some(field) => InvalidField {
+                field: field.to_string(),
+                kind: FieldErrorKind::Missing,
+            },
+            // the path is only `.` when the error isn't about one field
+            None if path != "." => InvalidField {
+                field: path,
+                kind: FieldErrorKind::Invalid,
+            },
+            None => return Err(fields),
+        };
+        if fields.iter().any(|seen| seen.field == invalid.field) {
+            return Err(fields);
+        }
+
+        values.retain(|(name, _)| *name != invalid.field);
+        if invalid.kind == FieldErrorKind::Missing {
+            values.push((invalid.field.clone(), String::new()));
+        }
+        fields.push(invalid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FieldErrorKind, InvalidField, deserialize};
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Signup {
+        email: String,
+        username: String,
+        age: Option<u32>,
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn invalid(field: &str, kind: FieldErrorKind) -> InvalidField {
+        InvalidField {
+            field: field.to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn every_missing_field_is_listed() {
+        let fields = deserialize::<Signup>(values(&[("age", "42")])).unwrap_err();
+        assert_eq!(
+            vec![
+                invalid("email", FieldErrorKind::Missing),
+                invalid("username", FieldErrorKind::Missing)
+            ],
+            fields
+        );
+    }
+
+    #[test]
+    fn fields_of_the_wrong_type_are_invalid() {
+        let fields =
+            deserialize::<Signup>(values(&[("username", "alice"), ("age", "old")])).unwrap_err();
+        assert_eq!(
+            vec![
+                invalid("age", FieldErrorKind::Invalid),
+                invalid("email", FieldErrorKind::Missing)
+            ],
+            fields
+        );
+    }
+
+    #[test]
+    fn complete_forms_are_read() {
+        let form = deserialize::<Signup>(values(&[
+            ("email", "alice@example.com"),
+            ("username", "alice"),
+        ]))
+        .unwrap();
+        assert_eq!("alice@example.com", form.email);
+        assert_eq!("alice", form.username);
+        assert_eq!(None, form.age);
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::postgres::PgExecutor;
+use uuid::Uuid;
+
+use crate::routes::todo::history::TodoChange;
+
+/// Most webhooks a single user can set up
+pub const MAX_WEBHOOKS_PER_USER: i64 = 5;
+
+/// Header carrying `sha256=<hex HMAC of the body>`, keyed with the webhook's secret
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+/// Header carrying the event name, e.g. `todo.created`
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+/// Header carrying the delivery id, the same across retries of a delivery
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+/// Todo changes webhooks can be sent for. The names are part of the payload
+/// and the stored event masks, so they can't change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WebhookEvent {
+    TodoCreated,
+    TodoCompleted,
+    TodoDeleted,
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("Unknown webhook event")]
+pub struct UnknownWebhookEventError;
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 3] = [
+        WebhookEvent::TodoCreated,
+        WebhookEvent::TodoCompleted,
+        WebhookEvent::TodoDeleted,
+    ];
+
+    pub fn parse(s: &str) -> Result<Self, UnknownWebhookEventError> {
+        Self::ALL
+            .into_iter()
+            .find(|event| event.as_str() == s)
+            .ok_or(UnknownWebhookEventError)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::TodoCreated => "todo.created",
+            WebhookEvent::TodoCompleted => "todo.completed",
+            WebhookEvent::TodoDeleted => "todo.deleted",
+        }
+    }
+
+    /// The event sent for a change in the todo history, if any
+    pub fn for_change(change: &TodoChange) -> Option<Self> {
+        match change {
+            TodoChange::Created => Some(WebhookEvent::TodoCreated),
+            TodoChange::Completed => Some(WebhookEvent::TodoCompleted),
+            TodoChange::Deleted => Some(WebhookEvent::TodoDeleted),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Queues a delivery of `event` to every enabled webhook subscribed to it
+/// whose owner can see the todo, i.e. owns or is a member of its list.
+///
+/// Should run in the same transaction as the change, so a rolled back change
+/// is never sent and a committed one always is. The payload is taken from
+/// the todo as it is now rather than when it is delivered.
+pub async fn enqueue(
+    executor: impl PgExecutor<'_>,
+    todo_id: Uuid,
+    event: WebhookEvent,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO outbox (webhook_id, event, payload)
+        SELECT wh.webhook_id, $2, jsonb_build_object(
+            'event', $2::text,
+            'occurred_at', NOW(),
+            'todo', jsonb_build_object(
+                'todo_id', td.todo_id,
+                'list_id', td.list_id,
+                'content', td.todo_content,
+                'is_completed', td.is_completed,
+                'priority', td.priority,
+                'due_date', td.due_date
+            )
+        )
+        FROM todo AS td
+        JOIN webhooks AS wh ON wh.enabled AND $2 = ANY(wh.events)
+        WHERE td.todo_id = $1
+            AND wh.user_id IN (
+                SELECT tl.owner_id FROM todo_list AS tl WHERE tl.list_id = td.list_id
+                UNION
+                SELECT lm.user_id FROM list_members AS lm WHERE lm.list_id = td.list_id
+            )
+        "#,
+        todo_id,
+        event.as_str()
+    )
+    .execute(executor)
+    .await
+    .context("Failed to queue webhook deliveries")?;
+
+    Ok(())
+}
+
+/// New random secret for signing a webhook's payloads
+pub fn generate_secret() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+/// Value of the [`SIGNATURE_HEADER`] for `body`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+
+    use crate::webhook::{WebhookEvent, sign};
+
+    #[test]
+    pub fn event_names_round_trip() {
+        for event in WebhookEvent::ALL {
+            assert_ok_eq!(WebhookEvent::parse(event.as_str()), event);
+        }
+        assert_err!(WebhookEvent::parse("todo.updated"));
+    }
+
+    #[test]
+    pub fn signature_is_the_hex_hmac_of_the_body() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            sign("Jefe", b"what do ya want for nothing?")
+        );
+    }
+}
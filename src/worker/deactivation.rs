@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use sqlx::PgPool;
+
+use super::scheduler::PeriodicTask;
+use crate::{
+    app::ApiContext,
+    audit::{AuditEntry, AuditEvent, RequestMetadata},
+    routes::settings::{DeletedUser, clean_up_deleted_user},
+};
+
+/// How often expired deactivations are looked for, the grace period is
+/// counted in days so this needn't be precise
+const DELETE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Deletes the accounts that weren't reactivated within the grace period
+pub struct DeleteDeactivatedUsersTask {
+    pub grace_days: i32,
+}
+
+#[async_trait]
+impl PeriodicTask for DeleteDeactivatedUsersTask {
+    fn name(&self) -> &'static str {
+        "delete_deactivated_users"
+    }
+
+    fn interval(&self) -> Duration {
+        DELETE_INTERVAL
+    }
+
+    async fn run(&self, api_context: &ApiContext) -> Result<(), anyhow::Error> {
+        let deleted = delete_deactivated_users(&api_context.db, self.grace_days).await?;
+        for user in &deleted {
+            if let Err(e) = clean_up_deleted_user(api_context, user).await {
+                tracing::warn!(user_id = %user.user_id, error = ?e, "Failed to clean up after deleted user");
+            }
+            api_context.audit.record(
+                AuditEntry::new(
+                    AuditEvent::AccountDeleted,
+                    None,
+                    &RequestMetadata::default(),
+                )
+                .with_metadata(serde_json::json!({
+                    "user_id": user.user_id,
+                    "username": user.username,
+                    "deactivated": true,
+                })),
+            );
+        }
+        if !deleted.is_empty() {
+            tracing::info!(deleted = deleted.len(), "Deleted deactivated users");
+        }
+        Ok(())
+    }
+}
+
+/// Deletes the users deactivated more than `grace_days` ago, like deleting
+/// the account would, returning them so what they leave outside of Postgres
+/// can be cleaned up
+pub async fn delete_deactivated_users(
+    db: &PgPool,
+    grace_days: i32,
+) -> Result<Vec<DeletedUser>, anyhow::Error> {
+    sqlx::query_as!(
+        DeletedUser,
+        r#"
+        DELETE FROM user_info
+        WHERE deactivated_at < NOW() - make_interval(days => $1)
+        RETURNING user_id, username, avatar
+        "#,
+        grace_days
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to delete deactivated users")
+}
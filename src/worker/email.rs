@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+
+use super::scheduler::{PeriodicTask, retry_delay};
+use crate::{app::ApiContext, domain::email_address::EmailAddress, emails::Email};
+
+/// Maximum number of emails claimed per tick
+const BATCH_SIZE: i64 = 50;
+/// How long claimed emails are left to a worker, longer than sending a whole
+/// batch one after the other can take
+const LEASE: Duration = Duration::from_secs(BATCH_SIZE as u64 * 12);
+/// Longest error message kept for the admin page
+const MAX_ERROR_LENGTH: usize = 500;
+
+/// Sends the emails queued in the outbox
+pub struct DeliverEmailsTask {
+    pub interval: Duration,
+    /// Wait before the first retry, doubling with each one after
+    pub retry_base: Duration,
+    /// Attempts after which an email is given up on
+    pub max_attempts: i32,
+}
+
+#[async_trait]
+impl PeriodicTask for DeliverEmailsTask {
+    fn name(&self) -> &'static str {
+        "email_deliveries"
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    async fn run(&self, api_context: &ApiContext) -> Result<(), anyhow::Error> {
+        let sent = self.deliver_emails(api_context).await?;
+        if sent > 0 {
+            tracing::info!(sent, "Sent queued emails");
+        }
+        Ok(())
+    }
+}
+
+/// A claimed email
+struct QueuedEmail {
+    email_id: i64,
+    recipient: String,
+    template: String,
+    params: serde_json::Value,
+    attempts: i32,
+}
+
+impl DeliverEmailsTask {
+    /// Sends one batch of due emails, returning how many were sent.
+    ///
+    /// Rows are claimed by setting `locked_until` in a statement of their
+    /// own, so no transaction stays open while the email API is called. Sent
+    /// emails are removed, failed ones retried with exponential backoff until
+    /// `max_attempts`, after which they are kept as dead letters for the
+    /// admins.
+    pub async fn deliver_emails(&self, api_context: &ApiContext) -> Result<usize, anyhow::Error> {
+        let emails = claim_emails(&api_context.db).await?;
+
+        let mut sent = 0;
+        for email in emails {
+            match send(api_context, &email).await {
+                Ok(()) => {
+                    record_success(&api_context.db, email.email_id).await?;
+                    sent += 1;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        error = ?e,
+                        email_id = email.email_id,
+                        template = email.template,
+                        "Failed to send queued email"
+                    );
+                    let mut error = format!("{e:#}");
+                    error.truncate(error.floor_char_boundary(MAX_ERROR_LENGTH));
+                    self.record_failure(&api_context.db, &email, error).await?;
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+
+    async fn record_failure(
+        &self,
+        db: &PgPool,
+        email: &QueuedEmail,
+        error: String,
+    ) -> Result<(), anyhow::Error> {
+        let next_attempt_at =
+            OffsetDateTime::now_utc() + retry_delay(self.retry_base, email.attempts);
+        let dead = sqlx::query_scalar!(
+            r#"
+            UPDATE email_outbox SET
+                attempts = attempts + 1,
+                next_attempt_at = $2,
+                locked_until = NULL,
+                last_error = $3,
+                dead_at = CASE WHEN attempts + 1 >= $4 THEN NOW() END
+            WHERE email_id = $1
+            RETURNING dead_at IS NOT NULL AS "dead!"
+            "#,
+            email.email_id,
+            next_attempt_at,
+            error,
+            self.max_attempts
+        )
+        .fetch_one(db)
+        .await
+        .context("Failed to reschedule queued email")?;
+
+        if dead {
+            tracing::error!(
+                email_id = email.email_id,
+                template = email.template,
+                "Gave up on queued email after repeated failures"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Claims up to [`BATCH_SIZE`] due emails for [`LEASE`]
+async fn claim_emails(db: &PgPool) -> Result<Vec<QueuedEmail>, anyhow::Error> {
+    sqlx::query_as!(
+        QueuedEmail,
+        r#"
+        UPDATE email_outbox SET locked_until = NOW() + make_interval(secs => $2)
+        WHERE email_id IN (
+            SELECT email_id FROM email_outbox
+            WHERE dead_at IS NULL
+                AND next_attempt_at <= NOW()
+                AND (locked_until IS NULL OR locked_until <= NOW())
+            ORDER BY email_id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING email_id, recipient, template, params, attempts
+        "#,
+        BATCH_SIZE,
+        LEASE.as_secs_f64()
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to claim queued emails")
+}
+
+async fn send(api_context: &ApiContext, email: &QueuedEmail) -> Result<(), anyhow::Error> {
+    let recipient = EmailAddress::parse(&email.recipient).context("Invalid recipient")?;
+    let rendered =
+        Email::from_row(&email.template, email.params.clone())?.render(&api_context.urls)?;
+    api_context
+        .email_client
+        .send_email(
+            &recipient,
+            &rendered.subject,
+            &rendered.html,
+            &rendered.text,
+        )
+        .await
+        .context("Failed to send email")?;
+    Ok(())
+}
+
+async fn record_success(db: &PgPool, email_id: i64) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM email_outbox WHERE email_id = $1
+        "#,
+        email_id
+    )
+    .execute(db)
+    .await
+    .context("Failed to remove sent email")?;
+    Ok(())
+}
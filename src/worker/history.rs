@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use sqlx::PgPool;
+
+use super::scheduler::PeriodicTask;
+use crate::app::ApiContext;
+
+/// How often old history is looked for, retention is counted in days so this
+/// needn't be precise
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Removes todo history older than the retention period
+pub struct PruneTodoHistoryTask {
+    pub retention_days: i32,
+}
+
+#[async_trait]
+impl PeriodicTask for PruneTodoHistoryTask {
+    fn name(&self) -> &'static str {
+        "prune_todo_history"
+    }
+
+    fn interval(&self) -> Duration {
+        PRUNE_INTERVAL
+    }
+
+    async fn run(&self, api_context: &ApiContext) -> Result<(), anyhow::Error> {
+        let pruned = prune_todo_events(&api_context.db, self.retention_days).await?;
+        if pruned > 0 {
+            tracing::info!(pruned, "Pruned todo history");
+        }
+        Ok(())
+    }
+}
+
+/// Deletes history entries older than `retention_days`, returning how many
+/// were removed
+pub async fn prune_todo_events(db: &PgPool, retention_days: i32) -> Result<u64, anyhow::Error> {
+    let query_result = sqlx::query!(
+        r#"
+        DELETE FROM todo_events
+        WHERE created_at < NOW() - make_interval(days => $1)
+        "#,
+        retention_days
+    )
+    .execute(db)
+    .await
+    .context("Failed to prune todo history")?;
+
+    Ok(query_result.rows_affected())
+}
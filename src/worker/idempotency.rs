@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use super::scheduler::PeriodicTask;
+use crate::{app::ApiContext, idempotency::delete_expired_keys};
+
+/// Clients retry within minutes, a day leaves plenty of room
+const KEY_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Removes idempotency keys once they are too old to be retried
+pub struct ExpireIdempotencyKeysTask;
+
+#[async_trait]
+impl PeriodicTask for ExpireIdempotencyKeysTask {
+    fn name(&self) -> &'static str {
+        "expire_idempotency_keys"
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(60 * 60)
+    }
+
+    async fn run(&self, api_context: &ApiContext) -> Result<(), anyhow::Error> {
+        let expired = delete_expired_keys(&api_context.db, KEY_MAX_AGE).await?;
+        if expired > 0 {
+            tracing::info!(expired, "Deleted expired idempotency keys");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use super::scheduler::PeriodicTask;
+use crate::{app::ApiContext, auth::delete_expired_magic_links};
+
+/// Removes login links once they have expired, used or not
+pub struct ExpireMagicLinksTask;
+
+#[async_trait]
+impl PeriodicTask for ExpireMagicLinksTask {
+    fn name(&self) -> &'static str {
+        "expire_magic_links"
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(60 * 60)
+    }
+
+    async fn run(&self, api_context: &ApiContext) -> Result<(), anyhow::Error> {
+        let expired = delete_expired_magic_links(&api_context.db).await?;
+        if expired > 0 {
+            tracing::info!(expired, "Deleted expired magic links");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,11 @@
+pub mod deactivation;
+pub mod email;
+pub mod history;
+pub mod idempotency;
+pub mod magic_link;
+pub mod notifications;
+pub mod purge;
+pub mod refresh_token;
+pub mod reminder;
+pub mod scheduler;
+pub mod webhook;
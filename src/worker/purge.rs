@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use sqlx::PgPool;
+
+use super::scheduler::PeriodicTask;
+use crate::app::ApiContext;
+
+/// Removes deleted todos whose undo window has passed
+pub struct PurgeDeletedTodosTask {
+    pub grace_period: Duration,
+}
+
+#[async_trait]
+impl PeriodicTask for PurgeDeletedTodosTask {
+    fn name(&self) -> &'static str {
+        "purge_deleted_todos"
+    }
+
+    fn interval(&self) -> Duration {
+        // nothing becomes purgeable more than one grace period after its deletion
+        self.grace_period.max(Duration::from_secs(1))
+    }
+
+    async fn run(&self, api_context: &ApiContext) -> Result<(), anyhow::Error> {
+        let purged = purge_deleted_todos(&api_context.db, self.grace_period).await?;
+        if purged > 0 {
+            tracing::info!(purged, "Purged deleted todos");
+        }
+        Ok(())
+    }
+}
+
+/// Hard deletes todos that were deleted more than `grace_period` ago, returning
+/// how many were removed.
+///
+/// Tags that were only used by the purged todos are removed with them.
+pub async fn purge_deleted_todos(
+    db: &PgPool,
+    grace_period: Duration,
+) -> Result<usize, anyhow::Error> {
+    let mut transaction = db.begin().await.context("Failed to begin transaction")?;
+
+    let list_ids = sqlx::query_scalar!(
+        r#"
+        DELETE FROM todo
+        WHERE deleted_at < NOW() - make_interval(secs => $1)
+        RETURNING list_id AS "list_id!"
+        "#,
+        grace_period.as_secs_f64()
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to purge deleted todos")?;
+
+    if !list_ids.is_empty() {
+        sqlx::query!(
+            r#"
+            DELETE FROM tag AS tg
+            USING todo_list AS tl
+            WHERE tl.list_id = ANY($1)
+                AND tg.user_id = tl.owner_id
+                AND NOT EXISTS (SELECT 1 FROM todo_tag AS tt WHERE tt.tag_id = tg.tag_id)
+            "#,
+            &list_ids
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete orphaned tags")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(list_ids.len())
+}
@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use super::scheduler::PeriodicTask;
+use crate::{app::ApiContext, auth::delete_expired_refresh_tokens};
+
+/// Removes refresh tokens once they have expired, or a day after they were
+/// used or revoked
+pub struct ExpireRefreshTokensTask;
+
+#[async_trait]
+impl PeriodicTask for ExpireRefreshTokensTask {
+    fn name(&self) -> &'static str {
+        "expire_refresh_tokens"
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(60 * 60)
+    }
+
+    async fn run(&self, api_context: &ApiContext) -> Result<(), anyhow::Error> {
+        let expired = delete_expired_refresh_tokens(&api_context.db).await?;
+        if expired > 0 {
+            tracing::info!(expired, "Deleted expired refresh tokens");
+        }
+        Ok(())
+    }
+}
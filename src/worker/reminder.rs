@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+
+use super::scheduler::PeriodicTask;
+use crate::{
+    app::ApiContext,
+    domain::email_address::EmailAddress,
+    emails::{self, DueDateReminder, Email},
+};
+
+/// Maximum number of reminders claimed per tick
+const BATCH_SIZE: i64 = 100;
+
+/// Emails users about incomplete todos that are due today
+pub struct DueDateReminderTask {
+    pub interval: Duration,
+}
+
+#[async_trait]
+impl PeriodicTask for DueDateReminderTask {
+    fn name(&self) -> &'static str {
+        "due_date_reminders"
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    async fn run(&self, api_context: &ApiContext) -> Result<(), anyhow::Error> {
+        let sent = send_due_date_reminders(api_context).await?;
+        if sent > 0 {
+            tracing::info!(sent, "Queued due date reminders");
+        }
+        Ok(())
+    }
+}
+
+/// Queues reminders for one batch of due todos, returning how many were queued.
+///
+/// Rows are claimed with `FOR UPDATE SKIP LOCKED` so multiple app instances don't
+/// double-send, and the email is queued in the same transaction that records
+/// `reminder_sent_at`. Sending it, and retrying it, is up to the email worker.
+pub async fn send_due_date_reminders(api_context: &ApiContext) -> Result<usize, anyhow::Error> {
+    let mut transaction = api_context
+        .db
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+
+    let due_todos = sqlx::query!(
+        r#"
+        SELECT td.todo_id, td.todo_content, ui.email
+        FROM todo AS td
+        JOIN user_info AS ui ON ui.user_id = td.user_id
+        LEFT JOIN user_preferences AS up ON up.user_id = td.user_id
+        -- due dates are calendar dates of the user, so "today" is theirs too
+        WHERE td.due_date = (NOW() AT TIME ZONE COALESCE(up.timezone, 'UTC'))::date
+            AND td.reminder_sent_at IS NULL
+            AND NOT td.is_completed
+            AND td.deleted_at IS NULL
+            AND ui.due_date_reminders
+        ORDER BY td.todo_id
+        LIMIT $1
+        FOR UPDATE OF td SKIP LOCKED
+        "#,
+        BATCH_SIZE
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch due todos")?;
+
+    let mut sent = 0;
+    for todo in due_todos {
+        let recipient = match EmailAddress::parse(&todo.email) {
+            Ok(recipient) => recipient,
+            Err(e) => {
+                tracing::warn!(error = ?e, todo_id = %todo.todo_id, "Skipping reminder to invalid email");
+                continue;
+            }
+        };
+
+        let reminder = Email::DueDateReminder(DueDateReminder {
+            todo_content: todo.todo_content,
+        });
+        emails::enqueue(&mut *transaction, &recipient, &reminder).await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE todo SET reminder_sent_at = NOW() WHERE todo_id = $1
+            "#,
+            todo.todo_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to record sent reminder")?;
+        sent += 1;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(sent)
+}
@@ -0,0 +1,145 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use rand::Rng;
+use time::OffsetDateTime;
+
+use crate::app::ApiContext;
+
+/// Longest wait between two attempts of a queued delivery
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Background work that runs on a fixed interval
+#[async_trait]
+pub trait PeriodicTask: Send + Sync {
+    /// Unique across tasks, used for the lock and the recorded runs
+    fn name(&self) -> &'static str;
+
+    fn interval(&self) -> Duration;
+
+    async fn run(&self, api_context: &ApiContext) -> Result<(), anyhow::Error>;
+}
+
+/// The periodic tasks of the application, each spawned as its own tokio task
+#[derive(Default)]
+pub struct Scheduler {
+    tasks: Vec<Arc<dyn PeriodicTask>>,
+}
+
+impl Scheduler {
+    pub fn register(mut self, task: impl PeriodicTask + 'static) -> Self {
+        self.tasks.push(Arc::new(task));
+        self
+    }
+
+    pub fn spawn(self, api_context: Arc<ApiContext>) {
+        for task in self.tasks {
+            tokio::spawn(run_periodically(task, api_context.clone()));
+        }
+    }
+}
+
+async fn run_periodically(task: Arc<dyn PeriodicTask>, api_context: Arc<ApiContext>) {
+    let period = task.interval();
+    // spreads out the runs of instances started at the same time, which would
+    // otherwise contend for the lock on every tick
+    let jitter = period.mul_f64(rand::thread_rng().gen_range(0.0..0.1));
+    let mut interval = tokio::time::interval_at((Instant::now() + jitter).into(), period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = run_once(task.as_ref(), &api_context).await {
+            tracing::error!(task = task.name(), error = ?e, "Failed to run scheduled task");
+        }
+    }
+}
+
+/// Runs the task unless another instance already is, and records the outcome.
+///
+/// The advisory lock is tied to a transaction that stays open for the whole
+/// run, so it is released even if the instance dies halfway.
+async fn run_once(task: &dyn PeriodicTask, api_context: &ApiContext) -> Result<(), anyhow::Error> {
+    let name = task.name();
+    let mut lock = api_context
+        .db
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+
+    let acquired = sqlx::query_scalar!(
+        r#"
+        SELECT pg_try_advisory_xact_lock(hashtext($1)) AS "acquired!"
+        "#,
+        name
+    )
+    .fetch_one(&mut *lock)
+    .await
+    .context("Failed to acquire task lock")?;
+    if !acquired {
+        tracing::debug!(task = name, "Scheduled task is already running elsewhere");
+        return Ok(());
+    }
+
+    let started_at = OffsetDateTime::now_utc();
+    let start = Instant::now();
+    let result = task.run(api_context).await;
+    let duration_ms = start.elapsed().as_millis() as i64;
+
+    match &result {
+        Ok(()) => tracing::info!(task = name, duration_ms, "Scheduled task finished"),
+        Err(e) => tracing::error!(task = name, duration_ms, error = ?e, "Scheduled task failed"),
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO scheduled_task (name, last_started_at, last_duration_ms, last_error)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (name) DO UPDATE
+        SET last_started_at = EXCLUDED.last_started_at,
+            last_duration_ms = EXCLUDED.last_duration_ms,
+            last_error = EXCLUDED.last_error
+        "#,
+        name,
+        started_at,
+        duration_ms,
+        result.as_ref().err().map(|e| format!("{e:#}"))
+    )
+    .execute(&mut *lock)
+    .await
+    .context("Failed to record task run")?;
+
+    lock.commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(())
+}
+
+/// Wait after the given number of earlier failed attempts of a queued
+/// delivery, doubling from `base` with each one up to an hour
+pub fn retry_delay(base: Duration, attempts: i32) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.max(0) as u32);
+    base.saturating_mul(factor).min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::worker::scheduler::retry_delay;
+
+    #[test]
+    pub fn retry_delay_doubles_up_to_an_hour() {
+        let base = Duration::from_secs(30);
+        assert_eq!(Duration::from_secs(30), retry_delay(base, 0));
+        assert_eq!(Duration::from_secs(60), retry_delay(base, 1));
+        assert_eq!(Duration::from_secs(240), retry_delay(base, 3));
+        assert_eq!(Duration::from_secs(60 * 60), retry_delay(base, 10));
+        assert_eq!(Duration::from_secs(60 * 60), retry_delay(base, i32::MAX));
+    }
+}
@@ -0,0 +1,510 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use http::header;
+use reqwest::{Url, redirect};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use super::scheduler::{PeriodicTask, retry_delay};
+use crate::{
+    app::ApiContext,
+    features::Feature,
+    webhook::{self, DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER},
+};
+
+/// Maximum number of deliveries claimed per tick
+const BATCH_SIZE: i64 = 50;
+/// Endpoints called at the same time
+const MAX_CONCURRENT_ENDPOINTS: usize = 8;
+/// How long an endpoint gets to respond
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long claimed deliveries are left to a worker, longer than a whole
+/// batch sent to a single endpoint can take
+const LEASE: Duration = Duration::from_secs(BATCH_SIZE as u64 * 12);
+/// Longest error message kept for the settings page
+const MAX_ERROR_LENGTH: usize = 500;
+
+/// Sends the queued webhook deliveries
+pub struct DeliverWebhooksTask {
+    pub interval: Duration,
+    /// Wait before the first retry, doubling with each one after
+    pub retry_base: Duration,
+    /// Failed attempts in a row after which an endpoint is turned off
+    pub max_failures: i32,
+    /// Whether endpoints may resolve to loopback or private addresses
+    pub allow_private_targets: bool,
+}
+
+#[async_trait]
+impl PeriodicTask for DeliverWebhooksTask {
+    fn name(&self) -> &'static str {
+        "webhook_deliveries"
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    async fn run(&self, api_context: &ApiContext) -> Result<(), anyhow::Error> {
+        // deliveries wait in the queue until webhooks are turned on again
+        if !api_context.features.enabled(Feature::Webhooks) {
+            return Ok(());
+        }
+        let delivered = self.deliver_webhooks(api_context).await?;
+        if delivered > 0 {
+            tracing::info!(delivered, "Delivered webhooks");
+        }
+        Ok(())
+    }
+}
+
+/// A claimed delivery and where it goes
+struct Delivery {
+    delivery_id: i64,
+    webhook_id: Uuid,
+    event: String,
+    payload: serde_json::Value,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+/// What came of a claimed delivery
+enum Outcome {
+    Delivered {
+        status: i16,
+    },
+    Failed {
+        status: Option<i16>,
+        error: Option<String>,
+    },
+    /// Left for the next tick, as an earlier delivery to the endpoint failed
+    Skipped,
+}
+
+impl DeliverWebhooksTask {
+    /// Sends one batch of due deliveries, returning how many succeeded.
+    ///
+    /// Rows are claimed by setting `locked_until` in a statement of their
+    /// own, so no transaction stays open while endpoints are called. The
+    /// endpoints are then called [`MAX_CONCURRENT_ENDPOINTS`] at a time, the
+    /// deliveries of each in order, and the outcomes written back together.
+    /// Delivered rows are removed, failed ones retried with exponential
+    /// backoff. The rest of the batch for an endpoint that failed waits for
+    /// the next tick, so its deliveries stay in order and a dead endpoint
+    /// isn't counted as failing several times per tick. An endpoint failing
+    /// `max_failures` times in a row is turned off and its queued deliveries
+    /// dropped.
+    pub async fn deliver_webhooks(&self, api_context: &ApiContext) -> Result<usize, anyhow::Error> {
+        let deliveries = claim_deliveries(&api_context.db).await?;
+
+        let mut by_webhook: Vec<Vec<Delivery>> = Vec::new();
+        for delivery in deliveries {
+            match by_webhook
+                .iter_mut()
+                .find(|group| group[0].webhook_id == delivery.webhook_id)
+            {
+                Some(group) => group.push(delivery),
+                None => by_webhook.push(vec![delivery]),
+            }
+        }
+
+        let mut sending = JoinSet::new();
+        let mut outcomes = Vec::new();
+        for group in by_webhook {
+            if sending.len() >= MAX_CONCURRENT_ENDPOINTS
+                && let Some(sent) = sending.join_next().await
+            {
+                outcomes.extend(sent.context("Webhook delivery panicked")?);
+            }
+            sending.spawn(send_in_order(self.allow_private_targets, group));
+        }
+        while let Some(sent) = sending.join_next().await {
+            outcomes.extend(sent.context("Webhook delivery panicked")?);
+        }
+
+        self.record_outcomes(api_context, outcomes).await
+    }
+
+    /// Writes back what came of the deliveries in one short transaction,
+    /// returning how many were delivered
+    async fn record_outcomes(
+        &self,
+        api_context: &ApiContext,
+        outcomes: Vec<(Delivery, Outcome)>,
+    ) -> Result<usize, anyhow::Error> {
+        let mut transaction = api_context
+            .db
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+
+        let mut delivered = 0;
+        for (delivery, outcome) in outcomes {
+            match outcome {
+                Outcome::Delivered { status } => {
+                    record_success(
+                        &mut transaction,
+                        delivery.delivery_id,
+                        delivery.webhook_id,
+                        status,
+                    )
+                    .await?;
+                    delivered += 1;
+                }
+                Outcome::Failed { status, error } => {
+                    let next_attempt_at =
+                        OffsetDateTime::now_utc() + retry_delay(self.retry_base, delivery.attempts);
+                    self.record_failure(
+                        &mut transaction,
+                        delivery.delivery_id,
+                        delivery.webhook_id,
+                        next_attempt_at,
+                        status,
+                        error,
+                    )
+                    .await?;
+                }
+                Outcome::Skipped => release(&mut transaction, delivery.delivery_id).await?,
+            }
+        }
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+
+        Ok(delivered)
+    }
+
+    async fn record_failure(
+        &self,
+        connection: &mut sqlx::PgConnection,
+        delivery_id: i64,
+        webhook_id: Uuid,
+        next_attempt_at: OffsetDateTime,
+        status: Option<i16>,
+        error: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE outbox SET attempts = attempts + 1, next_attempt_at = $2, locked_until = NULL
+            WHERE delivery_id = $1
+            "#,
+            delivery_id,
+            next_attempt_at
+        )
+        .execute(&mut *connection)
+        .await
+        .context("Failed to reschedule webhook delivery")?;
+
+        let enabled = sqlx::query_scalar!(
+            r#"
+            UPDATE webhooks SET
+                consecutive_failures = consecutive_failures + 1,
+                enabled = consecutive_failures + 1 < $2,
+                last_attempt_at = NOW(),
+                last_status = $3,
+                last_error = $4
+            WHERE webhook_id = $1
+            RETURNING enabled
+            "#,
+            webhook_id,
+            self.max_failures,
+            status,
+            error
+        )
+        .fetch_one(&mut *connection)
+        .await
+        .context("Failed to record webhook failure")?;
+
+        if !enabled {
+            tracing::warn!(%webhook_id, "Turned off webhook after repeated failures");
+            sqlx::query!(
+                r#"
+                DELETE FROM outbox WHERE webhook_id = $1
+                "#,
+                webhook_id
+            )
+            .execute(&mut *connection)
+            .await
+            .context("Failed to drop deliveries of turned off webhook")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Claims up to [`BATCH_SIZE`] due deliveries for [`LEASE`]. Deliveries
+/// behind a claimed one of the same endpoint are left alone, so they can't be
+/// sent before it by another worker.
+async fn claim_deliveries(db: &PgPool) -> Result<Vec<Delivery>, anyhow::Error> {
+    sqlx::query_as!(
+        Delivery,
+        r#"
+        WITH claimed AS (
+            UPDATE outbox SET locked_until = NOW() + make_interval(secs => $2)
+            WHERE delivery_id IN (
+                SELECT o.delivery_id
+                FROM outbox AS o
+                JOIN webhooks AS wh ON wh.webhook_id = o.webhook_id
+                WHERE wh.enabled
+                    AND o.next_attempt_at <= NOW()
+                    AND (o.locked_until IS NULL OR o.locked_until <= NOW())
+                    AND NOT EXISTS (
+                        SELECT 1 FROM outbox AS earlier
+                        WHERE earlier.webhook_id = o.webhook_id
+                            AND earlier.delivery_id < o.delivery_id
+                            AND earlier.locked_until > NOW()
+                    )
+                ORDER BY o.delivery_id
+                LIMIT $1
+                FOR UPDATE OF o SKIP LOCKED
+            )
+            RETURNING delivery_id, webhook_id, event, payload, attempts
+        )
+        SELECT c.delivery_id, c.webhook_id, c.event, c.payload, c.attempts, wh.url, wh.secret
+        FROM claimed AS c
+        JOIN webhooks AS wh ON wh.webhook_id = c.webhook_id
+        ORDER BY c.delivery_id
+        "#,
+        BATCH_SIZE,
+        LEASE.as_secs_f64()
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to claim queued webhook deliveries")
+}
+
+/// Sends the deliveries of one endpoint in order, skipping the rest after
+/// the first failure
+async fn send_in_order(
+    allow_private_targets: bool,
+    deliveries: Vec<Delivery>,
+) -> Vec<(Delivery, Outcome)> {
+    let mut outcomes = Vec::with_capacity(deliveries.len());
+    let mut failed = false;
+    for delivery in deliveries {
+        if failed {
+            outcomes.push((delivery, Outcome::Skipped));
+            continue;
+        }
+        let outcome = match send(allow_private_targets, &delivery).await {
+            Ok(status) if status.is_success() => Outcome::Delivered {
+                status: status.as_u16() as i16,
+            },
+            Ok(status) => Outcome::Failed {
+                status: Some(status.as_u16() as i16),
+                error: None,
+            },
+            Err(e) => {
+                let mut error = format!("{e:#}");
+                error.truncate(error.floor_char_boundary(MAX_ERROR_LENGTH));
+                Outcome::Failed {
+                    status: None,
+                    error: Some(error),
+                }
+            }
+        };
+        failed = matches!(outcome, Outcome::Failed { .. });
+        outcomes.push((delivery, outcome));
+    }
+    outcomes
+}
+
+async fn send(
+    allow_private_targets: bool,
+    delivery: &Delivery,
+) -> Result<http::StatusCode, anyhow::Error> {
+    let body = serde_json::to_vec(&delivery.payload).context("Failed to serialize payload")?;
+    let url = Url::parse(&delivery.url).context("Invalid webhook URL")?;
+    let client = client_for(allow_private_targets, &url).await?;
+    let response = client
+        .post(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, webhook::sign(&delivery.secret, &body))
+        .header(EVENT_HEADER, &delivery.event)
+        .header(DELIVERY_HEADER, delivery.delivery_id.to_string())
+        .body(body)
+        .send()
+        .await
+        .context("Failed to send webhook")?;
+    Ok(response.status())
+}
+
+/// Client that connects to the addresses checked here rather than
+/// resolving the host again, which could give a different answer the
+/// second time. Redirects aren't followed, as they could lead anywhere.
+async fn client_for(
+    allow_private_targets: bool,
+    url: &Url,
+) -> Result<reqwest::Client, anyhow::Error> {
+    let builder = reqwest::Client::builder()
+        .timeout(DELIVERY_TIMEOUT)
+        .redirect(redirect::Policy::none());
+    if allow_private_targets {
+        return builder.build().context("Failed to build HTTP client");
+    }
+
+    let host = url.host_str().context("Webhook URL has no host")?;
+    let port = url
+        .port_or_known_default()
+        .context("Webhook URL has no port")?;
+    // IPv6 hosts come in brackets
+    let builder = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => {
+            check_address(ip)?;
+            builder
+        }
+        Err(_) => {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+                .await
+                .context("Failed to resolve webhook host")?
+                .collect();
+            if addresses.is_empty() {
+                anyhow::bail!("Webhook host has no addresses");
+            }
+            for address in &addresses {
+                check_address(address.ip())?;
+            }
+            builder.resolve_to_addrs(host, &addresses)
+        }
+    };
+    builder.build().context("Failed to build HTTP client")
+}
+
+/// Frees a claimed delivery that wasn't attempted
+async fn release(
+    connection: &mut sqlx::PgConnection,
+    delivery_id: i64,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE outbox SET locked_until = NULL WHERE delivery_id = $1
+        "#,
+        delivery_id
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to release webhook delivery")?;
+    Ok(())
+}
+
+fn check_address(ip: IpAddr) -> Result<(), anyhow::Error> {
+    if !is_public(ip) {
+        anyhow::bail!("Webhook host resolves to a non-public address");
+    }
+    Ok(())
+}
+
+async fn record_success(
+    connection: &mut sqlx::PgConnection,
+    delivery_id: i64,
+    webhook_id: Uuid,
+    status: i16,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM outbox WHERE delivery_id = $1
+        "#,
+        delivery_id
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to remove delivered webhook")?;
+
+    sqlx::query!(
+        r#"
+        UPDATE webhooks SET
+            consecutive_failures = 0,
+            last_attempt_at = NOW(),
+            last_status = $2,
+            last_error = NULL
+        WHERE webhook_id = $1
+        "#,
+        webhook_id,
+        status
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to record webhook success")?;
+
+    Ok(())
+}
+
+/// Whether `ip` is reachable on the internet, as opposed to loopback,
+/// private networks and the like, where webhooks could reach services that
+/// aren't meant to be exposed
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // shared address space of carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(ip.into()),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // unique local, fc00::/7
+                    || first & 0xfe00 == 0xfc00
+                    // link local, fe80::/10
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::worker::webhook::is_public;
+
+    #[test]
+    pub fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} is public");
+        }
+    }
+
+    #[test]
+    pub fn internet_addresses_are_public() {
+        for ip in [
+            "93.184.216.34",
+            "1.1.1.1",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{ip} is not public");
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::helpers::{TestApp, logged_in_client, spawn_app};
+
+pub async fn make_admin(app: &TestApp, username: &str) {
+    sqlx::query!(
+        "UPDATE user_info SET role = 'admin' WHERE username = $1",
+        username
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+}
+
+async fn user_id(app: &TestApp, username: &str) -> uuid::Uuid {
+    sqlx::query_scalar!(
+        "SELECT user_id FROM user_info WHERE username = $1",
+        username
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn admin_pages_are_forbidden_for_ordinary_users() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    logged_in_client(&app, "bob").await;
+    let bob_id = user_id(&app, "bob").await;
+
+    let response = client
+        .get(format!("{}/admin", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+
+    let response = client
+        .post(format!("{}/admin/users/{}/lock", app.address, bob_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn admin_pages_redirect_anonymous_users_to_login() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let response = client
+        .get(format!("{}/admin", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(307, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn admins_can_list_and_search_users() {
+    let app = spawn_app().await;
+    let admin = logged_in_client(&app, "alice").await;
+    make_admin(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    bob.post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "buy milk")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    logged_in_client(&app, "carol").await;
+
+    let response = admin
+        .get(format!("{}/admin", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    for username in ["alice", "bob", "carol"] {
+        assert!(body.contains(username));
+    }
+
+    let body = admin
+        .get(format!("{}/admin?q=BOB@test", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("bob@test.com"));
+    assert!(!body.contains("carol"));
+    assert!(body.contains("<td>1</td>"));
+}
+
+#[tokio::test]
+async fn locked_users_are_logged_out_and_cannot_log_in() {
+    let app = spawn_app().await;
+    let admin = logged_in_client(&app, "alice").await;
+    make_admin(&app, "alice").await;
+    let bob = reqwest::Client::builder()
+        .cookie_store(true)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    logged_in_client(&app, "bob").await;
+    let login = |client: &reqwest::Client| {
+        client
+            .post(format!("{}/api/login", app.address))
+            .header("HX-Request", "true")
+            .form(&[
+                ("username", "bob"),
+                ("password", "correct horse battery staple"),
+            ])
+            .send()
+    };
+    assert_eq!(200, login(&bob).await.unwrap().status().as_u16());
+    let bob_id = user_id(&app, "bob").await;
+
+    let response = admin
+        .post(format!("{}/admin/users/{}/lock", app.address, bob_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = bob
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(307, response.status().as_u16());
+    assert_eq!(401, login(&bob).await.unwrap().status().as_u16());
+
+    let response = admin
+        .post(format!("{}/admin/users/{}/unlock", app.address, bob_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(200, login(&bob).await.unwrap().status().as_u16());
+}
+
+#[tokio::test]
+async fn admins_cannot_lock_themselves() {
+    let app = spawn_app().await;
+    let admin = logged_in_client(&app, "alice").await;
+    make_admin(&app, "alice").await;
+    let alice_id = user_id(&app, "alice").await;
+
+    let response = admin
+        .post(format!("{}/admin/users/{}/lock", app.address, alice_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn admins_see_when_scheduled_tasks_last_ran() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    make_admin(&app, "alice").await;
+
+    // the reminder task runs every second in tests
+    for _ in 0..50 {
+        let runs = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM scheduled_task WHERE name = 'due_date_reminders'"
+        )
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+        if runs == Some(1) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    let body = client
+        .get(format!("{}/admin", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("<td>due_date_reminders</td>"));
+    assert!(body.contains("Succeeded"));
+}
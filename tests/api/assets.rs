@@ -0,0 +1,46 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn embedded_stylesheet_is_served_with_an_etag() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .get(format!("{}/assets/css/todo.css", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("text/css", response.headers()["content-type"]);
+    assert!(response.headers().contains_key("cache-control"));
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    assert_eq!(
+        std::fs::read("assets/css/todo.css").unwrap(),
+        response.bytes().await.unwrap()
+    );
+
+    let response = app
+        .client
+        .get(format!("{}/assets/css/todo.css", app.address))
+        .header("If-None-Match", etag)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(304, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn missing_asset_is_not_found() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .get(format!("{}/assets/css/missing.css", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(404, response.status().as_u16());
+}
@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app};
+
+/// Audit entries are written in the background, so wait for them to show up
+pub async fn wait_for_events(app: &TestApp, event_type: &str, expected: i64) {
+    for _ in 0..50 {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM audit_log WHERE event_type = $1"#,
+            event_type
+        )
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+        if count >= expected {
+            assert_eq!(expected, count);
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Timed out waiting for {expected} {event_type} events");
+}
+
+#[tokio::test]
+async fn registration_and_logins_are_audited() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = app
+        .client
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .header("User-Agent", "curious-agent/1.0")
+        .form(&[("username", "alice"), ("password", "not the password")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(401, response.status().as_u16());
+
+    client
+        .get(format!("{}/logout", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    wait_for_events(&app, "registered", 1).await;
+    wait_for_events(&app, "login_succeeded", 1).await;
+    wait_for_events(&app, "login_failed", 1).await;
+    wait_for_events(&app, "logout", 1).await;
+
+    let failure = sqlx::query!(
+        r#"
+        SELECT user_id, ip_address, user_agent, metadata->>'username' AS username
+        FROM audit_log WHERE event_type = 'login_failed'
+        "#
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    assert_eq!(None, failure.user_id);
+    assert_eq!(Some("127.0.0.1"), failure.ip_address.as_deref());
+    assert_eq!(Some("curious-agent/1.0"), failure.user_agent.as_deref());
+    assert_eq!(Some("alice"), failure.username.as_deref());
+
+    // recorded for the id the database gave the account
+    let registered = sqlx::query_scalar!(
+        r#"
+        SELECT al.user_id FROM audit_log AS al
+        JOIN user_info AS ui ON ui.user_id = al.user_id
+        WHERE al.event_type = 'registered' AND ui.username = 'alice'
+        "#
+    )
+    .fetch_optional(&app.db)
+    .await
+    .unwrap();
+    assert!(registered.is_some());
+}
+
+#[tokio::test]
+async fn users_see_their_own_security_events_in_settings() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    logged_in_client(&app, "bob").await;
+    wait_for_events(&app, "login_succeeded", 2).await;
+
+    let body = client
+        .get(format!("{}/settings", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(1, body.matches("login_succeeded").count());
+    assert!(body.contains("registered"));
+}
+
+#[tokio::test]
+async fn admins_can_filter_the_audit_log() {
+    let app = spawn_app().await;
+    let admin = logged_in_client(&app, "alice").await;
+    sqlx::query!("UPDATE user_info SET role = 'admin' WHERE username = 'alice'")
+        .execute(&app.db)
+        .await
+        .unwrap();
+    logged_in_client(&app, "bob").await;
+    app.client
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[("username", "bob"), ("password", "not the password")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    wait_for_events(&app, "login_failed", 1).await;
+    wait_for_events(&app, "login_succeeded", 2).await;
+
+    let audit_page = |query: &'static str| {
+        admin
+            .get(format!("{}/admin/audit{}", app.address, query))
+            .send()
+    };
+
+    let body = audit_page("?user=bob").await.unwrap().text().await.unwrap();
+    assert!(body.contains("login_failed"));
+    assert!(body.contains("<td>bob</td>"));
+    assert!(!body.contains("<td>alice</td>"));
+
+    let body = audit_page("?event=login_failed")
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(!body.contains("<td>login_succeeded</td>"));
+
+    let response = audit_page("?event=made_up").await.unwrap();
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn audit_log_is_forbidden_for_ordinary_users() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = client
+        .get(format!("{}/admin/audit", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+}
@@ -1,4 +1,18 @@
-use crate::app::{TestApp, spawn_app};
+use std::time::Duration;
+
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHasher, Version,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::{
+    audit::wait_for_events,
+    helpers::{TestApp, assert_api_error, logged_in_client, spawn_app, spawn_app_with},
+};
 
 #[derive(serde::Serialize)]
 struct LoginFormData {
@@ -16,15 +30,18 @@ struct RegisterFormData {
 async fn register_user(app: &TestApp, params: RegisterFormData) -> reqwest::Response {
     app.client
         .post(format!("{}/api/register", app.address))
+        .header("HX-Request", "true")
         .form(&params)
         .send()
         .await
         .expect("Failed to execute request")
 }
 
+/// Logs in as a client that gets its errors as JSON, htmx gets the form back
 async fn login_user(app: &TestApp, params: LoginFormData) -> reqwest::Response {
     app.client
         .post(format!("{}/api/login", app.address))
+        .header("Accept", "application/json")
         .form(&params)
         .send()
         .await
@@ -69,7 +86,7 @@ async fn register_with_invalid_credentials_returns_400() {
         password: "correct horse battery staple".to_string(),
     };
     let response = register_user(&app, body_invalid_email).await;
-    assert_eq!(400, response.status().as_u16());
+    assert_api_error(response, 400, "invalid_email", Some("email")).await;
 
     let body_invalid_username = RegisterFormData {
         email: "test@test.com".to_string(),
@@ -77,7 +94,7 @@ async fn register_with_invalid_credentials_returns_400() {
         password: "correct horse battery staple".to_string(),
     };
     let response = register_user(&app, body_invalid_username).await;
-    assert_eq!(400, response.status().as_u16());
+    assert_api_error(response, 400, "invalid_username", Some("username")).await;
 
     let body_invalid_password = RegisterFormData {
         email: "test@test.com".to_string(),
@@ -85,7 +102,7 @@ async fn register_with_invalid_credentials_returns_400() {
         password: "hunter2".to_string(),
     };
     let response = register_user(&app, body_invalid_password).await;
-    assert_eq!(400, response.status().as_u16());
+    assert_api_error(response, 400, "invalid_password", Some("password")).await;
 }
 
 #[tokio::test]
@@ -106,7 +123,7 @@ async fn duplicate_user_name_or_email_returns_409() {
         password: "correct horse battery staple".to_string(),
     };
     let response = register_user(&app, body_duplicate_email).await;
-    assert_eq!(409, response.status().as_u16());
+    assert_api_error(response, 409, "email_taken", Some("email")).await;
 
     let body_duplicate_username = RegisterFormData {
         email: "test_different@test.com".to_string(),
@@ -114,7 +131,86 @@ async fn duplicate_user_name_or_email_returns_409() {
         password: "correct horse battery staple".to_string(),
     };
     let response = register_user(&app, body_duplicate_username).await;
-    assert_eq!(409, response.status().as_u16());
+    assert_api_error(response, 409, "username_taken", Some("username")).await;
+}
+
+#[tokio::test]
+async fn emails_and_usernames_differing_in_case_are_taken() {
+    let app = spawn_app().await;
+
+    let body = RegisterFormData {
+        email: "user@example.com".to_string(),
+        username: "testuser".to_string(),
+        password: "correct horse battery staple".to_string(),
+    };
+    let response = register_user(&app, body).await;
+    assert_eq!(201, response.status().as_u16());
+
+    let body = RegisterFormData {
+        email: "USER@EXAMPLE.COM".to_string(),
+        username: "otheruser".to_string(),
+        password: "correct horse battery staple".to_string(),
+    };
+    let response = register_user(&app, body).await;
+    assert_api_error(response, 409, "email_taken", Some("email")).await;
+
+    let body = RegisterFormData {
+        email: "other@example.com".to_string(),
+        username: "TestUser".to_string(),
+        password: "correct horse battery staple".to_string(),
+    };
+    let response = register_user(&app, body).await;
+    assert_api_error(response, 409, "username_taken", Some("username")).await;
+    assert_eq!(1, user_count(&app).await);
+}
+
+#[tokio::test]
+async fn capitals_cant_be_stored_past_the_app() {
+    let app = spawn_app().await;
+
+    let result = sqlx::query!(
+        "INSERT INTO user_info (username, email) VALUES ('testuser', 'User@Example.com')"
+    )
+    .execute(&app.db)
+    .await;
+    let error = result.unwrap_err();
+    assert_eq!(
+        Some("user_info_email_lowercase"),
+        error.as_database_error().and_then(|e| e.constraint())
+    );
+
+    let result = sqlx::query!(
+        "INSERT INTO user_info (username, email) VALUES ('TestUser', 'user@example.com')"
+    )
+    .execute(&app.db)
+    .await;
+    let error = result.unwrap_err();
+    assert_eq!(
+        Some("user_info_username_lowercase"),
+        error.as_database_error().and_then(|e| e.constraint())
+    );
+}
+
+#[tokio::test]
+async fn concurrent_registrations_of_a_username_let_only_one_through() {
+    let app = spawn_app().await;
+
+    let register = |email: &str| {
+        register_user(
+            &app,
+            RegisterFormData {
+                email: email.to_string(),
+                username: "testuser".to_string(),
+                password: "correct horse battery staple".to_string(),
+            },
+        )
+    };
+    let (first, second) = tokio::join!(register("first@test.com"), register("second@test.com"));
+
+    let mut statuses = [first.status().as_u16(), second.status().as_u16()];
+    statuses.sort();
+    assert_eq!([201, 409], statuses);
+    assert_eq!(1, user_count(&app).await);
 }
 
 #[tokio::test]
@@ -137,6 +233,58 @@ async fn login_with_valid_credentials_returns_200() {
     assert_eq!(200, response.status().as_u16());
 }
 
+/// Recorded in the background, so waits for it to be set after `after`
+async fn wait_for_last_login(
+    app: &TestApp,
+    after: Option<time::OffsetDateTime>,
+) -> time::OffsetDateTime {
+    for _ in 0..50 {
+        let last_login_at =
+            sqlx::query_scalar!("SELECT last_login_at FROM user_info WHERE username = 'testuser'")
+                .fetch_one(&app.db)
+                .await
+                .unwrap();
+        if let Some(last_login_at) = last_login_at
+            && after.is_none_or(|after| last_login_at > after)
+        {
+            return last_login_at;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Timed out waiting for the last login to advance");
+}
+
+#[tokio::test]
+async fn logins_advance_the_last_login() {
+    let app = spawn_app().await;
+
+    let register_body = RegisterFormData {
+        email: "test@test.com".to_string(),
+        username: "testuser".to_string(),
+        password: "correct horse battery staple".to_string(),
+    };
+    let response = register_user(&app, register_body).await;
+    assert_eq!(201, response.status().as_u16());
+    let last_login_at =
+        sqlx::query_scalar!("SELECT last_login_at FROM user_info WHERE username = 'testuser'")
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+    assert_eq!(None, last_login_at);
+
+    let login_body = || LoginFormData {
+        username: "testuser".to_string(),
+        password: "correct horse battery staple".to_string(),
+    };
+    let response = login_user(&app, login_body()).await;
+    assert_eq!(200, response.status().as_u16());
+    let first = wait_for_last_login(&app, None).await;
+
+    let response = login_user(&app, login_body()).await;
+    assert_eq!(200, response.status().as_u16());
+    wait_for_last_login(&app, Some(first)).await;
+}
+
 #[tokio::test]
 async fn login_with_incorrect_credentials_returns_401() {
     let app = spawn_app().await;
@@ -154,7 +302,7 @@ async fn login_with_incorrect_credentials_returns_401() {
         password: "hunter2".to_string(),
     };
     let response = login_user(&app, login_body).await;
-    assert_eq!(401, response.status().as_u16());
+    assert_api_error(response, 401, "invalid_credentials", None).await;
 }
 
 #[tokio::test]
@@ -166,5 +314,651 @@ async fn login_with_unregistered_credentials_returns_401() {
         password: "correct horse battery staple".to_string(),
     };
     let response = login_user(&app, login_body).await;
+    assert_api_error(response, 401, "invalid_credentials", None).await;
+}
+
+async fn register_with_form(app: &TestApp, fields: &[(&str, &str)]) -> reqwest::Response {
+    let mut form = vec![
+        ("email", "bot@example.com"),
+        ("username", "notabot"),
+        ("password", "correct horse battery staple"),
+    ];
+    form.extend_from_slice(fields);
+    app.client
+        .post(format!("{}/api/register", app.address))
+        .header("HX-Request", "true")
+        .form(&form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn user_count(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM user_info"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap()
+}
+
+/// The signed timestamp embedded in the registration form
+async fn form_token(app: &TestApp) -> String {
+    let body = app
+        .client
+        .get(format!("{}/register", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    let start = body
+        .find(r#"name="form_token" value=""#)
+        .expect("No form token")
+        + 25;
+    let end = start + body[start..].find('"').unwrap();
+    body[start..end].to_string()
+}
+
+#[tokio::test]
+async fn filled_in_honeypot_pretends_success_without_creating_the_account() {
+    let app = spawn_app().await;
+
+    let response = register_with_form(&app, &[("website", "http://spam.example")]).await;
+    assert_eq!(201, response.status().as_u16());
+    assert_eq!("/login", response.headers()["HX-Redirect"]);
+
+    assert_eq!(0, user_count(&app).await);
+    wait_for_events(&app, "registration_blocked", 1).await;
+}
+
+#[tokio::test]
+async fn honeypot_can_be_turned_off() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.registration_honeypot_enabled = false;
+    })
+    .await;
+
+    let response = register_with_form(&app, &[("website", "http://example.com")]).await;
+    assert_eq!(201, response.status().as_u16());
+    assert_eq!(1, user_count(&app).await);
+}
+
+#[tokio::test]
+async fn form_sent_too_quickly_is_rejected() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.registration_min_fill_secs = 2;
+    })
+    .await;
+
+    let token = form_token(&app).await;
+    let response = register_with_form(&app, &[("form_token", &token)]).await;
+    assert_api_error(response, 400, "submitted_too_quickly", None).await;
+
+    assert_eq!(0, user_count(&app).await);
+    wait_for_events(&app, "registration_blocked", 1).await;
+}
+
+#[tokio::test]
+async fn form_sent_after_the_minimum_fill_time_is_accepted() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.registration_min_fill_secs = 1;
+    })
+    .await;
+
+    let token = form_token(&app).await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let response = register_with_form(&app, &[("form_token", &token)]).await;
+    assert_eq!(201, response.status().as_u16());
+    assert_eq!(1, user_count(&app).await);
+}
+
+#[tokio::test]
+async fn missing_or_forged_form_token_is_rejected() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.registration_min_fill_secs = 1;
+    })
+    .await;
+
+    let response = register_with_form(&app, &[]).await;
+    assert_api_error(response, 400, "invalid_form_token", None).await;
+
+    let response = register_with_form(&app, &[("form_token", "0.deadbeef")]).await;
+    assert_api_error(response, 400, "invalid_form_token", None).await;
+
+    assert_eq!(0, user_count(&app).await);
+    wait_for_events(&app, "registration_blocked", 2).await;
+}
+
+/// A client keeping the session the registration form was served in, and
+/// the single-use token of that form
+async fn served_register_form(app: &TestApp) -> (reqwest::Client, String) {
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let body = client
+        .get(format!("{}/register", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    let start = body
+        .find(r#"name="submission_token" value=""#)
+        .expect("No submission token")
+        + 31;
+    let end = start + body[start..].find('"').unwrap();
+    (client, body[start..end].to_string())
+}
+
+async fn send_register_form(
+    client: &reqwest::Client,
+    app: &TestApp,
+    token: &str,
+    password: &str,
+) -> reqwest::Response {
+    client
+        .post(format!("{}/api/register", app.address))
+        .header("HX-Request", "true")
+        .form(&[
+            ("email", "alice@test.com"),
+            ("username", "alice"),
+            ("password", password),
+            ("submission_token", token),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn registration_form_sent_twice_at_once_registers_once() {
+    let app = spawn_app().await;
+    let (client, token) = served_register_form(&app).await;
+
+    let password = "correct horse battery staple";
+    let (first, second) = tokio::join!(
+        send_register_form(&client, &app, &token, password),
+        send_register_form(&client, &app, &token, password)
+    );
+
+    let mut statuses = [first.status().as_u16(), second.status().as_u16()];
+    statuses.sort();
+    assert_eq!([201, 409], statuses);
+    assert_eq!(1, user_count(&app).await);
+
+    // used up by the registration
+    let response = send_register_form(&client, &app, &token, password).await;
+    assert_api_error(response, 400, "invalid_form_token", None).await;
+}
+
+#[tokio::test]
+async fn failed_registration_gives_the_form_back() {
+    let app = spawn_app().await;
+    let (client, token) = served_register_form(&app).await;
+
+    let response = send_register_form(&client, &app, &token, "hunter2").await;
+    assert_api_error(response, 400, "invalid_password", Some("password")).await;
+
+    let response = send_register_form(&client, &app, &token, "correct horse battery staple").await;
+    assert_eq!(201, response.status().as_u16());
+    assert_eq!(1, user_count(&app).await);
+}
+
+#[tokio::test]
+async fn registration_forms_can_be_sent_twice_when_single_use_forms_are_off() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.registration_single_use_forms = false;
+    })
+    .await;
+    let body = app
+        .client
+        .get(format!("{}/register", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(!body.contains("submission_token"));
+
+    let client = reqwest::Client::new();
+    let password = "correct horse battery staple";
+    let response = send_register_form(&client, &app, "not checked", password).await;
+    assert_eq!(201, response.status().as_u16());
+    // the same form again, which finds the account taken
+    let response = send_register_form(&client, &app, "not checked", password).await;
+    assert_api_error(response, 409, "email_taken", Some("email")).await;
+}
+
+async fn register_alice_twice(app: &TestApp, second_username: &str) -> reqwest::Response {
+    let body = RegisterFormData {
+        email: "alice@test.com".to_string(),
+        username: "alice".to_string(),
+        password: "correct horse battery staple".to_string(),
+    };
+    let response = register_user(app, body).await;
+    assert_eq!(201, response.status().as_u16());
+
+    let body = RegisterFormData {
+        email: "alice@test.com".to_string(),
+        username: second_username.to_string(),
+        password: "correct horse battery staple".to_string(),
+    };
+    register_user(app, body).await
+}
+
+#[tokio::test]
+async fn privacy_mode_hides_that_an_email_is_registered() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.registration_privacy_mode = Some(true);
+    })
+    .await;
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = register_alice_twice(&app, "notalice").await;
+    assert_eq!(201, response.status().as_u16());
+    assert_eq!("/login", response.headers()["HX-Redirect"]);
+    assert_eq!(1, user_count(&app).await);
+
+    let email = app.last_email_to("alice@test.com").await;
+    assert!(email.text_body.contains("already has one"));
+}
+
+#[tokio::test]
+async fn privacy_mode_still_reports_taken_usernames() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.registration_privacy_mode = Some(true);
+    })
+    .await;
+
+    let response = register_alice_twice(&app, "alice").await;
+    assert_api_error(response, 409, "username_taken", Some("username")).await;
+}
+
+#[tokio::test]
+async fn without_privacy_mode_taken_emails_are_reported() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.registration_privacy_mode = Some(false);
+    })
+    .await;
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = register_alice_twice(&app, "notalice").await;
+    assert_api_error(response, 409, "email_taken", Some("email")).await;
+}
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query_scalar!("SELECT password_hash FROM user_password")
+        .fetch_one(&app.db)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn weak_password_hash_is_upgraded_on_login() {
+    let app = spawn_app().await;
+    let body = RegisterFormData {
+        email: "alice@test.com".to_string(),
+        username: "alice".to_string(),
+        password: "correct horse battery staple".to_string(),
+    };
+    register_user(&app, body).await;
+
+    let weak_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(64, 1, 1, None).unwrap(),
+    )
+    .hash_password(
+        b"correct horse battery staple",
+        &SaltString::generate(&mut OsRng),
+    )
+    .unwrap()
+    .to_string();
+    sqlx::query!("UPDATE user_password SET password_hash = $1", weak_hash)
+        .execute(&app.db)
+        .await
+        .unwrap();
+
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = client
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[
+            ("username", "alice"),
+            ("password", "correct horse battery staple"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let hash = stored_password_hash(&app).await;
+    assert_ne!(weak_hash, hash);
+    assert!(hash.contains("m=19456,t=2,p=1"));
+
+    // the session was made for the new hash, so the upgrade doesn't end it
+    let response = client
+        .get(format!("{}/settings", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!("/settings", response.url().path());
+}
+
+#[tokio::test]
+async fn current_password_hash_is_left_alone_on_login() {
+    let app = spawn_app().await;
+    let body = RegisterFormData {
+        email: "alice@test.com".to_string(),
+        username: "alice".to_string(),
+        password: "correct horse battery staple".to_string(),
+    };
+    register_user(&app, body).await;
+    let hash = stored_password_hash(&app).await;
+
+    let login_body = LoginFormData {
+        username: "alice".to_string(),
+        password: "correct horse battery staple".to_string(),
+    };
+    let response = login_user(&app, login_body).await;
+    assert_eq!(200, response.status().as_u16());
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(hash, stored_password_hash(&app).await);
+}
+
+/// Logs alice in `count` times at once and returns the statuses
+async fn concurrent_logins(app: &TestApp, count: usize) -> Vec<u16> {
+    let logins: Vec<_> = (0..count)
+        .map(|_| {
+            let url = format!("{}/api/login", app.address);
+            tokio::spawn(async move {
+                reqwest::Client::new()
+                    .post(url)
+                    .header("HX-Request", "true")
+                    .form(&[
+                        ("username", "alice"),
+                        ("password", "correct horse battery staple"),
+                    ])
+                    .send()
+                    .await
+                    .expect("Failed to execute request")
+                    .status()
+                    .as_u16()
+            })
+        })
+        .collect();
+
+    let mut statuses = Vec::new();
+    for login in logins {
+        statuses.push(login.await.unwrap());
+    }
+    statuses
+}
+
+#[tokio::test]
+async fn logins_posted_without_htmx_redirect_home() {
+    let app = spawn_app().await;
+    register_alice(&app).await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let login = |htmx: bool| {
+        let mut request = client.post(format!("{}/api/login", app.address)).form(&[
+            ("username", "alice"),
+            ("password", "correct horse battery staple"),
+        ]);
+        if htmx {
+            request = request.header("HX-Request", "true");
+        }
+        request.send()
+    };
+
+    let response = login(false).await.expect("Failed to execute request");
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!("/", response.headers()["Location"].to_str().unwrap());
+    assert!(response.headers().get("HX-Redirect").is_none());
+
+    let response = login(true).await.expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("/", response.headers()["HX-Redirect"].to_str().unwrap());
+    assert!(response.headers().get("Location").is_none());
+}
+
+#[tokio::test]
+async fn failed_logins_posted_without_htmx_show_the_login_page_again() {
+    let app = spawn_app().await;
+    register_alice(&app).await;
+
+    let response = app
+        .client
+        .post(format!("{}/api/login", app.address))
+        .form(&[("username", "alice"), ("password", "wrong password")])
+        .send()
+        .await
+        .expect("Failed to execute request");
     assert_eq!(401, response.status().as_u16());
+    assert!(
+        response.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html")
+    );
+    let body = response.text().await.unwrap();
+    assert!(body.contains(r#"<form method="post" action="/api/login""#));
+    assert!(body.contains("Invalid credentials"));
+}
+
+#[tokio::test]
+async fn registrations_missing_a_field_say_which() {
+    let app = spawn_app().await;
+    let form = [("username", "alice"), ("password", "correct horse")];
+
+    let response = app
+        .client
+        .post(format!("{}/api/register", app.address))
+        .header("HX-Request", "true")
+        .form(&form)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(422, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("invalid_form", body["code"]);
+    assert_eq!(
+        serde_json::json!([{
+            "field": "email",
+            "code": "missing",
+            "message": "This field is required"
+        }]),
+        body["errors"]
+    );
+
+    // browsers get the form back, filled in but for the password
+    let response = app
+        .client
+        .post(format!("{}/api/register", app.address))
+        .form(&form)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(422, response.status().as_u16());
+    let page = response.text().await.unwrap();
+    assert!(page.contains(
+        r#"<input type="email" id="email" name="email" value="" required><span class="field-error">This field is required</span>"#
+    ));
+    assert!(page.contains(r#"name="username" value="alice""#));
+    assert!(!page.contains("correct horse"));
+    assert_eq!(0, user_count(&app).await);
+}
+
+#[tokio::test]
+async fn logins_missing_the_password_get_the_form_back() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[("username", "alice")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(422, response.status().as_u16());
+    let form = response.text().await.unwrap();
+    assert!(form.contains(r#"value="alice""#));
+    assert!(form.contains(
+        r#"name="password" required><span class="field-error">This field is required</span>"#
+    ));
+
+    let response = app
+        .client
+        .post(format!("{}/api/login", app.address))
+        .header("Accept", "application/json")
+        .form(&[("username", "alice")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(422, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("password", body["errors"][0]["field"]);
+}
+
+async fn register_alice(app: &TestApp) {
+    let body = RegisterFormData {
+        email: "alice@test.com".to_string(),
+        username: "alice".to_string(),
+        password: "correct horse battery staple".to_string(),
+    };
+    let response = register_user(app, body).await;
+    assert_eq!(201, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn logins_beyond_the_hashing_permits_wait_their_turn() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.password_hashing_permits = Some(1);
+    })
+    .await;
+    register_alice(&app).await;
+
+    let statuses = concurrent_logins(&app, 4).await;
+    assert_eq!(vec![200; 4], statuses);
+}
+
+#[tokio::test]
+async fn logins_waiting_too_long_for_hashing_get_503() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.password_hashing_permits = Some(1);
+        config
+            .application_settings
+            .password_hashing_queue_timeout_millis = 1;
+    })
+    .await;
+    register_alice(&app).await;
+
+    let statuses = concurrent_logins(&app, 8).await;
+    assert!(statuses.contains(&200));
+    assert!(statuses.contains(&503));
+    assert!(statuses.iter().all(|status| [200, 503].contains(status)));
+}
+
+#[tokio::test]
+async fn register_page_is_translated_for_accept_language() {
+    let app = spawn_app().await;
+    let get_page = |accept_language: &'static str| {
+        app.client
+            .get(format!("{}/register", app.address))
+            .header("Accept-Language", accept_language)
+            .send()
+    };
+
+    let german = get_page("de-DE,de;q=0.9,en;q=0.8")
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(german.contains(r#"<html lang="de" "#));
+    assert!(german.contains("<title>Registrieren</title>"));
+    assert!(german.contains("Benutzername"));
+    assert!(german.contains("Passwort"));
+
+    // unsupported languages get English
+    let english = get_page("fr")
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(english.contains("<title>Register</title>"));
+    assert!(english.contains("Username"));
+}
+
+#[tokio::test]
+async fn registration_errors_are_translated() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .post(format!("{}/api/register", app.address))
+        .header("HX-Request", "true")
+        .header("Accept-Language", "de")
+        .form(&RegisterFormData {
+            email: "alice@test.com".to_string(),
+            username: "alice/".to_string(),
+            password: "correct horse battery staple".to_string(),
+        })
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("invalid_username", body["code"]);
+    assert_eq!("Ungültiger Benutzername", body["message"]);
+}
+
+#[tokio::test]
+async fn homepage_shows_who_is_logged_in() {
+    let app = spawn_app().await;
+    let homepage = |client: reqwest::Client| {
+        let address = app.address.clone();
+        async move {
+            client
+                .get(format!("{address}/"))
+                .send()
+                .await
+                .expect("Failed to execute request")
+                .text()
+                .await
+                .unwrap()
+        }
+    };
+
+    let anonymous = homepage(app.client.clone()).await;
+    assert!(anonymous.contains(r#"<a href="/login">Login</a>"#));
+    assert!(anonymous.contains(r#"<a href="/register">Register</a>"#));
+    assert!(!anonymous.contains("Logged in as"));
+
+    let alice = homepage(logged_in_client(&app, "alice").await).await;
+    assert!(alice.contains(r#"Logged in as alice / <a href="/logout">Logout</a>"#));
+    assert!(!alice.contains(r#"<a href="/login">"#));
 }
@@ -0,0 +1,197 @@
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use reqwest::multipart::{Form, Part};
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app};
+
+/// Two 1×1 frames that loop forever
+const ANIMATED_GIF: &[u8] = &[
+    b'G', b'I', b'F', b'8', b'9', b'a', 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xff, 0x0b, b'N', b'E', b'T', b'S', b'C', b'A', b'P', b'E', b'2', b'.',
+    b'0', 0x03, 0x01, 0x00, 0x00, 0x00, 0x21, 0xf9, 0x04, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x2c, 0x00,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x21, 0xf9, 0x04,
+    0x00, 0x0a, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02,
+    0x02, 0x4c, 0x01, 0x00, 0x3b,
+];
+
+fn fixture_image(width: u32, height: u32, color: [u8; 3], format: ImageFormat) -> Vec<u8> {
+    let image = RgbImage::from_pixel(width, height, Rgb(color));
+    let mut bytes = Vec::new();
+    DynamicImage::ImageRgb8(image)
+        .write_to(&mut Cursor::new(&mut bytes), format)
+        .unwrap();
+    bytes
+}
+
+async fn upload_avatar(
+    app: &TestApp,
+    client: &reqwest::Client,
+    bytes: Vec<u8>,
+    mime: &str,
+) -> reqwest::Response {
+    let form = Form::new().part(
+        "avatar",
+        Part::bytes(bytes)
+            .file_name("avatar")
+            .mime_str(mime)
+            .unwrap(),
+    );
+
+    client
+        .post(format!("{}/settings/avatar", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn stored_avatar(app: &TestApp, username: &str) -> Option<String> {
+    sqlx::query_scalar!("SELECT avatar FROM user_info WHERE username = $1", username)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to get avatar")
+}
+
+fn avatar_file_exists(app: &TestApp, filename: &str) -> bool {
+    app.upload_dir
+        .join("avatars")
+        .join(&filename[..2])
+        .join(filename)
+        .exists()
+}
+
+#[tokio::test]
+async fn uploaded_avatars_are_resized_and_served() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    for format in [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP] {
+        let bytes = fixture_image(300, 200, [200, 30, 30], format);
+        let response = upload_avatar(&app, &client, bytes, format.to_mime_type()).await;
+        assert_eq!(200, response.status().as_u16());
+
+        let filename = stored_avatar(&app, "alice").await.unwrap();
+        assert!(avatar_file_exists(&app, &filename));
+
+        // avatars are public, so no session is needed
+        let response = app
+            .client
+            .get(format!("{}/avatars/{filename}", app.address))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!("image/png", response.headers()["content-type"]);
+        assert_eq!(
+            "public, max-age=31536000, immutable",
+            response.headers()["cache-control"]
+        );
+
+        let image = image::load_from_memory(&response.bytes().await.unwrap()).unwrap();
+        assert_eq!((256, 256), (image.width(), image.height()));
+    }
+}
+
+#[tokio::test]
+async fn content_type_is_not_trusted() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = upload_avatar(&app, &client, b"<svg></svg>".to_vec(), "image/png").await;
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(None, stored_avatar(&app, "alice").await);
+}
+
+#[tokio::test]
+async fn animated_gifs_are_rejected() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = upload_avatar(&app, &client, ANIMATED_GIF.to_vec(), "image/gif").await;
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(None, stored_avatar(&app, "alice").await);
+}
+
+#[tokio::test]
+async fn oversized_images_are_rejected() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let bytes = fixture_image(5000, 1, [0, 0, 0], ImageFormat::Png);
+    let response = upload_avatar(&app, &client, bytes, "image/png").await;
+    assert_eq!(400, response.status().as_u16());
+
+    let mut bytes = fixture_image(1, 1, [0, 0, 0], ImageFormat::Png);
+    bytes.resize(2 * 1024 * 1024 + 1, 0);
+    let response = upload_avatar(&app, &client, bytes, "image/png").await;
+    assert_eq!(413, response.status().as_u16());
+
+    assert_eq!(None, stored_avatar(&app, "alice").await);
+}
+
+#[tokio::test]
+async fn replacing_and_deleting_avatars_removes_old_files() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let bytes = fixture_image(64, 64, [255, 0, 0], ImageFormat::Png);
+    upload_avatar(&app, &client, bytes, "image/png").await;
+    let first = stored_avatar(&app, "alice").await.unwrap();
+
+    let bytes = fixture_image(64, 64, [0, 0, 255], ImageFormat::Png);
+    upload_avatar(&app, &client, bytes, "image/png").await;
+    let second = stored_avatar(&app, "alice").await.unwrap();
+    assert_ne!(first, second);
+    assert!(!avatar_file_exists(&app, &first));
+    assert!(avatar_file_exists(&app, &second));
+
+    let response = client
+        .delete(format!("{}/settings/avatar", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(None, stored_avatar(&app, "alice").await);
+    assert!(!avatar_file_exists(&app, &second));
+}
+
+#[tokio::test]
+async fn shared_avatar_files_are_kept_while_in_use() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+
+    let bytes = fixture_image(64, 64, [0, 255, 0], ImageFormat::Png);
+    upload_avatar(&app, &alice, bytes.clone(), "image/png").await;
+    upload_avatar(&app, &bob, bytes, "image/png").await;
+    let filename = stored_avatar(&app, "alice").await.unwrap();
+    assert_eq!(Some(&filename), stored_avatar(&app, "bob").await.as_ref());
+
+    alice
+        .delete(format!("{}/settings/avatar", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert!(avatar_file_exists(&app, &filename));
+}
+
+#[tokio::test]
+async fn unknown_avatars_are_not_found() {
+    let app = spawn_app().await;
+
+    for filename in [
+        format!("{}.png", "a".repeat(64)),
+        "..%2F..%2FCargo.toml".to_string(),
+        format!("{}.png", Uuid::new_v4()),
+    ] {
+        let response = app
+            .client
+            .get(format!("{}/avatars/{filename}", app.address))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(404, response.status().as_u16());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{
+    helpers::{TestApp, logged_in_client, spawn_app, spawn_app_with},
+    webhooks::add_webhook,
+};
+
+async fn add_batch(app: &TestApp, client: &reqwest::Client, text: &str) -> reqwest::Response {
+    client
+        .post(format!("{}/todo/batch", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", text)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn todo_contents(app: &TestApp) -> Vec<String> {
+    sqlx::query_scalar!("SELECT todo_content FROM todo ORDER BY todo_content")
+        .fetch_all(&app.db)
+        .await
+        .expect("Failed to fetch todos")
+}
+
+#[tokio::test]
+async fn each_line_becomes_a_todo_and_invalid_lines_are_reported() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let too_long = "x".repeat(1025);
+    let text = format!("buy milk\n\n  walk the dog\n{too_long}\r\ncall mom\n");
+    let response = add_batch(&app, &alice, &text).await;
+    assert_eq!(201, response.status().as_u16());
+    let trigger = response.headers()["HX-Trigger"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(trigger.contains(r#""active":3"#), "{trigger}");
+
+    let body = response.text().await.unwrap();
+    assert_eq!(3, body.matches("<tr").count());
+    for todo in ["buy milk", "walk the dog", "call mom"] {
+        assert!(body.contains(todo), "{todo} isn't in {body}");
+    }
+    assert!(body.contains(r#"<div id="batch-summary" hx-swap-oob="true">"#));
+    assert!(body.contains("Added 3 todos."));
+    assert!(body.contains("Skipped 2 lines:"));
+    assert!(body.contains("Line 2: Todo is empty"));
+    assert!(body.contains("Line 4: Todo is too long"));
+
+    assert_eq!(
+        vec!["buy milk", "call mom", "walk the dog"],
+        todo_contents(&app).await
+    );
+    let events: i64 = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM todo_events WHERE payload->>'kind' = 'created'"#
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    assert_eq!(3, events);
+}
+
+#[tokio::test]
+async fn batches_over_the_line_cap_add_nothing() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = add_batch(&app, &alice, &vec!["buy milk"; 101].join("\n")).await;
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        "Too many lines, the maximum is 100",
+        response.text().await.unwrap()
+    );
+    assert!(todo_contents(&app).await.is_empty());
+
+    let response = add_batch(&app, &alice, &vec!["buy milk"; 100].join("\n")).await;
+    assert_eq!(201, response.status().as_u16());
+    assert_eq!(100, todo_contents(&app).await.len());
+}
+
+#[tokio::test]
+async fn batches_are_added_whole_or_not_at_all_under_the_limit() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    sqlx::query!("UPDATE user_info SET todo_limit = 2 WHERE username = 'alice'")
+        .execute(&app.db)
+        .await
+        .unwrap();
+
+    let response = add_batch(&app, &alice, "buy milk\nwalk the dog\ncall mom").await;
+    assert_eq!(422, response.status().as_u16());
+    assert!(todo_contents(&app).await.is_empty());
+
+    let response = add_batch(&app, &alice, "buy milk\nwalk the dog").await;
+    assert_eq!(201, response.status().as_u16());
+    assert_eq!(2, todo_contents(&app).await.len());
+}
+
+#[tokio::test]
+async fn batches_can_be_sent_as_json() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = alice
+        .post(format!("{}/todo/batch", app.address))
+        .header("Accept", "application/json")
+        .form(&[("todo_content", "buy milk\n ")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+    let batch: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("buy milk", batch["todos"][0]["todo_content"]);
+    assert_eq!(1, batch["todos"].as_array().unwrap().len());
+    assert_eq!(
+        serde_json::json!([{ "line": 2, "reason": "Todo is empty" }]),
+        batch["skipped"]
+    );
+}
+
+#[tokio::test]
+async fn each_added_line_queues_a_webhook() {
+    // nothing is delivered, so what was queued stays in the outbox
+    let app = spawn_app_with(|config| {
+        config.application_settings.webhook_interval_secs = 3600;
+    })
+    .await;
+    let alice = logged_in_client(&app, "alice").await;
+    add_webhook(&app, &alice, "https://example.com/hooks", &["todo_created"]).await;
+
+    let response = add_batch(&app, &alice, "buy milk\n\nwalk the dog\ncall mom").await;
+    assert_eq!(201, response.status().as_u16());
+
+    let queued = sqlx::query_scalar!(
+        r#"
+        SELECT payload->'todo'->>'content' AS "content!" FROM outbox
+        WHERE event = 'todo.created'
+        ORDER BY payload->'todo'->>'content'
+        "#
+    )
+    .fetch_all(&app.db)
+    .await
+    .unwrap();
+    assert_eq!(vec!["buy milk", "call mom", "walk the dog"], queued);
+}
@@ -0,0 +1,246 @@
+use jsonwebtoken::{EncodingKey, Header};
+use secrecy::SecretString;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, assert_api_error, logged_in_client, spawn_app, spawn_app_with};
+
+const SIGNING_KEY: &str = "test signing key that is at least 32 bytes long";
+const OLD_KEY: &str = "old signing key that is still at least 32 bytes";
+
+async fn spawn_app_with_tokens() -> TestApp {
+    spawn_app_with(|config| {
+        config.application_settings.jwt_signing_key = Some(SecretString::from(SIGNING_KEY));
+        config.application_settings.jwt_verification_keys = Some(SecretString::from(OLD_KEY));
+    })
+    .await
+}
+
+async fn request_token(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/api/v1/auth/token", app.address))
+        .form(&[("username", username), ("password", password)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// Registers the user and returns the body with their tokens
+async fn tokens_for(app: &TestApp, username: &str) -> serde_json::Value {
+    logged_in_client(app, username).await;
+    let response = request_token(app, username, "correct horse battery staple").await;
+    assert_eq!(200, response.status().as_u16());
+    response.json().await.expect("Failed to parse tokens")
+}
+
+async fn post_refresh(app: &TestApp, path: &str, refresh_token: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/api/v1/auth/{}", app.address, path))
+        .form(&[("refresh_token", refresh_token)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn get_todos(app: &TestApp, access_token: &str) -> reqwest::Response {
+    app.client
+        .get(format!("{}/api/todo", app.address))
+        .bearer_auth(access_token)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn user_id(app: &TestApp, username: &str) -> Uuid {
+    sqlx::query_scalar!(
+        "SELECT user_id FROM user_info WHERE username = $1",
+        username
+    )
+    .fetch_one(&app.db)
+    .await
+    .expect("Failed to get user")
+}
+
+fn sign(key: &str, user_id: Uuid, expires_in: i64) -> String {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    jsonwebtoken::encode(
+        &Header::default(),
+        &serde_json::json!({ "sub": user_id, "iat": now, "exp": now + expires_in }),
+        &EncodingKey::from_secret(key.as_bytes()),
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn access_token_authenticates_api_requests() {
+    let app = spawn_app_with_tokens().await;
+    let tokens = tokens_for(&app, "alice").await;
+
+    assert_eq!("Bearer", tokens["token_type"]);
+    assert_eq!(15 * 60, tokens["expires_in"]);
+    let response = get_todos(&app, tokens["access_token"].as_str().unwrap()).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn token_is_refused_for_wrong_credentials() {
+    let app = spawn_app_with_tokens().await;
+    logged_in_client(&app, "alice").await;
+
+    let response = request_token(&app, "alice", "not the password").await;
+
+    assert_api_error(response, 401, "invalid_credentials", None).await;
+}
+
+#[tokio::test]
+async fn expired_access_token_is_rejected() {
+    let app = spawn_app_with_tokens().await;
+    logged_in_client(&app, "alice").await;
+    let token = sign(SIGNING_KEY, user_id(&app, "alice").await, -1);
+
+    let response = get_todos(&app, &token).await;
+
+    assert_api_error(response, 401, "invalid_access_token", None).await;
+}
+
+#[tokio::test]
+async fn tampered_access_token_is_rejected() {
+    let app = spawn_app_with_tokens().await;
+    let tokens = tokens_for(&app, "alice").await;
+    logged_in_client(&app, "bob").await;
+
+    // bob's claims with alice's signature
+    let bob_token = sign(
+        "some other key that is 32 bytes long",
+        user_id(&app, "bob").await,
+        60,
+    );
+    let (_, alice_signature) = tokens["access_token"]
+        .as_str()
+        .unwrap()
+        .rsplit_once('.')
+        .unwrap();
+    let (bob_claims, _) = bob_token.rsplit_once('.').unwrap();
+    let response = get_todos(&app, &format!("{bob_claims}.{alice_signature}")).await;
+    assert_api_error(response, 401, "invalid_access_token", None).await;
+
+    let response = get_todos(&app, "not.a.token").await;
+    assert_api_error(response, 401, "invalid_access_token", None).await;
+}
+
+#[tokio::test]
+async fn access_token_signed_with_a_previous_key_is_accepted() {
+    let app = spawn_app_with_tokens().await;
+    logged_in_client(&app, "alice").await;
+    let token = sign(OLD_KEY, user_id(&app, "alice").await, 60);
+
+    let response = get_todos(&app, &token).await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn refresh_token_can_only_be_used_once() {
+    let app = spawn_app_with_tokens().await;
+    let tokens = tokens_for(&app, "alice").await;
+    let refresh_token = tokens["refresh_token"].as_str().unwrap();
+
+    let response = post_refresh(&app, "refresh", refresh_token).await;
+    assert_eq!(200, response.status().as_u16());
+    let refreshed: serde_json::Value = response.json().await.unwrap();
+    assert_ne!(tokens["refresh_token"], refreshed["refresh_token"]);
+    let response = get_todos(&app, refreshed["access_token"].as_str().unwrap()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let response = post_refresh(&app, "refresh", refresh_token).await;
+    assert_api_error(
+        response,
+        401,
+        "invalid_refresh_token",
+        Some("refresh_token"),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn revoked_refresh_token_is_rejected() {
+    let app = spawn_app_with_tokens().await;
+    let tokens = tokens_for(&app, "alice").await;
+    let refresh_token = tokens["refresh_token"].as_str().unwrap();
+
+    let response = post_refresh(&app, "logout", refresh_token).await;
+    assert_eq!(204, response.status().as_u16());
+
+    let response = post_refresh(&app, "refresh", refresh_token).await;
+    assert_api_error(
+        response,
+        401,
+        "invalid_refresh_token",
+        Some("refresh_token"),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn refresh_tokens_are_revoked_when_the_user_is_locked() {
+    let app = spawn_app_with_tokens().await;
+    let tokens = tokens_for(&app, "alice").await;
+    let admin = logged_in_client(&app, "carol").await;
+    sqlx::query!("UPDATE user_info SET role = 'admin' WHERE username = 'carol'")
+        .execute(&app.db)
+        .await
+        .unwrap();
+
+    let response = admin
+        .post(format!(
+            "{}/admin/users/{}/lock",
+            app.address,
+            user_id(&app, "alice").await
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = get_todos(&app, tokens["access_token"].as_str().unwrap()).await;
+    assert_api_error(response, 401, "invalid_access_token", None).await;
+    let response = post_refresh(&app, "refresh", tokens["refresh_token"].as_str().unwrap()).await;
+    assert_api_error(
+        response,
+        401,
+        "invalid_refresh_token",
+        Some("refresh_token"),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn token_routes_are_not_found_without_a_signing_key() {
+    let app = spawn_app().await;
+    logged_in_client(&app, "alice").await;
+
+    let response = request_token(&app, "alice", "correct horse battery staple").await;
+    assert_api_error(response, 404, "not_found", None).await;
+
+    let response = get_todos(&app, "not.a.token").await;
+    assert_api_error(response, 401, "invalid_access_token", None).await;
+}
+
+#[tokio::test]
+async fn deactivated_accounts_are_told_so_only_with_the_right_password() {
+    let app = spawn_app_with_tokens().await;
+    let tokens = tokens_for(&app, "alice").await;
+    sqlx::query!("UPDATE user_info SET deactivated_at = NOW() WHERE username = 'alice'")
+        .execute(&app.db)
+        .await
+        .unwrap();
+
+    let response = request_token(&app, "alice", "wrong password").await;
+    assert_api_error(response, 401, "invalid_credentials", None).await;
+
+    let response = request_token(&app, "alice", "correct horse battery staple").await;
+    assert_api_error(response, 403, "account_deactivated", None).await;
+
+    let access_token = tokens["access_token"].as_str().unwrap();
+    assert_eq!(401, get_todos(&app, access_token).await.status().as_u16());
+}
@@ -0,0 +1,177 @@
+use std::io::BufReader;
+
+use ical::parser::ical::component::{IcalCalendar, IcalEvent};
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app};
+
+/// Makes a new feed URL and returns its path, as the URL has the configured
+/// base URL rather than the test app's address
+async fn rotate_feed(app: &TestApp, client: &reqwest::Client) -> String {
+    let response = client
+        .post(format!("{}/settings/calendar-feed", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    let start = body.find("/calendar/").expect("Missing feed URL");
+    let end = body.find(".ics").expect("Missing feed URL") + ".ics".len();
+    body[start..end].to_string()
+}
+
+async fn get_feed(app: &TestApp, path: &str) -> reqwest::Response {
+    app.client
+        .get(format!("{}{}", app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn create_todo_due_in(
+    app: &TestApp,
+    client: &reqwest::Client,
+    content: &str,
+    days: Option<i32>,
+) -> String {
+    let mut form = vec![("todo_content", content.to_string())];
+    if let Some(days) = days {
+        let due_date: String = sqlx::query_scalar!(
+            r#"SELECT to_char(CURRENT_DATE + $1::int, 'YYYY-MM-DD') AS "due_date!""#,
+            days
+        )
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to compute due date");
+        form.push(("due_date", due_date));
+    }
+    let response = client
+        .post(format!("{}/todo", app.address))
+        .header("Accept", "application/json")
+        .form(&form)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    let todo: serde_json::Value = response.json().await.expect("Failed to parse todo");
+    todo["todo_id"].as_str().unwrap().to_string()
+}
+
+fn parse(body: &str) -> IcalCalendar {
+    let mut calendars = ical::IcalParser::new(BufReader::new(body.as_bytes()));
+    let calendar = calendars
+        .next()
+        .expect("Missing calendar")
+        .expect("Invalid calendar");
+    assert!(calendars.next().is_none());
+    calendar
+}
+
+fn property<'a>(event: &'a IcalEvent, name: &str) -> Option<&'a str> {
+    event
+        .properties
+        .iter()
+        .find(|property| property.name == name)
+        .and_then(|property| property.value.as_deref())
+}
+
+#[tokio::test]
+async fn feed_has_the_incomplete_todos_with_a_due_date() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let due = create_todo_due_in(&app, &alice, "buy milk, eggs; bread", Some(1)).await;
+    create_todo_due_in(&app, &alice, "someday", None).await;
+    let done = create_todo_due_in(&app, &alice, "already done", Some(1)).await;
+    let response = alice
+        .put(format!("{}/todo/{}", app.address, done))
+        .form(&[("is_completed", "true"), ("version", "1")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let path = rotate_feed(&app, &alice).await;
+
+    // no session needed
+    let response = get_feed(&app, &path).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "text/calendar; charset=utf-8",
+        response.headers()["Content-Type"]
+    );
+    let body = response.text().await.unwrap();
+    let calendar = parse(&body);
+
+    assert!(
+        calendar
+            .properties
+            .iter()
+            .any(|property| property.name == "REFRESH-INTERVAL")
+    );
+    assert_eq!(1, calendar.events.len());
+    let event = &calendar.events[0];
+    assert!(property(event, "UID").unwrap().starts_with(&due));
+    assert_eq!(Some(r"buy milk\, eggs\; bread"), property(event, "SUMMARY"));
+    assert_eq!(8, property(event, "DTSTART").unwrap().len());
+}
+
+#[tokio::test]
+async fn long_todos_are_folded() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let content = "ünïcödé ".repeat(20);
+    create_todo_due_in(&app, &alice, &content, Some(0)).await;
+    let path = rotate_feed(&app, &alice).await;
+
+    let body = get_feed(&app, &path).await.text().await.unwrap();
+    for line in body.split("\r\n") {
+        assert!(line.len() <= 75, "{line} is too long");
+    }
+    let calendar = parse(&body);
+    assert_eq!(
+        Some(content.trim()),
+        property(&calendar.events[0], "SUMMARY")
+    );
+}
+
+#[tokio::test]
+async fn rotating_the_feed_url_stops_the_old_one() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let old_path = rotate_feed(&app, &alice).await;
+    assert_eq!(200, get_feed(&app, &old_path).await.status().as_u16());
+
+    let new_path = rotate_feed(&app, &alice).await;
+    assert_ne!(old_path, new_path);
+    assert_eq!(404, get_feed(&app, &old_path).await.status().as_u16());
+    assert_eq!(200, get_feed(&app, &new_path).await.status().as_u16());
+
+    let response = alice
+        .delete(format!("{}/settings/calendar-feed", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(404, get_feed(&app, &new_path).await.status().as_u16());
+}
+
+#[tokio::test]
+async fn unknown_feeds_are_not_found() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let path = rotate_feed(&app, &alice).await;
+
+    assert_eq!(
+        404,
+        get_feed(&app, path.trim_end_matches(".ics"))
+            .await
+            .status()
+            .as_u16()
+    );
+    assert_eq!(
+        404,
+        get_feed(&app, "/calendar/not-a-token.ics")
+            .await
+            .status()
+            .as_u16()
+    );
+}
@@ -0,0 +1,149 @@
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{body_string_contains, method},
+};
+
+use crate::helpers::{PASSWORD, TestApp, assert_api_error, logged_in_client, spawn_app_with};
+
+/// Token the stubbed provider accepts, any other is refused
+const SOLVED: &str = "solved-token";
+
+/// An app asking for a CAPTCHA after `threshold` failures, verified by the
+/// returned stub of the provider
+async fn spawn_app_with_captcha(threshold: u32) -> (TestApp, MockServer) {
+    let provider = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(body_string_contains("secret=test-secret"))
+        .and(body_string_contains(format!("response={SOLVED}")))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "success": true })),
+        )
+        .mount(&provider)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": false,
+            "error-codes": ["invalid-input-response"]
+        })))
+        .mount(&provider)
+        .await;
+
+    let verify_url = provider.uri();
+    let app = spawn_app_with(|config| {
+        let settings = &mut config.application_settings;
+        settings.captcha_site_key = Some("test-site-key".to_string());
+        settings.captcha_secret_key = Some("test-secret".into());
+        settings.captcha_verify_url = Some(verify_url);
+        settings.captcha_failure_threshold = threshold;
+    })
+    .await;
+    (app, provider)
+}
+
+async fn login(app: &TestApp, password: &str, token: Option<&str>) -> reqwest::Response {
+    let mut form = vec![("username", "alice"), ("password", password)];
+    if let Some(token) = token {
+        form.push(("cf-turnstile-response", token));
+    }
+    app.client
+        .post(format!("{}/api/login", app.address))
+        .header("Accept", "application/json")
+        .form(&form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn page(app: &TestApp, path: &str) -> String {
+    app.client
+        .get(format!("{}{path}", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn logins_ask_for_a_captcha_once_the_address_failed_too_often() {
+    let (app, _provider) = spawn_app_with_captcha(2).await;
+    logged_in_client(&app, "alice").await;
+    assert!(!page(&app, "/login").await.contains("cf-turnstile"));
+
+    let response = login(&app, "wrong password", None).await;
+    assert_api_error(response, 401, "invalid_credentials", None).await;
+    assert!(!page(&app, "/login").await.contains("cf-turnstile"));
+
+    // the form sent back after the failure that crossed the threshold has it
+    let response = app
+        .client
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[("username", "alice"), ("password", "wrong password")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(401, response.status().as_u16());
+    let form = response.text().await.unwrap();
+    assert!(form.contains(r#"<div class="cf-turnstile" data-sitekey="test-site-key"></div>"#));
+    assert!(page(&app, "/login").await.contains("cf-turnstile"));
+    // registering from the address needs it too
+    assert!(page(&app, "/register").await.contains("cf-turnstile"));
+
+    // the right password alone isn't enough anymore
+    let response = login(&app, PASSWORD, None).await;
+    assert_api_error(response, 400, "invalid_captcha", None).await;
+    let response = login(&app, PASSWORD, Some("guessed-token")).await;
+    assert_api_error(response, 400, "invalid_captcha", None).await;
+
+    // JSON clients are sent on to the site once they are logged in
+    let response = login(&app, PASSWORD, Some(SOLVED)).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn registrations_are_verified_with_the_provider() {
+    let (app, provider) = spawn_app_with_captcha(0).await;
+    assert!(page(&app, "/register").await.contains("cf-turnstile"));
+
+    let register = |token: Option<&'static str>| {
+        let mut form = vec![
+            ("email", "alice@test.com"),
+            ("username", "alice"),
+            ("password", PASSWORD),
+        ];
+        if let Some(token) = token {
+            form.push(("cf-turnstile-response", token));
+        }
+        app.client
+            .post(format!("{}/api/register", app.address))
+            .header("HX-Request", "true")
+            .form(&form)
+            .send()
+    };
+
+    let response = register(None).await.expect("Failed to execute request");
+    assert_api_error(response, 400, "invalid_captcha", None).await;
+    let response = register(Some("guessed-token"))
+        .await
+        .expect("Failed to execute request");
+    assert_api_error(response, 400, "invalid_captcha", None).await;
+    // a missing token isn't worth asking the provider about
+    assert_eq!(1, provider.received_requests().await.unwrap().len());
+
+    let response = register(Some(SOLVED))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn nobody_is_asked_without_keys() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.captcha_failure_threshold = 0;
+    })
+    .await;
+    assert!(!page(&app, "/login").await.contains("cf-turnstile"));
+    assert!(!page(&app, "/register").await.contains("cf-turnstile"));
+}
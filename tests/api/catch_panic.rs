@@ -0,0 +1,52 @@
+use crate::helpers::{assert_api_error, spawn_app};
+
+#[tokio::test]
+async fn panicking_page_answers_with_an_error() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .get(format!("{}/test/panic", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(500, response.status().as_u16());
+    assert_eq!(
+        "An internal server error occured",
+        response.text().await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn panicking_api_route_answers_with_an_api_error() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .get(format!("{}/api/test/panic", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_api_error(response, 500, "internal_error", None).await;
+}
+
+#[tokio::test]
+async fn app_keeps_serving_after_a_panic() {
+    let app = spawn_app().await;
+
+    app.client
+        .get(format!("{}/test/panic", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let response = app
+        .client
+        .get(format!("{}/health_check", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert!(response.status().is_success());
+}
@@ -0,0 +1,121 @@
+use crate::helpers::{TestApp, spawn_app, spawn_app_with};
+
+async fn preflight(app: &TestApp, path: &str, origin: &str) -> reqwest::Response {
+    app.client
+        .request(reqwest::Method::OPTIONS, format!("{}{}", app.address, path))
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+fn header<'a>(response: &'a reqwest::Response, name: &str) -> Option<&'a str> {
+    response
+        .headers()
+        .get(name)
+        .map(|value| value.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn preflight_from_an_allowed_origin_succeeds_without_a_session() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.cors_allowed_origins =
+            Some("https://app.example.com".to_string());
+        config.application_settings.cors_allow_credentials = Some(true);
+    })
+    .await;
+
+    let response = preflight(&app, "/api/todo", "https://app.example.com").await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        Some("https://app.example.com"),
+        header(&response, "Access-Control-Allow-Origin")
+    );
+    assert_eq!(
+        Some("true"),
+        header(&response, "Access-Control-Allow-Credentials")
+    );
+    let methods = header(&response, "Access-Control-Allow-Methods").unwrap();
+    assert!(methods.contains("POST"), "{methods}");
+    let headers = header(&response, "Access-Control-Allow-Headers").unwrap();
+    assert!(headers.contains("content-type"), "{headers}");
+}
+
+#[tokio::test]
+async fn api_responses_to_an_allowed_origin_have_cors_headers() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.cors_allowed_origins =
+            Some("https://app.example.com".to_string());
+    })
+    .await;
+
+    let response = app
+        .client
+        .get(format!("{}/api/todo", app.address))
+        .header("Origin", "https://app.example.com")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        Some("https://app.example.com"),
+        header(&response, "Access-Control-Allow-Origin")
+    );
+}
+
+#[tokio::test]
+async fn other_origins_get_no_cors_headers() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.cors_allowed_origins =
+            Some("https://app.example.com".to_string());
+    })
+    .await;
+
+    let response = preflight(&app, "/api/todo", "https://evil.example.com").await;
+
+    assert_eq!(None, header(&response, "Access-Control-Allow-Origin"));
+}
+
+#[tokio::test]
+async fn pages_get_no_cors_headers() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.cors_allowed_origins =
+            Some("https://app.example.com".to_string());
+    })
+    .await;
+
+    let response = app
+        .client
+        .get(format!("{}/login", app.address))
+        .header("Origin", "https://app.example.com")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(None, header(&response, "Access-Control-Allow-Origin"));
+}
+
+#[tokio::test]
+async fn localhost_is_allowed_by_default_in_development() {
+    // the test config is for development
+    let app = spawn_app().await;
+
+    let response = preflight(&app, "/api/login", "http://localhost:5173").await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        Some("http://localhost:5173"),
+        header(&response, "Access-Control-Allow-Origin")
+    );
+    assert_eq!(
+        Some("true"),
+        header(&response, "Access-Control-Allow-Credentials")
+    );
+
+    let response = preflight(&app, "/api/login", "https://app.example.com").await;
+    assert_eq!(None, header(&response, "Access-Control-Allow-Origin"));
+}
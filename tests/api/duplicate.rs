@@ -0,0 +1,282 @@
+use uuid::Uuid;
+
+use crate::helpers::{PASSWORD, TestApp, list_id_of, logged_in_client, spawn_app};
+
+async fn send(request: reqwest::RequestBuilder) {
+    let response = request.send().await.expect("Failed to execute request");
+    assert!(response.status().is_success(), "Got {}", response.status());
+}
+
+async fn duplicate(app: &TestApp, client: &reqwest::Client, todo_id: Uuid) -> reqwest::Response {
+    client
+        .post(format!("{}/todo/{}/duplicate", app.address, todo_id))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Copied {
+    list_id: Uuid,
+    todo_content: String,
+    description: String,
+    due_date: Option<time::Date>,
+    priority: String,
+    tags: Vec<String>,
+    /// Content and completion of each subtask, in order
+    subtasks: Vec<(String, bool)>,
+}
+
+async fn copied(app: &TestApp, todo_id: Uuid) -> Copied {
+    let todo = sqlx::query!(
+        r#"
+        SELECT list_id, todo_content, description, due_date, priority::text AS "priority!"
+        FROM todo WHERE todo_id = $1
+        "#,
+        todo_id
+    )
+    .fetch_one(&app.db)
+    .await
+    .expect("Failed to fetch todo");
+    let tags = sqlx::query_scalar!(
+        r#"
+        SELECT tg.name FROM todo_tag AS tt
+        JOIN tag AS tg ON tg.tag_id = tt.tag_id
+        WHERE tt.todo_id = $1
+        ORDER BY tg.name
+        "#,
+        todo_id
+    )
+    .fetch_all(&app.db)
+    .await
+    .unwrap();
+    let subtasks = sqlx::query!(
+        "SELECT content, is_completed FROM subtask WHERE todo_id = $1 ORDER BY position",
+        todo_id
+    )
+    .fetch_all(&app.db)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|subtask| (subtask.content, subtask.is_completed))
+    .collect();
+    Copied {
+        list_id: todo.list_id,
+        todo_content: todo.todo_content,
+        description: todo.description,
+        due_date: todo.due_date,
+        priority: todo.priority,
+        tags,
+        subtasks,
+    }
+}
+
+#[tokio::test]
+async fn duplicates_are_deep_copies_without_the_completion() {
+    let app = spawn_app().await;
+    let alice = app.register_and_login("alice", PASSWORD).await;
+    let original_id = alice.create_todo("buy milk").await.todo_id;
+    let alice = alice.client;
+    let todo_url = format!("{}/todo/{}", app.address, original_id);
+    sqlx::query!(
+        "UPDATE todo SET due_date = '2030-01-31' WHERE todo_id = $1",
+        original_id
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+    send(alice.put(&todo_url).form(&[
+        ("is_completed", "true"),
+        ("priority", "high"),
+        ("version", "1"),
+    ]))
+    .await;
+    send(
+        alice
+            .put(format!("{todo_url}/tags"))
+            .form(&[("tags", "errands, shopping")]),
+    )
+    .await;
+    send(alice.post(format!("{todo_url}/pin"))).await;
+    send(
+        alice
+            .put(format!("{todo_url}/description"))
+            .form(&[("description", "the *oat* one")]),
+    )
+    .await;
+    for content in ["find a shop", "pay"] {
+        send(
+            alice
+                .post(format!("{todo_url}/subtasks"))
+                .form(&[("content", content)]),
+        )
+        .await;
+    }
+    let first_subtask =
+        sqlx::query_scalar!("SELECT subtask_id FROM subtask WHERE content = 'find a shop'")
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+    send(
+        alice
+            .put(format!("{todo_url}/subtasks/{first_subtask}"))
+            .form(&[("is_completed", "true")]),
+    )
+    .await;
+    let original = copied(&app, original_id).await;
+
+    let response = duplicate(&app, &alice, original_id).await;
+    assert_eq!(201, response.status().as_u16());
+    assert!(response.headers().contains_key("HX-Trigger"));
+    let todo: serde_json::Value = response.json().await.unwrap();
+    let copy_id: Uuid = todo["todo_id"].as_str().unwrap().parse().unwrap();
+    assert_ne!(original_id, copy_id);
+    assert_eq!(false, todo["is_completed"]);
+    assert_eq!(false, todo["is_pinned"]);
+    assert_eq!(serde_json::Value::Null, todo["completed_at"]);
+    assert_eq!(2, todo["subtask_count"]);
+    assert_eq!(0, todo["completed_subtask_count"]);
+
+    let copy = copied(&app, copy_id).await;
+    assert_eq!(
+        Copied {
+            subtasks: vec![
+                ("find a shop".to_string(), false),
+                ("pay".to_string(), false)
+            ],
+            ..original.clone()
+        },
+        copy
+    );
+    let copy_row = sqlx::query!(
+        "SELECT is_completed, is_pinned, version FROM todo WHERE todo_id = $1",
+        copy_id
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    assert!(!copy_row.is_completed);
+    assert!(!copy_row.is_pinned);
+    assert_eq!(1, copy_row.version);
+
+    // changing the copy leaves the original as it was
+    let copy_url = format!("{}/todo/{}", app.address, copy_id);
+    send(
+        alice
+            .put(format!("{copy_url}/description"))
+            .form(&[("description", "any milk")]),
+    )
+    .await;
+    send(
+        alice
+            .put(format!("{copy_url}/tags"))
+            .form(&[("tags", "dairy")]),
+    )
+    .await;
+    let copied_subtask = sqlx::query_scalar!(
+        "SELECT subtask_id FROM subtask WHERE todo_id = $1 AND content = 'pay'",
+        copy_id
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    send(
+        alice
+            .put(format!("{copy_url}/subtasks/{copied_subtask}"))
+            .form(&[("content", "pay cash"), ("is_completed", "true")]),
+    )
+    .await;
+    send(alice.delete(&copy_url)).await;
+    assert_eq!(original, copied(&app, original_id).await);
+}
+
+#[tokio::test]
+async fn only_editors_can_duplicate_todos_of_shared_lists() {
+    let app = spawn_app().await;
+    let alice = app.register_and_login("alice", PASSWORD).await;
+    let bob = logged_in_client(&app, "bob").await;
+    let carol = logged_in_client(&app, "carol").await;
+    let list_id = list_id_of(&app, "alice").await;
+    let todo_id = alice.create_todo("buy milk").await.todo_id;
+    let alice = alice.client;
+
+    let response = duplicate(&app, &carol, todo_id).await;
+    assert_eq!(404, response.status().as_u16());
+
+    for (username, role) in [("bob", "viewer"), ("carol", "editor")] {
+        send(
+            alice
+                .post(format!("{}/lists/{}/share", app.address, list_id))
+                .header("HX-Request", "true")
+                .form(&[("username", username), ("role", role)]),
+        )
+        .await;
+    }
+    let response = duplicate(&app, &bob, todo_id).await;
+    assert_eq!(403, response.status().as_u16());
+
+    // the copy stays in the list, added by the editor
+    let response = duplicate(&app, &carol, todo_id).await;
+    assert_eq!(201, response.status().as_u16());
+    let todo: serde_json::Value = response.json().await.unwrap();
+    let copy = sqlx::query!(
+        r#"
+        SELECT td.list_id, ui.username
+        FROM todo AS td
+        JOIN user_info AS ui ON ui.user_id = td.user_id
+        WHERE td.todo_id = $1
+        "#,
+        todo["todo_id"].as_str().unwrap().parse::<Uuid>().unwrap()
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    assert_eq!(list_id, copy.list_id);
+    assert_eq!("carol", copy.username);
+}
+
+#[tokio::test]
+async fn deleted_todos_cant_be_duplicated() {
+    let app = spawn_app().await;
+    let alice = app.register_and_login("alice", PASSWORD).await;
+    let todo_id = alice.create_todo("buy milk").await.todo_id;
+    let alice = alice.client;
+    send(alice.delete(format!("{}/todo/{}", app.address, todo_id))).await;
+
+    let response = duplicate(&app, &alice, todo_id).await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn htmx_gets_the_copy_below_the_original_without_a_reload() {
+    let app = spawn_app().await;
+    let alice = app.register_and_login("alice", PASSWORD).await;
+    let todo_id = alice.create_todo("buy milk").await.todo_id;
+    let alice = alice.client;
+
+    let response = alice
+        .post(format!("{}/todo/{}/duplicate", app.address, todo_id))
+        .header("HX-Request", "true")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+    assert!(response.headers().get("HX-Redirect").is_none());
+    assert_eq!(
+        format!("#todo-{todo_id}"),
+        response.headers()["HX-Retarget"].to_str().unwrap()
+    );
+    assert_eq!("afterend", response.headers()["HX-Reswap"]);
+
+    let copy_id: Uuid = sqlx::query_scalar!(
+        "SELECT todo_id FROM todo WHERE todo_content = 'buy milk' AND todo_id <> $1",
+        todo_id
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    let body = response.text().await.unwrap();
+    assert!(body.contains(&format!(r#"id="todo-{copy_id}""#)));
+    assert!(!body.contains(&format!(r#"id="todo-{todo_id}""#)));
+}
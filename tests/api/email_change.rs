@@ -0,0 +1,142 @@
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, assert_api_error, logged_in_client, spawn_app};
+
+async fn request_change(app: &TestApp, client: &reqwest::Client, email: &str) -> reqwest::Response {
+    client
+        .post(format!("{}/api/user/email", app.address))
+        .form(&[("email", email)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn confirm(app: &TestApp, token: &str) -> reqwest::Response {
+    app.client
+        .get(format!("{}/confirm-email?token={}", app.address, token))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn current_email(app: &TestApp, username: &str) -> String {
+    sqlx::query_scalar!("SELECT email FROM user_info WHERE username = $1", username)
+        .fetch_one(&app.db)
+        .await
+        .unwrap()
+}
+
+/// Pulls the token out of the confirmation email sent to `recipient`
+async fn confirmation_token(app: &TestApp, recipient: &str) -> String {
+    let link = app
+        .last_email_to(recipient)
+        .await
+        .link_to(app, "/confirm-email");
+    let start = link.find("token=").expect("No token in the link") + "token=".len();
+    link[start..].to_string()
+}
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn email_changes_only_after_confirmation() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = request_change(&app, &client, "alice@new.com").await;
+    assert_eq!(202, response.status().as_u16());
+    assert_eq!("alice@test.com", current_email(&app, "alice").await);
+
+    // the current address is told about it too
+    let notice = app.last_email_to("alice@test.com").await;
+    assert!(notice.text_body.contains("alice@new.com"));
+
+    let token = confirmation_token(&app, "alice@new.com").await;
+    let response = confirm(&app, &token).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("alice@new.com", current_email(&app, "alice").await);
+
+    // links can only be used once
+    let response = confirm(&app, &token).await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn expired_or_unknown_tokens_are_rejected() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    let client = logged_in_client(&app, "alice").await;
+
+    request_change(&app, &client, "alice@new.com").await;
+    let token = confirmation_token(&app, "alice@new.com").await;
+    sqlx::query!(
+        "UPDATE pending_email_changes SET created_at = NOW() - INTERVAL '25 hours' WHERE token = $1",
+        token
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let response = confirm(&app, &token).await;
+    assert_eq!(400, response.status().as_u16());
+    let response = confirm(&app, "00000000000000000000000000000000").await;
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!("alice@test.com", current_email(&app, "alice").await);
+}
+
+#[tokio::test]
+async fn address_registered_before_confirmation_is_not_taken_over() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = request_change(&app, &client, "bob@test.com").await;
+    assert_eq!(202, response.status().as_u16());
+    let token = confirmation_token(&app, "bob@test.com").await;
+
+    // bob registers the address while alice's confirmation is pending
+    logged_in_client(&app, "bob").await;
+
+    let response = confirm(&app, &token).await;
+    assert_eq!(409, response.status().as_u16());
+    assert_eq!("alice@test.com", current_email(&app, "alice").await);
+}
+
+#[tokio::test]
+async fn taken_or_invalid_addresses_are_rejected_up_front() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    let client = logged_in_client(&app, "alice").await;
+    logged_in_client(&app, "bob").await;
+
+    let response = request_change(&app, &client, "BOB@test.com").await;
+    assert_api_error(response, 409, "email_taken", Some("email")).await;
+    let response = request_change(&app, &client, "not an email").await;
+    assert_api_error(response, 400, "invalid_email", Some("email")).await;
+    let response = request_change(&app, &client, "alice@test.com").await;
+    assert_api_error(response, 400, "same_email", Some("email")).await;
+
+    let pending = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM pending_email_changes"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(0, pending);
+}
+
+#[tokio::test]
+async fn email_change_requires_login() {
+    let app = spawn_app().await;
+
+    let response = request_change(&app, &app.client, "alice@new.com").await;
+    assert_api_error(response, 401, "not_logged_in", None).await;
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::{
+    admin::make_admin,
+    helpers::{TestApp, logged_in_client, spawn_app, spawn_app_with},
+};
+
+/// Queues a login link email the way the handlers do, without the link
+async fn queue_email(app: &TestApp, recipient: &str) {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (recipient, template, params)
+        VALUES ($1, 'magic_link', '{"token": "abc", "lifetime_minutes": 15}')
+        "#,
+        recipient
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+}
+
+/// Waits for the worker to be done with the queued emails, sent or given up on
+async fn wait_for_outbox(app: &TestApp) {
+    for _ in 0..100 {
+        let pending = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM email_outbox WHERE dead_at IS NULL"#
+        )
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+        if pending == 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Queued emails were not sent in time");
+}
+
+#[tokio::test]
+async fn queued_emails_are_sent_and_removed() {
+    let app = spawn_app().await;
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    queue_email(&app, "alice@test.com").await;
+    wait_for_outbox(&app).await;
+
+    let email = app.last_email_to("alice@test.com").await;
+    assert_eq!("Your login link", email.subject);
+    assert!(email.html_body.contains("/login/magic/abc\""));
+    email.link_to(&app, "/login/magic/abc");
+    let left = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM email_outbox"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(0, left);
+}
+
+#[tokio::test]
+async fn failed_emails_are_retried() {
+    let app = spawn_app().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    queue_email(&app, "alice@test.com").await;
+    wait_for_outbox(&app).await;
+}
+
+#[tokio::test]
+async fn emails_are_given_up_on_after_repeated_failures() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.email_max_attempts = 2;
+    })
+    .await;
+    let admin = logged_in_client(&app, "admin").await;
+    make_admin(&app, "admin").await;
+    // checked when the mock server is dropped, no attempts after the last one
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    queue_email(&app, "bob@test.com").await;
+    wait_for_outbox(&app).await;
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+
+    let dead =
+        sqlx::query!("SELECT attempts, last_error FROM email_outbox WHERE dead_at IS NOT NULL")
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+    assert_eq!(2, dead.attempts);
+    assert!(dead.last_error.unwrap().contains("500"));
+
+    let page = admin
+        .get(format!("{}/admin/emails", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("bob@test.com"));
+    assert!(page.contains("magic_link"));
+}
@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::helpers::{logged_in_client, spawn_app};
+
+const PAYLOAD: &str = "<img src=x onerror=alert(1)>";
+const ESCAPED: &str = "&lt;img src=x onerror=alert(1)&gt;";
+
+/// Reads from an event stream until `needle` shows up, or panics after a timeout
+async fn read_until(response: &mut reqwest::Response, needle: &str) -> String {
+    let mut received = String::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !received.contains(needle) {
+            let chunk = response
+                .chunk()
+                .await
+                .expect("Failed to read event stream")
+                .expect("Event stream ended");
+            received.push_str(&String::from_utf8_lossy(&chunk));
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("Timed out waiting for {needle}, received {received:?}"));
+    received
+}
+
+/// The headers and body of a response, where the payload mustn't show up
+async fn everything(response: reqwest::Response) -> String {
+    let headers = format!("{:?}", response.headers());
+    format!("{headers}\n{}", response.text().await.unwrap())
+}
+
+fn assert_escaped(what: &str, received: &str) {
+    assert!(
+        !received.contains(PAYLOAD),
+        "{what} has the todo unescaped: {received}"
+    );
+}
+
+#[tokio::test]
+async fn html_in_todos_is_escaped_everywhere_they_are_shown() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let mut events = alice
+        .get(format!("{}/todo/events", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let response = alice
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", PAYLOAD)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+    assert!(response.headers().contains_key("HX-Trigger"));
+    let created = everything(response).await;
+    assert!(created.contains(ESCAPED));
+    assert_escaped("The created row", &created);
+
+    let received = read_until(&mut events, ESCAPED).await;
+    assert_escaped("The event stream", &received);
+
+    let todo_id: Uuid =
+        sqlx::query_scalar!("SELECT todo_id FROM todo WHERE todo_content = $1", PAYLOAD)
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+    for path in [
+        "/todo".to_string(),
+        format!("/todo/{todo_id}"),
+        "/todo/search?q=onerror".to_string(),
+    ] {
+        let response = alice
+            .get(format!("{}{path}", app.address))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(200, response.status().as_u16());
+        let page = everything(response).await;
+        assert!(page.contains(ESCAPED), "{path} doesn't show the todo");
+        assert_escaped(&path, &page);
+    }
+
+    // the undo toast, and the counts in HX-Trigger
+    let response = alice
+        .delete(format!("{}/todo/{todo_id}", app.address))
+        .header("HX-Request", "true")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert!(response.status().is_success());
+    let deleted = everything(response).await;
+    assert!(deleted.contains(ESCAPED));
+    assert_escaped("The deletion", &deleted);
+
+    let received = read_until(&mut events, "event: deleted-").await;
+    assert_escaped("The event stream", &received);
+}
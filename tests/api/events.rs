@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use crate::helpers::{logged_in_client, spawn_app};
+
+/// Reads from an event stream until `needle` shows up, or panics after a timeout
+async fn read_until(response: &mut reqwest::Response, needle: &str) -> String {
+    let mut received = String::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !received.contains(needle) {
+            let chunk = response
+                .chunk()
+                .await
+                .expect("Failed to read event stream")
+                .expect("Event stream ended");
+            received.push_str(&String::from_utf8_lossy(&chunk));
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("Timed out waiting for {needle}, received {received:?}"));
+    received
+}
+
+#[tokio::test]
+async fn created_todo_is_pushed_to_open_event_streams() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let mut events = alice
+        .get(format!("{}/todo/events", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, events.status().as_u16());
+    assert_eq!(
+        "text/event-stream",
+        events.headers().get("Content-Type").unwrap()
+    );
+
+    let response = alice
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "buy milk")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    let received = read_until(&mut events, "buy milk").await;
+    assert!(received.contains("event: created-"));
+}
+
+#[tokio::test]
+async fn updates_are_pushed_to_members_of_shared_lists() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    let list_id = sqlx::query_scalar!(
+        r#"
+        SELECT tl.list_id FROM todo_list AS tl
+        JOIN user_info AS ui ON ui.user_id = tl.owner_id
+        WHERE ui.username = 'alice'
+        "#
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    alice
+        .post(format!("{}/lists/{}/share", app.address, list_id))
+        .header("HX-Request", "true")
+        .form(&[("username", "bob"), ("role", "viewer")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    alice
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "buy milk")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let todo_id = sqlx::query_scalar!("SELECT todo_id FROM todo")
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+
+    let mut events = bob
+        .get(format!("{}/todo/events", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    alice
+        .delete(format!("{}/todo/{}", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    read_until(&mut events, &format!("event: deleted-{todo_id}")).await;
+}
+
+#[tokio::test]
+async fn event_streams_per_user_are_capped() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let mut streams = Vec::new();
+    loop {
+        let response = alice
+            .get(format!("{}/todo/events", app.address))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        if response.status().as_u16() != 200 {
+            assert_eq!(429, response.status().as_u16());
+            break;
+        }
+        streams.push(response);
+        assert!(streams.len() <= 8, "Event streams are not capped");
+    }
+
+    // closing a stream frees up a slot
+    streams.pop();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let response = alice
+        .get(format!("{}/todo/events", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
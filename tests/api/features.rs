@@ -0,0 +1,77 @@
+use crate::helpers::{assert_api_error, logged_in_client, spawn_app_with};
+
+#[tokio::test]
+async fn disabled_registration_is_not_found() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.feature_registration = false;
+    })
+    .await;
+
+    let response = app
+        .client
+        .get(format!("{}/register", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+
+    let response = app
+        .client
+        .post(format!("{}/api/register", app.address))
+        .header("HX-Request", "true")
+        .form(&[
+            ("email", "alice@test.com"),
+            ("username", "alice"),
+            ("password", "correct horse battery staple"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_api_error(response, 404, "not_found", None).await;
+}
+
+#[tokio::test]
+async fn disabled_registration_is_not_linked() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.feature_registration = false;
+    })
+    .await;
+
+    let body = app
+        .client
+        .get(&app.address)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(!body.contains("/register"));
+}
+
+#[tokio::test]
+async fn disabled_webhooks_are_not_found_or_shown() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.feature_webhooks = false;
+    })
+    .await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = client
+        .post(format!("{}/settings/webhooks", app.address))
+        .form(&[("url", "https://example.com/hook"), ("todo_created", "on")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+
+    let body = client
+        .get(format!("{}/settings", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(!body.contains("<h2>Webhooks</h2>"));
+}
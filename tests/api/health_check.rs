@@ -1,4 +1,4 @@
-use crate::app::spawn_app;
+use crate::helpers::spawn_app;
 
 #[tokio::test]
 async fn health_check_works() {
@@ -15,3 +15,22 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn ready_reports_postgres_and_the_redis_pool() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .client
+        .get(format!("{}/health/ready", &test_app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(true, body["postgres"]);
+    assert_eq!(true, body["redis"]);
+    assert!(body["redis_pool"]["connected_clients"].as_u64().unwrap() > 0);
+    assert_eq!(0, body["redis_pool"]["reconnects"]);
+}
@@ -0,0 +1,192 @@
+use site::worker::history::prune_todo_events;
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app};
+
+async fn create_todo(app: &TestApp, client: &reqwest::Client, content: &str) -> Uuid {
+    let response = client
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", content)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    sqlx::query_scalar!("SELECT todo_id FROM todo WHERE todo_content = $1", content)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to fetch todo id")
+}
+
+async fn update_todo(
+    app: &TestApp,
+    client: &reqwest::Client,
+    todo_id: Uuid,
+    form: &[(&str, &str)],
+) -> reqwest::Response {
+    client
+        .put(format!("{}/todo/{}", app.address, todo_id))
+        .form(form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn get_text(client: &reqwest::Client, url: String) -> String {
+    client
+        .get(url)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap()
+}
+
+async fn event_count(app: &TestApp, todo_id: Uuid) -> i64 {
+    sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM todo_events WHERE todo_id = $1"#,
+        todo_id
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn changes_are_shown_newest_first() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+
+    let updates = [
+        [("is_completed", "true"), ("version", "1")],
+        [("priority", "high"), ("version", "2")],
+        [("is_completed", "false"), ("version", "3")],
+    ];
+    for form in updates {
+        let response = update_todo(&app, &alice, todo_id, &form).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+    alice
+        .put(format!("{}/todo/{}/description", app.address, todo_id))
+        .form(&[("description", "the oat one")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let body = get_text(&alice, format!("{}/todo/{}", app.address, todo_id)).await;
+    let positions: Vec<usize> = [
+        "edited the description",
+        "marked it as not done",
+        "changed the priority from normal to high",
+        "marked it as done",
+        "created this todo",
+    ]
+    .iter()
+    .map(|change| {
+        body.find(change)
+            .unwrap_or_else(|| panic!("{change} missing"))
+    })
+    .collect();
+    assert!(positions.is_sorted());
+    assert!(body.contains("<strong>alice</strong>"));
+}
+
+#[tokio::test]
+async fn rejected_updates_are_not_recorded() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+    assert_eq!(1, event_count(&app, todo_id).await);
+
+    let response = update_todo(
+        &app,
+        &alice,
+        todo_id,
+        &[("is_completed", "true"), ("version", "7")],
+    )
+    .await;
+    assert_eq!(409, response.status().as_u16());
+    assert_eq!(1, event_count(&app, todo_id).await);
+
+    // submitting the current value is not a change
+    let response = update_todo(
+        &app,
+        &alice,
+        todo_id,
+        &[("priority", "normal"), ("version", "1")],
+    )
+    .await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(1, event_count(&app, todo_id).await);
+}
+
+#[tokio::test]
+async fn history_is_paginated() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+    sqlx::query!(
+        r#"
+        INSERT INTO todo_events (todo_id, payload)
+        SELECT $1, '{"kind": "completed"}' FROM generate_series(1, 24)
+        "#,
+        todo_id
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let body = get_text(&alice, format!("{}/todo/{}", app.address, todo_id)).await;
+    assert_eq!(20, body.matches("marked it as done").count());
+    assert!(!body.contains("created this todo"));
+    assert!(body.contains(&format!("/todo/{todo_id}/history?page=2")));
+
+    let body = get_text(
+        &alice,
+        format!("{}/todo/{}/history?page=2", app.address, todo_id),
+    )
+    .await;
+    assert_eq!(4, body.matches("marked it as done").count());
+    assert!(body.contains("created this todo"));
+    assert!(body.contains("A deleted user"));
+    assert!(!body.contains("Show more"));
+}
+
+#[tokio::test]
+async fn history_is_private_to_the_list() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let mallory = logged_in_client(&app, "mallory").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+
+    let response = mallory
+        .get(format!("{}/todo/{}/history", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn old_history_is_pruned() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+    sqlx::query!(
+        r#"
+        INSERT INTO todo_events (todo_id, payload, created_at)
+        VALUES ($1, '{"kind": "completed"}', NOW() - INTERVAL '400 days')
+        "#,
+        todo_id
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let pruned = prune_todo_events(&app.db, 365).await.unwrap();
+    assert_eq!(1, pruned);
+    assert_eq!(1, event_count(&app, todo_id).await);
+}
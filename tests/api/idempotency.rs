@@ -0,0 +1,101 @@
+use crate::helpers::{TestApp, logged_in_client, spawn_app};
+
+async fn post_todo(
+    app: &TestApp,
+    client: &reqwest::Client,
+    key: &str,
+    content: &str,
+) -> reqwest::Response {
+    client
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .header("Idempotency-Key", key)
+        .form(&[("todo_content", content)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn todo_count(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM todo"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn retried_request_is_replayed() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let first = post_todo(&app, &alice, "key-1", "buy milk").await;
+    assert_eq!(201, first.status().as_u16());
+    assert!(first.headers().get("idempotent-replayed").is_none());
+
+    let retry = post_todo(&app, &alice, "key-1", "buy milk").await;
+    assert_eq!(201, retry.status().as_u16());
+    assert_eq!("true", retry.headers()["idempotent-replayed"]);
+    assert_eq!(first.headers()["hx-trigger"], retry.headers()["hx-trigger"]);
+    assert_eq!(1, todo_count(&app).await);
+
+    // a new key is a new request
+    let response = post_todo(&app, &alice, "key-2", "buy milk").await;
+    assert_eq!(201, response.status().as_u16());
+    assert_eq!(2, todo_count(&app).await);
+}
+
+#[tokio::test]
+async fn reusing_a_key_for_a_different_request_is_rejected() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    post_todo(&app, &alice, "key-1", "buy milk").await;
+    let response = post_todo(&app, &alice, "key-1", "buy eggs").await;
+    assert_eq!(422, response.status().as_u16());
+    assert_eq!(1, todo_count(&app).await);
+}
+
+#[tokio::test]
+async fn keys_are_scoped_to_the_user() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+
+    post_todo(&app, &alice, "key-1", "buy milk").await;
+    let response = post_todo(&app, &bob, "key-1", "buy milk").await;
+    assert_eq!(201, response.status().as_u16());
+    assert!(response.headers().get("idempotent-replayed").is_none());
+    assert_eq!(2, todo_count(&app).await);
+}
+
+#[tokio::test]
+async fn invalid_keys_are_rejected() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = post_todo(&app, &alice, "", "buy milk").await;
+    assert_eq!(400, response.status().as_u16());
+    let response = post_todo(&app, &alice, &"k".repeat(256), "buy milk").await;
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(0, todo_count(&app).await);
+}
+
+#[tokio::test]
+async fn concurrent_requests_with_the_same_key_add_one_todo() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let (a, b, c) = tokio::join!(
+        post_todo(&app, &alice, "key-1", "buy milk"),
+        post_todo(&app, &alice, "key-1", "buy milk"),
+        post_todo(&app, &alice, "key-1", "buy milk"),
+    );
+    let responses = [a, b, c];
+    let statuses: Vec<u16> = responses.iter().map(|r| r.status().as_u16()).collect();
+    assert!(statuses.contains(&201), "{statuses:?}");
+    assert!(
+        statuses.iter().all(|status| [201, 409].contains(status)),
+        "{statuses:?}"
+    );
+    assert_eq!(1, todo_count(&app).await);
+}
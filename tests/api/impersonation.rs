@@ -0,0 +1,165 @@
+use uuid::Uuid;
+
+use crate::{
+    admin::make_admin,
+    audit::wait_for_events,
+    helpers::{TestApp, logged_in_client, spawn_app},
+};
+
+async fn user_id(app: &TestApp, username: &str) -> Uuid {
+    sqlx::query_scalar!(
+        "SELECT user_id FROM user_info WHERE username = $1",
+        username
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap()
+}
+
+/// Logs the admin the client belongs to in as the user
+async fn impersonate(app: &TestApp, client: &reqwest::Client, user_id: Uuid) -> reqwest::Response {
+    client
+        .post(format!(
+            "{}/admin/users/{}/impersonate",
+            app.address, user_id
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn stop_impersonating(app: &TestApp, client: &reqwest::Client) -> reqwest::Response {
+    client
+        .post(format!("{}/admin/stop-impersonating", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn settings_page(app: &TestApp, client: &reqwest::Client) -> String {
+    let response = client
+        .get(format!("{}/settings", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn admins_see_the_site_as_the_user_until_they_return() {
+    let app = spawn_app().await;
+    let admin = logged_in_client(&app, "alice").await;
+    make_admin(&app, "alice").await;
+    logged_in_client(&app, "bob").await;
+    let bob_id = user_id(&app, "bob").await;
+
+    let response = impersonate(&app, &admin, bob_id).await;
+    assert_eq!(200, response.status().as_u16());
+    wait_for_events(&app, "impersonation_started", 1).await;
+
+    let page = settings_page(&app, &admin).await;
+    assert!(page.contains("Viewing as bob"));
+    // bob's own pages, not alice's admin ones
+    let response = admin
+        .get(format!("{}/admin", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+
+    let response = stop_impersonating(&app, &admin).await;
+    assert_eq!(200, response.status().as_u16());
+    wait_for_events(&app, "impersonation_ended", 1).await;
+
+    let page = settings_page(&app, &admin).await;
+    assert!(!page.contains("Viewing as"));
+    let response = admin
+        .get(format!("{}/admin", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = stop_impersonating(&app, &admin).await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn impersonated_sessions_cant_change_the_account() {
+    let app = spawn_app().await;
+    let admin = logged_in_client(&app, "alice").await;
+    make_admin(&app, "alice").await;
+    logged_in_client(&app, "bob").await;
+    // an admin too, so only the impersonation stops nesting
+    make_admin(&app, "bob").await;
+    logged_in_client(&app, "carol").await;
+    let bob_id = user_id(&app, "bob").await;
+    let carol_id = user_id(&app, "carol").await;
+    assert_eq!(
+        200,
+        impersonate(&app, &admin, bob_id).await.status().as_u16()
+    );
+
+    let blocked = [
+        ("/api/user/email", vec![("email", "alice@evil.com")]),
+        ("/settings/username", vec![("username", "mallory")]),
+        ("/settings/delete-account", vec![("password", "anything")]),
+        ("/settings/deactivate", vec![("password", "anything")]),
+        ("/settings/passkeys/start", vec![]),
+    ];
+    for (path, form) in blocked {
+        let response = admin
+            .post(format!("{}{}", app.address, path))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(403, response.status().as_u16(), "{path}");
+    }
+    let response = impersonate(&app, &admin, carol_id).await;
+    assert_eq!(403, response.status().as_u16());
+
+    let email = sqlx::query_scalar!("SELECT email FROM user_info WHERE user_id = $1", bob_id)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!("bob@test.com", email);
+}
+
+#[tokio::test]
+async fn changes_made_while_impersonating_are_recorded_under_the_admin() {
+    let app = spawn_app().await;
+    let admin = logged_in_client(&app, "alice").await;
+    make_admin(&app, "alice").await;
+    logged_in_client(&app, "bob").await;
+    let alice_id = user_id(&app, "alice").await;
+    let bob_id = user_id(&app, "bob").await;
+    impersonate(&app, &admin, bob_id).await;
+
+    let response = admin
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "buy milk")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert!(response.status().is_success());
+    // reading doesn't change anything
+    settings_page(&app, &admin).await;
+    wait_for_events(&app, "impersonated_request", 1).await;
+
+    let entry = sqlx::query!(
+        r#"
+        SELECT user_id, impersonator_id, metadata FROM audit_log
+        WHERE event_type = 'impersonated_request'
+        "#
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    assert_eq!(Some(bob_id), entry.user_id);
+    assert_eq!(Some(alice_id), entry.impersonator_id);
+    assert_eq!("POST", entry.metadata["method"]);
+    assert_eq!("/todo", entry.metadata["path"]);
+}
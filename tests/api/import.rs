@@ -0,0 +1,137 @@
+use reqwest::multipart::{Form, Part};
+
+use crate::{
+    helpers::{TestApp, logged_in_client, spawn_app, spawn_app_with},
+    webhooks::add_webhook,
+};
+
+async fn import_csv(app: &TestApp, client: &reqwest::Client, bytes: Vec<u8>) -> reqwest::Response {
+    let form = Form::new().part(
+        "file",
+        Part::bytes(bytes)
+            .file_name("todos.csv")
+            .mime_str("text/csv")
+            .unwrap(),
+    );
+
+    client
+        .post(format!("{}/todo/import", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn todo_count(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM todo"#)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to count todos")
+}
+
+#[tokio::test]
+async fn import_skips_invalid_rows_and_reports_them() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let csv = "todo_content,is_completed,due_date\n\
+               buy milk,false,2030-01-01\n\
+               ,false,\n\
+               walk dog,maybe,\n\
+               file taxes,true,not-a-date\n\
+               \"call mom, then dad\",true,\n";
+    let response = import_csv(&app, &alice, csv.as_bytes().to_vec()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let body = response.text().await.unwrap();
+    assert!(body.contains("Imported 2 todos"));
+    assert!(body.contains("Skipped 3 rows"));
+    assert!(body.contains("Line 3: Todo is empty"));
+    assert!(body.contains("Line 4: is_completed must be true or false"));
+    assert!(body.contains("Line 5: due_date must be formatted as YYYY-MM-DD"));
+
+    let saved = sqlx::query!(
+        r#"SELECT todo_content, is_completed, due_date::text AS due_date FROM todo ORDER BY todo_content"#
+    )
+    .fetch_all(&app.db)
+    .await
+    .expect("Failed to fetch imported todos");
+    assert_eq!(2, saved.len());
+    assert_eq!("buy milk", saved[0].todo_content);
+    assert!(!saved[0].is_completed);
+    assert_eq!(Some("2030-01-01".to_string()), saved[0].due_date);
+    assert_eq!("call mom, then dad", saved[1].todo_content);
+    assert!(saved[1].is_completed);
+}
+
+#[tokio::test]
+async fn rejected_files_import_nothing() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let mut too_many_rows = "todo_content\n".to_string();
+    for i in 0..1001 {
+        too_many_rows.push_str(&format!("todo {i}\n"));
+    }
+    let response = import_csv(&app, &alice, too_many_rows.into_bytes()).await;
+    assert_eq!(400, response.status().as_u16());
+
+    let not_utf8 = b"todo_content\nbuy \xff milk\n".to_vec();
+    let response = import_csv(&app, &alice, not_utf8).await;
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        "File is not valid UTF-8 text",
+        response.text().await.unwrap()
+    );
+
+    let missing_column = b"title\nbuy milk\n".to_vec();
+    let response = import_csv(&app, &alice, missing_column).await;
+    assert_eq!(400, response.status().as_u16());
+
+    let too_large = format!("todo_content\n{}\n", "a".repeat(1024 * 1024)).into_bytes();
+    let response = import_csv(&app, &alice, too_large).await;
+    assert_eq!(413, response.status().as_u16());
+
+    assert_eq!(0, todo_count(&app).await);
+}
+
+#[tokio::test]
+async fn import_is_limited_to_1000_rows() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let mut csv = "todo_content\n".to_string();
+    for i in 0..1000 {
+        csv.push_str(&format!("todo {i}\n"));
+    }
+    let response = import_csv(&app, &alice, csv.into_bytes()).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(1000, todo_count(&app).await);
+}
+
+#[tokio::test]
+async fn each_imported_todo_queues_a_webhook() {
+    // nothing is delivered, so what was queued stays in the outbox
+    let app = spawn_app_with(|config| {
+        config.application_settings.webhook_interval_secs = 3600;
+    })
+    .await;
+    let alice = logged_in_client(&app, "alice").await;
+    add_webhook(&app, &alice, "https://example.com/hooks", &["todo_created"]).await;
+
+    let csv = "todo_content,is_completed\nbuy milk,false\n,false\nfile taxes,true\n";
+    let response = import_csv(&app, &alice, csv.as_bytes().to_vec()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let queued = sqlx::query_scalar!(
+        r#"
+        SELECT payload->'todo'->>'content' AS "content!" FROM outbox
+        WHERE event = 'todo.created'
+        ORDER BY payload->'todo'->>'content'
+        "#
+    )
+    .fetch_all(&app.db)
+    .await
+    .unwrap();
+    assert_eq!(vec!["buy milk", "file taxes"], queued);
+}
@@ -0,0 +1,227 @@
+use site::config::RegistrationMode;
+
+use crate::{
+    admin::make_admin,
+    helpers::{PASSWORD, TestApp, logged_in_client, spawn_app, spawn_app_with},
+};
+
+async fn register(app: &TestApp, username: &str, invite_code: Option<&str>) -> reqwest::Response {
+    let email = format!("{username}@test.com");
+    let mut form = vec![
+        ("email", email.as_str()),
+        ("username", username),
+        ("password", PASSWORD),
+    ];
+    if let Some(invite_code) = invite_code {
+        form.push(("invite_code", invite_code));
+    }
+    app.client
+        .post(format!("{}/api/register", app.address))
+        .header("HX-Request", "true")
+        .form(&form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn add_invitation(app: &TestApp, code: &str, email: Option<&str>, uses: i32) {
+    sqlx::query!(
+        "INSERT INTO invitations (code, email, uses_remaining) VALUES ($1, $2, $3)",
+        code,
+        email,
+        uses
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+}
+
+async fn uses_remaining(app: &TestApp, code: &str) -> i32 {
+    sqlx::query_scalar!(
+        "SELECT uses_remaining FROM invitations WHERE code = $1",
+        code
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap()
+}
+
+async fn user_count(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM user_info"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn open_registration_needs_no_invitation() {
+    let app = spawn_app().await;
+    let response = register(&app, "alice", None).await;
+    assert_eq!(201, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn invite_only_registration_needs_a_valid_code() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.registration_mode = RegistrationMode::Invite;
+    })
+    .await;
+    add_invitation(&app, "twouses", None, 2).await;
+
+    let response = register(&app, "alice", None).await;
+    assert_eq!(403, response.status().as_u16());
+    let response = register(&app, "alice", Some("madeup")).await;
+    assert_eq!(403, response.status().as_u16());
+    assert_eq!(0, user_count(&app).await);
+
+    assert_eq!(
+        201,
+        register(&app, "alice", Some("twouses"))
+            .await
+            .status()
+            .as_u16()
+    );
+    assert_eq!(
+        201,
+        register(&app, "bob", Some("twouses"))
+            .await
+            .status()
+            .as_u16()
+    );
+    assert_eq!(0, uses_remaining(&app, "twouses").await);
+
+    // used up
+    let response = register(&app, "carol", Some("twouses")).await;
+    assert_eq!(403, response.status().as_u16());
+    assert_eq!(2, user_count(&app).await);
+}
+
+#[tokio::test]
+async fn failed_registrations_dont_use_up_the_invitation() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.registration_mode = RegistrationMode::Invite;
+    })
+    .await;
+    add_invitation(&app, "oneuse", None, 1).await;
+    add_invitation(&app, "another", None, 1).await;
+    assert_eq!(
+        201,
+        register(&app, "alice", Some("another"))
+            .await
+            .status()
+            .as_u16()
+    );
+
+    let response = register(&app, "alice", Some("oneuse")).await;
+    assert_eq!(409, response.status().as_u16());
+    assert_eq!(1, uses_remaining(&app, "oneuse").await);
+}
+
+#[tokio::test]
+async fn invitations_for_an_email_only_work_for_that_email() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.registration_mode = RegistrationMode::Invite;
+    })
+    .await;
+    add_invitation(&app, "foralice", Some("Alice@Test.com"), 1).await;
+
+    let response = register(&app, "bob", Some("foralice")).await;
+    assert_eq!(403, response.status().as_u16());
+    // the address matches whatever its case
+    let response = register(&app, "alice", Some("foralice")).await;
+    assert_eq!(201, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn closed_registration_collects_emails_for_the_waitlist() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.registration_mode = RegistrationMode::Closed;
+    })
+    .await;
+
+    for _ in 0..2 {
+        let response = app
+            .client
+            .post(format!("{}/api/register", app.address))
+            .header("HX-Request", "true")
+            .form(&[("email", "alice@test.com")])
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(202, response.status().as_u16());
+        assert_eq!(
+            Some("/register/waitlist"),
+            response
+                .headers()
+                .get("HX-Redirect")
+                .and_then(|value| value.to_str().ok())
+        );
+    }
+    // even with a whole registration no account is made
+    assert_eq!(202, register(&app, "bob", None).await.status().as_u16());
+    assert_eq!(0, user_count(&app).await);
+
+    let waitlist: Vec<String> = sqlx::query_scalar!("SELECT email FROM waitlist ORDER BY email")
+        .fetch_all(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(vec!["alice@test.com", "bob@test.com"], waitlist);
+
+    let response = app
+        .client
+        .get(format!("{}/register/waitlist", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("let you know"));
+}
+
+#[tokio::test]
+async fn admins_create_and_list_invitations() {
+    let app = spawn_app().await;
+    let admin = logged_in_client(&app, "alice").await;
+    make_admin(&app, "alice").await;
+
+    let response = admin
+        .post(format!("{}/admin/invitations", app.address))
+        .form(&[
+            ("email", "bob@test.com"),
+            ("uses", ""),
+            ("expires_in_days", "7"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let response = admin
+        .post(format!("{}/admin/invitations", app.address))
+        .form(&[("uses", "0")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+
+    let code = sqlx::query_scalar!("SELECT code FROM invitations")
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    let page = admin
+        .get(format!("{}/admin/invitations", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains(&format!("/register?code={code}")));
+    assert!(page.contains("bob@test.com"));
+
+    let carol = logged_in_client(&app, "carol").await;
+    let response = carol
+        .get(format!("{}/admin/invitations", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+}
@@ -0,0 +1,82 @@
+use std::net::Ipv6Addr;
+
+use uuid::Uuid;
+
+use crate::helpers::{PASSWORD, TestApp, assert_api_error, spawn_app_with};
+
+/// Logins an address can make per minute
+const LOGIN_LIMIT: usize = 10;
+
+async fn login_from(
+    app: &TestApp,
+    forwarded_for: &str,
+    username: &str,
+    password: &str,
+) -> reqwest::Response {
+    app.client
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .header("X-Forwarded-For", forwarded_for)
+        .form(&[("username", username), ("password", password)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// An address no other test uses, so buckets aren't shared between tests
+fn unique_ip() -> String {
+    Ipv6Addr::from(Uuid::new_v4().as_u128()).to_string()
+}
+
+fn retry_after(response: &reqwest::Response) -> u64 {
+    response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn throttled_logins_get_the_form_back_with_the_time_left() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.rate_limit_enabled = true;
+        config.application_settings.trusted_proxies = Some("127.0.0.1, ::1".to_string());
+    })
+    .await;
+    let ip = unique_ip();
+
+    for _ in 0..LOGIN_LIMIT {
+        let response = login_from(&app, &ip, "nobody", PASSWORD).await;
+        assert_eq!(401, response.status().as_u16());
+        let form = response.text().await.unwrap();
+        assert!(!form.contains("data-retry-after"));
+    }
+
+    let response = login_from(&app, &ip, "onetoomany", PASSWORD).await;
+    assert_eq!(429, response.status().as_u16());
+    let seconds = retry_after(&response);
+    let form = response.text().await.unwrap();
+    assert!(!form.contains("<html"), "Only the form should be sent back");
+    assert!(
+        form.contains(&format!(
+            "Too many login attempts, try again in {seconds} second"
+        )),
+        "{form}"
+    );
+    assert!(form.contains(&format!(r#"data-retry-after="{seconds}""#)));
+    assert!(form.contains(r#"<button type="submit" disabled>"#));
+    assert!(form.contains(r#"value="onetoomany""#));
+
+    // JSON clients get the code and the header
+    let response = app
+        .client
+        .post(format!("{}/api/login", app.address))
+        .header("Accept", "application/json")
+        .header("X-Forwarded-For", &ip)
+        .form(&[("username", "onetoomany"), ("password", PASSWORD)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert!(retry_after(&response) > 0);
+    assert_api_error(response, 429, "rate_limited", None).await;
+}
@@ -0,0 +1,180 @@
+use std::{net::Ipv6Addr, time::Duration};
+
+use uuid::Uuid;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app, spawn_app_with};
+
+async fn mock_email_server(app: &TestApp, expected: u64) {
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected)
+        .mount(&app.email_server)
+        .await;
+}
+
+/// Registers the user and confirms their address, as only confirmed
+/// addresses get links
+async fn verified_user(app: &TestApp, username: &str) {
+    logged_in_client(app, username).await;
+    sqlx::query!(
+        "UPDATE user_info SET email_verified_at = NOW() WHERE username = $1",
+        username
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+}
+
+async fn request_link(app: &TestApp, email: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/login/magic", app.address))
+        .form(&[("email", email)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// The link is sent in the background, wait for it to arrive
+async fn wait_for_link(app: &TestApp, email: &str) -> String {
+    app.last_email_to(email).await.link_to(app, "/login/magic/")
+}
+
+async fn open_link(link: &str) -> (reqwest::Client, reqwest::Response) {
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = client
+        .get(link)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    (client, response)
+}
+
+#[tokio::test]
+async fn link_logs_the_user_in_once() {
+    let app = spawn_app().await;
+    mock_email_server(&app, 1).await;
+    verified_user(&app, "alice").await;
+
+    let response = request_link(&app, "alice@test.com").await;
+    assert_eq!(202, response.status().as_u16());
+    let link = wait_for_link(&app, "alice@test.com").await;
+
+    let (client, response) = open_link(&link).await;
+    assert!(response.status().is_success());
+    let response = client
+        .get(format!("{}/settings", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!("/settings", response.url().path());
+
+    let (_, response) = open_link(&link).await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn expired_link_is_rejected() {
+    let app = spawn_app().await;
+    mock_email_server(&app, 1).await;
+    verified_user(&app, "alice").await;
+
+    request_link(&app, "alice@test.com").await;
+    let link = wait_for_link(&app, "alice@test.com").await;
+    sqlx::query!("UPDATE magic_links SET expires_at = NOW() - interval '1 minute'")
+        .execute(&app.db)
+        .await
+        .unwrap();
+
+    let (_, response) = open_link(&link).await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn response_is_the_same_whether_or_not_the_address_can_log_in() {
+    let app = spawn_app().await;
+    // only for the confirmed address, checked when the mock server is dropped
+    mock_email_server(&app, 1).await;
+    verified_user(&app, "alice").await;
+    logged_in_client(&app, "bob").await;
+
+    let mut responses = Vec::new();
+    for email in ["alice@test.com", "bob@test.com", "nobody@test.com"] {
+        let response = request_link(&app, email).await;
+        responses.push((response.status().as_u16(), response.text().await.unwrap()));
+    }
+
+    assert_eq!(responses[0], responses[1]);
+    assert_eq!(responses[0], responses[2]);
+    wait_for_link(&app, "alice@test.com").await;
+    // the unconfirmed one would have been sent by now
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let links = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM magic_links"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(1, links);
+}
+
+#[tokio::test]
+async fn link_stops_working_when_the_email_changes() {
+    let app = spawn_app().await;
+    mock_email_server(&app, 1).await;
+    verified_user(&app, "alice").await;
+
+    request_link(&app, "alice@test.com").await;
+    let link = wait_for_link(&app, "alice@test.com").await;
+    sqlx::query!("UPDATE user_info SET email = 'alice@example.com' WHERE username = 'alice'")
+        .execute(&app.db)
+        .await
+        .unwrap();
+
+    let (_, response) = open_link(&link).await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn links_are_rate_limited_per_address() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.rate_limit_enabled = true;
+        config.application_settings.trusted_proxies = Some("127.0.0.1, ::1".to_string());
+    })
+    .await;
+    // unknown addresses count the same as known ones, a fresh one keeps the
+    // bucket to this test
+    let email = format!("{}@test.com", Uuid::new_v4().simple());
+
+    let mut statuses = Vec::new();
+    for i in 0..4 {
+        // each from its own address, so only the per address limit applies
+        let response = app
+            .client
+            .post(format!("{}/login/magic", app.address))
+            .header(
+                "X-Forwarded-For",
+                Ipv6Addr::from(Uuid::new_v4().as_u128()).to_string(),
+            )
+            .form(&[(
+                "email",
+                if i == 3 {
+                    email.to_uppercase()
+                } else {
+                    email.clone()
+                },
+            )])
+            .send()
+            .await
+            .expect("Failed to execute request");
+        statuses.push(response.status().as_u16());
+    }
+
+    // the last one differently cased, still the same address
+    assert_eq!(vec![202, 202, 202, 429], statuses);
+}
@@ -1,3 +1,49 @@
-mod app;
+mod admin;
+mod assets;
+mod audit;
 mod auth;
+mod avatar;
+mod batch;
+mod bearer;
+mod calendar;
+mod captcha;
+mod catch_panic;
+mod cors;
+mod duplicate;
+mod email_change;
+mod email_outbox;
+mod escaping;
+mod events;
+mod features;
 mod health_check;
+mod helpers;
+mod history;
+mod idempotency;
+mod impersonation;
+mod import;
+mod invitation;
+mod login_throttling;
+mod magic_link;
+mod maintenance;
+mod new_device;
+mod notifications;
+mod passkey;
+mod pin;
+mod quota;
+mod rate_limit;
+mod reminder;
+mod routing;
+mod search;
+mod session;
+mod settings;
+mod stats;
+mod storage;
+mod subtask;
+mod tag;
+mod toast;
+mod todo;
+mod tos;
+mod undo;
+mod user_cache;
+mod username_change;
+mod webhooks;
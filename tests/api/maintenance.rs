@@ -0,0 +1,137 @@
+use crate::{
+    admin::make_admin,
+    helpers::{TestApp, assert_api_error, logged_in_client, spawn_app},
+};
+
+async fn start_maintenance(app: &TestApp, admin: &reqwest::Client, admin_bypass: bool) {
+    let mut form = vec![("message", "Upgrading the database"), ("eta_minutes", "30")];
+    if admin_bypass {
+        form.push(("admin_bypass", "on"));
+    }
+    let response = admin
+        .post(format!("{}/admin/maintenance", app.address))
+        .form(&form)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn maintenance_closes_pages_and_api() {
+    let app = spawn_app().await;
+    let admin = logged_in_client(&app, "alice").await;
+    make_admin(&app, "alice").await;
+    let user = logged_in_client(&app, "bob").await;
+
+    start_maintenance(&app, &admin, false).await;
+
+    let response = user
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(503, response.status().as_u16());
+    let retry_after: u64 = response.headers()["retry-after"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 25 * 60 && retry_after <= 30 * 60);
+    let body = response.text().await.unwrap();
+    assert!(body.contains("Down for maintenance"));
+    assert!(body.contains("Upgrading the database"));
+
+    let response = user
+        .get(format!("{}/api/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert!(response.headers().contains_key("retry-after"));
+    assert_api_error(response, 503, "maintenance", None).await;
+
+    // admins too, unless they are let through
+    let response = admin
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(503, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn health_check_stays_up_during_maintenance() {
+    let app = spawn_app().await;
+    let admin = logged_in_client(&app, "alice").await;
+    make_admin(&app, "alice").await;
+
+    start_maintenance(&app, &admin, false).await;
+
+    let response = app
+        .client
+        .get(format!("{}/health_check", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn admins_can_bypass_maintenance() {
+    let app = spawn_app().await;
+    let admin = logged_in_client(&app, "alice").await;
+    make_admin(&app, "alice").await;
+    let user = logged_in_client(&app, "bob").await;
+
+    start_maintenance(&app, &admin, true).await;
+
+    let response = admin
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let response = user
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(503, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn ending_maintenance_opens_the_site() {
+    let app = spawn_app().await;
+    let admin = logged_in_client(&app, "alice").await;
+    make_admin(&app, "alice").await;
+    let user = logged_in_client(&app, "bob").await;
+    start_maintenance(&app, &admin, false).await;
+
+    let response = admin
+        .post(format!("{}/admin/maintenance/clear", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = user
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn ordinary_users_cannot_start_maintenance() {
+    let app = spawn_app().await;
+    let user = logged_in_client(&app, "bob").await;
+
+    let response = user
+        .post(format!("{}/admin/maintenance", app.address))
+        .form(&[("message", "closed")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app};
+
+const FIREFOX: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:140.0) Gecko/20100101 Firefox/140.0";
+
+async fn login_with_user_agent(app: &TestApp, username: &str, user_agent: &str) {
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .header("User-Agent", user_agent)
+        .form(&[
+            ("username", username),
+            ("password", "correct horse battery staple"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
+
+/// Devices are recorded in the background, wait until the login was processed
+async fn wait_for_devices(app: &TestApp, expected: i64) {
+    for _ in 0..50 {
+        let count = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM known_devices"#)
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+        if count == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Timed out waiting for {expected} known devices");
+}
+
+#[tokio::test]
+async fn login_from_a_new_device_sends_an_email_once() {
+    let app = spawn_app().await;
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // the first device of an account doesn't trigger an alert
+    logged_in_client(&app, "alice").await;
+    wait_for_devices(&app, 1).await;
+
+    login_with_user_agent(&app, "alice", FIREFOX).await;
+    let email = app.last_email_to("alice@test.com").await;
+    assert_eq!("New sign-in to your account", email.subject);
+    assert!(email.text_body.contains("Firefox"));
+    assert!(email.html_body.contains("Firefox"));
+    assert!(email.text_body.contains("127.0.0.1"));
+    email.link_to(&app, "/settings");
+
+    // a repeat login, even after a browser update, is not new
+    login_with_user_agent(
+        &app,
+        "alice",
+        "Mozilla/5.0 (X11; Linux x86_64; rv:141.0) Gecko/20100101 Firefox/141.0",
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    wait_for_devices(&app, 2).await;
+}
+
+#[tokio::test]
+async fn new_device_alerts_can_be_turned_off() {
+    let app = spawn_app().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    logged_in_client(&app, "alice").await;
+    wait_for_devices(&app, 1).await;
+    sqlx::query!("UPDATE user_info SET new_device_alerts = FALSE WHERE username = 'alice'")
+        .execute(&app.db)
+        .await
+        .unwrap();
+
+    login_with_user_agent(&app, "alice", FIREFOX).await;
+    wait_for_devices(&app, 2).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
@@ -0,0 +1,146 @@
+use site::worker::notifications::prune_notifications;
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, list_id_of, logged_in_client, spawn_app};
+
+async fn share_list(app: &TestApp, client: &reqwest::Client, list_id: Uuid, username: &str) {
+    let response = client
+        .post(format!("{}/lists/{}/share", app.address, list_id))
+        .header("HX-Request", "true")
+        .form(&[("username", username), ("role", "editor")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
+
+async fn get_text(app: &TestApp, client: &reqwest::Client, path: &str) -> String {
+    let response = client
+        .get(format!("{}{}", app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    response.text().await.unwrap()
+}
+
+async fn post(app: &TestApp, client: &reqwest::Client, path: &str) -> reqwest::Response {
+    client
+        .post(format!("{}{}", app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn sharing_a_list_notifies_the_new_member() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    assert_eq!("", get_text(&app, &bob, "/notifications/bell").await.trim());
+
+    let list_id = list_id_of(&app, "alice").await;
+    share_list(&app, &alice, list_id, "bob").await;
+
+    assert!(
+        get_text(&app, &bob, "/notifications/bell")
+            .await
+            .contains(">1</span>")
+    );
+    let page = get_text(&app, &bob, "/notifications").await;
+    assert!(page.contains(&format!(
+        r#"<a href="/todo?list_id={list_id}">alice shared a list with you as editor</a>"#
+    )));
+    assert!(page.contains(r#"class="unread""#));
+
+    // only the member is notified
+    assert_eq!(
+        "",
+        get_text(&app, &alice, "/notifications/bell").await.trim()
+    );
+}
+
+#[tokio::test]
+async fn notifications_can_be_marked_as_read() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    let carol = logged_in_client(&app, "carol").await;
+    let list_id = list_id_of(&app, "alice").await;
+    share_list(&app, &alice, list_id, "bob").await;
+    share_list(&app, &alice, list_id, "bob").await;
+
+    let notification_id = sqlx::query_scalar!(
+        r#"
+        SELECT notification_id FROM notifications
+        JOIN user_info USING (user_id)
+        WHERE username = 'bob'
+        LIMIT 1
+        "#
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+
+    let path = format!("/notifications/{notification_id}/read");
+    assert_eq!(404, post(&app, &carol, &path).await.status().as_u16());
+    assert_eq!(200, post(&app, &bob, &path).await.status().as_u16());
+    assert!(
+        get_text(&app, &bob, "/notifications/bell")
+            .await
+            .contains(">1</span>")
+    );
+
+    let response = post(&app, &bob, "/notifications/read-all").await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("/notifications", response.headers()["HX-Redirect"]);
+    assert_eq!("", get_text(&app, &bob, "/notifications/bell").await.trim());
+    assert!(
+        !get_text(&app, &bob, "/notifications")
+            .await
+            .contains(r#"class="unread""#)
+    );
+}
+
+#[tokio::test]
+async fn notifications_require_login() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let response = client
+        .get(format!("{}/notifications/bell", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(307, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn old_notifications_are_pruned() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    logged_in_client(&app, "bob").await;
+    let list_id = list_id_of(&app, "alice").await;
+    share_list(&app, &alice, list_id, "bob").await;
+    sqlx::query!(
+        r#"
+        INSERT INTO notifications (user_id, kind, payload, created_at)
+        SELECT user_id, 'list_shared', '{}', NOW() - INTERVAL '100 days'
+        FROM user_info WHERE username = 'bob'
+        "#
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let pruned = prune_notifications(&app.db, 90).await.unwrap();
+    assert_eq!(1, pruned);
+    let left = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM notifications"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(1, left);
+}
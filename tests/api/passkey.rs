@@ -0,0 +1,232 @@
+use webauthn_authenticator_rs::{WebauthnAuthenticator, softpasskey::SoftPasskey};
+use webauthn_rs::prelude::{CreationChallengeResponse, RequestChallengeResponse, Url};
+
+use crate::{
+    audit::wait_for_events,
+    helpers::{TestApp, assert_api_error, logged_in_client, spawn_app_with},
+};
+
+const ORIGIN: &str = "http://localhost:8000";
+
+async fn spawn_app_with_passkeys() -> TestApp {
+    spawn_app_with(|config| {
+        config.application_settings.webauthn_rp_origin = Some(ORIGIN.to_string());
+    })
+    .await
+}
+
+fn authenticator() -> WebauthnAuthenticator<SoftPasskey> {
+    WebauthnAuthenticator::new(SoftPasskey::new(true))
+}
+
+fn cookie_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .unwrap()
+}
+
+/// Runs the registration ceremony for the logged in user of `client`
+async fn register_passkey(
+    app: &TestApp,
+    client: &reqwest::Client,
+    authenticator: &mut WebauthnAuthenticator<SoftPasskey>,
+    label: &str,
+) -> reqwest::Response {
+    let response = client
+        .post(format!("{}/settings/passkeys/start", app.address))
+        .form(&[("label", label)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let challenge: CreationChallengeResponse = response.json().await.unwrap();
+
+    let credential = authenticator
+        .do_registration(Url::parse(ORIGIN).unwrap(), challenge)
+        .expect("Failed to create passkey");
+
+    client
+        .post(format!("{}/settings/passkeys/finish", app.address))
+        .json(&credential)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn start_login(app: &TestApp, client: &reqwest::Client, username: &str) -> reqwest::Response {
+    client
+        .post(format!("{}/api/login/passkey/start", app.address))
+        .form(&[("username", username)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// Runs the login ceremony, returning the response to the answered challenge
+async fn log_in_with_passkey(
+    app: &TestApp,
+    client: &reqwest::Client,
+    authenticator: &mut WebauthnAuthenticator<SoftPasskey>,
+    username: &str,
+) -> reqwest::Response {
+    let response = start_login(app, client, username).await;
+    assert_eq!(200, response.status().as_u16());
+    let challenge: RequestChallengeResponse = response.json().await.unwrap();
+
+    let credential = authenticator
+        .do_authentication(Url::parse(ORIGIN).unwrap(), challenge)
+        .expect("Failed to use passkey");
+
+    client
+        .post(format!("{}/api/login/passkey/finish", app.address))
+        .json(&credential)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn settings_page(app: &TestApp, client: &reqwest::Client) -> reqwest::Response {
+    client
+        .get(format!("{}/settings", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn registered_passkey_logs_the_user_in() {
+    let app = spawn_app_with_passkeys().await;
+    let client = logged_in_client(&app, "alice").await;
+    let mut authenticator = authenticator();
+
+    let response = register_passkey(&app, &client, &mut authenticator, "Work laptop").await;
+    assert_eq!(201, response.status().as_u16());
+    let page = settings_page(&app, &client).await.text().await.unwrap();
+    assert!(page.contains("Work laptop"));
+
+    let passkey_client = cookie_client();
+    let response = log_in_with_passkey(&app, &passkey_client, &mut authenticator, "alice").await;
+    assert_eq!(200, response.status().as_u16());
+
+    let response = settings_page(&app, &passkey_client).await;
+    assert_eq!("/settings", response.url().path());
+
+    let passkey = sqlx::query!("SELECT sign_count, last_used_at FROM passkeys")
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert!(passkey.sign_count > 0);
+    assert!(passkey.last_used_at.is_some());
+    wait_for_events(&app, "passkey_added", 1).await;
+    wait_for_events(&app, "login_succeeded", 2).await;
+}
+
+#[tokio::test]
+async fn user_without_passkeys_is_refused() {
+    let app = spawn_app_with_passkeys().await;
+    logged_in_client(&app, "alice").await;
+
+    let response = start_login(&app, &cookie_client(), "alice").await;
+    assert_api_error(response, 401, "invalid_credentials", None).await;
+
+    let response = start_login(&app, &cookie_client(), "nobody").await;
+    assert_api_error(response, 401, "invalid_credentials", None).await;
+}
+
+#[tokio::test]
+async fn challenge_can_only_be_answered_once() {
+    let app = spawn_app_with_passkeys().await;
+    let client = logged_in_client(&app, "alice").await;
+    let mut authenticator = authenticator();
+    register_passkey(&app, &client, &mut authenticator, "Phone").await;
+
+    let passkey_client = cookie_client();
+    let response = start_login(&app, &passkey_client, "alice").await;
+    let challenge: RequestChallengeResponse = response.json().await.unwrap();
+    let credential = authenticator
+        .do_authentication(Url::parse(ORIGIN).unwrap(), challenge)
+        .unwrap();
+
+    for expected in [200, 401] {
+        let response = passkey_client
+            .post(format!("{}/api/login/passkey/finish", app.address))
+            .json(&credential)
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(expected, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn sign_count_regression_is_rejected_as_suspicious() {
+    let app = spawn_app_with_passkeys().await;
+    let client = logged_in_client(&app, "alice").await;
+    let mut authenticator = authenticator();
+    register_passkey(&app, &client, &mut authenticator, "Security key").await;
+
+    // as if a clone of the key had been used many more times
+    sqlx::query!(
+        r#"
+        UPDATE passkeys SET
+            sign_count = 1000,
+            public_key = jsonb_set(public_key, '{cred,counter}', '1000')
+        "#
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let response = log_in_with_passkey(&app, &cookie_client(), &mut authenticator, "alice").await;
+
+    assert_api_error(response, 401, "passkey_rejected", None).await;
+    wait_for_events(&app, "suspicious_passkey_use", 1).await;
+}
+
+#[tokio::test]
+async fn removed_passkey_can_no_longer_log_in() {
+    let app = spawn_app_with_passkeys().await;
+    let client = logged_in_client(&app, "alice").await;
+    let mut authenticator = authenticator();
+    register_passkey(&app, &client, &mut authenticator, "Old phone").await;
+    let passkey_id = sqlx::query_scalar!("SELECT passkey_id FROM passkeys")
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+
+    // only its owner can remove it
+    let bob = logged_in_client(&app, "bob").await;
+    let response = bob
+        .delete(format!("{}/settings/passkeys/{}", app.address, passkey_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+
+    let response = client
+        .delete(format!("{}/settings/passkeys/{}", app.address, passkey_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    wait_for_events(&app, "passkey_removed", 1).await;
+
+    let response = start_login(&app, &cookie_client(), "alice").await;
+    assert_api_error(response, 401, "invalid_credentials", None).await;
+}
+
+#[tokio::test]
+async fn passkey_needs_a_label() {
+    let app = spawn_app_with_passkeys().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = client
+        .post(format!("{}/settings/passkeys/start", app.address))
+        .form(&[("label", "  ")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(400, response.status().as_u16());
+}
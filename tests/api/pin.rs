@@ -0,0 +1,103 @@
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app};
+
+async fn create_todo(app: &TestApp, client: &reqwest::Client, content: &str) -> Uuid {
+    let response = client
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", content)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    sqlx::query_scalar!("SELECT todo_id FROM todo WHERE todo_content = $1", content)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to fetch todo id")
+}
+
+async fn toggle_pin(app: &TestApp, client: &reqwest::Client, todo_id: Uuid) -> reqwest::Response {
+    client
+        .post(format!("{}/todo/{}/pin", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn pinned_todos_are_listed_first() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let older = create_todo(&app, &alice, "older todo").await;
+    create_todo(&app, &alice, "newer todo").await;
+
+    let response = toggle_pin(&app, &alice, older).await;
+    assert_eq!(200, response.status().as_u16());
+    let trigger = response.headers()["hx-trigger-after-swap"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(trigger.contains("todoPinned"));
+    let body = response.text().await.unwrap();
+    assert!(body.contains(&format!("id=\"todo-{older}\"")));
+    assert!(body.contains("data-pinned"));
+
+    let body = alice
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.find("older todo").unwrap() < body.find("newer todo").unwrap());
+
+    // toggling again unpins
+    toggle_pin(&app, &alice, older).await;
+    let is_pinned = sqlx::query_scalar!("SELECT is_pinned FROM todo WHERE todo_id = $1", older)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert!(!is_pinned);
+}
+
+#[tokio::test]
+async fn pins_are_capped() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let mut todo_ids = Vec::new();
+    for i in 0..11 {
+        todo_ids.push(create_todo(&app, &alice, &format!("todo {i}")).await);
+    }
+    for todo_id in &todo_ids[..10] {
+        let response = toggle_pin(&app, &alice, *todo_id).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    let response = toggle_pin(&app, &alice, todo_ids[10]).await;
+    assert_eq!(422, response.status().as_u16());
+    assert_eq!(
+        "You can pin up to 10 todos, unpin one first",
+        response.text().await.unwrap()
+    );
+
+    // unpinning is always possible and frees a slot
+    let response = toggle_pin(&app, &alice, todo_ids[0]).await;
+    assert_eq!(200, response.status().as_u16());
+    let response = toggle_pin(&app, &alice, todo_ids[10]).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn strangers_cannot_pin() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+
+    let response = toggle_pin(&app, &bob, todo_id).await;
+    assert_eq!(404, response.status().as_u16());
+}
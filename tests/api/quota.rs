@@ -0,0 +1,247 @@
+use std::time::Duration;
+
+use reqwest::multipart::{Form, Part};
+use site::worker::purge::purge_deleted_todos;
+use uuid::Uuid;
+
+use crate::{
+    admin::make_admin,
+    helpers::{TestApp, logged_in_client, spawn_app, spawn_app_with},
+};
+
+async fn create_todo(app: &TestApp, client: &reqwest::Client, content: &str) -> reqwest::Response {
+    client
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", content)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn import_csv(app: &TestApp, client: &reqwest::Client, csv: &str) -> reqwest::Response {
+    let form = Form::new().part(
+        "file",
+        Part::bytes(csv.as_bytes().to_vec())
+            .file_name("todos.csv")
+            .mime_str("text/csv")
+            .unwrap(),
+    );
+    client
+        .post(format!("{}/todo/import", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn todo_id(app: &TestApp, content: &str) -> Uuid {
+    sqlx::query_scalar!("SELECT todo_id FROM todo WHERE todo_content = $1", content)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to fetch todo id")
+}
+
+async fn post(app: &TestApp, client: &reqwest::Client, path: &str) -> reqwest::Response {
+    client
+        .post(format!("{}{}", app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// The counter of the user, checked against the todos it counts
+async fn active_todo_count(app: &TestApp, username: &str) -> i64 {
+    let counts = sqlx::query!(
+        r#"
+        SELECT
+            ui.active_todo_count,
+            (
+                SELECT COUNT(*) FROM todo AS td
+                WHERE td.user_id = ui.user_id AND td.deleted_at IS NULL
+            ) AS "actual!"
+        FROM user_info AS ui
+        WHERE ui.username = $1
+        "#,
+        username
+    )
+    .fetch_one(&app.db)
+    .await
+    .expect("Failed to count todos");
+    assert_eq!(counts.actual, counts.active_todo_count);
+    counts.active_todo_count
+}
+
+#[tokio::test]
+async fn active_todo_count_follows_every_change() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    assert_eq!(0, active_todo_count(&app, "alice").await);
+
+    assert_eq!(201, create_todo(&app, &alice, "buy milk").await.status());
+    assert_eq!(201, create_todo(&app, &alice, "walk dog").await.status());
+    assert_eq!(2, active_todo_count(&app, "alice").await);
+
+    let response = import_csv(&app, &alice, "todo_content\ncall mom\nfile taxes\n").await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(4, active_todo_count(&app, "alice").await);
+
+    let milk = todo_id(&app, "buy milk").await;
+    let dog = todo_id(&app, "walk dog").await;
+    for todo_id in [milk, dog] {
+        let response = alice
+            .delete(format!("{}/todo/{}", app.address, todo_id))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(200, response.status().as_u16());
+    }
+    assert_eq!(2, active_todo_count(&app, "alice").await);
+
+    let response = post(&app, &alice, &format!("/todo/{milk}/undo")).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(3, active_todo_count(&app, "alice").await);
+
+    // the deleted todo was already taken off the count
+    let purged = purge_deleted_todos(&app.db, Duration::ZERO).await.unwrap();
+    assert_eq!(1, purged);
+    assert_eq!(3, active_todo_count(&app, "alice").await);
+}
+
+#[tokio::test]
+async fn todos_past_the_limit_are_refused() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.max_active_todos = 2;
+    })
+    .await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    assert_eq!(201, create_todo(&app, &alice, "buy milk").await.status());
+    let response = import_csv(&app, &alice, "todo_content\ncall mom\nfile taxes\n").await;
+    assert_eq!(422, response.status().as_u16());
+    assert_eq!(
+        "Importing these todos would take you over your limit of 2 todos",
+        response.text().await.unwrap()
+    );
+    assert_eq!(1, active_todo_count(&app, "alice").await);
+
+    assert_eq!(201, create_todo(&app, &alice, "walk dog").await.status());
+    let response = create_todo(&app, &alice, "call mom").await;
+    assert_eq!(422, response.status().as_u16());
+    // shown in the form, which keeps what was typed
+    let form = response.text().await.unwrap();
+    assert!(form.contains("You can&#39;t have more than 2 todos, delete some to add new ones"));
+    assert!(form.contains(r#"value="call mom""#));
+    assert_eq!(2, active_todo_count(&app, "alice").await);
+
+    let milk = todo_id(&app, "buy milk").await;
+    let response = alice
+        .delete(format!("{}/todo/{}", app.address, milk))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(201, create_todo(&app, &alice, "call mom").await.status());
+}
+
+#[tokio::test]
+async fn undoing_a_delete_is_refused_once_the_limit_is_reached() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.max_active_todos = 2;
+    })
+    .await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    assert_eq!(201, create_todo(&app, &alice, "buy milk").await.status());
+    assert_eq!(201, create_todo(&app, &alice, "walk dog").await.status());
+    let milk = todo_id(&app, "buy milk").await;
+    let response = alice
+        .delete(format!("{}/todo/{}", app.address, milk))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    // the freed place is taken again before the undo
+    assert_eq!(201, create_todo(&app, &alice, "call mom").await.status());
+    let response = post(&app, &alice, &format!("/todo/{milk}/undo")).await;
+    assert_eq!(422, response.status().as_u16());
+    assert_eq!(
+        "You can't have more than 2 todos, delete some to add new ones",
+        response.text().await.unwrap()
+    );
+    assert_eq!(2, active_todo_count(&app, "alice").await);
+
+    // up to the limit, it is restored
+    let mom = todo_id(&app, "call mom").await;
+    let response = alice
+        .delete(format!("{}/todo/{}", app.address, mom))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let response = post(&app, &alice, &format!("/todo/{milk}/undo")).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(2, active_todo_count(&app, "alice").await);
+}
+
+#[tokio::test]
+async fn admins_can_give_a_user_a_limit_of_their_own() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.max_active_todos = 1;
+    })
+    .await;
+    let admin = logged_in_client(&app, "admin").await;
+    make_admin(&app, "admin").await;
+    let alice = logged_in_client(&app, "alice").await;
+    let alice_id = sqlx::query_scalar!("SELECT user_id FROM user_info WHERE username = 'alice'")
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    let path = format!("{}/admin/users/{}/todo-limit", app.address, alice_id);
+
+    let response = admin
+        .post(&path)
+        .form(&[("todo_limit", "-1")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+    let response = alice
+        .post(&path)
+        .form(&[("todo_limit", "2")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+
+    let response = admin
+        .post(&path)
+        .form(&[("todo_limit", "2")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(201, create_todo(&app, &alice, "buy milk").await.status());
+    assert_eq!(201, create_todo(&app, &alice, "walk dog").await.status());
+    assert_eq!(422, create_todo(&app, &alice, "call mom").await.status());
+
+    // back to the configured limit
+    let response = admin
+        .post(&path)
+        .form(&[("todo_limit", "")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(422, create_todo(&app, &alice, "call mom").await.status());
+    let page = admin
+        .get(format!("{}/admin", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("2 of"));
+}
@@ -0,0 +1,132 @@
+use std::{
+    net::{IpAddr, Ipv6Addr},
+    time::Duration,
+};
+
+use clap::Parser;
+use site::{
+    config::Config,
+    rate_limit::RateLimit,
+    redis::{self, Redis},
+};
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, assert_api_error, spawn_app_with};
+
+/// Registration allows this many attempts per address
+const REGISTER_LIMIT: usize = 5;
+
+async fn register_from(app: &TestApp, forwarded_for: &str, username: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/api/register", app.address))
+        .header("HX-Request", "true")
+        .header("X-Forwarded-For", forwarded_for)
+        .form(&[
+            ("email", format!("{username}@test.com")),
+            ("username", username.to_string()),
+            ("password", "correct horse battery staple".to_string()),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// An address no other test uses, so buckets aren't shared between tests
+fn unique_ip() -> String {
+    Ipv6Addr::from(Uuid::new_v4().as_u128()).to_string()
+}
+
+async fn connect_redis() -> Redis {
+    dotenvy::dotenv().ok();
+    let config = Config::parse();
+    redis::connect(&config)
+        .await
+        .expect("Failed to connect to Redis")
+}
+
+#[tokio::test]
+async fn registration_is_limited_per_client_address() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.rate_limit_enabled = true;
+        config.application_settings.trusted_proxies = Some("127.0.0.1, ::1".to_string());
+    })
+    .await;
+    let ip = unique_ip();
+
+    for i in 0..REGISTER_LIMIT {
+        let response = register_from(&app, &ip, &format!("user{i}")).await;
+        assert_eq!(201, response.status().as_u16());
+    }
+
+    let response = register_from(&app, &ip, "onetoomany").await;
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0);
+    assert_api_error(response, 429, "rate_limited", None).await;
+
+    // the client is only limited at its own address
+    let response = register_from(&app, &unique_ip(), "onetoomany").await;
+    assert_eq!(201, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_proxy_adds_the_last_forwarded_address() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.rate_limit_enabled = true;
+        config.application_settings.trusted_proxies = Some("127.0.0.1, ::1".to_string());
+    })
+    .await;
+    let ip = unique_ip();
+
+    // a client making up addresses in front of its real one gets nowhere
+    for i in 0..REGISTER_LIMIT {
+        let forwarded_for = format!("{}, {ip}", unique_ip());
+        let response = register_from(&app, &forwarded_for, &format!("user{i}")).await;
+        assert_eq!(201, response.status().as_u16());
+    }
+
+    let forwarded_for = format!("{}, {ip}", unique_ip());
+    let response = register_from(&app, &forwarded_for, "onetoomany").await;
+    assert_eq!(429, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn forwarded_addresses_are_ignored_without_a_trusted_proxy() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.rate_limit_enabled = true;
+        config.application_settings.trusted_proxies = None;
+    })
+    .await;
+
+    // the peer address may already have used some of its attempts in an
+    // earlier run, so only the last attempt is certain to be limited
+    let mut last_status = 0;
+    for i in 0..=REGISTER_LIMIT {
+        let response = register_from(&app, &unique_ip(), &format!("user{i}")).await;
+        last_status = response.status().as_u16();
+    }
+    assert_eq!(429, last_status);
+}
+
+#[tokio::test]
+async fn the_limit_recovers_after_waiting() {
+    let redis = connect_redis().await;
+    let rate_limit = RateLimit::new("test", 2, Duration::from_secs(1));
+    let ip: IpAddr = Ipv6Addr::from(Uuid::new_v4().as_u128()).into();
+
+    assert_eq!(None, rate_limit.check(&redis, ip).await.unwrap());
+    assert_eq!(None, rate_limit.check(&redis, ip).await.unwrap());
+
+    let retry_after = rate_limit
+        .check(&redis, ip)
+        .await
+        .unwrap()
+        .expect("Bucket should be empty");
+    assert!(retry_after <= Duration::from_secs(1));
+
+    tokio::time::sleep(retry_after).await;
+    assert_eq!(None, rate_limit.check(&redis, ip).await.unwrap());
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app};
+
+async fn create_todo_due_in(
+    app: &TestApp,
+    client: &reqwest::Client,
+    content: &str,
+    days: i32,
+) -> Uuid {
+    let due_date: String = sqlx::query_scalar!(
+        r#"SELECT to_char(CURRENT_DATE + $1::int, 'YYYY-MM-DD') AS "due_date!""#,
+        days
+    )
+    .fetch_one(&app.db)
+    .await
+    .expect("Failed to compute due date");
+
+    let response = client
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", content), ("due_date", &due_date)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    sqlx::query_scalar!("SELECT todo_id FROM todo WHERE todo_content = $1", content)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to fetch todo id")
+}
+
+async fn reminder_sent(app: &TestApp, todo_id: Uuid) -> bool {
+    sqlx::query_scalar!(
+        r#"SELECT reminder_sent_at IS NOT NULL AS "sent!" FROM todo WHERE todo_id = $1"#,
+        todo_id
+    )
+    .fetch_one(&app.db)
+    .await
+    .expect("Failed to fetch reminder state")
+}
+
+async fn wait_for_reminder(app: &TestApp, todo_id: Uuid) {
+    for _ in 0..50 {
+        if reminder_sent(app, todo_id).await {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Reminder was not sent in time");
+}
+
+#[tokio::test]
+async fn reminder_is_sent_once_for_todo_due_today() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let todo_id = create_todo_due_in(&app, &alice, "file taxes", 0).await;
+    wait_for_reminder(&app, todo_id).await;
+
+    // give the worker a couple more ticks to make sure it doesn't send again
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+
+    let emails = app.emails().await;
+    assert_eq!("alice@test.com", emails[0].to);
+    assert!(emails[0].text_body.contains("file taxes"));
+}
+
+#[tokio::test]
+async fn no_reminder_for_opted_out_users_or_todos_not_due_today() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    sqlx::query!("UPDATE user_info SET due_date_reminders = FALSE WHERE username = 'alice'")
+        .execute(&app.db)
+        .await
+        .expect("Failed to opt out of reminders");
+
+    let opted_out = create_todo_due_in(&app, &alice, "file taxes", 0).await;
+    let due_tomorrow = create_todo_due_in(&app, &bob, "walk dog", 1).await;
+
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+
+    assert!(!reminder_sent(&app, opted_out).await);
+    assert!(!reminder_sent(&app, due_tomorrow).await);
+}
@@ -0,0 +1,77 @@
+use crate::helpers::{assert_api_error, spawn_app};
+
+#[tokio::test]
+async fn unknown_api_routes_get_a_json_error() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .get(format!("{}/api/does-not-exist", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_api_error(response, 404, "not_found", None).await;
+}
+
+#[tokio::test]
+async fn api_routes_called_with_the_wrong_method_get_a_json_error() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .delete(format!("{}/api/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_api_error(response, 405, "method_not_allowed", None).await;
+}
+
+#[tokio::test]
+async fn unknown_pages_are_not_answered_with_json() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .get(format!("{}/does-not-exist", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(404, response.status().as_u16());
+    let content_type = response
+        .headers()
+        .get("Content-Type")
+        .map(|value| value.to_str().unwrap().to_string());
+    assert!(!content_type.is_some_and(|value| value.starts_with("application/json")));
+}
+
+#[tokio::test]
+async fn logged_out_clients_get_a_json_error_from_the_api_and_a_redirect_from_pages() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let response = client
+        .get(format!("{}/api/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_api_error(response, 401, "not_logged_in", None).await;
+
+    let response = client
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(307, response.status().as_u16());
+    assert!(
+        response.headers()["Location"]
+            .to_str()
+            .unwrap()
+            .starts_with("/login")
+    );
+}
@@ -0,0 +1,66 @@
+use crate::helpers::{LoggedInClient, spawn_app};
+
+async fn search(client: &LoggedInClient, app_address: &str, q: &str) -> String {
+    let response = client
+        .client
+        .get(format!("{app_address}/todo/search"))
+        .query(&[("q", q)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn search_finds_todos_with_the_words() {
+    let app = spawn_app().await;
+    let alice = app.register_and_login("alice", "password123").await;
+    let groceries = alice.create_todo("buy groceries").await;
+    alice.create_todo("call mom").await;
+
+    let page = search(&alice, &app.address, "groceries").await;
+    assert!(page.contains(&format!(
+        r#"<a href="/todo/{}">buy groceries</a>"#,
+        groceries.todo_id
+    )));
+    assert!(!page.contains("call mom"));
+    assert!(!page.contains("Did you mean"));
+}
+
+#[tokio::test]
+async fn search_suggests_similar_todos_after_a_typo() {
+    let app = spawn_app().await;
+    let alice = app.register_and_login("alice", "password123").await;
+    alice.create_todo("buy groceries").await;
+    alice.create_todo("call mom").await;
+
+    let page = search(&alice, &app.address, "grocceries").await;
+    assert!(page.contains("Did you mean"));
+    assert!(page.contains("buy groceries"));
+    assert!(!page.contains("call mom"));
+}
+
+#[tokio::test]
+async fn search_without_anything_similar_finds_nothing() {
+    let app = spawn_app().await;
+    let alice = app.register_and_login("alice", "password123").await;
+    alice.create_todo("buy groceries").await;
+
+    let page = search(&alice, &app.address, "xyzzy").await;
+    assert!(page.contains("No todos match"));
+    assert!(!page.contains("buy groceries"));
+}
+
+#[tokio::test]
+async fn search_only_finds_todos_of_lists_the_user_can_see() {
+    let app = spawn_app().await;
+    let alice = app.register_and_login("alice", "password123").await;
+    let bob = app.register_and_login("bob", "password123").await;
+    alice.create_todo("buy groceries").await;
+
+    for q in ["groceries", "grocceries"] {
+        let page = search(&bob, &app.address, q).await;
+        assert!(!page.contains("buy groceries"));
+    }
+}
@@ -0,0 +1,334 @@
+use std::sync::Arc;
+
+use site::{
+    auth::sessions::{self, RedisSessionStore},
+    config::SessionLimitStrategy,
+};
+use time::OffsetDateTime;
+use tokio::task::JoinSet;
+use tower_sessions::SessionStore;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{PASSWORD, TestApp, spawn_app, spawn_app_with};
+
+/// The `Set-Cookie` header of the session cookie, if the response has one
+fn set_session_cookie(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get_all("set-cookie")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find(|value| value.starts_with("id="))
+        .map(str::to_string)
+}
+
+/// The `id=...` pair to send back, from a `Set-Cookie` header
+fn cookie_pair(set_cookie: &str) -> String {
+    set_cookie.split(';').next().unwrap().to_string()
+}
+
+/// A client that doesn't keep cookies, so each test decides which session
+/// cookie is sent
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+async fn register(app: &TestApp, username: &str) {
+    let response = client()
+        .post(format!("{}/api/register", app.address))
+        .header("HX-Request", "true")
+        .form(&[
+            ("email", format!("{username}@test.com").as_str()),
+            ("username", username),
+            ("password", PASSWORD),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+}
+
+/// Logs in with the session cookie, if any, returning the new one
+async fn login(app: &TestApp, username: &str, cookie: Option<&str>) -> String {
+    let mut request = client()
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[("username", username), ("password", PASSWORD)]);
+    if let Some(cookie) = cookie {
+        request = request.header("Cookie", cookie);
+    }
+    let response = request.send().await.expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    cookie_pair(&set_session_cookie(&response).expect("No session cookie after login"))
+}
+
+/// Whether the session cookie is logged in, going by a page that needs it
+async fn is_logged_in(app: &TestApp, cookie: &str) -> bool {
+    let response = client()
+        .get(format!("{}/settings", app.address))
+        .header("Cookie", cookie)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    response.status().as_u16() == 200
+}
+
+#[tokio::test]
+async fn login_replaces_a_planted_session_id() {
+    let app = spawn_app().await;
+    register(&app, "alice").await;
+
+    // starting a passkey login gives an anonymous session, which an attacker
+    // could get a victim's browser to use
+    let response = client()
+        .post(format!("{}/api/login/passkey/start", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let planted = cookie_pair(&set_session_cookie(&response).expect("No session cookie"));
+
+    let logged_in = login(&app, "alice", Some(&planted)).await;
+    assert_ne!(planted, logged_in);
+    assert!(is_logged_in(&app, &logged_in).await);
+    assert!(!is_logged_in(&app, &planted).await);
+}
+
+#[tokio::test]
+async fn logging_in_again_replaces_the_session_id() {
+    let app = spawn_app().await;
+    register(&app, "alice").await;
+    register(&app, "bob").await;
+
+    let alice = login(&app, "alice", None).await;
+    // axum-login keeps the id of a session that already is logged in
+    let bob = login(&app, "bob", Some(&alice)).await;
+    assert_ne!(alice, bob);
+    assert!(is_logged_in(&app, &bob).await);
+    assert!(!is_logged_in(&app, &alice).await);
+}
+
+#[tokio::test]
+async fn confirming_an_email_change_replaces_the_session_id() {
+    let app = spawn_app().await;
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    register(&app, "alice").await;
+    let cookie = login(&app, "alice", None).await;
+
+    let response = client()
+        .post(format!("{}/api/user/email", app.address))
+        .header("Cookie", &cookie)
+        .form(&[("email", "alice@new.com")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(202, response.status().as_u16());
+    let link = app
+        .last_email_to("alice@new.com")
+        .await
+        .link_to(&app, "/confirm-email");
+
+    let response = client()
+        .get(&link)
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let rotated = cookie_pair(&set_session_cookie(&response).expect("No new session cookie"));
+    assert_ne!(cookie, rotated);
+    assert!(is_logged_in(&app, &rotated).await);
+    assert!(!is_logged_in(&app, &cookie).await);
+}
+
+#[tokio::test]
+async fn session_cookie_has_the_configured_same_site() {
+    let app = spawn_app().await;
+    register(&app, "alice").await;
+    let response = client()
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[("username", "alice"), ("password", PASSWORD)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert!(
+        set_session_cookie(&response)
+            .unwrap()
+            .contains("SameSite=Lax")
+    );
+
+    let app = spawn_app_with(|config| {
+        config.application_settings.session_same_site = site::config::SessionSameSite::Strict;
+    })
+    .await;
+    register(&app, "alice").await;
+    let response = client()
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[("username", "alice"), ("password", PASSWORD)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert!(
+        set_session_cookie(&response)
+            .unwrap()
+            .contains("SameSite=Strict")
+    );
+}
+
+async fn spawn_app_with_session_limit(strategy: SessionLimitStrategy) -> TestApp {
+    spawn_app_with(|config| {
+        config.application_settings.max_sessions_per_user = Some(2);
+        config.application_settings.session_limit_strategy = strategy;
+    })
+    .await
+}
+
+async fn try_login(app: &TestApp, username: &str, cookie: Option<&str>) -> reqwest::Response {
+    let mut request = client()
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[("username", username), ("password", PASSWORD)]);
+    if let Some(cookie) = cookie {
+        request = request.header("Cookie", cookie);
+    }
+    request.send().await.expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn logging_in_past_the_session_limit_logs_the_oldest_session_out() {
+    let app = spawn_app_with_session_limit(SessionLimitStrategy::EvictOldest).await;
+    register(&app, "alice").await;
+    register(&app, "bob").await;
+    let bob = login(&app, "bob", None).await;
+
+    let first = login(&app, "alice", None).await;
+    let second = login(&app, "alice", None).await;
+    let third = login(&app, "alice", None).await;
+    assert!(is_logged_in(&app, &second).await);
+    assert!(is_logged_in(&app, &third).await);
+    // other users' sessions don't count
+    assert!(is_logged_in(&app, &bob).await);
+
+    let response = client()
+        .get(format!("{}/settings", app.address))
+        .header("Cookie", &first)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(307, response.status().as_u16());
+    let anonymous = cookie_pair(&set_session_cookie(&response).expect("No session cookie"));
+    let login_page = client()
+        .get(format!("{}/login", app.address))
+        .header("Cookie", &anonymous)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(login_page.contains("You were logged out because you logged in elsewhere"));
+}
+
+#[tokio::test]
+async fn logging_in_past_the_session_limit_can_be_refused() {
+    let app = spawn_app_with_session_limit(SessionLimitStrategy::Reject).await;
+    register(&app, "alice").await;
+
+    let first = login(&app, "alice", None).await;
+    let second = login(&app, "alice", None).await;
+    let response = try_login(&app, "alice", None).await;
+    assert_eq!(409, response.status().as_u16());
+    let form = response.text().await.unwrap();
+    assert!(form.contains("You are logged in on too many devices"));
+    assert!(is_logged_in(&app, &first).await);
+    assert!(is_logged_in(&app, &second).await);
+
+    // logging in again with a session of the user takes no more room
+    let response = try_login(&app, "alice", Some(&first)).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn parallel_logins_cant_pass_the_session_limit_together() {
+    let app = Arc::new(spawn_app_with_session_limit(SessionLimitStrategy::Reject).await);
+    register(&app, "alice").await;
+
+    let mut logins = JoinSet::new();
+    for _ in 0..5 {
+        let app = app.clone();
+        logins.spawn(async move { try_login(&app, "alice", None).await.status().as_u16() });
+    }
+    let mut statuses = logins.join_all().await;
+    statuses.sort();
+    assert_eq!(vec![200, 200, 409, 409, 409], statuses);
+}
+
+/// Moves the login of the user's only session `age` into the past
+async fn age_session(app: &TestApp, username: &str, age: time::Duration) {
+    let user_id = sqlx::query_scalar!(
+        "SELECT user_id FROM user_info WHERE username = $1",
+        username
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    let redis = app.redis().await;
+    let tracked = sessions::user_sessions(&redis, user_id).await.unwrap();
+    assert_eq!(1, tracked.len());
+
+    let store = RedisSessionStore::new(redis);
+    let session_id = tracked[0].session_id.parse().unwrap();
+    let mut record = store.load(&session_id).await.unwrap().unwrap();
+    let logged_in_at = OffsetDateTime::now_utc() - age;
+    record.data.insert(
+        "logged_in_at".to_string(),
+        logged_in_at.unix_timestamp().into(),
+    );
+    store.save(&record).await.unwrap();
+}
+
+#[tokio::test]
+async fn sessions_are_logged_out_once_they_are_too_old() {
+    let app = spawn_app().await;
+    register(&app, "alice").await;
+    let cookie = login(&app, "alice", None).await;
+
+    age_session(&app, "alice", time::Duration::days(29)).await;
+    assert!(is_logged_in(&app, &cookie).await);
+
+    age_session(&app, "alice", time::Duration::days(31)).await;
+    let response = client()
+        .get(format!("{}/settings", app.address))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(307, response.status().as_u16());
+    let anonymous = cookie_pair(&set_session_cookie(&response).expect("No session cookie"));
+    let login_page = client()
+        .get(format!("{}/login", app.address))
+        .header("Cookie", &anonymous)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(login_page.contains("Your session expired, log in again"));
+    assert!(!is_logged_in(&app, &cookie).await);
+
+    // logging in again starts over
+    let cookie = login(&app, "alice", None).await;
+    assert!(is_logged_in(&app, &cookie).await);
+}
@@ -0,0 +1,832 @@
+use site::worker::deactivation::delete_deactivated_users;
+
+use crate::helpers::{PASSWORD, TestApp, logged_in_client, spawn_app};
+
+async fn save_settings(
+    app: &TestApp,
+    client: &reqwest::Client,
+    form: &[(&str, &str)],
+) -> reqwest::Response {
+    client
+        .post(format!("{}/settings", app.address))
+        .form(form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn todo_page(app: &TestApp, client: &reqwest::Client, query: &str) -> String {
+    client
+        .get(format!("{}/todo{}", app.address, query))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn settings_page_requires_login() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let response = client
+        .get(format!("{}/settings", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(307, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn settings_page_shows_defaults() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = client
+        .get(format!("{}/settings", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains(r#"value="50""#));
+    assert!(body.contains(r#"value="UTC""#));
+}
+
+#[tokio::test]
+async fn invalid_settings_are_rejected() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let cases = [
+        (
+            [
+                ("default_sort", "created"),
+                ("items_per_page", "20"),
+                ("timezone", "Mars/Base"),
+            ],
+            "an unknown timezone",
+        ),
+        (
+            [
+                ("default_sort", "created"),
+                ("items_per_page", "0"),
+                ("timezone", "UTC"),
+            ],
+            "zero items per page",
+        ),
+        (
+            [
+                ("default_sort", "created"),
+                ("items_per_page", "many"),
+                ("timezone", "UTC"),
+            ],
+            "a non-numeric page size",
+        ),
+        (
+            [
+                ("default_sort", "random"),
+                ("items_per_page", "20"),
+                ("timezone", "UTC"),
+            ],
+            "an unknown sort order",
+        ),
+    ];
+    for (form, description) in cases {
+        let response = save_settings(&app, &client, &form).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject settings with {}",
+            description
+        );
+    }
+
+    let saved = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM user_preferences"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(0, saved);
+}
+
+#[tokio::test]
+async fn preferences_are_applied_to_the_todo_list() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    for (content, priority) in [
+        ("low one", "low"),
+        ("high one", "high"),
+        ("normal one", "normal"),
+        ("done one", "high"),
+    ] {
+        client
+            .post(format!("{}/todo", app.address))
+            .header("HX-Request", "true")
+            .form(&[("todo_content", content), ("priority", priority)])
+            .send()
+            .await
+            .expect("Failed to execute request");
+    }
+    sqlx::query!("UPDATE todo SET is_completed = TRUE WHERE todo_content = 'done one'")
+        .execute(&app.db)
+        .await
+        .unwrap();
+    // warm the cache so saving has to invalidate it
+    todo_page(&app, &client, "").await;
+
+    let response = save_settings(
+        &app,
+        &client,
+        &[
+            ("default_sort", "priority"),
+            ("items_per_page", "2"),
+            ("timezone", "Europe/Berlin"),
+        ],
+    )
+    .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let body = todo_page(&app, &client, "").await;
+    let high = body.find("high one").expect("Missing high priority todo");
+    let normal = body
+        .find("normal one")
+        .expect("Missing normal priority todo");
+    assert!(high < normal);
+    assert!(!body.contains("low one"));
+    assert!(!body.contains("done one"));
+    assert!(body.contains("Next"));
+
+    let body = todo_page(&app, &client, "?page=2").await;
+    assert!(body.contains("low one"));
+    assert!(!body.contains("high one"));
+
+    // query parameters win over preferences
+    let body = todo_page(&app, &client, "?show_completed=true").await;
+    assert!(body.contains("done one"));
+}
+
+#[tokio::test]
+async fn chosen_language_beats_the_browsers() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    assert!(
+        todo_page(&app, &client, "")
+            .await
+            .contains("Sort by priority")
+    );
+
+    let response = save_settings(
+        &app,
+        &client,
+        &[
+            ("default_sort", "created"),
+            ("items_per_page", "50"),
+            ("timezone", "UTC"),
+            ("locale", "de"),
+        ],
+    )
+    .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let body = todo_page(&app, &client, "").await;
+    assert!(body.contains(r#"<html lang="de" "#));
+    assert!(body.contains("Nach Priorität sortieren"));
+
+    let response = save_settings(
+        &app,
+        &client,
+        &[
+            ("default_sort", "created"),
+            ("items_per_page", "50"),
+            ("timezone", "UTC"),
+            ("locale", "xx"),
+        ],
+    )
+    .await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn saved_settings_are_confirmed_on_the_next_page_only() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    let settings_page = || async {
+        client
+            .get(format!("{}/settings", app.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    };
+    assert!(!settings_page().await.contains("Settings saved"));
+
+    let response = save_settings(
+        &app,
+        &client,
+        &[
+            ("default_sort", "created"),
+            ("items_per_page", "50"),
+            ("timezone", "UTC"),
+        ],
+    )
+    .await;
+    assert_eq!(200, response.status().as_u16());
+
+    assert!(
+        settings_page()
+            .await
+            .contains(r#"<div class="flash flash-success" role="status">Settings saved</div>"#)
+    );
+    assert!(!settings_page().await.contains("Settings saved"));
+}
+
+#[tokio::test]
+async fn saved_theme_is_the_class_of_every_page() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    assert!(
+        todo_page(&app, &client, "")
+            .await
+            .contains(r#"class="theme-system""#)
+    );
+
+    for theme in ["dark", "light", "system"] {
+        let response = save_settings(
+            &app,
+            &client,
+            &[
+                ("default_sort", "created"),
+                ("items_per_page", "50"),
+                ("timezone", "UTC"),
+                ("theme", theme),
+            ],
+        )
+        .await;
+        assert_eq!(200, response.status().as_u16());
+
+        let class = format!(r#"class="theme-{theme}""#);
+        assert!(
+            todo_page(&app, &client, "").await.contains(&class),
+            "{theme}"
+        );
+        let settings = client
+            .get(format!("{}/settings", app.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap();
+        assert!(settings.contains(&class), "{theme}");
+    }
+
+    let response = save_settings(
+        &app,
+        &client,
+        &[
+            ("default_sort", "created"),
+            ("items_per_page", "50"),
+            ("timezone", "UTC"),
+            ("theme", "purple"),
+        ],
+    )
+    .await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn saved_theme_follows_the_user_to_a_new_browser() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    let response = save_settings(
+        &app,
+        &client,
+        &[
+            ("default_sort", "created"),
+            ("items_per_page", "50"),
+            ("timezone", "UTC"),
+            ("theme", "dark"),
+        ],
+    )
+    .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let new_browser = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = new_browser
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[("username", "alice"), ("password", PASSWORD)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    assert!(
+        todo_page(&app, &new_browser, "")
+            .await
+            .contains(r#"class="theme-dark""#)
+    );
+}
+
+#[tokio::test]
+async fn anonymous_theme_is_kept_in_a_cookie_only() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let response = client
+        .post(format!("{}/theme", app.address))
+        .header("Referer", format!("{}/login", app.address))
+        .form(&[("theme", "dark")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!("/login", response.headers()["Location"]);
+    let cookie = response.headers()["Set-Cookie"].to_str().unwrap();
+    assert!(cookie.starts_with("theme=dark;"));
+
+    let login = client
+        .get(format!("{}/login", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(login.contains(r#"class="theme-dark""#));
+
+    let saved = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM user_preferences"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(0, saved);
+
+    let response = client
+        .post(format!("{}/theme", app.address))
+        .form(&[("theme", "purple")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn reminders_can_be_turned_off_in_settings() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = save_settings(
+        &app,
+        &client,
+        &[
+            ("default_sort", "created"),
+            ("items_per_page", "50"),
+            ("show_completed", "on"),
+            ("timezone", "UTC"),
+        ],
+    )
+    .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let reminders =
+        sqlx::query_scalar!("SELECT due_date_reminders FROM user_info WHERE username = 'alice'")
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+    assert!(!reminders);
+}
+
+async fn delete_account(
+    app: &TestApp,
+    client: &reqwest::Client,
+    password: &str,
+) -> reqwest::Response {
+    client
+        .post(format!("{}/settings/delete-account", app.address))
+        .form(&[("password", password)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn account_deletion_requires_the_right_password() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = delete_account(&app, &client, "wrong password").await;
+    assert_eq!(401, response.status().as_u16());
+
+    let users = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM user_info"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(1, users);
+}
+
+#[tokio::test]
+async fn deleted_account_can_be_registered_again_and_old_sessions_are_gone() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    client
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "buy milk")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // a second session for the same user, e.g. another device
+    let other_device = reqwest::Client::builder()
+        .cookie_store(true)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = other_device
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[
+            ("username", "alice"),
+            ("password", "correct horse battery staple"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = delete_account(&app, &client, "correct horse battery staple").await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        Some("/register"),
+        response
+            .headers()
+            .get("HX-Redirect")
+            .and_then(|h| h.to_str().ok())
+    );
+
+    let todos = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM todo"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(0, todos);
+
+    let response = other_device
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(307, response.status().as_u16());
+
+    // registering and logging in again with the same username works
+    logged_in_client(&app, "alice").await;
+}
+
+async fn deactivate_account(
+    app: &TestApp,
+    client: &reqwest::Client,
+    password: &str,
+) -> reqwest::Response {
+    client
+        .post(format!("{}/settings/deactivate", app.address))
+        .form(&[("password", password)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// Logs in with a new client that doesn't follow redirects
+async fn log_in(app: &TestApp, username: &str, password: &str) -> (reqwest::Client, u16) {
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = client
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[("username", username), ("password", password)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let status = response.status().as_u16();
+    (client, status)
+}
+
+async fn is_deactivated(app: &TestApp, username: &str) -> bool {
+    sqlx::query_scalar!(
+        r#"SELECT deactivated_at IS NOT NULL AS "deactivated!" FROM user_info WHERE username = $1"#,
+        username
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn deactivated_accounts_are_logged_out_until_they_log_in_again() {
+    let app = spawn_app().await;
+    logged_in_client(&app, "alice").await;
+    let (client, status) = log_in(&app, "alice", PASSWORD).await;
+    assert_eq!(200, status);
+
+    let response = deactivate_account(&app, &client, "wrong password").await;
+    assert_eq!(401, response.status().as_u16());
+    assert!(!is_deactivated(&app, "alice").await);
+
+    let response = deactivate_account(&app, &client, PASSWORD).await;
+    assert_eq!(200, response.status().as_u16());
+    assert!(is_deactivated(&app, "alice").await);
+    let response = client
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(307, response.status().as_u16());
+
+    let (_, status) = log_in(&app, "alice", "wrong password").await;
+    assert_eq!(401, status);
+    assert!(is_deactivated(&app, "alice").await);
+
+    let (client, status) = log_in(&app, "alice", PASSWORD).await;
+    assert_eq!(200, status);
+    assert!(!is_deactivated(&app, "alice").await);
+    let response = client
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn deactivated_accounts_are_deleted_after_the_grace_period() {
+    let app = spawn_app().await;
+    for username in ["alice", "bob"] {
+        let client = logged_in_client(&app, username).await;
+        let response = deactivate_account(&app, &client, PASSWORD).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+    sqlx::query!(
+        "UPDATE user_info SET deactivated_at = NOW() - INTERVAL '31 days' WHERE username = 'alice'"
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let deleted = delete_deactivated_users(&app.db, 30).await.unwrap();
+    assert_eq!(
+        vec!["alice"],
+        deleted
+            .iter()
+            .map(|user| user.username.as_str())
+            .collect::<Vec<_>>()
+    );
+    let usernames = sqlx::query_scalar!("SELECT username FROM user_info")
+        .fetch_all(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(vec!["bob"], usernames);
+
+    let (_, status) = log_in(&app, "alice", PASSWORD).await;
+    assert_eq!(401, status);
+    let (_, status) = log_in(&app, "bob", PASSWORD).await;
+    assert_eq!(200, status);
+}
+
+#[tokio::test]
+async fn todos_an_editor_added_to_a_shared_list_outlive_their_account() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    let list_id = sqlx::query_scalar!(
+        r#"
+        SELECT tl.list_id FROM todo_list AS tl
+        JOIN user_info AS ui ON ui.user_id = tl.owner_id
+        WHERE ui.username = 'alice'
+        "#
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    let response = alice
+        .post(format!("{}/lists/{}/share", app.address, list_id))
+        .header("HX-Request", "true")
+        .form(&[("username", "bob"), ("role", "editor")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let response = bob
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[
+            ("todo_content", "buy eggs".to_string()),
+            ("list_id", list_id.to_string()),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    let response = delete_account(&app, &bob, "correct horse battery staple").await;
+    assert_eq!(200, response.status().as_u16());
+
+    let page = todo_page(&app, &alice, "").await;
+    assert!(page.contains("buy eggs"));
+    let added_by = sqlx::query_scalar!("SELECT user_id FROM todo WHERE list_id = $1", list_id)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(None, added_by);
+}
+
+#[tokio::test]
+async fn data_export_contains_everything_but_the_password() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    client
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "buy milk"), ("tags", "errands")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    sqlx::query!(
+        r#"
+        WITH alice AS (SELECT user_id FROM user_info WHERE username = 'alice'),
+        subtasks AS (
+            INSERT INTO subtask (todo_id, content, is_completed, position)
+            SELECT td.todo_id, 'oat milk', TRUE, 0 FROM todo AS td JOIN alice USING (user_id)
+        ),
+        audit AS (
+            INSERT INTO audit_log (user_id, event_type, ip_address)
+            SELECT user_id, 'email_changed', '203.0.113.7' FROM alice
+        ),
+        devices AS (
+            INSERT INTO known_devices (user_id, user_agent_family, network)
+            SELECT user_id, 'Firefox', '203.0.113.0/24' FROM alice
+            ON CONFLICT DO NOTHING
+        ),
+        usernames AS (
+            INSERT INTO username_history (user_id, username)
+            SELECT user_id, 'alice_old' FROM alice
+        ),
+        passkeys AS (
+            INSERT INTO passkeys (user_id, credential_id, public_key, label)
+            SELECT user_id, '\x0102', '{}', 'Work laptop' FROM alice
+        ),
+        webhooks AS (
+            INSERT INTO webhooks (user_id, url, secret, events)
+            SELECT user_id, 'https://example.com/hook', 'whsec_do_not_export', '{todo.created}'
+            FROM alice
+        )
+        UPDATE user_info SET avatar = 'alice.png' FROM alice
+        WHERE user_info.user_id = alice.user_id
+        "#
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let response = client
+        .get(format!("{}/settings/export", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let disposition = response
+        .headers()
+        .get("Content-Disposition")
+        .and_then(|h| h.to_str().ok())
+        .unwrap()
+        .to_string();
+    assert!(disposition.starts_with("attachment"));
+
+    let body = response.text().await.unwrap();
+    assert!(!body.contains("password"));
+    assert!(!body.contains("$argon2"));
+    assert!(!body.contains("whsec_do_not_export"));
+
+    let export: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(5, export["schema_version"]);
+    assert_eq!("alice", export["profile"]["username"]);
+    assert_eq!("alice@test.com", export["profile"]["email"]);
+    assert_eq!(true, export["profile"]["has_avatar"]);
+    assert!(export["profile"]["created_at"].is_string());
+    assert_eq!(50, export["preferences"]["items_per_page"]);
+    assert_eq!("created", export["preferences"]["default_sort"]);
+    assert_eq!(serde_json::Value::Null, export["preferences"]["locale"]);
+    assert_eq!("system", export["preferences"]["theme"]);
+
+    let todos = export["todos"].as_array().unwrap();
+    assert_eq!(1, todos.len());
+    assert_eq!("buy milk", todos[0]["todo_content"]);
+    assert_eq!("normal", todos[0]["priority"]);
+    assert_eq!(serde_json::json!(["errands"]), todos[0]["tags"]);
+    assert!(todos[0]["created_at"].is_string());
+    assert_eq!(
+        serde_json::json!([{ "content": "oat milk", "is_completed": true }]),
+        todos[0]["subtasks"]
+    );
+
+    let sessions = export["sessions"].as_array().unwrap();
+    assert_eq!(1, sessions.len());
+    assert_eq!(true, sessions[0]["current"]);
+
+    let audit_log = export["audit_log"].as_array().unwrap();
+    assert!(audit_log.iter().any(
+        |entry| entry["event_type"] == "email_changed" && entry["ip_address"] == "203.0.113.7"
+    ));
+    let known_devices = export["known_devices"].as_array().unwrap();
+    assert!(
+        known_devices
+            .iter()
+            .any(|device| device["user_agent_family"] == "Firefox")
+    );
+    assert_eq!("alice_old", export["username_history"][0]["username"]);
+    assert_eq!("Work laptop", export["passkeys"][0]["label"]);
+    assert!(export["passkeys"][0]["created_at"].is_string());
+    assert_eq!("https://example.com/hook", export["webhooks"][0]["url"]);
+    assert_eq!(
+        serde_json::json!(["todo.created"]),
+        export["webhooks"][0]["events"]
+    );
+}
+
+#[tokio::test]
+async fn large_list_is_paged_while_the_export_has_every_todo() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    sqlx::query!(
+        r#"
+        INSERT INTO todo (user_id, list_id, todo_content)
+        SELECT ui.user_id, tl.list_id, 'todo ' || n
+        FROM user_info AS ui
+        JOIN todo_list AS tl ON tl.owner_id = ui.user_id
+        CROSS JOIN generate_series(1, 3000) AS n
+        WHERE ui.username = 'alice'
+        "#
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let body = client
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(50, body.matches("id=\"todo-").count());
+
+    let response = client
+        .get(format!("{}/settings/export", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(3000, export["todos"].as_array().unwrap().len());
+    assert_eq!("alice", export["profile"]["username"]);
+}
+
+#[tokio::test]
+async fn data_export_is_rate_limited() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let export = || {
+        client
+            .get(format!("{}/settings/export", app.address))
+            .send()
+    };
+    let response = export().await.expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let response = export().await.expect("Failed to execute request");
+    assert_eq!(429, response.status().as_u16());
+
+    // the limit is per user
+    let other = logged_in_client(&app, "bob").await;
+    let response = other
+        .get(format!("{}/settings/export", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
@@ -0,0 +1,118 @@
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app};
+
+async fn user_and_list_id(app: &TestApp, username: &str) -> (Uuid, Uuid) {
+    let row = sqlx::query!(
+        r#"
+        SELECT ui.user_id, tl.list_id
+        FROM user_info AS ui
+        JOIN todo_list AS tl ON tl.owner_id = ui.user_id
+        WHERE ui.username = $1
+        "#,
+        username
+    )
+    .fetch_one(&app.db)
+    .await
+    .expect("Failed to fetch user");
+    (row.user_id, row.list_id)
+}
+
+/// Creates `created` todos and completes `completed` older ones `days_ago`
+async fn seed_day(app: &TestApp, username: &str, days_ago: i32, created: i32, completed: i32) {
+    let (user_id, list_id) = user_and_list_id(app, username).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO todo (user_id, list_id, todo_content, created_at, updated_at)
+        SELECT $1, $2, 'seeded', NOW() - make_interval(days => $3), NOW() - make_interval(days => $3)
+        FROM generate_series(1, $4)
+        "#,
+        user_id,
+        list_id,
+        days_ago,
+        created
+    )
+    .execute(&app.db)
+    .await
+    .expect("Failed to seed todos");
+
+    // created before the chart begins, so only their completion shows
+    sqlx::query!(
+        r#"
+        INSERT INTO todo (
+            user_id, list_id, todo_content, is_completed, completed_at, created_at, updated_at
+        )
+        SELECT
+            $1, $2, 'seeded', TRUE, NOW() - make_interval(days => $3),
+            NOW() - INTERVAL '100 days', NOW() - make_interval(days => $3)
+        FROM generate_series(1, $4)
+        "#,
+        user_id,
+        list_id,
+        days_ago,
+        completed
+    )
+    .execute(&app.db)
+    .await
+    .expect("Failed to seed completions");
+}
+
+fn bucket(days_ago: i64, created: i64, completed: i64) -> String {
+    let day = (OffsetDateTime::now_utc() - Duration::days(days_ago)).date();
+    format!("data-day=\"{day}\" data-created=\"{created}\" data-completed=\"{completed}\"")
+}
+
+async fn stats_page(app: &TestApp, client: &reqwest::Client) -> String {
+    let response = client
+        .get(format!("{}/stats", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn stats_are_bucketed_per_day() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    seed_day(&app, "alice", 0, 2, 1).await;
+    seed_day(&app, "alice", 1, 1, 2).await;
+    seed_day(&app, "alice", 2, 0, 1).await;
+    seed_day(&app, "alice", 4, 3, 1).await;
+    seed_day(&app, "alice", 45, 5, 5).await;
+
+    let body = stats_page(&app, &alice).await;
+    assert_eq!(30, body.matches("<g data-day=").count());
+    assert!(body.contains(&bucket(0, 2, 1)));
+    assert!(body.contains(&bucket(1, 1, 2)));
+    assert!(body.contains(&bucket(2, 0, 1)));
+    assert!(body.contains(&bucket(3, 0, 0)));
+    assert!(body.contains(&bucket(4, 3, 1)));
+    assert!(body.contains("<dd>3 days</dd>"));
+}
+
+#[tokio::test]
+async fn other_users_activity_is_not_counted() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    logged_in_client(&app, "bob").await;
+    seed_day(&app, "bob", 0, 4, 4).await;
+
+    let body = stats_page(&app, &alice).await;
+    assert!(body.contains(&bucket(0, 0, 0)));
+    assert!(body.contains("<dd>0 days</dd>"));
+    assert!(body.contains("No open todos"));
+}
+
+#[tokio::test]
+async fn stats_are_cached() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    seed_day(&app, "alice", 0, 1, 1).await;
+    assert!(stats_page(&app, &alice).await.contains(&bucket(0, 1, 1)));
+
+    seed_day(&app, "alice", 0, 1, 1).await;
+    assert!(stats_page(&app, &alice).await.contains(&bucket(0, 1, 1)));
+}
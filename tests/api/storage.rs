@@ -0,0 +1,85 @@
+use site::storage::{FileStore, LocalFileStore};
+use uuid::Uuid;
+
+/// The behavior every backend has to agree on
+async fn check_file_store(store: &dyn FileStore) {
+    let key = format!("test/{}.txt", Uuid::new_v4());
+
+    assert_eq!(None, store.get(&key).await.unwrap());
+
+    store
+        .put(&key, b"first".to_vec(), "text/plain")
+        .await
+        .unwrap();
+    assert_eq!(Some(b"first".to_vec()), store.get(&key).await.unwrap());
+
+    store
+        .put(&key, b"second".to_vec(), "text/plain")
+        .await
+        .unwrap();
+    assert_eq!(Some(b"second".to_vec()), store.get(&key).await.unwrap());
+
+    store.delete(&key).await.unwrap();
+    assert_eq!(None, store.get(&key).await.unwrap());
+
+    // deleting twice is fine
+    store.delete(&key).await.unwrap();
+}
+
+#[tokio::test]
+async fn local_file_store_works() {
+    let root = std::env::temp_dir().join(format!("uploads-{}", Uuid::new_v4()));
+    check_file_store(&LocalFileStore::new(root)).await;
+}
+
+#[tokio::test]
+async fn local_file_store_rejects_keys_outside_its_root() {
+    let root = std::env::temp_dir().join(format!("uploads-{}", Uuid::new_v4()));
+    let store = LocalFileStore::new(root);
+
+    for key in ["../escape.txt", "/etc/passwd", "a/../../escape.txt", ""] {
+        assert!(store.put(key, b"x".to_vec(), "text/plain").await.is_err());
+        assert!(store.get(key).await.is_err());
+        assert!(store.delete(key).await.is_err());
+    }
+}
+
+#[cfg(feature = "minio-tests")]
+#[tokio::test]
+async fn s3_file_store_works_with_minio() {
+    use secrecy::SecretString;
+    use site::{
+        config::{StorageBackend, StorageSettings},
+        storage::S3FileStore,
+    };
+
+    let endpoint =
+        std::env::var("STORAGE_ENDPOINT").unwrap_or_else(|_| "http://localhost:9000".to_string());
+    let bucket = format!("test-{}", Uuid::new_v4());
+
+    let credentials =
+        aws_sdk_s3::config::Credentials::new("minioadmin", "minioadmin", None, None, "test");
+    let config = aws_sdk_s3::config::Builder::new()
+        .region(aws_sdk_s3::config::Region::new("us-east-1"))
+        .credentials_provider(credentials)
+        .endpoint_url(&endpoint)
+        .force_path_style(true)
+        .build();
+    aws_sdk_s3::Client::from_conf(config)
+        .create_bucket()
+        .bucket(&bucket)
+        .send()
+        .await
+        .expect("Failed to create bucket");
+
+    let store = S3FileStore::new(&StorageSettings {
+        storage_backend: StorageBackend::S3,
+        upload_dir: "uploads".into(),
+        storage_endpoint: Some(endpoint),
+        storage_bucket: Some(bucket),
+        storage_region: "us-east-1".to_string(),
+        storage_access_key_id: Some("minioadmin".to_string()),
+        storage_secret_access_key: Some(SecretString::from("minioadmin")),
+    });
+    check_file_store(&store).await;
+}
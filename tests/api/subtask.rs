@@ -0,0 +1,222 @@
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app};
+
+async fn create_todo(app: &TestApp, client: &reqwest::Client, content: &str) -> Uuid {
+    let response = client
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", content)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    sqlx::query_scalar!("SELECT todo_id FROM todo WHERE todo_content = $1", content)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to fetch todo id")
+}
+
+async fn create_subtask(
+    app: &TestApp,
+    client: &reqwest::Client,
+    todo_id: Uuid,
+    content: &str,
+) -> Uuid {
+    let response = client
+        .post(format!("{}/todo/{}/subtasks", app.address, todo_id))
+        .form(&[("content", content)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    sqlx::query_scalar!("SELECT subtask_id FROM subtask WHERE content = $1", content)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to fetch subtask id")
+}
+
+async fn complete_subtask(
+    app: &TestApp,
+    client: &reqwest::Client,
+    todo_id: Uuid,
+    subtask_id: Uuid,
+) -> reqwest::Response {
+    client
+        .put(format!(
+            "{}/todo/{}/subtasks/{}",
+            app.address, todo_id, subtask_id
+        ))
+        .form(&[("is_completed", "true")])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn todo_is_completed(app: &TestApp, todo_id: Uuid) -> bool {
+    sqlx::query_scalar!("SELECT is_completed FROM todo WHERE todo_id = $1", todo_id)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to fetch todo")
+}
+
+#[tokio::test]
+async fn list_shows_subtask_progress() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "move house").await;
+    let first = create_subtask(&app, &alice, todo_id, "pack boxes").await;
+    create_subtask(&app, &alice, todo_id, "book van").await;
+
+    let response = complete_subtask(&app, &alice, todo_id, first).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let body = alice
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains(">1/2</small>"));
+
+    let body = alice
+        .get(format!("{}/todo/{}", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("pack boxes"));
+    assert!(body.contains("book van"));
+}
+
+#[tokio::test]
+async fn deleted_subtasks_are_gone() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "move house").await;
+    let subtask_id = create_subtask(&app, &alice, todo_id, "pack boxes").await;
+
+    let response = alice
+        .delete(format!(
+            "{}/todo/{}/subtasks/{}",
+            app.address, todo_id, subtask_id
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let count = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM subtask"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(0, count);
+}
+
+#[tokio::test]
+async fn completing_all_subtasks_completes_the_todo_only_when_enabled() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "move house").await;
+    let first = create_subtask(&app, &alice, todo_id, "pack boxes").await;
+    let second = create_subtask(&app, &alice, todo_id, "book van").await;
+
+    complete_subtask(&app, &alice, todo_id, first).await;
+    complete_subtask(&app, &alice, todo_id, second).await;
+    assert!(!todo_is_completed(&app, todo_id).await);
+
+    let response = alice
+        .post(format!("{}/settings", app.address))
+        .form(&[
+            ("default_sort", "created"),
+            ("items_per_page", "50"),
+            ("timezone", "UTC"),
+            ("auto_complete_todos", "on"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let other_todo_id = create_todo(&app, &alice, "paint walls").await;
+    let first = create_subtask(&app, &alice, other_todo_id, "buy paint").await;
+    let second = create_subtask(&app, &alice, other_todo_id, "buy brushes").await;
+
+    complete_subtask(&app, &alice, other_todo_id, first).await;
+    assert!(!todo_is_completed(&app, other_todo_id).await);
+    complete_subtask(&app, &alice, other_todo_id, second).await;
+    assert!(todo_is_completed(&app, other_todo_id).await);
+}
+
+#[tokio::test]
+async fn subtasks_of_other_todos_are_not_found() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    let alice_todo = create_todo(&app, &alice, "move house").await;
+    let bob_todo = create_todo(&app, &bob, "paint walls").await;
+    let bob_subtask = create_subtask(&app, &bob, bob_todo, "buy paint").await;
+
+    // through a todo alice can access, but the subtask belongs elsewhere
+    let response = complete_subtask(&app, &alice, alice_todo, bob_subtask).await;
+    assert_eq!(404, response.status().as_u16());
+    let response = alice
+        .delete(format!(
+            "{}/todo/{}/subtasks/{}",
+            app.address, alice_todo, bob_subtask
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+
+    // through the todo it belongs to, which alice can't access
+    let response = complete_subtask(&app, &alice, bob_todo, bob_subtask).await;
+    assert_eq!(404, response.status().as_u16());
+    let response = alice
+        .get(format!("{}/todo/{}/subtasks", app.address, bob_todo))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+    let response = alice
+        .post(format!("{}/todo/{}/subtasks", app.address, bob_todo))
+        .form(&[("content", "sneaky")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+
+    let is_completed = sqlx::query_scalar!(
+        "SELECT is_completed FROM subtask WHERE subtask_id = $1",
+        bob_subtask
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    assert!(!is_completed);
+}
+
+#[tokio::test]
+async fn subtasks_of_deleted_todos_are_not_found() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "move house").await;
+    let subtask_id = create_subtask(&app, &alice, todo_id, "pack boxes").await;
+
+    let response = alice
+        .delete(format!("{}/todo/{}", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = complete_subtask(&app, &alice, todo_id, subtask_id).await;
+    assert_eq!(404, response.status().as_u16());
+}
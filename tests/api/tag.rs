@@ -0,0 +1,195 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app, spawn_app_with};
+
+async fn create_tagged_todo(
+    app: &TestApp,
+    client: &reqwest::Client,
+    content: &str,
+    tags: &str,
+) -> reqwest::Response {
+    client
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", content), ("tags", tags)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn todo_id_of(app: &TestApp, content: &str) -> Uuid {
+    sqlx::query_scalar!("SELECT todo_id FROM todo WHERE todo_content = $1", content)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to fetch todo id")
+}
+
+async fn tags_of(app: &TestApp, username: &str) -> Vec<String> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT tg.name FROM tag AS tg
+        JOIN user_info AS ui ON ui.user_id = tg.user_id
+        WHERE ui.username = $1
+        ORDER BY tg.name
+        "#,
+        username
+    )
+    .fetch_all(&app.db)
+    .await
+    .expect("Failed to fetch tags")
+}
+
+#[tokio::test]
+async fn tags_are_normalized_on_create() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = create_tagged_todo(&app, &client, "buy milk", " Work, home ,WORK,, ").await;
+    assert_eq!(201, response.status().as_u16());
+
+    assert_eq!(vec!["home", "work"], tags_of(&app, "alice").await);
+}
+
+#[tokio::test]
+async fn invalid_tags_are_rejected() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    let too_many = (0..11)
+        .map(|i| format!("tag{i}"))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    for (tags, description) in [
+        ("work, not allowed", "tag with a space"),
+        ("work,<script>", "tag with forbidden characters"),
+        (too_many.as_str(), "more than 10 tags"),
+    ] {
+        let response = create_tagged_todo(&app, &client, "buy milk", tags).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject a todo with {}",
+            description
+        );
+    }
+
+    let count = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM todo"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(0, count);
+}
+
+#[tokio::test]
+async fn todos_can_be_filtered_by_tag() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    create_tagged_todo(&app, &client, "write report", "work").await;
+    create_tagged_todo(&app, &client, "water plants", "home").await;
+
+    let response = client
+        .get(format!("{}/todo?tag=Work", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("write report"));
+    assert!(!body.contains("water plants"));
+
+    let response = client
+        .get(format!("{}/todo?tag=not%20a%20tag", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn tags_are_isolated_between_users() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    create_tagged_todo(&app, &alice, "task for alice", "work").await;
+    create_tagged_todo(&app, &bob, "task for bob", "work").await;
+
+    let tag_count =
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM tag WHERE name = 'work'"#)
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+    assert_eq!(2, tag_count);
+
+    let body = bob
+        .get(format!("{}/todo?tag=work", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("task for bob"));
+    assert!(!body.contains("task for alice"));
+
+    let body = bob
+        .get(format!("{}/tags", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("work</a> (1)"));
+}
+
+#[tokio::test]
+async fn tags_can_be_replaced() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+    create_tagged_todo(&app, &client, "buy milk", "errands").await;
+    let todo_id = todo_id_of(&app, "buy milk").await;
+
+    let response = client
+        .put(format!("{}/todo/{}/tags", app.address, todo_id))
+        .form(&[("tags", "shopping, Home")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    assert_eq!(vec!["home", "shopping"], tags_of(&app, "alice").await);
+}
+
+#[tokio::test]
+async fn deleting_a_todo_cleans_up_orphaned_tags() {
+    let app = spawn_app_with(|config| config.application_settings.todo_undo_grace_secs = 1).await;
+    let client = logged_in_client(&app, "alice").await;
+    create_tagged_todo(&app, &client, "write report", "work, urgent").await;
+    create_tagged_todo(&app, &client, "send invoice", "work").await;
+    let todo_id = todo_id_of(&app, "write report").await;
+
+    let response = client
+        .delete(format!("{}/todo/{}", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let body = client
+        .get(format!("{}/tags", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("work</a> (1)"));
+    assert!(!body.contains("urgent"));
+
+    // the tags stay around until the undo window has passed and the todo is
+    // purged
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert_eq!(vec!["work"], tags_of(&app, "alice").await);
+}
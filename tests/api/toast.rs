@@ -0,0 +1,92 @@
+use crate::helpers::{logged_in_client, spawn_app};
+
+fn toast_of(response: &reqwest::Response) -> Option<serde_json::Value> {
+    let trigger = response.headers().get("HX-Trigger")?.to_str().unwrap();
+    let events: serde_json::Value = serde_json::from_str(trigger).unwrap();
+    events.get("toast").cloned()
+}
+
+#[tokio::test]
+async fn adding_a_todo_sends_a_success_toast_with_the_counts() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = alice
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "buy milk")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(201, response.status().as_u16());
+    assert_eq!(
+        serde_json::json!({ "level": "success", "message": "Todo added" }),
+        toast_of(&response).unwrap()
+    );
+    let trigger: serde_json::Value =
+        serde_json::from_str(response.headers()["HX-Trigger"].to_str().unwrap()).unwrap();
+    assert_eq!(1, trigger["todoCounts"]["active"]);
+}
+
+#[tokio::test]
+async fn failed_htmx_requests_get_an_error_toast() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = alice
+        .get(format!("{}/todo/{}", app.address, uuid::Uuid::new_v4()))
+        .header("HX-Request", "true")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+    assert_eq!("error", toast_of(&response).unwrap()["level"]);
+
+    // the message of an API error is the message of the toast
+    let response = app
+        .client
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[("username", "alice"), ("password", "wrong password")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(401, response.status().as_u16());
+    let toast = toast_of(&response).unwrap();
+    assert_eq!("error", toast["level"]);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["message"], toast["message"]);
+}
+
+#[tokio::test]
+async fn errors_are_only_toasted_for_htmx_requests() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = alice
+        .get(format!("{}/todo/{}", app.address, uuid::Uuid::new_v4()))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(404, response.status().as_u16());
+    assert!(toast_of(&response).is_none());
+}
+
+#[tokio::test]
+async fn field_errors_are_left_to_the_form() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = alice
+        .post(format!("{}/api/user/email", app.address))
+        .header("HX-Request", "true")
+        .form(&[("email", "not an email")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(400, response.status().as_u16());
+    assert!(toast_of(&response).is_none());
+}
@@ -0,0 +1,1503 @@
+use site::query_count::QUERY_COUNT_HEADER;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use uuid::Uuid;
+
+use crate::helpers::{
+    PASSWORD, TestApp, assert_api_error, list_id_of, logged_in_client, spawn_app, spawn_app_with,
+};
+
+async fn create_todo(app: &TestApp, client: &reqwest::Client, content: &str) -> Uuid {
+    let response = client
+        .post(format!("{}/todo", app.address))
+        .header("Accept", "application/json")
+        .form(&[("todo_content", content)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    let todo: serde_json::Value = response.json().await.expect("Failed to parse todo");
+    todo["todo_id"].as_str().unwrap().parse().unwrap()
+}
+
+async fn share_list(
+    app: &TestApp,
+    client: &reqwest::Client,
+    list_id: Uuid,
+    username: &str,
+    role: &str,
+) -> reqwest::Response {
+    client
+        .post(format!("{}/lists/{}/share", app.address, list_id))
+        .header("HX-Request", "true")
+        .form(&[("username", username), ("role", role)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn shared_list_is_visible_to_member() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    let list_id = list_id_of(&app, "alice").await;
+    create_todo(&app, &alice, "buy milk").await;
+
+    let response = bob
+        .get(format!("{}/todo?list_id={}", app.address, list_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+
+    let response = share_list(&app, &alice, list_id, "bob", "viewer").await;
+    assert_eq!(200, response.status().as_u16());
+
+    let response = bob
+        .get(format!("{}/todo?list_id={}", app.address, list_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("buy milk"));
+
+    let response = alice
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert!(response.text().await.unwrap().contains("bob (viewer)"));
+}
+
+#[tokio::test]
+async fn viewer_mutations_return_403() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    let list_id = list_id_of(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+    share_list(&app, &alice, list_id, "bob", "viewer").await;
+
+    let response = bob
+        .put(format!("{}/todo/{}", app.address, todo_id))
+        .form(&[("is_completed", "true"), ("version", "1")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+
+    let response = bob
+        .delete(format!("{}/todo/{}", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+
+    let response = bob
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[
+            ("todo_content", "buy eggs".to_string()),
+            ("list_id", list_id.to_string()),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+
+    let response = share_list(&app, &bob, list_id, "bob", "editor").await;
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn editor_can_mutate_shared_list() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    let list_id = list_id_of(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+    share_list(&app, &alice, list_id, "bob", "editor").await;
+
+    let response = bob
+        .put(format!("{}/todo/{}", app.address, todo_id))
+        .form(&[("is_completed", "true"), ("version", "1")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = bob
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[
+            ("todo_content", "buy eggs".to_string()),
+            ("list_id", list_id.to_string()),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT is_completed FROM todo WHERE todo_id = $1", todo_id)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to fetch todo");
+    assert!(saved.is_completed);
+
+    let list_size = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM todo WHERE list_id = $1"#,
+        list_id
+    )
+    .fetch_one(&app.db)
+    .await
+    .expect("Failed to count todos");
+    assert_eq!(2, list_size);
+}
+
+#[tokio::test]
+async fn strangers_get_404_on_foreign_todos() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let mallory = logged_in_client(&app, "mallory").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+
+    let response = mallory
+        .put(format!("{}/todo/{}", app.address, todo_id))
+        .form(&[("is_completed", "true"), ("version", "1")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+
+    let response = mallory
+        .delete(format!("{}/todo/{}", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn sharing_with_unknown_username_returns_404() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    logged_in_client(&app, "bob").await;
+    let list_id = list_id_of(&app, "alice").await;
+
+    let response = share_list(&app, &alice, list_id, "bobby", "viewer").await;
+    assert_eq!(404, response.status().as_u16());
+
+    let response = share_list(&app, &alice, list_id, "bo b", "viewer").await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn revoked_member_loses_access() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    let list_id = list_id_of(&app, "alice").await;
+    share_list(&app, &alice, list_id, "bob", "editor").await;
+
+    let bob_id = sqlx::query_scalar!("SELECT user_id FROM user_info WHERE username = 'bob'")
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to fetch user id");
+
+    let response = alice
+        .delete(format!(
+            "{}/lists/{}/members/{}",
+            app.address, list_id, bob_id
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = bob
+        .get(format!("{}/todo?list_id={}", app.address, list_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn concurrent_updates_with_same_version_conflict_once() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+
+    let update = |is_completed: &'static str| {
+        alice
+            .put(format!("{}/todo/{}", app.address, todo_id))
+            .form(&[("is_completed", is_completed), ("version", "1")])
+            .send()
+    };
+    let (first, second) = tokio::join!(update("true"), update("false"));
+    let mut statuses = [
+        first.expect("Failed to execute request").status().as_u16(),
+        second.expect("Failed to execute request").status().as_u16(),
+    ];
+    statuses.sort();
+    assert_eq!([200, 409], statuses);
+
+    let response = alice
+        .put(format!("{}/todo/{}", app.address, todo_id))
+        .form(&[("is_completed", "true"), ("version", "1")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(409, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("buy milk"));
+    assert!(body.contains(r#"name="version" value="2""#));
+}
+
+#[tokio::test]
+async fn blank_todo_returns_400() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = alice
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "   ")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_same_todo_sent_twice_at_once_is_added_once() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.todo_repeat_window_secs = 2;
+    })
+    .await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let add = || {
+        alice
+            .post(format!("{}/todo", app.address))
+            .header("HX-Request", "true")
+            .form(&[("todo_content", "buy milk")])
+            .send()
+    };
+    let (first, second) = tokio::join!(add(), add());
+
+    let mut statuses = [
+        first.unwrap().status().as_u16(),
+        second.unwrap().status().as_u16(),
+    ];
+    statuses.sort();
+    assert_eq!([200, 201], statuses);
+    let count = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM todo"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(1, count);
+}
+
+async fn list_cache_stats(app: &TestApp) -> serde_json::Value {
+    let response = app
+        .client
+        .get(format!("{}/health/ready", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["list_cache"].clone()
+}
+
+#[tokio::test]
+async fn cached_list_views_show_changes_made_since() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.list_cache_enabled = true;
+        config.application_settings.list_cache_ttl_secs = 600;
+    })
+    .await;
+    let alice = logged_in_client(&app, "alice").await;
+    create_todo(&app, &alice, "buy milk").await;
+
+    let get_list = || async {
+        alice
+            .get(format!("{}/todo", app.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    };
+    let first = get_list().await;
+    let second = get_list().await;
+    assert_eq!(first, second);
+    let stats = list_cache_stats(&app).await;
+    assert_eq!(true, stats["enabled"]);
+    assert_eq!(1, stats["hits"]);
+    assert_eq!(1, stats["misses"]);
+
+    create_todo(&app, &alice, "buy eggs").await;
+
+    let page = get_list().await;
+    assert!(page.contains("buy milk"));
+    assert!(page.contains("buy eggs"));
+    assert_eq!(2, list_cache_stats(&app).await["misses"]);
+}
+
+#[tokio::test]
+async fn cached_list_views_arent_served_for_other_preferences() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.list_cache_enabled = true;
+        config.application_settings.list_cache_ttl_secs = 600;
+    })
+    .await;
+    let alice = logged_in_client(&app, "alice").await;
+    create_todo(&app, &alice, "buy milk").await;
+    create_todo(&app, &alice, "buy eggs").await;
+
+    let get_list = || async {
+        alice
+            .get(format!("{}/todo", app.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    };
+    let page = get_list().await;
+    assert!(page.contains("buy milk") && page.contains("buy eggs"));
+
+    let response = alice
+        .post(format!("{}/settings", app.address))
+        .form(&[
+            ("default_sort", "created"),
+            ("items_per_page", "1"),
+            ("timezone", "UTC"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let page = get_list().await;
+    assert!(!(page.contains("buy milk") && page.contains("buy eggs")));
+}
+
+#[tokio::test]
+async fn cached_list_views_of_members_show_changes_of_the_owner() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.list_cache_enabled = true;
+        config.application_settings.list_cache_ttl_secs = 600;
+    })
+    .await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    let list_id = list_id_of(&app, "alice").await;
+    share_list(&app, &alice, list_id, "bob", "viewer").await;
+
+    let get_list = || async {
+        bob.get(format!("{}/todo?list_id={}", app.address, list_id))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    };
+    assert!(!get_list().await.contains("buy milk"));
+
+    create_todo(&app, &alice, "buy milk").await;
+    assert!(get_list().await.contains("buy milk"));
+}
+
+#[tokio::test]
+async fn list_page_runs_as_many_queries_for_many_todos_as_for_one() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let query_count = || async {
+        let response = alice
+            .get(format!("{}/todo", app.address))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(200, response.status().as_u16());
+        response.headers()[QUERY_COUNT_HEADER]
+            .to_str()
+            .unwrap()
+            .parse::<usize>()
+            .unwrap()
+    };
+    create_todo(&app, &alice, "todo 0").await;
+    let for_one = query_count().await;
+
+    for i in 1..20 {
+        create_todo(&app, &alice, &format!("todo {i}")).await;
+    }
+    sqlx::query!(
+        r#"
+        WITH new_tags AS (
+            INSERT INTO tag (user_id, name)
+            SELECT user_id, name FROM user_info, UNNEST(ARRAY['errands', 'home']) AS name
+            WHERE username = 'alice'
+            RETURNING tag_id
+        )
+        INSERT INTO todo_tag (todo_id, tag_id)
+        SELECT todo_id, tag_id FROM todo, new_tags
+        "#
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO subtask (todo_id, content, position, is_completed)
+        SELECT todo_id, 'step ' || position, position, position = 0
+        FROM todo, generate_series(0, 2) AS position
+        "#
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+    let for_many = query_count().await;
+
+    assert_eq!(for_one, for_many);
+    assert!(for_many <= 8, "{for_many} queries");
+}
+
+#[tokio::test]
+async fn todos_posted_without_htmx_redirect_back_to_the_list() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let list_id = list_id_of(&app, "alice").await;
+
+    let response = alice
+        .post(format!("{}/todo", app.address))
+        .form(&[("todo_content", "buy milk")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    // the 303 was followed to the page
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("/todo", response.url().path());
+    assert_eq!(
+        Some(format!("list_id={list_id}").as_str()),
+        response.url().query()
+    );
+    assert!(response.text().await.unwrap().contains("buy milk"));
+
+    // htmx gets the row instead, see `htmx_gets_the_added_row_and_an_emptied_form`
+    let response = alice
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "buy eggs")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+    assert!(response.headers().get("HX-Redirect").is_none());
+}
+
+#[tokio::test]
+async fn invalid_todos_posted_without_htmx_show_the_list_with_the_error() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    alice
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "buy milk")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let response = alice
+        .post(format!("{}/todo", app.address))
+        .form(&[("todo_content", "   ")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+    assert!(response.headers().get("HX-Redirect").is_none());
+    let body = response.text().await.unwrap();
+    assert!(body.contains(r#"<p class="error" role="alert">"#));
+    assert!(body.contains("buy milk"));
+}
+
+#[tokio::test]
+async fn htmx_gets_the_added_row_and_an_emptied_form() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let list_id = list_id_of(&app, "alice").await;
+
+    let response = alice
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[
+            ("todo_content", "buy milk"),
+            ("tags", "errands"),
+            ("priority", "high"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+    assert_eq!("afterbegin", response.headers()["HX-Reswap"]);
+    let todo_id: Uuid =
+        sqlx::query_scalar!("SELECT todo_id FROM todo WHERE todo_content = 'buy milk'")
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+    let added: serde_json::Value = serde_json::from_str(
+        response.headers()["HX-Trigger-After-Settle"]
+            .to_str()
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(todo_id.to_string(), added["todoAdded"]["todo_id"]);
+
+    let body = response.text().await.unwrap();
+    let (row, form) = body.split_once("<form").unwrap();
+    assert!(row.contains(&format!(r#"id="todo-{todo_id}""#)));
+    assert!(form.contains(r#"id="todo-form""#));
+    assert!(form.contains(r#"hx-swap-oob="true""#));
+    assert!(form.contains(&format!(r#"name="list_id" value="{list_id}""#)));
+    assert!(form.contains(r#"name="todo_content" value="" required autofocus"#));
+    assert!(form.contains(r#"name="tags" value="""#));
+    assert!(form.contains(r#"<option value="normal" selected>"#));
+    assert!(!form.contains(r#"class="error""#));
+}
+
+#[tokio::test]
+async fn htmx_gets_the_form_back_with_the_error_and_what_was_typed() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = alice
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[
+            ("todo_content", "buy milk"),
+            ("tags", "errands, out&about"),
+            ("priority", "high"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!("#todo-form", response.headers()["HX-Retarget"]);
+    assert_eq!("outerHTML", response.headers()["HX-Reswap"]);
+    // the error is shown in the form rather than as a toast
+    assert!(response.headers().get("HX-Trigger").is_none());
+
+    let form = response.text().await.unwrap();
+    assert!(form.starts_with(r#"<form id="todo-form""#));
+    assert!(!form.contains("hx-swap-oob"));
+    assert!(form.contains(r#"<p class="error" role="alert">"#));
+    assert!(form.contains(r#"name="todo_content" value="buy milk" required autofocus"#));
+    assert!(form.contains(r#"name="tags" value="errands, out&amp;about""#));
+    assert!(form.contains(r#"<option value="high" selected>"#));
+    let todo_count = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM todo"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(0, todo_count);
+}
+
+#[tokio::test]
+async fn todos_can_be_sorted_by_priority() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    for (content, priority) in [
+        ("first high", "high"),
+        ("low one", "low"),
+        ("normal one", "normal"),
+        ("second high", "high"),
+    ] {
+        let response = alice
+            .post(format!("{}/todo", app.address))
+            .header("HX-Request", "true")
+            .form(&[("todo_content", content), ("priority", priority)])
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(201, response.status().as_u16());
+    }
+
+    let body = alice
+        .get(format!("{}/todo?sort=priority", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    let positions: Vec<usize> = ["second high", "first high", "normal one", "low one"]
+        .iter()
+        .map(|content| body.find(content).expect("Todo missing from page"))
+        .collect();
+    assert!(positions.is_sorted());
+
+    let response = alice
+        .get(format!("{}/todo?sort=nonsense", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn priority_can_be_updated() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+
+    let priority = sqlx::query_scalar!(
+        r#"SELECT priority::text AS "priority!" FROM todo WHERE todo_id = $1"#,
+        todo_id
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    assert_eq!("normal", priority);
+
+    let response = alice
+        .put(format!("{}/todo/{}", app.address, todo_id))
+        .form(&[("priority", "urgent"), ("version", "1")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+
+    let response = alice
+        .put(format!("{}/todo/{}", app.address, todo_id))
+        .form(&[("priority", "high"), ("version", "1")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let todo = sqlx::query!(
+        r#"SELECT priority::text AS "priority!", is_completed FROM todo WHERE todo_id = $1"#,
+        todo_id
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    assert_eq!("high", todo.priority);
+    assert!(!todo.is_completed);
+}
+
+#[tokio::test]
+async fn unknown_priority_on_create_returns_400() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = alice
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "buy milk"), ("priority", "urgent")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn footer_shows_active_count() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let first = create_todo(&app, &alice, "buy milk").await;
+    create_todo(&app, &alice, "walk dog").await;
+
+    let body = alice
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("2 items left"));
+
+    alice
+        .put(format!("{}/todo/{}", app.address, first))
+        .form(&[("is_completed", "true"), ("version", "1")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let body = alice
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("1 item left"));
+    assert!(body.contains("1 completed"));
+}
+
+#[tokio::test]
+async fn mutations_send_counts_in_hx_trigger() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let hx_trigger = |response: &reqwest::Response| -> serde_json::Value {
+        let header = response
+            .headers()
+            .get("HX-Trigger")
+            .expect("Missing HX-Trigger header");
+        serde_json::from_slice(header.as_bytes()).expect("HX-Trigger is not valid JSON")
+    };
+
+    let response = alice
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "buy milk")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(
+        serde_json::json!({ "todoCounts": { "active": 1, "completed": 0 } }),
+        hx_trigger(&response)
+    );
+
+    let todo_id = sqlx::query_scalar!("SELECT todo_id FROM todo")
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    let response = alice
+        .put(format!("{}/todo/{}", app.address, todo_id))
+        .form(&[("is_completed", "true"), ("version", "1")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(
+        serde_json::json!({ "todoCounts": { "active": 0, "completed": 1 } }),
+        hx_trigger(&response)
+    );
+
+    let response = alice
+        .delete(format!("{}/todo/{}", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(
+        serde_json::json!({ "todoCounts": { "active": 0, "completed": 0 } }),
+        hx_trigger(&response)
+    );
+}
+
+#[tokio::test]
+async fn empty_list_and_empty_filter_show_different_messages() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let body = alice
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("This list has no todos yet."));
+
+    create_todo(&app, &alice, "buy milk").await;
+    let body = alice
+        .get(format!("{}/todo?tag=work", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("No todos match this filter."));
+    assert!(!body.contains("This list has no todos yet."));
+}
+
+#[tokio::test]
+async fn todo_rows_show_relative_timestamps() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+    sqlx::query!(
+        "UPDATE todo SET created_at = NOW() - INTERVAL '3 hours' WHERE todo_id = $1",
+        todo_id
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let body = alice
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("Added 3 hours ago"));
+    assert!(body.contains("Updated just now"));
+}
+
+#[tokio::test]
+async fn todo_content_is_rendered_as_sanitized_markdown() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    create_todo(
+        &app,
+        &alice,
+        "**read** https://example.com <script>alert(1)</script>",
+    )
+    .await;
+
+    let body = alice
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("<strong>read</strong>"));
+    assert!(body.contains("<a href=\"https://example.com\""));
+    assert!(body.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+    assert!(!body.contains("<script>alert(1)</script>"));
+}
+
+#[tokio::test]
+async fn todo_detail_is_not_found_for_strangers() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+
+    let response = alice
+        .get(format!("{}/todo/{}", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = bob
+        .get(format!("{}/todo/{}", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+
+    let response = bob
+        .put(format!("{}/todo/{}/description", app.address, todo_id))
+        .form(&[("description", "mine now")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn todo_description_round_trips() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+
+    let response = alice
+        .put(format!("{}/todo/{}/description", app.address, todo_id))
+        .form(&[("description", "  - **oat** milk\n- <b>2 litres</b>  ")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let description =
+        sqlx::query_scalar!("SELECT description FROM todo WHERE todo_id = $1", todo_id)
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+    assert_eq!("- **oat** milk\n- <b>2 litres</b>", description);
+
+    let body = alice
+        .get(format!("{}/todo/{}", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    // rendered for display, raw in the edit form
+    assert!(body.contains("<li><strong>oat</strong> milk</li>"));
+    assert!(body.contains("&lt;b&gt;2 litres&lt;/b&gt;"));
+    assert!(body.contains("- **oat** milk"));
+
+    let body = alice
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains(&format!("href=\"/todo/{todo_id}\"")));
+}
+
+#[tokio::test]
+async fn overlong_description_returns_400() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+
+    let response = alice
+        .put(format!("{}/todo/{}/description", app.address, todo_id))
+        .form(&[("description", "a".repeat(10_001))])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn created_todo_is_returned_as_stored() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = alice
+        .post(format!("{}/todo", app.address))
+        .header("Accept", "application/json")
+        .form(&[
+            ("todo_content", "buy milk"),
+            ("due_date", "2030-01-31"),
+            ("priority", "high"),
+            ("tags", "Shopping, errands"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+    let todo: serde_json::Value = response.json().await.unwrap();
+
+    let stored = sqlx::query!(
+        r#"
+        SELECT
+            todo_id, list_id, todo_content, is_completed, version, due_date,
+            priority::text AS "priority!", created_at, updated_at
+        FROM todo
+        "#
+    )
+    .fetch_one(&app.db)
+    .await
+    .expect("Failed to fetch todo");
+    assert_eq!(stored.todo_id.to_string(), todo["todo_id"]);
+    assert_eq!(stored.list_id.to_string(), todo["list_id"]);
+    assert_eq!(stored.todo_content, todo["todo_content"]);
+    assert_eq!(stored.is_completed, todo["is_completed"]);
+    assert_eq!(stored.version, todo["version"]);
+    assert_eq!(stored.due_date.unwrap().to_string(), todo["due_date"]);
+    assert_eq!(stored.priority, todo["priority"]);
+    assert_eq!(serde_json::json!(["errands", "shopping"]), todo["tags"]);
+    assert_eq!(0, todo["subtask_count"]);
+
+    let rfc3339 = |value: &serde_json::Value| {
+        time::OffsetDateTime::parse(
+            value.as_str().unwrap(),
+            &time::format_description::well_known::Rfc3339,
+        )
+        .unwrap()
+    };
+    assert_eq!(stored.created_at, rfc3339(&todo["created_at"]));
+    assert_eq!(stored.updated_at, rfc3339(&todo["updated_at"]));
+}
+
+async fn get_todos_with_etag(
+    app: &TestApp,
+    client: &reqwest::Client,
+    etag: Option<&str>,
+) -> reqwest::Response {
+    let mut request = client.get(format!("{}/todo", app.address));
+    if let Some(etag) = etag {
+        request = request.header("If-None-Match", etag);
+    }
+    request.send().await.expect("Failed to execute request")
+}
+
+fn etag_of(response: &reqwest::Response) -> String {
+    response.headers()["ETag"].to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn unchanged_list_is_not_sent_again() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    create_todo(&app, &alice, "buy milk").await;
+
+    let response = get_todos_with_etag(&app, &alice, None).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("private, no-cache", response.headers()["Cache-Control"]);
+    let etag = etag_of(&response);
+
+    let response = get_todos_with_etag(&app, &alice, Some(&etag)).await;
+    assert_eq!(304, response.status().as_u16());
+    assert_eq!(etag, etag_of(&response));
+    assert!(response.text().await.unwrap().is_empty());
+
+    create_todo(&app, &alice, "buy eggs").await;
+    let response = get_todos_with_etag(&app, &alice, Some(&etag)).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_ne!(etag, etag_of(&response));
+    assert!(response.text().await.unwrap().contains("buy eggs"));
+}
+
+#[tokio::test]
+async fn cached_list_page_carries_no_idempotency_key() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    // a key rendered into the page would be sent again by every copy of it
+    // the browser reuses after a 304
+    let page = get_todos_with_etag(&app, &alice, None)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("data-idempotent"));
+    assert!(!page.contains("Idempotency-Key"));
+}
+
+#[tokio::test]
+async fn timezone_changes_change_the_list_etag() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let etag = etag_of(&get_todos_with_etag(&app, &alice, None).await);
+
+    let response = alice
+        .post(format!("{}/settings", app.address))
+        .form(&[
+            ("default_sort", "created"),
+            // the defaults, only the timezone changes
+            ("items_per_page", "50"),
+            ("timezone", "Europe/Berlin"),
+            ("show_completed", "true"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = get_todos_with_etag(&app, &alice, Some(&etag)).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn due_today_is_today_in_the_users_timezone() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let response = alice
+        .post(format!("{}/settings", app.address))
+        .form(&[
+            ("default_sort", "created"),
+            ("items_per_page", "50"),
+            // UTC+13
+            ("timezone", "Pacific/Tongatapu"),
+            ("show_completed", "true"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let today =
+        sqlx::query_scalar!(r#"SELECT (NOW() AT TIME ZONE 'Pacific/Tongatapu')::date AS "today!""#)
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+
+    for (content, due_date) in [
+        ("due today", today),
+        ("due yesterday", today.previous_day().unwrap()),
+    ] {
+        let response = alice
+            .post(format!("{}/todo", app.address))
+            .header("Accept", "application/json")
+            .form(&[
+                ("todo_content", content),
+                ("due_date", &due_date.to_string()),
+            ])
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(201, response.status().as_u16());
+        // stored as the date that was entered
+        let todo: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(due_date.to_string(), todo["due_date"]);
+    }
+
+    for (due, expected) in [("today", "due today"), ("overdue", "due yesterday")] {
+        let page: serde_json::Value = alice
+            .get(format!("{}/todo?due={due}", app.address))
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .json()
+            .await
+            .unwrap();
+        let todos = page["todos"].as_array().unwrap();
+        assert_eq!(1, todos.len(), "{due}");
+        assert_eq!(expected, todos[0]["todo_content"]);
+    }
+}
+
+#[tokio::test]
+async fn subtask_changes_change_the_list_etag() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+    let etag = etag_of(&get_todos_with_etag(&app, &alice, None).await);
+
+    let response = alice
+        .post(format!("{}/todo/{}/subtasks", app.address, todo_id))
+        .form(&[("content", "oat milk")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    let response = get_todos_with_etag(&app, &alice, Some(&etag)).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+async fn api_todo_page(
+    app: &TestApp,
+    client: &reqwest::Client,
+    query: &[(&str, &str)],
+) -> reqwest::Response {
+    client
+        .get(format!("{}/api/todo", app.address))
+        .query(query)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+fn page_contents(page: &serde_json::Value) -> Vec<String> {
+    page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|todo| todo["todo_content"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn api_pages_stay_stable_while_todos_are_added() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    for n in 1..=5 {
+        create_todo(&app, &alice, &format!("todo {n}")).await;
+    }
+
+    let page: serde_json::Value = api_todo_page(&app, &alice, &[("limit", "2")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(vec!["todo 5", "todo 4"], page_contents(&page));
+
+    // newer todos come before the cursor, so they don't shift the next pages
+    create_todo(&app, &alice, "todo 6").await;
+    create_todo(&app, &alice, "todo 7").await;
+
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let page: serde_json::Value =
+        api_todo_page(&app, &alice, &[("limit", "2"), ("cursor", cursor)])
+            .await
+            .json()
+            .await
+            .unwrap();
+    assert_eq!(vec!["todo 3", "todo 2"], page_contents(&page));
+
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let page: serde_json::Value =
+        api_todo_page(&app, &alice, &[("limit", "2"), ("cursor", cursor)])
+            .await
+            .json()
+            .await
+            .unwrap();
+    assert_eq!(vec!["todo 1"], page_contents(&page));
+    assert!(page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn unchanged_api_page_is_not_sent_again() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    create_todo(&app, &alice, "buy milk").await;
+
+    let response = api_todo_page(&app, &alice, &[]).await;
+    assert_eq!(200, response.status().as_u16());
+    let etag = etag_of(&response);
+
+    let response = alice
+        .get(format!("{}/api/todo", app.address))
+        .header("If-None-Match", &etag)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(304, response.status().as_u16());
+    assert_eq!(etag, etag_of(&response));
+
+    // another page of the same list is tagged apart
+    let response = api_todo_page(&app, &alice, &[("limit", "1")]).await;
+    assert_ne!(etag, etag_of(&response));
+
+    create_todo(&app, &alice, "buy eggs").await;
+    let response = alice
+        .get(format!("{}/api/todo", app.address))
+        .header("If-None-Match", &etag)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn api_page_limit_is_capped() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    sqlx::query!(
+        r#"
+        INSERT INTO todo (user_id, list_id, todo_content)
+        SELECT tl.owner_id, tl.list_id, 'todo ' || n
+        FROM todo_list AS tl
+        CROSS JOIN generate_series(1, 150) AS n
+        "#
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+
+    let page: serde_json::Value = api_todo_page(&app, &alice, &[("limit", "1000")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(100, page["items"].as_array().unwrap().len());
+    assert!(page["next_cursor"].is_string());
+}
+
+#[tokio::test]
+async fn api_rejects_forged_cursors_and_anonymous_clients() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = api_todo_page(&app, &alice, &[("cursor", "eyJ9.Zm9yZ2Vk")]).await;
+    assert_api_error(response, 400, "invalid_cursor", Some("cursor")).await;
+
+    let response = api_todo_page(&app, &app.client, &[]).await;
+    assert_api_error(response, 401, "not_logged_in", None).await;
+}
+
+#[tokio::test]
+async fn todos_can_be_created_toggled_and_deleted() {
+    let app = spawn_app().await;
+    let alice = app.register_and_login("alice", PASSWORD).await;
+
+    let todo = alice.create_todo("buy milk").await;
+    assert_eq!("buy milk", todo.todo_content);
+    assert!(!todo.is_completed);
+    let todos = alice.get_todos().await;
+    assert_eq!(1, todos.len());
+    assert_eq!(todo.todo_id, todos[0].todo_id);
+
+    let response = alice.toggle_todo(&todo).await;
+    assert_eq!(200, response.status().as_u16());
+    let toggled = alice.get_todos().await.remove(0);
+    assert!(toggled.is_completed);
+    assert_eq!(todo.version + 1, toggled.version);
+
+    let response = alice.toggle_todo(&toggled).await;
+    assert_eq!(200, response.status().as_u16());
+    assert!(!alice.get_todos().await[0].is_completed);
+
+    let response = alice.delete_todo(todo.todo_id).await;
+    assert_eq!(200, response.status().as_u16());
+    assert!(alice.get_todos().await.is_empty());
+}
+
+async fn completed_at(app: &TestApp, todo_id: Uuid) -> Option<OffsetDateTime> {
+    sqlx::query_scalar!("SELECT completed_at FROM todo WHERE todo_id = $1", todo_id)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to fetch todo")
+}
+
+/// `completed_at` of the user's newest todo as the JSON API sends it
+async fn api_completed_at(client: &reqwest::Client, app: &TestApp) -> Option<OffsetDateTime> {
+    let page: serde_json::Value = client
+        .get(format!("{}/api/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .unwrap();
+    page["items"][0]["completed_at"]
+        .as_str()
+        .map(|value| OffsetDateTime::parse(value, &Rfc3339).unwrap())
+}
+
+async fn list_page(client: &reqwest::Client, app: &TestApp) -> String {
+    client
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn completing_a_todo_sets_completed_at_until_it_is_uncompleted() {
+    let app = spawn_app().await;
+    let alice = app.register_and_login("alice", PASSWORD).await;
+    let todo = alice.create_todo("buy milk").await;
+    assert_eq!(None, completed_at(&app, todo.todo_id).await);
+    assert_eq!(None, api_completed_at(&alice.client, &app).await);
+    assert!(
+        !list_page(&alice.client, &app)
+            .await
+            .contains("Completed today")
+    );
+
+    let response = alice.toggle_todo(&todo).await;
+    assert_eq!(200, response.status().as_u16());
+    let first = completed_at(&app, todo.todo_id)
+        .await
+        .expect("Completing didn't set completed_at");
+    assert_eq!(Some(first), api_completed_at(&alice.client, &app).await);
+    assert!(
+        list_page(&alice.client, &app)
+            .await
+            .contains("Completed today")
+    );
+
+    // other changes to a completed todo keep it
+    let response = alice
+        .client
+        .put(format!("{}/todo/{}", app.address, todo.todo_id))
+        .form(&[("priority", "high"), ("version", "2")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(Some(first), completed_at(&app, todo.todo_id).await);
+
+    let completed = alice.get_todos().await.remove(0);
+    let response = alice.toggle_todo(&completed).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(None, completed_at(&app, todo.todo_id).await);
+    assert_eq!(None, api_completed_at(&alice.client, &app).await);
+    assert!(
+        !list_page(&alice.client, &app)
+            .await
+            .contains("Completed today")
+    );
+
+    let uncompleted = alice.get_todos().await.remove(0);
+    let response = alice.toggle_todo(&uncompleted).await;
+    assert_eq!(200, response.status().as_u16());
+    let again = completed_at(&app, todo.todo_id)
+        .await
+        .expect("Completing again didn't set completed_at");
+    assert!(again > first);
+}
+
+#[tokio::test]
+async fn users_only_see_and_change_their_own_todos() {
+    let app = spawn_app().await;
+    let alice = app.register_and_login("alice", PASSWORD).await;
+    let bob = app.register_and_login("bob", PASSWORD).await;
+    let alices_todo = alice.create_todo("buy milk").await;
+    let bobs_todo = bob.create_todo("walk the dog").await;
+    assert_ne!(alices_todo.list_id, bobs_todo.list_id);
+
+    let bobs_todos = bob.get_todos().await;
+    assert_eq!(1, bobs_todos.len());
+    assert_eq!(bobs_todo.todo_id, bobs_todos[0].todo_id);
+
+    let response = bob.toggle_todo(&alices_todo).await;
+    assert_eq!(404, response.status().as_u16());
+    let response = bob.delete_todo(alices_todo.todo_id).await;
+    assert_eq!(404, response.status().as_u16());
+
+    let alices_todos = alice.get_todos().await;
+    assert_eq!(1, alices_todos.len());
+    assert!(!alices_todos[0].is_completed);
+    assert_eq!(alices_todo.version, alices_todos[0].version);
+}
+
+#[tokio::test]
+async fn todo_list_is_sent_as_html_or_json_depending_on_accept() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    create_todo(&app, &alice, "buy milk").await;
+
+    let response = alice
+        .get(format!("{}/todo", app.address))
+        .header("Accept", "text/html")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert!(
+        response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html")
+    );
+    assert!(response.text().await.unwrap().contains("buy milk"));
+
+    let response = alice
+        .get(format!("{}/todo", app.address))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "Accept, Accept-Language, HX-Request",
+        response.headers()["vary"]
+    );
+    let page: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("buy milk", page["todos"][0]["todo_content"]);
+    assert_eq!("alice", page["owner_username"]);
+}
+
+#[tokio::test]
+async fn anonymous_json_requests_get_401_instead_of_a_redirect() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let response = client
+        .get(format!("{}/todo", app.address))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_api_error(response, 401, "not_logged_in", None).await;
+
+    let response = client
+        .get(format!("{}/todo", app.address))
+        .header("Accept", "text/html")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(307, response.status().as_u16());
+}
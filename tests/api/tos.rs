@@ -0,0 +1,160 @@
+use crate::{
+    audit::wait_for_events,
+    helpers::{PASSWORD, TestApp, logged_in_client, spawn_app_with},
+};
+
+/// An app at version 2 of the terms, where `username` accepted version 1
+/// before they were raised
+async fn app_after_a_version_bump(username: &str) -> (TestApp, reqwest::Client) {
+    let app = spawn_app_with(|config| {
+        config.application_settings.tos_version = 2;
+        // the version is read from the cached user
+        config.application_settings.user_cache_enabled = false;
+    })
+    .await;
+    let client = logged_in_client(&app, username).await;
+    sqlx::query!(
+        "UPDATE user_info SET tos_accepted_version = 1 WHERE username = $1",
+        username
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+    (app, client)
+}
+
+fn no_redirects() -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::none()
+}
+
+#[tokio::test]
+async fn registering_accepts_the_current_terms() {
+    let app = spawn_app_with(|config| config.application_settings.tos_version = 3).await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let accepted = sqlx::query!(
+        "SELECT tos_accepted_version, tos_accepted_at FROM user_info WHERE username = 'alice'"
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    assert_eq!(3, accepted.tos_accepted_version);
+    assert!(accepted.tos_accepted_at.is_some());
+
+    let response = client
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.url().path().starts_with("/todo"));
+}
+
+#[tokio::test]
+async fn users_are_sent_to_the_terms_until_they_accept_the_new_ones() {
+    let (app, _) = app_after_a_version_bump("alice").await;
+    // the same session, without following redirects
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .redirect(no_redirects())
+        .build()
+        .unwrap();
+    client
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[("username", "alice"), ("password", PASSWORD)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let response = client
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!("/tos", response.headers()["location"]);
+
+    let response = client
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "buy milk")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!("/tos", response.headers()["HX-Redirect"]);
+
+    let response = client
+        .get(format!("{}/tos", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("have changed"));
+
+    // accepting the version the page showed, not an older one
+    let response = client
+        .post(format!("{}/tos", app.address))
+        .form(&[("version", "1")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(409, response.status().as_u16());
+    let response = client
+        .post(format!("{}/tos", app.address))
+        .form(&[("version", "2")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = client
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let version =
+        sqlx::query_scalar!("SELECT tos_accepted_version FROM user_info WHERE username = 'alice'")
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+    assert_eq!(2, version);
+    wait_for_events(&app, "tos_accepted", 1).await;
+}
+
+#[tokio::test]
+async fn api_requests_are_refused_until_the_new_terms_are_accepted() {
+    let (app, client) = app_after_a_version_bump("alice").await;
+
+    let response = client
+        .get(format!("{}/api/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("tos_not_accepted", body["code"]);
+
+    // the pages asked for as JSON get the same error
+    let response = client
+        .get(format!("{}/todo", app.address))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(403, response.status().as_u16());
+
+    client
+        .post(format!("{}/tos", app.address))
+        .form(&[("version", "2")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let response = client
+        .get(format!("{}/api/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
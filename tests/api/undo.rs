@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app, spawn_app_with};
+
+async fn create_todo(app: &TestApp, client: &reqwest::Client, content: &str) -> Uuid {
+    let response = client
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", content)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    sqlx::query_scalar!("SELECT todo_id FROM todo WHERE todo_content = $1", content)
+        .fetch_one(&app.db)
+        .await
+        .expect("Failed to fetch todo id")
+}
+
+async fn delete_todo(app: &TestApp, client: &reqwest::Client, todo_id: Uuid) -> reqwest::Response {
+    client
+        .delete(format!("{}/todo/{}", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn undo_delete(app: &TestApp, client: &reqwest::Client, todo_id: Uuid) -> reqwest::Response {
+    client
+        .post(format!("{}/todo/{}/undo", app.address, todo_id))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn list_page(app: &TestApp, client: &reqwest::Client) -> String {
+    client
+        .get(format!("{}/todo", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn deleted_todo_can_be_restored() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+
+    let response = delete_todo(&app, &alice, todo_id).await;
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("hx-swap-oob"));
+    assert!(body.contains(&format!("/todo/{todo_id}/undo")));
+    assert!(!list_page(&app, &alice).await.contains("buy milk"));
+
+    let response = undo_delete(&app, &alice, todo_id).await;
+    assert_eq!(200, response.status().as_u16());
+    assert!(list_page(&app, &alice).await.contains("buy milk"));
+
+    // nothing left to undo
+    let response = undo_delete(&app, &alice, todo_id).await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn other_users_cannot_restore_a_todo() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let mallory = logged_in_client(&app, "mallory").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+    delete_todo(&app, &alice, todo_id).await;
+
+    let response = undo_delete(&app, &mallory, todo_id).await;
+    assert_eq!(404, response.status().as_u16());
+    assert!(!list_page(&app, &alice).await.contains("buy milk"));
+}
+
+#[tokio::test]
+async fn undo_fails_after_the_grace_period() {
+    let app = spawn_app_with(|config| config.application_settings.todo_undo_grace_secs = 1).await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+    delete_todo(&app, &alice, todo_id).await;
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    // depending on whether the purge worker got to it yet
+    let response = undo_delete(&app, &alice, todo_id).await;
+    assert!([404, 410].contains(&response.status().as_u16()));
+    assert!(!list_page(&app, &alice).await.contains("buy milk"));
+}
+
+#[tokio::test]
+async fn expired_deletions_are_purged() {
+    let app = spawn_app_with(|config| config.application_settings.todo_undo_grace_secs = 1).await;
+    let alice = logged_in_client(&app, "alice").await;
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+    alice
+        .put(format!("{}/todo/{}/tags", app.address, todo_id))
+        .form(&[("tags", "groceries")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    delete_todo(&app, &alice, todo_id).await;
+
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+
+    let todos = sqlx::query_scalar!("SELECT COUNT(*) FROM todo WHERE todo_id = $1", todo_id)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(Some(0), todos);
+    let tags = sqlx::query_scalar!("SELECT COUNT(*) FROM tag WHERE name = 'groceries'")
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(Some(0), tags);
+}
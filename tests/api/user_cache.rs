@@ -0,0 +1,109 @@
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHasher, Version,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app, spawn_app_with};
+
+async fn is_logged_in(app: &TestApp, client: &reqwest::Client) -> bool {
+    let response = client
+        .get(format!("{}/settings", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    response.url().path() == "/settings"
+}
+
+/// Replaces alice's password hash without going through the app, so nothing
+/// is invalidated. The hash is weak enough to be upgraded on the next login.
+async fn replace_hash_behind_the_apps_back(app: &TestApp) {
+    let weak_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(64, 1, 1, None).unwrap(),
+    )
+    .hash_password(
+        b"correct horse battery staple",
+        &SaltString::generate(&mut OsRng),
+    )
+    .unwrap()
+    .to_string();
+    sqlx::query!(
+        r#"
+        UPDATE user_password SET password_hash = $1
+        WHERE user_id = (SELECT user_id FROM user_info WHERE username = 'alice')
+        "#,
+        weak_hash
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+}
+
+async fn log_in_alice(app: &TestApp) {
+    let response = app
+        .client
+        .post(format!("{}/api/login", app.address))
+        .header("HX-Request", "true")
+        .form(&[
+            ("username", "alice"),
+            ("password", "correct horse battery staple"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn password_change_ends_sessions_despite_the_cache() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    assert!(is_logged_in(&app, &alice).await);
+
+    // served from the cache, which still has the hash the session was made with
+    replace_hash_behind_the_apps_back(&app).await;
+    assert!(is_logged_in(&app, &alice).await);
+
+    // upgrading the hash is a password change made by the app
+    log_in_alice(&app).await;
+    assert!(!is_logged_in(&app, &alice).await);
+}
+
+#[tokio::test]
+async fn without_the_cache_every_request_sees_the_stored_user() {
+    let app = spawn_app_with(|config| {
+        config.application_settings.user_cache_enabled = false;
+    })
+    .await;
+    let alice = logged_in_client(&app, "alice").await;
+    assert!(is_logged_in(&app, &alice).await);
+
+    replace_hash_behind_the_apps_back(&app).await;
+    assert!(!is_logged_in(&app, &alice).await);
+}
+
+#[tokio::test]
+async fn username_change_shows_up_right_away() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    assert!(is_logged_in(&app, &alice).await);
+
+    let response = alice
+        .post(format!("{}/settings/username", app.address))
+        .form(&[("username", "alicia")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let body = alice
+        .get(format!("{}/settings", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("alicia"));
+}
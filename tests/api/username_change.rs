@@ -0,0 +1,125 @@
+use crate::helpers::{TestApp, logged_in_client, spawn_app};
+
+async fn change_username(
+    app: &TestApp,
+    client: &reqwest::Client,
+    username: &str,
+) -> reqwest::Response {
+    client
+        .post(format!("{}/settings/username", app.address))
+        .form(&[("username", username)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn username_change_keeps_the_session_working() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = change_username(&app, &client, "alicia").await;
+    assert_eq!(200, response.status().as_u16());
+
+    let response = client
+        .get(format!("{}/settings", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    assert!(body.contains(r#"value="alicia""#));
+    assert!(body.contains("You can change your username again on"));
+
+    let response = client
+        .post(format!("{}/todo", app.address))
+        .header("HX-Request", "true")
+        .form(&[("todo_content", "still here")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn username_can_only_be_changed_once_per_30_days() {
+    let app = spawn_app().await;
+    let client = logged_in_client(&app, "alice").await;
+
+    let response = change_username(&app, &client, "alicia").await;
+    assert_eq!(200, response.status().as_u16());
+
+    let response = change_username(&app, &client, "ali").await;
+    assert_eq!(409, response.status().as_u16());
+    let message = response.text().await.unwrap();
+    assert!(message.starts_with("You can change your username again on"));
+
+    sqlx::query!("UPDATE username_history SET changed_at = NOW() - INTERVAL '31 days'")
+        .execute(&app.db)
+        .await
+        .unwrap();
+    let response = change_username(&app, &client, "ali").await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn old_usernames_are_held_for_their_previous_owner() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+
+    let response = change_username(&app, &alice, "alicia").await;
+    assert_eq!(200, response.status().as_u16());
+
+    let response = change_username(&app, &bob, "alice").await;
+    assert_eq!(409, response.status().as_u16());
+    assert_eq!(
+        "This username was recently used by another account",
+        response.text().await.unwrap()
+    );
+
+    let response = app
+        .client
+        .post(format!("{}/api/register", app.address))
+        .header("HX-Request", "true")
+        .form(&[
+            ("email", "mallory@test.com"),
+            ("username", "Alice"),
+            ("password", "correct horse battery staple"),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(409, response.status().as_u16());
+
+    // the hold ends after the configured period
+    sqlx::query!("UPDATE username_history SET changed_at = NOW() - INTERVAL '91 days'")
+        .execute(&app.db)
+        .await
+        .unwrap();
+    let response = change_username(&app, &bob, "alice").await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn taken_or_invalid_usernames_are_rejected() {
+    let app = spawn_app().await;
+    let alice = logged_in_client(&app, "alice").await;
+    logged_in_client(&app, "bob").await;
+
+    let response = change_username(&app, &alice, "BOB").await;
+    assert_eq!(409, response.status().as_u16());
+    assert_eq!("Username already exists", response.text().await.unwrap());
+
+    let response = change_username(&app, &alice, "").await;
+    assert_eq!(400, response.status().as_u16());
+
+    let response = change_username(&app, &alice, "alice").await;
+    assert_eq!(400, response.status().as_u16());
+
+    let history = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM username_history"#)
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(0, history);
+}
@@ -0,0 +1,393 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use site::config::Config;
+use uuid::Uuid;
+use wiremock::{
+    Mock, MockServer, Request, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, logged_in_client, spawn_app_with};
+
+/// Delivers every second and retries right away, as webhooks can't reach a
+/// mock server on localhost otherwise
+fn deliver_quickly(config: &mut Config) {
+    config.application_settings.webhook_interval_secs = 1;
+    config.application_settings.webhook_retry_base_secs = 0;
+    config.application_settings.webhook_max_failures = 3;
+    config.application_settings.webhook_allow_private_targets = true;
+}
+
+pub async fn add_webhook(
+    app: &TestApp,
+    client: &reqwest::Client,
+    url: &str,
+    events: &[&str],
+) -> reqwest::Response {
+    let mut form = vec![("url", url)];
+    form.extend(events.iter().map(|event| (*event, "on")));
+    client
+        .post(format!("{}/settings/webhooks", app.address))
+        .form(&form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn create_todo(app: &TestApp, client: &reqwest::Client, content: &str) -> Uuid {
+    let response = client
+        .post(format!("{}/todo", app.address))
+        .header("Accept", "application/json")
+        .form(&[("todo_content", content)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(201, response.status().as_u16());
+
+    let todo: serde_json::Value = response.json().await.expect("Failed to parse todo");
+    todo["todo_id"].as_str().unwrap().parse().unwrap()
+}
+
+async fn webhook_of(app: &TestApp, username: &str) -> (Uuid, String) {
+    let webhook = sqlx::query!(
+        r#"
+        SELECT wh.webhook_id, wh.secret FROM webhooks AS wh
+        JOIN user_info AS ui ON ui.user_id = wh.user_id
+        WHERE ui.username = $1
+        "#,
+        username
+    )
+    .fetch_one(&app.db)
+    .await
+    .expect("Failed to fetch webhook");
+    (webhook.webhook_id, webhook.secret)
+}
+
+async fn wait_for_requests(server: &MockServer, count: usize) -> Vec<Request> {
+    for _ in 0..100 {
+        let requests = server.received_requests().await.unwrap();
+        if requests.len() >= count {
+            return requests;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Webhook was not delivered in time");
+}
+
+fn header<'a>(request: &'a Request, name: &str) -> &'a str {
+    request.headers.get(name).unwrap().to_str().unwrap()
+}
+
+#[tokio::test]
+async fn created_todo_is_delivered_signed() {
+    let app = spawn_app_with(deliver_quickly).await;
+    let endpoint = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hooks"))
+        .respond_with(ResponseTemplate::new(204))
+        .mount(&endpoint)
+        .await;
+    let alice = logged_in_client(&app, "alice").await;
+    let url = format!("{}/hooks", endpoint.uri());
+    let response = add_webhook(&app, &alice, &url, &["todo_created"]).await;
+    assert_eq!(201, response.status().as_u16());
+    let (_, secret) = webhook_of(&app, "alice").await;
+
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+
+    let requests = wait_for_requests(&endpoint, 1).await;
+    let request = &requests[0];
+    assert_eq!("application/json", header(request, "Content-Type"));
+    assert_eq!("todo.created", header(request, "X-Webhook-Event"));
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(&request.body);
+    assert_eq!(
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes())),
+        header(request, "X-Webhook-Signature")
+    );
+
+    let payload: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!("todo.created", payload["event"]);
+    assert!(payload["occurred_at"].is_string());
+    assert_eq!(todo_id.to_string(), payload["todo"]["todo_id"]);
+    assert_eq!("buy milk", payload["todo"]["content"]);
+    assert_eq!(false, payload["todo"]["is_completed"]);
+    assert!(payload["todo"]["list_id"].is_string());
+}
+
+#[tokio::test]
+async fn only_subscribed_events_are_delivered() {
+    let app = spawn_app_with(deliver_quickly).await;
+    let endpoint = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&endpoint)
+        .await;
+    let alice = logged_in_client(&app, "alice").await;
+    add_webhook(&app, &alice, &endpoint.uri(), &["todo_completed"]).await;
+
+    let todo_id = create_todo(&app, &alice, "buy milk").await;
+    let response = alice
+        .put(format!("{}/todo/{}", app.address, todo_id))
+        .form(&[("is_completed", "true"), ("version", "1")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    // deliveries go out in order, so the creation would have come first
+    let requests = wait_for_requests(&endpoint, 1).await;
+    assert_eq!("todo.completed", header(&requests[0], "X-Webhook-Event"));
+    let payload: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(true, payload["todo"]["is_completed"]);
+}
+
+#[tokio::test]
+async fn failed_delivery_is_retried() {
+    let app = spawn_app_with(deliver_quickly).await;
+    let endpoint = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&endpoint)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&endpoint)
+        .await;
+    let alice = logged_in_client(&app, "alice").await;
+    add_webhook(&app, &alice, &endpoint.uri(), &["todo_created"]).await;
+
+    create_todo(&app, &alice, "buy milk").await;
+
+    let requests = wait_for_requests(&endpoint, 2).await;
+    // a retry is the same delivery
+    assert_eq!(
+        header(&requests[0], "X-Webhook-Delivery"),
+        header(&requests[1], "X-Webhook-Delivery")
+    );
+    assert_eq!(requests[0].body, requests[1].body);
+
+    // the outcome is recorded in the transaction that is committed after the request
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let (webhook_id, _) = webhook_of(&app, "alice").await;
+    let webhook = sqlx::query!(
+        r#"
+        SELECT enabled, consecutive_failures, last_status,
+            (SELECT COUNT(*) FROM outbox WHERE webhook_id = $1) AS "queued!"
+        FROM webhooks WHERE webhook_id = $1
+        "#,
+        webhook_id
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    assert!(webhook.enabled);
+    assert_eq!(0, webhook.consecutive_failures);
+    assert_eq!(Some(200), webhook.last_status);
+    assert_eq!(0, webhook.queued);
+}
+
+#[tokio::test]
+async fn endpoint_failing_repeatedly_is_turned_off() {
+    let app = spawn_app_with(deliver_quickly).await;
+    let endpoint = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&endpoint)
+        .await;
+    let alice = logged_in_client(&app, "alice").await;
+    add_webhook(&app, &alice, &endpoint.uri(), &["todo_created"]).await;
+    let (webhook_id, _) = webhook_of(&app, "alice").await;
+
+    create_todo(&app, &alice, "buy milk").await;
+    create_todo(&app, &alice, "buy eggs").await;
+
+    let mut enabled = true;
+    for _ in 0..100 {
+        enabled = sqlx::query_scalar!(
+            "SELECT enabled FROM webhooks WHERE webhook_id = $1",
+            webhook_id
+        )
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+        if !enabled {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(!enabled);
+
+    // only the first delivery was tried, once per tick, as the endpoint kept failing
+    let requests = endpoint.received_requests().await.unwrap();
+    assert_eq!(3, requests.len());
+    let queued = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM outbox WHERE webhook_id = $1"#,
+        webhook_id
+    )
+    .fetch_one(&app.db)
+    .await
+    .unwrap();
+    assert_eq!(0, queued);
+
+    let body = alice
+        .get(format!("{}/settings", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("HTTP 503"));
+    assert!(body.contains("Turned off"));
+}
+
+#[tokio::test]
+async fn private_addresses_are_refused_by_default() {
+    let app = spawn_app_with(|config| {
+        deliver_quickly(config);
+        config.application_settings.webhook_allow_private_targets = false;
+    })
+    .await;
+    let endpoint = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&endpoint)
+        .await;
+    let alice = logged_in_client(&app, "alice").await;
+    add_webhook(&app, &alice, &endpoint.uri(), &["todo_created"]).await;
+    let (webhook_id, _) = webhook_of(&app, "alice").await;
+
+    create_todo(&app, &alice, "buy milk").await;
+
+    let mut last_error = None;
+    for _ in 0..50 {
+        last_error = sqlx::query_scalar!(
+            "SELECT last_error FROM webhooks WHERE webhook_id = $1",
+            webhook_id
+        )
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+        if last_error.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(last_error.unwrap().contains("non-public address"));
+    assert!(endpoint.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn invalid_webhooks_are_rejected() {
+    let app = spawn_app_with(deliver_quickly).await;
+    let alice = logged_in_client(&app, "alice").await;
+
+    let response = add_webhook(&app, &alice, "ftp://example.com", &["todo_created"]).await;
+    assert_eq!(400, response.status().as_u16());
+
+    let response = add_webhook(&app, &alice, "https://example.com/hooks", &[]).await;
+    assert_eq!(400, response.status().as_u16());
+
+    for _ in 0..5 {
+        let response =
+            add_webhook(&app, &alice, "https://example.com/hooks", &["todo_created"]).await;
+        assert_eq!(201, response.status().as_u16());
+    }
+    let response = add_webhook(&app, &alice, "https://example.com/hooks", &["todo_created"]).await;
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn webhooks_can_only_be_changed_by_their_owner() {
+    let app = spawn_app_with(deliver_quickly).await;
+    let alice = logged_in_client(&app, "alice").await;
+    let bob = logged_in_client(&app, "bob").await;
+    add_webhook(&app, &alice, "https://example.com/hooks", &["todo_created"]).await;
+    let (webhook_id, _) = webhook_of(&app, "alice").await;
+
+    let response = bob
+        .post(format!(
+            "{}/settings/webhooks/{}/disable",
+            app.address, webhook_id
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+    let response = bob
+        .delete(format!("{}/settings/webhooks/{}", app.address, webhook_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(404, response.status().as_u16());
+
+    let response = alice
+        .post(format!(
+            "{}/settings/webhooks/{}/disable",
+            app.address, webhook_id
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let response = alice
+        .delete(format!("{}/settings/webhooks/{}", app.address, webhook_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn deliveries_are_leased_rather_than_locked_while_sent() {
+    let app = spawn_app_with(deliver_quickly).await;
+    let endpoint = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hooks"))
+        .respond_with(ResponseTemplate::new(204).set_delay(Duration::from_secs(3)))
+        .mount(&endpoint)
+        .await;
+    let alice = logged_in_client(&app, "alice").await;
+    let url = format!("{}/hooks", endpoint.uri());
+    add_webhook(&app, &alice, &url, &["todo_created"]).await;
+    let (webhook_id, _) = webhook_of(&app, "alice").await;
+
+    create_todo(&app, &alice, "buy milk").await;
+    wait_for_requests(&endpoint, 1).await;
+
+    // the endpoint hasn't answered yet, but no transaction holds the row
+    let mut transaction = app.db.begin().await.unwrap();
+    let leased = sqlx::query_scalar!(
+        r#"
+        SELECT locked_until > NOW() AS "leased!" FROM outbox
+        WHERE webhook_id = $1
+        FOR UPDATE NOWAIT
+        "#,
+        webhook_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .expect("Delivery is locked while it is sent");
+    assert!(leased);
+    transaction.rollback().await.unwrap();
+
+    for _ in 0..100 {
+        let queued = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM outbox WHERE webhook_id = $1"#,
+            webhook_id
+        )
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+        if queued == 0 {
+            assert_eq!(1, endpoint.received_requests().await.unwrap().len());
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Delivered webhook was not removed from the queue");
+}